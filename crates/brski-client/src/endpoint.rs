@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tracing::{event, Level};
+
+use crate::ClientError;
+
+pub(crate) fn url(base_url: &str, endpoint: &str) -> String {
+    format!("{}/.well-known/brski/{}", base_url.trim_end_matches('/'), endpoint)
+}
+
+/// Sends the request and checks the status and, if given, the content type of the response
+pub(crate) async fn send(
+    endpoint: &str,
+    request: RequestBuilder,
+    expected_content_type: Option<&'static str>,
+) -> Result<Response, ClientError> {
+    let response = request.send().await?;
+
+    event!(Level::INFO, "Received {} response", endpoint);
+    event!(Level::DEBUG, "Response: {:#?}", response);
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        event!(Level::ERROR, "{} failed with Status: {} - {}", endpoint, status, body);
+        return Err(ClientError::Status {
+            endpoint: endpoint.to_string(),
+            status,
+            body,
+        });
+    }
+
+    // an accepted request has no artifact in the response yet
+    if status == StatusCode::ACCEPTED && expected_content_type.is_some() {
+        return Err(ClientError::Accepted {
+            endpoint: endpoint.to_string(),
+            retry_after: retry_after(response.headers()),
+        });
+    }
+
+    if let Some(expected) = expected_content_type {
+        check_content_type(endpoint, response.headers(), expected)?;
+    }
+
+    Ok(response)
+}
+
+/// Only the delay in seconds, not the HTTP date form of `Retry-After`
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// EST bodies are base64 encoded, possibly with line breaks
+pub(crate) fn decode_base64(body: &str) -> Result<Vec<u8>, ClientError> {
+    let body: String = body.split_whitespace().collect();
+    Ok(openssl::base64::decode_block(&body)?)
+}
+
+/// Compares the media type only, parameters like a charset are ignored
+fn check_content_type(endpoint: &str, headers: &HeaderMap, expected: &'static str) -> Result<(), ClientError> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .ok_or(ClientError::MissingContentType { endpoint: endpoint.to_string() })?;
+
+    let received = content_type.to_str().unwrap_or_default();
+    let media_type = received.split(';').next().unwrap_or_default().trim();
+
+    if !media_type.eq_ignore_ascii_case(expected) {
+        return Err(ClientError::WrongContentType {
+            endpoint: endpoint.to_string(),
+            expected,
+            received: received.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_check_content_type() {
+        let mut headers = HeaderMap::new();
+        assert!(matches!(
+            check_content_type("tpvr", &headers, "application/voucher-jws+json"),
+            Err(ClientError::MissingContentType { .. })
+        ));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/voucher-jws+json; charset=utf-8"));
+        assert!(check_content_type("tpvr", &headers, "application/voucher-jws+json").is_ok());
+        assert!(matches!(
+            check_content_type("tpvr", &headers, "application/jose+json"),
+            Err(ClientError::WrongContentType { .. })
+        ));
+
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(30)));
+
+        assert_eq!(url("http://registrar/", "requestenroll"), "http://registrar/.well-known/brski/requestenroll");
+    }
+}
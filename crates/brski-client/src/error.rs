@@ -0,0 +1,80 @@
+use common::server_error::ServerError;
+use reqwest::StatusCode;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("{endpoint} failed with Status: {status}")]
+    Status {
+        endpoint: String,
+        status: StatusCode,
+        body: String,
+    },
+
+    /// The request was accepted but not answered yet, e.g. a voucher request held while the MASA is unreachable
+    #[error("{endpoint} was accepted, retry after {retry_after:?}")]
+    Accepted {
+        endpoint: String,
+        retry_after: Option<std::time::Duration>,
+    },
+
+    #[error("No certificate in {endpoint} response")]
+    MissingCertificate { endpoint: String },
+
+    #[error("No content type in {endpoint} response")]
+    MissingContentType { endpoint: String },
+
+    #[error("Wrong content type in {endpoint} response - expected {expected}, received {received}")]
+    WrongContentType {
+        endpoint: String,
+        expected: &'static str,
+        received: String,
+    },
+
+    #[error(transparent)]
+    Transport(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Jose(#[from] josekit::JoseError),
+
+    #[error(transparent)]
+    Openssl(#[from] openssl::error::ErrorStack),
+
+    #[error(transparent)]
+    Asn1(#[from] common::asn1::Asn1Error),
+}
+
+impl From<ClientError> for ServerError {
+    fn from(err: ClientError) -> Self {
+        match err {
+            ClientError::Transport(source) => ServerError::ReqwestError { source },
+            ClientError::Jose(err) => ServerError::JWSError(err),
+            ClientError::Openssl(source) => ServerError::OpensslError { source },
+            err => ServerError::BadResponse(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_to_server_error() {
+        let err: ServerError = ClientError::Status {
+            endpoint: "requestvoucher".to_string(),
+            status: StatusCode::FORBIDDEN,
+            body: String::new(),
+        }
+        .into();
+        assert!(matches!(err, ServerError::BadResponse(reason) if reason == "requestvoucher failed with Status: 403 Forbidden"));
+
+        let err: ServerError = ClientError::WrongContentType {
+            endpoint: "wrappedcacerts".to_string(),
+            expected: "application/jose+json",
+            received: "text/plain".to_string(),
+        }
+        .into();
+        assert!(matches!(err, ServerError::BadResponse(_)));
+    }
+}
@@ -0,0 +1,85 @@
+use brski_prm_artifacts::content_type::{PKCS10, PKCS7};
+use common::asn1;
+use openssl::x509::X509;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder};
+use tracing::{event, Level};
+
+use crate::{endpoint, ClientError};
+
+/// Client for the EST (RFC 7030) operations of a CA. The base URL is the EST path itself, e.g.
+/// `https://ca.example.com/.well-known/est` or one with an additional label, so any EST server can be addressed.
+#[derive(Debug, Clone)]
+pub struct EstClient {
+    client: Client,
+    base_url: String,
+    /// HTTP basic authentication, RFC 7030 Section 3.2.3
+    credentials: Option<(String, Option<String>)>,
+}
+
+impl EstClient {
+    pub fn new(client: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into(),
+            credentials: None,
+        }
+    }
+
+    pub fn with_basic_auth(mut self, username: impl Into<String>, password: Option<String>) -> Self {
+        self.credentials = Some((username.into(), password));
+        self
+    }
+
+    pub fn url(&self, operation: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), operation)
+    }
+
+    fn authenticated(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.credentials {
+            Some((username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// `/cacerts`, the certificates of the base64 encoded certs-only PKCS#7 response
+    pub async fn ca_certs(&self) -> Result<Vec<X509>, ClientError> {
+        let url = self.url("cacerts");
+        event!(Level::INFO, "Requesting cacerts from EST server at: {}", url);
+
+        let request = self.authenticated(self.client.get(url).header(ACCEPT, PKCS7));
+        let response = endpoint::send("cacerts", request, Some(PKCS7)).await?;
+
+        Ok(asn1::certs_only(&endpoint::decode_base64(&response.text().await?)?)?)
+    }
+
+    /// `/simpleenroll` for the DER encoded PKCS#10 `csr`, returns the certificate of the certs-only response
+    pub async fn simple_enroll(&self, csr: &[u8]) -> Result<X509, ClientError> {
+        let url = self.url("simpleenroll");
+        event!(Level::INFO, "Sending simpleenroll to EST server at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, PKCS7)
+            .header(CONTENT_TYPE, PKCS10)
+            .body(openssl::base64::encode_block(csr));
+        let response = endpoint::send("simpleenroll", self.authenticated(request), Some(PKCS7)).await?;
+
+        let certificates = asn1::certs_only(&endpoint::decode_base64(&response.text().await?)?)?;
+        certificates.into_iter().next().ok_or(ClientError::MissingCertificate {
+            endpoint: "simpleenroll".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let client = EstClient::new(Client::new(), "https://ca.example.com/.well-known/est/ldevid/");
+        assert_eq!(client.url("simpleenroll"), "https://ca.example.com/.well-known/est/ldevid/simpleenroll");
+    }
+}
@@ -0,0 +1,16 @@
+//! Typed HTTP client for the BRSKI endpoints of the registrar, the MASA and the pledge, and for EST servers.
+//! Each method sets the content types of its endpoint and checks the one of the response,
+//! so callers only deal with artifacts and [`ClientError`].
+
+mod error;
+mod endpoint;
+mod registrar;
+mod masa;
+mod pledge;
+mod est;
+
+pub use error::ClientError;
+pub use registrar::RegistrarClient;
+pub use masa::MasaClient;
+pub use pledge::PledgeClient;
+pub use est::EstClient;
@@ -0,0 +1,61 @@
+use brski_prm_artifacts::content_type::{JOSE, JWS_VOUCHER};
+use brski_prm_artifacts::issued_voucher::IssuedVoucherJWS;
+use brski_prm_artifacts::rvr::RVR_JWS;
+use brski_prm_artifacts::status::voucher::response::vStatus_JWS;
+use common::idempotency::IDEMPOTENCY_KEY;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Client;
+use tracing::{event, Level};
+
+use crate::{endpoint, ClientError};
+
+/// Client for the `/.well-known/brski` endpoints of a MASA
+#[derive(Debug, Clone)]
+pub struct MasaClient {
+    client: Client,
+    base_url: String,
+}
+
+impl MasaClient {
+    pub fn new(client: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into(),
+        }
+    }
+
+    /// The idempotency key is derived from the RVR, so sending the same RVR again after a timeout returns the
+    /// voucher the MASA issued for the first attempt
+    pub async fn request_voucher(&self, rvr: RVR_JWS) -> Result<IssuedVoucherJWS, ClientError> {
+        let url = endpoint::url(&self.base_url, "requestvoucher");
+        event!(Level::INFO, "Sending RVR to MASA at {:?}", url);
+
+        let rvr = rvr.try_encoded_data()?;
+        let idempotency_key = openssl::base64::encode_block(&openssl::sha::sha256(rvr.as_bytes()));
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JWS_VOUCHER)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .header(IDEMPOTENCY_KEY, format!("\"{}\"", idempotency_key))
+            .body(rvr);
+        let response = endpoint::send("requestvoucher", request, Some(JWS_VOUCHER)).await?;
+
+        Ok(IssuedVoucherJWS::Encoded(response.text().await?))
+    }
+
+    /// Relays the voucher status of a pledge as the pledge signed it
+    pub async fn voucher_status(&self, voucher_status: vStatus_JWS) -> Result<(), ClientError> {
+        let url = endpoint::url(&self.base_url, "voucher_status");
+        event!(Level::INFO, "Sending Voucher Status to MASA at {:?}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, JOSE)
+            .body(voucher_status.try_encoded_data()?);
+        endpoint::send("voucher_status", request, None).await?;
+
+        Ok(())
+    }
+}
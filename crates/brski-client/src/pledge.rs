@@ -0,0 +1,116 @@
+use brski_prm_artifacts::cacerts::response::CACERTS_JWS;
+use brski_prm_artifacts::content_type::{JOSE, JSON, JWS_VOUCHER, PKCS7, SSH_CERTIFICATE};
+use brski_prm_artifacts::issued_voucher::IssuedVoucherJWS;
+use brski_prm_artifacts::per::response::PER_JWS;
+use brski_prm_artifacts::pvr::response::PVR_JWS;
+use brski_prm_artifacts::status::enroll::response::EnrollStatusJWS;
+use brski_prm_artifacts::status::voucher::response::vStatus_JWS;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Client;
+use tracing::{event, Level};
+
+use crate::{endpoint, ClientError};
+
+/// Client for the BRSKI-PRM endpoints a pledge serves to the registrar-agent
+#[derive(Debug, Clone)]
+pub struct PledgeClient {
+    client: Client,
+    base_url: String,
+}
+
+impl PledgeClient {
+    pub fn new(client: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into(),
+        }
+    }
+
+    /// Sends the serialized PVR trigger and returns the PVR of the pledge
+    pub async fn trigger_voucher_request(&self, trigger: String) -> Result<PVR_JWS, ClientError> {
+        let url = endpoint::url(&self.base_url, "tpvr");
+        event!(Level::INFO, "Sending tPVR to pledge at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JWS_VOUCHER)
+            .header(CONTENT_TYPE, JSON)
+            .body(trigger);
+        let response = endpoint::send("tpvr", request, Some(JWS_VOUCHER)).await?;
+
+        Ok(PVR_JWS::Encoded(response.text().await?))
+    }
+
+    /// Sends the serialized PER trigger and returns the PER of the pledge
+    pub async fn trigger_enroll_request(&self, trigger: String) -> Result<PER_JWS, ClientError> {
+        let url = endpoint::url(&self.base_url, "tper");
+        event!(Level::INFO, "Sending tPER to pledge at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JOSE)
+            .header(CONTENT_TYPE, JSON)
+            .body(trigger);
+        let response = endpoint::send("tper", request, Some(JOSE)).await?;
+
+        Ok(PER_JWS::Encoded(response.text().await?))
+    }
+
+    /// Returns the voucher status the pledge answers with
+    pub async fn send_voucher(&self, voucher: IssuedVoucherJWS) -> Result<vStatus_JWS, ClientError> {
+        let url = endpoint::url(&self.base_url, "svr");
+        event!(Level::INFO, "Sending Voucher to pledge at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JOSE)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .body(voucher.try_encoded_data()?);
+        let response = endpoint::send("svr", request, Some(JOSE)).await?;
+
+        Ok(vStatus_JWS::Encoded(response.text().await?))
+    }
+
+    pub async fn send_ca_certs(&self, cacerts: CACERTS_JWS) -> Result<(), ClientError> {
+        let url = endpoint::url(&self.base_url, "scac");
+        event!(Level::INFO, "Sending Wrapped CA Certs to pledge at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JOSE)
+            .header(CONTENT_TYPE, JOSE)
+            .body(cacerts.try_encoded_data()?);
+        endpoint::send("scac", request, None).await?;
+
+        Ok(())
+    }
+
+    /// Sends the DER encoded LDevID and returns the enroll status the pledge answers with
+    pub async fn send_enroll_response(&self, response: Vec<u8>) -> Result<EnrollStatusJWS, ClientError> {
+        let url = endpoint::url(&self.base_url, "ser");
+        event!(Level::INFO, "Sending Registrar Enroll-Response to pledge at: {}", url);
+
+        let request = self.client.post(url).header(CONTENT_TYPE, PKCS7).body(response);
+        let response = endpoint::send("ser", request, None).await?;
+
+        Ok(EnrollStatusJWS::Encoded(response.text().await?))
+    }
+
+    pub async fn send_ssh_certificate(&self, certificate: String) -> Result<(), ClientError> {
+        let url = endpoint::url(&self.base_url, "sshc");
+        event!(Level::INFO, "Sending SSH host certificate to pledge at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, SSH_CERTIFICATE)
+            .body(certificate);
+        endpoint::send("sshc", request, None).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use brski_prm_artifacts::cacerts::response::CACERTS_JWS;
+use brski_prm_artifacts::content_type::{CSRATTRS, JOSE, JWS_VOUCHER, PKCS7, SSH_CERTIFICATE};
+use brski_prm_artifacts::issued_voucher::IssuedVoucherJWS;
+use brski_prm_artifacts::per::response::PER_JWS;
+use brski_prm_artifacts::pvr::response::PVR_JWS;
+use brski_prm_artifacts::status::enroll::response::EnrollStatusJWS;
+use brski_prm_artifacts::status::voucher::response::vStatus_JWS;
+use common::asn1;
+use openssl::x509::X509;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Client;
+use tracing::{event, Level};
+
+use crate::{endpoint, ClientError};
+
+/// Client for the `/.well-known/brski` endpoints of a registrar
+#[derive(Debug, Clone)]
+pub struct RegistrarClient {
+    client: Client,
+    base_url: String,
+}
+
+impl RegistrarClient {
+    pub fn new(client: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into(),
+        }
+    }
+
+    pub fn url(&self, endpoint: &str) -> String {
+        endpoint::url(&self.base_url, endpoint)
+    }
+
+    /// Any answer counts, the registrar only has to be reachable
+    pub async fn probe(&self, timeout: Duration) -> Result<(), ClientError> {
+        self.client.get(self.url("")).timeout(timeout).send().await?;
+        Ok(())
+    }
+
+    /// Certificate the registrar presented on the provisional TLS connection (RFC 8995 Section 5.1),
+    /// `None` without TLS. Needs a client built with `tls_info(true)`.
+    pub async fn peer_certificate(&self, timeout: Duration) -> Result<Option<X509>, ClientError> {
+        let response = self.client.get(self.url("")).timeout(timeout).send().await?;
+        let certificate = response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(X509::from_der)
+            .transpose()?;
+        Ok(certificate)
+    }
+
+    pub async fn request_voucher(&self, pvr: PVR_JWS) -> Result<IssuedVoucherJWS, ClientError> {
+        let url = self.url("requestvoucher");
+        event!(Level::INFO, "Sending PVR to registrar at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JWS_VOUCHER)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .body(pvr.try_encoded_data()?);
+        let response = endpoint::send("requestvoucher", request, Some(JWS_VOUCHER)).await?;
+
+        Ok(IssuedVoucherJWS::Encoded(response.text().await?))
+    }
+
+    /// Returns the LDevID the registrar issued for the CSR in the PER.
+    /// The registrar expects the voucher media type as `Accept` here, even though it answers with PKCS#7.
+    pub async fn request_enroll(&self, per: PER_JWS) -> Result<X509, ClientError> {
+        let url = self.url("requestenroll");
+        event!(Level::INFO, "Sending PER to registrar at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, JWS_VOUCHER)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .body(per.try_encoded_data()?);
+        let response = endpoint::send("requestenroll", request, Some(PKCS7)).await?;
+
+        let data = response.bytes().await?;
+        event!(Level::DEBUG, "Enroll Response Data: Bytes with length {}", data.len());
+
+        Ok(asn1::certificate(&data)?)
+    }
+
+    pub async fn wrapped_ca_certs(&self) -> Result<CACERTS_JWS, ClientError> {
+        let url = self.url("wrappedcacerts");
+        event!(Level::INFO, "Requesting wrappedcacerts from registrar at: {}", url);
+
+        let request = self.client.get(url).header(ACCEPT, JOSE);
+        let response = endpoint::send("wrappedcacerts", request, Some(JOSE)).await?;
+
+        Ok(CACERTS_JWS::Encoded(response.text().await?))
+    }
+
+    /// EST `/cacerts`, the certificates of the base64 encoded certs-only PKCS#7 response
+    pub async fn ca_certs(&self) -> Result<Vec<X509>, ClientError> {
+        let url = self.url("cacerts");
+        event!(Level::INFO, "Requesting cacerts from registrar at: {}", url);
+
+        let request = self.client.get(url).header(ACCEPT, PKCS7);
+        let response = endpoint::send("cacerts", request, Some(PKCS7)).await?;
+
+        Ok(asn1::certs_only(&endpoint::decode_base64(&response.text().await?)?)?)
+    }
+
+    /// EST `/csrattrs`, the DER encoded CSR attributes
+    pub async fn csr_attrs(&self) -> Result<Vec<u8>, ClientError> {
+        let url = self.url("csrattrs");
+        event!(Level::INFO, "Requesting csrattrs from registrar at: {}", url);
+
+        let request = self.client.get(url).header(ACCEPT, CSRATTRS);
+        let response = endpoint::send("csrattrs", request, Some(CSRATTRS)).await?;
+
+        endpoint::decode_base64(&response.text().await?)
+    }
+
+    pub async fn voucher_status(&self, voucher_status: vStatus_JWS) -> Result<(), ClientError> {
+        let url = self.url("voucher_status");
+        event!(Level::INFO, "Sending Voucher Status to registrar at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, JOSE)
+            .body(voucher_status.try_encoded_data()?);
+        endpoint::send("voucher_status", request, None).await?;
+
+        Ok(())
+    }
+
+    pub async fn enroll_status(&self, enroll_status: EnrollStatusJWS) -> Result<(), ClientError> {
+        let url = self.url("enrollstatus");
+        event!(Level::INFO, "Sending Enroll Status to registrar at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, JOSE)
+            .body(enroll_status.try_encoded_data()?);
+        endpoint::send("enrollstatus", request, None).await?;
+
+        Ok(())
+    }
+
+    /// Returns the OpenSSH host certificate for the host key carried in the PER
+    pub async fn request_ssh_certificate(&self, per: PER_JWS) -> Result<String, ClientError> {
+        let url = self.url("requestsshcert");
+        event!(Level::INFO, "Requesting SSH host certificate from registrar at: {}", url);
+
+        let request = self
+            .client
+            .post(url)
+            .header(ACCEPT, SSH_CERTIFICATE)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .body(per.try_encoded_data()?);
+        let response = endpoint::send("requestsshcert", request, Some(SSH_CERTIFICATE)).await?;
+
+        Ok(response.text().await?)
+    }
+}
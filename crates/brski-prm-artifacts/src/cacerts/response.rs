@@ -10,6 +10,7 @@ pub struct Response {
     registrar_ldevid_certs: Vec<X509>,
 }
 
+#[allow(non_camel_case_types)]
 pub type CACERTS_JWS = JWS<ResponsePayload>;
 
 #[cfg(feature = "axum")]
@@ -20,7 +21,7 @@ impl axum::response::IntoResponse for CACERTS_JWS {
                 .header(axum::http::header::CONTENT_TYPE, content_type::JOSE)
                 .body(encoded.into())
                 .unwrap(),
-            JWS::Decoded(decoded) => (
+            JWS::Decoded(_decoded) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 "Server attempted to send a JWS that was not encoded".to_string(),
             )
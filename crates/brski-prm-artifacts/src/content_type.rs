@@ -1,6 +1,24 @@
 pub const JSON: &str = "application/json";
 pub const JWS_VOUCHER: &str = "application/voucher-jws+json";
+/// CMS signed vouchers and voucher-requests, RFC 8366 Section 8.3
+pub const CMS_VOUCHER: &str = "application/voucher-cms+json";
+
+/// Problem details, RFC 9457
+pub const PROBLEM_JSON: &str = "application/problem+json";
 
 pub const JOSE: &str = "application/jose+json";
 
-pub const PKCS7: &str = "application/pkcs7-mime";
\ No newline at end of file
+pub const PKCS7: &str = "application/pkcs7-mime";
+/// EST enrollment responses, RFC 7030 Section 4.2.3
+pub const PKCS7_CERTS_ONLY: &str = "application/pkcs7-mime; smime-type=certs-only";
+/// EST enrollment requests, RFC 7030 Section 4.2.1
+pub const PKCS10: &str = "application/pkcs10";
+/// DER encoded CRL, RFC 5280 Section 4.2.1.13
+pub const PKIX_CRL: &str = "application/pkix-crl";
+pub const SUIT_ENVELOPE: &str = "application/suit-envelope+cose";
+/// Firmware images described by a SUIT manifest
+pub const OCTET_STREAM: &str = "application/octet-stream";
+/// OpenSSH certificate in its single line `*-cert.pub` format
+pub const SSH_CERTIFICATE: &str = "application/vnd.open-brski.ssh-certificate";
+/// EST CSR attributes, RFC 7030 Section 4.5.2
+pub const CSRATTRS: &str = "application/csrattrs";
@@ -0,0 +1,16 @@
+use openssl::{
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{HasParams, PKeyRef},
+};
+
+/// The hash paired with the curve of an EC key, SHA-256 for all other keys
+pub fn message_digest<T: HasParams>(key: &PKeyRef<T>) -> MessageDigest {
+    let curve = key.ec_key().ok().and_then(|key| key.group().curve_name());
+
+    match curve {
+        Some(Nid::SECP384R1) => MessageDigest::sha384(),
+        Some(Nid::SECP521R1) => MessageDigest::sha512(),
+        _ => MessageDigest::sha256(),
+    }
+}
@@ -42,7 +42,7 @@ impl axum::response::IntoResponse for IssuedVoucherJWS {
                 .header(axum::http::header::CONTENT_TYPE, content_type::JWS_VOUCHER)
                 .body(encoded.into())
                 .unwrap(),
-            JWS::Decoded(decoded) => (
+            JWS::Decoded(_decoded) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 "Server attempted to send a JWS that was not encoded".to_string(),
             )
@@ -1,12 +1,10 @@
 use crate::content_type;
-use anyhow::Context;
 use ietf_voucher::VoucherRequest;
 #[cfg(feature = "json")]
-use josekit::{jwk::Jwk, jws::*, JoseError};
+use josekit::{jws::*, JoseError};
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
-use strum::Display;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
@@ -18,12 +16,17 @@ use strum::EnumIs;
 use super::decoded_jws::DecodedJWS;
 
 
+#[cfg(feature = "json")]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct Signature {
     protected: String,
+    /// Not covered by any signature, e.g. the time-stamp token of the MASA signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header: Option<serde_json::Map<String, serde_json::Value>>,
     signature: String
 }
 
+#[cfg(feature = "json")]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct EncodedJWS {
     payload: String,
@@ -64,20 +67,22 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
 
         let payload = data.payload;
 
-        info!("Gathering EcdsaSigner from keypair");
-        let signer = josekit::jws::alg::ecdsa::EcdsaJwsAlgorithm::Es256.signer_from_der(keypair)?;
+        info!("Gathering signer from keypair");
+        let signer = ietf_voucher::signer(keypair)?;
 
         // this is NOT base64 encoded! It is a compact JWS! We must encode it later!
         info!("Serializing payload into bytes");
-        let serialized = serde_json::to_vec(&payload).map_err(|_| {
-            josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not serialize payload"))
+        let serialized = ietf_voucher::canonical::to_vec(&payload).map_err(|err| {
+            josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not serialize payload: {}", err))
         })?;
 
         info!("Creating JWS Header from header_set");
-        let header = josekit::jws::JwsHeader::from_map(data.header_set.unwrap().to_map())?;
+        let mut header = josekit::jws::JwsHeader::from_map(data.header_set.unwrap().to_map())?;
+        // the caller's header set may name another algorithm, the signer's one derived from the key wins
+        header.set_algorithm(signer.algorithm().name());
 
         info!("Serializing JWS into compact format");
-        let serialized_jws = josekit::jws::serialize_compact(&serialized,&header, &signer)?;
+        let serialized_jws = josekit::jws::serialize_compact(&serialized,&header, signer.as_ref())?;
 
         Ok(JWS::Encoded(serialized_jws))
     }
@@ -97,19 +102,21 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
 
         let payload = data.payload;
 
-        info!("Gathering EcdsaSigner from keypair");
-        let signer = josekit::jws::alg::ecdsa::EcdsaJwsAlgorithm::Es256.signer_from_der(keypair)?;
+        info!("Gathering signer from keypair");
+        let signer = ietf_voucher::signer(keypair)?;
 
         // this is NOT base64 encoded! It is a compact JWS! We must encode it later!
         info!("Serializing payload into bytes");
-        let serialized = serde_json::to_vec(&payload).map_err(|_| {
-            josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not serialize payload"))
+        let serialized = ietf_voucher::canonical::to_vec(&payload).map_err(|err| {
+            josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not serialize payload: {}", err))
         })?;
 
-        let header_set = data.header_set.unwrap();
+        let mut header_set = data.header_set.unwrap();
+        // protected, so the signature covers the algorithm of the signer
+        header_set.set_algorithm(signer.algorithm().name(), true);
 
         info!("Serializing JWS into general JSON format");
-        let serialized_jws = josekit::jws::serialize_general_json(&serialized, &[(&header_set, &signer)])?;
+        let serialized_jws = josekit::jws::serialize_general_json(&serialized, &[(&header_set, signer.as_ref())])?;
 
         Ok(JWS::Encoded(serialized_jws))
     }
@@ -148,6 +155,42 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
     }
 
 
+    fn parse_general_json(&self) -> Result<EncodedJWS, josekit::JoseError> {
+        match self {
+            JWS::Encoded(data) => serde_json::from_str(data).map_err(|_| josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not deserialize JWS"))),
+            JWS::Decoded(_) => Err(josekit::JoseError::InvalidJwsFormat(anyhow::anyhow!("Expected encoded data"))),
+        }
+    }
+
+    /// Signature values of an encoded general JSON JWS, in the order of its signatures
+    pub fn signature_values(&self) -> Result<Vec<Vec<u8>>, josekit::JoseError> {
+        use base64::Engine;
+
+        self.parse_general_json()?
+            .signatures
+            .iter()
+            .map(|signature| {
+                base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .decode(&signature.signature)
+                    .map_err(|err| josekit::JoseError::InvalidJwsFormat(anyhow::anyhow!("Invalid signature encoding: {}", err)))
+            })
+            .collect()
+    }
+
+    /// Sets `name` in the unprotected header of the `index`th signature of an encoded general JSON JWS.
+    /// The signatures stay valid, as the unprotected header is not signed.
+    pub fn set_unprotected_header(self, index: usize, name: &str, value: serde_json::Value) -> Result<JWS<T>, josekit::JoseError> {
+        let mut deserialized_jws = self.parse_general_json()?;
+        let signature = deserialized_jws
+            .signatures
+            .get_mut(index)
+            .ok_or(josekit::JoseError::InvalidJwsFormat(anyhow::anyhow!("JWS has no signature {}", index)))?;
+        signature.header.get_or_insert_with(Default::default).insert(name.to_string(), value);
+
+        let encoded_jws = serde_json::to_string(&deserialized_jws).map_err(|_| josekit::JoseError::InvalidJson(anyhow::anyhow!("Could not serialize JWS")))?;
+        Ok(JWS::Encoded(encoded_jws))
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn try_encoded_data(self) -> Result<String, josekit::JoseError> {
         match self {
@@ -180,6 +223,7 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
         };
 
         debug!("Extracted data from JWS: {}", data);
+        let data = tolerant(&data);
 
         let mut jws_context = josekit::jws::JwsContext::new();
         info!("Adding 'created-on' to acceptable critical headers");
@@ -234,11 +278,10 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
         } 
 
         let data = match self {
-            JWS::Encoded(data) => data,
+            JWS::Encoded(data) => tolerant(data),
             JWS::Decoded(_) => unreachable!(),
         };
 
-
         let mut jws_context = josekit::jws::JwsContext::new();
         info!("Adding 'created-on' to acceptable critical headers");
         jws_context.add_acceptable_critical("created-on");
@@ -270,6 +313,32 @@ impl<T: Serialize + DeserializeOwned + std::clone::Clone + Debug> JWS<T> {
 
 }
 
+/// Restores the base64url members of a general or flattened JWS that an intermediary padded or re-encoded, see
+/// [`ietf_voucher::canonical`]. Signatures are verified over the payload as the signer encoded it, so a re-serialized
+/// outer JSON object does not break them either. Anything that is not a JWS object is left to the parser to reject.
+#[cfg(feature = "json")]
+fn tolerant(data: &str) -> String {
+    let Ok(mut jws) = serde_json::from_str::<serde_json::Value>(data) else {
+        return data.to_string();
+    };
+
+    let restore = |object: &mut serde_json::Value, names: &[&str]| {
+        for name in names {
+            if let Some(serde_json::Value::String(segment)) = object.get_mut(*name) {
+                *segment = ietf_voucher::canonical::base64url(segment).into_owned();
+            }
+        }
+    };
+    restore(&mut jws, &["payload", "protected", "signature"]);
+    if let Some(serde_json::Value::Array(signatures)) = jws.get_mut("signatures") {
+        for signature in signatures {
+            restore(signature, &["protected", "signature"]);
+        }
+    }
+
+    jws.to_string()
+}
+
 #[cfg(feature = "json")]
 fn get_jws_verifier(der: impl AsRef<[u8]>, header: &JwsHeader) -> Result<Option<Box<dyn JwsVerifier>>, JoseError> {
     match header.algorithm() {
@@ -304,7 +373,7 @@ impl axum::response::IntoResponse for JWS<VoucherRequest> {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    
 
     use super::*;
 
@@ -339,4 +408,129 @@ mod tests {
 
         assert_eq!(decoded_jws.try_decoded_data().unwrap().payload, payload.to_string());
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_unprotected_header_survives_inflight_signature() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (vendor_cert, vendor_key) = certs.vendor;
+        let (registrar_cert, registrar_key) = certs.registrar;
+
+        let mut header = josekit::jws::JwsHeaderSet::new();
+        header.set_x509_certificate_chain(&vec![vendor_cert.to_der().unwrap()], true);
+        header.set_algorithm(josekit::jws::ES256.to_string(), true);
+        let jws = JWS::Decoded(DecodedJWS {
+            payload: "Hello, World!".to_string(),
+            header_set: Some(header),
+            header: None
+        })
+        .encode(vendor_key.private_key_to_der().unwrap())
+        .unwrap();
+
+        assert_eq!(jws.signature_values().unwrap()[0].len(), 64);
+        let jws = jws.set_unprotected_header(0, "timestamp-token", "dG9rZW4".into()).unwrap();
+        assert!(jws.clone().set_unprotected_header(1, "timestamp-token", "dG9rZW4".into()).is_err());
+
+        let mut header = josekit::jws::JwsHeaderSet::new();
+        header.set_x509_certificate_chain(&vec![registrar_cert.to_der().unwrap()], true);
+        header.set_algorithm(josekit::jws::ES256.to_string(), true);
+        let jws = jws.add_signature(registrar_key.private_key_to_der().unwrap(), header).unwrap();
+
+        let encoded: serde_json::Value = serde_json::from_str(&jws.clone().try_encoded_data().unwrap()).unwrap();
+        assert_eq!(encoded["signatures"][0]["header"]["timestamp-token"], "dG9rZW4");
+        assert!(encoded["signatures"][1].get("header").is_none());
+        jws.verify().unwrap();
+        assert_eq!(jws.decode().unwrap().try_decoded_data().unwrap().payload, "Hello, World!".to_string());
+    }
+
+    #[test]
+    pub fn test_p384_pledge_signature() {
+        let certs: example_certs::OpensslTestCerts =
+            example_certs::generate_certs_with_pledge_curve(example_certs::Curve::P384).into();
+
+        let (pledge_cert, pledge_key) = certs.pledge;
+
+        let mut header = josekit::jws::JwsHeaderSet::new();
+        header.set_x509_certificate_chain(&vec![pledge_cert.to_der().unwrap()], true);
+        header.set_algorithm(josekit::jws::ES256.to_string(), true);
+
+        let jws = JWS::Decoded(DecodedJWS {
+            payload: "Hello, World!".to_string(),
+            header_set: Some(header),
+            header: None
+        });
+
+        // the ES256 header is replaced by the algorithm of the P-384 key
+        let jws = jws.encode(pledge_key.private_key_to_der().unwrap()).unwrap();
+        let decoded_jws = jws.decode().unwrap();
+        assert_eq!(decoded_jws.try_decoded_data().unwrap().payload, "Hello, World!".to_string());
+    }
+
+    #[test]
+    pub fn test_ed25519_signature() {
+        let key = openssl::pkey::PKey::generate_ed25519().unwrap();
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(openssl::nid::Nid::COMMONNAME, "MASA").unwrap();
+        let name = name.build();
+        let mut builder = openssl::x509::X509Builder::new().unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&key, openssl::hash::MessageDigest::null()).unwrap();
+        let cert = builder.build();
+
+        let mut header = josekit::jws::JwsHeaderSet::new();
+        header.set_x509_certificate_chain(&vec![cert.to_der().unwrap()], true);
+        let jws = JWS::Decoded(DecodedJWS {
+            payload: "Hello, World!".to_string(),
+            header_set: Some(header),
+            header: None
+        })
+        .encode(key.private_key_to_der().unwrap())
+        .unwrap();
+
+        assert_eq!(jws.signature_values().unwrap()[0].len(), 64);
+        let decoded_jws = jws.decode().unwrap().try_decoded_data().unwrap();
+        assert_eq!(decoded_jws.header.unwrap().algorithm(), Some("EdDSA"));
+        assert_eq!(decoded_jws.payload, "Hello, World!".to_string());
+    }
+
+    #[test]
+    pub fn test_canonical_payload_and_tolerant_decoding() {
+        use base64::Engine;
+
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (vendor_cert, vendor_key) = certs.vendor;
+
+        let mut header = josekit::jws::JwsHeaderSet::new();
+        header.set_x509_certificate_chain(&vec![vendor_cert.to_der().unwrap()], true);
+        let payload = serde_json::json!({"serial-number": "00-D0-E5-F2-00-02", "assertion": "verified"});
+        let jws = JWS::Decoded(DecodedJWS {
+            payload: payload.clone(),
+            header_set: Some(header),
+            header: None
+        })
+        .encode(vendor_key.private_key_to_der().unwrap())
+        .unwrap();
+
+        let mut encoded: serde_json::Value = serde_json::from_str(&jws.try_encoded_data().unwrap()).unwrap();
+        let signed = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(encoded["payload"].as_str().unwrap()).unwrap();
+        assert_eq!(signed, br#"{"assertion":"verified","serial-number":"00-D0-E5-F2-00-02"}"#);
+
+        // an intermediary that pads the segments and pretty prints the JWS does not break the signature
+        for pointer in ["/payload", "/signatures/0/signature"] {
+            let segment = encoded.pointer_mut(pointer).unwrap();
+            *segment = format!("{}==", segment.as_str().unwrap()).into();
+        }
+        let reencoded: JWS<serde_json::Value> = JWS::Encoded(serde_json::to_string_pretty(&encoded).unwrap());
+        reencoded.verify().unwrap();
+        assert_eq!(reencoded.decode().unwrap().try_decoded_data().unwrap().payload, payload);
+
+        // a re-serialized payload is a different payload
+        encoded["payload"] = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec_pretty(&payload).unwrap())
+            .into();
+        assert!(JWS::<serde_json::Value>::Encoded(encoded.to_string()).verify().is_err());
+    }
+}
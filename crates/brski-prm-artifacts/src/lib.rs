@@ -1,7 +1,10 @@
 pub mod content_type;
+#[cfg(feature = "openssl")]
+pub mod digest;
 pub mod error;
 pub mod issued_voucher;
 pub mod jws;
+pub mod network;
 pub mod per;
 pub mod pvr;
 pub mod rvr;
@@ -0,0 +1,92 @@
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::BRSKIPRMError;
+
+/// Production network the registrar-agent hands to an enrolled pledge, so it leaves the bootstrap network without
+/// being reflashed. Not part of BRSKI-PRM, the pledge only accepts it after its LDevID was stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkProfile {
+    pub ssid: String,
+    pub security: NetworkSecurity,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum NetworkSecurity {
+    Wpa2Psk {
+        psk: String,
+    },
+    /// WPA2-Enterprise with the LDevID as client certificate, the authentication server has to chain to the
+    /// pinned domain CA
+    EapTls {
+        /// Outer EAP identity, the serial-number of the pledge if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        identity: Option<String>,
+    },
+}
+
+impl fmt::Debug for NetworkSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkSecurity::Wpa2Psk { .. } => f.debug_struct("Wpa2Psk").field("psk", &"..").finish(),
+            NetworkSecurity::EapTls { identity } => f.debug_struct("EapTls").field("identity", identity).finish(),
+        }
+    }
+}
+
+impl NetworkProfile {
+    /// Checks the limits of 802.11, so a profile the pledge can not join is rejected before it is handed out
+    pub fn validate(&self) -> Result<(), BRSKIPRMError> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err(BRSKIPRMError::Malformed("SSID must have 1 to 32 bytes".to_string()));
+        }
+
+        match &self.security {
+            NetworkSecurity::Wpa2Psk { psk } if !(8..=63).contains(&psk.len()) || !psk.is_ascii() => {
+                Err(BRSKIPRMError::Malformed("WPA2 passphrase must have 8 to 63 ASCII characters".to_string()))
+            }
+            NetworkSecurity::EapTls { identity: Some(identity) } if identity.is_empty() || identity.len() > 128 => {
+                Err(BRSKIPRMError::Malformed("EAP identity must have 1 to 128 bytes".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let profile = NetworkProfile {
+            ssid: "plant-floor".to_string(),
+            security: NetworkSecurity::EapTls { identity: None },
+        };
+        let json = serde_json::to_string(&profile).unwrap();
+        assert_eq!(json, r#"{"ssid":"plant-floor","security":{"type":"eap-tls"}}"#);
+        assert_eq!(serde_json::from_str::<NetworkProfile>(&json).unwrap(), profile);
+
+        let profile: NetworkProfile =
+            serde_json::from_str(r#"{"ssid":"plant-floor","security":{"type":"wpa2-psk","psk":"correct horse"}}"#).unwrap();
+        assert!(profile.validate().is_ok());
+        assert!(!format!("{:?}", profile).contains("correct horse"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let psk = |psk: &str| NetworkProfile {
+            ssid: "plant-floor".to_string(),
+            security: NetworkSecurity::Wpa2Psk { psk: psk.to_string() },
+        };
+        assert!(psk("short").validate().is_err());
+        assert!(psk(&"x".repeat(64)).validate().is_err());
+
+        let mut profile = psk("correct horse");
+        profile.ssid = "x".repeat(33);
+        assert!(profile.validate().is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use ietf_voucher::pki::X509;
 use crate::content_type;
 use crate::jws::JWS;
-use crate::per::response_payload::ResponsePayload;
+use crate::per::response_payload::{RawResponsePayload, ResponsePayload};
 
 #[derive(Debug)]
 pub struct Response {
@@ -9,8 +9,13 @@ pub struct Response {
     pledge_idevid_certs: Vec<X509>,
 }
 
+#[allow(non_camel_case_types)]
 pub type PER_JWS = JWS<ResponsePayload>;
 
+/// [`PER_JWS`] with the CSR left as DER
+#[allow(non_camel_case_types)]
+pub type RAW_PER_JWS = JWS<RawResponsePayload>;
+
 #[cfg(feature = "axum")]
 impl axum::response::IntoResponse for PER_JWS {
     fn into_response(self) -> axum::response::Response {
@@ -19,7 +24,7 @@ impl axum::response::IntoResponse for PER_JWS {
                 .header(axum::http::header::CONTENT_TYPE, content_type::JOSE)
                 .body(encoded.into())
                 .unwrap(),
-            JWS::Decoded(decoded) => (
+            JWS::Decoded(_decoded) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 "Server attempted to send a JWS that was not encoded".to_string(),
             )
@@ -68,14 +73,12 @@ impl Response {
 #[cfg(test)]
 mod tests {
 
-    use base64::{Engine};
-    
     use serde_json::json;
 
     use crate::{
         per::{
             response::{Response, PER_JWS},
-            response_payload::ResponsePayload,
+            response_payload::{RawResponsePayload, ResponsePayload},
         },
     };
 
@@ -104,7 +107,7 @@ mod tests {
     pub fn test_response_payload_serialization() {
         let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
 
-        let (pledge_cert, pledge_key) = certs.pledge;
+        let (_pledge_cert, pledge_key) = certs.pledge;
 
         let csr = ResponsePayload::try_new(&pledge_key);
 
@@ -121,9 +124,13 @@ mod tests {
         let json = json!({
             "ietf-ztp-types":{ "p10-csr": "MIIBWzCCAQICAQAwgZ8xFDASBgNVBAMMC2NvbW1vbl9uYW1lMQswCQYDVQQGEwJERTEQMA4GA1UECAwHQmF2YXJpYTEPMA0GA1UEBwwGTXVuaWNoMS4wLAYDVQQKDCVVbml2ZXJzaXR5IG9mIEFwcGxpZWQgU2NpZW5jZXMgTXVuaWNoMScwJQYDVQQLDB5EZXBhcnRtZW50IG9mIENvbXB1dGVyIFNjaWVuY2UwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAQMJsyFnfXanjWCNimECrucESP0W7NVHhLHDqZntHhAY2f3ZVGMrXRsAzNt+kRoBXOg6PCGV3tsHrxyCaMs2Ja3oAAwCgYIKoZIzj0EAwIDRwAwRAIgTMtHRzt0zIzbjW5lBFGBlutvm7O+MGPuLWTNJR2ZIDcCICkQ9Ytnp8CuxyT58BhL/TrX44Z3elZiU9uWqWr1Fz7E"}
         });
-        let deserialized = serde_json::from_value::<ResponsePayload>(json);
+        let deserialized = serde_json::from_value::<ResponsePayload>(json.clone());
+
+        assert!(deserialized.is_ok());
 
-        assert!(deserialized.is_ok())
+        // the raw payload keeps the same DER without parsing it
+        let raw = serde_json::from_value::<RawResponsePayload>(json).unwrap();
+        assert_eq!(raw.csr.p10_csr, deserialized.unwrap().csr.p10_csr.as_ref());
     }
 
     #[test]
@@ -7,6 +7,9 @@ use serde_with::serde_as;
 pub struct ResponsePayload {
     #[serde(rename = "ietf-ztp-types")]
     pub csr: ResponsePayloadInner,
+    /// OpenSSH public host key the registrar should issue an SSH host certificate for
+    #[serde(rename = "ssh-host-key", default, skip_serializing_if = "Option::is_none")]
+    pub ssh_host_key: Option<String>,
 }
 
 #[serde_as]
@@ -17,12 +20,32 @@ pub struct ResponsePayloadInner {
     pub p10_csr: X509Req,
 }
 
+/// [`ResponsePayload`] with the CSR left as DER, for receivers that decode it with their own parser before openssl
+/// sees it
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RawResponsePayload {
+    #[serde(rename = "ietf-ztp-types")]
+    pub csr: RawResponsePayloadInner,
+    #[serde(rename = "ssh-host-key", default, skip_serializing_if = "Option::is_none")]
+    pub ssh_host_key: Option<String>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawResponsePayloadInner {
+    #[serde_as(as = "Base64")]
+    pub p10_csr: Vec<u8>,
+}
+
 #[cfg(feature = "openssl")]
 impl ResponsePayload {
     pub fn try_new(
         keypair: &openssl::pkey::PKey<openssl::pkey::Private>,
     ) -> Result<ResponsePayload, openssl::error::ErrorStack> {
         let mut cert_req = openssl::x509::X509Req::builder()?;
+        // PKCS#10 only defines version 1, the registrar does not verify other versions
+        cert_req.set_version(0)?;
         cert_req.set_pubkey(keypair).unwrap();
         let mut parsed_subject_name = openssl::x509::X509NameBuilder::new()?;
         parsed_subject_name.append_entry_by_text("CN", "common_name")?;
@@ -32,13 +55,14 @@ impl ResponsePayload {
         parsed_subject_name.append_entry_by_text("O", "University of Applied Sciences Munich")?;
         parsed_subject_name.append_entry_by_text("OU", "Department of Computer Science")?;
         cert_req.set_subject_name(&parsed_subject_name.build())?;
-        cert_req.sign(keypair, openssl::hash::MessageDigest::sha256())?;
+        cert_req.sign(keypair, crate::digest::message_digest(keypair))?;
 
         let req = cert_req.build();
         Ok(ResponsePayload {
             csr: ResponsePayloadInner {
                 p10_csr: req.into(),
             },
+            ssh_host_key: None,
         })
     }
 }
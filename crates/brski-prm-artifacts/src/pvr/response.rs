@@ -15,6 +15,7 @@ pub struct Response {
     pledge_idevid_certs: Vec<X509>,
 }
 
+#[allow(non_camel_case_types)]
 pub type PVR_JWS = JWS<VoucherRequest>;
 
 #[cfg(feature = "json")]
@@ -84,7 +85,7 @@ mod tests {
     fn test_encode_decode_response() {
         let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
 
-        let c = example_certs::generate_certs();
+        let _c = example_certs::generate_certs();
 
         let created_on = chrono::DateTime::parse_from_rfc3339("2022-04-26T05:16:17.709Z")
             .unwrap()
@@ -60,7 +60,6 @@ impl TryFrom<TriggerOptions<'_>> for Trigger {
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-
 pub struct Trigger {
     /// base-64 encoded registrar EE TLS certificate
     #[serde_as(as = "Base64")]
@@ -81,7 +80,6 @@ impl fmt::Display for Trigger {
 }
 
 #[cfg(test)]
-
 mod tests {
     use chrono::Utc;
     use example_certs::OpensslTestCerts;
@@ -11,6 +11,7 @@ pub struct RVR {
     registrar_ldevid_certs: Vec<X509>,
 }
 
+#[allow(non_camel_case_types)]
 pub type RVR_JWS = JWS<VoucherRequest>;
 
 #[cfg(feature = "json")]
@@ -107,7 +108,7 @@ mod tests {
         let encoded = jws
             .encode(registrar_key.private_key_to_der().unwrap()).unwrap();
         
-        let payload: VoucherRequest = encoded.decode().unwrap().try_decoded_data().unwrap().payload;
+        let _payload: VoucherRequest = encoded.decode().unwrap().try_decoded_data().unwrap().payload;
 
     }
 }
\ No newline at end of file
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// Counters a pledge puts into its status telemetry, signed along with it. Pledges without a persistent counter leave
+/// them out.
+/// Both are persisted by the pledge and never decrease, so a registrar that receives a counter it has seen before
+/// deals with a pledge that was rolled back to an earlier state, a replay or a clone reporting the same state.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CounterAttestation {
+    /// Incremented on every start of the pledge
+    pub boot_count: u64,
+    /// Incremented for every status the pledge sends, across restarts
+    pub monotonic_counter: u64,
+}
+
+impl CounterAttestation {
+    /// Whether `self` was sent after `previous` by the same pledge
+    pub fn follows(&self, previous: &CounterAttestation) -> bool {
+        self.monotonic_counter > previous.monotonic_counter && self.boot_count >= previous.boot_count
+    }
+}
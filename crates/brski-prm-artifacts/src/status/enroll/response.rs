@@ -22,7 +22,7 @@ impl axum::response::IntoResponse for EnrollStatusJWS {
                 .header(axum::http::header::CONTENT_TYPE, content_type::JOSE)
                 .body(encoded.into())
                 .unwrap(),
-            JWS::Decoded(decoded) => (
+            JWS::Decoded(_decoded) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 "Server attempted to send a JWS that was not encoded".to_string(),
             )
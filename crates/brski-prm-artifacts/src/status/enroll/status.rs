@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::status::{attestation::CounterAttestation, reason_code::ReasonCode};
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub struct ReasonContext {
@@ -12,7 +14,12 @@ pub struct Status {
     pub version: u32,
     pub status: bool,
     pub reason: String,
+    /// Pledges predating reason codes explain a failed enrollment only by `reason`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<ReasonCode>,
     pub reason_context: ReasonContext,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<CounterAttestation>,
 }
 
 impl Default for Status {
@@ -21,9 +28,11 @@ impl Default for Status {
             version: 1,
             status: true,
             reason: "Enroll-Response successfully processed".to_string(),
+            reason_code: Some(ReasonCode::Success),
             reason_context: ReasonContext {
                 pes_details: "JSON".to_string(),
             },
+            attestation: None,
         }
     }
 }
@@ -1,3 +1,5 @@
+pub mod attestation;
 pub mod enroll;
 pub mod pledge;
+pub mod reason_code;
 pub mod voucher;
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::status::{attestation::CounterAttestation, reason_code::ReasonCode};
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub struct QueryContext {
@@ -39,7 +41,12 @@ pub struct PledgeStatus {
     pub version: u32,
     pub status: PledgeStatusDetails,
     pub reason: Option<String>,
+    /// Missing in the pledge status of pledges predating reason codes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<ReasonCode>,
     pub reason_context: Option<StatusContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<CounterAttestation>,
 }
 
 impl Default for PledgeStatus {
@@ -48,7 +55,9 @@ impl Default for PledgeStatus {
             version: 1,
             status: PledgeStatusDetails::FactoryDefault,
             reason: None,
+            reason_code: None,
             reason_context: None,
+            attestation: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Machine readable cause carried next to the free-text `reason` of voucher and enroll status telemetry,
+/// so failures can be aggregated across pledges. Codes unknown to the receiver are read as [`ReasonCode::Unknown`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReasonCode {
+    Success,
+    /// The artifact could not be parsed or has an unsupported format
+    MalformedArtifact,
+    InvalidSignature,
+    /// The voucher is not signed by a trusted MASA
+    UntrustedMasa,
+    NonceMismatch,
+    SerialNumberMismatch,
+    VoucherExpired,
+    MissingPinnedDomainCert,
+    /// The LDevID does not chain to the pinned domain certificate
+    UntrustedCertificate,
+    /// The public key of the LDevID is not the one of the pledge
+    KeyMismatch,
+    /// The bootstrap network intercepts connections with a captive portal, so the registrar can not be reached
+    CaptivePortal,
+    /// No join proxy or registrar answered the pledge
+    RegistrarUnreachable,
+    InternalError,
+    #[serde(other)]
+    Unknown,
+}
+
+impl ReasonCode {
+    pub fn is_success(&self) -> bool {
+        *self == ReasonCode::Success
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReasonCode::Success => "Successfully processed",
+            ReasonCode::MalformedArtifact => "Artifact is malformed",
+            ReasonCode::InvalidSignature => "Signature is invalid",
+            ReasonCode::UntrustedMasa => "Voucher is not signed by a trusted MASA",
+            ReasonCode::NonceMismatch => "Nonce does not match the request",
+            ReasonCode::SerialNumberMismatch => "Serial-number does not match the pledge",
+            ReasonCode::VoucherExpired => "Voucher has expired",
+            ReasonCode::MissingPinnedDomainCert => "Voucher has no pinned-domain-cert",
+            ReasonCode::UntrustedCertificate => "Certificate does not chain to the pinned domain certificate",
+            ReasonCode::KeyMismatch => "Certificate is not issued for the pledge key",
+            ReasonCode::CaptivePortal => "Bootstrap network is behind a captive portal",
+            ReasonCode::RegistrarUnreachable => "Registrar could not be reached",
+            ReasonCode::InternalError => "Internal error",
+            ReasonCode::Unknown => "Unknown reason",
+        }
+    }
+}
+
+impl std::fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason_code_wire_format() {
+        assert_eq!(serde_json::to_string(&ReasonCode::NonceMismatch).unwrap(), "\"nonce-mismatch\"");
+        assert_eq!(serde_json::from_str::<ReasonCode>("\"key-mismatch\"").unwrap(), ReasonCode::KeyMismatch);
+        assert_eq!(serde_json::from_str::<ReasonCode>("\"some-future-code\"").unwrap(), ReasonCode::Unknown);
+    }
+}
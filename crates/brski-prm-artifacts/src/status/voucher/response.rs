@@ -11,6 +11,7 @@ pub struct Response {
     pledge_idevid_certs: Vec<X509>,
 }
 
+#[allow(non_camel_case_types)]
 pub type vStatus_JWS = JWS<Status>;
 
 #[cfg(feature = "axum")]
@@ -21,7 +22,7 @@ impl axum::response::IntoResponse for vStatus_JWS {
                 .header(axum::http::header::CONTENT_TYPE, content_type::JOSE)
                 .body(encoded.into())
                 .unwrap(),
-            JWS::Decoded(decoded) => (
+            JWS::Decoded(_decoded) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 "Server attempted to send a JWS that was not encoded".to_string(),
             )
@@ -1,8 +1,8 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::NullableConfig, masa_config::NullableMasaConfig, pledge_config::NullablePledgeConfig, registrar_agent_config::NullableRegistrarAgentConfig, registrar_config::NullableRegistrarConfig
+    masa_config::NullableMasaConfig, pledge_config::NullablePledgeConfig, registrar_agent_config::NullableRegistrarAgentConfig, registrar_config::NullableRegistrarConfig
 };
 
 #[derive(Parser)]
@@ -12,6 +12,8 @@ pub struct Cli {
     pub command: Command,
 }
 
+// parsed once at startup, so the size of the config variants does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Command {
     RegistrarAgent(NullableRegistrarAgentConfig),
@@ -20,7 +22,20 @@ pub enum Command {
     Pledge(NullablePledgeConfig),
 
     All,
-    TestCerts,
+    TestCerts(TestCertsArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TestCertsArgs {
+    /// Curve of the generated pledge IDevID
+    #[arg(long, value_enum, default_value_t = PledgeCurve::P256)]
+    pub pledge_curve: PledgeCurve,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PledgeCurve {
+    P256,
+    P384,
 }
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub enum OperatingMode {
@@ -28,6 +43,8 @@ pub enum OperatingMode {
     Registrar,
     Masa,
     Pledge,
+    /// Only the circuit proxy of the registrar
+    JoinProxy,
     TestCerts,
     All,
     #[default] None,
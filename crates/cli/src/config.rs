@@ -1,19 +1,21 @@
-use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
 use crate::cli::{Cli, OperatingMode};
 
-pub use crate::masa_config::MasaConfig;
+pub use crate::masa_config::{
+    CrlConfig, DeviceCloudConfig, MasaConfig, TimestampConfig, VoucherAlgorithm, VoucherManufacturerConfig, VoucherSignerConfig,
+    VoucherSigningConfig, VoucherValidity,
+};
 use crate::masa_config::NullableMasaConfig;
 use crate::pledge_config::NullablePledgeConfig;
-pub use crate::pledge_config::PledgeConfig;
+pub use crate::pledge_config::{ArtifactFormat, PledgeConfig};
 use crate::registrar_agent_config::NullableRegistrarAgentConfig;
-pub use crate::registrar_agent_config::RegistrarAgentConfig;
+pub use crate::registrar_agent_config::{PledgeTransport, RegistrarAgentConfig};
 use crate::registrar_config::NullableRegistrarConfig;
-pub use crate::registrar_config::RegistrarConfig;
+pub use crate::registrar_config::{AdmissionWindowConfig, CaBackend, CaptureConfig, CoapsConfig, JoinProxyConfig, ManufacturerConfig, MdnsConfig, ProxyConfig, RegistrarConfig, SnmpConfig, UpstreamCaConfig};
 use crate::validate::Validate;
-use crate::Command;
+use crate::{Command, RegistrarCommand};
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Config {
@@ -30,6 +32,14 @@ impl Validate for Config {
             OperatingMode::Registrar => self.registrar.validate(),
             OperatingMode::Masa => self.masa.validate(),
             OperatingMode::Pledge => self.pledge.validate(),
+            // the proxy runs without the keys and certificates of the registrar
+            OperatingMode::JoinProxy => {
+                self.registrar.join_proxy.validate()?;
+                if self.registrar.mdns.enabled {
+                    self.registrar.mdns.validate()?;
+                }
+                Ok(())
+            }
             OperatingMode::RegistrarAgent => self.registrar_agent.validate(),
             OperatingMode::TestCerts => Ok(()),
             OperatingMode::None => Ok(()),
@@ -76,6 +86,11 @@ impl From<Cli> for NullableConfig {
                 operating_mode: OperatingMode::RegistrarAgent,
                 ..Default::default()
             },
+            Command::Registrar(conf) if conf.command == Some(RegistrarCommand::JoinProxy) => NullableConfig {
+                registrar: Some(conf),
+                operating_mode: OperatingMode::JoinProxy,
+                ..Default::default()
+            },
             Command::Registrar(conf) => NullableConfig {
                 registrar: Some(conf),
                 operating_mode: OperatingMode::Registrar,
@@ -86,12 +101,18 @@ impl From<Cli> for NullableConfig {
                 operating_mode: OperatingMode::Masa,
                 ..Default::default()
             },
+            // the doctor reports configuration problems itself instead of failing validation
+            Command::Pledge(conf) if conf.command.is_some() => NullableConfig {
+                pledge: Some(conf),
+                operating_mode: OperatingMode::None,
+                ..Default::default()
+            },
             Command::Pledge(conf) => NullableConfig {
                 pledge: Some(conf),
                 operating_mode: OperatingMode::Pledge,
                 ..Default::default()
             },
-            Command::TestCerts => NullableConfig {
+            Command::TestCerts(_) => NullableConfig {
                 operating_mode: OperatingMode::TestCerts,
                 ..Default::default()
             },
@@ -8,7 +8,9 @@ mod registrar_config;
 mod util;
 mod validate;
 
-pub use cli::Command;
+pub use cli::{Command, PledgeCurve};
+pub use pledge_config::PledgeCommand;
+pub use registrar_config::RegistrarCommand;
 
 use clap::Parser;
 use cli::Cli;
@@ -26,6 +28,7 @@ pub fn get_config() -> anyhow::Result<Config, AppError> {
 }
 
 #[cfg(test)]
+#[allow(clippy::result_large_err)]
 mod tests {
     use super::*;
 
@@ -106,4 +109,147 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn it_parses_a_subordinate_registrar() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                [registrar]
+                parent_registrar_url = "http://parent-registrar:3001"
+            "#,
+            )?;
+
+            let config = get_config().unwrap();
+
+            assert_eq!(
+                config.registrar.parent_registrar_url.as_deref(),
+                Some("http://parent-registrar:3001")
+            );
+            assert_eq!(config.registrar.masa_url, "http://localhost:3000");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn it_parses_a_masa_proxy() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                [registrar.masa_proxy]
+                url = "socks5h://proxy.example.com:1080"
+                username = "registrar"
+                password = "secret"
+                no_proxy = ["localhost"]
+            "#,
+            )?;
+
+            let config = get_config().unwrap();
+
+            assert_eq!(config.registrar.masa_proxy.url.as_deref(), Some("socks5h://proxy.example.com:1080"));
+            assert_eq!(config.registrar.masa_proxy.username.as_deref(), Some("registrar"));
+            assert_eq!(config.registrar.masa_proxy.no_proxy, vec!["localhost"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn it_parses_an_snmp_subagent() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                [registrar.snmp]
+                enabled = true
+                master_address = "tcp:localhost:705"
+                base_oid = ".1.3.6.1.4.1.32473.1"
+            "#,
+            )?;
+
+            let config = get_config().unwrap();
+
+            use crate::validate::Validate;
+            let snmp = &config.registrar.snmp;
+            assert!(snmp.enabled);
+            assert_eq!(snmp.master_address, "tcp:localhost:705");
+            assert_eq!(snmp.base_oid().unwrap(), vec![1, 3, 6, 1, 4, 1, 32473, 1]);
+            assert_eq!(snmp.reconnect_secs, 15);
+            snmp.validate().unwrap();
+
+            let mut invalid = snmp.clone();
+            invalid.master_address = "tcp:localhost".to_owned();
+            assert!(invalid.validate().is_err());
+            invalid.master_address = "/var/agentx/master".to_owned();
+            invalid.base_oid = "1.3.6.1.enterprises".to_owned();
+            assert!(invalid.validate().is_err());
+            // there is no default subtree
+            invalid.base_oid = String::new();
+            assert!(invalid.validate().is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn it_parses_an_upstream_ca() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                [registrar]
+                ca_backend = "est"
+
+                [registrar.upstream_ca]
+                url = "https://ca.example.com/.well-known/est"
+                username = "registrar"
+            "#,
+            )?;
+
+            let config = get_config().unwrap();
+
+            assert_eq!(config.registrar.ca_backend, crate::config::CaBackend::Est);
+            assert!(!config.registrar.ca_backend.is_local());
+            assert_eq!(config.registrar.upstream_ca.url.as_deref(), Some("https://ca.example.com/.well-known/est"));
+            assert_eq!(config.registrar.upstream_ca.username.as_deref(), Some("registrar"));
+            assert!(config.registrar.upstream_ca.validate(config.registrar.ca_backend).is_ok());
+
+            // CSRs and the EST credentials are not sent to a plain http upstream
+            let mut plain = config.registrar.upstream_ca.clone();
+            plain.url = Some("http://ca.example.com/.well-known/est".to_owned());
+            assert!(plain.validate(crate::config::CaBackend::Est).is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn it_parses_voucher_signing() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                [masa.voucher_signing]
+                algorithms = ["ES384", "ES256"]
+
+                [masa.voucher_signing.manufacturers.constrained]
+                idevid_issuers = ["Constrained IDevID CA"]
+                algorithms = ["EdDSA", "ES256"]
+            "#,
+            )?;
+
+            let config = get_config().unwrap();
+
+            use crate::config::VoucherAlgorithm;
+            let voucher_signing = &config.masa.voucher_signing;
+            assert_eq!(voucher_signing.algorithms, vec![VoucherAlgorithm::ES384, VoucherAlgorithm::ES256]);
+            assert_eq!(voucher_signing.manufacturers["constrained"].algorithms, vec![VoucherAlgorithm::EdDSA, VoucherAlgorithm::ES256]);
+            assert!(voucher_signing.signers.is_empty());
+
+            Ok(())
+        })
+    }
 }
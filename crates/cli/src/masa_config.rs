@@ -1,7 +1,15 @@
+use std::collections::HashMap;
+
 use crate::util::parse_relative_path_buf;
+use common::auth::ApiKey;
+use common::revocation::RevocationMode;
+use common::serial_pattern::parse_serial_patterns;
 use anyhow::anyhow;
-use clap::{arg, Args};
+use clap::Args;
 use figment::value::magic::RelativePathBuf;
+use common::http_server::HttpServerConfig;
+use common::verification::VerificationConfig;
+use common::limits::ArtifactLimits;
 use serde::{Deserialize, Serialize};
 
 use crate::validate::Validate;
@@ -14,7 +22,315 @@ pub struct MasaConfig {
     pub masa_certificate: RelativePathBuf,
     pub masa_key: RelativePathBuf,
     pub registrar_ee_certificate: RelativePathBuf,
+    pub admin_api_keys: Vec<ApiKey>,
+    pub oidc_issuer: Option<String>,
+    pub oidc_audience: Option<String>,
+    pub manual_approval: bool,
+    /// Evaluates the voucher policy without enforcing it, voucher requests it would deny are only logged
+    pub policy_dry_run: bool,
+    pub integrator_ca_certificates: Vec<RelativePathBuf>,
+    /// Keyed by serial-number pattern, see `common::serial_pattern::SerialPattern`
+    pub additional_configuration: HashMap<String, String>,
+    /// Persists the audit log as JSON lines, one entry per voucher request, to this append-only file
+    pub audit_log_file: Option<RelativePathBuf>,
+    /// Persists the audit log to this SQLite database instead, only one of the two may be set
+    pub audit_log_database: Option<RelativePathBuf>,
+    /// Alert when this many registrars request vouchers for the same pledge within a day, 0 disables the check
+    pub anomaly_registrars_per_serial: usize,
+    /// Alert when this many nonceless voucher requests arrive within a minute, 0 disables the check
+    pub anomaly_nonceless_burst: usize,
+    /// Receives anomaly alerts as JSON POST requests, retried twice with backoff
+    pub anomaly_webhook_url: Option<String>,
+    /// Revocation checking of registrar certificates
+    pub registrar_revocation: RevocationMode,
+    /// Issuers of registrar certificates, needed to check their revocation status
+    pub registrar_ca_certificates: Vec<RelativePathBuf>,
+    /// Seconds after which the cached CRLs of registrar certificate issuers are fetched again
+    pub revocation_refresh_secs: u64,
+    /// Days before the MASA, CA, voucher signer or integrator CA certificates expire from which they are alerted
+    pub expiry_warning_days: u32,
+    /// Seconds between two expiry checks
+    pub expiry_check_secs: u64,
+    /// Receives expiry alerts as JSON POST requests
+    pub expiry_webhook_url: Option<String>,
+    /// Maximum request body sizes per artifact
+    pub artifact_limits: ArtifactLimits,
+    /// Limits of the plain HTTP server registrars send their voucher requests to
+    pub http: HttpServerConfig,
+    /// Worker pool verifying signatures and certificate chains
+    pub verification: VerificationConfig,
+    /// Seconds the response to a voucher request with an `Idempotency-Key` is replayed to retries, 0 disables it
+    pub idempotency_key_ttl_secs: u64,
+    /// Idempotency keys kept at most, a new key evicts the oldest replayable response
+    pub idempotency_key_capacity: usize,
+    /// Expiry of the issued vouchers
+    pub voucher_validity: VoucherValidity,
+    /// CSV, JSON or SQLite file mapping serial-numbers to the domain IDs of their owners.
+    /// Vouchers are only issued to owners if set
+    pub device_registry: Option<RelativePathBuf>,
+    /// RFC 3161 time-stamps of issued vouchers and audit log checkpoints
+    pub timestamping: TimestampConfig,
+    /// Registrar certificates revoked by the manufacturer and the CRL listing them
+    pub crl: CrlConfig,
+    /// Reports issued vouchers to the device management cloud of the manufacturer
+    pub device_cloud: DeviceCloudConfig,
+    /// Exports counters of voucher requests, issued vouchers and denials and the request latency at `/admin/metrics`
+    pub voucher_metrics: bool,
+    /// Signing keys and signature algorithms of the vouchers, per manufacturer
+    pub voucher_signing: VoucherSigningConfig,
+}
+
+/// JWS algorithms the MASA signs vouchers with
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoucherAlgorithm {
+    ES256,
+    ES384,
+    EdDSA,
+}
+
+impl VoucherAlgorithm {
+    /// The `alg` header parameter
+    pub fn name(self) -> &'static str {
+        match self {
+            VoucherAlgorithm::ES256 => "ES256",
+            VoucherAlgorithm::ES384 => "ES384",
+            VoucherAlgorithm::EdDSA => "EdDSA",
+        }
+    }
+}
+
+impl std::fmt::Display for VoucherAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Vouchers are signed with `masa_key` unless more `signers` are configured. The algorithm of a signer follows its
+/// key: ES256 for P-256, ES384 for P-384 and EdDSA for Ed25519 keys.
+/// The MASA picks the first algorithm of the preference list that the pledge supports. A pledge supports ES256, which
+/// is mandatory to implement, and the algorithm it signed its voucher request with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct VoucherSigningConfig {
+    /// Signing keys in addition to `masa_key`, their certificates must be issued by the MASA CA
+    pub signers: Vec<VoucherSignerConfig>,
+    /// Algorithms in order of preference, if empty `masa_key` and then the signers in order
+    pub algorithms: Vec<VoucherAlgorithm>,
+    /// Preferences per manufacturer, keyed by a name used in logs
+    pub manufacturers: HashMap<String, VoucherManufacturerConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VoucherSignerConfig {
+    pub certificate: RelativePathBuf,
+    pub key: RelativePathBuf,
+}
+
+/// Selected by the issuer of the IDevID that signed the pledge voucher request, given as common name or as hex
+/// encoded authority key identifier
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct VoucherManufacturerConfig {
+    pub idevid_issuers: Vec<String>,
+    /// Algorithms in order of preference, replacing the global ones
+    pub algorithms: Vec<VoucherAlgorithm>,
+}
+
+impl Validate for VoucherSigningConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        for signer in &self.signers {
+            if !signer.certificate.relative().exists() {
+                return Err(anyhow!("voucher_signing certificate {:?} does not exist", signer.certificate.relative()));
+            }
+            if !signer.key.relative().exists() {
+                return Err(anyhow!("voucher_signing key {:?} does not exist", signer.key.relative()));
+            }
+        }
+
+        let mut issuers = std::collections::HashSet::new();
+        for (name, manufacturer) in &self.manufacturers {
+            if manufacturer.idevid_issuers.is_empty() {
+                return Err(anyhow!("voucher_signing manufacturer {} has no idevid_issuers", name));
+            }
+            if manufacturer.algorithms.is_empty() {
+                return Err(anyhow!("voucher_signing manufacturer {} has no algorithms", name));
+            }
+            if let Some(issuer) = manufacturer.idevid_issuers.iter().find(|issuer| !issuers.insert(issuer.as_str())) {
+                return Err(anyhow!("IDevID issuer {} is configured for more than one voucher_signing manufacturer", issuer));
+            }
+        }
+        Ok(())
+    }
 }
+
+/// Every issued voucher is reported with an HTTP request built from templates. `{{serial-number}}`,
+/// `{{domain-id}}`, `{{registrar}}`, `{{nonce}}`, `{{nonceless}}`, `{{assertion}}`, `{{expires-on}}` and
+/// `{{issued-at}}` are replaced by the values of the issued voucher, percent-encoded in the URL and JSON escaped in
+/// JSON bodies. Absent values are replaced by an empty string.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeviceCloudConfig {
+    /// URL template of the device management API, reporting is off if unset
+    pub url: Option<String>,
+    pub method: String,
+    /// Header templates, e.g. `Authorization`
+    pub headers: HashMap<String, String>,
+    /// Body template, the issued voucher's audit log entry as JSON if unset
+    pub body: Option<String>,
+    pub content_type: String,
+    pub timeout_secs: u64,
+    /// Attempts per report, with exponential backoff from one second
+    pub attempts: u32,
+}
+
+impl Default for DeviceCloudConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            method: "POST".to_owned(),
+            headers: HashMap::new(),
+            body: None,
+            content_type: "application/json".to_owned(),
+            timeout_secs: 10,
+            attempts: 3,
+        }
+    }
+}
+
+impl Validate for DeviceCloudConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        let Some(url) = &self.url else {
+            return Ok(());
+        };
+
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow!("device_cloud url must be an http or https URL".to_owned()));
+        }
+        if !matches!(self.method.as_str(), "POST" | "PUT" | "PATCH") {
+            return Err(anyhow!("device_cloud method must be POST, PUT or PATCH".to_owned()));
+        }
+        if self.timeout_secs == 0 {
+            return Err(anyhow!("device_cloud timeout_secs must be at least 1".to_owned()));
+        }
+        if self.attempts == 0 {
+            return Err(anyhow!("device_cloud attempts must be at least 1".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Registrar certificates revoked through the admin API are rejected by the MASA and published in a CRL signed with
+/// the MASA CA key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CrlConfig {
+    /// HTTP path the DER encoded CRL is served at, e.g. `/crl/registrars.crl`, it is not served if unset
+    pub path: Option<String>,
+    /// Persists the revoked certificates and the CRL number to this JSON file, they are lost on restart if unset
+    pub revocations_file: Option<RelativePathBuf>,
+    /// Seconds between two CRLs, a revocation publishes a new one right away
+    pub publish_secs: u64,
+    /// Seconds until the `nextUpdate` of a CRL
+    pub validity_secs: u64,
+}
+
+impl Default for CrlConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            revocations_file: None,
+            publish_secs: 3600,
+            validity_secs: 86400,
+        }
+    }
+}
+
+impl Validate for CrlConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.path {
+            if !path.starts_with('/') || path.contains(':') || path.contains('*') {
+                return Err(anyhow!("crl path must be an absolute path without parameters".to_owned()));
+            }
+            if path.starts_with("/.well-known/") || path.starts_with("/admin") {
+                return Err(anyhow!("crl path must not be below /.well-known or /admin".to_owned()));
+            }
+        }
+        if self.publish_secs == 0 {
+            return Err(anyhow!("crl publish_secs must be at least 1".to_owned()));
+        }
+        // relying parties fetching right before the next CRL must not end up with an expired one
+        if self.validity_secs <= self.publish_secs {
+            return Err(anyhow!("crl validity_secs must be longer than publish_secs".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Time-stamp tokens keep vouchers and the audit log verifiable after the MASA certificate expired or was revoked,
+/// as they prove that the signature existed while the certificate was valid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct TimestampConfig {
+    /// Time-stamp authority, time-stamping is off if unset
+    pub tsa_url: Option<String>,
+    /// Issuers of the TSA certificate
+    pub tsa_ca_certificates: Vec<RelativePathBuf>,
+    /// Policy OID the TSA has to issue the tokens under
+    pub policy: Option<String>,
+    /// Vouchers are not issued without a time-stamp if set, otherwise they are issued without one while the TSA fails
+    pub required: bool,
+    /// Seconds between two time-stamped checkpoints of the persisted audit log, 0 disables checkpoints
+    pub checkpoint_secs: u64,
+}
+
+impl Validate for TimestampConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        let Some(tsa_url) = &self.tsa_url else {
+            if self.required || self.checkpoint_secs > 0 {
+                return Err(anyhow!("timestamping tsa_url must be set to require time-stamps or checkpoints".to_owned()));
+            }
+            return Ok(());
+        };
+
+        if !tsa_url.starts_with("http://") && !tsa_url.starts_with("https://") {
+            return Err(anyhow!("timestamping tsa_url must be an http or https URL".to_owned()));
+        }
+        if self.tsa_ca_certificates.is_empty() {
+            return Err(anyhow!("timestamping tsa_ca_certificates must be set to verify the time-stamps".to_owned()));
+        }
+        if let Some(missing) = self.tsa_ca_certificates.iter().find(|cert| !cert.relative().exists()) {
+            return Err(anyhow!("timestamping tsa_ca_certificate {:?} does not exist", missing.relative()));
+        }
+        if self.policy.as_ref().is_some_and(|policy| {
+            policy.split('.').count() < 2 || policy.split('.').any(|arc| arc.is_empty() || !arc.bytes().all(|byte| byte.is_ascii_digit()))
+        }) {
+            return Err(anyhow!("timestamping policy must be a dotted OID".to_owned()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Vouchers for requests with a nonce are bound to that request and do not expire.
+/// Nonceless vouchers, e.g. for pledges onboarded offline, can be replayed and expire instead, see RFC 8995 Section 3
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct VoucherValidity {
+    /// Nonceless voucher requests are denied if unset
+    pub allow_nonceless: bool,
+    /// `expires-on` of nonceless vouchers, counted from their creation
+    pub nonceless_days: u32,
+}
+
+impl Default for VoucherValidity {
+    fn default() -> Self {
+        Self {
+            allow_nonceless: true,
+            nonceless_days: 30,
+        }
+    }
+}
+
 impl Validate for MasaConfig {
     fn validate(&self) -> anyhow::Result<()> {
         if self.port.is_empty() {
@@ -39,6 +355,45 @@ impl Validate for MasaConfig {
         if !self.registrar_ee_certificate.relative().exists() {
             return Err(anyhow!("registrar ee_certificate is empty or not exist".to_owned()));
         }
+        if self.expiry_check_secs == 0 {
+            return Err(anyhow!("expiry_check_secs must be at least 1".to_owned()));
+        }
+        if self.oidc_issuer.is_some() && self.oidc_audience.is_none() {
+            return Err(anyhow!("oidc_audience must be set when oidc_issuer is set".to_owned()));
+        }
+        if let Some(missing) = self.integrator_ca_certificates.iter().find(|cert| !cert.relative().exists()) {
+            return Err(anyhow!("integrator ca_certificate {:?} does not exist", missing.relative()));
+        }
+        if let Some(missing) = self.registrar_ca_certificates.iter().find(|cert| !cert.relative().exists()) {
+            return Err(anyhow!("registrar ca_certificate {:?} does not exist", missing.relative()));
+        }
+        if self.audit_log_file.is_some() && self.audit_log_database.is_some() {
+            return Err(anyhow!("Only one of audit_log_file and audit_log_database may be set".to_owned()));
+        }
+        if let Some(url) = &self.anomaly_webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(anyhow!("anomaly_webhook_url must be an http or https URL".to_owned()));
+            }
+        }
+        parse_serial_patterns(self.additional_configuration.keys())
+            .map_err(|err| anyhow!("additional_configuration: {}", err))?;
+        self.artifact_limits.validate()?;
+        self.http.validate()?;
+        self.verification.validate()?;
+        if self.device_registry.as_ref().is_some_and(|path| !path.relative().exists()) {
+            return Err(anyhow!("device_registry does not exist".to_owned()));
+        }
+        if self.voucher_validity.allow_nonceless && self.voucher_validity.nonceless_days == 0 {
+            return Err(anyhow!("voucher_validity nonceless_days must be at least 1".to_owned()));
+        }
+        self.timestamping.validate()?;
+        if self.timestamping.checkpoint_secs > 0 && self.audit_log_file.is_none() && self.audit_log_database.is_none() {
+            return Err(anyhow!("timestamping checkpoints need audit_log_file or audit_log_database".to_owned()));
+        }
+        self.crl.validate()?;
+        self.device_cloud.validate()?;
+        self.voucher_signing.validate()?;
+
         Ok(())
     }
 }
@@ -60,6 +415,36 @@ impl Default for MasaConfig {
             registrar_ee_certificate: RelativePathBuf::from(
                 "/etc/open-brski/conf/registrar/signing-authority/registrar.cert",
             ),
+            admin_api_keys: vec![],
+            oidc_issuer: None,
+            oidc_audience: None,
+            manual_approval: false,
+            policy_dry_run: false,
+            integrator_ca_certificates: vec![],
+            additional_configuration: HashMap::new(),
+            audit_log_file: None,
+            audit_log_database: None,
+            anomaly_registrars_per_serial: 3,
+            anomaly_nonceless_burst: 20,
+            anomaly_webhook_url: None,
+            registrar_revocation: RevocationMode::default(),
+            registrar_ca_certificates: vec![],
+            revocation_refresh_secs: 3600,
+            expiry_warning_days: 30,
+            expiry_check_secs: 3600,
+            expiry_webhook_url: None,
+            artifact_limits: ArtifactLimits::default(),
+            http: HttpServerConfig::default(),
+            verification: VerificationConfig::default(),
+            idempotency_key_ttl_secs: 86400,
+            idempotency_key_capacity: 10000,
+            voucher_validity: VoucherValidity::default(),
+            device_registry: None,
+            timestamping: TimestampConfig::default(),
+            crl: CrlConfig::default(),
+            device_cloud: DeviceCloudConfig::default(),
+            voucher_metrics: false,
+            voucher_signing: VoucherSigningConfig::default(),
         }
     }
 }
@@ -85,4 +470,99 @@ pub struct NullableMasaConfig {
     #[clap(value_parser = parse_relative_path_buf)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub masa_key: Option<RelativePathBuf>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_api_keys: Option<Vec<ApiKey>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc_issuer: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc_audience: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual_approval: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_dry_run: Option<bool>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrator_ca_certificates: Option<Vec<RelativePathBuf>>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additional_configuration: Option<HashMap<String, String>>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log_file: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log_database: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly_registrars_per_serial: Option<usize>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly_nonceless_burst: Option<usize>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly_webhook_url: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registrar_revocation: Option<RevocationMode>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registrar_ca_certificates: Option<Vec<RelativePathBuf>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation_refresh_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_warning_days: Option<u32>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_check_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_webhook_url: Option<String>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_limits: Option<ArtifactLimits>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpServerConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<VerificationConfig>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key_ttl_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key_capacity: Option<usize>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voucher_validity: Option<VoucherValidity>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_registry: Option<RelativePathBuf>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamping: Option<TimestampConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl: Option<CrlConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_cloud: Option<DeviceCloudConfig>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voucher_metrics: Option<bool>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voucher_signing: Option<VoucherSigningConfig>,
 }
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+
 use crate::util::parse_relative_path_buf;
 use crate::validate::Validate;
 use anyhow::anyhow;
-use clap::Args;
+use brski_prm_artifacts::ietf_voucher::serial_number;
+use clap::{Args, Subcommand, ValueEnum};
 use figment::value::magic::RelativePathBuf;
+use common::limits::ArtifactLimits;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -11,6 +15,63 @@ pub struct PledgeConfig {
     pub idev_id: String,
     pub idevid_certificate: RelativePathBuf,
     pub idevid_privkey: RelativePathBuf,
+    pub grasp_discovery: bool,
+    /// Onboards on its own through a join proxy found by GRASP discovery (RFC 8995),
+    /// instead of waiting for a registrar-agent
+    pub pledge_initiated: bool,
+    pub grasp_interface: u32,
+    /// Interfaces, by name or index, that GRASP discovery listens on at the same time.
+    /// `grasp_interface` is used if empty.
+    pub grasp_interfaces: Vec<String>,
+    /// Keeps discovered join proxies with their lifetime and failures in this JSON file,
+    /// so a pledge rebooting during rollout resumes with the proxies it already knew
+    pub discovery_cache: Option<RelativePathBuf>,
+    /// Plain HTTP URL answering `204 No Content`, anything else means the bootstrap network has a captive portal
+    pub captive_portal_check_url: Option<String>,
+    pub manufacturer_anchors: Vec<RelativePathBuf>,
+    /// Trust anchors pinned per MASA, keyed by the MASA-URI of the IDevID, e.g. `masa.example.com:443`.
+    /// Vouchers have to chain to the anchor pinned for the own MASA-URI, to `manufacturer_anchors` if none is pinned.
+    pub masa_anchors: HashMap<String, RelativePathBuf>,
+    pub firmware_signers: Vec<RelativePathBuf>,
+    /// Vendor ID, a UUID, that SUIT manifests have to name, manifests for other vendors are rejected
+    pub firmware_vendor_id: Option<String>,
+    /// Class ID, a UUID, that SUIT manifests have to name, manifests for other device classes are rejected
+    pub firmware_class_id: Option<String>,
+    /// Writes firmware images posted to `/.well-known/brski/sfi` to this file, where the update of the device picks them up.
+    /// An image is only written if it matches the latest accepted SUIT manifest.
+    pub firmware_image: Option<RelativePathBuf>,
+    /// Keeps the sequence number of the installed firmware in this JSON file, so manifests that are not newer
+    /// are rejected after a restart as well
+    pub firmware_state: Option<RelativePathBuf>,
+    /// Writes the LDevID with its key and chain as PKCS#12 to this file once enrolled
+    pub ldevid_pkcs12: Option<RelativePathBuf>,
+    pub ldevid_pkcs12_password: Option<String>,
+    /// Keeps the domain CA certificates, installed from `/.well-known/est/cacerts` before enrolling, in this PEM file
+    /// so they are trusted again after a restart
+    pub trust_store: Option<RelativePathBuf>,
+    /// OpenSSH host key, generated on the first enrollment, that the registrar issues a host certificate for.
+    /// The certificate is written next to it with a `-cert.pub` suffix.
+    pub ssh_host_key: Option<RelativePathBuf>,
+    /// OpenSSH public key of the CA signing the host certificate, certificates of other CAs are rejected.
+    /// Must be set with `ssh_host_key`.
+    pub ssh_ca_public_key: Option<RelativePathBuf>,
+    /// Keeps the boot count and the telemetry counter in this JSON file, status telemetry carries no counter attestation without it
+    pub attestation_counter: Option<RelativePathBuf>,
+    /// Signature format of the tPVR response. The registrar-agent has to ask for `cms` with its `Accept` header,
+    /// pledge-initiated voucher-requests are always JWS
+    pub artifact_format: ArtifactFormat,
+    /// Maximum request body sizes per artifact
+    pub artifact_limits: ArtifactLimits,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactFormat {
+    /// JWS voucher-request, BRSKI-PRM
+    #[default]
+    Jose,
+    /// CMS SignedData voucher-request, RFC 8995
+    Cms,
 }
 
 impl Validate for PledgeConfig {
@@ -18,9 +79,7 @@ impl Validate for PledgeConfig {
         if self.port.is_empty() {
             return Err(anyhow!("Port cannot be empty".to_owned()));
         }
-        if self.idev_id.is_empty() {
-            return Err(anyhow!("idev_id cannot be empty".to_owned()));
-        }
+        serial_number::validate(&self.idev_id).map_err(|err| anyhow!("idev_id: {}", err))?;
 
         if !self.idevid_certificate.relative().exists() {
             return Err(anyhow!("idevid_certificate does not exist".to_owned()));
@@ -29,6 +88,45 @@ impl Validate for PledgeConfig {
         if !self.idevid_privkey.relative().exists() {
             return Err(anyhow!("idevid_privkey does not exist".to_owned()));
         }
+
+        if let Some((masa_uri, anchor)) = self.masa_anchors.iter().find(|(_, anchor)| !anchor.relative().exists()) {
+            return Err(anyhow!("masa_anchors entry {:?} of {} does not exist", anchor.relative(), masa_uri));
+        }
+
+        if let Some(signer) = self.firmware_signers.iter().find(|signer| !signer.relative().exists()) {
+            return Err(anyhow!("firmware_signers entry {:?} does not exist", signer.relative()));
+        }
+
+        for (name, uuid) in [("firmware_vendor_id", &self.firmware_vendor_id), ("firmware_class_id", &self.firmware_class_id)] {
+            if uuid.as_deref().is_some_and(|uuid| common::util::parse_uuid(uuid).is_none()) {
+                return Err(anyhow!("{} must be a UUID", name));
+            }
+        }
+
+        if self.firmware_image.is_some() && self.firmware_state.is_none() {
+            return Err(anyhow!("firmware_state must be set when firmware_image is set, the installed sequence number has to survive restarts".to_owned()));
+        }
+
+        if self.pledge_initiated && !self.grasp_discovery {
+            return Err(anyhow!("pledge_initiated needs grasp_discovery to find a join proxy".to_owned()));
+        }
+
+        if let Some(url) = &self.captive_portal_check_url {
+            if !url.starts_with("http://") {
+                return Err(anyhow!("captive_portal_check_url must be a plain http:// URL, portals can not intercept TLS transparently".to_owned()));
+            }
+        }
+
+        if self.ldevid_pkcs12.is_some() && self.ldevid_pkcs12_password.as_deref().unwrap_or_default().is_empty() {
+            return Err(anyhow!("ldevid_pkcs12_password must be set when ldevid_pkcs12 is set".to_owned()));
+        }
+        match (&self.ssh_host_key, &self.ssh_ca_public_key) {
+            (Some(_), None) => return Err(anyhow!("ssh_ca_public_key must be set when ssh_host_key is set, host certificates are verified against it".to_owned())),
+            (_, Some(ca_public_key)) if !ca_public_key.relative().exists() => return Err(anyhow!("ssh_ca_public_key does not exist".to_owned())),
+            _ => {}
+        }
+        self.artifact_limits.validate()?;
+
         Ok(())
     }
 }
@@ -44,12 +142,50 @@ impl Default for PledgeConfig {
             idevid_privkey: RelativePathBuf::from(
                 "/etc/open-brski/conf/registrar-agent/idevid_privkey.key",
             ),
+            grasp_discovery: false,
+            pledge_initiated: false,
+            grasp_interface: 0,
+            grasp_interfaces: vec![],
+            discovery_cache: None,
+            captive_portal_check_url: None,
+            manufacturer_anchors: vec![],
+            masa_anchors: HashMap::new(),
+            firmware_signers: vec![],
+            firmware_vendor_id: None,
+            firmware_class_id: None,
+            firmware_image: None,
+            firmware_state: None,
+            ldevid_pkcs12: None,
+            ldevid_pkcs12_password: None,
+            trust_store: None,
+            ssh_host_key: None,
+            ssh_ca_public_key: None,
+            attestation_counter: None,
+            artifact_format: ArtifactFormat::Jose,
+            artifact_limits: ArtifactLimits::default(),
         }
     }
 }
 
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PledgeCommand {
+    /// Check the local identity, clock and network and print a report
+    Doctor,
+}
+
 #[derive(Args, Serialize, Deserialize, Debug)]
 pub struct NullablePledgeConfig {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<PledgeCommand>,
+    /// Onboards once through a discovered join proxy without installing the domain CA certificates or the LDevID
+    #[arg(long)]
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// Writes every artifact of the dry run to this directory, as exchanged and pretty-printed
+    #[arg(long, requires = "dry_run")]
+    #[serde(skip)]
+    pub dump_artifacts: Option<std::path::PathBuf>,
     #[arg(short, long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<String>,
@@ -64,4 +200,77 @@ pub struct NullablePledgeConfig {
     #[clap(value_parser = parse_relative_path_buf)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub idevid_privkey: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grasp_discovery: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pledge_initiated: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grasp_interface: Option<u32>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grasp_interfaces: Option<Vec<String>>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery_cache: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captive_portal_check_url: Option<String>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer_anchors: Option<Vec<RelativePathBuf>>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub masa_anchors: Option<HashMap<String, RelativePathBuf>>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_signers: Option<Vec<RelativePathBuf>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_vendor_id: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_class_id: Option<String>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_image: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_state: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ldevid_pkcs12: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ldevid_pkcs12_password: Option<String>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_store: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_host_key: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_ca_public_key: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation_counter: Option<RelativePathBuf>,
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_format: Option<ArtifactFormat>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_limits: Option<ArtifactLimits>,
 }
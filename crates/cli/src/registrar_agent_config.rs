@@ -1,7 +1,10 @@
 use crate::{util::parse_relative_path_buf, validate::Validate};
+use common::auth::ApiKey;
 use anyhow::anyhow;
-use clap::{arg, Args};
+use brski_prm_artifacts::network::NetworkProfile;
+use clap::{Args, ValueEnum};
 use figment::value::magic::RelativePathBuf;
+use common::limits::ArtifactLimits;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,6 +18,37 @@ pub struct RegistrarAgentConfig {
     pub ee_key: RelativePathBuf,
     pub registrar_certificate: RelativePathBuf,
     pub registrar_url: String,
+    /// Keys for the installer API used by phone and tablet front ends, the API is disabled if empty
+    pub installer_api_keys: Vec<ApiKey>,
+    /// How the agent talks to pledges
+    pub transport: PledgeTransport,
+    /// PC/SC reader pledges are tapped on with the `nfc` transport, all readers if unset
+    pub nfc_reader: Option<String>,
+    /// Serial ports of the pledges connected with the `usb` transport, e.g. `/dev/ttyACM0`
+    pub usb_ports: Vec<String>,
+    /// Only onboard pledges whose QR code label was scanned by the installer
+    pub require_identification: bool,
+    /// Pledges onboarded at the same time in batch mode
+    pub batch_workers: usize,
+    /// Maximum request body sizes per artifact
+    pub artifact_limits: ArtifactLimits,
+    /// Production network handed to pledges after enrollment, e.g. `[registrar-agent.network_profile]`.
+    /// Only the `ble` transport delivers it.
+    pub network_profile: Option<NetworkProfile>,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PledgeTransport {
+    /// HTTP-over-TLS, the pledge is reached by its URL
+    #[default]
+    Http,
+    /// BLE central, needs the `ble` feature of the registrar-agent and BlueZ
+    Ble,
+    /// NFC tap on a PC/SC reader, needs the `nfc` feature of the registrar-agent. Only delivers the trigger and retrieves the PVR
+    Nfc,
+    /// USB serial line per pledge, needs the `usb` feature of the registrar-agent. Used by production line fixtures
+    Usb,
 }
 
 impl Default for RegistrarAgentConfig {
@@ -33,6 +67,14 @@ impl Default for RegistrarAgentConfig {
                 "/etc/open-brski/conf/registrar/ee_certificate.pem",
             ),
             registrar_url: "http://localhost:3001".to_owned(),
+            installer_api_keys: vec![],
+            transport: PledgeTransport::Http,
+            nfc_reader: None,
+            usb_ports: vec![],
+            require_identification: false,
+            batch_workers: 4,
+            artifact_limits: ArtifactLimits::default(),
+            network_profile: None,
         }
     }
 }
@@ -43,6 +85,10 @@ impl Validate for RegistrarAgentConfig {
             return Err(anyhow!("registrar-agent: Port cannot be empty".to_owned()));
         }
 
+        if self.batch_workers == 0 {
+            return Err(anyhow!("registrar-agent: batch_workers must be at least 1".to_owned()));
+        }
+
         if !self.ee_certificate.relative().exists() {
             return Err(anyhow!("registrar-agent: ee_certificate is empty or not exist".to_owned()));
         }
@@ -60,6 +106,21 @@ impl Validate for RegistrarAgentConfig {
             ));
         }
 
+        if self.transport == PledgeTransport::Usb && self.usb_ports.is_empty() {
+            return Err(anyhow!("registrar-agent: The usb transport needs at least one of usb_ports".to_owned()));
+        }
+
+        self.artifact_limits.validate()?;
+
+        if let Some(network_profile) = &self.network_profile {
+            if self.transport != PledgeTransport::Ble {
+                return Err(anyhow!("registrar-agent: network_profile is only delivered with the ble transport".to_owned()));
+            }
+            network_profile
+                .validate()
+                .map_err(|err| anyhow!("registrar-agent: network_profile is invalid: {}", err))?;
+        }
+
         Ok(())
     }
 }
@@ -96,4 +157,28 @@ pub struct NullableRegistrarAgentConfig {
     #[arg(long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub registrar_url: Option<String>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installer_api_keys: Option<Vec<ApiKey>>,
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<PledgeTransport>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nfc_reader: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usb_ports: Option<Vec<String>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_identification: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_workers: Option<usize>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_limits: Option<ArtifactLimits>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_profile: Option<NetworkProfile>,
 }
@@ -1,7 +1,15 @@
+use std::collections::HashMap;
+
 use crate::util::parse_relative_path_buf;
+use common::auth::ApiKey;
+use common::revocation::RevocationMode;
+use common::serial_pattern::parse_serial_patterns;
 use anyhow::anyhow;
-use clap::Args;
+use clap::{Args, Subcommand, ValueEnum};
 use figment::value::magic::RelativePathBuf;
+use common::http_server::HttpServerConfig;
+use common::verification::VerificationConfig;
+use common::limits::ArtifactLimits;
 use serde::{Deserialize, Serialize};
 
 use crate::validate::Validate;
@@ -12,10 +20,488 @@ pub struct RegistrarConfig {
     pub port: String,
     pub ca_certificate: RelativePathBuf,
     pub ca_key: RelativePathBuf,
+    /// CA that issues LDevIDs, the local one or an upstream CA
+    pub ca_backend: CaBackend,
+    /// Upstream CA of `ca_backend = "est"` or `"cmp"`
+    pub upstream_ca: UpstreamCaConfig,
     pub registrar_certificate: RelativePathBuf,
     pub registrar_key: RelativePathBuf,
+    /// Server identity of the DTLS front end and the HTTPS listener, kept apart from the voucher-request signing
+    /// `registrar_certificate`. Falls back to the registrar certificate if unset.
+    pub tls_certificate: Option<RelativePathBuf>,
+    pub tls_key: Option<RelativePathBuf>,
+    /// Port of the HTTPS listener next to the plain HTTP one on `port`. Pledges authenticate on it with their IDevID
+    /// as client certificate, which `/simpleenroll` requires.
+    pub https_port: Option<u16>,
     pub reg_agt_ee_cert: RelativePathBuf,
-    pub masa_url: String
+    pub masa_url: String,
+    /// Serial-number patterns, see `common::serial_pattern::SerialPattern`
+    pub blocked_serials: Vec<String>,
+    pub blocked_idevid_issuers: Vec<String>,
+    pub parent_registrar_url: Option<String>,
+    pub voucher_cache_dir: Option<RelativePathBuf>,
+    /// CA certificates of the MASA, vouchers from `voucher_cache_dir` must be signed by a certificate chaining to one of them
+    pub voucher_cache_trust_anchors: Vec<RelativePathBuf>,
+    /// SQLite database of the background job queue, jobs are lost on restart if unset
+    pub job_database: Option<RelativePathBuf>,
+    /// SQLite database of the inventory of bootstrapped devices, the inventory is lost on restart if unset
+    pub device_database: Option<RelativePathBuf>,
+    /// Background jobs running at the same time
+    pub job_workers: usize,
+    /// Holds voucher requests in the job queue while the MASA is unreachable, pledges are answered with 202 and
+    /// get the voucher when they ask again
+    pub store_and_forward: bool,
+    /// Relays the voucher status telemetry of pledges to their MASA through the job queue
+    pub forward_voucher_status: bool,
+    /// Rejects voucher and enroll status telemetry without a counter attestation, otherwise only the attestations that
+    /// are sent are checked
+    pub require_attestation: bool,
+    /// Revocation checking of pledge IDevIDs
+    pub idevid_revocation: RevocationMode,
+    /// Seconds after which the cached CRLs of IDevID issuers are fetched again
+    pub revocation_refresh_secs: u64,
+    /// Days before the registrar, CA, agent and TLS certificates or an issued LDevID expire from which they are alerted
+    pub expiry_warning_days: u32,
+    /// Seconds between two expiry checks
+    pub expiry_check_secs: u64,
+    /// Receives expiry alerts as JSON POST requests
+    pub expiry_webhook_url: Option<String>,
+    /// OpenSSH private key of the CA that signs SSH host certificates for pledges sending a host key
+    pub ssh_ca_key: Option<RelativePathBuf>,
+    pub ssh_certificate_validity_days: u32,
+    pub masa_srv_domains: HashMap<String, String>,
+    pub admin_api_keys: Vec<ApiKey>,
+    pub oidc_issuer: Option<String>,
+    pub oidc_audience: Option<String>,
+    pub ldevid_curves: Vec<String>,
+    pub ldevid_validity_days: u32,
+    /// Settings per manufacturer, keyed by a name used in logs
+    pub manufacturers: HashMap<String, ManufacturerConfig>,
+    /// New pledges are only onboarded within one of these windows, at any time if empty
+    pub admission_windows: Vec<AdmissionWindowConfig>,
+    /// Initial state of the maintenance mode, which can be toggled at runtime through the admin API
+    pub maintenance_mode: bool,
+    /// Evaluates the admission windows without enforcing them, pledges they would reject are only logged. The
+    /// maintenance mode and the blocklists are still enforced.
+    pub policy_dry_run: bool,
+    /// Lists the pledges matching `blocked_serials` or `blocked_idevid_issuers` under the blocked attempts but lets
+    /// them through. Separate from `policy_dry_run`, so trialling a policy never lets a quarantined batch onboard.
+    pub quarantine_dry_run: bool,
+    /// Maximum request body sizes per artifact
+    pub artifact_limits: ArtifactLimits,
+    /// Limits of the HTTP listener on `port` and of the HTTPS listener on `https_port`
+    pub http: HttpServerConfig,
+    /// Worker pool verifying signatures and certificate chains
+    pub verification: VerificationConfig,
+    /// DNS-SD advertisement of the registrar on the local link
+    pub mdns: MdnsConfig,
+    /// CoAP over DTLS front end for constrained pledges
+    pub coaps: CoapsConfig,
+    /// AgentX subagent exposing the onboarding progress to the SNMP agent of the host
+    pub snmp: SnmpConfig,
+    /// Seconds in which onboarding attempts of the same pledge from different networks are flagged as a cloned IDevID, 0 disables the check
+    pub clone_detection_window_secs: u64,
+    /// Seconds in which a voucher request with the same signature or nonce is rejected as a replay, 0 disables the check
+    pub replay_window_secs: u64,
+    /// Egress proxy for requests to the MASA
+    pub masa_proxy: ProxyConfig,
+    /// Certificates besides `registrar_certificate` that pledges may name as their proximity registrar, e.g. those of a
+    /// TLS terminating proxy or of subordinate registrars forwarding voucher requests to this one
+    pub proximity_registrar_certificates: Vec<RelativePathBuf>,
+    /// Circuit proxy of `registrar join-proxy`
+    pub join_proxy: JoinProxyConfig,
+    /// Debug capture of the artifacts exchanged with pledges
+    pub capture: CaptureConfig,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaBackend {
+    /// Issues LDevIDs with openssl
+    #[default]
+    Openssl,
+    /// Issues the same LDevIDs in pure Rust with rcgen, the CA key has to be an ECDSA key
+    Rcgen,
+    /// Forwards the CSRs to the EST server of `upstream_ca`
+    Est,
+    /// Forwards the CSRs to the CMP server of `upstream_ca` as lightweight CMP (RFC 9483) `p10cr`
+    Cmp,
+}
+
+impl CaBackend {
+    /// Whether LDevIDs are issued with the local `ca_certificate` and `ca_key`
+    pub fn is_local(self) -> bool {
+        matches!(self, CaBackend::Openssl | CaBackend::Rcgen)
+    }
+}
+
+/// CA that issues the LDevIDs for `ca_backend = "est"` or `"cmp"`. The registrar authenticates as RA: to an EST
+/// server with basic authentication, to a CMP server by signing its requests with `registrar_key`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct UpstreamCaConfig {
+    /// `https://` EST base URL, e.g. `https://ca.example.com/.well-known/est`, or the URL CMP messages are posted to
+    pub url: Option<String>,
+    /// HTTP basic authentication at the EST server
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM certificate of the CMP server, its responses have to be signed with the key of this certificate
+    pub cmp_server_certificate: Option<RelativePathBuf>,
+    /// Additional trust anchors for the TLS certificate of the upstream CA
+    pub tls_trust_anchors: Vec<RelativePathBuf>,
+}
+
+impl UpstreamCaConfig {
+    pub(crate) fn validate(&self, backend: CaBackend) -> anyhow::Result<()> {
+        match &self.url {
+            // the basic authentication of EST and the CSRs and LDevIDs must not travel in the clear
+            Some(url) if url.starts_with("https://") => {}
+            _ => return Err(anyhow!("upstream_ca url must be an https URL for ca_backend {:?}", backend)),
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(anyhow!("upstream_ca password is set without username"));
+        }
+        if let Some(anchor) = self.tls_trust_anchors.iter().find(|anchor| !anchor.relative().exists()) {
+            return Err(anyhow!("upstream_ca tls_trust_anchor {:?} does not exist", anchor.relative()));
+        }
+        if backend == CaBackend::Cmp {
+            match &self.cmp_server_certificate {
+                Some(certificate) if certificate.relative().exists() => {}
+                Some(_) => return Err(anyhow!("upstream_ca cmp_server_certificate does not exist")),
+                None => return Err(anyhow!("upstream_ca cmp_server_certificate must be set for ca_backend cmp")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Recurring time window in UTC, e.g. `{ days = ["mon", "tue"], start = "08:00", end = "18:00" }`.
+/// A window with `end` before `start` spans midnight, a window without days applies to every day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AdmissionWindowConfig {
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+/// Overrides of the global settings for the pledges of one manufacturer.
+/// The section is selected by the IDevID issuer, given as common name or as hex encoded authority key identifier.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ManufacturerConfig {
+    pub idevid_issuers: Vec<String>,
+    /// IDevIDs must chain to one of these certificates, not checked if empty
+    pub trust_anchors: Vec<RelativePathBuf>,
+    pub masa_url: Option<String>,
+    pub masa_srv_domain: Option<String>,
+    pub ldevid_curves: Option<Vec<String>>,
+    pub ldevid_validity_days: Option<u32>,
+    /// Blocked in addition to the global `blocked_serials`
+    pub blocked_serials: Vec<String>,
+}
+
+/// Services announced by the mDNS responder, pledges and join proxies on the link find the registrar without configuration
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MdnsConfig {
+    pub enabled: bool,
+    /// Instance name of the services, e.g. `open-brski registrar._brski-registrar._tcp.local.`
+    pub instance_name: String,
+    /// Host name announced for the addresses of all interfaces, `.local.` is appended
+    pub hostname: String,
+    /// Advertised port of `_brski-registrar._tcp`, the listening port if unset, e.g. behind a TLS terminating proxy
+    pub port: Option<u16>,
+    /// Whether the advertised port expects TLS, announced as `tls` TXT record
+    pub tls: bool,
+    /// Also advertises `_brski-proxy._tcp`, for registrars that pledges can reach directly
+    pub advertise_proxy: bool,
+    /// Advertised port of `_brski-proxy._tcp`, the registrar port if unset
+    pub proxy_port: Option<u16>,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            instance_name: "open-brski registrar".to_owned(),
+            hostname: "open-brski-registrar".to_owned(),
+            port: None,
+            tls: false,
+            advertise_proxy: false,
+            proxy_port: None,
+        }
+    }
+}
+
+/// Stateless circuit proxy (RFC 8995 Section 4) relaying the TLS connections of pledges on the join network to the
+/// registrar. Only the mDNS settings are shared with the registrar, the proxy advertises `_brski-proxy._tcp` on its port.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct JoinProxyConfig {
+    /// TCP port pledges connect to
+    pub port: u16,
+    /// `host:port` of the registrar the byte streams are forwarded to, the TLS endpoint pledges expect
+    pub registrar_address: String,
+    /// Connections relayed at the same time, further ones wait in the listen backlog
+    pub max_connections: usize,
+    /// Seconds without traffic in either direction after which a connection is closed
+    pub idle_timeout_secs: u64,
+    /// Seconds to establish the connection to the registrar
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for JoinProxyConfig {
+    fn default() -> Self {
+        Self {
+            port: 3004,
+            registrar_address: "localhost:3001".to_owned(),
+            max_connections: 256,
+            idle_timeout_secs: 60,
+            connect_timeout_secs: 10,
+        }
+    }
+}
+
+/// Writes every request and response of the BRSKI and EST endpoints to disk, for interop troubleshooting with
+/// third-party pledges. Captures hold the unencrypted artifacts of the pledges, so this is off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    /// Gets a subdirectory per session, or a bundle per session with `encryption_key`
+    pub directory: RelativePathBuf,
+    /// PEM public key (EC or RSA) of the operator. Exchanges are then only written encrypted to it as JWEs.
+    pub encryption_key: Option<RelativePathBuf>,
+    /// Exchanges kept per session, later ones are dropped
+    pub max_exchanges: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: RelativePathBuf::from("/var/lib/open-brski/registrar/captures"),
+            encryption_key: None,
+            max_exchanges: 64,
+        }
+    }
+}
+
+impl Validate for CaptureConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.max_exchanges == 0 {
+            return Err(anyhow!("capture max_exchanges must be at least 1"));
+        }
+        if self.encryption_key.as_ref().is_some_and(|key| !key.relative().exists()) {
+            return Err(anyhow!("capture encryption_key does not exist"));
+        }
+        Ok(())
+    }
+}
+
+/// EST-coaps (RFC 9148) and constrained BRSKI resources over DTLS 1.2, and optionally OSCORE, handled by the HTTP
+/// endpoints of the registrar
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CoapsConfig {
+    pub enabled: bool,
+    /// UDP port, 5684 is the default port of `coaps`
+    pub port: u16,
+    /// Path MTU DTLS fragments its handshake messages to
+    pub mtu: u32,
+    /// Size of Block2 blocks in responses, a power of two from 16 to 1024 bytes
+    pub block_size: usize,
+    /// Seconds without a request after which a DTLS or OSCORE session is dropped
+    pub idle_timeout_secs: u64,
+    /// UDP port serving the same resources over plain CoAP protected with OSCORE (RFC 8613), whose security context is
+    /// derived from an EDHOC (RFC 9528) key exchange at `/.well-known/edhoc`. Off if unset, 5683 is the default port
+    /// of `coap`.
+    pub oscore_port: Option<u16>,
+}
+
+impl Default for CoapsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 5684,
+            mtu: 1280,
+            block_size: 1024,
+            idle_timeout_secs: 300,
+            oscore_port: None,
+        }
+    }
+}
+
+/// AgentX (RFC 2741) subagent serving the onboarding counters and sessions read-only below `base_oid`, for network
+/// management systems that poll SNMP. The master agent, e.g. net-snmp's `snmpd` with `master agentx`, handles the
+/// SNMP versions and access control.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SnmpConfig {
+    pub enabled: bool,
+    /// AgentX socket of the master agent, a Unix socket path or `tcp:host:port`
+    pub master_address: String,
+    /// Subtree registered at the master agent, below the private enterprise number of the operator. There is no default,
+    /// `OPEN-BRSKI-REGISTRAR-MIB` in `crates/registrar/assets` has to be rooted at the same OID.
+    pub base_oid: String,
+    /// Seconds between attempts to reach the master agent after the connection failed or was closed
+    pub reconnect_secs: u64,
+}
+
+impl Default for SnmpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            master_address: "/var/agentx/master".to_owned(),
+            base_oid: String::new(),
+            reconnect_secs: 15,
+        }
+    }
+}
+
+impl SnmpConfig {
+    /// Sub-identifiers of `base_oid`
+    pub fn base_oid(&self) -> anyhow::Result<Vec<u32>> {
+        if self.base_oid.is_empty() {
+            return Err(anyhow!("snmp base_oid must be set, e.g. to an OID below the private enterprise number of the operator"));
+        }
+        let oid = self
+            .base_oid
+            .trim_start_matches('.')
+            .split('.')
+            .map(|arc| arc.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("snmp base_oid must be a dotted OID, e.g. 1.3.6.1.4.1.32473.1"))?;
+        // AgentX limits object identifiers to 128 sub-identifiers, the rest is left to the serial-number index
+        if oid.len() < 2 || oid.len() > 64 {
+            return Err(anyhow!("snmp base_oid must have 2 to 64 sub-identifiers"));
+        }
+        Ok(oid)
+    }
+}
+
+impl Validate for SnmpConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.master_address.is_empty() {
+            return Err(anyhow!("snmp master_address must not be empty"));
+        }
+        if let Some(address) = self.master_address.strip_prefix("tcp:") {
+            match address.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0) => {}
+                _ => return Err(anyhow!("snmp master_address must be given as tcp:host:port")),
+            }
+        }
+        self.base_oid()?;
+        if self.reconnect_secs == 0 {
+            return Err(anyhow!("snmp reconnect_secs must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// HTTP(S) proxy, tunnelling TLS with CONNECT, or SOCKS5 proxy
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` URL of the proxy, requests go out directly if unset.
+    /// With `socks5h` the proxy resolves the host names.
+    pub url: Option<String>,
+    /// Sent as basic `Proxy-Authorization` to HTTP(S) proxies and as username/password authentication to SOCKS5 proxies
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Hosts, domains and IP networks that are reached without the proxy, e.g. `masa.example.com` or `10.0.0.0/8`
+    pub no_proxy: Vec<String>,
+}
+
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+impl Validate for ProxyConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        let Some(url) = &self.url else {
+            return Ok(());
+        };
+        match url.split_once("://") {
+            Some((scheme, host)) if PROXY_SCHEMES.contains(&scheme) && !host.is_empty() => {}
+            _ => return Err(anyhow!("masa_proxy url must be an http, https, socks5 or socks5h URL")),
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(anyhow!("masa_proxy password is set without username"));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for CoapsConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.port == 0 {
+            return Err(anyhow!("coaps port must not be 0"));
+        }
+        // DTLS can not fragment below 256 bytes, see SSL_set_mtu
+        if self.mtu < 256 {
+            return Err(anyhow!("coaps mtu must be at least 256"));
+        }
+        if !self.block_size.is_power_of_two() || !(16..=1024).contains(&self.block_size) {
+            return Err(anyhow!("coaps block_size must be a power of two from 16 to 1024"));
+        }
+        if self.idle_timeout_secs == 0 {
+            return Err(anyhow!("coaps idle_timeout_secs must be at least 1"));
+        }
+        if matches!(self.oscore_port, Some(port) if port == 0 || port == self.port) {
+            return Err(anyhow!("coaps oscore_port must not be 0 or the DTLS port"));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for JoinProxyConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.port == 0 {
+            return Err(anyhow!("join_proxy port must not be 0"));
+        }
+        match self.registrar_address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0) => {}
+            _ => return Err(anyhow!("join_proxy registrar_address must be given as host:port")),
+        }
+        if self.max_connections == 0 {
+            return Err(anyhow!("join_proxy max_connections must be at least 1"));
+        }
+        if self.idle_timeout_secs == 0 {
+            return Err(anyhow!("join_proxy idle_timeout_secs must be at least 1"));
+        }
+        if self.connect_timeout_secs == 0 {
+            return Err(anyhow!("join_proxy connect_timeout_secs must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for MdnsConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        // DNS labels, see RFC 6763 Section 4.1.1
+        if self.instance_name.is_empty() || self.instance_name.len() > 63 {
+            return Err(anyhow!("mdns instance_name must have 1 to 63 bytes"));
+        }
+        if self.hostname.is_empty()
+            || self.hostname.len() > 63
+            || !self.hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(anyhow!("mdns hostname must be a single DNS label of letters, digits and hyphens"));
+        }
+        if self.port == Some(0) || self.proxy_port == Some(0) {
+            return Err(anyhow!("mdns ports must not be 0"));
+        }
+        Ok(())
+    }
+}
+
+const SUPPORTED_CURVES: [&str; 3] = ["P-256", "P-384", "P-521"];
+
+fn validate_curves(curves: &[String]) -> anyhow::Result<()> {
+    match curves.iter().find(|curve| !SUPPORTED_CURVES.contains(&curve.as_str())) {
+        Some(curve) => Err(anyhow!("ldevid_curves contains unsupported curve {}", curve)),
+        None => Ok(()),
+    }
 }
 
 impl Default for RegistrarConfig {
@@ -31,13 +517,59 @@ impl Default for RegistrarConfig {
             ca_key: RelativePathBuf::from(
                 "/etc/open-brski/conf/registrar/certificate-authority/registrar-ca.key",
             ),
+            ca_backend: CaBackend::default(),
+            upstream_ca: UpstreamCaConfig::default(),
             registrar_certificate: RelativePathBuf::from(
                 "/etc/open-brski/conf/registrar/signing-authority/registrar.cert",
             ),
             registrar_key: RelativePathBuf::from(
                 "/etc/open-brski/conf/registrar/signing-authority/registrar.key",
             ),
-            masa_url: "http://localhost:3000".to_owned()
+            tls_certificate: None,
+            tls_key: None,
+            https_port: None,
+            masa_url: "http://localhost:3000".to_owned(),
+            blocked_serials: vec![],
+            blocked_idevid_issuers: vec![],
+            parent_registrar_url: None,
+            voucher_cache_dir: None,
+            voucher_cache_trust_anchors: vec![],
+            job_database: None,
+            device_database: None,
+            job_workers: 2,
+            store_and_forward: false,
+            forward_voucher_status: false,
+            require_attestation: false,
+            idevid_revocation: RevocationMode::default(),
+            revocation_refresh_secs: 3600,
+            expiry_warning_days: 30,
+            expiry_check_secs: 3600,
+            expiry_webhook_url: None,
+            ssh_ca_key: None,
+            ssh_certificate_validity_days: 365,
+            masa_srv_domains: HashMap::new(),
+            admin_api_keys: vec![],
+            oidc_issuer: None,
+            oidc_audience: None,
+            ldevid_curves: vec!["P-256".to_owned(), "P-384".to_owned()],
+            ldevid_validity_days: 365,
+            manufacturers: HashMap::new(),
+            admission_windows: vec![],
+            maintenance_mode: false,
+            policy_dry_run: false,
+            quarantine_dry_run: false,
+            artifact_limits: ArtifactLimits::default(),
+            http: HttpServerConfig::default(),
+            verification: VerificationConfig::default(),
+            mdns: MdnsConfig::default(),
+            coaps: CoapsConfig::default(),
+            snmp: SnmpConfig::default(),
+            clone_detection_window_secs: 3600,
+            replay_window_secs: 600,
+            masa_proxy: ProxyConfig::default(),
+            proximity_registrar_certificates: vec![],
+            join_proxy: JoinProxyConfig::default(),
+            capture: CaptureConfig::default(),
         }
     }
 }
@@ -48,12 +580,32 @@ impl Validate for RegistrarConfig {
             return Err(anyhow!("Port cannot be empty".to_owned()));
         }
 
-        if !self.ca_certificate.relative().exists() {
-            return Err(anyhow!("ee_certificate is empty or not exist".to_owned()));
+        if self.job_workers == 0 {
+            return Err(anyhow!("job_workers must be at least 1".to_owned()));
         }
 
-        if !self.ca_key.relative().exists() {
-            return Err(anyhow!("ee_certificate is empty or not exist".to_owned()));
+        if self.expiry_check_secs == 0 {
+            return Err(anyhow!("expiry_check_secs must be at least 1".to_owned()));
+        }
+
+        if self.ssh_ca_key.as_ref().is_some_and(|key| !key.relative().exists()) {
+            return Err(anyhow!("ssh_ca_key does not exist".to_owned()));
+        }
+
+        // a subordinate registrar forwards issuance to its parent and does not need a local CA, neither does one
+        // with an upstream CA
+        if self.parent_registrar_url.is_none() && !self.ca_backend.is_local() {
+            self.upstream_ca.validate(self.ca_backend)?;
+        }
+
+        if self.parent_registrar_url.is_none() && self.ca_backend.is_local() {
+            if !self.ca_certificate.relative().exists() {
+                return Err(anyhow!("ee_certificate is empty or not exist".to_owned()));
+            }
+
+            if !self.ca_key.relative().exists() {
+                return Err(anyhow!("ee_certificate is empty or not exist".to_owned()));
+            }
         }
 
         if !self.registrar_certificate.relative().exists() {
@@ -63,12 +615,93 @@ impl Validate for RegistrarConfig {
         if !self.registrar_key.relative().exists() {
             return Err(anyhow!("ee_certificate is empty or not exist".to_owned()));
         }
+
+        match (&self.tls_certificate, &self.tls_key) {
+            (Some(certificate), Some(key)) => {
+                if !certificate.relative().exists() {
+                    return Err(anyhow!("tls_certificate does not exist".to_owned()));
+                }
+                if !key.relative().exists() {
+                    return Err(anyhow!("tls_key does not exist".to_owned()));
+                }
+            }
+            (None, None) => {}
+            _ => return Err(anyhow!("tls_certificate and tls_key must be set together".to_owned())),
+        }
+
+        if let Some(missing) = self.proximity_registrar_certificates.iter().find(|cert| !cert.relative().exists()) {
+            return Err(anyhow!("proximity_registrar_certificate {:?} does not exist", missing.relative()));
+        }
+
+        if self.voucher_cache_dir.is_some() && self.voucher_cache_trust_anchors.is_empty() {
+            return Err(anyhow!("voucher_cache_dir is set without voucher_cache_trust_anchors"));
+        }
+        if let Some(missing) = self.voucher_cache_trust_anchors.iter().find(|anchor| !anchor.relative().exists()) {
+            return Err(anyhow!("voucher_cache_trust_anchor {:?} does not exist", missing.relative()));
+        }
+
+        if self.oidc_issuer.is_some() && self.oidc_audience.is_none() {
+            return Err(anyhow!("oidc_audience must be set when oidc_issuer is set".to_owned()));
+        }
+
+        validate_curves(&self.ldevid_curves)?;
+
+        parse_serial_patterns(&self.blocked_serials).map_err(|err| anyhow!("blocked_serials: {}", err))?;
+
+        let mut issuers = std::collections::HashSet::new();
+        for (name, manufacturer) in &self.manufacturers {
+            if manufacturer.idevid_issuers.is_empty() {
+                return Err(anyhow!("manufacturer {} has no idevid_issuers", name));
+            }
+            // the section must be unambiguous for every pledge
+            if let Some(issuer) = manufacturer.idevid_issuers.iter().find(|issuer| !issuers.insert(issuer.as_str())) {
+                return Err(anyhow!("IDevID issuer {} is configured for more than one manufacturer", issuer));
+            }
+            if let Some(anchor) = manufacturer.trust_anchors.iter().find(|anchor| !anchor.relative().exists()) {
+                return Err(anyhow!("trust anchor {:?} of manufacturer {} does not exist", anchor.relative(), name));
+            }
+            if let Some(curves) = &manufacturer.ldevid_curves {
+                validate_curves(curves)?;
+            }
+            parse_serial_patterns(&manufacturer.blocked_serials)
+                .map_err(|err| anyhow!("blocked_serials of manufacturer {}: {}", name, err))?;
+        }
+        self.artifact_limits.validate()?;
+        self.http.validate()?;
+        self.verification.validate()?;
+        if self.coaps.enabled {
+            self.coaps.validate()?;
+        }
+
+        if self.mdns.enabled {
+            self.mdns.validate()?;
+        }
+
+        if self.snmp.enabled {
+            self.snmp.validate()?;
+        }
+
+        self.masa_proxy.validate()?;
+
+        if self.capture.enabled {
+            self.capture.validate()?;
+        }
+
         Ok(())
     }
 }
 
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RegistrarCommand {
+    /// Relay the connections of pledges on the join network to the registrar instead of serving it
+    JoinProxy,
+}
+
 #[derive(Args, Serialize, Deserialize, Debug)]
 pub struct NullableRegistrarConfig {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<RegistrarCommand>,
     #[arg(short, long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<String>,
@@ -81,6 +714,12 @@ pub struct NullableRegistrarConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ca_key: Option<RelativePathBuf>,
     #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_backend: Option<CaBackend>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ca: Option<UpstreamCaConfig>,
+    #[arg(long)]
     #[clap(value_parser = parse_relative_path_buf)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub masa_certificate: Option<RelativePathBuf>,
@@ -89,6 +728,146 @@ pub struct NullableRegistrarConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub masa_key: Option<RelativePathBuf>,
     #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_certificate: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_port: Option<u16>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub masa_url: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_serials: Option<Vec<String>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_idevid_issuers: Option<Vec<String>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_registrar_url: Option<String>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voucher_cache_dir: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voucher_cache_trust_anchors: Option<Vec<RelativePathBuf>>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_database: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_database: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_workers: Option<usize>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store_and_forward: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_voucher_status: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_attestation: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idevid_revocation: Option<RevocationMode>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation_refresh_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_warning_days: Option<u32>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_check_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_webhook_url: Option<String>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_ca_key: Option<RelativePathBuf>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_certificate_validity_days: Option<u32>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub masa_srv_domains: Option<HashMap<String, String>>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_api_keys: Option<Vec<ApiKey>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc_issuer: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc_audience: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ldevid_curves: Option<Vec<String>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ldevid_validity_days: Option<u32>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturers: Option<HashMap<String, ManufacturerConfig>>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admission_windows: Option<Vec<AdmissionWindowConfig>>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance_mode: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_dry_run: Option<bool>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_dry_run: Option<bool>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_limits: Option<ArtifactLimits>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpServerConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<VerificationConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mdns: Option<MdnsConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coaps: Option<CoapsConfig>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snmp: Option<SnmpConfig>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clone_detection_window_secs: Option<u64>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_window_secs: Option<u64>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub masa_proxy: Option<ProxyConfig>,
+    #[arg(long)]
+    #[clap(value_parser = parse_relative_path_buf)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proximity_registrar_certificates: Option<Vec<RelativePathBuf>>,
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_proxy: Option<JoinProxyConfig>,
+    #[arg(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub masa_url: Option<String>
+    pub capture: Option<CaptureConfig>,
 }
@@ -0,0 +1,404 @@
+//! Pure-Rust decoding of the DER structures exchanged over EST (RFC 7030). CSRs, certificates and certs-only
+//! PKCS#7 received from the network are decoded here before openssl sees them. openssl only gets DER the decoder
+//! accepted and that re-encodes byte for byte, so malformed input never reaches its parsers.
+//! [`with_challenge_password`], [`signed_data`], [`encode_certs_only`] and [`encode_csr_attrs`] encode what openssl has
+//! no API for. The time-stamp protocol (RFC 3161) messages are defined here as well.
+
+use cmpv2::status::PkiStatusInfo;
+use cms::{
+    cert::{CertificateChoices, IssuerAndSerialNumber},
+    content_info::{CmsVersion, ContentInfo},
+    signed_data::{EncapsulatedContentInfo, SignedData, SignerIdentifier, SignerInfo},
+};
+use der::{
+    asn1::{Any, BitString, Int, Null, ObjectIdentifier, OctetString, PrintableStringRef, SetOfVec, Utf8StringRef},
+    Decode, Encode, Sequence, Tag, Tagged,
+};
+use openssl::{
+    error::ErrorStack,
+    hash::{hash, MessageDigest},
+    pkey::{Id, PKeyRef, Private},
+    sign::Signer,
+    x509::{X509Req, X509},
+};
+use thiserror::Error;
+use x509_cert::{
+    attr::Attribute,
+    ext::{pkix::name::GeneralName, Extensions},
+    request::CertReq,
+    spki::AlgorithmIdentifierOwned,
+    Certificate,
+};
+
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_CHALLENGE_PASSWORD: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.7");
+const ID_CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+pub(crate) const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const SHA256_WITH_RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+/// Content type of JSON vouchers and voucher-requests in CMS, RFC 8366 Section 8.3
+pub const ID_CT_ANIMA_JSON_VOUCHER: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.40");
+/// Content type of time-stamp tokens, RFC 3161 Section 2.4.2
+pub const ID_CT_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+
+#[derive(Error, Debug)]
+pub enum Asn1Error {
+    #[error("malformed DER: {0}")]
+    Der(#[from] der::Error),
+    #[error("DER is not canonically encoded")]
+    NotCanonical,
+    #[error("content type {0} is not signed-data")]
+    NotSignedData(ObjectIdentifier),
+    #[error(transparent)]
+    Openssl(#[from] ErrorStack),
+}
+
+/// Decodes `der` and checks that it is the DER encoding of what was decoded, e.g. that the SET OF attributes of a
+/// CSR is sorted. Signatures are verified over the original bytes, so they are not re-encoded.
+fn canonical<'a, T: Decode<'a> + Encode>(der: &'a [u8]) -> Result<T, Asn1Error> {
+    let decoded = T::from_der(der)?;
+    if decoded.to_der()? != der {
+        return Err(Asn1Error::NotCanonical);
+    }
+    Ok(decoded)
+}
+
+/// PKCS#10 certification request, e.g. the body of `/simpleenroll`
+pub fn certification_request(der: &[u8]) -> Result<X509Req, Asn1Error> {
+    canonical::<CertReq>(der)?;
+    Ok(X509Req::from_der(der)?)
+}
+
+/// X.509 certificate, e.g. an LDevID
+pub fn certificate(der: &[u8]) -> Result<X509, Asn1Error> {
+    canonical::<Certificate>(der)?;
+    Ok(X509::from_der(der)?)
+}
+
+/// SignedData of a CMS ContentInfo, RFC 5652 Section 5.1
+pub fn decode_signed_data(der: &[u8]) -> Result<SignedData, Asn1Error> {
+    let content_info = ContentInfo::from_der(der)?;
+    if content_info.content_type != ID_SIGNED_DATA {
+        return Err(Asn1Error::NotSignedData(content_info.content_type));
+    }
+    Ok(content_info.content.decode_as()?)
+}
+
+/// Certificates of a CMS SignedData, e.g. the certs-only response of `/cacerts` (RFC 7030 Section 4.1.3).
+/// Signatures and other certificate formats are ignored.
+pub fn certs_only(der: &[u8]) -> Result<Vec<X509>, Asn1Error> {
+    decode_signed_data(der)?
+        .certificates
+        .map(|certificates| certificates.0.into_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(certificate) => Some(certificate),
+            CertificateChoices::Other(_) => None,
+        })
+        .map(|certificate| Ok(X509::from_der(&certificate.to_der()?)?))
+        .collect()
+}
+
+/// challengePassword attribute of a PKCS#10 certification request, RFC 2985 Section 5.4.1
+pub fn challenge_password(der: &[u8]) -> Result<Option<String>, Asn1Error> {
+    let csr = CertReq::from_der(der)?;
+    let values = csr
+        .info
+        .attributes
+        .iter()
+        .filter(|attribute| attribute.oid == ID_CHALLENGE_PASSWORD)
+        .flat_map(|attribute| attribute.values.iter());
+
+    for value in values {
+        let password = match value.tag() {
+            Tag::Utf8String => value.decode_as::<Utf8StringRef>()?.to_string(),
+            Tag::PrintableString => value.decode_as::<PrintableStringRef>()?.to_string(),
+            _ => continue,
+        };
+        return Ok(Some(password));
+    }
+    Ok(None)
+}
+
+/// Adds a challengePassword attribute to a certification request and signs it again with `key`, which has to be the
+/// key of the request. Pledges send the channel binding of their TLS session this way, RFC 7030 Section 3.5.
+pub fn with_challenge_password(
+    csr: &X509Req,
+    password: &str,
+    key: &PKeyRef<Private>,
+    digest: MessageDigest,
+) -> Result<X509Req, Asn1Error> {
+    let der = csr.to_der()?;
+    let mut request = CertReq::from_der(&der)?;
+    request.info.attributes.insert(Attribute {
+        oid: ID_CHALLENGE_PASSWORD,
+        values: SetOfVec::try_from(vec![Any::encode_from(&Utf8StringRef::new(password)?)?])?,
+    })?;
+
+    let mut signer = Signer::new(digest, key)?;
+    signer.update(&request.info.to_der()?)?;
+    request.signature = BitString::from_bytes(&signer.sign_to_vec()?)?;
+
+    Ok(X509Req::from_der(&request.to_der()?)?)
+}
+
+/// Encodes `content` as CMS SignedData (RFC 5652) of `content_type`, signed by `signer` with SHA-256 and carrying its
+/// certificate. openssl's CMS API always labels the content as id-data, RFC 8366 needs its own content type.
+pub fn signed_data(
+    content_type: ObjectIdentifier,
+    content: &[u8],
+    signer: &X509,
+    key: &PKeyRef<Private>,
+) -> Result<Vec<u8>, Asn1Error> {
+    let certificate = Certificate::from_der(&signer.to_der()?)?;
+    let digest_algorithm = AlgorithmIdentifierOwned { oid: ID_SHA256, parameters: None };
+    let signature_algorithm = match key.id() {
+        Id::RSA => AlgorithmIdentifierOwned { oid: SHA256_WITH_RSA_ENCRYPTION, parameters: Some(Any::encode_from(&Null)?) },
+        _ => AlgorithmIdentifierOwned { oid: ECDSA_WITH_SHA256, parameters: None },
+    };
+
+    // the signature covers the DER of the signed attributes as SET OF, RFC 5652 Section 5.4
+    let signed_attrs = SetOfVec::try_from(vec![
+        Attribute {
+            oid: ID_CONTENT_TYPE,
+            values: SetOfVec::try_from(vec![Any::encode_from(&content_type)?])?,
+        },
+        Attribute {
+            oid: ID_MESSAGE_DIGEST,
+            values: SetOfVec::try_from(vec![Any::encode_from(&OctetString::new(hash(MessageDigest::sha256(), content)?.to_vec())?)?])?,
+        },
+    ])?;
+    let signature = Signer::new(MessageDigest::sha256(), key)?.sign_oneshot_to_vec(&signed_attrs.to_der()?)?;
+
+    let signer_info = SignerInfo {
+        version: CmsVersion::V1,
+        sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: certificate.tbs_certificate.issuer.clone(),
+            serial_number: certificate.tbs_certificate.serial_number.clone(),
+        }),
+        digest_alg: digest_algorithm.clone(),
+        signed_attrs: Some(signed_attrs),
+        signature_algorithm,
+        signature: OctetString::new(signature)?,
+        unsigned_attrs: None,
+    };
+    let signed_data = SignedData {
+        // content other than id-data needs version 3, RFC 5652 Section 5.1
+        version: CmsVersion::V3,
+        digest_algorithms: SetOfVec::try_from(vec![digest_algorithm])?,
+        encap_content_info: EncapsulatedContentInfo {
+            econtent_type: content_type,
+            econtent: Some(Any::encode_from(&OctetString::new(content)?)?),
+        },
+        certificates: Some(SetOfVec::try_from(vec![CertificateChoices::Certificate(certificate)])?.into()),
+        crls: None,
+        signer_infos: SetOfVec::try_from(vec![signer_info])?.into(),
+    };
+
+    Ok(ContentInfo {
+        content_type: ID_SIGNED_DATA,
+        content: Any::encode_from(&signed_data)?,
+    }
+    .to_der()?)
+}
+
+/// Degenerate certs-only CMS SignedData, RFC 7030 Section 4.1.3, e.g. the CA certificates of `/cacerts` or the
+/// issued certificate of `/simpleenroll`
+pub fn encode_certs_only(certificates: &[X509]) -> Result<Vec<u8>, Asn1Error> {
+    let certificates = certificates
+        .iter()
+        .map(|certificate| Ok(CertificateChoices::Certificate(Certificate::from_der(&certificate.to_der()?)?)))
+        .collect::<Result<Vec<_>, Asn1Error>>()?;
+
+    let signed_data = SignedData {
+        version: CmsVersion::V1,
+        digest_algorithms: SetOfVec::new(),
+        encap_content_info: EncapsulatedContentInfo {
+            econtent_type: ID_DATA,
+            econtent: None,
+        },
+        certificates: Some(SetOfVec::try_from(certificates)?.into()),
+        crls: None,
+        signer_infos: SetOfVec::new().into(),
+    };
+
+    Ok(ContentInfo {
+        content_type: ID_SIGNED_DATA,
+        content: Any::encode_from(&signed_data)?,
+    }
+    .to_der()?)
+}
+
+/// CSR attributes asking for an EC key on one of `curves`, RFC 7030 Section 4.5.2
+pub fn encode_csr_attrs(curves: &[ObjectIdentifier]) -> Result<Vec<u8>, Asn1Error> {
+    let curves = curves.iter().map(Any::encode_from).collect::<Result<Vec<_>, _>>()?;
+    let attribute = Attribute {
+        oid: ID_EC_PUBLIC_KEY,
+        values: SetOfVec::try_from(curves)?,
+    };
+
+    Ok(vec![attribute].to_der()?)
+}
+
+/// MessageImprint, RFC 3161 Section 2.4.1
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct MessageImprint {
+    pub hash_algorithm: AlgorithmIdentifierOwned,
+    pub hashed_message: OctetString,
+}
+
+/// TimeStampReq, RFC 3161 Section 2.4.1. Request extensions are not supported.
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct TimeStampReq {
+    pub version: u8,
+    pub message_imprint: MessageImprint,
+    pub req_policy: Option<ObjectIdentifier>,
+    pub nonce: Option<Int>,
+    #[asn1(default = "Default::default")]
+    pub cert_req: bool,
+}
+
+/// TimeStampResp, RFC 3161 Section 2.4.2. The token is a CMS SignedData over a [`TstInfo`].
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct TimeStampResp<'a> {
+    pub status: PkiStatusInfo<'a>,
+    pub time_stamp_token: Option<ContentInfo>,
+}
+
+/// TSTInfo, RFC 3161 Section 2.4.2. genTime is left undecoded, it may carry fractional seconds which
+/// [`der::asn1::GeneralizedTime`] rejects.
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct TstInfo {
+    pub version: u8,
+    pub policy: ObjectIdentifier,
+    pub message_imprint: MessageImprint,
+    pub serial_number: Int,
+    pub gen_time: Any,
+    pub accuracy: Option<Accuracy>,
+    #[asn1(default = "Default::default")]
+    pub ordering: bool,
+    pub nonce: Option<Int>,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    pub tsa: Option<GeneralName>,
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", optional = "true")]
+    pub extensions: Option<Extensions>,
+}
+
+/// Accuracy of a [`TstInfo`], RFC 3161 Section 2.4.2
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct Accuracy {
+    pub seconds: Option<Int>,
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT", optional = "true")]
+    pub millis: Option<u16>,
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", optional = "true")]
+    pub micros: Option<u16>,
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+    use openssl::{
+        cms::{CMSOptions, CmsContentInfo},
+        hash::MessageDigest,
+        pkcs7::{Pkcs7, Pkcs7Flags},
+        stack::Stack,
+        x509::{store::X509StoreBuilder, X509NameBuilder, X509ReqBuilder},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_certificate() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let der = certs.registrar.0.to_der().unwrap();
+        assert_eq!(certificate(&der).unwrap().to_der().unwrap(), der);
+
+        let mut trailing = der.clone();
+        trailing.push(0);
+        assert!(certificate(&trailing).is_err());
+        assert!(certificate(&der[..der.len() - 1]).is_err());
+
+        // the same certificate with a needlessly long length of the outer SEQUENCE
+        let (_, content) = der.split_at(4);
+        let long_form = [&[0x30, 0x83, 0x00][..], &der[2..4], content].concat();
+        assert!(matches!(certificate(&long_form), Err(Asn1Error::Der(_))));
+    }
+
+    #[test]
+    fn test_certification_request() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (_, key) = &certs.pledge;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("serialNumber", "00-D0-E5-F2-00-02").unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        let der = builder.build().to_der().unwrap();
+
+        assert_eq!(certification_request(&der).unwrap().to_der().unwrap(), der);
+        assert!(challenge_password(&der).unwrap().is_none());
+        assert!(certification_request(&der[1..]).is_err());
+
+        let bound = with_challenge_password(&X509Req::from_der(&der).unwrap(), "c2Vzc2lvbg==", key, MessageDigest::sha256()).unwrap();
+        let bound = bound.to_der().unwrap();
+        assert_eq!(challenge_password(&bound).unwrap().as_deref(), Some("c2Vzc2lvbg=="));
+        assert!(certification_request(&bound).unwrap().verify(key).unwrap());
+    }
+
+    #[test]
+    fn test_certs_only() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar, registrar_key) = &certs.registrar;
+        let mut chain = Stack::new().unwrap();
+        chain.push(certs.registrar_ca.0.clone()).unwrap();
+
+        let pkcs7 = Pkcs7::sign(registrar, registrar_key, &chain, b"", Pkcs7Flags::BINARY).unwrap();
+        let mut decoded: Vec<Vec<u8>> = certs_only(&pkcs7.to_der().unwrap())
+            .unwrap()
+            .iter()
+            .map(|certificate| certificate.to_der().unwrap())
+            .collect();
+        let mut expected = vec![registrar.to_der().unwrap(), certs.registrar_ca.0.to_der().unwrap()];
+        decoded.sort();
+        expected.sort();
+        assert_eq!(decoded, expected);
+
+        assert!(certs_only(&registrar.to_der().unwrap()).is_err());
+
+        let encoded = encode_certs_only(std::slice::from_ref(registrar)).unwrap();
+        let decoded = certs_only(&encoded).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].to_der().unwrap(), registrar.to_der().unwrap());
+    }
+
+    #[test]
+    fn test_signed_data() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge, pledge_key) = &certs.pledge;
+
+        let der = signed_data(ID_CT_ANIMA_JSON_VOUCHER, br#"{"ietf-voucher-request:voucher":{}}"#, pledge, pledge_key).unwrap();
+
+        let content_info = ContentInfo::from_der(&der).unwrap();
+        let signed = content_info.content.decode_as::<SignedData>().unwrap();
+        assert_eq!(signed.encap_content_info.econtent_type, ID_CT_ANIMA_JSON_VOUCHER);
+
+        // openssl checks the signature, the message digest and that the contentType attribute matches
+        let mut cms = CmsContentInfo::from_der(&der).unwrap();
+        let mut content = vec![];
+        cms.verify(
+            None,
+            Some(&X509StoreBuilder::new().unwrap().build()),
+            None,
+            Some(&mut content),
+            CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY,
+        )
+        .unwrap();
+        assert_eq!(content, br#"{"ietf-voucher-request:voucher":{}}"#);
+    }
+}
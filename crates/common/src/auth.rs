@@ -0,0 +1,315 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use josekit::{
+    jwk::JwkSet,
+    jws::{JwsVerifier, ES256, ES384, RS256, RS384, RS512},
+    jwt::JwtPayloadValidator,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+/// How long fetched signing keys of the OIDC provider are used before they are fetched again
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+/// Tokens with an unknown key id make the keys be fetched again at most this often, so unauthenticated clients
+/// cannot make every request hit the OIDC provider
+const JWKS_MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "admin:read")]
+    Read,
+    #[serde(rename = "admin:write")]
+    Write,
+}
+
+impl Scope {
+    /// Reading requests need [`Scope::Read`], everything else changes state and needs [`Scope::Write`]
+    pub fn for_method(method: &Method) -> Self {
+        if method == Method::GET || method == Method::HEAD {
+            Scope::Read
+        } else {
+            Scope::Write
+        }
+    }
+
+    fn from_claim(scope: &str) -> Option<Self> {
+        match scope {
+            "admin:read" => Some(Scope::Read),
+            "admin:write" => Some(Scope::Write),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    pub key: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct OidcValidator {
+    issuer: String,
+    audience: String,
+    jwks: RwLock<Option<(JwkSet, Instant)>>,
+}
+
+/// Authenticates requests to admin and inspection endpoints, either with a static API key
+/// or with a bearer token issued by an OpenID Connect provider. Token scopes are read from the `scope` claim.
+#[derive(Debug)]
+pub struct Authenticator {
+    api_keys: Vec<ApiKey>,
+    oidc: Option<OidcValidator>,
+    client: reqwest::Client,
+}
+
+impl Authenticator {
+    pub fn new(
+        api_keys: Vec<ApiKey>,
+        oidc_issuer: Option<String>,
+        oidc_audience: Option<String>,
+        client: reqwest::Client,
+    ) -> Self {
+        let oidc = oidc_issuer.zip(oidc_audience).map(|(issuer, audience)| OidcValidator {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audience,
+            jwks: RwLock::new(None),
+        });
+
+        if api_keys.is_empty() && oidc.is_none() {
+            event!(Level::WARN, "No API keys or OIDC provider configured, admin endpoints will reject all requests");
+        }
+
+        Self {
+            api_keys,
+            oidc,
+            client,
+        }
+    }
+
+    /// Returns the scopes granted to the token, `None` if the token is not valid at all
+    pub async fn authenticate(&self, token: &str) -> Option<HashSet<Scope>> {
+        if let Some(api_key) = self.api_keys.iter().find(|api_key| key_matches(&api_key.key, token)) {
+            return Some(api_key.scopes.iter().copied().collect());
+        }
+
+        let oidc = self.oidc.as_ref()?;
+        match oidc.validate(token, &self.client).await {
+            Ok(scopes) => Some(scopes),
+            Err(err) => {
+                event!(Level::WARN, "Rejected OIDC token: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl OidcValidator {
+    async fn validate(&self, token: &str, client: &reqwest::Client) -> anyhow::Result<HashSet<Scope>> {
+        let header = josekit::jwt::decode_header(token)?;
+        let kid = header
+            .claim("kid")
+            .and_then(|kid| kid.as_str())
+            .ok_or(anyhow!("token has no kid"))?
+            .to_string();
+        let alg = header
+            .claim("alg")
+            .and_then(|alg| alg.as_str())
+            .ok_or(anyhow!("token has no alg"))?
+            .to_string();
+
+        let jwks = self.jwks(client, &kid).await?;
+        let jwk = jwks
+            .get(&kid)
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("unknown signing key {}", kid))?
+            .clone();
+
+        let verifier: Box<dyn JwsVerifier> = match alg.as_str() {
+            "RS256" => Box::new(RS256.verifier_from_jwk(&jwk)?),
+            "RS384" => Box::new(RS384.verifier_from_jwk(&jwk)?),
+            "RS512" => Box::new(RS512.verifier_from_jwk(&jwk)?),
+            "ES256" => Box::new(ES256.verifier_from_jwk(&jwk)?),
+            "ES384" => Box::new(ES384.verifier_from_jwk(&jwk)?),
+            _ => return Err(anyhow!("unsupported algorithm {}", alg)),
+        };
+
+        let (payload, _) = josekit::jwt::decode_with_verifier(token, verifier.as_ref())?;
+
+        let mut validator = JwtPayloadValidator::new();
+        validator.set_base_time(SystemTime::now());
+        validator.set_issuer(self.issuer.clone());
+        validator.set_audience(self.audience.clone());
+        validator.validate(&payload)?;
+
+        event!(Level::DEBUG, "Authenticated {:?} via OIDC", payload.subject());
+
+        let scopes = payload
+            .claim("scope")
+            .and_then(|scope| scope.as_str())
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(Scope::from_claim)
+            .collect();
+
+        Ok(scopes)
+    }
+
+    /// Returns the provider keys, fetching them again when they are stale or do not contain the key id.
+    /// Keys fetched less than [`JWKS_MIN_REFETCH_INTERVAL`] ago are used even without the key id.
+    async fn jwks(&self, client: &reqwest::Client, kid: &str) -> anyhow::Result<JwkSet> {
+        if let Some(jwks) = self.jwks.read().await.as_ref().and_then(|cached| fresh(cached, kid)) {
+            return Ok(jwks);
+        }
+
+        // requests waiting for the lock use the keys fetched by the first one
+        let mut cached = self.jwks.write().await;
+        if let Some(jwks) = cached.as_ref().and_then(|cached| fresh(cached, kid)) {
+            return Ok(jwks);
+        }
+
+        let metadata_url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let metadata: ProviderMetadata = client.get(metadata_url).send().await?.json().await?;
+        let jwks = JwkSet::from_bytes(client.get(metadata.jwks_uri).send().await?.bytes().await?)?;
+
+        *cached = Some((jwks.clone(), Instant::now()));
+        Ok(jwks)
+    }
+}
+
+fn fresh((jwks, fetched): &(JwkSet, Instant), kid: &str) -> Option<JwkSet> {
+    let usable = fetched.elapsed() < JWKS_MIN_REFETCH_INTERVAL
+        || (fetched.elapsed() < JWKS_REFRESH_INTERVAL && !jwks.get(kid).is_empty());
+    usable.then(|| jwks.clone())
+}
+
+/// Compares in constant time, so the time to reject a token does not tell how much of a key it guessed
+fn key_matches(key: &str, token: &str) -> bool {
+    key.len() == token.len() && openssl::memcmp::eq(key.as_bytes(), token.as_bytes())
+}
+
+/// Middleware for admin routers, use with `axum::middleware::from_fn_with_state`
+pub async fn require_scope(
+    State(authenticator): State<Arc<Authenticator>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let scopes = authenticator
+        .authenticate(token)
+        .await
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let required = Scope::for_method(request.method());
+    if !scopes.contains(&required) {
+        event!(Level::WARN, "Token lacks scope {:?} for {} {}", required, request.method(), request.uri());
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_key_scopes() {
+        let authenticator = Authenticator::new(
+            vec![
+                ApiKey {
+                    key: "reader".to_string(),
+                    scopes: vec![Scope::Read],
+                },
+                ApiKey {
+                    key: "admin".to_string(),
+                    scopes: vec![Scope::Read, Scope::Write],
+                },
+            ],
+            None,
+            None,
+            reqwest::Client::new(),
+        );
+
+        let reader = authenticator.authenticate("reader").await.unwrap();
+        assert!(reader.contains(&Scope::Read));
+        assert!(!reader.contains(&Scope::Write));
+
+        let admin = authenticator.authenticate("admin").await.unwrap();
+        assert!(admin.contains(&Scope::Write));
+
+        assert!(authenticator.authenticate("wrong").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_kid_does_not_refetch_keys() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use axum::{routing::get, Json, Router};
+        use serde_json::json;
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let counter = fetches.clone();
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({ "jwks_uri": format!("http://{}/jwks", address) }))
+                }),
+            )
+            .route("/jwks", get(|| async { Json(json!({ "keys": [] })) }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let authenticator = Authenticator::new(
+            vec![],
+            Some(format!("http://{}", address)),
+            Some("registrar".to_string()),
+            reqwest::Client::new(),
+        );
+        let header = openssl::base64::encode_block(br#"{"alg":"ES256","kid":"unknown"}"#).trim_end_matches('=').to_string();
+        let token = format!("{}.e30.c2lnbmF0dXJl", header);
+
+        for _ in 0..3 {
+            assert!(authenticator.authenticate(&token).await.is_none());
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_key_matches() {
+        assert!(key_matches("secret", "secret"));
+        assert!(!key_matches("secret", "secreT"));
+        assert!(!key_matches("secret", "secret-but-longer"));
+    }
+
+    #[test]
+    fn test_scope_for_method() {
+        assert_eq!(Scope::for_method(&Method::GET), Scope::Read);
+        assert_eq!(Scope::for_method(&Method::POST), Scope::Write);
+    }
+}
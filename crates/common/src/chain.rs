@@ -0,0 +1,68 @@
+//! Certificate path validation of the MASA, the registrar and the pledges with OpenSSL's verifier
+
+use openssl::{
+    error::ErrorStack,
+    stack::Stack,
+    x509::{store::X509StoreBuilder, X509Ref, X509StoreContext, X509VerifyResult, X509},
+};
+
+/// Verifies that `certificate` chains to one of `anchors`, with `intermediates` as untrusted certificates to build
+/// the path from. A certificate that does not chain comes back with the reason of the verifier.
+pub fn verify_chain(certificate: &X509Ref, anchors: &[X509], intermediates: &[X509]) -> Result<Result<(), X509VerifyResult>, ErrorStack> {
+    Ok(verified_chain(certificate, anchors, intermediates)?.map(|_| ()))
+}
+
+/// [`verify_chain`] returning the validated path, from `certificate` up to the anchor
+pub fn verified_chain(
+    certificate: &X509Ref,
+    anchors: &[X509],
+    intermediates: &[X509],
+) -> Result<Result<Vec<X509>, X509VerifyResult>, ErrorStack> {
+    let mut store = X509StoreBuilder::new()?;
+    for anchor in anchors {
+        store.add_cert(anchor.clone())?;
+    }
+    let store = store.build();
+
+    let mut chain = Stack::new()?;
+    for intermediate in intermediates {
+        chain.push(intermediate.clone())?;
+    }
+
+    let mut context = X509StoreContext::new()?;
+    context.init(&store, certificate, &chain, |context| {
+        if !context.verify_cert()? {
+            return Ok(Err(context.error()));
+        }
+        let path = context.chain().map(|path| path.iter().map(ToOwned::to_owned).collect()).unwrap_or_default();
+        Ok(Ok(path))
+    })
+}
+
+/// [`verify_chain`] when only the outcome matters
+pub fn chains_to(certificate: &X509Ref, anchors: &[X509], intermediates: &[X509]) -> Result<bool, ErrorStack> {
+    Ok(verify_chain(certificate, anchors, intermediates)?.is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    #[test]
+    fn test_verify_chain() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar, _) = &certs.registrar;
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let (vendor_ca, _) = &certs.vendor_ca;
+
+        assert!(chains_to(registrar, std::slice::from_ref(registrar_ca), &[]).unwrap());
+        assert!(verify_chain(registrar, std::slice::from_ref(vendor_ca), &[]).unwrap().is_err());
+        assert!(!chains_to(registrar, &[], &[]).unwrap());
+
+        let path = verified_chain(registrar, std::slice::from_ref(registrar_ca), std::slice::from_ref(vendor_ca)).unwrap().unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[1].to_der().unwrap(), registrar_ca.to_der().unwrap());
+    }
+}
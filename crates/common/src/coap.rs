@@ -0,0 +1,350 @@
+//! CoAP messages (RFC 7252) for the constrained transports, as carried in DTLS records or UDP datagrams.
+//!
+//! Options are kept as raw values in the order of their numbers, see [`crate::coap_block`] for the Block1/Block2 values.
+
+use thiserror::Error;
+
+/// Option numbers, RFC 7252 Section 12.2
+/// OSCORE, RFC 8613 Section 2
+pub const OSCORE: u16 = 9;
+pub const URI_PATH: u16 = 11;
+pub const CONTENT_FORMAT: u16 = 12;
+pub const URI_QUERY: u16 = 15;
+pub const ACCEPT: u16 = 17;
+pub const SIZE2: u16 = 28;
+pub const SIZE1: u16 = 60;
+
+/// Content-Formats of the EST-coaps payloads, RFC 9148 Section 8.3
+pub const PKCS7_CERTS_ONLY: u16 = 281;
+pub const PKCS8: u16 = 284;
+pub const CSRATTRS: u16 = 285;
+pub const PKCS10: u16 = 286;
+pub const PKIX_CERT: u16 = 287;
+pub const JSON: u16 = 50;
+
+/// Content-Formats of EDHOC messages over CoAP, RFC 9528 Section 10.9. The second one is prefixed with a connection
+/// identifier, as message_3 sent to the responder.
+pub const EDHOC_CBOR_SEQ: u16 = 64;
+pub const CID_EDHOC_CBOR_SEQ: u16 = 65;
+
+/// Resource EDHOC messages are POSTed to, RFC 9528 Appendix A.2
+pub const EDHOC_PATH: &str = ".well-known/edhoc";
+/// CBOR `true`, which precedes message_1 to tell it apart from a message_3 prefixed with C_R
+pub const EDHOC_MESSAGE_1_PREFIX: u8 = 0xf5;
+
+/// Message codes as `class << 5 | detail`, RFC 7252 Section 12.1
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    pub const CREATED: u8 = 0x41;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    /// 2.31 Continue, RFC 7959 Section 2.9.1
+    pub const CONTINUE: u8 = 0x5f;
+
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const UNAUTHORIZED: u8 = 0x81;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const FORBIDDEN: u8 = 0x83;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const NOT_ACCEPTABLE: u8 = 0x86;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    /// 4.09 Conflict, RFC 8132 Section 6
+    pub const CONFLICT: u8 = 0x89;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const UNSUPPORTED_CONTENT_FORMAT: u8 = 0x8f;
+
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const BAD_GATEWAY: u8 = 0xa2;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+    pub const GATEWAY_TIMEOUT: u8 = 0xa4;
+
+    pub fn is_request(code: u8) -> bool {
+        (0x01..0x20).contains(&code)
+    }
+}
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+const MAX_TOKEN_LENGTH: usize = 8;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CoapError {
+    #[error("Message is truncated")]
+    Truncated,
+    #[error("Message has CoAP version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Token is longer than {MAX_TOKEN_LENGTH} bytes")]
+    TokenTooLong,
+    #[error("Option delta or length uses the reserved value 15")]
+    InvalidOption,
+    #[error("Payload marker is followed by an empty payload")]
+    EmptyPayload,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Vec<u8>,
+    /// Sorted by option number, repeated options keep their order
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    /// Response to `request`, piggybacked on the acknowledgement of a confirmable request
+    pub fn response(request: &Message, code: u8) -> Self {
+        let message_type = match request.message_type {
+            MessageType::Confirmable => MessageType::Acknowledgement,
+            _ => MessageType::NonConfirmable,
+        };
+        Self {
+            message_type,
+            code,
+            message_id: request.message_id,
+            token: request.token.clone(),
+            options: vec![],
+            payload: vec![],
+        }
+    }
+
+    pub fn decode(datagram: &[u8]) -> Result<Self, CoapError> {
+        let [first, code, id_high, id_low, rest @ ..] = datagram else {
+            return Err(CoapError::Truncated);
+        };
+        if first >> 6 != VERSION {
+            return Err(CoapError::UnsupportedVersion(first >> 6));
+        }
+        let token_length = usize::from(first & 0x0f);
+        if token_length > MAX_TOKEN_LENGTH {
+            return Err(CoapError::TokenTooLong);
+        }
+        if rest.len() < token_length {
+            return Err(CoapError::Truncated);
+        }
+        let (token, mut rest) = rest.split_at(token_length);
+
+        let mut options = vec![];
+        let mut number = 0u16;
+        let payload = loop {
+            let Some((&header, tail)) = rest.split_first() else {
+                break vec![];
+            };
+            if header == PAYLOAD_MARKER {
+                if tail.is_empty() {
+                    return Err(CoapError::EmptyPayload);
+                }
+                break tail.to_vec();
+            }
+
+            let (delta, tail) = extended(header >> 4, tail)?;
+            let (length, tail) = extended(header & 0x0f, tail)?;
+            if tail.len() < usize::from(length) {
+                return Err(CoapError::Truncated);
+            }
+            let (value, tail) = tail.split_at(usize::from(length));
+
+            number = number.checked_add(delta).ok_or(CoapError::InvalidOption)?;
+            options.push((number, value.to_vec()));
+            rest = tail;
+        };
+
+        Ok(Self {
+            message_type: MessageType::from_bits(first >> 4),
+            code: *code,
+            message_id: u16::from_be_bytes([*id_high, *id_low]),
+            token: token.to_vec(),
+            options,
+            payload,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![
+            VERSION << 6 | (self.message_type as u8) << 4 | self.token.len() as u8,
+            self.code,
+        ];
+        out.extend_from_slice(&self.message_id.to_be_bytes());
+        out.extend_from_slice(&self.token);
+
+        let mut options: Vec<&(u16, Vec<u8>)> = self.options.iter().collect();
+        options.sort_by_key(|(number, _)| *number);
+
+        let mut previous = 0;
+        for (number, value) in options {
+            let (delta_nibble, delta_extension) = nibble(number - previous);
+            let (length_nibble, length_extension) = nibble(value.len() as u16);
+            out.push(delta_nibble << 4 | length_nibble);
+            out.extend_from_slice(&delta_extension);
+            out.extend_from_slice(&length_extension);
+            out.extend_from_slice(value);
+            previous = *number;
+        }
+
+        if !self.payload.is_empty() {
+            out.push(PAYLOAD_MARKER);
+            out.extend_from_slice(&self.payload);
+        }
+        out
+    }
+
+    pub fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options.iter().find(|(option, _)| *option == number).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).map(decode_uint)
+    }
+
+    /// Replaces every value of the option
+    pub fn set_option(&mut self, number: u16, value: Vec<u8>) {
+        self.options.retain(|(option, _)| *option != number);
+        self.options.push((number, value));
+    }
+
+    pub fn set_uint_option(&mut self, number: u16, value: u32) {
+        self.set_option(number, encode_uint(value));
+    }
+
+    /// Uri-Path segments joined by `/`, without a leading slash
+    pub fn uri_path(&self) -> String {
+        self.options
+            .iter()
+            .filter(|(option, _)| *option == URI_PATH)
+            .map(|(_, segment)| String::from_utf8_lossy(segment))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    pub fn set_uri_path(&mut self, path: &str) {
+        self.options.retain(|(option, _)| *option != URI_PATH);
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            self.options.push((URI_PATH, segment.as_bytes().to_vec()));
+        }
+    }
+}
+
+/// Reads the extended delta or length of an option, RFC 7252 Section 3.1
+fn extended(nibble: u8, data: &[u8]) -> Result<(u16, &[u8]), CoapError> {
+    match nibble {
+        13 => {
+            let (&byte, rest) = data.split_first().ok_or(CoapError::Truncated)?;
+            Ok((u16::from(byte) + 13, rest))
+        }
+        14 => {
+            let [high, low, rest @ ..] = data else {
+                return Err(CoapError::Truncated);
+            };
+            u16::from_be_bytes([*high, *low])
+                .checked_add(269)
+                .map(|value| (value, rest))
+                .ok_or(CoapError::InvalidOption)
+        }
+        15 => Err(CoapError::InvalidOption),
+        nibble => Ok((u16::from(nibble), data)),
+    }
+}
+
+fn nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, vec![]),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+/// Encodes an option value as the shortest unsigned integer, `0` is the empty value
+pub fn encode_uint(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+pub fn decode_uint(value: &[u8]) -> u32 {
+    value.iter().fold(0u32, |value, byte| value << 8 | u32::from(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut message = Message {
+            message_type: MessageType::Confirmable,
+            code: code::POST,
+            message_id: 0x1234,
+            token: vec![0xde, 0xad],
+            options: vec![],
+            payload: b"voucher request".to_vec(),
+        };
+        message.set_uri_path("/.well-known/brski/rv");
+        message.set_uint_option(CONTENT_FORMAT, u32::from(PKCS10));
+        // needs the two byte extended delta
+        message.set_option(SIZE1, encode_uint(300));
+        message.set_option(1000, vec![0; 20]);
+
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.uri_path(), ".well-known/brski/rv");
+        assert_eq!(decoded.uint_option(CONTENT_FORMAT), Some(286));
+        assert_eq!(decoded.uint_option(SIZE1), Some(300));
+        assert_eq!(decoded.option(1000), Some(&[0; 20][..]));
+        assert_eq!(decoded.token, message.token);
+        assert_eq!(decoded.payload, message.payload);
+    }
+
+    #[test]
+    fn test_decode_rfc_example() {
+        // RFC 7252 Appendix A, GET /temperature without token
+        let mut datagram = vec![0x40, 0x01, 0x7d, 0x34, 0xbb];
+        datagram.extend_from_slice(b"temperature");
+
+        let message = Message::decode(&datagram).unwrap();
+        assert_eq!(message.message_type, MessageType::Confirmable);
+        assert_eq!(message.code, code::GET);
+        assert_eq!(message.message_id, 0x7d34);
+        assert_eq!(message.uri_path(), "temperature");
+        assert!(message.payload.is_empty());
+
+        let response = Message::response(&message, code::CONTENT);
+        assert_eq!(response.message_type, MessageType::Acknowledgement);
+        assert_eq!(response.message_id, 0x7d34);
+        assert_eq!(Message::decode(&response.encode()).unwrap(), response);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Message::decode(&[0x40, 0x01, 0x00]), Err(CoapError::Truncated));
+        assert_eq!(Message::decode(&[0x80, 0x01, 0x00, 0x00]), Err(CoapError::UnsupportedVersion(2)));
+        assert_eq!(Message::decode(&[0x49, 0x01, 0x00, 0x00]), Err(CoapError::TokenTooLong));
+        assert_eq!(Message::decode(&[0x40, 0x01, 0x00, 0x00, 0xf0]), Err(CoapError::InvalidOption));
+        assert_eq!(Message::decode(&[0x40, 0x01, 0x00, 0x00, 0xff]), Err(CoapError::EmptyPayload));
+        assert_eq!(Message::decode(&[0x40, 0x01, 0x00, 0x00, 0xb4, b'a']), Err(CoapError::Truncated));
+    }
+}
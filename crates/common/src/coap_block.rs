@@ -0,0 +1,186 @@
+//! Block-wise transfer for CoAP (RFC 7959), so artifacts larger than a datagram, like vouchers and
+//! certificate chains, can be carried by the constrained transports.
+//!
+//! This only covers the Block1/Block2 option handling, see [`crate::coap`] for the messages carrying them.
+//! The registrar uses it for the requests and responses of its DTLS and OSCORE listeners, pledges through
+//! [`crate::coap_client`].
+
+use thiserror::Error;
+
+/// Block2, the block of a response body (RFC 7959 Section 2.1)
+pub const BLOCK2: u16 = 23;
+/// Block1, the block of a request body (RFC 7959 Section 2.1)
+pub const BLOCK1: u16 = 27;
+
+/// Largest block size exponent, 1024 byte blocks
+pub const MAX_SZX: u8 = 6;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BlockError {
+    #[error("Block option value is longer than 3 bytes")]
+    InvalidOption,
+    #[error("Block size exponent 7 is reserved")]
+    ReservedSize,
+    #[error("Block {num} is beyond the end of the body")]
+    OutOfRange { num: u32 },
+    /// Answered with 4.08 Request Entity Incomplete
+    #[error("Expected the block at offset {expected}, got offset {offset}")]
+    Incomplete { expected: usize, offset: usize },
+    /// Answered with 4.13 Request Entity Too Large
+    #[error("Body exceeds {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("Block {num} is announced with more blocks following but is not full")]
+    ShortBlock { num: u32 },
+}
+
+/// Value of a Block1 or Block2 option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    pub szx: u8,
+}
+
+impl Block {
+    pub fn new(num: u32, more: bool, szx: u8) -> Result<Self, BlockError> {
+        if szx > MAX_SZX {
+            return Err(BlockError::ReservedSize);
+        }
+        Ok(Self { num, more, szx })
+    }
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    /// Encodes the option as the shortest unsigned integer, `0` is the empty value
+    pub fn encode(&self) -> Vec<u8> {
+        let value = self.num << 4 | u32::from(self.more) << 3 | u32::from(self.szx);
+        let bytes = value.to_be_bytes();
+        let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+        bytes[start..].to_vec()
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self, BlockError> {
+        if value.len() > 3 {
+            return Err(BlockError::InvalidOption);
+        }
+        let value = value.iter().fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+
+        Self::new(value >> 4, value & 0x08 != 0, (value & 0x07) as u8)
+    }
+}
+
+/// Returns the requested Block2 of a response body together with the option to send along
+pub fn block(body: &[u8], num: u32, szx: u8) -> Result<(Block, &[u8]), BlockError> {
+    let requested = Block::new(num, false, szx)?;
+    let offset = requested.offset();
+
+    // the first block is always answered, even for an empty body
+    if offset > body.len() || (offset == body.len() && num != 0) {
+        return Err(BlockError::OutOfRange { num });
+    }
+
+    let end = usize::min(offset + requested.size(), body.len());
+    let block = Block::new(num, end < body.len(), szx)?;
+
+    Ok((block, &body[offset..end]))
+}
+
+/// Reassembles a request body from its Block1 blocks.
+/// Blocks must arrive in order, a retransmitted block that was already received is ignored.
+/// The block size may shrink during the transfer, as RFC 7959 allows.
+#[derive(Debug)]
+pub struct BlockAssembler {
+    body: Vec<u8>,
+    limit: usize,
+}
+
+impl BlockAssembler {
+    pub fn new(limit: usize) -> Self {
+        Self { body: vec![], limit }
+    }
+
+    /// Returns the body once the last block arrived
+    pub fn push(&mut self, block: Block, payload: &[u8]) -> Result<Option<Vec<u8>>, BlockError> {
+        if block.more && payload.len() != block.size() {
+            return Err(BlockError::ShortBlock { num: block.num });
+        }
+
+        let offset = block.offset();
+        if offset < self.body.len() && offset + payload.len() <= self.body.len() {
+            return Ok(None);
+        }
+        if offset != self.body.len() {
+            return Err(BlockError::Incomplete {
+                expected: self.body.len(),
+                offset,
+            });
+        }
+        if offset + payload.len() > self.limit {
+            return Err(BlockError::TooLarge { limit: self.limit });
+        }
+
+        self.body.extend_from_slice(payload);
+
+        if block.more {
+            Ok(None)
+        } else {
+            Ok(Some(std::mem::take(&mut self.body)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_encoding() {
+        let block = Block::new(0, false, 0).unwrap();
+        assert!(block.encode().is_empty());
+        assert_eq!(Block::decode(&[]).unwrap(), block);
+
+        let block = Block::new(21, true, MAX_SZX).unwrap();
+        assert_eq!(block.encode(), vec![0x01, 0x5e]);
+        assert_eq!(Block::decode(&block.encode()).unwrap(), block);
+
+        assert_eq!(Block::decode(&[0x0f]), Err(BlockError::ReservedSize));
+        assert_eq!(Block::decode(&[0, 0, 0, 1]), Err(BlockError::InvalidOption));
+    }
+
+    #[test]
+    fn test_transfer_over_lossy_link() {
+        let body: Vec<u8> = (0..100u8).collect();
+        let mut assembler = BlockAssembler::new(1024);
+
+        // 32 byte blocks, block 1 is retransmitted as its acknowledgement got lost
+        let mut received = None;
+        for num in [0, 1, 1, 2, 3] {
+            let (block, payload) = block(&body, num, 1).unwrap();
+            received = assembler.push(block, payload).unwrap();
+        }
+        assert_eq!(received, Some(body.clone()));
+
+        assert_eq!(block(&body, 4, 1), Err(BlockError::OutOfRange { num: 4 }));
+
+        let mut assembler = BlockAssembler::new(1024);
+        let (second, payload) = block(&body, 1, 1).unwrap();
+        assert_eq!(
+            assembler.push(second, payload),
+            Err(BlockError::Incomplete { expected: 0, offset: 32 })
+        );
+
+        let mut assembler = BlockAssembler::new(64);
+        for num in 0..2 {
+            let (block, payload) = block(&body, num, 1).unwrap();
+            assembler.push(block, payload).unwrap();
+        }
+        let (third, payload) = block(&body, 2, 1).unwrap();
+        assert_eq!(assembler.push(third, payload), Err(BlockError::TooLarge { limit: 64 }));
+    }
+}
@@ -0,0 +1,250 @@
+//! CoAP client of a constrained pledge for the OSCORE listener of the registrar.
+//!
+//! The pledge runs EDHOC (RFC 9528) with POSTs to `/.well-known/edhoc` as in RFC 9528 Appendix A.2 and protects its
+//! further requests with the OSCORE (RFC 8613) context derived from the session. Request bodies larger than a block are
+//! sent in Block1 blocks and responses larger than a block are fetched in Block2 blocks (RFC 7959), so vouchers and
+//! certificate chains do not have to fit into a datagram. Every message is confirmable and retransmitted as in
+//! RFC 7252 Section 4.2 until it is acknowledged. Responses have to be piggybacked on the acknowledgement, as the
+//! registrar sends them.
+
+use std::time::Duration;
+
+use openssl::x509::X509;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+
+use crate::coap::{self, code, CoapError, Message, MessageType, EDHOC_MESSAGE_1_PREFIX, EDHOC_PATH};
+use crate::coap_block::{Block, BlockError, BLOCK1, BLOCK2, MAX_SZX};
+use crate::edhoc::{self, Credential, EdhocError, Initiator, Session};
+use crate::oscore::{self, OscoreError, OscoreOption, SecurityContext};
+
+/// C_I, the OSCORE Recipient ID of the pledge. A pledge only has a single session with the registrar.
+const C_I: &[u8] = &[0x37];
+/// Transmission parameters, RFC 7252 Section 4.8
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRANSMIT: u32 = 4;
+const MAX_DATAGRAM: usize = 2048;
+
+#[derive(Error, Debug)]
+pub enum CoapClientError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error("no acknowledgement after {MAX_RETRANSMIT} retransmissions")]
+    Timeout,
+    #[error("the registrar reset the exchange")]
+    Reset,
+    #[error("the registrar answered {}.{:02}: {diagnostic}", .code >> 5, .code & 0x1f)]
+    Rejected { code: u8, diagnostic: String },
+    #[error("Block2 of the response does not continue at offset {expected}")]
+    UnexpectedBlock { expected: usize },
+    #[error(transparent)]
+    Coap(#[from] CoapError),
+    #[error(transparent)]
+    Block(#[from] BlockError),
+    #[error(transparent)]
+    Edhoc(#[from] EdhocError),
+    #[error(transparent)]
+    Oscore(#[from] OscoreError),
+}
+
+impl CoapClientError {
+    fn rejected(response: &Message) -> Self {
+        Self::Rejected {
+            code: response.code,
+            diagnostic: String::from_utf8_lossy(&response.payload).to_string(),
+        }
+    }
+}
+
+/// Sends confirmable requests to the registrar the socket is connected to
+struct Endpoint {
+    socket: UdpSocket,
+    message_id: u16,
+}
+
+impl Endpoint {
+    /// `request` is sent with the next message ID, which is also its token
+    async fn exchange(&mut self, mut request: Message) -> Result<Message, CoapClientError> {
+        self.message_id = self.message_id.wrapping_add(1);
+        request.message_type = MessageType::Confirmable;
+        request.message_id = self.message_id;
+        request.token = self.message_id.to_be_bytes().to_vec();
+        let datagram = request.encode();
+
+        // the initial timeout is randomized between ACK_TIMEOUT and 1.5 times it, then doubled for every retransmission
+        let mut random = [0u8; 1];
+        openssl::rand::rand_bytes(&mut random)?;
+        let mut timeout = ACK_TIMEOUT.mul_f64(1.0 + f64::from(random[0]) / 510.0);
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+
+        for _ in 0..=MAX_RETRANSMIT {
+            self.socket.send(&datagram).await?;
+            let deadline = tokio::time::Instant::now() + timeout;
+            while let Ok(received) = tokio::time::timeout_at(deadline, self.socket.recv(&mut buffer)).await {
+                let Ok(response) = Message::decode(&buffer[..received?]) else {
+                    continue;
+                };
+                if response.message_id != request.message_id {
+                    continue;
+                }
+                match response.message_type {
+                    MessageType::Reset => return Err(CoapClientError::Reset),
+                    MessageType::Acknowledgement if response.token == request.token => return Ok(response),
+                    _ => {}
+                }
+            }
+            timeout *= 2;
+        }
+        Err(CoapClientError::Timeout)
+    }
+}
+
+/// OSCORE session of a pledge with the registrar
+pub struct CoapClient {
+    endpoint: Endpoint,
+    session: Session,
+    context: SecurityContext,
+    szx: u8,
+}
+
+impl CoapClient {
+    /// Runs EDHOC with the registrar `socket` is connected to, authenticating with `credential`.
+    /// `trusted` decides whether the certificate the registrar authenticates with is accepted.
+    pub async fn connect(socket: UdpSocket, credential: Credential, trusted: impl FnOnce(&X509) -> bool) -> Result<Self, CoapClientError> {
+        let mut message_id = [0u8; 2];
+        openssl::rand::rand_bytes(&mut message_id)?;
+        let mut endpoint = Endpoint {
+            socket,
+            message_id: u16::from_be_bytes(message_id),
+        };
+
+        let (initiator, message_1) = Initiator::new(credential, C_I)?;
+        let response = endpoint.exchange(edhoc_request([&[EDHOC_MESSAGE_1_PREFIX][..], &message_1].concat())).await?;
+        if response.code != code::CHANGED {
+            return Err(CoapClientError::rejected(&response));
+        }
+
+        let (message_3, session) = initiator.process_message_2(&response.payload, trusted)?;
+        let mut request = edhoc_request(edhoc::prefix_connection_identifier(session.c_r(), &message_3)?);
+        request.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::CID_EDHOC_CBOR_SEQ));
+        // without message_4 the registrar confirms message_3 with an empty response
+        let response = endpoint.exchange(request).await?;
+        if response.code != code::CHANGED {
+            return Err(CoapClientError::rejected(&response));
+        }
+
+        Ok(Self {
+            endpoint,
+            context: session.oscore_context()?,
+            session,
+            szx: MAX_SZX,
+        })
+    }
+
+    /// Largest block the client sends and asks for, a power of two from 16 to 1024 bytes. The registrar may answer
+    /// with smaller blocks, which are then used for the rest of the transfer.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.szx = (block_size.clamp(16, 1024).ilog2() - 4) as u8;
+        self
+    }
+
+    /// The EDHOC session, e.g. for the exporter the challengePassword of a CSR is bound to
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Sends `request` protected with OSCORE and returns the inner response with its complete payload. The message
+    /// type, ID and token of `request` are not used.
+    pub async fn request(&mut self, request: &Message) -> Result<Message, CoapClientError> {
+        let mut response = self.upload(request).await?;
+        let mut body = vec![];
+
+        while let Some(block2) = response.option(BLOCK2).map(Block::decode).transpose()? {
+            if block2.offset() != body.len() {
+                return Err(CoapClientError::UnexpectedBlock { expected: body.len() });
+            }
+            body.extend_from_slice(&response.payload);
+            if !block2.more {
+                response.options.retain(|(number, _)| *number != BLOCK2);
+                response.payload = body;
+                return Ok(response);
+            }
+
+            // the further blocks are asked for with the request without its body, RFC 7959 Section 2.4
+            let mut next = request.clone();
+            next.options.retain(|(number, _)| *number != BLOCK1);
+            next.set_option(BLOCK2, Block::new(block2.num + 1, false, block2.szx)?.encode());
+            next.payload = vec![];
+            response = self.protected(&next).await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Sends the body of `request` in Block1 blocks if it does not fit into one, returns the response to the last one
+    async fn upload(&mut self, request: &Message) -> Result<Message, CoapClientError> {
+        let mut szx = self.szx;
+        if request.payload.len() <= Block::new(0, false, szx)?.size() {
+            return self.protected(request).await;
+        }
+
+        let mut offset = 0;
+        loop {
+            let size = Block::new(0, false, szx)?.size();
+            let end = usize::min(offset + size, request.payload.len());
+            let block = Block::new((offset / size) as u32, end < request.payload.len(), szx)?;
+
+            let mut inner = request.clone();
+            inner.set_option(BLOCK1, block.encode());
+            inner.payload = request.payload[offset..end].to_vec();
+            let response = self.protected(&inner).await?;
+
+            // anything but 2.31 Continue ends the transfer, e.g. 4.13 Request Entity Too Large
+            if !block.more || response.code != code::CONTINUE {
+                return Ok(response);
+            }
+            // the registrar may ask for smaller blocks, RFC 7959 Section 2.3
+            if let Some(acknowledged) = response.option(BLOCK1).map(Block::decode).transpose()? {
+                szx = szx.min(acknowledged.szx);
+            }
+            offset = end;
+        }
+    }
+
+    /// One OSCORE protected exchange, returns the decrypted inner response
+    async fn protected(&mut self, inner: &Message) -> Result<Message, CoapClientError> {
+        let protected = self.context.protect_request(&oscore::plaintext(inner))?;
+        let mut outer = Message {
+            message_type: MessageType::Confirmable,
+            code: code::POST,
+            message_id: 0,
+            token: vec![],
+            options: vec![],
+            payload: protected.ciphertext.clone(),
+        };
+        outer.set_option(coap::OSCORE, OscoreOption::request(&protected).encode());
+
+        let response = self.endpoint.exchange(outer).await?;
+        // errors of the registrar's OSCORE layer, like 4.01 for an unknown session, are not protected
+        if response.option(coap::OSCORE).is_none() {
+            return Err(CoapClientError::rejected(&response));
+        }
+        let plaintext = self.context.unprotect_response(&protected.partial_iv, &response.payload)?;
+        Ok(oscore::inner_message(&response, &plaintext)?)
+    }
+}
+
+fn edhoc_request(payload: Vec<u8>) -> Message {
+    let mut request = Message {
+        message_type: MessageType::Confirmable,
+        code: code::POST,
+        message_id: 0,
+        token: vec![],
+        options: vec![],
+        payload,
+    };
+    request.set_uri_path(EDHOC_PATH);
+    request
+}
@@ -1,6 +1,5 @@
 /// taken from https://github.com/JohnTheCoolingFan/factorio-lib-rs/blob/02137bb3f231f63a3cdef32add5511d92352e062/src/util/fixed_serde.rs
 /// due to unresolved serde issue in https://github.com/serde-rs/serde/issues/368
-
 pub const fn default_i128<const V: i128>() -> i128 {
     V
 }
@@ -0,0 +1,673 @@
+//! EDHOC (RFC 9528), the authenticated key exchange of the constrained onboarding path.
+//!
+//! Both parties authenticate with signatures (method 0), the pledge with its IDevID and the registrar with its certificate.
+//! Certificates are sent by value in `x5chain`, the caller decides whether the certificate of the peer is trusted.
+//! Only cipher suite 2 (AES-CCM-16-64-128, SHA-256, P-256, ES256) is supported, so both keys must be P-256 keys.
+//! EAD items, error messages and message_4 are not supported.
+//! The OSCORE security context of the session is derived as described in RFC 9528 Appendix A.1.
+
+use ciborium::Value;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    derive::Deriver,
+    ec::{EcGroup, EcKey, EcPoint, PointConversionForm},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, PKeyRef, Private, Public},
+    sha::sha256,
+    sign::{Signer, Verifier},
+    x509::X509,
+};
+use thiserror::Error;
+
+use crate::oscore::{cbor, open, seal, OscoreError, SecurityContext};
+
+const METHOD_SIGNATURE: u64 = 0;
+const CIPHER_SUITE: u64 = 2;
+
+const HASH_LENGTH: usize = 32;
+const KEY_LENGTH: usize = 16;
+const IV_LENGTH: usize = 13;
+const COORDINATE_LENGTH: usize = 32;
+
+/// COSE header parameter carrying the certificate of a party
+const X5CHAIN: u64 = 33;
+
+const OSCORE_MASTER_SECRET_LENGTH: usize = 16;
+const OSCORE_MASTER_SALT_LENGTH: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum EdhocError {
+    #[error("Key is not a P-256 key, which cipher suite 2 requires")]
+    UnsupportedKey,
+    #[error("Initiator did not select method 0 with cipher suite 2")]
+    UnsupportedSuite,
+    #[error("Malformed EDHOC message: {0}")]
+    Malformed(&'static str),
+    #[error("Certificate of the peer is not trusted")]
+    UntrustedCredential,
+    #[error("Signature of the peer does not verify")]
+    InvalidSignature,
+    #[error("message_3 could not be decrypted")]
+    Decryption,
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error(transparent)]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+    #[error(transparent)]
+    Oscore(#[from] OscoreError),
+}
+
+/// Certificate and private key a party authenticates with
+pub struct Credential {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl Credential {
+    pub fn new(cert: X509, key: PKey<Private>) -> Result<Self, EdhocError> {
+        check_curve(&key)?;
+        Ok(Self { cert, key })
+    }
+
+    /// ID_CRED_x, the certificate by value
+    fn id_cred(&self) -> Result<Vec<u8>, EdhocError> {
+        Ok(cbor(&Value::Map(vec![(Value::from(X5CHAIN), Value::Bytes(self.cert.to_der()?))]))?)
+    }
+
+    /// CRED_x
+    fn cred(&self) -> Result<Vec<u8>, EdhocError> {
+        Ok(cbor(&Value::Bytes(self.cert.to_der()?))?)
+    }
+}
+
+/// The party sending message_1, the pledge
+pub struct Initiator {
+    credential: Credential,
+    ephemeral: PKey<Private>,
+    c_i: Vec<u8>,
+    message_1: Vec<u8>,
+}
+
+impl Initiator {
+    /// Returns the initiator together with message_1
+    pub fn new(credential: Credential, c_i: &[u8]) -> Result<(Self, Vec<u8>), EdhocError> {
+        let (ephemeral, g_x) = ephemeral_key()?;
+
+        let mut message_1 = cbor(&Value::from(METHOD_SIGNATURE))?;
+        message_1.extend(cbor(&Value::from(CIPHER_SUITE))?);
+        message_1.extend(cbor(&Value::Bytes(g_x))?);
+        message_1.extend(encode_id(c_i)?);
+
+        let initiator = Self {
+            credential,
+            ephemeral,
+            c_i: c_i.to_vec(),
+            message_1: message_1.clone(),
+        };
+        Ok((initiator, message_1))
+    }
+
+    /// Authenticates the responder and returns message_3 together with the established session
+    pub fn process_message_2(
+        self,
+        message_2: &[u8],
+        trusted: impl FnOnce(&X509) -> bool,
+    ) -> Result<(Vec<u8>, Session), EdhocError> {
+        let items = decode_sequence(message_2)?;
+        let [(Value::Bytes(g_y_ciphertext_2), _)] = items.as_slice() else {
+            return Err(EdhocError::Malformed("message_2 is not a single byte string"));
+        };
+        if g_y_ciphertext_2.len() <= COORDINATE_LENGTH {
+            return Err(EdhocError::Malformed("message_2 is too short"));
+        }
+        let (g_y, ciphertext_2) = g_y_ciphertext_2.split_at(COORDINATE_LENGTH);
+
+        let th_2 = th_2(g_y, &self.message_1)?;
+        let prk_2e = extract(&th_2, &ecdh(&self.ephemeral, g_y)?)?;
+        // the responder authenticates with a signature, so no static DH key is mixed in
+        let prk_3e2m = prk_2e.clone();
+
+        let keystream_2 = kdf(&prk_2e, 0, &th_2, ciphertext_2.len())?;
+        let plaintext_2: Vec<u8> = ciphertext_2.iter().zip(&keystream_2).map(|(byte, key)| byte ^ key).collect();
+
+        let items = decode_sequence(&plaintext_2)?;
+        let [(c_r, c_r_raw), (id_cred_r, id_cred_r_raw), (Value::Bytes(signature_2), _)] = items.as_slice() else {
+            return Err(EdhocError::Malformed("plaintext_2 does not match (C_R, ID_CRED_R, Signature_or_MAC_2)"));
+        };
+        let c_r = decode_id(c_r, c_r_raw)?;
+
+        let cert_r = parse_id_cred(id_cred_r)?;
+        if !trusted(&cert_r) {
+            return Err(EdhocError::UntrustedCredential);
+        }
+        let cred_r = cbor(&Value::Bytes(cert_r.to_der()?))?;
+
+        let mac_2 = kdf(&prk_3e2m, 2, &[*c_r_raw, *id_cred_r_raw, &bstr(&th_2)?, &cred_r].concat(), HASH_LENGTH)?;
+        verify(&cert_r.public_key()?, id_cred_r_raw, &th_2, &cred_r, &mac_2, signature_2)?;
+
+        let th_3 = transcript_hash(&th_2, &plaintext_2, &cred_r)?;
+
+        let id_cred_i = self.credential.id_cred()?;
+        let cred_i = self.credential.cred()?;
+        let prk_4e3m = prk_3e2m.clone();
+        let mac_3 = kdf(&prk_4e3m, 6, &[id_cred_i.as_slice(), &bstr(&th_3)?, &cred_i].concat(), HASH_LENGTH)?;
+        let signature_3 = sign(&self.credential.key, &id_cred_i, &th_3, &cred_i, &mac_3)?;
+
+        let plaintext_3 = [id_cred_i, cbor(&Value::Bytes(signature_3))?].concat();
+        let k_3 = kdf(&prk_3e2m, 3, &th_3, KEY_LENGTH)?;
+        let iv_3 = kdf(&prk_3e2m, 4, &th_3, IV_LENGTH)?;
+        let ciphertext_3 = seal(&k_3, &iv_3, &encrypt0_aad(&th_3)?, &plaintext_3)?;
+        let message_3 = cbor(&Value::Bytes(ciphertext_3))?;
+
+        let th_4 = transcript_hash(&th_3, &plaintext_3, &cred_i)?;
+        let session = Session {
+            prk_out: kdf(&prk_4e3m, 7, &th_4, HASH_LENGTH)?,
+            c_i: self.c_i,
+            c_r,
+            initiator: true,
+            peer: cert_r,
+        };
+        Ok((message_3, session))
+    }
+}
+
+/// The party answering message_1, the registrar
+pub struct Responder {
+    credential: Credential,
+    c_r: Vec<u8>,
+}
+
+impl Responder {
+    pub fn new(credential: Credential, c_r: &[u8]) -> Self {
+        Self {
+            credential,
+            c_r: c_r.to_vec(),
+        }
+    }
+
+    /// Returns message_2 together with the state waiting for message_3
+    pub fn process_message_1(&self, message_1: &[u8]) -> Result<(Vec<u8>, AwaitingMessage3), EdhocError> {
+        let items = decode_sequence(message_1)?;
+        let [(method, _), (suites, _), (Value::Bytes(g_x), _), (c_i, c_i_raw)] = items.as_slice() else {
+            return Err(EdhocError::Malformed("message_1 does not match (METHOD, SUITES_I, G_X, C_I)"));
+        };
+
+        // the selected suite is the last one of the array
+        let selected = match suites {
+            Value::Array(suites) => suites.last(),
+            suite => Some(suite),
+        };
+        if as_u64(method) != Some(METHOD_SIGNATURE) || selected.and_then(as_u64) != Some(CIPHER_SUITE) {
+            return Err(EdhocError::UnsupportedSuite);
+        }
+
+        let c_i = decode_id(c_i, c_i_raw)?;
+        if c_i == self.c_r {
+            return Err(EdhocError::Malformed("C_I equals C_R"));
+        }
+
+        let (ephemeral, g_y) = ephemeral_key()?;
+        let th_2 = th_2(&g_y, message_1)?;
+        let prk_2e = extract(&th_2, &ecdh(&ephemeral, g_x)?)?;
+        let prk_3e2m = prk_2e.clone();
+
+        let c_r = encode_id(&self.c_r)?;
+        let id_cred_r = self.credential.id_cred()?;
+        let cred_r = self.credential.cred()?;
+        let mac_2 = kdf(&prk_3e2m, 2, &[c_r.as_slice(), &id_cred_r, &bstr(&th_2)?, &cred_r].concat(), HASH_LENGTH)?;
+        let signature_2 = sign(&self.credential.key, &id_cred_r, &th_2, &cred_r, &mac_2)?;
+
+        let plaintext_2 = [c_r, id_cred_r, cbor(&Value::Bytes(signature_2))?].concat();
+        let keystream_2 = kdf(&prk_2e, 0, &th_2, plaintext_2.len())?;
+        let ciphertext_2 = plaintext_2.iter().zip(&keystream_2).map(|(byte, key)| byte ^ key);
+        let message_2 = cbor(&Value::Bytes(g_y.into_iter().chain(ciphertext_2).collect()))?;
+
+        let awaiting = AwaitingMessage3 {
+            th_3: transcript_hash(&th_2, &plaintext_2, &cred_r)?,
+            prk_3e2m,
+            c_i,
+            c_r: self.c_r.clone(),
+        };
+        Ok((message_2, awaiting))
+    }
+}
+
+/// Responder state after sending message_2
+pub struct AwaitingMessage3 {
+    th_3: Vec<u8>,
+    prk_3e2m: Vec<u8>,
+    c_i: Vec<u8>,
+    c_r: Vec<u8>,
+}
+
+impl AwaitingMessage3 {
+    pub fn c_i(&self) -> &[u8] {
+        &self.c_i
+    }
+
+    /// Authenticates the initiator and returns the established session
+    pub fn process_message_3(self, message_3: &[u8], trusted: impl FnOnce(&X509) -> bool) -> Result<Session, EdhocError> {
+        let items = decode_sequence(message_3)?;
+        let [(Value::Bytes(ciphertext_3), _)] = items.as_slice() else {
+            return Err(EdhocError::Malformed("message_3 is not a single byte string"));
+        };
+
+        let k_3 = kdf(&self.prk_3e2m, 3, &self.th_3, KEY_LENGTH)?;
+        let iv_3 = kdf(&self.prk_3e2m, 4, &self.th_3, IV_LENGTH)?;
+        let plaintext_3 =
+            open(&k_3, &iv_3, &encrypt0_aad(&self.th_3)?, ciphertext_3).map_err(|_| EdhocError::Decryption)?;
+
+        let items = decode_sequence(&plaintext_3)?;
+        let [(id_cred_i, id_cred_i_raw), (Value::Bytes(signature_3), _)] = items.as_slice() else {
+            return Err(EdhocError::Malformed("plaintext_3 does not match (ID_CRED_I, Signature_or_MAC_3)"));
+        };
+
+        let cert_i = parse_id_cred(id_cred_i)?;
+        if !trusted(&cert_i) {
+            return Err(EdhocError::UntrustedCredential);
+        }
+        let cred_i = cbor(&Value::Bytes(cert_i.to_der()?))?;
+
+        let prk_4e3m = self.prk_3e2m;
+        let mac_3 = kdf(&prk_4e3m, 6, &[*id_cred_i_raw, &bstr(&self.th_3)?, &cred_i].concat(), HASH_LENGTH)?;
+        verify(&cert_i.public_key()?, id_cred_i_raw, &self.th_3, &cred_i, &mac_3, signature_3)?;
+
+        let th_4 = transcript_hash(&self.th_3, &plaintext_3, &cred_i)?;
+        Ok(Session {
+            prk_out: kdf(&prk_4e3m, 7, &th_4, HASH_LENGTH)?,
+            c_i: self.c_i,
+            c_r: self.c_r,
+            initiator: false,
+            peer: cert_i,
+        })
+    }
+}
+
+/// Established EDHOC session
+pub struct Session {
+    prk_out: Vec<u8>,
+    c_i: Vec<u8>,
+    c_r: Vec<u8>,
+    initiator: bool,
+    peer: X509,
+}
+
+impl Session {
+    /// The authenticated certificate of the peer
+    pub fn peer(&self) -> &X509 {
+        &self.peer
+    }
+
+    /// C_R, which the initiator prefixes message_3 with and which is its OSCORE Sender ID
+    pub fn c_r(&self) -> &[u8] {
+        &self.c_r
+    }
+
+    /// EDHOC_Exporter, see RFC 9528 Section 4.2.1
+    pub fn exporter(&self, label: u64, context: &[u8], length: usize) -> Result<Vec<u8>, EdhocError> {
+        let prk_exporter = kdf(&self.prk_out, 10, &[], HASH_LENGTH)?;
+        kdf(&prk_exporter, label, context, length)
+    }
+
+    /// The sender ID of each party is the connection identifier chosen by the other party
+    pub fn oscore_context(&self) -> Result<SecurityContext, EdhocError> {
+        let master_secret = self.exporter(0, &[], OSCORE_MASTER_SECRET_LENGTH)?;
+        let master_salt = self.exporter(1, &[], OSCORE_MASTER_SALT_LENGTH)?;
+        let (sender_id, recipient_id) = if self.initiator {
+            (&self.c_r, &self.c_i)
+        } else {
+            (&self.c_i, &self.c_r)
+        };
+
+        Ok(SecurityContext::derive(&master_secret, &master_salt, sender_id, recipient_id, None)?)
+    }
+}
+
+fn check_curve<T: openssl::pkey::HasPublic>(key: &PKeyRef<T>) -> Result<(), EdhocError> {
+    match key.ec_key().ok().and_then(|key| key.group().curve_name()) {
+        Some(Nid::X9_62_PRIME256V1) => Ok(()),
+        _ => Err(EdhocError::UnsupportedKey),
+    }
+}
+
+/// Generates the ephemeral key, the public key is sent as its x-coordinate only
+fn ephemeral_key() -> Result<(PKey<Private>, Vec<u8>), EdhocError> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = EcKey::generate(&group)?;
+    let mut ctx = BigNumContext::new()?;
+    let compressed = key.public_key().to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx)?;
+
+    Ok((PKey::from_ec_key(key)?, compressed[1..].to_vec()))
+}
+
+/// G_XY, the x-coordinate of the shared point, which is the same for either y-coordinate of the peer key
+fn ecdh(key: &PKey<Private>, peer_x: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    if peer_x.len() != COORDINATE_LENGTH {
+        return Err(EdhocError::Malformed("ephemeral key is not a P-256 x-coordinate"));
+    }
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let mut ctx = BigNumContext::new()?;
+    let point = EcPoint::from_bytes(&group, &[&[0x02], peer_x].concat(), &mut ctx)
+        .map_err(|_| EdhocError::Malformed("ephemeral key is not on P-256"))?;
+    let peer: PKey<Public> = PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)?;
+
+    let mut deriver = Deriver::new(key)?;
+    deriver.set_peer(&peer)?;
+    Ok(deriver.derive_to_vec()?)
+}
+
+/// ES256 over the COSE Sig_structure, see RFC 9528 Section 5.3.2
+fn sign(key: &PKey<Private>, id_cred: &[u8], th: &[u8], cred: &[u8], mac: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(&sig_structure(id_cred, th, cred, mac)?)?;
+    let signature = EcdsaSig::from_der(&signer.sign_to_vec()?)?;
+
+    Ok([signature.r().to_vec_padded(32)?, signature.s().to_vec_padded(32)?].concat())
+}
+
+fn verify(
+    key: &PKey<Public>,
+    id_cred: &[u8],
+    th: &[u8],
+    cred: &[u8],
+    mac: &[u8],
+    signature: &[u8],
+) -> Result<(), EdhocError> {
+    check_curve(key)?;
+    if signature.len() != 64 {
+        return Err(EdhocError::InvalidSignature);
+    }
+    let (r, s) = signature.split_at(32);
+    let signature = EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+
+    let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+    verifier.update(&sig_structure(id_cred, th, cred, mac)?)?;
+    if verifier.verify(&signature.to_der()?)? {
+        Ok(())
+    } else {
+        Err(EdhocError::InvalidSignature)
+    }
+}
+
+fn sig_structure(id_cred: &[u8], th: &[u8], cred: &[u8], mac: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok(cbor(&Value::Array(vec![
+        Value::from("Signature1"),
+        Value::Bytes(id_cred.to_vec()),
+        Value::Bytes([bstr(th)?, cred.to_vec()].concat()),
+        Value::Bytes(mac.to_vec()),
+    ]))?)
+}
+
+fn encrypt0_aad(th_3: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok(cbor(&Value::Array(vec![
+        Value::from("Encrypt0"),
+        Value::Bytes(vec![]),
+        Value::Bytes(th_3.to_vec()),
+    ]))?)
+}
+
+/// TH_2 = H( G_Y, H(message_1) )
+fn th_2(g_y: &[u8], message_1: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok(sha256(&[bstr(g_y)?, bstr(&sha256(message_1))?].concat()).to_vec())
+}
+
+/// TH_3 and TH_4, H( TH_n, PLAINTEXT_n, CRED_x )
+fn transcript_hash(th: &[u8], plaintext: &[u8], cred: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok(sha256(&[&bstr(th)?, plaintext, cred].concat()).to_vec())
+}
+
+/// EDHOC_KDF, HKDF-Expand with the info ( label, context, length ), see RFC 9528 Section 4.1.2
+fn kdf(prk: &[u8], label: u64, context: &[u8], length: usize) -> Result<Vec<u8>, EdhocError> {
+    let info = [
+        cbor(&Value::from(label))?,
+        bstr(context)?,
+        cbor(&Value::from(length as u64))?,
+    ]
+    .concat();
+
+    let key = PKey::hmac(prk)?;
+    let mut okm = vec![];
+    let mut block = vec![];
+    for counter in 1..=length.div_ceil(HASH_LENGTH) {
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(&block)?;
+        signer.update(&info)?;
+        signer.update(&[counter as u8])?;
+        block = signer.sign_to_vec()?;
+        okm.extend_from_slice(&block);
+    }
+    okm.truncate(length);
+    Ok(okm)
+}
+
+fn extract(salt: &[u8], ikm: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    let mut signer = Signer::new(MessageDigest::sha256(), &*PKey::hmac(salt)?)?;
+    signer.update(ikm)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+fn bstr(data: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok(cbor(&Value::Bytes(data.to_vec()))?)
+}
+
+/// Connection identifiers that are the encoding of an integer between -24 and 23 are encoded as that integer
+fn encode_id(id: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    match id {
+        [byte] if *byte <= 0x17 || (0x20..=0x37).contains(byte) => Ok(vec![*byte]),
+        _ => bstr(id),
+    }
+}
+
+fn decode_id(value: &Value, raw: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    match value {
+        Value::Integer(_) if raw.len() == 1 => Ok(raw.to_vec()),
+        Value::Bytes(id) => Ok(id.clone()),
+        _ => Err(EdhocError::Malformed("connection identifier is neither a byte string nor an integer in -24..23")),
+    }
+}
+
+/// Takes the end-entity certificate of `x5chain`
+fn parse_id_cred(id_cred: &Value) -> Result<X509, EdhocError> {
+    let x5chain = id_cred
+        .as_map()
+        .and_then(|map| map.iter().find(|(label, _)| as_u64(label) == Some(X5CHAIN)))
+        .map(|(_, x5chain)| x5chain);
+
+    let cert = match x5chain {
+        Some(Value::Bytes(cert)) => cert,
+        Some(Value::Array(chain)) => match chain.first() {
+            Some(Value::Bytes(cert)) => cert,
+            _ => return Err(EdhocError::Malformed("x5chain is empty")),
+        },
+        _ => return Err(EdhocError::Malformed("ID_CRED does not carry an x5chain")),
+    };
+
+    X509::from_der(cert).map_err(|_| EdhocError::Malformed("x5chain is not a DER certificate"))
+}
+
+/// Prefixes a message with a connection identifier, see [`split_connection_identifier`]
+pub fn prefix_connection_identifier(id: &[u8], message: &[u8]) -> Result<Vec<u8>, EdhocError> {
+    Ok([encode_id(id)?, message.to_vec()].concat())
+}
+
+/// Splits the connection identifier off a CoAP payload that is prefixed with it, like message_3 sent to the responder,
+/// see RFC 9528 Appendix A.2
+pub fn split_connection_identifier(payload: &[u8]) -> Result<(Vec<u8>, &[u8]), EdhocError> {
+    let mut rest = payload;
+    let value: Value = ciborium::de::from_reader(&mut rest)?;
+    let id = decode_id(&value, &payload[..payload.len() - rest.len()])?;
+    Ok((id, rest))
+}
+
+/// Decodes a CBOR sequence, keeping the encoding of each item as it is part of the transcript
+fn decode_sequence(data: &[u8]) -> Result<Vec<(Value, &[u8])>, EdhocError> {
+    let mut items = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let start = data.len() - rest.len();
+        let value: Value = ciborium::de::from_reader(&mut rest)?;
+        items.push((value, &data[start..data.len() - rest.len()]));
+    }
+    Ok(items)
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    value.as_integer().and_then(|integer| u64::try_from(integer).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(
+        certs: &example_certs::OpensslTestCerts,
+        message_3: impl FnOnce(Vec<u8>) -> Vec<u8>,
+    ) -> Result<(Session, Session), EdhocError> {
+        let (pledge_cert, pledge_key) = certs.pledge.clone();
+        let (registrar_cert, registrar_key) = certs.registrar.clone();
+
+        let (initiator, message_1) = Initiator::new(Credential::new(pledge_cert.clone(), pledge_key)?, &[0x37])?;
+        let responder = Responder::new(Credential::new(registrar_cert.clone(), registrar_key)?, &[0x27, 0x01]);
+
+        let (message_2, awaiting) = responder.process_message_1(&message_1)?;
+        let (message_3_sent, pledge) = initiator.process_message_2(&message_2, |cert| cert == &registrar_cert)?;
+        let registrar = awaiting.process_message_3(&message_3(message_3_sent), |cert| cert == &pledge_cert)?;
+
+        Ok((pledge, registrar))
+    }
+
+    #[test]
+    fn test_handshake_derives_oscore_context() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge, registrar) = handshake(&certs, |message_3| message_3).unwrap();
+
+        assert_eq!(pledge.peer(), &certs.registrar.0);
+        assert_eq!(registrar.peer(), &certs.pledge.0);
+        assert_eq!(pledge.exporter(0, &[], 16).unwrap(), registrar.exporter(0, &[], 16).unwrap());
+
+        let mut pledge = pledge.oscore_context().unwrap();
+        let mut registrar = registrar.oscore_context().unwrap();
+        let request = pledge.protect_request(b"voucher request").unwrap();
+        assert_eq!(request.kid, vec![0x27, 0x01]);
+        assert_eq!(
+            registrar.unprotect_request(&request.partial_iv, &request.ciphertext).unwrap(),
+            b"voucher request"
+        );
+    }
+
+    #[test]
+    fn test_split_connection_identifier() {
+        let (id, rest) = split_connection_identifier(&[0x37, 0x58, 0x01, 0xff]).unwrap();
+        assert_eq!((id.as_slice(), rest), (&[0x37][..], &[0x58, 0x01, 0xff][..]));
+
+        let (id, rest) = split_connection_identifier(&[0x42, 0x27, 0x01, 0x40]).unwrap();
+        assert_eq!((id.as_slice(), rest), (&[0x27, 0x01][..], &[0x40][..]));
+
+        assert_eq!(prefix_connection_identifier(&[0x27, 0x01], &[0x40]).unwrap(), [0x42, 0x27, 0x01, 0x40]);
+        assert!(split_connection_identifier(&[0x60]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampered_message_3() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let result = handshake(&certs, |mut message_3| {
+            let last = message_3.len() - 1;
+            message_3[last] ^= 1;
+            message_3
+        });
+
+        assert!(matches!(result, Err(EdhocError::Decryption)));
+    }
+
+    fn hex(value: &str) -> Vec<u8> {
+        crate::util::from_hex(value).unwrap()
+    }
+
+    fn trace_key(private: &str) -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let private = BigNum::from_slice(&hex(private)).unwrap();
+        let mut public = EcPoint::new(&group).unwrap();
+        public.mul_generator2(&group, &private, &mut ctx).unwrap();
+        PKey::from_ec_key(EcKey::from_private_components(&group, &private, &public).unwrap()).unwrap()
+    }
+
+    fn trace_cred(subject: &str, kid: u8, x: &str, y: &str) -> Vec<u8> {
+        [
+            &[0xa2, 0x02, 0x60 + subject.len() as u8][..],
+            subject.as_bytes(),
+            &[0x08, 0xa1, 0x01, 0xa5, 0x01, 0x02, 0x02, 0x41, kid, 0x20, 0x01, 0x21, 0x58, 0x20],
+            &hex(x),
+            &[0x22, 0x58, 0x20],
+            &hex(y),
+        ]
+        .concat()
+    }
+
+    /// Test vectors of RFC 9529 Section 3, the key schedule is the one of method 0 except for the static DH steps
+    #[test]
+    fn test_rfc9529_key_schedule() {
+        let x = trace_key("368ec1f69aeb659ba37d5a8d45b21bdc0299dceaa8ef235f3ca42ce3530f9525");
+        let y = trace_key("e2f4126777205e853b437d6eaca1e1f753cdcc3e2c69fa884b0a1a640977e418");
+        let r = trace_key("72cc4761dbd4c78f758931aa589d348d1ef874a7e303ede2f140dcf3e6aa4aac");
+        let i = trace_key("fb13adeb6518cee5f88417660841142e830a81fe334380a953406a1305e8706b");
+        let g_x = hex("8af6f430ebe18d34184017a9a11bf511c8dff8f834730b96c1b7c8dbca2fc3b6");
+        let g_y = hex("419701d7f00a26c2dc587a36dd752549f33763c893422c8ea0f955a13a4ff5d5");
+        let (g_r_x, g_r_y) = (
+            "bbc34960526ea4d32e940cad2a234148ddc21791a12afbcbac93622046dd44f0",
+            "4519e257236b2a0ce2023f0931f1f386ca7afda64fcde0108c224c51eabf6072",
+        );
+        let (g_i_x, g_i_y) = (
+            "ac75e9ece3e50bfc8ed60399889522405c47bf16df96660a41298cb4307f7eb6",
+            "6e5de611388a4b8a8211334ac7d37ecb52a387d257e6db3c2a93df21ff3affc8",
+        );
+        let message_1 = [&hex("038206025820")[..], &g_x, &[0x37]].concat();
+
+        let th_2 = th_2(&g_y, &message_1).unwrap();
+        assert_eq!(th_2, hex("356efd53771425e008f3fe3a86c83ff4c6b16e57028ff39d5236c182b202084b"));
+        let g_xy = ecdh(&x, &g_y).unwrap();
+        assert_eq!(g_xy, ecdh(&y, &g_x).unwrap());
+        assert_eq!(g_xy, hex("2f0cb7e860ba538fbf5c8bded009f6259b4b628fe1eb7dbe9378e5ecf7a824ba"));
+        let prk_2e = extract(&th_2, &g_xy).unwrap();
+        assert_eq!(prk_2e, hex("5aa0d69f3e3d1e0c479f0b8a486690c9802630c3466b1dc92371c982563170b5"));
+
+        // the responder authenticates with its static DH key
+        let salt_3e2m = kdf(&prk_2e, 1, &th_2, HASH_LENGTH).unwrap();
+        let prk_3e2m = extract(&salt_3e2m, &ecdh(&r, &g_x).unwrap()).unwrap();
+        assert_eq!(prk_3e2m, hex("0ca3d3398296b3c03900987620c11f6fce70781c1d1219720f9ec08c122d8434"));
+
+        let cred_r = trace_cred("example.edu", 0x32, g_r_x, g_r_y);
+        let context_2 = [&[0x27, 0xa1, 0x04, 0x41, 0x32][..], &bstr(&th_2).unwrap(), &cred_r].concat();
+        let mac_2 = kdf(&prk_3e2m, 2, &context_2, 8).unwrap();
+        assert_eq!(mac_2, hex("0943305c899f5c54"));
+        let th_3 = transcript_hash(&th_2, &[&[0x27, 0x32, 0x48][..], &mac_2].concat(), &cred_r).unwrap();
+        assert_eq!(th_3, hex("adaf67a78a4bcc91e018f8882762a722000b2507039df0bc1bbf0c161bb3155c"));
+
+        // and so does the initiator
+        let salt_4e3m = kdf(&prk_3e2m, 5, &th_3, HASH_LENGTH).unwrap();
+        let prk_4e3m = extract(&salt_4e3m, &ecdh(&i, &g_y).unwrap()).unwrap();
+        assert_eq!(prk_4e3m, hex("81cc8a298e357044e3c466bb5c0a1e507e01d49238aeba138df94635407c0ff7"));
+
+        let cred_i = trace_cred("42-50-31-FF-EF-37-32-39", 0x2b, g_i_x, g_i_y);
+        let context_3 = [&[0xa1, 0x04, 0x41, 0x2b][..], &bstr(&th_3).unwrap(), &cred_i].concat();
+        let mac_3 = kdf(&prk_4e3m, 6, &context_3, 8).unwrap();
+        assert_eq!(mac_3, hex("623c91df41e34c2f"));
+        let th_4 = transcript_hash(&th_3, &[&[0x2b, 0x48][..], &mac_3].concat(), &cred_i).unwrap();
+        assert_eq!(th_4, hex("c902b1e3a4326c93c5551f5f3aa6c5ecc0246806765612e52b5d99e6059d6b6e"));
+
+        let prk_out = kdf(&prk_4e3m, 7, &th_4, HASH_LENGTH).unwrap();
+        assert_eq!(prk_out, hex("2c71afc1a9338a940bb3529ca734b886f30d1aba0b4dc51beeaeabdfea9ecbf8"));
+        let prk_exporter = kdf(&prk_out, 10, &[], HASH_LENGTH).unwrap();
+        assert_eq!(prk_exporter, hex("e14d06699cee248c5a04bf9227bbcd4ce394de7dcb56db43555474171e6446db"));
+        assert_eq!(
+            kdf(&prk_exporter, 0, &[], OSCORE_MASTER_SECRET_LENGTH).unwrap(),
+            hex("f9868f6a3aca78a05d1485b35030b162")
+        );
+        assert_eq!(
+            kdf(&prk_exporter, 1, &[], OSCORE_MASTER_SALT_LENGTH).unwrap(),
+            hex("ada24c7dbfc85eeb")
+        );
+    }
+}
@@ -3,6 +3,8 @@ use axum::{
     response::{IntoResponse, Response},
 };
 
+use crate::problem::Problem;
+
 // Make our own error that wraps `anyhow::Error`.
 #[derive(Debug)]
 pub struct AppError(anyhow::Error);
@@ -10,10 +12,8 @@ pub struct AppError(anyhow::Error);
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .detail(format!("Something went wrong: {}", self.0))
             .into_response()
     }
 }
@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Arc, time::Duration};
+
+use openssl::{
+    asn1::Asn1Time,
+    error::ErrorStack,
+    x509::{X509Ref, X509},
+};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+const SECS_PER_DAY: i64 = 86400;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExpiryStatus {
+    Valid,
+    /// Expires within the warning period
+    Expiring,
+    Expired,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateExpiry {
+    pub name: String,
+    pub subject: String,
+    pub not_after: String,
+    /// Negative once the certificate expired
+    pub expires_in_secs: i64,
+    pub status: ExpiryStatus,
+}
+
+impl CertificateExpiry {
+    fn new(name: &str, cert: &X509Ref, warning_secs: i64) -> Result<Self, ErrorStack> {
+        let now = Asn1Time::days_from_now(0)?;
+        let diff = now.diff(cert.not_after())?;
+        let expires_in_secs = diff.days as i64 * SECS_PER_DAY + diff.secs as i64;
+
+        let status = if expires_in_secs <= 0 {
+            ExpiryStatus::Expired
+        } else if expires_in_secs <= warning_secs {
+            ExpiryStatus::Expiring
+        } else {
+            ExpiryStatus::Valid
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            subject: format!("{:?}", cert.subject_name()),
+            not_after: cert.not_after().to_string(),
+            expires_in_secs,
+            status,
+        })
+    }
+}
+
+struct TrackedCertificate {
+    cert: X509,
+    /// Status of the last delivered alert, so every status is only alerted once
+    alerted: Option<ExpiryStatus>,
+}
+
+/// Tracks the expiry of operational certificates and alerts ahead of it, as a warning with the `Expiry` target
+/// and, if configured, as a webhook. Expiry is also exposed as Prometheus metrics.
+pub struct ExpiryMonitor {
+    warning_secs: i64,
+    webhook_url: Option<String>,
+    client: reqwest::Client,
+    certificates: RwLock<BTreeMap<String, TrackedCertificate>>,
+}
+
+impl ExpiryMonitor {
+    pub fn new(warning_days: u32, webhook_url: Option<String>, client: reqwest::Client) -> Self {
+        Self {
+            warning_secs: warning_days as i64 * SECS_PER_DAY,
+            webhook_url,
+            client,
+            certificates: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Starts tracking `cert` under `name`, replacing the certificate tracked under it before
+    pub async fn track(&self, name: impl Into<String>, cert: &X509Ref) {
+        let mut certificates = self.certificates.write().await;
+        let name = name.into();
+
+        if certificates.get(&name).is_some_and(|tracked| tracked.cert.to_der().ok() == cert.to_der().ok()) {
+            return;
+        }
+        certificates.insert(
+            name,
+            TrackedCertificate {
+                cert: cert.to_owned(),
+                alerted: None,
+            },
+        );
+    }
+
+    /// All tracked certificates, expiring first
+    pub async fn certificates(&self) -> Vec<CertificateExpiry> {
+        let mut certificates: Vec<CertificateExpiry> = self
+            .certificates
+            .read()
+            .await
+            .iter()
+            .filter_map(|(name, tracked)| CertificateExpiry::new(name, &tracked.cert, self.warning_secs).ok())
+            .collect();
+        certificates.sort_by_key(|certificate| certificate.expires_in_secs);
+        certificates
+    }
+
+    /// Prometheus text exposition of the tracked certificates
+    pub async fn metrics(&self) -> String {
+        let mut metrics = String::new();
+        let _ = writeln!(metrics, "# HELP open_brski_certificate_expiry_seconds Seconds until the certificate expires");
+        let _ = writeln!(metrics, "# TYPE open_brski_certificate_expiry_seconds gauge");
+        for certificate in self.certificates().await {
+            let _ = writeln!(
+                metrics,
+                "open_brski_certificate_expiry_seconds{{name=\"{}\"}} {}",
+                escape_label(&certificate.name),
+                certificate.expires_in_secs
+            );
+        }
+        metrics
+    }
+
+    /// Alerts every certificate that became expiring or expired since the last check
+    pub async fn check(&self) {
+        for certificate in self.certificates().await {
+            if certificate.status == ExpiryStatus::Valid {
+                continue;
+            }
+
+            let alerted = self.certificates.read().await.get(&certificate.name).and_then(|tracked| tracked.alerted);
+            if alerted == Some(certificate.status) {
+                continue;
+            }
+
+            event!(
+                target: "Expiry",
+                Level::WARN,
+                "Certificate {} ({}) is {:?}, not after {}",
+                certificate.name,
+                certificate.subject,
+                certificate.status,
+                certificate.not_after
+            );
+
+            // undelivered alerts are retried on the next check
+            if !self.send_webhook(&certificate).await {
+                continue;
+            }
+
+            if let Some(tracked) = self.certificates.write().await.get_mut(&certificate.name) {
+                tracked.alerted = Some(certificate.status);
+            }
+        }
+    }
+
+    async fn send_webhook(&self, certificate: &CertificateExpiry) -> bool {
+        let Some(url) = &self.webhook_url else {
+            return true;
+        };
+
+        match self.client.post(url).json(certificate).send().await.and_then(|response| response.error_for_status()) {
+            Ok(_) => true,
+            Err(err) => {
+                event!(target: "Expiry", Level::ERROR, "Delivering expiry alert for {} failed: {}", certificate.name, err);
+                false
+            }
+        }
+    }
+
+    pub fn spawn(self: &Arc<Self>, interval: Duration) {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                monitor.check().await;
+            }
+        });
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports_expiring_certificates() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let monitor = ExpiryMonitor::new(30, None, reqwest::Client::new());
+        monitor.track("registrar", &certs.registrar.0).await;
+        monitor.track("ldevid:00-D0-E5-F2-00-02", &certs.pledge.0).await;
+
+        let certificates = monitor.certificates().await;
+        assert_eq!(certificates.len(), 2);
+        assert!(certificates.iter().all(|certificate| certificate.status == ExpiryStatus::Valid));
+
+        // everything expires within a warning period of 100 years
+        let monitor = ExpiryMonitor::new(365 * 100, None, reqwest::Client::new());
+        monitor.track("registrar", &certs.registrar.0).await;
+        assert_eq!(monitor.certificates().await[0].status, ExpiryStatus::Expiring);
+
+        monitor.check().await;
+        assert_eq!(
+            monitor.certificates.read().await.get("registrar").unwrap().alerted,
+            Some(ExpiryStatus::Expiring)
+        );
+
+        let metrics = monitor.metrics().await;
+        assert!(metrics.contains("open_brski_certificate_expiry_seconds{name=\"registrar\"}"));
+    }
+}
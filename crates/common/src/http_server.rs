@@ -0,0 +1,422 @@
+//! HTTP server of the MASA and the registrar with configurable timeouts, keep-alive and connection limit.
+//!
+//! `axum::serve` has no knobs for any of them, so connections are accepted here and served by hyper directly. hyper
+//! has no write timeout either, connections get one from [`WriteTimeout`].
+//! [`serve_tls`] terminates TLS itself, so handlers can see what the handshake established, e.g. the client certificate.
+//! Request bodies are bounded per artifact by [`crate::limits::ArtifactLimits`], errors are answered as
+//! [`crate::problem`] details.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::Extensions,
+    middleware, Router,
+};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use openssl::ssl::{Ssl, SslAcceptor, SslRef};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::Sleep,
+};
+use tokio_openssl::SslStream;
+use tower::ServiceExt;
+use tower_http::timeout::TimeoutLayer;
+use tracing::{event, Level};
+
+use crate::problem::problem_details;
+
+/// Server tuning, configured as e.g. `[masa.http]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct HttpServerConfig {
+    /// Seconds to receive the request line and headers. Idle keep-alive connections are closed after it as well.
+    pub header_read_timeout_secs: u64,
+    /// Seconds to read the body and answer a request, answered with `408 Request Timeout` when exceeded
+    pub request_timeout_secs: u64,
+    /// Seconds a write to the client may make no progress before the connection is closed, e.g. when the client
+    /// stops reading its response
+    pub write_timeout_secs: u64,
+    /// Keeps connections open for further requests
+    pub keep_alive: bool,
+    /// Connections served at the same time, further ones wait in the listen backlog
+    pub max_connections: usize,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        Self {
+            header_read_timeout_secs: 30,
+            // the registrar waits for the MASA while answering voucher requests
+            request_timeout_secs: 120,
+            write_timeout_secs: 30,
+            keep_alive: true,
+            max_connections: 1024,
+        }
+    }
+}
+
+impl HttpServerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.header_read_timeout_secs == 0 {
+            return Err(anyhow!("http header_read_timeout_secs must be at least 1"));
+        }
+        if self.request_timeout_secs == 0 {
+            return Err(anyhow!("http request_timeout_secs must be at least 1"));
+        }
+        if self.write_timeout_secs == 0 {
+            return Err(anyhow!("http write_timeout_secs must be at least 1"));
+        }
+        if self.max_connections == 0 {
+            return Err(anyhow!("http max_connections must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Request extensions taken from an established TLS session, added to every request of its connection
+pub type SessionExtensions = Arc<dyn Fn(&SslRef) -> anyhow::Result<Extensions> + Send + Sync>;
+
+/// TLS of [`serve_tls`]
+#[derive(Clone)]
+pub struct TlsListener {
+    pub acceptor: SslAcceptor,
+    /// A session it fails for is closed before any request is read
+    pub extensions: SessionExtensions,
+}
+
+/// Longest pause after a failed accept
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Serves `app` on `listener`. The peer address is available as [`ConnectInfo<SocketAddr>`].
+pub async fn serve(listener: TcpListener, app: Router, config: &HttpServerConfig) {
+    serve_with(listener, app, config, None).await
+}
+
+/// Like [`serve`], with TLS in front of HTTP. The handshake has `header_read_timeout_secs` to complete.
+pub async fn serve_tls(listener: TcpListener, app: Router, config: &HttpServerConfig, tls: TlsListener) {
+    serve_with(listener, app, config, Some(tls)).await
+}
+
+/// Accepts the next connection on `listener`. Accepting fails e.g. when the process runs out of file descriptors,
+/// which should not stop a server: the error is logged and accepting is retried after a pause, doubled up to
+/// [`MAX_ACCEPT_BACKOFF`] while accepting keeps failing.
+pub async fn accept(listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    let mut pause = Duration::from_millis(10);
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            Err(err) => {
+                event!(Level::WARN, "Accepting a connection failed, retrying in {:?}: {}", pause, err);
+                tokio::time::sleep(pause).await;
+                pause = (pause * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn serve_with(listener: TcpListener, app: Router, config: &HttpServerConfig, tls: Option<TlsListener>) {
+    let app = app
+        .layer(TimeoutLayer::new(Duration::from_secs(config.request_timeout_secs)))
+        .layer(middleware::from_fn(problem_details));
+    let connections = Arc::new(Semaphore::new(config.max_connections));
+    let handshake_timeout = Duration::from_secs(config.header_read_timeout_secs);
+    let write_timeout = Duration::from_secs(config.write_timeout_secs);
+
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(Duration::from_secs(config.header_read_timeout_secs))
+        .keep_alive(config.keep_alive);
+
+    loop {
+        let permit = Arc::clone(&connections).acquire_owned().await.expect("connection semaphore is never closed");
+        let (stream, remote) = accept(&listener).await;
+        let stream = WriteTimeout::new(stream, write_timeout);
+
+        let app = app.clone();
+        let builder = builder.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(tls) => match tokio::time::timeout(handshake_timeout, accept_tls(stream, &tls)).await {
+                    Ok(Ok((stream, extensions))) => serve_connection(&builder, stream, remote, app, extensions).await,
+                    Ok(Err(err)) => Err(err),
+                    Err(_) => Err(anyhow!("TLS handshake timed out")),
+                },
+                None => serve_connection(&builder, stream, remote, app, Extensions::new()).await,
+            };
+            if let Err(err) = result {
+                event!(Level::DEBUG, "Connection from {} failed: {}", remote, err);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Fails writes to `stream` that make no progress for `timeout`
+struct WriteTimeout<S> {
+    stream: S,
+    timeout: Duration,
+    /// Armed while a write is pending, disarmed once it makes progress
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> WriteTimeout<S> {
+    fn new(stream: S, timeout: Duration) -> Self {
+        Self {
+            stream,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    fn progress<T>(&mut self, poll: Poll<io::Result<T>>, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            self.deadline = None;
+            return poll;
+        }
+
+        let timeout = self.timeout;
+        let deadline = self.deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WriteTimeout<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write(cx, buf);
+        self.progress(poll, cx)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write_vectored(cx, bufs);
+        self.progress(poll, cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.stream).poll_flush(cx);
+        self.progress(poll, cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.stream).poll_shutdown(cx);
+        self.progress(poll, cx)
+    }
+}
+
+async fn accept_tls<S: AsyncRead + AsyncWrite + Unpin>(stream: S, tls: &TlsListener) -> anyhow::Result<(SslStream<S>, Extensions)> {
+    let mut stream = SslStream::new(Ssl::new(tls.acceptor.context())?, stream)?;
+    Pin::new(&mut stream).accept().await?;
+    let extensions = (tls.extensions)(stream.ssl())?;
+    Ok((stream, extensions))
+}
+
+async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    builder: &http1::Builder,
+    stream: S,
+    remote: SocketAddr,
+    app: Router,
+    extensions: Extensions,
+) -> anyhow::Result<()> {
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().extend(extensions.clone());
+        request.extensions_mut().insert(ConnectInfo(remote));
+        app.clone().oneshot(request)
+    });
+    builder.serve_connection(TokioIo::new(stream), service).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{routing::get, Extension};
+    use example_certs::OpensslTestCerts;
+    use openssl::{
+        nid::Nid,
+        pkey::{PKey, Private},
+        ssl::{SslConnector, SslMethod, SslVerifyMode},
+        x509::X509,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Larger than the socket buffers of both ends
+    const LARGE_BODY: usize = 64 * 1024 * 1024;
+
+    async fn spawn(config: HttpServerConfig) -> SocketAddr {
+        let app = Router::new()
+            .route("/", get(|ConnectInfo(remote): ConnectInfo<SocketAddr>| async move { remote.ip().to_string() }))
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }))
+            .route("/large", get(|| async { vec![0u8; LARGE_BODY] }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, app, &config).await });
+        address
+    }
+
+    async fn get_response(address: SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serves_with_connect_info() {
+        let address = spawn(HttpServerConfig::default()).await;
+
+        let response = get_response(address, "/").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("127.0.0.1"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let address = spawn(HttpServerConfig {
+            request_timeout_secs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        let response = get_response(address, "/slow").await;
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "{}", response);
+        assert!(response.contains("content-type: application/problem+json"), "{}", response);
+        assert!(response.contains(r#""instance":"/slow""#), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_header_read_timeout() {
+        let address = spawn(HttpServerConfig {
+            header_read_timeout_secs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        // a client that never finishes its headers is disconnected
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await;
+        assert!(read.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let address = spawn(HttpServerConfig {
+            write_timeout_secs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        // a client that stops reading its response is disconnected
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(10), stream.read_to_end(&mut response)).await;
+        assert!(read.is_ok());
+        assert!(response.len() < LARGE_BODY);
+    }
+
+    async fn tls_get(address: SocketAddr, identity: Option<(X509, PKey<Private>)>) -> String {
+        let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        if let Some((certificate, key)) = identity {
+            connector.set_certificate(&certificate).unwrap();
+            connector.set_private_key(&key).unwrap();
+        }
+        let ssl = connector.build().configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(ssl, tokio::net::TcpStream::connect(address).await.unwrap()).unwrap();
+        // the server may close the session right after the handshake
+        if Pin::new(&mut stream).connect().await.is_err() {
+            return String::new();
+        }
+        let _ = stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        String::from_utf8(response).unwrap()
+    }
+
+    #[derive(Clone)]
+    struct ClientSerial(String);
+
+    #[tokio::test]
+    async fn test_tls_session_extensions() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+        acceptor.set_certificate(&certs.registrar.0).unwrap();
+        acceptor.set_private_key(&certs.registrar.1).unwrap();
+        acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+        let tls = TlsListener {
+            acceptor: acceptor.build(),
+            extensions: Arc::new(|ssl: &SslRef| {
+                let mut extensions = Extensions::new();
+                let certificate = ssl.peer_certificate().ok_or(anyhow!("no client certificate"))?;
+                let serial = certificate.subject_name().entries_by_nid(Nid::SERIALNUMBER).next().ok_or(anyhow!("no serialNumber"))?;
+                extensions.insert(ClientSerial(serial.data().to_string()?));
+                Ok(extensions)
+            }),
+        };
+
+        let app = Router::new().route("/", get(|Extension(ClientSerial(serial)): Extension<ClientSerial>| async move { serial }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve_tls(listener, app, &HttpServerConfig::default(), tls).await });
+
+        let response = tls_get(address, Some(certs.pledge.clone())).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("00-D0-E5-F2-00-02"), "{}", response);
+
+        // the session is closed before a request is answered
+        assert_eq!(tls_get(address, None).await, "");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(HttpServerConfig::default().validate().is_ok());
+        assert!(HttpServerConfig {
+            max_connections: 0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(HttpServerConfig {
+            write_timeout_secs: 0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
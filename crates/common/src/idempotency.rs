@@ -0,0 +1,349 @@
+//! Idempotency keys (draft-ietf-httpapi-idempotency-key-header) for the voucher requests the registrar retries at
+//! the MASA.
+//!
+//! A request with an `Idempotency-Key` header runs once. Retries with the same key and body get the stored response
+//! until the key expires, so a registrar that timed out waiting for a voucher gets the voucher issued for its first
+//! attempt instead of a second one. A retry arriving while the first attempt still runs waits for it. Reusing a key
+//! for another body is answered with `422 Unprocessable Content`.
+//!
+//! Only successful responses are stored. Errors are decided again on the next attempt, e.g. once an approval was
+//! granted or the MASA is no longer overloaded.
+//!
+//! Clients choose the keys, so the cache holds a bounded number of them. Once it is full, a new key evicts the oldest
+//! stored response, or the oldest running attempt if all are still running. An evicted key runs again on its next retry.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::watch;
+use tracing::{event, Level};
+
+use crate::problem::Problem;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.body).into_response();
+        if let Some(content_type) = self.content_type {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+enum Progress {
+    /// Closed once the first attempt finished
+    Running(watch::Receiver<()>),
+    Completed(StoredResponse),
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// SHA-256 over the path and the body of the first attempt
+    fingerprint: [u8; 32],
+    /// Tells the first attempt of the key from a later one after an eviction
+    attempt: u64,
+    stored: DateTime<Utc>,
+    progress: Progress,
+}
+
+enum Claim {
+    Run(FirstAttempt),
+    Replay(StoredResponse),
+    Wait(watch::Receiver<()>),
+    Mismatch,
+}
+
+/// Responses by idempotency key, keys expire `ttl_secs` after the first attempt and 0 disables the cache.
+/// At most `capacity` keys are kept.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    attempts: AtomicU64,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl_secs: u64, capacity: usize) -> Self {
+        Self {
+            ttl: Duration::seconds(ttl_secs as i64),
+            capacity: capacity.max(1),
+            entries: Mutex::new(HashMap::new()),
+            attempts: AtomicU64::new(0),
+        }
+    }
+
+    fn claim(self: &Arc<Self>, key: &str, fingerprint: [u8; 32]) -> Claim {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| matches!(entry.progress, Progress::Running(_)) || now - entry.stored <= self.ttl);
+
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Entry { progress: Progress::Completed(response), .. }) => Claim::Replay(response.clone()),
+            Some(Entry { progress: Progress::Running(finished), .. }) => Claim::Wait(finished.clone()),
+            None => {
+                while entries.len() >= self.capacity {
+                    let Some(oldest) = oldest_entry(&entries) else { break };
+                    event!(Level::DEBUG, "Evicting idempotency key {}", oldest);
+                    entries.remove(&oldest);
+                }
+
+                let (finish, finished) = watch::channel(());
+                let attempt = self.attempts.fetch_add(1, Ordering::Relaxed);
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        fingerprint,
+                        attempt,
+                        stored: now,
+                        progress: Progress::Running(finished),
+                    },
+                );
+                Claim::Run(FirstAttempt {
+                    cache: self.clone(),
+                    key: key.to_string(),
+                    attempt,
+                    _finish: finish,
+                    completed: false,
+                })
+            }
+        }
+    }
+}
+
+/// Stored responses go first, running attempts are only evicted if nothing else is left
+fn oldest_entry(entries: &HashMap<String, Entry>) -> Option<String> {
+    entries
+        .iter()
+        .min_by_key(|(_, entry)| (matches!(entry.progress, Progress::Running(_)), entry.stored))
+        .map(|(key, _)| key.clone())
+}
+
+/// The first attempt of a key. Retries waiting for it are released when it is dropped, and run themselves unless
+/// its response was stored.
+struct FirstAttempt {
+    cache: Arc<IdempotencyCache>,
+    key: String,
+    attempt: u64,
+    _finish: watch::Sender<()>,
+    completed: bool,
+}
+
+impl FirstAttempt {
+    async fn complete(mut self, response: Response) -> Response {
+        let status = response.status();
+        if !status.is_success() || status == StatusCode::ACCEPTED {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+            event!(Level::WARN, "Response for idempotency key {} could not be stored", self.key);
+            return Response::from_parts(parts, Body::empty());
+        };
+
+        if let Some(entry) = self.cache.entries.lock().unwrap().get_mut(&self.key).filter(|entry| entry.attempt == self.attempt) {
+            entry.progress = Progress::Completed(StoredResponse {
+                status,
+                content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+                body: body.clone(),
+            });
+            self.completed = true;
+        }
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+impl Drop for FirstAttempt {
+    fn drop(&mut self) {
+        if !self.completed {
+            let mut entries = self.cache.entries.lock().unwrap();
+            // the key may have been evicted and claimed again by another attempt in the meantime
+            if entries.get(&self.key).is_some_and(|entry| entry.attempt == self.attempt) {
+                entries.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// The key is a structured field string, a bare token is accepted as well
+fn parse_key(value: &HeaderValue) -> Option<String> {
+    let value = value.to_str().ok()?.trim();
+    let key = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+    let valid = !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.bytes().all(|byte| byte.is_ascii_graphic() && byte != b'"' && byte != b'\\');
+    valid.then(|| key.to_string())
+}
+
+/// Middleware running requests with an `Idempotency-Key` once per key, requests without one are passed on
+pub async fn idempotent(State(cache): State<Arc<IdempotencyCache>>, request: Request, next: Next) -> Response {
+    let Some(value) = request.headers().get(IDEMPOTENCY_KEY) else {
+        return next.run(request).await;
+    };
+    if cache.ttl <= Duration::zero() {
+        return next.run(request).await;
+    }
+    let Some(key) = parse_key(value) else {
+        return Problem::new(StatusCode::BAD_REQUEST)
+            .detail(format!("Idempotency-Key must be a string of at most {} printable characters", MAX_KEY_LENGTH))
+            .into_response();
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Problem::new(StatusCode::BAD_REQUEST).detail("Request body could not be read").into_response();
+    };
+    let fingerprint = openssl::sha::sha256(&[parts.uri.path().as_bytes(), &body].concat());
+
+    let first_attempt = loop {
+        match cache.claim(&key, fingerprint) {
+            Claim::Run(first_attempt) => break first_attempt,
+            Claim::Replay(response) => {
+                event!(Level::INFO, "Replaying the response for idempotency key {}", key);
+                return response.into_response();
+            }
+            Claim::Wait(mut finished) => {
+                event!(Level::DEBUG, "Waiting for the first request with idempotency key {}", key);
+                // the sender is never used, this returns once the first attempt is dropped
+                let _ = finished.changed().await;
+            }
+            Claim::Mismatch => {
+                return Problem::new(StatusCode::UNPROCESSABLE_ENTITY)
+                    .detail("Idempotency-Key was already used for another request")
+                    .extension("error", "idempotency-key-reused")
+                    .into_response();
+            }
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    first_attempt.complete(response).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn app(runs: Arc<AtomicUsize>, ttl_secs: u64) -> Router {
+        app_with_capacity(runs, ttl_secs, 100)
+    }
+
+    fn app_with_capacity(runs: Arc<AtomicUsize>, ttl_secs: u64, capacity: usize) -> Router {
+        Router::new()
+            .route(
+                "/requestvoucher",
+                post(move |body: String| async move {
+                    let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    match body.as_str() {
+                        "fail" => (StatusCode::FORBIDDEN, format!("denied {}", run)),
+                        _ => (StatusCode::OK, format!("voucher {}", run)),
+                    }
+                }),
+            )
+            .layer(middleware::from_fn_with_state(Arc::new(IdempotencyCache::new(ttl_secs, capacity)), idempotent))
+    }
+
+    async fn post_voucher_request(app: &Router, key: Option<&str>, body: &'static str) -> (StatusCode, String) {
+        let mut request = Request::builder().method("POST").uri("/requestvoucher");
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY, key);
+        }
+        let response = app.clone().oneshot(request.body(Body::from(body)).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_replays_first_response() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let app = app(runs.clone(), 600);
+
+        // the retry arrives while the first attempt still runs
+        let (first, retry) = tokio::join!(
+            post_voucher_request(&app, Some("\"key-1\""), "rvr"),
+            post_voucher_request(&app, Some("\"key-1\""), "rvr")
+        );
+        assert_eq!(first, (StatusCode::OK, "voucher 1".to_string()));
+        assert_eq!(retry, first);
+        assert_eq!(post_voucher_request(&app, Some("key-1"), "rvr").await, first);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        let (status, _) = post_voucher_request(&app, Some("\"key-1\""), "other rvr").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // errors are not stored
+        assert_eq!(post_voucher_request(&app, Some("\"key-2\""), "fail").await.1, "denied 2");
+        assert_eq!(post_voucher_request(&app, Some("\"key-2\""), "fail").await.1, "denied 3");
+
+        // without a key every request runs
+        assert_eq!(post_voucher_request(&app, None, "rvr").await.1, "voucher 4");
+        assert_eq!(post_voucher_request(&app, Some("\"\""), "rvr").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_oldest_key_when_full() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let app = app_with_capacity(runs.clone(), 600, 2);
+
+        assert_eq!(post_voucher_request(&app, Some("\"key-1\""), "rvr").await.1, "voucher 1");
+        assert_eq!(post_voucher_request(&app, Some("\"key-2\""), "rvr").await.1, "voucher 2");
+        assert_eq!(post_voucher_request(&app, Some("\"key-3\""), "rvr").await.1, "voucher 3");
+
+        // key-1 was evicted for key-3 and runs again, evicting key-2
+        assert_eq!(post_voucher_request(&app, Some("\"key-3\""), "rvr").await.1, "voucher 3");
+        assert_eq!(post_voucher_request(&app, Some("\"key-1\""), "rvr").await.1, "voucher 4");
+        assert_eq!(post_voucher_request(&app, Some("\"key-3\""), "rvr").await.1, "voucher 3");
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_attempt_releases_key() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let app = app(runs.clone(), 600);
+
+        // the registrar gave up on the first attempt before it was answered
+        let timed_out = tokio::time::timeout(
+            std::time::Duration::from_millis(10),
+            post_voucher_request(&app, Some("\"key\""), "rvr"),
+        );
+        assert!(timed_out.await.is_err());
+
+        assert_eq!(post_voucher_request(&app, Some("\"key\""), "rvr").await.1, "voucher 2");
+
+        let disabled = self::app(Arc::new(AtomicUsize::new(0)), 0);
+        assert_eq!(post_voucher_request(&disabled, Some("\"key\""), "rvr").await.1, "voucher 1");
+        assert_eq!(post_voucher_request(&disabled, Some("\"key\""), "rvr").await.1, "voucher 2");
+    }
+}
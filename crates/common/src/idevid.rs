@@ -0,0 +1,51 @@
+use openssl::{nid::Nid, x509::X509Ref};
+
+use crate::util::hex;
+
+/// id-pe-masa-url, see RFC 8995 Section 2.3.2
+pub const MASA_URL_OID: &str = "1.3.6.1.5.5.7.1.32";
+
+/// Reads the MASA-URI extension of an IDevID as it is written, usually only the authority of the MASA
+pub fn masa_uri(idevid: &X509Ref) -> Option<String> {
+    let der = idevid.to_der().ok()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&der).ok()?;
+
+    let extension = cert
+        .extensions()
+        .iter()
+        .find(|extension| extension.oid.to_id_string() == MASA_URL_OID)?;
+
+    let (_, value) = x509_parser::der_parser::der::parse_der_ia5string(extension.value).ok()?;
+    Some(value.as_str().ok()?.trim_end_matches('/').to_string())
+}
+
+/// Normalizes an IDevID issuer given in a configuration, hex encoded key identifiers with or without colons are
+/// lowercased, anything else is taken as common name
+pub fn normalize_issuer(issuer: &str) -> String {
+    let stripped = issuer.replace(':', "");
+    if !stripped.is_empty() && stripped.chars().all(|c| c.is_ascii_hexdigit()) {
+        stripped.to_lowercase()
+    } else {
+        issuer.to_string()
+    }
+}
+
+/// The common name of the certificate issuer
+pub fn issuer_common_name(cert: &X509Ref) -> Option<String> {
+    cert.issuer_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok())
+}
+
+/// The issuer common name and the hex encoded authority key identifier of a certificate, to match normalized issuers
+pub fn issuer_identifiers(cert: &X509Ref) -> Vec<String> {
+    let mut identifiers = vec![];
+    if let Some(cn) = issuer_common_name(cert) {
+        identifiers.push(cn);
+    }
+    if let Some(akid) = cert.authority_key_id() {
+        identifiers.push(hex(akid.as_slice()));
+    }
+    identifiers
+}
@@ -0,0 +1,163 @@
+use openssl::x509::X509Ref;
+use thiserror::Error;
+use x509_parser::{der_parser::oid, oid_registry::Oid};
+
+/// id-kp-cmcRA, RFC 6402 Section 2.10
+const ID_KP_CMC_RA: Oid<'static> = oid!(1.3.6.1.5.5.7.3.28);
+
+/// What a configured certificate and its key are used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRole {
+    /// Signs vouchers at the MASA
+    VoucherSigning,
+    /// Signs registrar voucher-requests and wrapped CA certificates, id-kp-cmcRA marks a registrar (RFC 8995 Section 2.3.2)
+    RegistrarSigning,
+    /// Server identity in (D)TLS handshakes
+    TlsServer,
+}
+
+impl std::fmt::Display for KeyRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyRole::VoucherSigning => write!(f, "voucher signing"),
+            KeyRole::RegistrarSigning => write!(f, "registrar signing"),
+            KeyRole::TlsServer => write!(f, "TLS server"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum KeyUsageError {
+    #[error("{0} certificate can not be parsed: {1}")]
+    Invalid(KeyRole, String),
+    #[error("{0} certificate is a CA certificate")]
+    Ca(KeyRole),
+    #[error("key usage of the {0} certificate lacks {1}")]
+    KeyUsage(KeyRole, &'static str),
+    #[error("extended key usage of the {0} certificate lacks {1}")]
+    ExtendedKeyUsage(KeyRole, &'static str),
+}
+
+/// Checks that `cert` may be used in `role`. Absent key usage extensions do not restrict the key (RFC 5280 Section
+/// 4.2.1.3), present ones need digitalSignature and, for roles with a defined purpose, the matching extended key usage.
+pub fn check_key_usage(cert: &X509Ref, role: KeyRole) -> Result<(), KeyUsageError> {
+    let der = cert.to_der().map_err(|err| KeyUsageError::Invalid(role, err.to_string()))?;
+    let (_, parsed) =
+        x509_parser::parse_x509_certificate(&der).map_err(|err| KeyUsageError::Invalid(role, err.to_string()))?;
+    let invalid = |err: x509_parser::error::X509Error| KeyUsageError::Invalid(role, err.to_string());
+
+    if parsed.basic_constraints().map_err(invalid)?.is_some_and(|constraints| constraints.value.ca) {
+        return Err(KeyUsageError::Ca(role));
+    }
+
+    if let Some(key_usage) = parsed.key_usage().map_err(invalid)? {
+        if !key_usage.value.digital_signature() {
+            return Err(KeyUsageError::KeyUsage(role, "digitalSignature"));
+        }
+    }
+
+    if let Some(extended) = parsed.extended_key_usage().map_err(invalid)? {
+        let extended = extended.value;
+        match role {
+            KeyRole::VoucherSigning => {}
+            KeyRole::RegistrarSigning if !extended.any && !extended.other.contains(&ID_KP_CMC_RA) => {
+                return Err(KeyUsageError::ExtendedKeyUsage(role, "id-kp-cmcRA"));
+            }
+            KeyRole::TlsServer if !extended.any && !extended.server_auth => {
+                return Err(KeyUsageError::ExtendedKeyUsage(role, "id-kp-serverAuth"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        nid::Nid,
+        x509::{
+            extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage},
+            X509Builder, X509NameBuilder, X509,
+        },
+    };
+
+    use super::*;
+
+    /// End entity certificate of the registrar CA with the given extensions
+    fn cert(certs: &OpensslTestCerts, key_usage: Option<KeyUsage>, extended: Option<ExtendedKeyUsage>) -> X509 {
+        let (ca_cert, ca_key) = &certs.registrar_ca;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "registrar-tls.example.com").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(5280).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(ca_cert.subject_name()).unwrap();
+        builder.set_pubkey(&certs.registrar.1).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.append_extension(BasicConstraints::new().build().unwrap()).unwrap();
+        if let Some(key_usage) = key_usage {
+            builder.append_extension(key_usage.build().unwrap()).unwrap();
+        }
+        if let Some(extended) = extended {
+            builder.append_extension(extended.build().unwrap()).unwrap();
+        }
+        builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_example_certificates() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+
+        assert_eq!(check_key_usage(&certs.vendor.0, KeyRole::VoucherSigning), Ok(()));
+        assert_eq!(check_key_usage(&certs.registrar.0, KeyRole::RegistrarSigning), Ok(()));
+        // the registrar certificate is restricted to cmcRA and may not serve TLS
+        assert_eq!(
+            check_key_usage(&certs.registrar.0, KeyRole::TlsServer),
+            Err(KeyUsageError::ExtendedKeyUsage(KeyRole::TlsServer, "id-kp-serverAuth"))
+        );
+        assert_eq!(check_key_usage(&certs.registrar_ca.0, KeyRole::RegistrarSigning), Err(KeyUsageError::Ca(KeyRole::RegistrarSigning)));
+    }
+
+    #[test]
+    fn test_tls_server_certificate() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+
+        let mut key_usage = KeyUsage::new();
+        key_usage.critical().digital_signature();
+        let mut extended = ExtendedKeyUsage::new();
+        extended.server_auth();
+        let tls = cert(&certs, Some(key_usage), Some(extended));
+        assert_eq!(check_key_usage(&tls, KeyRole::TlsServer), Ok(()));
+        assert_eq!(
+            check_key_usage(&tls, KeyRole::RegistrarSigning),
+            Err(KeyUsageError::ExtendedKeyUsage(KeyRole::RegistrarSigning, "id-kp-cmcRA"))
+        );
+
+        // without extensions the key is not restricted
+        assert_eq!(check_key_usage(&cert(&certs, None, None), KeyRole::TlsServer), Ok(()));
+    }
+
+    #[test]
+    fn test_missing_digital_signature() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+
+        let mut key_usage = KeyUsage::new();
+        key_usage.key_encipherment();
+        let cert = cert(&certs, Some(key_usage), None);
+        for role in [KeyRole::VoucherSigning, KeyRole::RegistrarSigning, KeyRole::TlsServer] {
+            assert_eq!(check_key_usage(&cert, role), Err(KeyUsageError::KeyUsage(role, "digitalSignature")));
+        }
+    }
+}
@@ -1,7 +1,27 @@
-#![feature(adt_const_params)]
+#![feature(adt_const_params, unsized_const_params)]
 #![allow(incomplete_features)]
 
+pub mod asn1;
+pub mod auth;
+pub mod chain;
+pub mod coap;
+pub mod coap_block;
+pub mod coap_client;
 pub mod defaults;
+pub mod edhoc;
 pub mod error;
+pub mod expiry;
+pub mod http_server;
+pub mod idempotency;
+pub mod idevid;
+pub mod key_usage;
+pub mod limits;
+pub mod oscore;
+pub mod pkcs12;
+pub mod problem;
+pub mod revocation;
+pub mod serial_pattern;
 pub mod server_error;
-pub mod util;
\ No newline at end of file
+pub mod timestamp;
+pub mod util;
+pub mod verification;
\ No newline at end of file
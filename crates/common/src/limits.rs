@@ -0,0 +1,179 @@
+//! Maximum sizes of request bodies, so oversized artifacts are rejected before they are buffered and parsed.
+//!
+//! Every route is guarded by [`enforce_body_limit`] with the limit of the artifact it accepts.
+//! Bodies above the limit are answered with `413 Payload Too Large` and a problem+json body (RFC 9457).
+
+use std::fmt;
+
+use anyhow::anyhow;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header::CONTENT_LENGTH,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::server_error::ServerError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    Voucher,
+    VoucherRequest,
+    /// Enrollment requests, i.e. PERs carrying the CSR
+    Csr,
+    /// Voucher and enroll status reports
+    Telemetry,
+    /// Firmware images, only accepted by the pledge
+    Firmware,
+    Other,
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Artifact::Voucher => "voucher",
+            Artifact::VoucherRequest => "voucher-request",
+            Artifact::Csr => "csr",
+            Artifact::Telemetry => "telemetry",
+            Artifact::Firmware => "firmware",
+            Artifact::Other => "body",
+        })
+    }
+}
+
+/// Limits in bytes, configured as e.g. `[registrar.artifact_limits]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ArtifactLimits {
+    pub voucher: usize,
+    pub voucher_request: usize,
+    pub csr: usize,
+    pub telemetry: usize,
+    pub firmware: usize,
+    /// All other endpoints, like triggers and the admin API
+    pub other: usize,
+}
+
+impl Default for ArtifactLimits {
+    fn default() -> Self {
+        Self {
+            voucher: 64 * 1024,
+            voucher_request: 64 * 1024,
+            csr: 32 * 1024,
+            telemetry: 32 * 1024,
+            firmware: 16 * 1024 * 1024,
+            other: 256 * 1024,
+        }
+    }
+}
+
+impl ArtifactLimits {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let limits = [
+            (Artifact::Voucher, self.voucher),
+            (Artifact::VoucherRequest, self.voucher_request),
+            (Artifact::Csr, self.csr),
+            (Artifact::Telemetry, self.telemetry),
+            (Artifact::Firmware, self.firmware),
+            (Artifact::Other, self.other),
+        ];
+        match limits.iter().find(|(_, limit)| *limit == 0) {
+            Some((artifact, _)) => Err(anyhow!("artifact_limits: limit for {} must be at least 1 byte", artifact)),
+            None => Ok(()),
+        }
+    }
+
+    /// State for [`enforce_body_limit`] on a route accepting `artifact`
+    pub fn guard(&self, artifact: Artifact) -> BodyLimit {
+        let limit = match artifact {
+            Artifact::Voucher => self.voucher,
+            Artifact::VoucherRequest => self.voucher_request,
+            Artifact::Csr => self.csr,
+            Artifact::Telemetry => self.telemetry,
+            Artifact::Firmware => self.firmware,
+            Artifact::Other => self.other,
+        };
+        BodyLimit { artifact, limit }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimit {
+    artifact: Artifact,
+    limit: usize,
+}
+
+/// Middleware rejecting bodies above the limit. A `Content-Length` above it is rejected before the body is read,
+/// bodies without one are read up to the limit only.
+pub async fn enforce_body_limit(
+    State(guard): State<BodyLimit>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    let too_large = || ServerError::PayloadTooLarge {
+        artifact: guard.artifact.to_string(),
+        limit: guard.limit,
+    };
+
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > guard.limit) {
+        return Err(too_large());
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, guard.limit).await.map_err(|err| {
+        match err.into_inner().downcast_ref::<http_body_util::LengthLimitError>() {
+            Some(_) => too_large(),
+            None => ServerError::BadRequestWithReason("Request body could not be read".to_string()),
+        }
+    })?;
+
+    Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header::CONTENT_TYPE, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_oversized_body() {
+        let limits = ArtifactLimits {
+            voucher_request: 8,
+            ..Default::default()
+        };
+        let app: Router = Router::new().route(
+            "/requestvoucher",
+            post(|body: String| async move { body })
+                .layer(middleware::from_fn_with_state(limits.guard(Artifact::VoucherRequest), enforce_body_limit)),
+        );
+
+        let request = |body: &'static str| Request::builder().method("POST").uri("/requestvoucher").body(Body::from(body)).unwrap();
+
+        let response = app.clone().oneshot(request("12345678")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(request("123456789")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "application/problem+json");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], 413);
+        assert_eq!(problem["artifact"], "voucher-request");
+        assert_eq!(problem["limit"], 8);
+    }
+}
@@ -0,0 +1,502 @@
+//! OSCORE (RFC 8613) message protection, an alternative to DTLS for the constrained transports.
+//!
+//! Only the AEAD algorithm AES-CCM-16-64-128 and HKDF SHA-256 are supported, which every OSCORE endpoint must implement.
+//! The security context is derived from a master secret and salt, e.g. as exported by EDHOC (RFC 9528 Appendix A.1).
+//! Options are not split into inner and outer options: every option of the inner CoAP message is protected, see
+//! [`plaintext`], and the outer message only carries the OSCORE option.
+
+use ciborium::Value;
+use openssl::{cipher::Cipher, cipher_ctx::CipherCtx, md::Md, pkey::Id, pkey_ctx::PkeyCtx};
+use thiserror::Error;
+
+use crate::coap::{CoapError, Message};
+
+/// COSE algorithm identifier of AES-CCM-16-64-128
+const AES_CCM_16_64_128: i64 = 10;
+const KEY_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 13;
+const TAG_LENGTH: usize = 8;
+
+/// Largest sender sequence number, see RFC 8613 Section 7.2.1
+const MAX_SEQUENCE_NUMBER: u64 = (1 << 40) - 1;
+
+/// Number of partial IVs below the highest received one that are still accepted once
+const REPLAY_WINDOW: u64 = 32;
+
+#[derive(Debug, Error)]
+pub enum OscoreError {
+    #[error("Sender ID is longer than {} bytes", NONCE_LENGTH - 6)]
+    IdTooLong,
+    #[error("Sender sequence numbers are exhausted, a new security context is needed")]
+    SequenceNumbersExhausted,
+    #[error("Partial IV is longer than 5 bytes")]
+    InvalidPartialIv,
+    #[error("Request with partial IV {0} was already received")]
+    Replay(u64),
+    #[error("Message could not be decrypted")]
+    Decryption,
+    #[error("OSCORE option is malformed")]
+    InvalidOption,
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error(transparent)]
+    Cbor(#[from] ciborium::ser::Error<std::io::Error>),
+}
+
+/// A request protected by [`SecurityContext::protect_request`], `kid` and `partial_iv` go into the OSCORE option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectedRequest {
+    pub kid: Vec<u8>,
+    pub partial_iv: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Value of the OSCORE option, see RFC 8613 Section 6.1
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OscoreOption {
+    pub partial_iv: Vec<u8>,
+    pub kid_context: Option<Vec<u8>>,
+    pub kid: Option<Vec<u8>>,
+}
+
+impl OscoreOption {
+    const KID: u8 = 0x08;
+    const KID_CONTEXT: u8 = 0x10;
+
+    /// The option of a request, which carries its partial IV and the Sender ID of the client as `kid`
+    pub fn request(request: &ProtectedRequest) -> Self {
+        Self {
+            partial_iv: request.partial_iv.clone(),
+            kid_context: None,
+            kid: Some(request.kid.clone()),
+        }
+    }
+
+    /// Responses reusing the nonce of the request have an empty option
+    pub fn encode(&self) -> Vec<u8> {
+        if self.partial_iv.is_empty() && self.kid_context.is_none() && self.kid.is_none() {
+            return vec![];
+        }
+
+        let mut flags = self.partial_iv.len() as u8;
+        if self.kid.is_some() {
+            flags |= Self::KID;
+        }
+        if self.kid_context.is_some() {
+            flags |= Self::KID_CONTEXT;
+        }
+
+        let mut value = vec![flags];
+        value.extend_from_slice(&self.partial_iv);
+        if let Some(kid_context) = &self.kid_context {
+            value.push(kid_context.len() as u8);
+            value.extend_from_slice(kid_context);
+        }
+        if let Some(kid) = &self.kid {
+            value.extend_from_slice(kid);
+        }
+        value
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self, OscoreError> {
+        let Some((&flags, mut rest)) = value.split_first() else {
+            return Ok(Self::default());
+        };
+        // the extension flag and partial IV lengths 6 and 7 are reserved
+        let partial_iv_length = usize::from(flags & 0x07);
+        if flags & 0xe0 != 0 || partial_iv_length > 5 || rest.len() < partial_iv_length {
+            return Err(OscoreError::InvalidOption);
+        }
+
+        let partial_iv = rest[..partial_iv_length].to_vec();
+        rest = &rest[partial_iv_length..];
+        let kid_context = match flags & Self::KID_CONTEXT {
+            0 => None,
+            _ => {
+                let (&length, context) = rest.split_first().ok_or(OscoreError::InvalidOption)?;
+                let length = usize::from(length);
+                if context.len() < length {
+                    return Err(OscoreError::InvalidOption);
+                }
+                rest = &context[length..];
+                Some(context[..length].to_vec())
+            }
+        };
+        let kid = match flags & Self::KID {
+            0 if rest.is_empty() => None,
+            0 => return Err(OscoreError::InvalidOption),
+            _ => Some(rest.to_vec()),
+        };
+
+        Ok(Self {
+            partial_iv,
+            kid_context,
+            kid,
+        })
+    }
+}
+
+/// Plaintext of a CoAP message, its code followed by all of its options and the payload, see RFC 8613 Section 5.3
+pub fn plaintext(message: &Message) -> Vec<u8> {
+    let inner = Message {
+        token: vec![],
+        ..message.clone()
+    };
+    let mut plaintext = vec![message.code];
+    plaintext.extend_from_slice(&inner.encode()[4..]);
+    plaintext
+}
+
+/// Inner message of a decrypted `plaintext`, with the type, message ID and token of the `outer` message
+pub fn inner_message(outer: &Message, plaintext: &[u8]) -> Result<Message, CoapError> {
+    let (&code, rest) = plaintext.split_first().ok_or(CoapError::Truncated)?;
+    let header = [0x40, code, 0, 0];
+    let inner = Message::decode(&[&header[..], rest].concat())?;
+    Ok(Message {
+        message_type: outer.message_type,
+        message_id: outer.message_id,
+        token: outer.token.clone(),
+        ..inner
+    })
+}
+
+/// Security context of one endpoint, see RFC 8613 Section 3
+#[derive(Debug)]
+pub struct SecurityContext {
+    sender_id: Vec<u8>,
+    recipient_id: Vec<u8>,
+    id_context: Option<Vec<u8>>,
+    sender_key: Vec<u8>,
+    recipient_key: Vec<u8>,
+    common_iv: Vec<u8>,
+    sequence_number: u64,
+    replay_window: Option<(u64, u32)>,
+}
+
+impl SecurityContext {
+    /// Derives the sender and recipient keys and the common IV, see RFC 8613 Section 3.2
+    pub fn derive(
+        master_secret: &[u8],
+        master_salt: &[u8],
+        sender_id: &[u8],
+        recipient_id: &[u8],
+        id_context: Option<&[u8]>,
+    ) -> Result<Self, OscoreError> {
+        if sender_id.len() > NONCE_LENGTH - 6 || recipient_id.len() > NONCE_LENGTH - 6 {
+            return Err(OscoreError::IdTooLong);
+        }
+
+        let derive = |id: &[u8], kind: &str, length: usize| {
+            let info = Value::Array(vec![
+                Value::Bytes(id.to_vec()),
+                id_context.map_or(Value::Null, |context| Value::Bytes(context.to_vec())),
+                Value::from(AES_CCM_16_64_128),
+                Value::from(kind),
+                Value::from(length as u64),
+            ]);
+            hkdf(master_secret, master_salt, &cbor(&info)?, length)
+        };
+
+        Ok(Self {
+            sender_key: derive(sender_id, "Key", KEY_LENGTH)?,
+            recipient_key: derive(recipient_id, "Key", KEY_LENGTH)?,
+            common_iv: derive(&[], "IV", NONCE_LENGTH)?,
+            sender_id: sender_id.to_vec(),
+            recipient_id: recipient_id.to_vec(),
+            id_context: id_context.map(<[u8]>::to_vec),
+            sequence_number: 0,
+            replay_window: None,
+        })
+    }
+
+    pub fn id_context(&self) -> Option<&[u8]> {
+        self.id_context.as_deref()
+    }
+
+    /// Encrypts a request with the next sender sequence number as partial IV
+    pub fn protect_request(&mut self, plaintext: &[u8]) -> Result<ProtectedRequest, OscoreError> {
+        if self.sequence_number > MAX_SEQUENCE_NUMBER {
+            return Err(OscoreError::SequenceNumbersExhausted);
+        }
+        let partial_iv = encode_partial_iv(self.sequence_number);
+        self.sequence_number += 1;
+
+        let nonce = self.nonce(&self.sender_id, &partial_iv);
+        let aad = aad(&self.sender_id, &partial_iv)?;
+        let ciphertext = seal(&self.sender_key, &nonce, &aad, plaintext)?;
+
+        Ok(ProtectedRequest {
+            kid: self.sender_id.clone(),
+            partial_iv,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts a request of the recipient and rejects partial IVs that were already received
+    pub fn unprotect_request(&mut self, partial_iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, OscoreError> {
+        let sequence_number = decode_partial_iv(partial_iv)?;
+        if self.is_replay(sequence_number) {
+            return Err(OscoreError::Replay(sequence_number));
+        }
+
+        let nonce = self.nonce(&self.recipient_id, partial_iv);
+        let aad = aad(&self.recipient_id, partial_iv)?;
+        let plaintext = open(&self.recipient_key, &nonce, &aad, ciphertext)?;
+
+        // only authenticated requests move the replay window
+        self.accept(sequence_number);
+        Ok(plaintext)
+    }
+
+    /// Encrypts the response to a request, reusing the nonce of the request, see RFC 8613 Section 8.3
+    pub fn protect_response(&self, request_partial_iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, OscoreError> {
+        let nonce = self.nonce(&self.recipient_id, request_partial_iv);
+        let aad = aad(&self.recipient_id, request_partial_iv)?;
+        seal(&self.sender_key, &nonce, &aad, plaintext)
+    }
+
+    /// Decrypts the response to a request protected with this context
+    pub fn unprotect_response(&self, request_partial_iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, OscoreError> {
+        let nonce = self.nonce(&self.sender_id, request_partial_iv);
+        let aad = aad(&self.sender_id, request_partial_iv)?;
+        open(&self.recipient_key, &nonce, &aad, ciphertext)
+    }
+
+    /// See RFC 8613 Section 5.2
+    fn nonce(&self, id: &[u8], partial_iv: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        nonce[0] = id.len() as u8;
+        nonce[NONCE_LENGTH - 5 - id.len()..NONCE_LENGTH - 5].copy_from_slice(id);
+        nonce[NONCE_LENGTH - partial_iv.len()..].copy_from_slice(partial_iv);
+
+        nonce.iter().zip(&self.common_iv).map(|(byte, iv)| byte ^ iv).collect()
+    }
+
+    fn is_replay(&self, sequence_number: u64) -> bool {
+        match self.replay_window {
+            None => false,
+            Some((highest, _)) if sequence_number > highest => false,
+            Some((highest, _)) if highest - sequence_number >= REPLAY_WINDOW => true,
+            Some((highest, seen)) => seen & (1 << (highest - sequence_number)) != 0,
+        }
+    }
+
+    fn accept(&mut self, sequence_number: u64) {
+        self.replay_window = Some(match self.replay_window {
+            Some((highest, seen)) if sequence_number <= highest => (highest, seen | 1 << (highest - sequence_number)),
+            Some((highest, seen)) => {
+                let shift = sequence_number - highest;
+                let seen = if shift >= REPLAY_WINDOW { 0 } else { seen << shift };
+                (sequence_number, seen | 1)
+            }
+            None => (sequence_number, 1),
+        });
+    }
+}
+
+/// AAD of the COSE Encrypt0 object, see RFC 8613 Section 5.4. Options are not integrity protected as Class I options.
+fn aad(request_kid: &[u8], request_partial_iv: &[u8]) -> Result<Vec<u8>, OscoreError> {
+    let external_aad = Value::Array(vec![
+        Value::from(1u64),
+        Value::Array(vec![Value::from(AES_CCM_16_64_128)]),
+        Value::Bytes(request_kid.to_vec()),
+        Value::Bytes(request_partial_iv.to_vec()),
+        Value::Bytes(vec![]),
+    ]);
+
+    cbor(&Value::Array(vec![
+        Value::from("Encrypt0"),
+        Value::Bytes(vec![]),
+        Value::Bytes(cbor(&external_aad)?),
+    ]))
+}
+
+/// AES-CCM-16-64-128 with the tag appended, which is also the AEAD of EDHOC cipher suite 2
+pub(crate) fn seal(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, OscoreError> {
+    let mut ctx = ccm_context(key, nonce, None, plaintext.len())?;
+    let mut ciphertext = vec![];
+    ctx.cipher_update(aad, None)?;
+    ctx.cipher_update_vec(plaintext, &mut ciphertext)?;
+    ctx.cipher_final_vec(&mut ciphertext)?;
+
+    let mut tag = [0u8; TAG_LENGTH];
+    ctx.tag(&mut tag)?;
+    ciphertext.extend_from_slice(&tag);
+    Ok(ciphertext)
+}
+
+pub(crate) fn open(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, OscoreError> {
+    let split = ciphertext.len().checked_sub(TAG_LENGTH).ok_or(OscoreError::Decryption)?;
+    let (ciphertext, tag) = ciphertext.split_at(split);
+
+    let mut ctx = ccm_context(key, nonce, Some(tag), ciphertext.len())?;
+    let mut plaintext = vec![];
+    ctx.cipher_update(aad, None)?;
+    // CCM verifies the tag while decrypting
+    ctx.cipher_update_vec(ciphertext, &mut plaintext).map_err(|_| OscoreError::Decryption)?;
+    Ok(plaintext)
+}
+
+/// CCM needs the nonce and tag length before the key, and the message length before the AAD
+fn ccm_context(key: &[u8], nonce: &[u8], tag: Option<&[u8]>, length: usize) -> Result<CipherCtx, OscoreError> {
+    let mut ctx = CipherCtx::new()?;
+    match tag {
+        None => {
+            ctx.encrypt_init(Some(Cipher::aes_128_ccm()), None, None)?;
+            ctx.set_iv_length(NONCE_LENGTH)?;
+            ctx.set_tag_length(TAG_LENGTH)?;
+            ctx.encrypt_init(None, Some(key), Some(nonce))?;
+        }
+        Some(tag) => {
+            ctx.decrypt_init(Some(Cipher::aes_128_ccm()), None, None)?;
+            ctx.set_iv_length(NONCE_LENGTH)?;
+            ctx.set_tag(tag)?;
+            ctx.decrypt_init(None, Some(key), Some(nonce))?;
+        }
+    }
+    ctx.set_data_len(length)?;
+    Ok(ctx)
+}
+
+fn hkdf(secret: &[u8], salt: &[u8], info: &[u8], length: usize) -> Result<Vec<u8>, OscoreError> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha256())?;
+    ctx.set_hkdf_key(secret)?;
+    if !salt.is_empty() {
+        ctx.set_hkdf_salt(salt)?;
+    }
+    ctx.add_hkdf_info(info)?;
+
+    let mut okm = vec![0u8; length];
+    ctx.derive(Some(&mut okm))?;
+    Ok(okm)
+}
+
+pub(crate) fn cbor(value: &Value) -> Result<Vec<u8>, OscoreError> {
+    let mut encoded = vec![];
+    ciborium::ser::into_writer(value, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Shortest big endian encoding, `0` is encoded as a single zero byte
+fn encode_partial_iv(sequence_number: u64) -> Vec<u8> {
+    let bytes = sequence_number.to_be_bytes();
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len() - 1);
+    bytes[start..].to_vec()
+}
+
+fn decode_partial_iv(partial_iv: &[u8]) -> Result<u64, OscoreError> {
+    if partial_iv.is_empty() || partial_iv.len() > 5 {
+        return Err(OscoreError::InvalidPartialIv);
+    }
+    Ok(partial_iv.iter().fold(0u64, |value, byte| value << 8 | u64::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        crate::util::from_hex(value).unwrap()
+    }
+
+    fn contexts() -> (SecurityContext, SecurityContext) {
+        let secret = hex("0102030405060708090a0b0c0d0e0f10");
+        let salt = hex("9e7ca92223786340");
+        let client = SecurityContext::derive(&secret, &salt, &[], &[0x01], None).unwrap();
+        let server = SecurityContext::derive(&secret, &salt, &[0x01], &[], None).unwrap();
+        (client, server)
+    }
+
+    /// Test vectors of RFC 8613 Appendix C.1.1, C.4 and C.7
+    #[test]
+    fn test_rfc8613_vectors() {
+        let (mut client, mut server) = contexts();
+        assert_eq!(client.sender_key, hex("f0910ed7295e6ad4b54fc793154302ff"));
+        assert_eq!(client.recipient_key, hex("ffb14e093c94c9cac9471648b4f98710"));
+        assert_eq!(client.common_iv, hex("4622d4dd6d944168eefb54987c"));
+
+        client.sequence_number = 20;
+        let request = client.protect_request(&hex("01b3747631")).unwrap();
+        assert_eq!(request.partial_iv, vec![0x14]);
+        assert_eq!(request.ciphertext, hex("612f1092f1776f1c1668b3825e"));
+        assert_eq!(
+            server.unprotect_request(&request.partial_iv, &request.ciphertext).unwrap(),
+            hex("01b3747631")
+        );
+
+        let response = server.protect_response(&request.partial_iv, &hex("45ff48656c6c6f20576f726c6421")).unwrap();
+        assert_eq!(response, hex("dbaad1e9a7e7b2a813d3c31524378303cdafae119106"));
+        assert_eq!(
+            client.unprotect_response(&request.partial_iv, &response).unwrap(),
+            hex("45ff48656c6c6f20576f726c6421")
+        );
+    }
+
+    #[test]
+    fn test_option() {
+        // RFC 8613 Appendix C.4, C.5 and C.7
+        let request = OscoreOption::decode(&hex("0914")).unwrap();
+        assert_eq!(request.partial_iv, vec![0x14]);
+        assert_eq!(request.kid, Some(vec![]));
+        assert_eq!(request.encode(), hex("0914"));
+
+        let with_kid = OscoreOption::decode(&hex("091400")).unwrap();
+        assert_eq!(with_kid.kid, Some(vec![0x00]));
+        let with_context = OscoreOption::decode(&hex("19140837cbf3210017a2d300")).unwrap();
+        assert_eq!(with_context.kid_context, Some(hex("37cbf3210017a2d3")));
+        assert_eq!(with_context.kid, Some(vec![0x00]));
+        assert_eq!(with_context.encode(), hex("19140837cbf3210017a2d300"));
+
+        assert_eq!(OscoreOption::decode(&[]).unwrap(), OscoreOption::default());
+        assert!(OscoreOption::default().encode().is_empty());
+        assert!(OscoreOption::decode(&hex("0614")).is_err());
+        assert!(OscoreOption::decode(&hex("011400")).is_err());
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let mut message = Message {
+            message_type: crate::coap::MessageType::Confirmable,
+            code: crate::coap::code::GET,
+            message_id: 7,
+            token: vec![0x42],
+            options: vec![],
+            payload: vec![],
+        };
+        message.set_uri_path("tv1");
+        // RFC 8613 Appendix C.4: GET with Uri-Path "tv1"
+        assert_eq!(plaintext(&message), hex("01b3747631"));
+
+        let inner = inner_message(&message, &hex("01b3747631")).unwrap();
+        assert_eq!(inner, message);
+        assert!(inner_message(&message, &[]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_replays() {
+        let (mut client, mut server) = contexts();
+        let first = client.protect_request(b"voucher request").unwrap();
+        let second = client.protect_request(b"voucher request").unwrap();
+
+        server.unprotect_request(&second.partial_iv, &second.ciphertext).unwrap();
+        // reordered, but within the window
+        server.unprotect_request(&first.partial_iv, &first.ciphertext).unwrap();
+
+        assert!(matches!(
+            server.unprotect_request(&first.partial_iv, &first.ciphertext),
+            Err(OscoreError::Replay(0))
+        ));
+
+        let mut tampered = second.ciphertext.clone();
+        tampered[0] ^= 1;
+        let mut fresh = contexts().1;
+        assert!(matches!(
+            fresh.unprotect_request(&second.partial_iv, &tampered),
+            Err(OscoreError::Decryption)
+        ));
+        // a forged request does not consume the partial IV
+        fresh.unprotect_request(&second.partial_iv, &second.ciphertext).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use openssl::{
+    error::ErrorStack,
+    pkcs12::Pkcs12,
+    pkey::{PKeyRef, Private},
+    stack::Stack,
+    x509::{X509Ref, X509},
+};
+
+/// Media type of PKCS#12 files
+pub const PKCS12: &str = "application/x-pkcs12";
+
+/// Password protected PKCS#12 file of an LDevID and its chain, with its key if it is known.
+/// Issuers of an LDevID only know its certificate, the key stays with the device.
+pub fn bundle(
+    name: &str,
+    key: Option<&PKeyRef<Private>>,
+    ldevid: &X509Ref,
+    chain: &[X509],
+    password: &str,
+) -> Result<Vec<u8>, ErrorStack> {
+    let mut ca = Stack::new()?;
+    for cert in chain {
+        ca.push(cert.clone())?;
+    }
+
+    let mut builder = Pkcs12::builder();
+    builder.name(name).cert(ldevid).ca(ca);
+    if let Some(key) = key {
+        builder.pkey(key);
+    }
+    builder.build2(password)?.to_der()
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    #[test]
+    fn test_bundle_is_password_protected() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (ldevid, key) = &certs.pledge;
+
+        let der = bundle("00-D0-E5-F2-00-02", Some(key), ldevid, std::slice::from_ref(&certs.registrar_ca.0), "secret").unwrap();
+        let pkcs12 = Pkcs12::from_der(&der).unwrap();
+        assert!(pkcs12.parse2("wrong").is_err());
+
+        let parsed = pkcs12.parse2("secret").unwrap();
+        assert_eq!(parsed.cert.unwrap().to_der().unwrap(), ldevid.to_der().unwrap());
+        assert!(parsed.pkey.unwrap().public_eq(key));
+        assert_eq!(parsed.ca.unwrap().len(), 1);
+
+        // without the key, as exported by the registrar
+        let der = bundle("00-D0-E5-F2-00-02", None, ldevid, &[], "secret").unwrap();
+        let parsed = Pkcs12::from_der(&der).unwrap().parse2("secret").unwrap();
+        assert!(parsed.pkey.is_none());
+        // without a key to match, OpenSSL lists every certificate of the bundle as CA
+        assert_eq!(parsed.ca.unwrap()[0].to_der().unwrap(), ldevid.to_der().unwrap());
+    }
+}
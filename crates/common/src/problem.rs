@@ -0,0 +1,189 @@
+//! Problem details (RFC 9457) for the error responses of the MASA, the registrar, the registrar-agent and the pledge.
+//!
+//! [`ServerError`](crate::server_error::ServerError) and [`AppError`](crate::error::AppError) answer with a
+//! [`Problem`]. The [`problem_details`] middleware fills in the `instance` of these and turns the remaining error
+//! responses, like bare status codes or axum's plain text rejections, into problem details as well.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use brski_prm_artifacts::content_type::PROBLEM_JSON;
+use serde_json::{Map, Value};
+use tracing::{event, Level};
+
+/// Error bodies above this size are passed on unchanged
+const MAX_PROBLEM_BODY: usize = 64 * 1024;
+
+/// An `application/problem+json` body. The `type` is always `about:blank`, so the `title` is the reason phrase of the
+/// status, machine readable error codes are passed as extension members instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    status: StatusCode,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Adds the extension member `name`, members defined by RFC 9457 can not be overwritten
+    pub fn extension(mut self, name: &str, value: impl Into<Value>) -> Self {
+        if !matches!(name, "type" | "title" | "status" | "detail" | "instance") {
+            self.extensions.insert(name.to_string(), value.into());
+        }
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut body = Map::new();
+        body.insert("type".to_string(), "about:blank".into());
+        body.insert("title".to_string(), self.status.canonical_reason().unwrap_or("Error").into());
+        body.insert("status".to_string(), self.status.as_u16().into());
+        if let Some(detail) = &self.detail {
+            body.insert("detail".to_string(), detail.as_str().into());
+        }
+        if let Some(instance) = &self.instance {
+            body.insert("instance".to_string(), instance.as_str().into());
+        }
+        body.extend(self.extensions.clone());
+        Value::Object(body)
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        (self.status, [(CONTENT_TYPE, PROBLEM_JSON)], self.to_json().to_string()).into_response()
+    }
+}
+
+/// Middleware answering every 4xx and 5xx response as problem details with the request path as `instance`
+pub async fn problem_details(request: Request, next: Next) -> Response {
+    let instance = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let is_problem = response.headers().get(CONTENT_TYPE).is_some_and(|value| value == PROBLEM_JSON);
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_PROBLEM_BODY).await else {
+        event!(Level::WARN, "Error response for {} could not be read for problem details", instance);
+        return Response::from_parts(parts, Body::empty());
+    };
+
+    let body = match (is_problem, serde_json::from_slice::<Value>(&body)) {
+        (true, Ok(Value::Object(mut problem))) => {
+            problem.entry("instance").or_insert(instance.into());
+            Value::Object(problem)
+        }
+        // an unknown problem+json body is passed on as is
+        (true, _) => return Response::from_parts(parts, Body::from(body)),
+        (false, _) => {
+            let problem = Problem::new(status).instance(instance);
+            let problem = match std::str::from_utf8(&body).map(str::trim) {
+                Ok(detail) if !detail.is_empty() => problem.detail(detail),
+                _ => problem,
+            };
+            problem.to_json()
+        }
+    };
+
+    parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::server_error::ServerError;
+
+    async fn get_problem(app: Router, uri: &str) -> (StatusCode, Value) {
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_problem_details() {
+        let app: Router = Router::new()
+            .route(
+                "/blocked",
+                get(|| async {
+                    Err::<(), _>(ServerError::PledgeBlocked {
+                        serial_number: "00-D0-E5-F2-00-02".to_string(),
+                        reason: "stolen".to_string(),
+                    })
+                }),
+            )
+            .route("/teapot", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .route("/text", get(|| async { (StatusCode::BAD_REQUEST, "Invalid URL") }))
+            .route("/ok", get(|| async { "fine" }))
+            .layer(middleware::from_fn(problem_details));
+
+        let (status, problem) = get_problem(app.clone(), "/blocked").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(problem["type"], "about:blank");
+        assert_eq!(problem["title"], "Forbidden");
+        assert_eq!(problem["status"], 403);
+        assert_eq!(problem["instance"], "/blocked");
+        assert_eq!(problem["error"], "pledge-blocked");
+        assert_eq!(problem["serial-number"], "00-D0-E5-F2-00-02");
+
+        let (status, problem) = get_problem(app.clone(), "/teapot").await;
+        assert_eq!(status, StatusCode::IM_A_TEAPOT);
+        assert_eq!(problem["title"], "I'm a teapot");
+        assert_eq!(problem["instance"], "/teapot");
+        assert!(problem.get("detail").is_none());
+
+        let (_, problem) = get_problem(app.clone(), "/text?query").await;
+        assert_eq!(problem["detail"], "Invalid URL");
+        assert_eq!(problem["instance"], "/text");
+
+        let response = app.oneshot(Request::builder().uri("/ok").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap(), "fine");
+    }
+
+    #[test]
+    fn test_reserved_members() {
+        let problem = Problem::new(StatusCode::CONFLICT).detail("clone").extension("status", 200).extension("reason", "x");
+        let body = problem.to_json();
+        assert_eq!(body["status"], 409);
+        assert_eq!(body["detail"], "clone");
+        assert_eq!(body["reason"], "x");
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use openssl::{
+    asn1::Asn1Time,
+    hash::MessageDigest,
+    ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus},
+    stack::Stack,
+    x509::{store::X509StoreBuilder, CrlStatus, X509Crl, X509Ref, X509VerifyResult, X509},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+/// Leeway for the clocks of OCSP responders
+const OCSP_CLOCK_SKEW_SECS: u32 = 300;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RevocationMode {
+    #[default]
+    Off,
+    /// Revoked certificates are rejected, certificates whose status cannot be determined are accepted
+    SoftFail,
+    /// Only certificates that are known not to be revoked are accepted
+    HardFail,
+}
+
+impl FromStr for RevocationMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(RevocationMode::Off),
+            "soft-fail" => Ok(RevocationMode::SoftFail),
+            "hard-fail" => Ok(RevocationMode::HardFail),
+            _ => Err(format!("unknown revocation mode {}, expected off, soft-fail or hard-fail", mode)),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RevocationError {
+    #[error("certificate is revoked")]
+    Revoked,
+    #[error("revocation status is unavailable: {0}")]
+    Unavailable(String),
+}
+
+fn unavailable(err: impl std::fmt::Display) -> RevocationError {
+    RevocationError::Unavailable(err.to_string())
+}
+
+struct CachedCrl {
+    crl: Arc<X509Crl>,
+    fetched: Instant,
+}
+
+/// Checks certificates against OCSP responders and CRL distribution points named in them.
+/// Certificates naming neither are not revocable and always pass. CRLs are fetched on first use, cached per URL
+/// and refreshed in the background, or on use once their next update passed. OCSP responses are not cached.
+pub struct RevocationChecker {
+    mode: RevocationMode,
+    client: reqwest::Client,
+    crls: RwLock<HashMap<String, CachedCrl>>,
+}
+
+impl RevocationChecker {
+    pub fn new(mode: RevocationMode, client: reqwest::Client) -> Self {
+        Self {
+            mode,
+            client,
+            crls: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Checks `cert`, whose issuer has to be among `issuers`
+    pub async fn check(&self, cert: &X509Ref, issuers: &[X509]) -> Result<(), RevocationError> {
+        if self.mode == RevocationMode::Off {
+            return Ok(());
+        }
+
+        match self.status(cert, issuers).await {
+            Err(RevocationError::Unavailable(reason)) if self.mode == RevocationMode::SoftFail => {
+                event!(target: "Revocation", Level::WARN, "Accepting {:?} without revocation status: {}", cert.subject_name(), reason);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    async fn status(&self, cert: &X509Ref, issuers: &[X509]) -> Result<(), RevocationError> {
+        let responders = ocsp_responders(cert);
+        let distribution_points = crl_distribution_points(cert);
+        if responders.is_empty() && distribution_points.is_empty() {
+            return Ok(());
+        }
+
+        let issuer = find_issuer(cert, issuers).ok_or(unavailable("issuer of the certificate is unknown"))?;
+
+        // OCSP first, it is more current than a CRL
+        let mut last_error = None;
+        for responder in &responders {
+            match self.ocsp_status(cert, issuer, responder).await {
+                Err(RevocationError::Unavailable(reason)) => {
+                    event!(target: "Revocation", Level::DEBUG, "OCSP responder {} failed: {}", responder, reason);
+                    last_error = Some(RevocationError::Unavailable(reason));
+                }
+                result => return result,
+            }
+        }
+        for url in &distribution_points {
+            match self.crl_status(cert, issuer, url).await {
+                Err(RevocationError::Unavailable(reason)) => {
+                    event!(target: "Revocation", Level::DEBUG, "CRL {} failed: {}", url, reason);
+                    last_error = Some(RevocationError::Unavailable(reason));
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error.unwrap_or(unavailable("no revocation source answered")))
+    }
+
+    async fn ocsp_status(&self, cert: &X509Ref, issuer: &X509, responder: &str) -> Result<(), RevocationError> {
+        let cert_id = || OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).map_err(unavailable);
+
+        let mut request = OcspRequest::new().map_err(unavailable)?;
+        request.add_id(cert_id()?).map_err(unavailable)?;
+        let request = request.to_der().map_err(unavailable)?;
+
+        let response = self
+            .client
+            .post(responder)
+            .header(reqwest::header::CONTENT_TYPE, "application/ocsp-request")
+            .body(request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unavailable)?
+            .bytes()
+            .await
+            .map_err(unavailable)?;
+
+        let response = OcspResponse::from_der(&response).map_err(unavailable)?;
+        if response.status() != OcspResponseStatus::SUCCESSFUL {
+            return Err(unavailable(format!("OCSP responder answered {:?}", response.status())));
+        }
+
+        let basic = response.basic().map_err(unavailable)?;
+        let mut store = X509StoreBuilder::new().map_err(unavailable)?;
+        store.add_cert(issuer.clone()).map_err(unavailable)?;
+        let mut signers = Stack::new().map_err(unavailable)?;
+        signers.push(issuer.clone()).map_err(unavailable)?;
+        basic
+            .verify(&signers, &store.build(), OcspFlag::TRUST_OTHER)
+            .map_err(|_| unavailable("OCSP response is not signed by the issuer"))?;
+
+        let cert_id = cert_id()?;
+        let status = basic.find_status(&cert_id).ok_or(unavailable("OCSP response lacks the certificate"))?;
+        status
+            .check_validity(OCSP_CLOCK_SKEW_SECS, None)
+            .map_err(|_| unavailable("OCSP response is outdated"))?;
+
+        match status.status {
+            OcspCertStatus::GOOD => Ok(()),
+            OcspCertStatus::REVOKED => Err(RevocationError::Revoked),
+            _ => Err(unavailable("OCSP responder does not know the certificate")),
+        }
+    }
+
+    async fn crl_status(&self, cert: &X509Ref, issuer: &X509, url: &str) -> Result<(), RevocationError> {
+        let cached = self.crls.read().await.get(url).filter(|cached| is_current(&cached.crl)).map(|cached| cached.crl.clone());
+        let crl = match cached {
+            Some(crl) => crl,
+            None => self.refresh_crl(url).await?,
+        };
+        // a distribution point that only serves outdated CRLs gives no status
+        if !is_current(&crl) {
+            return Err(unavailable("CRL is past its next update"));
+        }
+
+        let issuer_key = issuer.public_key().map_err(unavailable)?;
+        if !crl.verify(&issuer_key).map_err(unavailable)? {
+            return Err(unavailable("CRL is not signed by the issuer"));
+        }
+
+        match crl.get_by_cert(&cert.to_owned()) {
+            CrlStatus::NotRevoked | CrlStatus::RemoveFromCrl(_) => Ok(()),
+            CrlStatus::Revoked(_) => Err(RevocationError::Revoked),
+        }
+    }
+
+    async fn refresh_crl(&self, url: &str) -> Result<Arc<X509Crl>, RevocationError> {
+        event!(target: "Revocation", Level::DEBUG, "Fetching CRL {}", url);
+
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unavailable)?
+            .bytes()
+            .await
+            .map_err(unavailable)?;
+        let crl = Arc::new(X509Crl::from_der(&body).or_else(|_| X509Crl::from_pem(&body)).map_err(unavailable)?);
+
+        self.crls.write().await.insert(
+            url.to_string(),
+            CachedCrl {
+                crl: crl.clone(),
+                fetched: Instant::now(),
+            },
+        );
+        Ok(crl)
+    }
+
+    /// Refetches cached CRLs older than `interval`, so requests rarely wait for a download
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        if self.mode == RevocationMode::Off {
+            return;
+        }
+
+        // checking once per interval would let CRLs fetched in between age up to twice the interval
+        let tick = interval.min(Duration::from_secs(60));
+        let checker = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(tick).await;
+
+                let stale: Vec<String> = checker
+                    .crls
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(_, cached)| cached.fetched.elapsed() >= interval)
+                    .map(|(url, _)| url.clone())
+                    .collect();
+                for url in stale {
+                    // the previous CRL stays in use until it expires
+                    if let Err(err) = checker.refresh_crl(&url).await {
+                        event!(target: "Revocation", Level::WARN, "Refreshing CRL {} failed: {}", url, err);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Whether the next update of `crl` is still ahead, CRLs without one are always current
+fn is_current(crl: &X509Crl) -> bool {
+    let now = Asn1Time::days_from_now(0).ok();
+    match (crl.next_update(), now) {
+        (Some(next_update), Some(now)) => next_update > now,
+        _ => true,
+    }
+}
+
+fn find_issuer<'a>(cert: &X509Ref, issuers: &'a [X509]) -> Option<&'a X509> {
+    issuers.iter().find(|issuer| {
+        issuer.issued(cert) == X509VerifyResult::OK
+            && issuer.public_key().and_then(|key| cert.verify(&key)).unwrap_or(false)
+    })
+}
+
+fn ocsp_responders(cert: &X509Ref) -> Vec<String> {
+    cert.ocsp_responders()
+        .map(|responders| responders.iter().map(|responder| responder.to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn crl_distribution_points(cert: &X509Ref) -> Vec<String> {
+    let Some(points) = cert.crl_distribution_points() else {
+        return vec![];
+    };
+
+    points
+        .iter()
+        .filter_map(|point| point.distpoint())
+        .filter_map(|name| name.fullname())
+        .flat_map(|names| names.iter().filter_map(|name| name.uri().map(|uri| uri.to_string())))
+        .filter(|uri| uri.starts_with("http://") || uri.starts_with("https://"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use axum::{routing::get, Router};
+    use der::{
+        asn1::{BitString, GeneralizedTime, ObjectIdentifier},
+        Decode, Encode,
+    };
+    use example_certs::OpensslTestCerts;
+    use openssl::{
+        bn::BigNum,
+        nid::Nid,
+        sign::Signer,
+        x509::{X509Builder, X509Extension, X509NameBuilder},
+    };
+    use x509_cert::{
+        crl::{CertificateList, TbsCertList},
+        name::Name,
+        spki::AlgorithmIdentifierOwned,
+        time::Time,
+        Version,
+    };
+
+    use super::*;
+
+    /// EE certificate of the vendor CA naming the CRL distribution point `url`
+    fn cert_with_crl(certs: &OpensslTestCerts, url: &str) -> X509 {
+        let (ca_cert, ca_key) = &certs.vendor_ca;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "revocable").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(42).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(ca_cert.subject_name()).unwrap();
+        builder.set_pubkey(ca_key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        #[allow(deprecated)]
+        let distribution_point = X509Extension::new_nid(
+            None,
+            Some(&builder.x509v3_context(Some(ca_cert), None)),
+            Nid::CRL_DISTRIBUTION_POINTS,
+            &format!("URI:{}", url),
+        )
+        .unwrap();
+        builder.append_extension(distribution_point).unwrap();
+        builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[tokio::test]
+    async fn test_revocation_modes() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let cert = cert_with_crl(&certs, "http://127.0.0.1:1/vendor.crl");
+        let issuers = [certs.vendor_ca.0.clone()];
+
+        assert_eq!(crl_distribution_points(&cert), vec!["http://127.0.0.1:1/vendor.crl".to_string()]);
+        assert!(find_issuer(&cert, &issuers).is_some());
+        assert!(find_issuer(&cert, std::slice::from_ref(&certs.registrar_ca.0)).is_none());
+
+        let client = reqwest::Client::new();
+        let off = RevocationChecker::new(RevocationMode::Off, client.clone());
+        let soft_fail = RevocationChecker::new(RevocationMode::SoftFail, client.clone());
+        let hard_fail = RevocationChecker::new(RevocationMode::HardFail, client);
+
+        assert!(off.check(&cert, &issuers).await.is_ok());
+        assert!(soft_fail.check(&cert, &issuers).await.is_ok());
+        assert!(matches!(hard_fail.check(&cert, &issuers).await, Err(RevocationError::Unavailable(_))));
+        assert!(matches!(hard_fail.check(&cert, &[]).await, Err(RevocationError::Unavailable(_))));
+
+        // the test pledge names no revocation source, so there is nothing to check
+        assert!(hard_fail.check(&certs.pledge.0, &[]).await.is_ok());
+    }
+
+    /// Empty CRL of the vendor CA with the given next update
+    fn crl(certs: &OpensslTestCerts, next_update: SystemTime) -> Vec<u8> {
+        let (ca_cert, ca_key) = &certs.vendor_ca;
+        let time = |time| Time::GeneralTime(GeneralizedTime::from_system_time(time).unwrap());
+        let signature = AlgorithmIdentifierOwned {
+            oid: ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2"),
+            parameters: None,
+        };
+
+        let tbs_cert_list = TbsCertList {
+            version: Version::V2,
+            signature: signature.clone(),
+            issuer: Name::from_der(&ca_cert.subject_name().to_der().unwrap()).unwrap(),
+            this_update: time(next_update - Duration::from_secs(3600)),
+            next_update: Some(time(next_update)),
+            revoked_certificates: None,
+            crl_extensions: None,
+        };
+        let mut signer = Signer::new(MessageDigest::sha256(), ca_key).unwrap();
+        let signed = signer.sign_oneshot_to_vec(&tbs_cert_list.to_der().unwrap()).unwrap();
+        CertificateList {
+            tbs_cert_list,
+            signature_algorithm: signature,
+            signature: BitString::from_bytes(&signed).unwrap(),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejects_outdated_crl() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let issuers = [certs.vendor_ca.0.clone()];
+        let current = crl(&certs, SystemTime::now() + Duration::from_secs(3600));
+        let outdated = crl(&certs, SystemTime::now() - Duration::from_secs(60));
+
+        let app = Router::new()
+            .route("/current.crl", get(move || async move { current }))
+            .route("/outdated.crl", get(move || async move { outdated }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let hard_fail = RevocationChecker::new(RevocationMode::HardFail, reqwest::Client::new());
+        let current = cert_with_crl(&certs, &format!("http://{}/current.crl", address));
+        let outdated = cert_with_crl(&certs, &format!("http://{}/outdated.crl", address));
+
+        assert!(hard_fail.check(&current, &issuers).await.is_ok());
+        assert!(matches!(hard_fail.check(&outdated, &issuers).await, Err(RevocationError::Unavailable(_))));
+    }
+}
@@ -0,0 +1,137 @@
+use std::str::FromStr;
+
+use regex::Regex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SerialPatternError {
+    #[error("Empty serial-number pattern")]
+    Empty,
+    #[error("Invalid range {0:?}, both bounds must have the same length and the start must not be after the end")]
+    InvalidRange(String),
+    #[error("Invalid regular expression in {0:?}: {1}")]
+    InvalidRegex(String, regex::Error),
+}
+
+/// Matches device serial-numbers in MASA and registrar policies.
+///
+/// - `00-D0-E5-F2-00-02` matches the serial-number exactly
+/// - `00-D0-E5-*` matches all serial-numbers starting with `00-D0-E5-`
+/// - `00-D0-E5-F2-00-00..00-D0-E5-F2-00-FF` matches the serial-numbers of the same length between both bounds, inclusive.
+///   The bounds are compared character by character, so they should have a fixed width and the same case.
+/// - `re:^00-D0-E5-F2-00-[0-9]{2}$` matches the regular expression, which is not anchored unless written so
+#[derive(Debug, Clone)]
+pub enum SerialPattern {
+    Exact(String),
+    Prefix(String),
+    Range(String, String),
+    Regex(Regex),
+}
+
+impl SerialPattern {
+    pub fn matches(&self, serial_number: &str) -> bool {
+        match self {
+            SerialPattern::Exact(serial) => serial == serial_number,
+            SerialPattern::Prefix(prefix) => serial_number.starts_with(prefix.as_str()),
+            SerialPattern::Range(start, end) => {
+                serial_number.chars().count() == start.chars().count() && start.as_str() <= serial_number && serial_number <= end.as_str()
+            }
+            SerialPattern::Regex(regex) => regex.is_match(serial_number),
+        }
+    }
+
+    /// Used to pick the most specific of several matching patterns, exact serial-numbers win,
+    /// prefixes and ranges count their fixed leading characters and regular expressions come last
+    pub fn specificity(&self) -> usize {
+        match self {
+            SerialPattern::Exact(_) => usize::MAX,
+            SerialPattern::Prefix(prefix) => prefix.chars().count(),
+            SerialPattern::Range(start, end) => start.chars().zip(end.chars()).take_while(|(a, b)| a == b).count(),
+            SerialPattern::Regex(_) => 0,
+        }
+    }
+}
+
+impl FromStr for SerialPattern {
+    type Err = SerialPatternError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        if pattern.is_empty() {
+            return Err(SerialPatternError::Empty);
+        }
+
+        if let Some(expression) = pattern.strip_prefix("re:") {
+            return Regex::new(expression)
+                .map(SerialPattern::Regex)
+                .map_err(|err| SerialPatternError::InvalidRegex(pattern.to_string(), err));
+        }
+
+        if let Some((start, end)) = pattern.split_once("..") {
+            if start.is_empty() || start.chars().count() != end.chars().count() || start > end {
+                return Err(SerialPatternError::InvalidRange(pattern.to_string()));
+            }
+            return Ok(SerialPattern::Range(start.to_string(), end.to_string()));
+        }
+
+        match pattern.strip_suffix('*') {
+            Some(prefix) => Ok(SerialPattern::Prefix(prefix.to_string())),
+            None => Ok(SerialPattern::Exact(pattern.to_string())),
+        }
+    }
+}
+
+/// Parses all patterns, failing on the first invalid one
+pub fn parse_serial_patterns<'a>(patterns: impl IntoIterator<Item = &'a String>) -> Result<Vec<SerialPattern>, SerialPatternError> {
+    patterns.into_iter().map(|pattern| pattern.parse()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_syntax() {
+        let exact: SerialPattern = "00-D0-E5-F2-00-02".parse().unwrap();
+        assert!(exact.matches("00-D0-E5-F2-00-02"));
+        assert!(!exact.matches("00-D0-E5-F2-00-03"));
+
+        let prefix: SerialPattern = "00-D0-E5-*".parse().unwrap();
+        assert!(prefix.matches("00-D0-E5-F2-00-03"));
+        assert!(!prefix.matches("00-D0-AA-F2-00-03"));
+
+        let range: SerialPattern = "00-D0-E5-F2-00-00..00-D0-E5-F2-00-0F".parse().unwrap();
+        assert!(range.matches("00-D0-E5-F2-00-00"));
+        assert!(range.matches("00-D0-E5-F2-00-0F"));
+        assert!(!range.matches("00-D0-E5-F2-00-10"));
+        assert!(!range.matches("00-D0-E5-F2-00-0"));
+        assert_eq!(range.specificity(), "00-D0-E5-F2-00-0".len());
+
+        let regex: SerialPattern = "re:^00-D0-E5-F2-00-0[2-4]$".parse().unwrap();
+        assert!(regex.matches("00-D0-E5-F2-00-03"));
+        assert!(!regex.matches("00-D0-E5-F2-00-05"));
+    }
+
+    #[test]
+    fn test_rejects_invalid_patterns() {
+        assert!(matches!("".parse::<SerialPattern>(), Err(SerialPatternError::Empty)));
+        assert!(matches!("re:00-(".parse::<SerialPattern>(), Err(SerialPatternError::InvalidRegex(..))));
+        assert!(matches!("00-0F..00-00".parse::<SerialPattern>(), Err(SerialPatternError::InvalidRange(_))));
+        assert!(matches!("00-00..00-0FF".parse::<SerialPattern>(), Err(SerialPatternError::InvalidRange(_))));
+        assert!(matches!("..".parse::<SerialPattern>(), Err(SerialPatternError::InvalidRange(_))));
+    }
+
+    #[test]
+    fn test_utf8_serial_numbers() {
+        let range: SerialPattern = "Gerät-00..Gerät-FF".parse().unwrap();
+        assert!(range.matches("Gerät-0A"));
+        assert!(!range.matches("Gerat-0A"));
+        assert!(!range.matches("Gerät-0"));
+
+        // lengths and specificity count characters, not bytes
+        let prefix: SerialPattern = "Gerät-*".parse().unwrap();
+        assert!(prefix.matches("Gerät-0A"));
+        assert_eq!(prefix.specificity(), range.specificity());
+        assert!("ä0..a0".parse::<SerialPattern>().is_err());
+        assert!("a00..ä0".parse::<SerialPattern>().is_err());
+    }
+}
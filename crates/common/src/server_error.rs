@@ -1,10 +1,10 @@
 
-use core::error;
-use std::backtrace::Backtrace;
 
-use axum::{http::header::ToStrError, response::{IntoResponse, Response}};
+use axum::{http::{header::ToStrError, StatusCode}, response::{IntoResponse, Response}};
 use thiserror::Error;
 use tracing::event;
+
+use crate::problem::Problem;
 #[derive(Error, Debug)]
 pub enum ServerError {
     #[error("Bad Request")]
@@ -16,6 +16,9 @@ pub enum ServerError {
     #[error("Bad Response - Reason: {0}")]
     BadResponse(String),
 
+    #[error("Not Found - Reason: {0}")]
+    NotFound(String),
+
 
     #[error(transparent)]
     OpensslError {
@@ -26,6 +29,9 @@ pub enum ServerError {
     #[error(transparent)]
     JWSError(#[from] josekit::JoseError),
 
+    #[error(transparent)]
+    Asn1Error(#[from] crate::asn1::Asn1Error),
+
     //#[error("Internal crate error. Please report this issue.")]
     #[error(transparent)]
     InternalError{
@@ -37,6 +43,66 @@ pub enum ServerError {
     #[error(transparent)]
     BRSKIError(#[from] brski_prm_artifacts::error::BRSKIPRMError),
 
+    #[error("Pledge {serial_number} is blocked - Reason: {reason}")]
+    PledgeBlocked {
+        serial_number: String,
+        reason: String,
+    },
+
+    #[error("Voucher requests for pledge {serial_number} await approval by the manufacturer")]
+    ApprovalPending {
+        serial_number: String,
+    },
+
+    #[error("Voucher request for pledge {serial_number} violates the voucher policy - Reason: {reason}")]
+    PolicyViolation {
+        serial_number: String,
+        reason: String,
+    },
+
+    #[error("Registrar domain does not own pledge {serial_number} - Reason: {reason}")]
+    OwnershipMismatch {
+        serial_number: String,
+        reason: String,
+    },
+
+    #[error("Onboarding of new pledge {serial_number} is closed - Reason: {reason}")]
+    OnboardingClosed {
+        serial_number: String,
+        reason: String,
+    },
+
+    #[error("Pledge {serial_number} does not match its label - Reason: {reason}")]
+    IdentificationFailed {
+        serial_number: String,
+        reason: String,
+    },
+
+    #[error("Client is not authenticated - Reason: {reason}")]
+    Unauthenticated {
+        reason: String,
+    },
+
+    #[error("Revocation check of {subject} failed - Reason: {reason}")]
+    RevocationCheckFailed {
+        subject: String,
+        reason: String,
+    },
+
+    #[error("Required time-stamp could not be obtained - Reason: {reason}")]
+    TimestampUnavailable {
+        reason: String,
+    },
+
+    #[error("{artifact} exceeds the limit of {limit} bytes")]
+    PayloadTooLarge {
+        artifact: String,
+        limit: usize,
+    },
+
+    #[error("Too many artifacts are waiting for verification")]
+    Overloaded,
+
     #[error("Not Acceptible")]
     NotAcceptible,
 
@@ -59,27 +125,109 @@ pub enum ServerError {
     SerdeError(#[from] serde_json::Error),
 }
 
+impl ServerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::OpensslError { .. } => StatusCode::BAD_REQUEST,
+            Self::JWSError(_) => StatusCode::BAD_REQUEST,
+            Self::Asn1Error(_) => StatusCode::BAD_REQUEST,
+            Self::InternalError{..} => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotAcceptible => StatusCode::NOT_ACCEPTABLE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::BRSKIError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::BadResponse(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ReqwestError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::BadRequestWithReason(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::ToStrError(_) => StatusCode::BAD_REQUEST,
+            Self::SerdeError(_) => StatusCode::BAD_REQUEST,
+            Self::PledgeBlocked { .. } => StatusCode::FORBIDDEN,
+            Self::ApprovalPending { .. } => StatusCode::FORBIDDEN,
+            Self::PolicyViolation { .. } => StatusCode::FORBIDDEN,
+            Self::OwnershipMismatch { .. } => StatusCode::FORBIDDEN,
+            Self::OnboardingClosed { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::IdentificationFailed { .. } => StatusCode::CONFLICT,
+            Self::Unauthenticated { .. } => StatusCode::FORBIDDEN,
+            Self::RevocationCheckFailed { .. } => StatusCode::FORBIDDEN,
+            Self::TimestampUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// RFC 9457 problem details of the error. Errors the client can act on carry their `error` code and context as
+    /// extension members, internal errors are not detailed.
+    pub fn problem(&self) -> Problem {
+        let problem = Problem::new(self.status());
+        match self {
+            // blocked pledges get a machine readable body, so agents and telemetry can tell them apart from other failures
+            Self::PledgeBlocked { serial_number, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "pledge-blocked")
+                .extension("serial-number", serial_number.as_str())
+                .extension("reason", reason.as_str()),
+            // a closed registrar is temporary, agents should retry the pledge later instead of giving up on it
+            Self::OnboardingClosed { serial_number, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "onboarding-closed")
+                .extension("serial-number", serial_number.as_str())
+                .extension("reason", reason.as_str()),
+            Self::ApprovalPending { serial_number } => problem
+                .detail(self.to_string())
+                .extension("error", "approval-pending")
+                .extension("serial-number", serial_number.as_str()),
+            Self::PolicyViolation { serial_number, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "policy-violation")
+                .extension("serial-number", serial_number.as_str())
+                .extension("reason", reason.as_str()),
+            // so registrars can show why the manufacturer refuses the voucher
+            Self::OwnershipMismatch { serial_number, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "ownership-mismatch")
+                .extension("serial-number", serial_number.as_str())
+                .extension("reason", reason.as_str()),
+            // the installer has to set the device aside, retrying will not help
+            Self::IdentificationFailed { serial_number, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "identification-failed")
+                .extension("serial-number", serial_number.as_str())
+                .extension("reason", reason.as_str()),
+            // the client has to come back over an authenticated channel, the same request will not succeed
+            Self::Unauthenticated { reason } => problem
+                .detail(self.to_string())
+                .extension("error", "unauthenticated")
+                .extension("reason", reason.as_str()),
+            Self::RevocationCheckFailed { subject, reason } => problem
+                .detail(self.to_string())
+                .extension("error", "revocation-check-failed")
+                .extension("subject", subject.as_str())
+                .extension("reason", reason.as_str()),
+            // vouchers are not issued without their required time-stamp, the registrar may retry once the TSA is back
+            Self::TimestampUnavailable { reason } => problem
+                .detail(self.to_string())
+                .extension("error", "timestamp-unavailable")
+                .extension("reason", reason.as_str()),
+            Self::PayloadTooLarge { artifact, limit } => problem
+                .detail(self.to_string())
+                .extension("error", "payload-too-large")
+                .extension("artifact", artifact.as_str())
+                .extension("limit", *limit),
+            // a burst of onboarding traffic, the request may be repeated shortly
+            Self::Overloaded => problem.detail(self.to_string()).extension("error", "overloaded"),
+            _ if self.status().is_client_error() => problem.detail(self.to_string()),
+            _ => problem,
+        }
+    }
+}
+
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
 
         event!(tracing::Level::ERROR, error = %self);
 
-        let status = match self {
-            Self::BadRequest => axum::http::StatusCode::BAD_REQUEST,
-            Self::OpensslError { .. } => axum::http::StatusCode::BAD_REQUEST,
-            Self::JWSError(_) => axum::http::StatusCode::BAD_REQUEST,
-            Self::InternalError{..} => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::NotAcceptible => axum::http::StatusCode::NOT_ACCEPTABLE,
-            Self::UnsupportedMediaType => axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
-            Self::BRSKIError(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::BadResponse(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::ReqwestError { .. } => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::IoError(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::BadRequestWithReason(_) => axum::http::StatusCode::BAD_REQUEST,
-            Self::ToStrError(_) => axum::http::StatusCode::BAD_REQUEST,
-            Self::SerdeError(_) => axum::http::StatusCode::BAD_REQUEST,
-        };
-
-        status.into_response()
+        self.problem().into_response()
     }
 }
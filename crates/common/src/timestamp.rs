@@ -0,0 +1,338 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use cmpv2::status::PkiStatus;
+use der::{
+    asn1::{Int, ObjectIdentifier, OctetString},
+    Decode, Encode, Tag, Tagged,
+};
+use openssl::{
+    cms::{CMSOptions, CmsContentInfo},
+    x509::{
+        store::{X509Store, X509StoreBuilder},
+        X509PurposeId, X509,
+    },
+};
+use thiserror::Error;
+use tracing::{event, Level};
+use x509_cert::spki::AlgorithmIdentifierOwned;
+
+use crate::asn1::{self, MessageImprint, TimeStampReq, TimeStampResp, TstInfo, ID_CT_TST_INFO, ID_SHA256};
+
+#[derive(Error, Debug)]
+pub enum TimestampError {
+    #[error("time-stamp authority is unavailable: {0}")]
+    Unavailable(String),
+    #[error("time-stamp authority rejected the request: {0}")]
+    Rejected(String),
+    #[error("invalid time-stamp token: {0}")]
+    Invalid(String),
+}
+
+fn unavailable(err: impl std::fmt::Display) -> TimestampError {
+    TimestampError::Unavailable(err.to_string())
+}
+
+fn invalid(err: impl std::fmt::Display) -> TimestampError {
+    TimestampError::Invalid(err.to_string())
+}
+
+/// RFC 3161 time-stamp token, a CMS SignedData over a TSTInfo
+#[derive(Debug, Clone)]
+pub struct TimestampToken {
+    pub der: Vec<u8>,
+    /// genTime of the TSTInfo
+    pub time: DateTime<Utc>,
+}
+
+/// Obtains RFC 3161 time-stamp tokens over SHA-256 digests from a time-stamp authority (TSA). Tokens are only
+/// returned once they are verified against the TSA CA certificates and bound to the requested digest.
+pub struct TimestampClient {
+    url: String,
+    policy: Option<ObjectIdentifier>,
+    store: X509Store,
+    client: reqwest::Client,
+}
+
+impl TimestampClient {
+    pub fn new(url: String, policy: Option<String>, tsa_ca_certificates: &[X509], client: reqwest::Client) -> Result<Self, TimestampError> {
+        let mut store = X509StoreBuilder::new().map_err(invalid)?;
+        for cert in tsa_ca_certificates {
+            store.add_cert(cert.clone()).map_err(invalid)?;
+        }
+        // the TSA certificate needs the critical timeStamping extended key usage, RFC 3161 Section 2.3
+        store.set_purpose(X509PurposeId::TIMESTAMP_SIGN).map_err(invalid)?;
+
+        let policy = policy
+            .map(|policy| ObjectIdentifier::new(&policy).map_err(|_| invalid(format!("{} is not an OID", policy))))
+            .transpose()?;
+
+        Ok(Self {
+            url,
+            policy,
+            store: store.build(),
+            client,
+        })
+    }
+
+    /// Time-stamps the SHA-256 `digest`
+    pub async fn timestamp(&self, digest: &[u8]) -> Result<TimestampToken, TimestampError> {
+        let mut nonce = [0u8; 8];
+        openssl::rand::rand_bytes(&mut nonce).map_err(unavailable)?;
+        // positive and minimally encoded
+        nonce[0] = (nonce[0] & 0x7f) | 0x40;
+
+        let request = encode_request(digest, self.policy, &nonce).map_err(invalid)?;
+
+        event!(target: "Timestamp", Level::DEBUG, "Requesting time-stamp from {}", self.url);
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/timestamp-query")
+            .body(request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unavailable)?
+            .bytes()
+            .await
+            .map_err(unavailable)?;
+
+        let token = parse_response(&response)?;
+        let time = self.verify(&token, digest, Some(&nonce))?;
+        Ok(TimestampToken { der: token, time })
+    }
+
+    /// Verifies the signature of `token` and that it covers `digest`, returns its time
+    pub fn verify(&self, token: &[u8], digest: &[u8], nonce: Option<&[u8]>) -> Result<DateTime<Utc>, TimestampError> {
+        let signed_data = asn1::decode_signed_data(token).map_err(|_| invalid("token is not a CMS SignedData"))?;
+        if signed_data.encap_content_info.econtent_type != ID_CT_TST_INFO {
+            return Err(invalid("token does not carry a TSTInfo"));
+        }
+
+        let mut cms = CmsContentInfo::from_der(token).map_err(invalid)?;
+        let mut tst_info = Vec::new();
+        cms.verify(None, Some(&self.store), None, Some(&mut tst_info), CMSOptions::BINARY)
+            .map_err(|err| invalid(format!("signature verification failed: {}", err)))?;
+
+        let tst_info = TstInfo::from_der(&tst_info).map_err(|_| invalid("malformed TSTInfo"))?;
+        let message_imprint = &tst_info.message_imprint;
+        if message_imprint.hash_algorithm.oid != ID_SHA256 || message_imprint.hashed_message.as_bytes() != digest {
+            return Err(invalid("token does not cover the digest"));
+        }
+        if nonce.is_some_and(|nonce| tst_info.nonce.as_ref().map(Int::as_bytes) != Some(nonce)) {
+            return Err(invalid("nonce does not match the request"));
+        }
+        if self.policy.is_some_and(|policy| tst_info.policy != policy) {
+            return Err(invalid("token was issued under another policy"));
+        }
+
+        if tst_info.gen_time.tag() != Tag::GeneralizedTime {
+            return Err(invalid("malformed genTime"));
+        }
+        std::str::from_utf8(tst_info.gen_time.value())
+            .ok()
+            .and_then(|time| NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S%.fZ").ok())
+            .map(|time| time.and_utc())
+            .ok_or(invalid("malformed genTime"))
+    }
+}
+
+fn message_imprint(digest: &[u8]) -> Result<MessageImprint, der::Error> {
+    Ok(MessageImprint {
+        hash_algorithm: AlgorithmIdentifierOwned { oid: ID_SHA256, parameters: None },
+        hashed_message: OctetString::new(digest)?,
+    })
+}
+
+/// TimeStampReq, RFC 3161 Section 2.4.1
+fn encode_request(digest: &[u8], policy: Option<ObjectIdentifier>, nonce: &[u8]) -> Result<Vec<u8>, der::Error> {
+    TimeStampReq {
+        version: 1,
+        message_imprint: message_imprint(digest)?,
+        req_policy: policy,
+        nonce: Some(Int::new(nonce)?),
+        // so the token carries the TSA certificate
+        cert_req: true,
+    }
+    .to_der()
+}
+
+/// The token of a TimeStampResp, RFC 3161 Section 2.4.2
+fn parse_response(response: &[u8]) -> Result<Vec<u8>, TimestampError> {
+    let response = TimeStampResp::from_der(response).map_err(|_| invalid("malformed TimeStampResp"))?;
+
+    match response.status.status {
+        PkiStatus::Accepted | PkiStatus::GrantedWithMods => {}
+        status => {
+            let reason = response
+                .status
+                .status_string
+                .and_then(|texts| texts.first().map(|text| text.to_string()))
+                .unwrap_or_else(|| format!("status {:?}", status));
+            return Err(TimestampError::Rejected(reason));
+        }
+    }
+
+    response
+        .time_stamp_token
+        .ok_or(invalid("granted response lacks the token"))?
+        .to_der()
+        .map_err(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use cmpv2::status::PkiStatusInfo;
+    use cms::content_info::ContentInfo;
+    use der::asn1::{Any, Utf8StringRef};
+    use example_certs::OpensslTestCerts;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        x509::{X509Builder, X509Extension, X509NameBuilder},
+    };
+
+    use super::*;
+
+    const POLICY: &str = "1.3.6.1.4.1.4146.2.3";
+
+    /// TSA certificate of the vendor CA with the critical timeStamping extended key usage
+    fn tsa_cert(certs: &OpensslTestCerts) -> (X509, PKey<Private>) {
+        let (ca_cert, ca_key) = &certs.vendor_ca;
+        let (_, key) = &certs.vendor;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "tsa").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&BigNum::from_u32(3161).unwrap().to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(ca_cert.subject_name()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder
+            .append_extension(openssl::x509::extension::ExtendedKeyUsage::new().critical().time_stamping().build().unwrap())
+            .unwrap();
+        #[allow(deprecated)]
+        let key_usage = X509Extension::new_nid(None, None, Nid::KEY_USAGE, "critical,digitalSignature").unwrap();
+        builder.append_extension(key_usage).unwrap();
+        builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key.clone())
+    }
+
+    /// Granted TimeStampResp with a token signed by the TSA certificate
+    fn response(tsa: &(X509, PKey<Private>), digest: &[u8], policy: &str, nonce: &[u8]) -> Vec<u8> {
+        let (cert, key) = tsa;
+        let tst_info = TstInfo {
+            version: 1,
+            policy: ObjectIdentifier::new(policy).unwrap(),
+            message_imprint: message_imprint(digest).unwrap(),
+            serial_number: Int::new(&[7]).unwrap(),
+            gen_time: Any::new(Tag::GeneralizedTime, b"20261017120000.5Z".as_slice()).unwrap(),
+            accuracy: None,
+            ordering: false,
+            nonce: Some(Int::new(nonce).unwrap()),
+            tsa: None,
+            extensions: None,
+        };
+        let token = asn1::signed_data(ID_CT_TST_INFO, &tst_info.to_der().unwrap(), cert, key).unwrap();
+
+        TimeStampResp {
+            status: status(PkiStatus::Accepted, None),
+            time_stamp_token: Some(ContentInfo::from_der(&token).unwrap()),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    fn status(status: PkiStatus, text: Option<&str>) -> PkiStatusInfo<'_> {
+        PkiStatusInfo {
+            status,
+            status_string: text.map(|text| vec![Utf8StringRef::new(text).unwrap()]),
+            fail_info: None,
+        }
+    }
+
+    fn client(certs: &OpensslTestCerts, policy: Option<&str>) -> TimestampClient {
+        TimestampClient::new(
+            "http://127.0.0.1:1/tsa".to_string(),
+            policy.map(str::to_string),
+            std::slice::from_ref(&certs.vendor_ca.0),
+            reqwest::Client::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_token() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let tsa = tsa_cert(&certs);
+        let digest = openssl::sha::sha256(b"voucher signature");
+        let nonce = [0x41, 2, 3, 4, 5, 6, 7, 8];
+
+        let token = parse_response(&response(&tsa, &digest, POLICY, &nonce)).unwrap();
+        let time = client(&certs, Some(POLICY)).verify(&token, &digest, Some(&nonce)).unwrap();
+        assert_eq!(time.to_rfc3339(), "2026-10-17T12:00:00.500+00:00");
+        assert!(client(&certs, None).verify(&token, &digest, None).is_ok());
+
+        let client = client(&certs, Some(POLICY));
+        assert!(matches!(client.verify(&token, &[0; 32], Some(&nonce)), Err(TimestampError::Invalid(_))));
+        assert!(matches!(client.verify(&token, &digest, Some(&[0x41; 8])), Err(TimestampError::Invalid(_))));
+
+        let other_policy = parse_response(&response(&tsa, &digest, "1.2.3.4", &nonce)).unwrap();
+        assert!(client.verify(&other_policy, &digest, Some(&nonce)).is_err());
+
+        // signed by a key that is not the TSA certificate's
+        let forged = parse_response(&response(&(tsa.0.clone(), certs.registrar.1.clone()), &digest, POLICY, &nonce)).unwrap();
+        assert!(client.verify(&forged, &digest, Some(&nonce)).is_err());
+
+        // the registrar certificate lacks the timeStamping extended key usage
+        let registrar = parse_response(&response(&certs.registrar, &digest, POLICY, &nonce)).unwrap();
+        let registrar_client = TimestampClient::new("http://127.0.0.1:1/tsa".to_string(), None, std::slice::from_ref(&certs.registrar_ca.0), reqwest::Client::new()).unwrap();
+        assert!(registrar_client.verify(&registrar, &digest, Some(&nonce)).is_err());
+    }
+
+    #[test]
+    fn test_rejected_response() {
+        let rejected = TimeStampResp {
+            status: status(PkiStatus::Rejection, Some("bad digest")),
+            time_stamp_token: None,
+        };
+        match parse_response(&rejected.to_der().unwrap()) {
+            Err(TimestampError::Rejected(reason)) => assert_eq!(reason, "bad digest"),
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let without_token = TimeStampResp {
+            status: status(PkiStatus::Accepted, None),
+            time_stamp_token: None,
+        };
+        assert!(matches!(parse_response(&without_token.to_der().unwrap()), Err(TimestampError::Invalid(_))));
+        assert!(parse_response(&[0x30, 0x05, 0x30]).is_err());
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let policy = ObjectIdentifier::new(POLICY).unwrap();
+        let request = encode_request(&[0xab; 32], Some(policy), &[0x41; 8]).unwrap();
+
+        let decoded = TimeStampReq::from_der(&request).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.message_imprint, message_imprint(&[0xab; 32]).unwrap());
+        assert_eq!(decoded.req_policy, Some(policy));
+        assert_eq!(decoded.nonce.unwrap().as_bytes(), [0x41; 8]);
+        assert!(decoded.cert_req);
+        assert!(request.ends_with(&[0x01, 0x01, 0xff]));
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_tsa() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        assert!(matches!(client(&certs, None).timestamp(&[0; 32]).await, Err(TimestampError::Unavailable(_))));
+    }
+}
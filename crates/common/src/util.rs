@@ -1,10 +1,32 @@
-use std::any;
 
-use axum::http::{header::{ACCEPT, CONTENT_TYPE}, HeaderName, HeaderValue};
-use brski_prm_artifacts::{content_type::{JOSE, JSON, JWS_VOUCHER, PKCS7}, jws::JWS};
+use axum::http::{header::{ACCEPT, CONTENT_TYPE}, HeaderName};
+use brski_prm_artifacts::content_type::{JOSE, JSON, JWS_VOUCHER, PKCS7, SUIT_ENVELOPE};
+use serde_json::{json, Value};
 
 use crate::server_error::ServerError;
 
+/// Lower case hex encoding, e.g. of digests and key identifiers
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes [`hex`], upper case digits are accepted as well
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+/// The 16 bytes of a UUID in its hyphenated form, e.g. `fa6b4a53-d5ad-5fdf-be9d-e663e4d41ffe`
+pub fn parse_uuid(uuid: &str) -> Option<Vec<u8>> {
+    let groups = uuid.split('-').map(str::len).collect::<Vec<_>>();
+    if groups != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    from_hex(&uuid.replace('-', ""))
+}
+
 pub fn is_json(content_type: &str) -> Result<(), ServerError> {
     match content_type {
         JSON => Ok(()),
@@ -37,3 +59,39 @@ pub fn is_pkcs7(content_type: &str) -> Result<(), ServerError> {
         _ => Err(ServerError::UnsupportedMediaType),
     }
 }
+
+/// Protected headers and payload of a JWS in compact, flattened or general JSON serialization, without verifying
+/// the signature. Meant for logs and captures of artifacts.
+pub fn decode_jws_claims(body: &str) -> Option<Value> {
+    let body = body.trim();
+
+    if let [protected, payload, _signature] = body.split('.').collect::<Vec<_>>()[..] {
+        return Some(json!({ "protected": [decode_json(protected)?], "payload": decode_json(payload)? }));
+    }
+
+    let jws: Value = serde_json::from_str(body).ok()?;
+    let payload = decode_json(jws.get("payload")?.as_str()?)?;
+    let protected = match jws.get("signatures") {
+        Some(Value::Array(signatures)) => signatures
+            .iter()
+            .filter_map(|signature| decode_json(signature.get("protected")?.as_str()?))
+            .collect(),
+        _ => vec![decode_json(jws.get("protected")?.as_str()?)?],
+    };
+    Some(json!({ "protected": protected, "payload": payload }))
+}
+
+fn decode_json(base64url: &str) -> Option<Value> {
+    let mut base64 = base64url.replace('-', "+").replace('_', "/");
+    while !base64.len().is_multiple_of(4) {
+        base64.push('=');
+    }
+    serde_json::from_slice(&openssl::base64::decode_block(&base64).ok()?).ok()
+}
+
+pub fn is_suit_envelope(content_type: &str) -> Result<(), ServerError> {
+    match content_type {
+        SUIT_ENVELOPE => Ok(()),
+        _ => Err(ServerError::UnsupportedMediaType),
+    }
+}
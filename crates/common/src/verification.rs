@@ -0,0 +1,116 @@
+//! Worker pool for the signature and certificate chain verification of the MASA and the registrar.
+//!
+//! Verifying a JWS or a chain blocks for milliseconds, so a burst of onboarding traffic verified on the Tokio workers
+//! stalls every other connection. [`VerificationPool::run`] moves the work to the blocking threads instead. At most
+//! `workers` verifications run at once and at most `queue_depth` wait for a worker, further ones are answered with
+//! `503 Service Unavailable` right away.
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::server_error::ServerError;
+
+/// Pool size, configured as e.g. `[masa.verification]`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct VerificationConfig {
+    /// Verifications running at the same time, the number of CPUs by default
+    pub workers: usize,
+    /// Verifications waiting for a worker
+    pub queue_depth: usize,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            workers: std::thread::available_parallelism().map_or(4, |cpus| cpus.get()),
+            queue_depth: 256,
+        }
+    }
+}
+
+impl VerificationConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.workers == 0 {
+            return Err(anyhow!("verification workers must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VerificationPool {
+    workers: Arc<Semaphore>,
+    /// Running and waiting verifications
+    admitted: Arc<Semaphore>,
+}
+
+impl VerificationPool {
+    pub fn new(config: &VerificationConfig) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(config.workers)),
+            admitted: Arc::new(Semaphore::new(config.workers + config.queue_depth)),
+        }
+    }
+
+    /// Runs `verify` on a worker once one is free. A verification keeps its worker until it finished, even if the
+    /// request was dropped in the meantime.
+    pub async fn run<T: Send + 'static>(&self, verify: impl FnOnce() -> T + Send + 'static) -> Result<T, ServerError> {
+        let admitted = Arc::clone(&self.admitted).try_acquire_owned().map_err(|_| ServerError::Overloaded)?;
+        let worker = Arc::clone(&self.workers).acquire_owned().await.expect("worker semaphore is never closed");
+
+        tokio::task::spawn_blocking(move || {
+            let _permits = (admitted, worker);
+            verify()
+        })
+        .await
+        .map_err(|err| anyhow!("verification failed: {}", err).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_beyond_queue_depth() {
+        let pool = VerificationPool::new(&VerificationConfig {
+            workers: 1,
+            queue_depth: 1,
+        });
+
+        let (release, blocked) = mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || blocked.recv().is_ok()).await }
+        });
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(matches!(pool.run(|| 3).await, Err(ServerError::Overloaded)));
+
+        release.send(()).unwrap();
+        assert!(running.await.unwrap().unwrap());
+        assert_eq!(queued.await.unwrap().unwrap(), 2);
+        assert_eq!(pool.run(|| 4).await.unwrap(), 4);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(VerificationConfig::default().validate().is_ok());
+        assert!(VerificationConfig {
+            workers: 0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
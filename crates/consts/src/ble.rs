@@ -16,4 +16,18 @@ pub const CA_CERTS_WRITE_UUID: &str = "79739f27-6baf-4f91-9b82-37e59146e15c";
 
 pub const ENROLL_RESPONSE_UUID: &str = "d58c360d-08eb-4106-8b9a-d3c3ebffbf51";
 pub const ENROLL_RESPONSE_READ_UUID: &str = "d58c360d-08eb-4106-8b9a-d3c3ebffbf52";
-pub const ENROLL_RESPONSE_WRITE_UUID: &str = "d58c360d-08eb-4106-8b9a-d3c3ebffbf53";
\ No newline at end of file
+pub const ENROLL_RESPONSE_WRITE_UUID: &str = "d58c360d-08eb-4106-8b9a-d3c3ebffbf53";
+
+/// The agent writes a status query JWS, the pledge answers with its pledge status JWS
+pub const STATUS_UUID: &str = "3e1f8c42-5a7d-4b19-8e63-0c9a2d4f7b20";
+pub const STATUS_READ_UUID: &str = "3e1f8c42-5a7d-4b19-8e63-0c9a2d4f7b21";
+pub const STATUS_WRITE_UUID: &str = "3e1f8c42-5a7d-4b19-8e63-0c9a2d4f7b22";
+
+/// The agent writes a network profile JSON after enrollment, the pledge answers with an empty response once it is stored
+pub const NETWORK_PROFILE_UUID: &str = "7a4e2d91-3c58-4f0b-a6d2-81e9c0b5f3a0";
+pub const NETWORK_PROFILE_READ_UUID: &str = "7a4e2d91-3c58-4f0b-a6d2-81e9c0b5f3a1";
+pub const NETWORK_PROFILE_WRITE_UUID: &str = "7a4e2d91-3c58-4f0b-a6d2-81e9c0b5f3a2";
+
+/// Read-only, the pledge hands out its black box records as JSON lines, an empty read ends the dump
+pub const BLACK_BOX_UUID: &str = "5c0f1a2e-7d3b-4e8a-9f61-2b7c4d9e0a10";
+pub const BLACK_BOX_READ_UUID: &str = "5c0f1a2e-7d3b-4e8a-9f61-2b7c4d9e0a11";
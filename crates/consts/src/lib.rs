@@ -1 +1,3 @@
-pub mod ble;
\ No newline at end of file
+pub mod ble;
+pub mod nfc;
+pub mod usb;
@@ -0,0 +1,9 @@
+/// NDEF tag application of NFC Forum Type 4 tags
+pub const NDEF_APPLICATION_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+pub const CAPABILITY_CONTAINER_FILE_ID: [u8; 2] = [0xE1, 0x03];
+
+/// Media type of the record the pledge tag carries its serial-number in
+pub const SERIAL_NUMBER_MEDIA_TYPE: &str = "application/vnd.open-brski.serial-number";
+/// Media type of the record carrying the trigger, the pledge answers with a record of `application/jose+json`
+pub const TRIGGER_MEDIA_TYPE: &str = "application/json";
+pub const PVR_MEDIA_TYPE: &str = "application/jose+json";
@@ -0,0 +1,24 @@
+//! Framing of the PRM exchanges over a USB serial line (USB CDC-ACM or the USB Serial/JTAG of the ESP32).
+//!
+//! Each frame is `FRAME_MAGIC`, the exchange, the payload length as big endian `u32` and the payload.
+//! The agent sends one request frame per exchange, the pledge answers with a frame of the same exchange
+//! or with the exchange ORed with `ERROR_FLAG` and a UTF-8 reason as payload. An exchange carries the same
+//! message as the write and read of the matching BLE service.
+
+/// Starts every frame. `0xF5` never occurs in UTF-8, so log output sharing the line is skipped reliably
+pub const FRAME_MAGIC: u8 = 0xF5;
+/// Magic, exchange and length
+pub const HEADER_LENGTH: usize = 6;
+/// Upper bound of the payload, longer frames are treated as corrupt
+pub const MAX_PAYLOAD_LENGTH: usize = 64 * 1024;
+/// Set on the exchange of a response when the pledge could not process the request
+pub const ERROR_FLAG: u8 = 0x80;
+
+/// The pledge answers with its serial-number, the request is empty
+pub const IDENTIFY: u8 = 0x00;
+pub const TPVR: u8 = 0x01;
+pub const TPER: u8 = 0x02;
+pub const VOUCHER: u8 = 0x03;
+pub const CA_CERTS: u8 = 0x04;
+pub const ENROLL_RESPONSE: u8 = 0x05;
+pub const STATUS: u8 = 0x06;
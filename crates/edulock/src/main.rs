@@ -3,7 +3,7 @@ use std::env::current_dir;
 use common::error::AppError;
 use tokio::task::JoinHandle;
 use tracing_forest::ForestLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Registry};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 
 #[tokio::main]
@@ -16,8 +16,12 @@ async fn main() -> anyhow::Result<(), AppError> {
     let cli = cli::parse_args();
     let config = cli::get_config()?;
 
-    if matches!(cli.command, cli::Command::TestCerts) {
-        let certs = example_certs::generate_certs();
+    if let cli::Command::TestCerts(args) = &cli.command {
+        let curve = match args.pledge_curve {
+            cli::PledgeCurve::P256 => example_certs::Curve::P256,
+            cli::PledgeCurve::P384 => example_certs::Curve::P384,
+        };
+        let certs = example_certs::generate_certs_with_pledge_curve(curve);
         example_certs::serialize_certs(
             certs,
             current_dir().unwrap().join("reference_keys"),
@@ -25,12 +29,33 @@ async fn main() -> anyhow::Result<(), AppError> {
         return Ok(());
     }
 
+    if let cli::Command::Pledge(pledge_args) = &cli.command {
+        if pledge_args.command == Some(cli::PledgeCommand::Doctor) {
+            let report = pledge::doctor(config.pledge).await;
+            println!("{}", report);
+            if !report.is_healthy() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        if pledge_args.dry_run {
+            if let Err(err) = pledge::dry_run(config.pledge, pledge_args.dump_artifacts.clone()).await {
+                eprintln!("Dry run failed: {:?}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    }
+
     let mut tasks: Vec<JoinHandle<_>> = match &cli.command {
         cli::Command::RegistrarAgent(_) => vec![registrar_agent::start(config.registrar_agent).await.unwrap()],
+        cli::Command::Registrar(registrar_args) if registrar_args.command == Some(cli::RegistrarCommand::JoinProxy) => {
+            vec![registrar::start_join_proxy(config.registrar).await.unwrap()]
+        }
         cli::Command::Registrar(_) => vec![registrar::start(config.registrar).await.unwrap()],
         cli::Command::Masa(_) => vec![masa::start(config.masa).await.unwrap()],
         cli::Command::Pledge(_) => vec![pledge::start(config.pledge).await.unwrap()],
-        cli::Command::TestCerts => unreachable!(),
+        cli::Command::TestCerts(_) => unreachable!(),
         cli::Command::All => {
             vec![
                 registrar_agent::start(config.registrar_agent).await.unwrap(),
@@ -42,7 +67,7 @@ async fn main() -> anyhow::Result<(), AppError> {
     };
 
     if tasks.len() == 1 {
-        let _ = tasks.pop().unwrap().await?;
+        tasks.pop().unwrap().await?;
     } else {
         let _ = futures::future::join_all(tasks).await;
     }
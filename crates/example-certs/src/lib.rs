@@ -1,10 +1,24 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::{Path, PathBuf}};
 
 mod masa_cert;
 mod pledge_cert;
 mod registrar_agent_cert;
 mod registrar_cert;
 
+/// Start of the validity of generated certificates, backdated so certificates that are verified right after they were
+/// generated are not rejected as not yet valid
+fn not_before() -> time::OffsetDateTime {
+    time::OffsetDateTime::now_utc() - time::Duration::minutes(5)
+}
+
+/// Curve of the pledge IDevID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Curve {
+    #[default]
+    P256,
+    P384,
+}
+
 pub struct TestCerts {
     pub vendor_ca: (rcgen::Certificate, rcgen::KeyPair),
     pub vendor: (rcgen::Certificate, rcgen::KeyPair),
@@ -41,7 +55,7 @@ pub struct OpensslTestCerts {
     ),
 }
 
-fn serialize_certpair(name: &str, path: &PathBuf, pair: &(rcgen::Certificate, rcgen::KeyPair)) {
+fn serialize_certpair(name: &str, path: &Path, pair: &(rcgen::Certificate, rcgen::KeyPair)) {
     let cert = pair.0.pem();
     let key = pair.1.serialize_pem();
 
@@ -83,6 +97,19 @@ pub fn serialize_certs(certs: TestCerts, path: PathBuf) {
 }
 
 pub fn generate_certs() -> TestCerts {
+    generate_certs_with_pledge_curve(Curve::P256)
+}
+
+pub fn generate_certs_with_pledge_curve(curve: Curve) -> TestCerts {
+    generate("00-D0-E5-F2-00-02", curve)
+}
+
+/// Certificates of a pledge with another serial-number, which may contain any UTF-8 characters
+pub fn generate_certs_for_serial(serial_number: &str) -> TestCerts {
+    generate(serial_number, Curve::P256)
+}
+
+fn generate(serial_number: &str, curve: Curve) -> TestCerts {
     let (vendor_ca_cert, vendor_ca_keypair) =
         masa_cert::generate_vendor_ca_cert("masa-ca.example.com CA");
     let (vendor_cert, vendor_keypair) = masa_cert::generate_vendor_cert(
@@ -103,10 +130,11 @@ pub fn generate_certs() -> TestCerts {
         &registrar_ca_keypair,
     );
     let (idevid_cert, idevid_key) = pledge_cert::generate_idevid_cert(
-        "00-D0-E5-F2-00-02",
+        serial_number,
         "localhost:3000",
         &vendor_ca_cert,
         &vendor_ca_keypair,
+        curve,
     );
 
     TestCerts {
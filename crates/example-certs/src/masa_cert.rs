@@ -8,7 +8,7 @@ use time::OffsetDateTime;
 /// a custom extension with the MASA URL
 pub fn generate_vendor_ca_cert(common_name: &str) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc().replace_year(2999).unwrap();
     params.use_authority_key_identifier_extension = true;
     params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
@@ -40,7 +40,7 @@ pub fn generate_vendor_cert(
     ca_key: &KeyPair,
 ) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc().replace_year(2999).unwrap();
     params.use_authority_key_identifier_extension = true;
     params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
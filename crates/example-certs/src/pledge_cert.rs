@@ -6,6 +6,8 @@ use rcgen::SanType;
 use rcgen::SerialNumber;
 use time::OffsetDateTime;
 
+use crate::Curve;
+
 ///
 /// Generate a self-signed certificate with a serial number as the common name and
 /// a custom extension with the MASA URL
@@ -14,12 +16,14 @@ pub fn generate_idevid_cert(
     masa_url: &str,
     ca_cert: &Certificate,
     ca_key: &KeyPair,
+    curve: Curve,
 ) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc() + time::Duration::days(365);
     params.serial_number = Some(SerialNumber::from_slice("1".as_bytes()));
-    params.subject_alt_names = vec![SanType::DnsName(Ia5String::try_from(serial).unwrap())];
+    // a serial-number with non-ASCII characters is no DNS name
+    params.subject_alt_names = Ia5String::try_from(serial).map(SanType::DnsName).into_iter().collect();
 
     // Create a custom DN type for "serial-number"
     let serial_number_dn_type = rcgen::DnType::from_oid(&[2, 5, 4, 5]);
@@ -35,8 +39,12 @@ pub fn generate_idevid_cert(
 
     // key pair
 
-    let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
-    let cert = params.signed_by(&key_pair, &ca_cert, &ca_key).unwrap();
+    let algorithm = match curve {
+        Curve::P256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+        Curve::P384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+    };
+    let key_pair = rcgen::KeyPair::generate_for(algorithm).unwrap();
+    let cert = params.signed_by(&key_pair, ca_cert, ca_key).unwrap();
 
     (cert, key_pair)
 }
@@ -13,7 +13,7 @@ pub fn generate_regagt_cert(
     ca_key: &KeyPair,
 ) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc() + time::Duration::weeks(4);
     params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
     params.subject_alt_names = vec![rcgen::SanType::DnsName(
@@ -6,7 +6,7 @@ use time::OffsetDateTime;
 
 pub fn generate_owner_ca_cert(common_name: &str) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc() + time::Duration::days(365);
     params.use_authority_key_identifier_extension = true;
     params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
@@ -50,7 +50,7 @@ pub fn generate_owner_cert(
     ca_key: &KeyPair,
 ) -> (Certificate, KeyPair) {
     let mut params = CertificateParams::default();
-    params.not_before = OffsetDateTime::now_utc();
+    params.not_before = crate::not_before();
     params.not_after = OffsetDateTime::now_utc() + time::Duration::days(365);
     params.use_authority_key_identifier_extension = true;
     params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
@@ -73,7 +73,10 @@ pub fn generate_owner_cert(
     // what
     params.is_ca = rcgen::IsCa::NoCa;
 
-    params.key_usages = vec![rcgen::KeyUsagePurpose::KeyCertSign];
+    params.key_usages = vec![
+        rcgen::KeyUsagePurpose::DigitalSignature,
+        rcgen::KeyUsagePurpose::KeyCertSign,
+    ];
 
     params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::Other(
         [1, 3, 6, 1, 5, 5, 7, 3, 28].to_vec(),
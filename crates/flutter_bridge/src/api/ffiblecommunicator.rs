@@ -2,16 +2,18 @@ use std::sync::Arc;
 
 pub use flutter_rust_bridge::DartFnFuture;
 use registrar_agent::{PledgeCommunicator, PledgeCtx};
-use registrar_agent::{start, ParsedConfig};
 use common::server_error::ServerError;
 
+/// Dart callback handing an artifact to the pledge
+pub type FfiSender<T> = Arc<Box<dyn Fn(T, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>;
+
 #[derive(Default)]
 pub struct FFIBLECommunicatorBuilder {
-    pub ffi_send_pvr_trigger: Option<Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>>,
-    pub ffi_send_per_trigger: Option<Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>>,
-    pub ffi_send_voucher: Option<Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>>,
-    pub ffi_send_ca_certs: Option<Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>>,
-    pub ffi_send_enroll_response: Option<Arc<Box<dyn Fn(Vec<u8>, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>>,
+    pub ffi_send_pvr_trigger: Option<FfiSender<String>>,
+    pub ffi_send_per_trigger: Option<FfiSender<String>>,
+    pub ffi_send_voucher: Option<FfiSender<String>>,
+    pub ffi_send_ca_certs: Option<FfiSender<String>>,
+    pub ffi_send_enroll_response: Option<FfiSender<Vec<u8>>>,
 }
 
 impl FFIBLECommunicatorBuilder {
@@ -71,11 +73,11 @@ impl FFIBLECommunicatorBuilder {
 pub struct FFIBLECommunicator {
     //pub ffi_send_pvr_trigger: &'static (dyn Fn(&brski_prm_artifacts::pvr::trigger::Trigger, &String) -> DartFnFuture<String> + Sync + Send),
     // create place to store the function
-    ffi_send_pvr_trigger: Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>,
-    ffi_send_per_trigger: Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>,
-    ffi_send_voucher: Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>,
-    ffi_send_ca_certs: Arc<Box<dyn Fn(String, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>,
-    ffi_send_enroll_response: Arc<Box<dyn Fn(Vec<u8>, PledgeCtx) -> DartFnFuture<String> + Sync + Send>>,
+    ffi_send_pvr_trigger: FfiSender<String>,
+    ffi_send_per_trigger: FfiSender<String>,
+    ffi_send_voucher: FfiSender<String>,
+    ffi_send_ca_certs: FfiSender<String>,
+    ffi_send_enroll_response: FfiSender<Vec<u8>>,
 }
 
 #[async_trait::async_trait]
@@ -100,7 +102,7 @@ impl PledgeCommunicator for FFIBLECommunicator {
 
     #[tracing::instrument(skip(self, ctx), target = "RegistrarAgent", name="send_ca_certs")]
     async fn send_ca_certs(&self, cacerts: String, ctx: PledgeCtx) -> Result<(), ServerError> {
-        let result = (self.ffi_send_ca_certs)(cacerts, ctx).await;
+        let _result = (self.ffi_send_ca_certs)(cacerts, ctx).await;
         Ok(())
     }
 
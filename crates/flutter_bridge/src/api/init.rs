@@ -7,13 +7,12 @@ use std::io::Write;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
 struct LogSink {
     sink: StreamSink<String>,
 }
 
 /// Write log lines to our Flutter's sink.
-impl<'a> Write for &'a LogSink {
+impl Write for &LogSink {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let line = String::from_utf8_lossy(buf).to_string();
         self.sink.add(line).unwrap();
@@ -35,7 +34,7 @@ impl<'a> MakeWriter<'a> for LogSink {
 
 /// Public method that Dart will call into.
 pub fn setup_logs(sink: StreamSink<String>) -> Result<()> {
-    if(env::var("RUST_BACKTRACE").is_err()){
+    if env::var("RUST_BACKTRACE").is_err() {
         bail!("RUST_BACKTRACE not set");
     }
     let log_sink = LogSink { sink };
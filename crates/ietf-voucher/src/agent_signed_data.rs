@@ -1,5 +1,5 @@
 #[cfg(feature = "jws")]
-use josekit::jws::{self, JwsHeader, ES256};
+use josekit::jws::{self, JwsHeader, ES256, ES384, ES512};
 
 use crate::error::VoucherError;
 use chrono::{DateTime, Utc};
@@ -64,15 +64,13 @@ impl AgentSignedData {
         let mut header = JwsHeader::new();
         header.set_token_type("JWT");
 
-        let mut signer = ES256.signer_from_der(keypair)?;
+        let mut signer = crate::signing_algorithm(&keypair)?.signer_from_der(keypair)?;
 
         signer.set_key_id(skid);
 
         // this is NOT base64 encoded! It is a compact JWS! We must encode it later!
-        let serialized = serde_json::to_vec(&data).map_err(|_| {
-            VoucherError::MalformedAgentSignedData(
-                "Could not serialize AgentSignedData".to_string(),
-            )
+        let serialized = crate::canonical::to_vec(&data).map_err(|err| {
+            VoucherError::MalformedAgentSignedData(format!("Could not serialize AgentSignedData: {}", err))
         })?;
 
         let serialized_jws = jws::serialize_compact(&serialized, &header, &signer)?;
@@ -93,9 +91,16 @@ impl AgentSignedData {
             AgentSignedData::Unsigned(_) => unreachable!(),
         };
 
-        let verifier = ES256.verifier_from_der(public_key)?;
+        // an intermediary may have padded the segments, the signature covers them unpadded
+        let data = data.split('.').map(crate::canonical::base64url).collect::<Vec<_>>().join(".");
+
+        let verifier: Box<dyn jws::JwsVerifier> = match josekit::jwt::decode_header(&data)?.claim("alg").and_then(|alg| alg.as_str()) {
+            Some("ES384") => Box::new(ES384.verifier_from_der(public_key)?),
+            Some("ES512") => Box::new(ES512.verifier_from_der(public_key)?),
+            _ => Box::new(ES256.verifier_from_der(public_key)?),
+        };
 
-        let (data, _header) = jws::deserialize_compact(data, &verifier)?;
+        let (data, _header) = jws::deserialize_compact(data, verifier.as_ref())?;
 
         let data: Payload = serde_json::from_slice(&data).map_err(|_| {
             VoucherError::MalformedAgentSignedData(
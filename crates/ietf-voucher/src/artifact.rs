@@ -129,7 +129,9 @@ impl std::fmt::Debug for VoucherArtifactDetails {
 #[cfg(feature = "openssl")]
 impl VoucherArtifactDetails {
     /// Verifies the voucher for the given target. Vouchers without expiry date *and* without nonces are valid in this context. MASA services must make sure to make an informed security decision.
-    fn verify(&self, validity_information: Option<ValidityCtx>) -> Result<(), VoucherError> {
+    pub fn verify(&self, validity_information: Option<ValidityCtx>) -> Result<(), VoucherError> {
+        crate::serial_number::validate(&self.serial_number).map_err(|err| VoucherError::MalformedVoucher(err.to_string()))?;
+
         if self.expires_on.is_some()
             && self.created_on.is_some()
             && self.expires_on < self.created_on
@@ -0,0 +1,173 @@
+//! Deterministic JSON for signed artifacts, following the JSON Canonicalization Scheme (RFC 8785) for the values
+//! vouchers and voucher-requests carry.
+//!
+//! Payloads are serialized canonically before they are signed: object members sorted by their UTF-16 code units, no
+//! insignificant whitespace and the string escaping of RFC 8785. Numbers are limited to integers that survive the
+//! round trip through an IEEE 754 double, everything else is rejected instead of being rewritten by some parser on
+//! the way. The same artifact therefore always has the same signing input, whichever component signed it.
+//!
+//! Verification is tolerant instead: signatures are checked over the payload bytes as received and never over a
+//! re-serialization, so payloads of other implementations are accepted even if they are not canonical. Intermediaries
+//! that re-encode the base64url segments are tolerated by [`base64url`].
+
+use std::borrow::Cow;
+
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+/// Largest integer that is exact in an IEEE 754 double, RFC 8785 Section 3.2.2.3
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CanonicalError {
+    #[error("payload can not be serialized: {0}")]
+    Serialize(String),
+    #[error("payload contains the non-integer number {0}")]
+    NonIntegerNumber(String),
+    #[error("payload contains the integer {0} that is not exact as a double")]
+    IntegerOutOfRange(String),
+}
+
+/// Serializes `value` in canonical form
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CanonicalError> {
+    let value = serde_json::to_value(value).map_err(|err| CanonicalError::Serialize(err.to_string()))?;
+    let mut out = Vec::new();
+    write(&value, &mut out)?;
+    Ok(out)
+}
+
+/// Whether `payload` is JSON in canonical form, e.g. to report artifacts of other implementations
+pub fn is_canonical(payload: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(payload)
+        .ok()
+        .and_then(|value| {
+            let mut out = Vec::new();
+            write(&value, &mut out).ok()?;
+            Some(out == payload)
+        })
+        .unwrap_or(false)
+}
+
+/// Restores a base64url segment of a JWS that an intermediary padded or re-encoded with the standard alphabet. Both
+/// encode the same bytes, so the segment the signer produced is recovered and its signature still verifies.
+pub fn base64url(segment: &str) -> Cow<'_, str> {
+    let segment = segment.trim();
+    if !segment.contains(['=', '+', '/']) {
+        return Cow::Borrowed(segment);
+    }
+    Cow::Owned(
+        segment
+            .trim_end_matches('=')
+            .chars()
+            .map(|c| match c {
+                '+' => '-',
+                '/' => '_',
+                c => c,
+            })
+            .collect(),
+    )
+}
+
+fn write(value: &Value, out: &mut Vec<u8>) -> Result<(), CanonicalError> {
+    match value {
+        Value::Null => out.extend_from_slice(b"null"),
+        Value::Bool(true) => out.extend_from_slice(b"true"),
+        Value::Bool(false) => out.extend_from_slice(b"false"),
+        Value::Number(number) => {
+            let exact = match (number.as_u64(), number.as_i64()) {
+                (Some(n), _) => n <= MAX_SAFE_INTEGER,
+                (None, Some(n)) => n.unsigned_abs() <= MAX_SAFE_INTEGER,
+                (None, None) => return Err(CanonicalError::NonIntegerNumber(number.to_string())),
+            };
+            if !exact {
+                return Err(CanonicalError::IntegerOutOfRange(number.to_string()));
+            }
+            out.extend_from_slice(number.to_string().as_bytes());
+        }
+        // serde_json escapes exactly the characters RFC 8785 Section 3.2.2.2 escapes, in the same notation
+        Value::String(string) => write_string(string, out)?,
+        Value::Array(values) => {
+            out.push(b'[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write(value, out)?;
+            }
+            out.push(b']');
+        }
+        Value::Object(members) => {
+            let mut members: Vec<_> = members.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+            out.push(b'{');
+            for (i, (name, value)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_string(name, out)?;
+                out.push(b':');
+                write(value, out)?;
+            }
+            out.push(b'}');
+        }
+    }
+    Ok(())
+}
+
+fn write_string(string: &str, out: &mut Vec<u8>) -> Result<(), CanonicalError> {
+    serde_json::to_writer(out, string).map_err(|err| CanonicalError::Serialize(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_sorts_members_without_whitespace() {
+        let value = json!({
+            "ietf-voucher:voucher": {
+                "serial-number": "00-D0-E5-F2-00-02",
+                "assertion": "verified",
+                "nonce": "MTIz",
+                "created-on": "2024-01-01T00:00:00Z",
+            },
+            "a": [3, {"z": null, "b": true}],
+        });
+
+        assert_eq!(
+            String::from_utf8(to_vec(&value).unwrap()).unwrap(),
+            r#"{"a":[3,{"b":true,"z":null}],"ietf-voucher:voucher":{"assertion":"verified","created-on":"2024-01-01T00:00:00Z","nonce":"MTIz","serial-number":"00-D0-E5-F2-00-02"}}"#
+        );
+        assert!(is_canonical(&to_vec(&value).unwrap()));
+        assert!(!is_canonical(serde_json::to_string_pretty(&value).unwrap().as_bytes()));
+    }
+
+    #[test]
+    fn test_rfc8785_strings_and_ordering() {
+        // RFC 8785 Section 3.2.3, members are sorted by UTF-16 code units, not by UTF-8 bytes
+        let value = json!({"\u{20ac}": "Euro Sign", "\r": "Carriage Return", "\u{fb33}": "Hebrew", "1": "One", "\u{1f600}": "Emoji", "\u{80}": "Control"});
+        assert_eq!(
+            String::from_utf8(to_vec(&value).unwrap()).unwrap(),
+            "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\"\u{20ac}\":\"Euro Sign\",\"\u{1f600}\":\"Emoji\",\"\u{fb33}\":\"Hebrew\"}"
+        );
+
+        assert_eq!(to_vec("\u{1}\"\\/\n").unwrap(), br#""\u0001\"\\/\n""#);
+    }
+
+    #[test]
+    fn test_rejects_inexact_numbers() {
+        assert_eq!(to_vec(&json!({"n": -9007199254740991i64})).unwrap(), br#"{"n":-9007199254740991}"#);
+        assert_eq!(to_vec(&1.5f64), Err(CanonicalError::NonIntegerNumber("1.5".to_string())));
+        assert_eq!(to_vec(&(1u64 << 53)), Err(CanonicalError::IntegerOutOfRange("9007199254740992".to_string())));
+    }
+
+    #[test]
+    fn test_base64url() {
+        assert_eq!(base64url("eyJh-_"), Cow::Borrowed("eyJh-_"));
+        assert_eq!(base64url(" eyJh+/==\n"), "eyJh-_");
+    }
+}
@@ -1,6 +1,8 @@
 #![feature(cfg_eval)]
 pub mod artifact;
 pub mod assertion;
+#[cfg(feature = "json")]
+pub mod canonical;
 ///
 /// # Voucher
 ///
@@ -17,6 +19,7 @@ pub mod assertion;
 /// For simplified use, you can parse the JSON encoded voucher into a Voucher struct by calling `serde_json::try_from(json: &str) -> Result<Voucher, Error>`.
 pub mod error;
 pub mod request_artifact;
+pub mod serial_number;
 pub mod target;
 pub mod verified;
 
@@ -24,6 +27,8 @@ pub mod agent_signed_data;
 
 mod util;
 pub use util::pki;
+#[cfg(feature = "jws")]
+pub use util::jws::{signer, signing_algorithm};
 
 use request_artifact::VoucherRequestArtifact;
 
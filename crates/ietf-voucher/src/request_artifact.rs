@@ -187,7 +187,7 @@ impl fmt::Debug for VoucherRequestArtifactDetails {
                 &self.domain_cert_revocation_checks,
             )
             .field("nonce", &self.nonce)
-            .field("pinned_domain_pubk", &format!("Private Key"))
+            .field("pinned_domain_pubk", &"Private Key".to_string())
             .field("pinned_domain_pubk_sha256", &self.pinned_domain_pubk_sha256)
             .field("last_renewal_date", &self.last_renewal_date)
             .field("est_domain", &self.est_domain)
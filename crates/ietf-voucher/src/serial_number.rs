@@ -0,0 +1,134 @@
+//! serial-number of a pledge, as carried by vouchers, voucher-requests and the serialNumber attribute of its IDevID.
+//!
+//! Some manufacturers put non-ASCII characters into the serial-number. Vouchers compare it byte by byte with the
+//! IDevID, so it is read as UTF-8 whatever string type the certificate uses and never normalized, only checked.
+//! JSON and CBOR carry it as a text string unchanged.
+
+use thiserror::Error;
+
+/// ub-serial-number of RFC 5280 Appendix A.1, in characters
+pub const MAX_LENGTH: usize = 64;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SerialNumberError {
+    #[error("serial-number is empty")]
+    Empty,
+    #[error("serial-number has {0} characters, at most {MAX_LENGTH} are allowed")]
+    TooLong(usize),
+    #[error("serial-number contains the control character {0:?}")]
+    ControlCharacter(char),
+    #[error("certificate subject has no serialNumber")]
+    Missing,
+    #[error("serialNumber of the certificate subject is not a valid string")]
+    Undecodable,
+}
+
+/// Rejects serial-numbers no IDevID can carry and control characters, which would end up in logs and file names
+pub fn validate(serial_number: &str) -> Result<(), SerialNumberError> {
+    if serial_number.is_empty() {
+        return Err(SerialNumberError::Empty);
+    }
+    let length = serial_number.chars().count();
+    if length > MAX_LENGTH {
+        return Err(SerialNumberError::TooLong(length));
+    }
+    match serial_number.chars().find(|c| c.is_control()) {
+        Some(c) => Err(SerialNumberError::ControlCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+/// Whether the serial-number fits into a PrintableString, otherwise it has to be encoded as UTF8String
+pub fn is_printable(serial_number: &str) -> bool {
+    serial_number
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || " '()+,-./:=?".contains(c))
+}
+
+/// Reads the serialNumber attribute of a certificate or CSR subject. PrintableString, UTF8String, BMPString and
+/// UniversalString are all converted to UTF-8.
+#[cfg(feature = "openssl")]
+pub fn from_name(name: &openssl::x509::X509NameRef) -> Result<String, SerialNumberError> {
+    let entry = name
+        .entries_by_nid(openssl::nid::Nid::SERIALNUMBER)
+        .next()
+        .ok_or(SerialNumberError::Missing)?;
+    let serial_number = entry.data().to_string().map_err(|_| SerialNumberError::Undecodable)?;
+    validate(&serial_number)?;
+    Ok(serial_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate("00-D0-E5-F2-00-02"), Ok(()));
+        assert_eq!(validate("Gerät-Nr. 4711/ß"), Ok(()));
+        assert_eq!(validate("製造番号-０００２"), Ok(()));
+        // counted in characters, not bytes
+        assert_eq!(validate(&"ü".repeat(MAX_LENGTH)), Ok(()));
+        assert_eq!(validate(&"ü".repeat(MAX_LENGTH + 1)), Err(SerialNumberError::TooLong(MAX_LENGTH + 1)));
+        assert_eq!(validate(""), Err(SerialNumberError::Empty));
+        assert_eq!(validate("00-02\n"), Err(SerialNumberError::ControlCharacter('\n')));
+        assert_eq!(validate("00-02\u{85}"), Err(SerialNumberError::ControlCharacter('\u{85}')));
+    }
+
+    #[test]
+    fn test_is_printable() {
+        assert!(is_printable("00-D0-E5-F2-00-02"));
+        assert!(is_printable("SN 4711/2024 (rev. B)"));
+        assert!(!is_printable("00_02"));
+        assert!(!is_printable("Gerät-4711"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_and_cbor_keep_utf8() {
+        use crate::artifact::{VoucherArtifact, VoucherArtifactDetails};
+
+        let voucher = VoucherArtifact {
+            details: VoucherArtifactDetails {
+                serial_number: "Gerät-Nr. 製造-\u{1f600}".to_string(),
+                ..Default::default()
+            },
+        };
+
+        let json = crate::canonical::to_vec(&voucher).unwrap();
+        // RFC 8785 does not escape non-ASCII characters
+        assert!(String::from_utf8(json.clone()).unwrap().contains("\"Gerät-Nr. 製造-\u{1f600}\""));
+        let decoded: VoucherArtifact = serde_json::from_slice(&json).unwrap();
+        assert_eq!(decoded.details.serial_number, voucher.details.serial_number);
+
+        let mut cbor = vec![];
+        ciborium::into_writer(&voucher, &mut cbor).unwrap();
+        let decoded: VoucherArtifact = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(decoded.details.serial_number, voucher.details.serial_number);
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn test_from_name() {
+        use openssl::{asn1::Asn1Type, nid::Nid, x509::X509NameBuilder};
+
+        let name = |serial_number: &str, string_type: Asn1Type| {
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_nid_with_type(Nid::SERIALNUMBER, serial_number, string_type).unwrap();
+            name.build()
+        };
+
+        assert_eq!(from_name(&name("00-D0-E5-F2-00-02", Asn1Type::PRINTABLESTRING)).unwrap(), "00-D0-E5-F2-00-02");
+        assert_eq!(from_name(&name("Gerät-製造-0002", Asn1Type::UTF8STRING)).unwrap(), "Gerät-製造-0002");
+        assert_eq!(from_name(&name("00-02\t", Asn1Type::UTF8STRING)), Err(SerialNumberError::ControlCharacter('\t')));
+
+        let mut common_name_only = X509NameBuilder::new().unwrap();
+        common_name_only.append_entry_by_nid(Nid::COMMONNAME, "pledge").unwrap();
+        assert_eq!(from_name(&common_name_only.build()), Err(SerialNumberError::Missing));
+
+        // IDevIDs generated with a UTF-8 serial-number keep it
+        let certs = example_certs::generate_certs_for_serial("Gerät-製造-0002");
+        let idevid = openssl::x509::X509::from_der(certs.pledge.0.der()).unwrap();
+        assert_eq!(from_name(idevid.subject_name()).unwrap(), "Gerät-製造-0002");
+    }
+}
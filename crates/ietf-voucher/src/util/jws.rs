@@ -0,0 +1,33 @@
+use josekit::{
+    jws::{alg::ecdsa::EcdsaJwsAlgorithm, JwsSigner, EdDSA},
+    JoseError,
+};
+use openssl::{nid::Nid, pkey::{Id, PKey}};
+
+/// The ECDSA algorithm matching the curve of a DER encoded (PKCS#8 or SEC1) EC private key
+pub fn signing_algorithm(keypair: impl AsRef<[u8]>) -> Result<EcdsaJwsAlgorithm, JoseError> {
+    let curve = PKey::private_key_from_der(keypair.as_ref())
+        .and_then(|key| key.ec_key())
+        .map(|key| key.group().curve_name())
+        .map_err(|err| JoseError::InvalidKeyFormat(anyhow::Error::from(err)))?;
+
+    match curve {
+        Some(Nid::X9_62_PRIME256V1) => Ok(EcdsaJwsAlgorithm::Es256),
+        Some(Nid::SECP384R1) => Ok(EcdsaJwsAlgorithm::Es384),
+        Some(Nid::SECP521R1) => Ok(EcdsaJwsAlgorithm::Es512),
+        Some(Nid::SECP256K1) => Ok(EcdsaJwsAlgorithm::Es256k),
+        _ => Err(JoseError::InvalidKeyFormat(anyhow::anyhow!("unsupported EC curve"))),
+    }
+}
+
+/// A signer for a DER encoded private key, ECDSA for EC keys and EdDSA for Ed25519 keys in PKCS#8
+pub fn signer(keypair: impl AsRef<[u8]>) -> Result<Box<dyn JwsSigner>, JoseError> {
+    let id = PKey::private_key_from_der(keypair.as_ref())
+        .map(|key| key.id())
+        .map_err(|err| JoseError::InvalidKeyFormat(anyhow::Error::from(err)))?;
+
+    match id {
+        Id::ED25519 => Ok(Box::new(EdDSA.signer_from_der(keypair)?)),
+        _ => Ok(Box::new(signing_algorithm(&keypair)?.signer_from_der(keypair)?)),
+    }
+}
@@ -1 +1,3 @@
+#[cfg(feature = "jws")]
+pub mod jws;
 pub mod pki;
@@ -3,8 +3,9 @@ mod openssl;
 #[cfg(feature = "openssl")]
 pub use openssl::*;
 
+#[cfg(not(feature = "openssl"))]
 mod nossl;
-#[cfg(all(not(feature = "openssl")))]
+#[cfg(not(feature = "openssl"))]
 pub use nossl::*;
 
 #[cfg(feature = "ring")]
@@ -2,6 +2,3 @@ mod pkey;
 mod x509;
 mod x509_req;
 
-pub use pkey::*;
-pub use x509::*;
-pub use x509_req::*;
@@ -1,4 +1,3 @@
-use std::ops::Deref;
 
 use serde::{Deserialize, Serialize};
 
@@ -10,7 +9,7 @@ pub struct Pkey(Vec<u8>);
 
 impl AsRef<[u8]> for Pkey {
     fn as_ref(&self) -> &[u8] {
-        &self.0.as_ref()
+        self.0.as_ref()
     }
 }
 
@@ -1,4 +1,3 @@
-use std::ops::Deref;
 
 use serde::{Deserialize, Serialize};
 
@@ -10,7 +9,7 @@ pub struct X509Req(Vec<u8>);
 
 impl AsRef<[u8]> for X509Req {
     fn as_ref(&self) -> &[u8] {
-        &self.0.as_ref()
+        self.0.as_ref()
     }
 }
 
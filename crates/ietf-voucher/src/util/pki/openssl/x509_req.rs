@@ -27,10 +27,7 @@ impl std::fmt::Debug for X509Req {
         f.debug_struct("X509Req")
             .field(
                 "csr",
-                &format!(
-                    "{}",
-                    String::from_utf8(self.csr.to_text().unwrap()).unwrap()
-                ),
+                &String::from_utf8(self.csr.to_text().unwrap()).unwrap().to_string(),
             )
             .field(
                 "der",
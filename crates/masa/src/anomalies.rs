@@ -0,0 +1,254 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::audit_log::AuditEntry;
+
+/// Upper bound of alerts kept in memory, older alerts are dropped first
+const MAX_ALERTS: usize = 1024;
+
+/// Period in which registrars claiming the same pledge are counted
+const SERIAL_CLAIM_WINDOW: Duration = Duration::hours(24);
+
+/// Period in which nonceless voucher requests are counted
+const NONCELESS_BURST_WINDOW: Duration = Duration::minutes(1);
+
+/// Deliveries of an alert to the webhook before it is given up
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a webhook delivery, doubled for every further retry
+const WEBHOOK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub(crate) enum Anomaly {
+    /// A pledge belongs to one owner, several registrars asking for it hints at a cloned IDevID
+    SerialClaimedByManyRegistrars { serial_number: String, registrars: Vec<String> },
+    /// Nonceless vouchers stay valid without the pledge being online, so a burst hints at stockpiling
+    NoncelessBurst { requests: usize },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Alert {
+    pub(crate) anomaly: Anomaly,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// Receives every alert as JSON POST request
+#[derive(Debug, Clone)]
+struct AlertWebhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl AlertWebhook {
+    /// Retried with backoff in the background, so recording the audit entry does not wait for the webhook
+    fn deliver(&self, alert: Alert) {
+        let webhook = self.clone();
+        tokio::spawn(async move {
+            let mut delay = WEBHOOK_RETRY_DELAY;
+            for attempt in 1..=WEBHOOK_ATTEMPTS {
+                match webhook.client.post(&webhook.url).json(&alert).send().await.and_then(|response| response.error_for_status()) {
+                    Ok(_) => return,
+                    Err(err) => event!(target: "MASA::Alerts", Level::ERROR, "Delivering alert failed, attempt {} of {}: {}", attempt, WEBHOOK_ATTEMPTS, err),
+                }
+                if attempt < WEBHOOK_ATTEMPTS {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        });
+    }
+}
+
+/// Flags suspicious patterns in the audit log. A threshold of 0 disables the check.
+#[derive(Debug)]
+pub(crate) struct AnomalyDetector {
+    registrars_per_serial: usize,
+    nonceless_burst: usize,
+    webhook: Option<AlertWebhook>,
+    alerts: RwLock<Vec<Alert>>,
+}
+
+impl Default for AnomalyDetector {
+    fn default() -> Self {
+        Self::new(3, 20)
+    }
+}
+
+impl AnomalyDetector {
+    pub(crate) fn new(registrars_per_serial: usize, nonceless_burst: usize) -> Self {
+        Self {
+            registrars_per_serial,
+            nonceless_burst,
+            webhook: None,
+            alerts: RwLock::new(vec![]),
+        }
+    }
+
+    /// Also delivers the alerts to `url`, if set
+    pub(crate) fn with_webhook(mut self, url: Option<String>, client: reqwest::Client) -> Self {
+        self.webhook = url.map(|url| AlertWebhook { url, client });
+        self
+    }
+
+    /// Checks the audit log after `latest` was appended to `entries`
+    pub(crate) async fn inspect(&self, entries: &[AuditEntry], latest: &AuditEntry) {
+        let anomalies = [self.serial_claims(entries, latest), self.nonceless_burst(entries, latest)];
+
+        for anomaly in anomalies.into_iter().flatten() {
+            self.raise(anomaly, latest.timestamp).await;
+        }
+    }
+
+    fn serial_claims(&self, entries: &[AuditEntry], latest: &AuditEntry) -> Option<Anomaly> {
+        if self.registrars_per_serial == 0 {
+            return None;
+        }
+
+        let claims: Vec<&String> = entries
+            .iter()
+            .filter(|entry| entry.outcome.is_voucher_request() && entry.serial_number == latest.serial_number)
+            .filter(|entry| latest.timestamp - entry.timestamp <= SERIAL_CLAIM_WINDOW)
+            .filter_map(|entry| entry.registrar.as_ref())
+            .collect();
+        let registrars: HashSet<&String> = claims.iter().copied().collect();
+
+        // only raised once, when a new registrar crosses the threshold
+        let is_new_registrar = latest
+            .registrar
+            .as_ref()
+            .is_some_and(|registrar| claims.iter().filter(|claim| **claim == registrar).count() == 1);
+        if registrars.len() != self.registrars_per_serial || !is_new_registrar {
+            return None;
+        }
+
+        let mut registrars: Vec<String> = registrars.into_iter().cloned().collect();
+        registrars.sort();
+        Some(Anomaly::SerialClaimedByManyRegistrars {
+            serial_number: latest.serial_number.clone(),
+            registrars,
+        })
+    }
+
+    fn nonceless_burst(&self, entries: &[AuditEntry], latest: &AuditEntry) -> Option<Anomaly> {
+        if self.nonceless_burst == 0 || !latest.nonceless {
+            return None;
+        }
+
+        let requests = entries
+            .iter()
+            .filter(|entry| entry.outcome.is_voucher_request() && entry.nonceless && latest.timestamp - entry.timestamp <= NONCELESS_BURST_WINDOW)
+            .count();
+
+        // raised every time the burst grows by another threshold, not for every single request
+        (requests % self.nonceless_burst == 0).then_some(Anomaly::NoncelessBurst { requests })
+    }
+
+    async fn raise(&self, anomaly: Anomaly, timestamp: DateTime<Utc>) {
+        event!(target: "MASA::Alerts", Level::WARN, "Audit log anomaly: {:?}", anomaly);
+
+        let alert = Alert { anomaly, timestamp };
+        if let Some(webhook) = &self.webhook {
+            webhook.deliver(alert.clone());
+        }
+
+        let mut alerts = self.alerts.write().await;
+        if alerts.len() >= MAX_ALERTS {
+            alerts.remove(0);
+        }
+        alerts.push(alert);
+    }
+
+    /// Raised anomalies in reverse order of raising
+    pub(crate) async fn alerts(&self) -> Vec<Alert> {
+        self.alerts.read().await.iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+
+    use crate::audit_log::{AuditLog, AuditOutcome};
+
+    use super::*;
+
+    fn entry(serial_number: &str, registrar: &str, nonceless: bool) -> AuditEntry {
+        AuditEntry {
+            serial_number: serial_number.to_string(),
+            registrar: Some(registrar.to_string()),
+            domain_id: None,
+            nonce: None,
+            nonceless,
+            assertion: None,
+            expires_on: None,
+            outcome: AuditOutcome::Issued,
+            timestamp: Utc::now(),
+            voucher_status: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serial_claimed_by_many_registrars() {
+        let log = AuditLog::new(AnomalyDetector::new(2, 0));
+        log.record(entry("00-D0-E5-F2-00-02", "registrar-a", false)).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-02", "registrar-a", false)).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-03", "registrar-b", false)).await.unwrap();
+        assert!(log.alerts().await.is_empty());
+
+        log.record(entry("00-D0-E5-F2-00-02", "registrar-b", false)).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-02", "registrar-b", false)).await.unwrap();
+
+        let alerts = log.alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            alerts[0].anomaly,
+            Anomaly::SerialClaimedByManyRegistrars {
+                serial_number: "00-D0-E5-F2-00-02".to_string(),
+                registrars: vec!["registrar-a".to_string(), "registrar-b".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nonceless_burst() {
+        let log = AuditLog::new(AnomalyDetector::new(0, 3));
+        for serial in ["00-D0-E5-F2-00-02", "00-D0-E5-F2-00-03"] {
+            log.record(entry(serial, "registrar-a", true)).await.unwrap();
+        }
+        log.record(entry("00-D0-E5-F2-00-04", "registrar-a", false)).await.unwrap();
+        assert!(log.alerts().await.is_empty());
+
+        log.record(entry("00-D0-E5-F2-00-05", "registrar-a", true)).await.unwrap();
+        let alerts = log.alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].anomaly, Anomaly::NoncelessBurst { requests: 3 });
+    }
+
+    #[tokio::test]
+    async fn test_delivers_alerts_to_webhook() {
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/alerts",
+            post(move |Json(alert): Json<serde_json::Value>| async move {
+                sender.send(alert).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let detector = AnomalyDetector::new(0, 1).with_webhook(Some(format!("http://{}/alerts", address)), reqwest::Client::new());
+        let log = AuditLog::new(detector);
+        log.record(entry("00-D0-E5-F2-00-02", "registrar-a", true)).await.unwrap();
+
+        let alert = tokio::time::timeout(std::time::Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert_eq!(alert["anomaly"]["kind"], "nonceless-burst");
+        assert_eq!(alert["anomaly"]["requests"], 1);
+    }
+}
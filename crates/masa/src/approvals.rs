@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Decision {
+    Approved,
+    Denied,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PendingApproval {
+    pub(crate) serial_number: String,
+    pub(crate) registrar: Option<String>,
+    pub(crate) first_requested: DateTime<Utc>,
+    pub(crate) last_requested: DateTime<Utc>,
+    pub(crate) attempts: u32,
+}
+
+/// Holds voucher requests for pledges that support staff has not decided on yet.
+/// Only used when the MASA runs with `manual_approval`.
+#[derive(Debug, Default)]
+pub(crate) struct Approvals {
+    pending: RwLock<HashMap<String, PendingApproval>>,
+    decisions: RwLock<HashMap<String, Decision>>,
+}
+
+impl Approvals {
+    /// Returns the decision for the pledge, or queues it for approval if there is none yet
+    pub(crate) async fn check(&self, serial_number: &str, registrar: Option<&str>) -> Option<Decision> {
+        if let Some(decision) = self.decisions.read().await.get(serial_number) {
+            return Some(*decision);
+        }
+
+        let now = Utc::now();
+        let mut pending = self.pending.write().await;
+        let entry = pending
+            .entry(serial_number.to_string())
+            .or_insert_with(|| PendingApproval {
+                serial_number: serial_number.to_string(),
+                registrar: registrar.map(str::to_string),
+                first_requested: now,
+                last_requested: now,
+                attempts: 0,
+            });
+        entry.last_requested = now;
+        entry.attempts += 1;
+
+        None
+    }
+
+    pub(crate) async fn decide(&self, serial_number: &str, decision: Decision) {
+        self.pending.write().await.remove(serial_number);
+        self.decisions
+            .write()
+            .await
+            .insert(serial_number.to_string(), decision);
+    }
+
+    pub(crate) async fn pending(&self) -> Vec<PendingApproval> {
+        let mut pending: Vec<_> = self.pending.read().await.values().cloned().collect();
+        pending.sort_by_key(|approval| approval.first_requested);
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_approval_flow() {
+        let approvals = Approvals::default();
+
+        assert_eq!(approvals.check("00-D0-E5-F2-00-02", None).await, None);
+        assert_eq!(approvals.check("00-D0-E5-F2-00-02", None).await, None);
+
+        let pending = approvals.pending().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].attempts, 2);
+
+        approvals.decide("00-D0-E5-F2-00-02", Decision::Approved).await;
+        assert!(approvals.pending().await.is_empty());
+        assert_eq!(
+            approvals.check("00-D0-E5-F2-00-02", None).await,
+            Some(Decision::Approved)
+        );
+    }
+}
@@ -0,0 +1,876 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use brski_prm_artifacts::{
+    ietf_voucher::assertion::Assertion,
+    status::{attestation::CounterAttestation, reason_code::ReasonCode},
+};
+use chrono::{DateTime, Utc};
+use common::{error::AppError, server_error::ServerError, timestamp::TimestampClient, util::hex};
+use openssl::{hash::MessageDigest, x509::X509Ref};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{event, Level};
+
+use crate::anomalies::{Alert, AnomalyDetector};
+
+/// Upper bound of audit entries kept in memory, older entries are dropped first.
+/// The persisted log is never truncated.
+const MAX_AUDIT_ENTRIES: usize = 4096;
+
+/// Entries read from the store at once when going through the whole history of a pledge
+pub(crate) const HISTORY_PAGE_SIZE: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AuditOutcome {
+    Issued,
+    PendingApproval,
+    Denied,
+    /// Voucher status telemetry relayed by the registrar, the pledge accepted the voucher
+    VoucherAccepted,
+    /// Voucher status telemetry relayed by the registrar, the pledge rejected the voucher
+    VoucherRejected,
+}
+
+impl AuditOutcome {
+    /// Whether the entry records a voucher request, the other entries record telemetry about an issued voucher
+    pub(crate) fn is_voucher_request(&self) -> bool {
+        !matches!(self, AuditOutcome::VoucherAccepted | AuditOutcome::VoucherRejected)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AuditEntry {
+    pub(crate) serial_number: String,
+    pub(crate) registrar: Option<String>,
+    /// See [`domain_id`]
+    #[serde(default)]
+    pub(crate) domain_id: Option<String>,
+    /// Base64 encoded nonce of the voucher request
+    #[serde(default)]
+    pub(crate) nonce: Option<String>,
+    pub(crate) nonceless: bool,
+    pub(crate) assertion: Option<Assertion>,
+    pub(crate) expires_on: Option<DateTime<Utc>>,
+    pub(crate) outcome: AuditOutcome,
+    pub(crate) timestamp: DateTime<Utc>,
+    /// Only set for voucher status telemetry, which copies the other fields from the entry of the voucher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) voucher_status: Option<VoucherStatusReport>,
+}
+
+/// What the pledge reported about a voucher, see [`AuditOutcome::VoucherAccepted`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VoucherStatusReport {
+    /// Timestamp of the entry of the voucher the status belongs to
+    pub(crate) voucher_issued_on: DateTime<Utc>,
+    pub(crate) reason: Option<String>,
+    pub(crate) reason_code: Option<ReasonCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) attestation: Option<CounterAttestation>,
+}
+
+/// Latest entries of a pledge, indexed for every pledge so status reports do not search the persisted log
+#[derive(Debug, Clone, Default)]
+struct LatestEntries {
+    issued: Option<AuditEntry>,
+    status: Option<VoucherStatusReport>,
+}
+
+impl LatestEntries {
+    fn update(index: &mut HashMap<String, LatestEntries>, entry: &AuditEntry) {
+        if entry.outcome == AuditOutcome::Issued {
+            index.entry(entry.serial_number.clone()).or_default().issued = Some(entry.clone());
+        }
+        if let Some(status) = &entry.voucher_status {
+            index.entry(entry.serial_number.clone()).or_default().status = Some(status.clone());
+        }
+    }
+}
+
+/// Voucher status telemetry over the whole persisted log
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VoucherStatusCounts {
+    pub(crate) accepted: u64,
+    pub(crate) rejected: u64,
+    /// Rejections by reason code, pledges predating reason codes are counted as `unknown`
+    pub(crate) rejected_by_reason: BTreeMap<ReasonCode, u64>,
+}
+
+impl VoucherStatusCounts {
+    fn count(&mut self, entry: &AuditEntry) {
+        match entry.outcome {
+            AuditOutcome::VoucherAccepted => self.accepted += 1,
+            AuditOutcome::VoucherRejected => {
+                self.rejected += 1;
+                let reason_code = entry
+                    .voucher_status
+                    .as_ref()
+                    .and_then(|status| status.reason_code)
+                    .unwrap_or(ReasonCode::Unknown);
+                *self.rejected_by_reason.entry(reason_code).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Identifies the domain a voucher was issued to, see RFC 8995 Section 5.8.2: the base64 encoded
+/// SubjectKeyIdentifier of the pinned certificate, or the SHA-1 of its encoded public key if it has none
+pub(crate) fn domain_id(cert: &X509Ref) -> Result<String, ServerError> {
+    let key_id = match cert.subject_key_id() {
+        Some(key_id) => key_id.as_slice().to_vec(),
+        None => openssl::hash::hash(MessageDigest::sha1(), &cert.public_key()?.public_key_to_der()?)?.to_vec(),
+    };
+    Ok(openssl::base64::encode_block(&key_id))
+}
+
+/// Part of the history of a pledge, see [`AuditLog::history_page`]
+#[derive(Debug)]
+pub(crate) struct HistoryPage {
+    /// Entries with their position in the store, the history can be continued at any of them
+    pub(crate) entries: Vec<(u64, AuditEntry)>,
+    /// Position of the following entry of the pledge, `None` if this is the last page
+    pub(crate) next: Option<u64>,
+}
+
+/// RFC 3161 time-stamp over the first `entries` persisted entries, so they can be shown unaltered since `time`
+/// without relying on the MASA key. The digest is SHA-256 over the entries as JSON lines, each ending with a newline,
+/// which for an audit log file are its first `entries` lines.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Checkpoint {
+    pub(crate) entries: u64,
+    /// Hex encoded
+    pub(crate) sha256: String,
+    pub(crate) time: DateTime<Utc>,
+    /// Base64 encoded DER time-stamp token
+    pub(crate) token: String,
+}
+
+fn sql_error(err: rusqlite::Error) -> ServerError {
+    anyhow::Error::from(err).into()
+}
+
+/// Where entries are persisted, so the log survives restarts
+#[derive(Debug, Default)]
+enum AuditStore {
+    #[default]
+    Memory,
+    /// Append-only file with one JSON entry per line
+    File { path: PathBuf, file: Mutex<File> },
+    Sqlite(Mutex<Connection>),
+}
+
+impl AuditStore {
+    fn checkpoints_path(path: &Path) -> PathBuf {
+        let mut checkpoints = path.as_os_str().to_owned();
+        checkpoints.push(".checkpoints");
+        checkpoints.into()
+    }
+
+    fn open_file(path: &Path) -> anyhow::Result<(Self, Vec<AuditEntry>), AppError> {
+        event!(target: "MASA::AuditLog", Level::INFO, "Opening audit log {:?}", path);
+        let file = OpenOptions::new().create(true).append(true).read(true).open(path)?;
+        let entries = read_lines(path)?;
+
+        Ok((
+            AuditStore::File {
+                path: path.to_path_buf(),
+                file: Mutex::new(file),
+            },
+            entries,
+        ))
+    }
+
+    fn open_sqlite(path: &Path) -> anyhow::Result<(Self, Vec<AuditEntry>), AppError> {
+        event!(target: "MASA::AuditLog", Level::INFO, "Opening audit log database {:?}", path);
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                serial_number TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                entry TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS audit_log_serial_number ON audit_log (serial_number);
+            CREATE TABLE IF NOT EXISTS audit_checkpoints (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                checkpoint TEXT NOT NULL
+            );",
+        )?;
+
+        let entries = {
+            let mut statement = connection.prepare(
+                "SELECT entry FROM (SELECT id, entry FROM audit_log ORDER BY id DESC LIMIT ?1) ORDER BY id",
+            )?;
+            let rows = statement.query_map(params![MAX_AUDIT_ENTRIES], |row| row.get::<_, String>(0))?;
+            rows.map(|entry| Ok(serde_json::from_str(&entry?)?))
+                .collect::<anyhow::Result<Vec<AuditEntry>>>()?
+        };
+
+        Ok((AuditStore::Sqlite(Mutex::new(connection)), entries))
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> Result<(), ServerError> {
+        match self {
+            AuditStore::Memory => {}
+            AuditStore::File { file, .. } => {
+                let mut line = serde_json::to_vec(entry)?;
+                line.push(b'\n');
+
+                let mut file = file.lock().await;
+                file.write_all(&line)?;
+                file.sync_data()?;
+            }
+            AuditStore::Sqlite(connection) => {
+                connection
+                    .lock()
+                    .await
+                    .execute(
+                        "INSERT INTO audit_log (serial_number, timestamp, entry) VALUES (?1, ?2, ?3)",
+                        params![entry.serial_number, entry.timestamp.timestamp_millis(), serde_json::to_string(entry)?],
+                    )
+                    .map_err(sql_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the voucher status telemetry and indexes the latest entries of every pledge over all persisted entries,
+    /// once on start
+    fn scan(&self) -> anyhow::Result<(VoucherStatusCounts, HashMap<String, LatestEntries>)> {
+        let mut counts = VoucherStatusCounts::default();
+        let mut index = HashMap::new();
+        let mut add = |entry: AuditEntry| {
+            counts.count(&entry);
+            LatestEntries::update(&mut index, &entry);
+        };
+        match self {
+            AuditStore::Memory => {}
+            AuditStore::File { path, .. } => {
+                for entry in file_entries(path)? {
+                    add(entry?);
+                }
+            }
+            AuditStore::Sqlite(connection) => {
+                let connection = connection.try_lock()?;
+                let mut statement = connection.prepare("SELECT entry FROM audit_log ORDER BY id")?;
+                let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+                for entry in rows {
+                    add(serde_json::from_str(&entry?)?);
+                }
+            }
+        }
+        Ok((counts, index))
+    }
+
+    /// Checkpoints taken so far, oldest first
+    fn checkpoints(&self) -> anyhow::Result<Vec<Checkpoint>> {
+        match self {
+            AuditStore::Memory => Ok(vec![]),
+            AuditStore::File { path, .. } => match std::fs::read_to_string(Self::checkpoints_path(path)) {
+                Ok(content) => content.lines().filter(|line| !line.is_empty()).map(|line| Ok(serde_json::from_str(line)?)).collect(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+                Err(err) => Err(err.into()),
+            },
+            AuditStore::Sqlite(connection) => {
+                let connection = connection.try_lock()?;
+                let mut statement = connection.prepare("SELECT checkpoint FROM audit_checkpoints ORDER BY id")?;
+                let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+                rows.map(|checkpoint| Ok(serde_json::from_str(&checkpoint?)?)).collect()
+            }
+        }
+    }
+
+    async fn append_checkpoint(&self, checkpoint: &Checkpoint) -> Result<(), ServerError> {
+        match self {
+            AuditStore::Memory => {}
+            AuditStore::File { path, .. } => {
+                let mut line = serde_json::to_vec(checkpoint)?;
+                line.push(b'\n');
+
+                let mut file = OpenOptions::new().create(true).append(true).open(Self::checkpoints_path(path))?;
+                file.write_all(&line)?;
+                file.sync_data()?;
+            }
+            AuditStore::Sqlite(connection) => {
+                connection
+                    .lock()
+                    .await
+                    .execute("INSERT INTO audit_checkpoints (checkpoint) VALUES (?1)", params![serde_json::to_string(checkpoint)?])
+                    .map_err(sql_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of persisted entries and the SHA-256 digest over them, see [`Checkpoint`]. `None` for the in-memory store.
+    async fn digest(&self) -> Result<Option<(u64, [u8; 32])>, ServerError> {
+        let mut hasher = openssl::sha::Sha256::new();
+        let entries = match self {
+            AuditStore::Memory => return Ok(None),
+            AuditStore::File { path, file } => {
+                // a line that is still being appended is left for the next checkpoint
+                let _file = file.lock().await;
+                let content = std::fs::read(path)?;
+                let complete = content.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+                hasher.update(&content[..complete]);
+                content[..complete].iter().filter(|byte| **byte == b'\n').count() as u64
+            }
+            AuditStore::Sqlite(connection) => {
+                let connection = connection.lock().await;
+                let mut statement = connection.prepare("SELECT entry FROM audit_log ORDER BY id").map_err(sql_error)?;
+                let mut rows = statement.query([]).map_err(sql_error)?;
+                let mut entries = 0;
+                while let Some(row) = rows.next().map_err(sql_error)? {
+                    hasher.update(row.get::<_, String>(0).map_err(sql_error)?.as_bytes());
+                    hasher.update(b"\n");
+                    entries += 1;
+                }
+                entries
+            }
+        };
+        Ok(Some((entries, hasher.finish())))
+    }
+
+    /// Up to `limit` persisted entries of a pledge from position `from` on, oldest first, and the position of the
+    /// entry following them. Positions are byte offsets of lines in a file and row ids in a database, so a page is
+    /// read from where the previous one ended. `None` for the in-memory store.
+    async fn page(&self, serial_number: &str, from: u64, limit: usize) -> Result<Option<HistoryPage>, ServerError> {
+        let mut entries = match self {
+            AuditStore::Memory => return Ok(None),
+            AuditStore::File { path, .. } => {
+                let mut reader = BufReader::new(File::open(path)?);
+                reader.seek(SeekFrom::Start(from))?;
+
+                let mut entries = vec![];
+                let mut position = from;
+                let mut line = vec![];
+                while entries.len() <= limit {
+                    line.clear();
+                    let read = reader.read_until(b'\n', &mut line)?;
+                    // a line without newline is still being appended, it belongs to a later page
+                    if read == 0 || line.last() != Some(&b'\n') {
+                        break;
+                    }
+                    match serde_json::from_slice::<AuditEntry>(&line) {
+                        Ok(entry) if entry.serial_number == serial_number => entries.push((position, entry)),
+                        Ok(_) => {}
+                        Err(err) => event!(target: "MASA::AuditLog", Level::WARN, "Skipping unreadable audit log line: {}", err),
+                    }
+                    position += read as u64;
+                }
+                entries
+            }
+            AuditStore::Sqlite(connection) => {
+                let connection = connection.lock().await;
+                let mut statement = connection
+                    .prepare("SELECT id, entry FROM audit_log WHERE serial_number = ?1 AND id >= ?2 ORDER BY id LIMIT ?3")
+                    .map_err(sql_error)?;
+                // a negative limit is no limit to SQLite
+                let sql_limit = i64::try_from(limit.saturating_add(1)).unwrap_or(-1);
+                let sql_from = i64::try_from(from).unwrap_or(i64::MAX);
+                let entries = statement
+                    .query_map(params![serial_number, sql_from, sql_limit], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+                    .map_err(sql_error)?
+                    .map(|row| {
+                        let (id, entry) = row.map_err(sql_error)?;
+                        Ok((id as u64, serde_json::from_str(&entry)?))
+                    })
+                    .collect::<Result<Vec<(u64, AuditEntry)>, ServerError>>()?;
+                entries
+            }
+        };
+
+        let next = (entries.len() > limit).then(|| entries[limit].0);
+        entries.truncate(limit);
+        Ok(Some(HistoryPage { entries, next }))
+    }
+}
+
+/// Entries of an audit log file in order. A line cut short by a crash while appending is skipped.
+fn file_entries(path: &Path) -> std::io::Result<impl Iterator<Item = std::io::Result<AuditEntry>>> {
+    Ok(BufReader::new(File::open(path)?).lines().filter_map(|line| match line {
+        Err(err) => Some(Err(err)),
+        Ok(line) if line.is_empty() => None,
+        Ok(line) => match serde_json::from_str(&line) {
+            Ok(entry) => Some(Ok(entry)),
+            Err(err) => {
+                event!(target: "MASA::AuditLog", Level::WARN, "Skipping unreadable audit log line: {}", err);
+                None
+            }
+        },
+    }))
+}
+
+fn read_lines(path: &Path) -> std::io::Result<Vec<AuditEntry>> {
+    file_entries(path)?.collect()
+}
+
+/// Record of every voucher request the MASA handled, each new entry is checked for anomalies.
+/// Recent entries are kept in memory, all of them are persisted to the configured file or SQLite database.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    entries: RwLock<Vec<AuditEntry>>,
+    detector: AnomalyDetector,
+    store: AuditStore,
+    checkpoints: RwLock<Vec<Checkpoint>>,
+    status_counts: RwLock<VoucherStatusCounts>,
+    latest: RwLock<HashMap<String, LatestEntries>>,
+    /// Serializes the checks and the recording of voucher status reports
+    reporting: Mutex<()>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(detector: AnomalyDetector) -> Self {
+        Self {
+            entries: RwLock::new(vec![]),
+            detector,
+            store: AuditStore::Memory,
+            checkpoints: RwLock::new(vec![]),
+            status_counts: RwLock::new(VoucherStatusCounts::default()),
+            latest: RwLock::new(HashMap::new()),
+            reporting: Mutex::new(()),
+        }
+    }
+
+    /// Persists to the JSON-lines `file` or the SQLite `database`, at most one of them may be set.
+    /// Recent entries are loaded back, anomalies are only checked for new entries.
+    pub(crate) fn open(detector: AnomalyDetector, file: Option<&Path>, database: Option<&Path>) -> anyhow::Result<Self, AppError> {
+        let (store, mut entries) = match (file, database) {
+            (Some(path), None) => AuditStore::open_file(path)?,
+            (None, Some(path)) => AuditStore::open_sqlite(path)?,
+            (None, None) => return Ok(Self::new(detector)),
+            (Some(_), Some(_)) => return Err(anyhow::anyhow!("Only one of audit_log_file and audit_log_database may be set").into()),
+        };
+
+        let excess = entries.len().saturating_sub(MAX_AUDIT_ENTRIES);
+        entries.drain(..excess);
+        event!(target: "MASA::AuditLog", Level::INFO, "Loaded {} audit log entries", entries.len());
+        let checkpoints = store.checkpoints()?;
+        let (status_counts, latest) = store.scan()?;
+
+        Ok(Self {
+            entries: RwLock::new(entries),
+            detector,
+            store,
+            checkpoints: RwLock::new(checkpoints),
+            status_counts: RwLock::new(status_counts),
+            latest: RwLock::new(latest),
+            reporting: Mutex::new(()),
+        })
+    }
+
+    /// Fails if the entry could not be persisted, so no voucher is issued without a durable record
+    pub(crate) async fn record(&self, entry: AuditEntry) -> Result<(), ServerError> {
+        event!(target: "MASA::AuditLog", Level::INFO, "Voucher request for {}: {:?}", entry.serial_number, entry.outcome);
+
+        self.store.append(&entry).await?;
+        self.status_counts.write().await.count(&entry);
+        LatestEntries::update(&mut *self.latest.write().await, &entry);
+
+        let mut entries = self.entries.write().await;
+        if entries.len() >= MAX_AUDIT_ENTRIES {
+            entries.remove(0);
+        }
+        entries.push(entry);
+
+        if let Some(latest) = entries.last().filter(|latest| latest.outcome.is_voucher_request()) {
+            self.detector.inspect(&entries, latest).await;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn alerts(&self) -> Vec<Alert> {
+        self.detector.alerts().await
+    }
+
+    /// Newest entries first, optionally only for a single pledge
+    pub(crate) async fn entries(&self, serial_number: Option<&str>) -> Vec<AuditEntry> {
+        self.entries
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|entry| serial_number.is_none_or(|serial| entry.serial_number == serial))
+            .cloned()
+            .collect()
+    }
+
+    /// Up to `limit` entries of a pledge from position `from` on, oldest first, including those no longer kept in
+    /// memory. The history is read page by page, so a long-lived pledge does not have to fit into memory at once.
+    /// Positions are only meaningful to the store, the first page starts at 0. In memory they count the entries of
+    /// the pledge.
+    pub(crate) async fn history_page(&self, serial_number: &str, from: u64, limit: usize) -> Result<HistoryPage, ServerError> {
+        if let Some(page) = self.store.page(serial_number, from, limit).await? {
+            return Ok(page);
+        }
+
+        let mut entries = self.entries(Some(serial_number)).await;
+        entries.reverse();
+        let mut entries: Vec<(u64, AuditEntry)> = (0..).zip(entries).skip(usize::try_from(from).unwrap_or(usize::MAX)).take(limit.saturating_add(1)).collect();
+        let next = (entries.len() > limit).then(|| entries[limit].0);
+        entries.truncate(limit);
+        Ok(HistoryPage { entries, next })
+    }
+
+    /// Whether a voucher for the pledge was ever issued to the domain
+    pub(crate) async fn issued_to(&self, serial_number: &str, domain_id: &str) -> Result<bool, ServerError> {
+        let mut from = 0;
+        loop {
+            let page = self.history_page(serial_number, from, HISTORY_PAGE_SIZE).await?;
+            if page
+                .entries
+                .iter()
+                .any(|(_, entry)| entry.outcome == AuditOutcome::Issued && entry.domain_id.as_deref() == Some(domain_id))
+            {
+                return Ok(true);
+            }
+            match page.next {
+                Some(next) => from = next,
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Records voucher status telemetry against the latest voucher issued for the pledge. Only the first status of
+    /// each voucher is taken, and a status with a counter attestation has to follow the one of the previous status,
+    /// so replaying a status the pledge signed earlier is rejected.
+    pub(crate) async fn record_voucher_status(
+        &self,
+        serial_number: &str,
+        accepted: bool,
+        reason: Option<String>,
+        reason_code: Option<ReasonCode>,
+        attestation: Option<CounterAttestation>,
+    ) -> Result<(), ServerError> {
+        let _reporting = self.reporting.lock().await;
+        let latest = self.latest.read().await.get(serial_number).cloned().unwrap_or_default();
+
+        let issued = latest
+            .issued
+            .ok_or_else(|| ServerError::BadRequestWithReason(format!("No voucher was issued for {}", serial_number)))?;
+        if let Some(previous) = latest.status {
+            if previous.voucher_issued_on == issued.timestamp {
+                return Err(ServerError::BadRequestWithReason(format!(
+                    "The status of the voucher issued for {} on {} was already reported",
+                    serial_number, issued.timestamp
+                )));
+            }
+            if let (Some(attestation), Some(previous)) = (attestation, previous.attestation) {
+                if !attestation.follows(&previous) {
+                    return Err(ServerError::BadRequestWithReason(format!(
+                        "Counter attestation {:?} of {} does not follow {:?}",
+                        attestation, serial_number, previous
+                    )));
+                }
+            }
+        }
+
+        self.record(AuditEntry {
+            outcome: if accepted { AuditOutcome::VoucherAccepted } else { AuditOutcome::VoucherRejected },
+            timestamp: Utc::now(),
+            voucher_status: Some(VoucherStatusReport {
+                voucher_issued_on: issued.timestamp,
+                reason,
+                reason_code,
+                attestation,
+            }),
+            ..issued
+        })
+        .await
+    }
+
+    pub(crate) async fn voucher_status_counts(&self) -> VoucherStatusCounts {
+        self.status_counts.read().await.clone()
+    }
+
+    /// Newest checkpoints first
+    pub(crate) async fn checkpoints(&self) -> Vec<Checkpoint> {
+        self.checkpoints.read().await.iter().rev().cloned().collect()
+    }
+
+    /// Time-stamps the persisted entries, unless there are none or none were added since the last checkpoint
+    pub(crate) async fn checkpoint(&self, timestamps: &TimestampClient) -> Result<Option<Checkpoint>, ServerError> {
+        let Some((entries, digest)) = self.store.digest().await? else {
+            return Ok(None);
+        };
+        let last = self.checkpoints.read().await.last().map_or(0, |checkpoint| checkpoint.entries);
+        if entries == last {
+            return Ok(None);
+        }
+
+        let token = timestamps
+            .timestamp(&digest)
+            .await
+            .map_err(|err| ServerError::TimestampUnavailable { reason: err.to_string() })?;
+        let checkpoint = Checkpoint {
+            entries,
+            sha256: hex(&digest),
+            time: token.time,
+            token: openssl::base64::encode_block(&token.der),
+        };
+
+        self.store.append_checkpoint(&checkpoint).await?;
+        self.checkpoints.write().await.push(checkpoint.clone());
+        event!(target: "MASA::AuditLog", Level::INFO, "Time-stamped the first {} audit log entries", entries);
+        Ok(Some(checkpoint))
+    }
+
+    /// Takes a checkpoint every `interval`, failed ones are retried on the next
+    pub(crate) fn spawn_checkpoints(self: &Arc<Self>, timestamps: Arc<TimestampClient>, interval: Duration) {
+        let log = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if let Err(err) = log.checkpoint(&timestamps).await {
+                    event!(target: "MASA::AuditLog", Level::WARN, "Audit log checkpoint failed: {}", err);
+                }
+            }
+        });
+    }
+
+    pub(crate) async fn issued(&self) -> Vec<AuditEntry> {
+        self.entries(None)
+            .await
+            .into_iter()
+            .filter(|entry| entry.outcome == AuditOutcome::Issued)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn latest_issued(log: &AuditLog, serial_number: &str) -> Option<AuditEntry> {
+        log.latest.read().await.get(serial_number).and_then(|latest| latest.issued.clone())
+    }
+
+    fn entry(serial_number: &str, outcome: AuditOutcome) -> AuditEntry {
+        AuditEntry {
+            serial_number: serial_number.to_string(),
+            registrar: None,
+            domain_id: None,
+            nonce: None,
+            nonceless: false,
+            assertion: None,
+            expires_on: None,
+            outcome,
+            timestamp: Utc::now(),
+            voucher_status: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filters_entries() {
+        let log = AuditLog::default();
+        log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::PendingApproval)).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Issued)).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-03", AuditOutcome::Denied)).await.unwrap();
+
+        assert_eq!(log.entries(None).await.len(), 3);
+        assert_eq!(log.entries(Some("00-D0-E5-F2-00-02")).await.len(), 2);
+
+        let issued = log.issued().await;
+        assert_eq!(issued.len(), 1);
+        assert_eq!(issued[0].serial_number, "00-D0-E5-F2-00-02");
+    }
+
+    #[tokio::test]
+    async fn test_persists_entries() {
+        for extension in ["jsonl", "sqlite"] {
+            let path = std::env::temp_dir().join(format!("open-brski-audit-{}.{}", std::process::id(), extension));
+            let open = || match extension {
+                "jsonl" => AuditLog::open(AnomalyDetector::default(), Some(&path), None).unwrap(),
+                _ => AuditLog::open(AnomalyDetector::default(), None, Some(&path)).unwrap(),
+            };
+
+            let log = open();
+            log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Issued)).await.unwrap();
+            log.record(entry("00-D0-E5-F2-00-03", AuditOutcome::Denied)).await.unwrap();
+            log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Denied)).await.unwrap();
+            drop(log);
+
+            let reopened = open();
+            assert_eq!(reopened.entries(None).await.len(), 3);
+            let history = reopened.history_page("00-D0-E5-F2-00-02", 0, usize::MAX).await.unwrap();
+            assert_eq!(history.next, None);
+            assert_eq!(
+                history.entries.iter().map(|(_, entry)| entry.outcome).collect::<Vec<_>>(),
+                vec![AuditOutcome::Issued, AuditOutcome::Denied]
+            );
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_digests() {
+        let client = TimestampClient::new("http://127.0.0.1:1/tsa".to_string(), None, &[], reqwest::Client::new()).unwrap();
+        assert!(AuditLog::default().checkpoint(&client).await.unwrap().is_none());
+
+        let mut digests = vec![];
+        for extension in ["jsonl", "sqlite"] {
+            let path = std::env::temp_dir().join(format!("open-brski-audit-checkpoints-{}.{}", std::process::id(), extension));
+            let open = || match extension {
+                "jsonl" => AuditLog::open(AnomalyDetector::default(), Some(&path), None).unwrap(),
+                _ => AuditLog::open(AnomalyDetector::default(), None, Some(&path)).unwrap(),
+            };
+
+            let log = open();
+            assert_eq!(log.store.digest().await.unwrap().unwrap().0, 0);
+            let mut recorded = entry("00-D0-E5-F2-00-02", AuditOutcome::Issued);
+            recorded.timestamp = DateTime::from_timestamp(1_760_000_000, 0).unwrap();
+            log.record(recorded.clone()).await.unwrap();
+            log.record(recorded).await.unwrap();
+            let (entries, digest) = log.store.digest().await.unwrap().unwrap();
+            assert_eq!(entries, 2);
+            digests.push(digest);
+
+            // nothing is persisted while the TSA is unreachable
+            assert!(matches!(log.checkpoint(&client).await, Err(ServerError::TimestampUnavailable { .. })));
+            assert!(log.checkpoints().await.is_empty());
+
+            let checkpoint = Checkpoint {
+                entries,
+                sha256: "00".repeat(32),
+                time: Utc::now(),
+                token: "MA==".to_string(),
+            };
+            log.store.append_checkpoint(&checkpoint).await.unwrap();
+            drop(log);
+
+            let reopened = open();
+            assert_eq!(reopened.checkpoints().await, vec![checkpoint]);
+            // unchanged since the last checkpoint, so the TSA is not asked
+            assert!(reopened.checkpoint(&client).await.unwrap().is_none());
+            drop(reopened);
+
+            std::fs::remove_file(&path).unwrap();
+            if extension == "jsonl" {
+                std::fs::remove_file(AuditStore::checkpoints_path(&path)).unwrap();
+            }
+        }
+
+        // both stores hash the same JSON lines
+        assert_eq!(digests[0], digests[1]);
+    }
+
+    #[tokio::test]
+    async fn test_pages_history() {
+        for extension in [None, Some("jsonl"), Some("sqlite")] {
+            let path = std::env::temp_dir().join(format!("open-brski-audit-pages-{}.{}", std::process::id(), extension.unwrap_or("none")));
+            let log = match extension {
+                None => AuditLog::default(),
+                Some("jsonl") => AuditLog::open(AnomalyDetector::default(), Some(&path), None).unwrap(),
+                Some(_) => AuditLog::open(AnomalyDetector::default(), None, Some(&path)).unwrap(),
+            };
+
+            for _ in 0..3 {
+                log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Denied)).await.unwrap();
+                log.record(entry("00-D0-E5-F2-00-03", AuditOutcome::Denied)).await.unwrap();
+            }
+            let mut issued = entry("00-D0-E5-F2-00-02", AuditOutcome::Issued);
+            issued.domain_id = Some("domain".to_string());
+            log.record(issued).await.unwrap();
+
+            let first = log.history_page("00-D0-E5-F2-00-02", 0, 2).await.unwrap();
+            assert_eq!(first.entries.len(), 2);
+            // the next page starts at the third entry of the pledge, wherever it is stored
+            let next = first.next.unwrap();
+            assert!(next > first.entries[1].0);
+            let last = log.history_page("00-D0-E5-F2-00-02", next, 2).await.unwrap();
+            assert_eq!((last.entries.len(), last.next), (2, None));
+            assert_eq!(last.entries[0].0, next);
+            assert_eq!(last.entries[1].1.outcome, AuditOutcome::Issued);
+            assert!(log.history_page("00-D0-E5-F2-00-02", last.entries[1].0 + 1, 2).await.unwrap().entries.is_empty());
+
+            assert!(log.issued_to("00-D0-E5-F2-00-02", "domain").await.unwrap());
+            assert!(!log.issued_to("00-D0-E5-F2-00-03", "domain").await.unwrap());
+
+            if extension.is_some() {
+                std::fs::remove_file(&path).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counts_voucher_status() {
+        let path = std::env::temp_dir().join(format!("open-brski-audit-status-{}.sqlite", std::process::id()));
+        let open = || AuditLog::open(AnomalyDetector::new(0, 1), None, Some(&path)).unwrap();
+
+        let log = open();
+        assert!(latest_issued(&log, "00-D0-E5-F2-00-02").await.is_none());
+        let mut issued = entry("00-D0-E5-F2-00-02", AuditOutcome::Issued);
+        issued.nonceless = true;
+        log.record(issued.clone()).await.unwrap();
+        log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Denied)).await.unwrap();
+        assert_eq!(log.alerts().await.len(), 1);
+
+        let latest = latest_issued(&log, "00-D0-E5-F2-00-02").await.unwrap();
+        assert_eq!(latest.timestamp, issued.timestamp);
+        let status = |outcome, reason_code| AuditEntry {
+            outcome,
+            voucher_status: Some(VoucherStatusReport {
+                voucher_issued_on: latest.timestamp,
+                reason: None,
+                reason_code,
+                attestation: None,
+            }),
+            ..latest.clone()
+        };
+        log.record(status(AuditOutcome::VoucherAccepted, Some(ReasonCode::Success))).await.unwrap();
+        log.record(status(AuditOutcome::VoucherRejected, Some(ReasonCode::NonceMismatch))).await.unwrap();
+        log.record(status(AuditOutcome::VoucherRejected, None)).await.unwrap();
+        // telemetry copies the nonceless flag, but is no voucher request
+        assert_eq!(log.alerts().await.len(), 1);
+        assert_eq!(log.issued().await.len(), 1);
+        drop(log);
+
+        let reopened = open();
+        let counts = reopened.voucher_status_counts().await;
+        assert_eq!((counts.accepted, counts.rejected), (1, 2));
+        assert_eq!(
+            serde_json::to_value(&counts.rejected_by_reason).unwrap(),
+            serde_json::json!({"nonce-mismatch": 1, "unknown": 1})
+        );
+        // the index of the latest entries is rebuilt from the database
+        assert_eq!(latest_issued(&reopened, "00-D0-E5-F2-00-02").await.unwrap().timestamp, issued.timestamp);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_replayed_voucher_status() {
+        let attestation = |boot_count, monotonic_counter| Some(CounterAttestation { boot_count, monotonic_counter });
+        let log = AuditLog::default();
+        assert!(log.record_voucher_status("00-D0-E5-F2-00-02", true, None, None, None).await.is_err());
+
+        log.record(entry("00-D0-E5-F2-00-02", AuditOutcome::Issued)).await.unwrap();
+        log.record_voucher_status("00-D0-E5-F2-00-02", true, None, None, attestation(1, 2)).await.unwrap();
+        // a second status for the same voucher
+        assert!(log.record_voucher_status("00-D0-E5-F2-00-02", true, None, None, attestation(1, 3)).await.is_err());
+
+        let mut reissued = entry("00-D0-E5-F2-00-02", AuditOutcome::Issued);
+        reissued.timestamp += chrono::Duration::seconds(1);
+        log.record(reissued).await.unwrap();
+        // the status signed for the first voucher, replayed for the new one
+        assert!(log.record_voucher_status("00-D0-E5-F2-00-02", true, None, None, attestation(1, 2)).await.is_err());
+        log.record_voucher_status("00-D0-E5-F2-00-02", false, None, Some(ReasonCode::NonceMismatch), attestation(1, 4)).await.unwrap();
+
+        let counts = log.voucher_status_counts().await;
+        assert_eq!((counts.accepted, counts.rejected), (1, 1));
+    }
+}
@@ -0,0 +1,393 @@
+//! Registrar certificates revoked by the manufacturer
+//!
+//! Support staff revoke the certificate of a compromised or decommissioned registrar through the admin API. The MASA
+//! issues no further vouchers to it and lists it in a CRL signed with the MASA CA key, which is published every
+//! `publish_secs` and after every revocation.
+//!
+//! The registrar certificates are not issued by the MASA CA, so the CRL is an indirect CRL (RFC 5280 Section 5.2.5):
+//! its issuing distribution point is marked `indirectCRL` and every entry names the issuer of the revoked certificate
+//! in a critical `certificateIssuer` extension.
+
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use cli::config::CrlConfig;
+use common::{error::AppError, server_error::ServerError};
+use der::{
+    asn1::{BitString, GeneralizedTime, ObjectIdentifier, OctetString, Uint, UtcTime},
+    oid::AssociatedOid,
+    Decode, Encode,
+};
+use openssl::{
+    base64,
+    bn::BigNum,
+    ec::EcKey,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+    x509::{X509Ref, X509},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{event, Level};
+use x509_cert::{
+    crl::{CertificateList, RevokedCert, TbsCertList},
+    ext::{
+        pkix::{crl::dp::IssuingDistributionPoint, name::GeneralName, AuthorityKeyIdentifier, CrlNumber, CrlReason},
+        Extension,
+    },
+    name::Name,
+    serial_number::SerialNumber,
+    spki::AlgorithmIdentifierOwned,
+    time::Time,
+    Certificate, Version,
+};
+
+// x509-cert 0.2 associates the issuing distribution point with the wrong OID
+const ID_CE_ISSUING_DISTRIBUTION_POINT: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.28");
+const ID_CE_CERTIFICATE_ISSUER: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.29");
+
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+const ECDSA_WITH_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.4");
+
+/// The reason codes of RFC 5280 Section 5.3.1 that apply to registrar certificates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RevocationReason {
+    #[default]
+    Unspecified,
+    KeyCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+    PrivilegeWithdrawn,
+}
+
+impl From<RevocationReason> for CrlReason {
+    fn from(reason: RevocationReason) -> Self {
+        match reason {
+            RevocationReason::Unspecified => Self::Unspecified,
+            RevocationReason::KeyCompromise => Self::KeyCompromise,
+            RevocationReason::AffiliationChanged => Self::AffiliationChanged,
+            RevocationReason::Superseded => Self::Superseded,
+            RevocationReason::CessationOfOperation => Self::CessationOfOperation,
+            RevocationReason::PrivilegeWithdrawn => Self::PrivilegeWithdrawn,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct RevokedCertificate {
+    /// Hex encoded serial number of the registrar certificate
+    pub(crate) serial_number: String,
+    pub(crate) subject: String,
+    /// Base64 of the DER encoded issuer name, certificates of different issuers may share a serial number
+    pub(crate) issuer: String,
+    pub(crate) reason: RevocationReason,
+    pub(crate) revoked_at: DateTime<Utc>,
+}
+
+impl RevokedCertificate {
+    fn identifies(&self, certificate: &X509Ref) -> bool {
+        identity(certificate).is_ok_and(|(serial_number, issuer)| serial_number == self.serial_number && issuer == self.issuer)
+    }
+}
+
+fn identity(certificate: &X509Ref) -> Result<(String, String), openssl::error::ErrorStack> {
+    let serial_number = certificate.serial_number().to_bn()?.to_hex_str()?.to_string();
+    let issuer = base64::encode_block(&certificate.issuer_name().to_der()?);
+    Ok((serial_number, issuer))
+}
+
+fn extension(extn_id: ObjectIdentifier, critical: bool, value: &impl Encode) -> der::Result<Extension> {
+    Ok(Extension {
+        extn_id,
+        critical,
+        extn_value: OctetString::new(value.to_der()?)?,
+    })
+}
+
+/// UTCTime through 2049 and GeneralizedTime after, RFC 5280 Section 5.1.2.4
+fn time(time: SystemTime) -> der::Result<Time> {
+    match UtcTime::from_system_time(time) {
+        Ok(utc_time) => Ok(Time::UtcTime(utc_time)),
+        Err(_) => Ok(Time::GeneralTime(GeneralizedTime::from_system_time(time)?)),
+    }
+}
+
+/// Content of `revocations_file`
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct Revocations {
+    /// Number of the last published CRL, it has to increase across restarts
+    crl_number: u64,
+    revoked: Vec<RevokedCertificate>,
+}
+
+pub(crate) struct RegistrarCrl {
+    /// Subject of the MASA CA certificate, the issuer of the CRL
+    issuer: Name,
+    /// Subject key identifier of the MASA CA certificate
+    key_identifier: Vec<u8>,
+    key: PKey<Private>,
+    validity: Duration,
+    file: Option<PathBuf>,
+    revocations: RwLock<Revocations>,
+    /// Serializes publishing, so CRL numbers are written and served in order
+    publishing: Mutex<()>,
+    /// The last published CRL, DER encoded
+    crl: RwLock<Vec<u8>>,
+}
+
+impl RegistrarCrl {
+    /// Loads the revocations of `config.revocations_file` and publishes the first CRL
+    pub(crate) async fn open(ca_certificate: &X509, ca_key: &EcKey<Private>, config: &CrlConfig) -> anyhow::Result<Self, AppError> {
+        let certificate = Certificate::from_der(&ca_certificate.to_der()?).map_err(|err| anyhow!("MASA CA certificate: {}", err))?;
+        // the key identifier is derived as in RFC 5280 Section 4.2.1.2 if the certificate has none
+        let key_identifier = match ca_certificate.subject_key_id() {
+            Some(key_identifier) => key_identifier.as_slice().to_vec(),
+            None => openssl::sha::sha1(certificate.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes()).to_vec(),
+        };
+
+        let file = config.revocations_file.as_ref().map(|path| path.relative());
+        let revocations = match &file {
+            Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?)?,
+            _ => Revocations::default(),
+        };
+        event!(target: "MASA::Crl", Level::INFO, "Loaded {} revoked registrar certificates", revocations.revoked.len());
+
+        let crl = Self {
+            issuer: certificate.tbs_certificate.subject,
+            key_identifier,
+            key: PKey::from_ec_key(ca_key.clone())?,
+            validity: Duration::from_secs(config.validity_secs),
+            file,
+            revocations: RwLock::new(revocations),
+            publishing: Mutex::new(()),
+            crl: RwLock::new(Vec::new()),
+        };
+        crl.publish().await?;
+        Ok(crl)
+    }
+
+    /// Revokes `certificate` and publishes a new CRL. Revoking a certificate again keeps its first revocation.
+    pub(crate) async fn revoke(&self, certificate: &X509Ref, reason: RevocationReason) -> Result<RevokedCertificate, ServerError> {
+        let revoked = {
+            let mut revocations = self.revocations.write().await;
+            if let Some(revoked) = revocations.revoked.iter().find(|revoked| revoked.identifies(certificate)) {
+                return Ok(revoked.clone());
+            }
+
+            let (serial_number, issuer) = identity(certificate)?;
+            let revoked = RevokedCertificate {
+                serial_number,
+                subject: format!("{:?}", certificate.subject_name()),
+                issuer,
+                reason,
+                revoked_at: Utc::now(),
+            };
+            revocations.revoked.push(revoked.clone());
+            revoked
+        };
+
+        self.publish().await?;
+        event!(target: "MASA::Crl", Level::WARN, "Revoked registrar certificate {} of {}", revoked.serial_number, revoked.subject);
+        Ok(revoked)
+    }
+
+    pub(crate) async fn is_revoked(&self, certificate: &X509Ref) -> bool {
+        self.revocations.read().await.revoked.iter().any(|revoked| revoked.identifies(certificate))
+    }
+
+    pub(crate) async fn revoked(&self) -> Vec<RevokedCertificate> {
+        self.revocations.read().await.revoked.clone()
+    }
+
+    /// The last published CRL, DER encoded
+    pub(crate) async fn crl(&self) -> Vec<u8> {
+        self.crl.read().await.clone()
+    }
+
+    /// Signs a CRL with the next CRL number and persists the revocations before it is served. The revocations are
+    /// not locked while they are written, voucher requests keep checking them.
+    async fn publish(&self) -> anyhow::Result<()> {
+        let _publishing = self.publishing.lock().await;
+        let (crl_number, revoked, persisted) = {
+            let mut revocations = self.revocations.write().await;
+            revocations.crl_number += 1;
+            (revocations.crl_number, revocations.revoked.clone(), serde_json::to_vec_pretty(&*revocations)?)
+        };
+        if let Some(path) = &self.file {
+            tokio::fs::write(path, persisted).await?;
+        }
+
+        let crl = self.sign(crl_number, &revoked)?;
+        *self.crl.write().await = crl;
+        event!(target: "MASA::Crl", Level::DEBUG, "Published CRL {} with {} entries", crl_number, revoked.len());
+        Ok(())
+    }
+
+    /// Encodes and signs the indirect CRL, DER encoded
+    fn sign(&self, crl_number: u64, revoked: &[RevokedCertificate]) -> anyhow::Result<Vec<u8>> {
+        let revoked_certificates = revoked
+            .iter()
+            .map(|revoked| {
+                let issuer = Name::from_der(&base64::decode_block(&revoked.issuer)?)?;
+                let mut extensions = Vec::new();
+                // RFC 5280 Section 5.3.1, the reason code is left out rather than being unspecified
+                if revoked.reason != RevocationReason::Unspecified {
+                    extensions.push(extension(CrlReason::OID, false, &CrlReason::from(revoked.reason))?);
+                }
+                extensions.push(extension(ID_CE_CERTIFICATE_ISSUER, true, &vec![GeneralName::DirectoryName(issuer)])?);
+
+                Ok(RevokedCert {
+                    serial_number: SerialNumber::new(&BigNum::from_hex_str(&revoked.serial_number)?.to_vec())?,
+                    revocation_date: time(SystemTime::UNIX_EPOCH + Duration::from_secs(revoked.revoked_at.timestamp().max(0) as u64))?,
+                    crl_entry_extensions: Some(extensions),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let crl_extensions = vec![
+            extension(
+                AuthorityKeyIdentifier::OID,
+                false,
+                &AuthorityKeyIdentifier {
+                    key_identifier: Some(OctetString::new(self.key_identifier.clone())?),
+                    ..Default::default()
+                },
+            )?,
+            extension(CrlNumber::OID, false, &CrlNumber(Uint::new(&crl_number.to_be_bytes())?))?,
+            extension(
+                ID_CE_ISSUING_DISTRIBUTION_POINT,
+                true,
+                &IssuingDistributionPoint {
+                    distribution_point: None,
+                    only_contains_user_certs: false,
+                    only_contains_ca_certs: false,
+                    only_some_reasons: None,
+                    indirect_crl: true,
+                    only_contains_attribute_certs: false,
+                },
+            )?,
+        ];
+
+        let digest = brski_prm_artifacts::digest::message_digest(&self.key);
+        let signature_algorithm = AlgorithmIdentifierOwned {
+            oid: match digest.type_() {
+                nid if nid == MessageDigest::sha384().type_() => ECDSA_WITH_SHA384,
+                nid if nid == MessageDigest::sha512().type_() => ECDSA_WITH_SHA512,
+                _ => ECDSA_WITH_SHA256,
+            },
+            parameters: None,
+        };
+
+        let this_update = SystemTime::now();
+        let tbs_cert_list = TbsCertList {
+            version: Version::V2,
+            signature: signature_algorithm.clone(),
+            issuer: self.issuer.clone(),
+            this_update: time(this_update)?,
+            next_update: Some(time(this_update + self.validity)?),
+            revoked_certificates: (!revoked_certificates.is_empty()).then_some(revoked_certificates),
+            crl_extensions: Some(crl_extensions),
+        };
+
+        let signature = Signer::new(digest, &self.key)?.sign_oneshot_to_vec(&tbs_cert_list.to_der()?)?;
+        let crl = CertificateList {
+            tbs_cert_list,
+            signature_algorithm,
+            signature: BitString::from_bytes(&signature)?,
+        };
+        Ok(crl.to_der()?)
+    }
+
+    /// Publishes a CRL every `interval`, failed ones are retried on the next
+    pub(crate) fn spawn_publish(self: &Arc<Self>, interval: Duration) {
+        let crl = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if let Err(err) = crl.publish().await {
+                    event!(target: "MASA::Crl", Level::WARN, "Publishing the CRL failed: {}", err);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::x509::X509Crl;
+
+    use super::*;
+
+    fn config(revocations_file: Option<&std::path::Path>) -> CrlConfig {
+        CrlConfig {
+            revocations_file: revocations_file.map(|path| path.to_str().unwrap().into()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (ca_certificate, ca_key) = &certs.vendor_ca;
+        let (registrar, _) = &certs.registrar;
+        let ca_key = ca_key.ec_key().unwrap();
+
+        let path = std::env::temp_dir().join(format!("open-brski-revocations-{}.json", std::process::id()));
+        let crl = RegistrarCrl::open(ca_certificate, &ca_key, &config(Some(&path))).await.unwrap();
+        assert!(!crl.is_revoked(registrar).await);
+
+        let revoked = crl.revoke(registrar, RevocationReason::KeyCompromise).await.unwrap();
+        assert!(crl.is_revoked(registrar).await);
+        assert!(!crl.is_revoked(&certs.pledge.0).await);
+        // revoking again keeps the first revocation
+        assert_eq!(crl.revoke(registrar, RevocationReason::Superseded).await.unwrap(), revoked);
+
+        let published = X509Crl::from_der(&crl.crl().await).unwrap();
+        assert!(published.verify(&ca_certificate.public_key().unwrap()).unwrap());
+        assert_eq!(published.issuer_name().to_der().unwrap(), ca_certificate.subject_name().to_der().unwrap());
+        let entries = published.get_revoked().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries.get(0).unwrap().serial_number().to_bn().unwrap(),
+            registrar.serial_number().to_bn().unwrap()
+        );
+
+        // the entry names the issuer of the registrar certificate in an indirect CRL
+        let decoded = CertificateList::from_der(&crl.crl().await).unwrap();
+        let extensions = decoded.tbs_cert_list.crl_extensions.unwrap();
+        let idp = extensions.iter().find(|extension| extension.extn_id == ID_CE_ISSUING_DISTRIBUTION_POINT).unwrap();
+        assert!(idp.critical);
+        assert!(IssuingDistributionPoint::from_der(idp.extn_value.as_bytes()).unwrap().indirect_crl);
+        let entry = &decoded.tbs_cert_list.revoked_certificates.unwrap()[0];
+        let certificate_issuer = entry
+            .crl_entry_extensions
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|extension| extension.extn_id == ID_CE_CERTIFICATE_ISSUER)
+            .unwrap();
+        assert!(certificate_issuer.critical);
+        assert_eq!(
+            Vec::<GeneralName>::from_der(certificate_issuer.extn_value.as_bytes()).unwrap(),
+            vec![GeneralName::DirectoryName(Name::from_der(&registrar.issuer_name().to_der().unwrap()).unwrap())]
+        );
+
+        // the revocations and the CRL number survive a restart
+        let reopened = RegistrarCrl::open(ca_certificate, &ca_key, &config(Some(&path))).await.unwrap();
+        assert!(reopened.is_revoked(registrar).await);
+        assert_eq!(reopened.revocations.read().await.crl_number, 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+//! Reports issued vouchers to the device management cloud of the manufacturer, so the device records there show
+//! which domain claimed a device.
+//!
+//! Reports are sent in the background and retried with backoff, an unreachable cloud never delays or fails the
+//! voucher. [`DeviceCloudConnector`] is the extension point for clouds that need more than one templated request,
+//! [`HttpConnector`] is the one configured by `[masa.device_cloud]`.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use cli::config::DeviceCloudConfig;
+use reqwest::{
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Method,
+};
+use tracing::{event, Level};
+
+use crate::audit_log::AuditEntry;
+
+/// Placeholders of the templates, see [`placeholder_values`]
+const PLACEHOLDERS: &[&str] =
+    &["serial-number", "domain-id", "registrar", "nonce", "nonceless", "assertion", "expires-on", "issued-at"];
+
+#[async_trait::async_trait]
+pub(crate) trait DeviceCloudConnector: Send + Sync {
+    /// Reports a single issued voucher, errors are retried
+    async fn report(&self, issued: &AuditEntry) -> anyhow::Result<()>;
+}
+
+/// Hands issued vouchers to the connector, if one is configured
+#[derive(Clone, Default)]
+pub(crate) struct DeviceCloud {
+    connector: Option<Arc<dyn DeviceCloudConnector>>,
+    attempts: u32,
+}
+
+impl DeviceCloud {
+    pub(crate) fn new(config: &DeviceCloudConfig, client: reqwest::Client) -> anyhow::Result<Self> {
+        let connector = match &config.url {
+            Some(_) => Some(Arc::new(HttpConnector::new(config, client)?) as Arc<dyn DeviceCloudConnector>),
+            None => None,
+        };
+        Ok(Self {
+            connector,
+            attempts: config.attempts,
+        })
+    }
+
+    pub(crate) fn report(&self, issued: AuditEntry) {
+        let Some(connector) = self.connector.clone() else {
+            return;
+        };
+
+        let attempts = self.attempts;
+        tokio::spawn(async move {
+            for attempt in 1..=attempts {
+                match connector.report(&issued).await {
+                    Ok(()) => {
+                        event!(target: "MASA::DeviceCloud", Level::DEBUG, "Reported voucher for {}", issued.serial_number);
+                        return;
+                    }
+                    Err(err) if attempt < attempts => {
+                        event!(target: "MASA::DeviceCloud", Level::DEBUG, "Reporting voucher for {} failed: {}", issued.serial_number, err);
+                        tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(6))).await;
+                    }
+                    Err(err) => {
+                        event!(target: "MASA::DeviceCloud", Level::ERROR, "Giving up on reporting voucher for {}: {}", issued.serial_number, err);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// One request per issued voucher, built from the templates of `[masa.device_cloud]`
+pub(crate) struct HttpConnector {
+    client: reqwest::Client,
+    method: Method,
+    url: Template,
+    headers: Vec<(HeaderName, Template)>,
+    body: Option<Template>,
+    content_type: HeaderValue,
+    timeout: Duration,
+}
+
+impl HttpConnector {
+    pub(crate) fn new(config: &DeviceCloudConfig, client: reqwest::Client) -> anyhow::Result<Self> {
+        let url = config.url.as_deref().ok_or(anyhow!("device_cloud url is not set"))?;
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((HeaderName::from_str(name)?, Template::parse(value)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            client,
+            method: Method::from_str(&config.method)?,
+            url: Template::parse(url)?,
+            headers,
+            body: config.body.as_deref().map(Template::parse).transpose()?,
+            content_type: HeaderValue::from_str(&config.content_type)?,
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    fn is_json(&self) -> bool {
+        let media_type = self.content_type.to_str().unwrap_or_default().split(';').next().unwrap_or_default().trim();
+        media_type == "application/json" || media_type.ends_with("+json")
+    }
+}
+
+#[async_trait::async_trait]
+impl DeviceCloudConnector for HttpConnector {
+    async fn report(&self, issued: &AuditEntry) -> anyhow::Result<()> {
+        let values = placeholder_values(issued);
+
+        let mut request = self
+            .client
+            .request(self.method.clone(), self.url.render(&values, percent_encode))
+            .timeout(self.timeout)
+            .header(CONTENT_TYPE, self.content_type.clone());
+        for (name, value) in &self.headers {
+            request = request.header(name, value.render(&values, str::to_string));
+        }
+
+        let body = match &self.body {
+            Some(body) if self.is_json() => body.render(&values, json_escape),
+            Some(body) => body.render(&values, str::to_string),
+            None => serde_json::to_string(issued)?,
+        };
+
+        request.body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+fn placeholder_values(issued: &AuditEntry) -> HashMap<&'static str, String> {
+    let assertion = issued
+        .assertion
+        .as_ref()
+        .and_then(|assertion| serde_json::to_value(assertion).ok())
+        .and_then(|assertion| assertion.as_str().map(str::to_string));
+
+    HashMap::from([
+        ("serial-number", issued.serial_number.clone()),
+        ("domain-id", issued.domain_id.clone().unwrap_or_default()),
+        ("registrar", issued.registrar.clone().unwrap_or_default()),
+        ("nonce", issued.nonce.clone().unwrap_or_default()),
+        ("nonceless", issued.nonceless.to_string()),
+        ("assertion", assertion.unwrap_or_default()),
+        ("expires-on", issued.expires_on.map(|expires_on| expires_on.to_rfc3339()).unwrap_or_default()),
+        ("issued-at", issued.timestamp.to_rfc3339()),
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+/// Text with `{{placeholder}}`s, unknown placeholders are rejected when the template is parsed
+#[derive(Debug, Clone, PartialEq)]
+struct Template(Vec<Part>);
+
+impl Template {
+    fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..].find("}}").ok_or(anyhow!("device_cloud template {:?} has an unclosed {{{{", template))?;
+            let name = rest[start + 2..start + end].trim();
+            let placeholder = PLACEHOLDERS
+                .iter()
+                .find(|placeholder| **placeholder == name)
+                .ok_or(anyhow!("device_cloud template has the unknown placeholder {:?}", name))?;
+
+            parts.push(Part::Text(rest[..start].to_string()));
+            parts.push(Part::Placeholder(placeholder));
+            rest = &rest[start + end + 2..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+        Ok(Self(parts))
+    }
+
+    fn render(&self, values: &HashMap<&'static str, String>, escape: impl Fn(&str) -> String) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(name) => escape(values.get(name).map(String::as_str).unwrap_or_default()),
+            })
+            .collect()
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The content of a JSON string, without the quotes
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, http::HeaderMap, routing::put, Router};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::audit_log::AuditOutcome;
+
+    fn issued() -> AuditEntry {
+        AuditEntry {
+            serial_number: "Gerät 0002/\"a\"".to_string(),
+            registrar: Some("registrar".to_string()),
+            domain_id: Some("c29sZA==".to_string()),
+            nonce: None,
+            nonceless: true,
+            assertion: None,
+            expires_on: None,
+            outcome: AuditOutcome::Issued,
+            timestamp: chrono::Utc::now(),
+            voucher_status: None,
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        assert!(Template::parse("{{serial-number}").is_err());
+        assert!(Template::parse("{{owner}}").is_err());
+
+        let values = placeholder_values(&issued());
+        let url = Template::parse("https://cloud/devices/{{ serial-number }}/owner").unwrap();
+        assert_eq!(url.render(&values, percent_encode), "https://cloud/devices/Ger%C3%A4t%200002%2F%22a%22/owner");
+
+        let body = Template::parse(r#"{"device": "{{serial-number}}", "owner": "{{domain-id}}", "nonceless": {{nonceless}}}"#).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body.render(&values, json_escape)).unwrap();
+        assert_eq!(body["device"], "Gerät 0002/\"a\"");
+        assert_eq!(body["owner"], "c29sZA==");
+        assert_eq!(body["nonceless"], true);
+    }
+
+    #[tokio::test]
+    async fn test_reports_with_retries() {
+        let (received, mut reports) = mpsc::unbounded_channel();
+        let failures = Arc::new(std::sync::atomic::AtomicUsize::new(1));
+        let app = Router::new().route(
+            "/devices/:serial_number",
+            put(move |Path(serial_number): Path<String>, headers: HeaderMap, body: String| async move {
+                if failures.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) > 0 {
+                    return axum::http::StatusCode::SERVICE_UNAVAILABLE;
+                }
+                received.send((serial_number, headers.get("authorization").cloned(), body)).unwrap();
+                axum::http::StatusCode::NO_CONTENT
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let cloud = DeviceCloud::new(
+            &DeviceCloudConfig {
+                url: Some(format!("http://{}/devices/{{{{serial-number}}}}", address)),
+                method: "PUT".to_string(),
+                headers: HashMap::from([("Authorization".to_string(), "Bearer token".to_string())]),
+                body: Some(r#"{"owner": "{{domain-id}}"}"#.to_string()),
+                ..Default::default()
+            },
+            reqwest::Client::new(),
+        )
+        .unwrap();
+        cloud.report(issued());
+
+        let (serial_number, authorization, body) =
+            tokio::time::timeout(Duration::from_secs(5), reports.recv()).await.unwrap().unwrap();
+        assert_eq!(serial_number, "Gerät 0002/\"a\"");
+        assert_eq!(authorization.unwrap(), "Bearer token");
+        assert_eq!(body, r#"{"owner": "c29sZA=="}"#);
+    }
+}
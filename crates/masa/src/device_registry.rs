@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use common::{error::AppError, server_error::ServerError};
+use rusqlite::{params, Connection, OpenFlags};
+use tokio::sync::Mutex;
+use tracing::{event, Level};
+
+/// Whether the domain requesting a voucher owns the pledge according to the device registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ownership {
+    Owned,
+    /// The serial-number is not in the registry, the device was never sold
+    UnknownDevice,
+    /// The device was sold to other domains
+    OtherOwner,
+}
+
+/// Serial-numbers of the sold devices and the domains they were sold to, identified by their domain ID (see
+/// [`crate::audit_log::domain_id`]). A device may belong to several domains.
+///
+/// CSV and JSON registries are read on start. A SQLite registry is queried for every voucher request, so the
+/// sales database can be updated while the MASA runs. Without a registry every domain owns every device.
+#[derive(Debug, Default)]
+pub(crate) enum DeviceRegistry {
+    #[default]
+    Unrestricted,
+    Table(HashMap<String, HashSet<String>>),
+    /// Table `devices` with the columns `serial_number` and `domain_id`, one row per owner
+    Sqlite(Mutex<Connection>),
+}
+
+fn sql_error(err: rusqlite::Error) -> ServerError {
+    anyhow::Error::from(err).into()
+}
+
+impl DeviceRegistry {
+    /// The format is chosen by the file extension: `.csv`, `.json`, or `.sqlite`/`.db`
+    pub(crate) fn open(path: Option<&Path>) -> anyhow::Result<Self, AppError> {
+        let Some(path) = path else {
+            return Ok(Self::Unrestricted);
+        };
+
+        event!(target: "MASA::DeviceRegistry", Level::INFO, "Opening device registry {:?}", path);
+        let registry = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Self::Table(parse_csv(&std::fs::read_to_string(path)?)?),
+            Some("json") => {
+                let owners: HashMap<String, HashSet<String>> = serde_json::from_slice(&std::fs::read(path)?)?;
+                Self::Table(owners)
+            }
+            Some("sqlite" | "db") => {
+                let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+                // fails on start instead of on the first voucher request if the table is missing
+                connection.prepare("SELECT serial_number, domain_id FROM devices LIMIT 1")?;
+                Self::Sqlite(Mutex::new(connection))
+            }
+            _ => return Err(anyhow!("device_registry must be a .csv, .json, .sqlite or .db file").into()),
+        };
+
+        if let Self::Table(owners) = &registry {
+            event!(target: "MASA::DeviceRegistry", Level::INFO, "Loaded {} devices", owners.len());
+        }
+        Ok(registry)
+    }
+
+    pub(crate) async fn ownership(&self, serial_number: &str, domain_id: &str) -> Result<Ownership, ServerError> {
+        let owners = match self {
+            Self::Unrestricted => return Ok(Ownership::Owned),
+            Self::Table(owners) => owners.get(serial_number).cloned().unwrap_or_default(),
+            Self::Sqlite(connection) => {
+                let connection = connection.lock().await;
+                let mut statement = connection
+                    .prepare_cached("SELECT domain_id FROM devices WHERE serial_number = ?1")
+                    .map_err(sql_error)?;
+                let owners = statement
+                    .query_map(params![serial_number], |row| row.get::<_, String>(0))
+                    .map_err(sql_error)?
+                    .collect::<Result<HashSet<String>, _>>()
+                    .map_err(sql_error)?;
+                owners
+            }
+        };
+
+        Ok(if owners.contains(domain_id) {
+            Ownership::Owned
+        } else if owners.is_empty() {
+            Ownership::UnknownDevice
+        } else {
+            Ownership::OtherOwner
+        })
+    }
+}
+
+/// One `serial-number,domain-id` line per owner, an optional header line and empty lines are skipped
+fn parse_csv(content: &str) -> anyhow::Result<HashMap<String, HashSet<String>>> {
+    let mut owners: HashMap<String, HashSet<String>> = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (index == 0 && line.eq_ignore_ascii_case("serial_number,domain_id")) {
+            continue;
+        }
+        match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+            [serial_number, domain_id] if !serial_number.is_empty() && !domain_id.is_empty() => {
+                owners.entry(serial_number.to_string()).or_default().insert(domain_id.to_string());
+            }
+            _ => return Err(anyhow!("device_registry line {} is not serial_number,domain_id", index + 1)),
+        }
+    }
+    Ok(owners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ownership() {
+        let path = std::env::temp_dir().join(format!("open-brski-devices-{}", std::process::id()));
+        let registries = [
+            ("csv", "serial_number,domain_id\n00-D0-E5-F2-00-02,c29sZA==\n00-D0-E5-F2-00-02,b3RoZXI=\n00-D0-E5-F2-00-03,b3RoZXI=\n"),
+            ("json", r#"{"00-D0-E5-F2-00-02": ["c29sZA==", "b3RoZXI="], "00-D0-E5-F2-00-03": ["b3RoZXI="]}"#),
+            ("sqlite", ""),
+        ];
+
+        for (extension, content) in registries {
+            let path = path.with_extension(extension);
+            if extension == "sqlite" {
+                let connection = Connection::open(&path).unwrap();
+                connection
+                    .execute_batch(
+                        "CREATE TABLE devices (serial_number TEXT NOT NULL, domain_id TEXT NOT NULL);
+                        INSERT INTO devices VALUES ('00-D0-E5-F2-00-02', 'c29sZA=='), ('00-D0-E5-F2-00-02', 'b3RoZXI='), ('00-D0-E5-F2-00-03', 'b3RoZXI=');",
+                    )
+                    .unwrap();
+            } else {
+                std::fs::write(&path, content).unwrap();
+            }
+
+            let registry = DeviceRegistry::open(Some(&path)).unwrap();
+            assert_eq!(registry.ownership("00-D0-E5-F2-00-02", "c29sZA==").await.unwrap(), Ownership::Owned);
+            assert_eq!(registry.ownership("00-D0-E5-F2-00-03", "c29sZA==").await.unwrap(), Ownership::OtherOwner);
+            assert_eq!(registry.ownership("00-D0-E5-F2-00-04", "c29sZA==").await.unwrap(), Ownership::UnknownDevice);
+            drop(registry);
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        let unrestricted = DeviceRegistry::open(None).unwrap();
+        assert_eq!(unrestricted.ownership("00-D0-E5-F2-00-04", "c29sZA==").await.unwrap(), Ownership::Owned);
+    }
+
+    #[test]
+    fn test_rejects_malformed_csv() {
+        assert!(parse_csv("00-D0-E5-F2-00-02\n").is_err());
+        assert!(parse_csv("00-D0-E5-F2-00-02,c29sZA==,extra\n").is_err());
+        assert_eq!(parse_csv("\n00-D0-E5-F2-00-02, c29sZA==\n").unwrap().len(), 1);
+    }
+}
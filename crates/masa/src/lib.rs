@@ -1,5 +1,14 @@
+mod anomalies;
+mod approvals;
+mod audit_log;
+mod crl;
+mod device_cloud;
+mod device_registry;
+mod metrics;
 mod parsed_config;
+mod policy;
 mod server;
+mod voucher_signing;
 
 use cli::config::{MasaConfig};
 use common::error::AppError;
@@ -15,14 +24,15 @@ pub async fn start(config: MasaConfig) -> anyhow::Result<JoinHandle<()>, AppErro
     event!(Level::DEBUG, "Received config {:?}", config);
     event!(Level::INFO, "Starting server on {}", address);
 
+    let http = config.http.clone();
     let parsed_config = parse_config(config)?;
 
     let app = server::get_app(&parsed_config).await?;
 
     let listener = tokio::net::TcpListener::bind(parsed_address).await?;
 
-    let server_handle = tokio::spawn(async {
-        axum::serve(listener, app).await.unwrap()
+    let server_handle = tokio::spawn(async move {
+        common::http_server::serve(listener, app, &http).await
     });
 
     Ok(server_handle)
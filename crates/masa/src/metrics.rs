@@ -0,0 +1,140 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use common::server_error::ServerError;
+
+/// Upper bounds of the latency buckets in seconds, the defaults of the Prometheus client libraries
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counters of the voucher requests handled since start, exported next to the certificate expiry at
+/// `/admin/metrics` if `voucher_metrics` is set
+#[derive(Debug, Default)]
+pub(crate) struct VoucherMetrics {
+    received: AtomicU64,
+    issued: AtomicU64,
+    /// Keyed by the `error` code of the problem details, e.g. `policy-violation`
+    denied: Mutex<BTreeMap<String, u64>>,
+    /// Requests that failed on the MASA side (5xx) rather than being denied, keyed by status code
+    failed: Mutex<BTreeMap<u16, u64>>,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_micros: AtomicU64,
+}
+
+impl VoucherMetrics {
+    /// Records a handled voucher request with its outcome and the time it took
+    pub(crate) fn record<T>(&self, result: &Result<T, ServerError>, latency: Duration) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                self.issued.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => {
+                let status = err.problem().status();
+                if status.is_server_error() {
+                    *self.failed.lock().unwrap().entry(status.as_u16()).or_default() += 1;
+                } else {
+                    *self.denied.lock().unwrap().entry(denial_reason(err)).or_default() += 1;
+                }
+            }
+        }
+
+        let seconds = latency.as_secs_f64();
+        for (bucket, _) in self.latency_buckets.iter().zip(LATENCY_BUCKETS).filter(|(_, bound)| seconds <= *bound) {
+            bucket.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Prometheus text exposition of the counters
+    pub(crate) fn metrics(&self) -> String {
+        let received = self.received.load(Ordering::Relaxed);
+        let mut metrics = String::new();
+
+        let _ = writeln!(metrics, "# HELP open_brski_voucher_requests_total Voucher requests received");
+        let _ = writeln!(metrics, "# TYPE open_brski_voucher_requests_total counter");
+        let _ = writeln!(metrics, "open_brski_voucher_requests_total {}", received);
+
+        let _ = writeln!(metrics, "# HELP open_brski_vouchers_issued_total Vouchers issued");
+        let _ = writeln!(metrics, "# TYPE open_brski_vouchers_issued_total counter");
+        let _ = writeln!(metrics, "open_brski_vouchers_issued_total {}", self.issued.load(Ordering::Relaxed));
+
+        let _ = writeln!(metrics, "# HELP open_brski_voucher_denials_total Voucher requests denied, by reason");
+        let _ = writeln!(metrics, "# TYPE open_brski_voucher_denials_total counter");
+        for (reason, count) in self.denied.lock().unwrap().iter() {
+            let _ = writeln!(metrics, "open_brski_voucher_denials_total{{reason=\"{}\"}} {}", reason, count);
+        }
+
+        let _ = writeln!(metrics, "# HELP open_brski_voucher_request_errors_total Voucher requests failed by the MASA, by status code");
+        let _ = writeln!(metrics, "# TYPE open_brski_voucher_request_errors_total counter");
+        for (status, count) in self.failed.lock().unwrap().iter() {
+            let _ = writeln!(metrics, "open_brski_voucher_request_errors_total{{status=\"{}\"}} {}", status, count);
+        }
+
+        let _ = writeln!(metrics, "# HELP open_brski_voucher_request_duration_seconds Time to handle a voucher request");
+        let _ = writeln!(metrics, "# TYPE open_brski_voucher_request_duration_seconds histogram");
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                metrics,
+                "open_brski_voucher_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(metrics, "open_brski_voucher_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", received);
+        let _ = writeln!(
+            metrics,
+            "open_brski_voucher_request_duration_seconds_sum {}",
+            self.latency_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(metrics, "open_brski_voucher_request_duration_seconds_count {}", received);
+        metrics
+    }
+}
+
+/// The `error` code registrars see in the problem details, so denials can be told apart without parsing messages
+fn denial_reason(err: &ServerError) -> String {
+    match err.problem().to_json().get("error").and_then(|code| code.as_str()) {
+        Some(code) => code.to_string(),
+        None => "bad-request".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = VoucherMetrics::default();
+        metrics.record(&Ok(()), Duration::from_millis(20));
+        metrics.record::<()>(
+            &Err(ServerError::PolicyViolation {
+                serial_number: "00-D0-E5-F2-00-02".to_string(),
+                reason: "nonceless".to_string(),
+            }),
+            Duration::from_millis(3),
+        );
+        metrics.record::<()>(&Err(ServerError::BadRequest), Duration::from_secs(30));
+        metrics.record::<()>(&Err(ServerError::Overloaded), Duration::from_millis(1));
+
+        let text = metrics.metrics();
+        assert!(text.contains("open_brski_voucher_requests_total 4\n"));
+        assert!(text.contains("open_brski_vouchers_issued_total 1\n"));
+        assert!(text.contains("open_brski_voucher_denials_total{reason=\"policy-violation\"} 1\n"));
+        assert!(text.contains("open_brski_voucher_denials_total{reason=\"bad-request\"} 1\n"));
+        assert!(!text.contains("open_brski_voucher_denials_total{reason=\"overloaded\"}"));
+        assert!(text.contains("open_brski_voucher_request_errors_total{status=\"503\"} 1\n"));
+        assert!(text.contains("open_brski_voucher_request_duration_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(text.contains("open_brski_voucher_request_duration_seconds_bucket{le=\"0.025\"} 3\n"));
+        assert!(text.contains("open_brski_voucher_request_duration_seconds_bucket{le=\"10\"} 3\n"));
+        assert!(text.contains("open_brski_voucher_request_duration_seconds_bucket{le=\"+Inf\"} 4\n"));
+        assert!(text.contains("open_brski_voucher_request_duration_seconds_count 4\n"));
+    }
+}
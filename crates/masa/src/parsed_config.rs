@@ -1,6 +1,7 @@
 use anyhow::anyhow;
 use cli::config::MasaConfig;
 use common::error::AppError;
+use common::key_usage::{check_key_usage, KeyRole};
 use openssl::ec::{self, EcKey};
 use openssl::pkey::{Private};
 use openssl::x509::X509;
@@ -12,6 +13,9 @@ pub(crate) struct ParsedConfig {
     pub(crate) ca_key: EcKey<Private>,
     pub(crate) masa_certificate: X509,
     pub(crate) masa_key: EcKey<Private>,
+    pub(crate) integrator_ca_certificates: Vec<X509>,
+    pub(crate) registrar_ca_certificates: Vec<X509>,
+    pub(crate) tsa_ca_certificates: Vec<X509>,
 }
 
 pub(crate) fn parse_config(config: MasaConfig) -> anyhow::Result<ParsedConfig, AppError> {
@@ -27,6 +31,28 @@ pub(crate) fn parse_config(config: MasaConfig) -> anyhow::Result<ParsedConfig, A
     let unparsed_masa_key = std::fs::read(config.masa_key.relative())?;
     let masa_key = ec::EcKey::private_key_from_pem(&unparsed_masa_key)?;
 
+    // the MASA serves plain HTTP behind a TLS terminating proxy, its only key signs vouchers
+    check_key_usage(&masa_certificate, KeyRole::VoucherSigning).map_err(|err| anyhow!("masa_certificate: {}", err))?;
+
+    let integrator_ca_certificates = config
+        .integrator_ca_certificates
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+        .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+    let registrar_ca_certificates = config
+        .registrar_ca_certificates
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+        .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+    let tsa_ca_certificates = config
+        .timestamping
+        .tsa_ca_certificates
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+        .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
     assert!(masa_certificate.verify(&openssl::pkey::PKey::from_ec_key(ca_key.clone()).unwrap()).unwrap());
     assert!(ca_certificate.verify(&openssl::pkey::PKey::from_ec_key(ca_key.clone()).unwrap()).unwrap());
 
@@ -36,5 +62,8 @@ pub(crate) fn parse_config(config: MasaConfig) -> anyhow::Result<ParsedConfig, A
         ca_key,
         masa_certificate,
         masa_key,
+        integrator_ca_certificates,
+        registrar_ca_certificates,
+        tsa_ca_certificates,
     })
 }
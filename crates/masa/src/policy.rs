@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use cli::config::VoucherValidity;
+use brski_prm_artifacts::ietf_voucher::{
+    assertion::Assertion, request_artifact::VoucherRequestArtifactDetails,
+};
+use openssl::x509::X509;
+use common::{
+    chain::verify_chain,
+    serial_pattern::{SerialPattern, SerialPatternError},
+};
+use tracing::{event, Level};
+
+/// Rules the MASA applies to a registrar voucher request before a voucher is issued.
+/// Voucher requests asserting `agent-proximity` were relayed by a registrar-agent (BRSKI-PRM),
+/// so the agent has to be vouched for by one of the configured integrator CAs.
+///
+/// Nonceless voucher requests are only answered if `voucher_validity` allows them.
+///
+/// The policy also decides which additional configuration is embedded into the voucher and when it expires.
+/// Keys are serial-number patterns, e.g. a serial-number or a device class given as a serial-number prefix ending in `*`.
+#[derive(Debug, Clone)]
+pub(crate) struct VoucherPolicy {
+    integrator_ca_certificates: Vec<X509>,
+    additional_configuration: Vec<(String, SerialPattern, String)>,
+    voucher_validity: VoucherValidity,
+}
+
+impl VoucherPolicy {
+    pub(crate) fn new(
+        integrator_ca_certificates: Vec<X509>,
+        additional_configuration: HashMap<String, String>,
+        voucher_validity: VoucherValidity,
+    ) -> Result<Self, SerialPatternError> {
+        let additional_configuration = additional_configuration
+            .into_iter()
+            .map(|(key, configuration)| Ok((key.clone(), key.parse()?, configuration)))
+            .collect::<Result<_, SerialPatternError>>()?;
+
+        Ok(Self {
+            integrator_ca_certificates,
+            additional_configuration,
+            voucher_validity,
+        })
+    }
+
+    /// The configuration for the exact serial-number wins, otherwise the most specific matching pattern.
+    /// Patterns of equal specificity are ordered by their key to stay deterministic.
+    pub(crate) fn additional_configuration(&self, serial_number: &str) -> Option<String> {
+        self.additional_configuration
+            .iter()
+            .filter(|(_, pattern, _)| pattern.matches(serial_number))
+            .max_by(|(a_key, a, _), (b_key, b, _)| a.specificity().cmp(&b.specificity()).then_with(|| b_key.cmp(a_key)))
+            .map(|(_, _, configuration)| configuration.clone())
+    }
+
+    /// `expires-on` of a voucher created at `created_on`, only nonceless vouchers expire
+    pub(crate) fn expires_on(&self, details: &VoucherRequestArtifactDetails, created_on: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        details
+            .nonce
+            .is_none()
+            .then(|| created_on + Duration::days(self.voucher_validity.nonceless_days.into()))
+    }
+
+    /// Returns the reason the request is rejected, `Ok` if a voucher may be issued
+    pub(crate) fn evaluate(&self, details: &VoucherRequestArtifactDetails) -> anyhow::Result<()> {
+        if details.nonce.is_none() && !self.voucher_validity.allow_nonceless {
+            return Err(anyhow!("nonceless voucher requests are not allowed"));
+        }
+
+        match details.assertion {
+            Some(Assertion::AgentProximity) => self.check_agent_proximity(details),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_agent_proximity(&self, details: &VoucherRequestArtifactDetails) -> anyhow::Result<()> {
+        if details.prior_signed_voucher_request.is_none() {
+            return Err(anyhow!("agent-proximity requires the pledge voucher request"));
+        }
+
+        let agent_certs = details
+            .agent_sign_cert
+            .as_deref()
+            .filter(|certs| !certs.is_empty())
+            .ok_or(anyhow!("agent-proximity requires the agent-sign-cert"))?;
+
+        if self.integrator_ca_certificates.is_empty() {
+            return Err(anyhow!("no integrator CA is registered for agent-proximity"));
+        }
+
+        let intermediates: Vec<X509> = agent_certs[1..].iter().map(|cert| X509::clone(cert)).collect();
+        if let Err(error) = verify_chain(&agent_certs[0], &self.integrator_ca_certificates, &intermediates)? {
+            event!(Level::WARN, "Agent certificate does not chain to an integrator CA: {}", error);
+            return Err(anyhow!("agent-sign-cert is not issued by a registered integrator CA"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent_proximity_request(certs: &example_certs::OpensslTestCerts) -> VoucherRequestArtifactDetails {
+        let (agent_cert, _) = &certs.registrar_agent;
+
+        let mut details = VoucherRequestArtifactDetails::default();
+        details.assertion = Some(Assertion::AgentProximity);
+        details.serial_number = "00-D0-E5-F2-00-02".to_string();
+        details.prior_signed_voucher_request = Some(b"pvr".to_vec());
+        details.agent_sign_cert = Some(vec![agent_cert.clone().into()]);
+        details.nonce = Some(b"nonce".to_vec());
+        details
+    }
+
+    #[test]
+    fn test_agent_must_chain_to_integrator_ca() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let (vendor_ca, _) = &certs.vendor_ca;
+        let request = agent_proximity_request(&certs);
+
+        assert!(VoucherPolicy::new(vec![registrar_ca.clone()], HashMap::new(), VoucherValidity::default()).unwrap().evaluate(&request).is_ok());
+        assert!(VoucherPolicy::new(vec![vendor_ca.clone()], HashMap::new(), VoucherValidity::default()).unwrap().evaluate(&request).is_err());
+        assert!(VoucherPolicy::new(vec![], HashMap::new(), VoucherValidity::default()).unwrap().evaluate(&request).is_err());
+    }
+
+    #[test]
+    fn test_agent_proximity_requires_agent_cert() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let policy = VoucherPolicy::new(vec![registrar_ca.clone()], HashMap::new(), VoucherValidity::default()).unwrap();
+
+        let mut request = agent_proximity_request(&certs);
+        request.agent_sign_cert = None;
+        assert!(policy.evaluate(&request).is_err());
+
+        request.assertion = Some(Assertion::Proximity);
+        assert!(policy.evaluate(&request).is_ok());
+    }
+
+    #[test]
+    fn test_additional_configuration_lookup() {
+        let policy = VoucherPolicy::new(
+            vec![],
+            HashMap::from([
+                ("00-D0-E5-F2-00-02".to_string(), "https://controller.example/device".to_string()),
+                ("00-D0-*".to_string(), "https://controller.example/d0".to_string()),
+                ("00-D0-E5-*".to_string(), "https://controller.example/d0-e5".to_string()),
+                ("00-D0-E5-F2-00-10..00-D0-E5-F2-00-1F".to_string(), "https://controller.example/batch-1".to_string()),
+                ("re:^11-".to_string(), "https://controller.example/11".to_string()),
+            ]),
+            VoucherValidity::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.additional_configuration("00-D0-E5-F2-00-02").as_deref(),
+            Some("https://controller.example/device")
+        );
+        assert_eq!(
+            policy.additional_configuration("00-D0-E5-F2-00-03").as_deref(),
+            Some("https://controller.example/d0-e5")
+        );
+        assert_eq!(
+            policy.additional_configuration("00-D0-AA-00-00-01").as_deref(),
+            Some("https://controller.example/d0")
+        );
+        assert_eq!(
+            policy.additional_configuration("00-D0-E5-F2-00-12").as_deref(),
+            Some("https://controller.example/batch-1")
+        );
+        assert_eq!(
+            policy.additional_configuration("11-22-33-44-55-66").as_deref(),
+            Some("https://controller.example/11")
+        );
+        assert_eq!(policy.additional_configuration("22-22-33-44-55-66"), None);
+    }
+
+    #[test]
+    fn test_nonceless_vouchers_expire() {
+        let created_on = Utc::now();
+        let mut request = VoucherRequestArtifactDetails::default();
+        request.assertion = Some(Assertion::Proximity);
+        request.nonce = Some(b"nonce".to_vec());
+
+        let policy = VoucherPolicy::new(
+            vec![],
+            HashMap::new(),
+            VoucherValidity { allow_nonceless: true, nonceless_days: 7 },
+        )
+        .unwrap();
+        assert!(policy.evaluate(&request).is_ok());
+        assert_eq!(policy.expires_on(&request, created_on), None);
+
+        request.nonce = None;
+        assert!(policy.evaluate(&request).is_ok());
+        assert_eq!(policy.expires_on(&request, created_on), Some(created_on + Duration::days(7)));
+
+        let policy = VoucherPolicy::new(
+            vec![],
+            HashMap::new(),
+            VoucherValidity { allow_nonceless: false, nonceless_days: 7 },
+        )
+        .unwrap();
+        assert!(policy.evaluate(&request).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use tracing::{event, Level};
+
+use crate::{
+    approvals::{Decision, PendingApproval},
+    server::server::ServerState,
+};
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_pending_approvals(State(state): State<ServerState>) -> Json<Vec<PendingApproval>> {
+    event!(Level::INFO, "Received pending approvals request");
+
+    Json(state.approvals.pending().await)
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_approve(State(state): State<ServerState>, Path(serial_number): Path<String>) {
+    event!(Level::INFO, "Approving voucher requests for {}", serial_number);
+
+    state.approvals.decide(&serial_number, Decision::Approved).await;
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_deny(State(state): State<ServerState>, Path(serial_number): Path<String>) {
+    event!(Level::INFO, "Denying voucher requests for {}", serial_number);
+
+    state.approvals.decide(&serial_number, Decision::Denied).await;
+}
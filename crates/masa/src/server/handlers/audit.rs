@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::{
+    anomalies::Alert,
+    audit_log::{AuditEntry, Checkpoint, VoucherStatusCounts},
+    server::server::ServerState,
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditLogQuery {
+    serial_number: Option<String>,
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_vouchers(State(state): State<ServerState>) -> Json<Vec<AuditEntry>> {
+    event!(Level::INFO, "Received voucher history request");
+
+    Json(state.audit_log.issued().await)
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_audit_log(
+    State(state): State<ServerState>,
+    Query(query): Query<AuditLogQuery>,
+) -> Json<Vec<AuditEntry>> {
+    event!(Level::INFO, "Received audit log request");
+
+    Json(state.audit_log.entries(query.serial_number.as_deref()).await)
+}
+
+/// Time-stamped checkpoints of the persisted audit log, newest first
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_checkpoints(State(state): State<ServerState>) -> Json<Vec<Checkpoint>> {
+    event!(Level::INFO, "Received audit log checkpoints request");
+
+    Json(state.audit_log.checkpoints().await)
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_alerts(State(state): State<ServerState>) -> Json<Vec<Alert>> {
+    event!(Level::INFO, "Received alerts request");
+
+    Json(state.audit_log.alerts().await)
+}
+
+/// Voucher status telemetry relayed by registrars, counted over the whole audit log
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_voucher_status_counts(State(state): State<ServerState>) -> Json<VoucherStatusCounts> {
+    event!(Level::INFO, "Received voucher status counts request");
+
+    Json(state.audit_log.voucher_status_counts().await)
+}
@@ -0,0 +1,23 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse, Json};
+use common::expiry::CertificateExpiry;
+use tracing::{event, Level};
+
+use crate::server::server::ServerState;
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_certificates(State(state): State<ServerState>) -> Json<Vec<CertificateExpiry>> {
+    event!(Level::INFO, "Received certificate expiry request");
+
+    Json(state.expiry.certificates().await)
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    event!(Level::DEBUG, "Received metrics request");
+
+    let mut metrics = state.expiry.metrics().await;
+    if let Some(voucher_metrics) = &state.voucher_metrics {
+        metrics.push_str(&voucher_metrics.metrics());
+    }
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], metrics)
+}
@@ -0,0 +1,12 @@
+use axum::response::Html;
+use tracing::{event, Level};
+
+// The console is a single static page that is compiled into the binary
+const CONSOLE: &str = include_str!("../../../assets/console.html");
+
+#[tracing::instrument(target = "MASA")]
+pub async fn handle_console() -> Html<&'static str> {
+    event!(Level::INFO, "Received console request");
+
+    Html(CONSOLE)
+}
@@ -1,13 +1,70 @@
 mod requestvoucher;
-use axum::{routing::post, Router};
+mod requestauditlog;
+mod voucher_status;
+mod console;
+mod audit;
+mod approvals;
+mod certificates;
+mod revocations;
+use axum::{middleware, routing::{get, post}, Router};
+use std::sync::Arc;
+
+use common::{
+    idempotency::{idempotent, IdempotencyCache},
+    limits::{enforce_body_limit, Artifact, ArtifactLimits},
+};
 
 
 use super::server::ServerState;
 
 #[tracing::instrument(target = "MASA")]
-pub(crate) fn brski_routes() -> Router<ServerState> {
-    Router::new().route(
-        "/requestvoucher",
-        post(requestvoucher::handle_requestvoucher),
-    )
+pub(crate) fn brski_routes(limits: &ArtifactLimits, idempotency: Arc<IdempotencyCache>) -> Router<ServerState> {
+    Router::new()
+        .route(
+            "/requestvoucher",
+            post(requestvoucher::handle_requestvoucher)
+                .layer(middleware::from_fn_with_state(idempotency, idempotent))
+                .layer(middleware::from_fn_with_state(limits.guard(Artifact::VoucherRequest), enforce_body_limit)),
+        )
+        .route(
+            "/requestauditlog",
+            post(requestauditlog::handle_requestauditlog)
+                .layer(middleware::from_fn_with_state(limits.guard(Artifact::VoucherRequest), enforce_body_limit)),
+        )
+        .route(
+            "/voucher_status",
+            post(voucher_status::handle_voucher_status)
+                .layer(middleware::from_fn_with_state(limits.guard(Artifact::Telemetry), enforce_body_limit)),
+        )
+}
+
+/// The console page itself carries no data, every call it makes goes through the authenticated admin routes
+#[tracing::instrument(target = "MASA")]
+pub(crate) fn console_routes() -> Router<ServerState> {
+    Router::new().route("/console", get(console::handle_console))
+}
+
+#[tracing::instrument(target = "MASA")]
+pub(crate) fn admin_routes() -> Router<ServerState> {
+    Router::new()
+        .route("/vouchers", get(audit::handle_vouchers))
+        .route("/audit-log", get(audit::handle_audit_log))
+        .route("/audit-log/checkpoints", get(audit::handle_checkpoints))
+        .route("/alerts", get(audit::handle_alerts))
+        .route("/voucher-status", get(audit::handle_voucher_status_counts))
+        .route("/approvals", get(approvals::handle_pending_approvals))
+        .route("/approvals/:serial_number/approve", post(approvals::handle_approve))
+        .route("/approvals/:serial_number/deny", post(approvals::handle_deny))
+        .route("/certificates", get(certificates::handle_certificates))
+        .route("/metrics", get(certificates::handle_metrics))
+        .route("/revocations", get(revocations::handle_revocations).post(revocations::handle_revoke))
+}
+
+/// Serves the CRL of the revoked registrar certificates at `path`, next to the BRSKI routes
+#[tracing::instrument(target = "MASA")]
+pub(crate) fn crl_routes(path: Option<&str>) -> Router<ServerState> {
+    match path {
+        Some(path) => Router::new().route(path, get(revocations::handle_crl)),
+        None => Router::new(),
+    }
 }
@@ -0,0 +1,212 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use brski_prm_artifacts::{ietf_voucher::assertion::Assertion, rvr::RVR_JWS};
+use chrono::{DateTime, Utc};
+use common::{
+    chain::verify_chain,
+    server_error::ServerError,
+    util::{from_hex, hex},
+};
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    audit_log::{domain_id, AuditEntry, AuditOutcome, HISTORY_PAGE_SIZE},
+    server::server::ServerState,
+};
+
+/// Event of the audit log response, see RFC 8995 Section 5.8.1. The response is written by [`AuditLogStream`],
+/// nothing is truncated, so `truncation` is left out. A paged response adds a `continuation` token.
+#[derive(Serialize, Debug)]
+pub struct AuditLogEvent {
+    date: DateTime<Utc>,
+    #[serde(rename = "domainID")]
+    domain_id: Option<String>,
+    nonce: Option<String>,
+    assertion: Option<Assertion>,
+    truncated: String,
+}
+
+impl From<AuditEntry> for AuditLogEvent {
+    fn from(entry: AuditEntry) -> Self {
+        Self {
+            date: entry.timestamp,
+            domain_id: entry.domain_id,
+            nonce: entry.nonce,
+            assertion: entry.assertion,
+            truncated: "0".to_string(),
+        }
+    }
+}
+
+/// Optional paging of the audit log, a response without `limit` carries the whole log
+#[derive(Deserialize, Debug)]
+pub struct AuditLogPaging {
+    /// Maximum number of events in the response
+    limit: Option<usize>,
+    /// Token of the previous response to continue after its last event
+    continuation: Option<String>,
+}
+
+/// Opaque to the registrar: hex encoded position in the audit log store and the serial-number of the pledge
+fn continuation_token(serial_number: &str, position: u64) -> String {
+    hex(format!("{}:{}", position, serial_number).as_bytes())
+}
+
+fn continuation_position(token: &str, serial_number: &str) -> Result<u64, ServerError> {
+    let invalid = || ServerError::BadRequestWithReason("Invalid audit log continuation token".to_string());
+
+    let bytes = from_hex(token).ok_or_else(invalid)?;
+    let decoded = String::from_utf8(bytes).map_err(|_| invalid())?;
+    match decoded.split_once(':') {
+        Some((position, serial)) if serial == serial_number => position.parse().map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Writes the response body chunk by chunk, each chunk is one page of the history of the pledge
+struct AuditLogStream {
+    state: ServerState,
+    serial_number: String,
+    position: u64,
+    /// Events left until the limit of the request
+    remaining: Option<usize>,
+    started: bool,
+    first_event: bool,
+    finished: bool,
+}
+
+impl AuditLogStream {
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, ServerError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut chunk = vec![];
+        if !self.started {
+            chunk.extend_from_slice(br#"{"version":"1","events":["#);
+            self.started = true;
+        }
+
+        let page = self
+            .state
+            .audit_log
+            .history_page(&self.serial_number, self.position, HISTORY_PAGE_SIZE)
+            .await?;
+
+        for (position, entry) in page.entries {
+            if self.remaining == Some(0) {
+                // the limit is reached, the registrar continues with the entry after the last event
+                chunk.extend_from_slice(format!(r#"],"continuation":"{}"}}"#, continuation_token(&self.serial_number, position)).as_bytes());
+                self.finished = true;
+                return Ok(Some(chunk));
+            }
+            if entry.outcome != AuditOutcome::Issued {
+                continue;
+            }
+            if !self.first_event {
+                chunk.push(b',');
+            }
+            self.first_event = false;
+            serde_json::to_writer(&mut chunk, &AuditLogEvent::from(entry))?;
+            self.remaining = self.remaining.map(|remaining| remaining - 1);
+        }
+
+        match page.next {
+            None => {
+                chunk.extend_from_slice(b"]}");
+                self.finished = true;
+            }
+            Some(next) if self.remaining == Some(0) => {
+                chunk.extend_from_slice(format!(r#"],"continuation":"{}"}}"#, continuation_token(&self.serial_number, next)).as_bytes());
+                self.finished = true;
+            }
+            Some(next) => self.position = next,
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// The registrar posts the voucher request it sent for a pledge and gets all vouchers issued for that pledge,
+/// also those issued to other domains. Only domains that were issued a voucher for the pledge themselves may ask:
+/// the request has to be signed with its certificate to pin, which has to chain to a `registrar_ca_certificates`.
+/// The response is streamed page by page, so long histories neither time out nor have to fit into memory.
+/// With `limit` the response ends after as many events and carries a `continuation` token for the next request,
+/// which may come back without events if only entries of other outcomes remained.
+#[tracing::instrument(target = "MASA", skip(state, body))]
+pub async fn handle_requestauditlog(
+    State(state): State<ServerState>,
+    Query(paging): Query<AuditLogPaging>,
+    body: String,
+) -> Result<Response, ServerError> {
+    event!(Level::INFO, "Received requestauditlog request");
+
+    let rvr_jws = RVR_JWS::Encoded(body);
+    let rvr = state.verification.run(move || rvr_jws.decode()).await??.try_decoded_data()?;
+
+    let serial_number = rvr.payload.details.serial_number.clone();
+    let cert_to_pin = rvr
+        .payload
+        .details
+        .agent_provided_proximity_registrar_cert
+        .ok_or(ServerError::BadRequestWithReason("Registrar did not provide certificate to pin".to_string()))?;
+
+    // the domain is only taken from a certificate the registrar proved to hold the key of, issued by a registrar CA
+    let chain: Vec<X509> = rvr
+        .header
+        .as_ref()
+        .and_then(|header| header.x509_certificate_chain())
+        .unwrap_or_default()
+        .iter()
+        .map(|der| X509::from_der(der))
+        .collect::<Result<_, _>>()?;
+    let unauthorized = |reason: String| ServerError::PolicyViolation {
+        serial_number: serial_number.clone(),
+        reason,
+    };
+    if chain.first().map(|signer| signer.to_der()).transpose()? != Some(cert_to_pin.to_der()?) {
+        return Err(unauthorized("the voucher request is not signed with the certificate to pin".to_string()));
+    }
+    if let Err(error) = verify_chain(&cert_to_pin, &state.config.registrar_ca_certificates, &chain[1..])? {
+        return Err(unauthorized(format!("the certificate to pin is not issued by a registrar CA: {}", error)));
+    }
+    let requesting_domain = domain_id(&cert_to_pin)?;
+
+    if paging.limit == Some(0) {
+        return Err(ServerError::BadRequestWithReason("The audit log limit has to be positive".to_string()));
+    }
+    let position = paging
+        .continuation
+        .as_deref()
+        .map(|token| continuation_position(token, &serial_number))
+        .transpose()?
+        .unwrap_or(0);
+
+    if !state.audit_log.issued_to(&serial_number, &requesting_domain).await? {
+        return Err(ServerError::PolicyViolation {
+            serial_number,
+            reason: "the audit log is only available to domains that were issued a voucher for the pledge".to_string(),
+        });
+    }
+
+    event!(Level::INFO, "Streaming audit log events for {} from {}", serial_number, position);
+    let stream = AuditLogStream {
+        state,
+        serial_number,
+        position,
+        remaining: paging.limit,
+        started: false,
+        first_event: true,
+        finished: false,
+    };
+    let body = Body::from_stream(futures::stream::try_unfold(stream, |mut stream| async move {
+        Ok::<_, ServerError>(stream.next_chunk().await?.map(|chunk| (chunk, stream)))
+    }));
+
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
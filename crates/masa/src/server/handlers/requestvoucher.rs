@@ -1,3 +1,5 @@
+use std::{sync::Arc, time::Instant};
+
 use axum::{
     extract::State,
     http::{
@@ -7,9 +9,13 @@ use axum::{
 };
 use brski_prm_artifacts::{ietf_voucher::artifact::{VoucherArtifact, VoucherArtifactDetails}, issued_voucher::{IssuedVoucher, IssuedVoucherJWS}, rvr::RVR_JWS};
 use common::{server_error::ServerError, util::is_jws_voucher};
+use openssl::x509::X509;
 use tracing::{event, Level};
 
-use crate::{server::server::ServerState};
+use crate::{approvals::Decision, audit_log::{domain_id, AuditEntry, AuditOutcome}, device_registry::Ownership, server::server::ServerState};
+
+/// Unprotected header parameter of the MASA signature carrying the time-stamp token of the voucher
+const TIMESTAMP_TOKEN_HEADER: &str = "timestamp-token";
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "MASA", skip(state, headers, body))]
@@ -18,7 +24,15 @@ pub async fn handle_requestvoucher(
     headers: HeaderMap,
     body: String,
 ) -> Result<IssuedVoucherJWS, ServerError> {
+    let started = Instant::now();
+    let result = issue_voucher(&state, headers, body).await;
+    if let Some(voucher_metrics) = &state.voucher_metrics {
+        voucher_metrics.record(&result, started.elapsed());
+    }
+    result
+}
 
+async fn issue_voucher(state: &ServerState, headers: HeaderMap, body: String) -> Result<IssuedVoucherJWS, ServerError> {
     event!(Level::DEBUG, "Headers: {:#?}", headers);
     event!(Level::DEBUG, "Body: {:#?}", body);
     event!(Level::INFO, "Received requestvoucher request");
@@ -31,32 +45,157 @@ pub async fn handle_requestvoucher(
     // parse the rvr
 
     event!(Level::INFO, "Parsing RVR JWS from body");
-    let RVR_JWS = RVR_JWS::Encoded(body);
-    event!(Level::DEBUG, "RVR_JWS: {:#?}", RVR_JWS);
+    let rvr_jws = RVR_JWS::Encoded(body);
+    event!(Level::DEBUG, "RVR_JWS: {:#?}", rvr_jws);
 
     event!(Level::INFO, "Decoding RVR JWS");
-    let rvr = RVR_JWS.decode()?.try_decoded_data()?;
+    let rvr = state.verification.run(move || rvr_jws.decode()).await??.try_decoded_data()?;
 
     event!(Level::DEBUG, "RVR: {:#?}", rvr);
 
-    let cert_to_pin = rvr.payload.details.agent_provided_proximity_registrar_cert.ok_or(ServerError::BadRequestWithReason("Registrar did not provide certificate to pin".to_string()))?;
+    let cert_to_pin = rvr.payload.details.agent_provided_proximity_registrar_cert.clone().ok_or(ServerError::BadRequestWithReason("Registrar did not provide certificate to pin".to_string()))?;
     event!(Level::DEBUG, "Registrar requested cert to pin: {:#?}", cert_to_pin);
 
+    let serial_number = rvr.payload.details.serial_number.clone();
+    let registrar = cert_to_pin
+        .subject_name()
+        .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok());
+    let domain_id = domain_id(&cert_to_pin)?;
+    let nonce = rvr.payload.details.nonce.as_deref().map(openssl::base64::encode_block);
+
+    let audit_entry = |outcome, nonceless, assertion, expires_on| AuditEntry {
+        serial_number: serial_number.clone(),
+        registrar: registrar.clone(),
+        domain_id: Some(domain_id.clone()),
+        nonce: nonce.clone(),
+        nonceless,
+        assertion,
+        expires_on,
+        outcome,
+        timestamp: chrono::Utc::now(),
+        voucher_status: None,
+    };
+
+    let policy = Arc::clone(&state.policy);
+    let details = rvr.payload.details.clone();
+    match state.verification.run(move || policy.evaluate(&details)).await? {
+        Ok(()) if state.config.config.policy_dry_run => {
+            event!(target: "MASA::Policy", Level::INFO, "Dry run: voucher request for {} passes the voucher policy", serial_number);
+        }
+        Ok(()) => {}
+        Err(reason) if state.config.config.policy_dry_run => {
+            event!(target: "MASA::Policy", Level::WARN, "Dry run: voucher request for {} would be denied by the voucher policy: {}", serial_number, reason);
+        }
+        Err(reason) => {
+            event!(Level::WARN, "Voucher request for {} violates the voucher policy: {}", serial_number, reason);
+            state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+            return Err(ServerError::PolicyViolation {
+                serial_number: serial_number.clone(),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    let ownership = state.device_registry.ownership(&serial_number, &domain_id).await?;
+    if ownership != Ownership::Owned {
+        let reason = match ownership {
+            Ownership::UnknownDevice => "the serial-number is not in the device registry",
+            _ => "the device registry records another owner",
+        };
+        event!(Level::WARN, "Voucher request for {} from {:?}: {}", serial_number, registrar, reason);
+        state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+        return Err(ServerError::OwnershipMismatch {
+            serial_number: serial_number.clone(),
+            reason: reason.to_string(),
+        });
+    }
+
+    // the signer of the RVR and the certificate to pin are usually the same registrar EE certificate
+    let mut registrar_certs: Vec<X509> = vec![(*cert_to_pin).clone()];
+    let mut issuers = vec![];
+    if let Some(chain) = rvr.header.as_ref().and_then(|header| header.x509_certificate_chain()) {
+        let mut chain = chain.iter().filter_map(|der| X509::from_der(der).ok());
+        if let Some(signer) = chain.next() {
+            if signer.to_der().ok() != cert_to_pin.to_der().ok() {
+                registrar_certs.push(signer);
+            }
+        }
+        issuers.extend(chain);
+    }
+    issuers.extend(state.config.registrar_ca_certificates.iter().cloned());
+    issuers.extend(state.config.integrator_ca_certificates.iter().cloned());
+
+    for cert in &registrar_certs {
+        if state.crl.is_revoked(cert).await {
+            event!(Level::WARN, "Registrar {:?} was revoked by the MASA", registrar);
+            state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+            return Err(ServerError::RevocationCheckFailed {
+                subject: registrar.clone().unwrap_or_else(|| format!("{:?}", cert.subject_name())),
+                reason: "certificate is revoked by the MASA".to_string(),
+            });
+        }
+        if let Err(reason) = state.revocation.check(cert, &issuers).await {
+            event!(Level::WARN, "Revocation check of registrar {:?} failed: {}", registrar, reason);
+            state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+            return Err(ServerError::RevocationCheckFailed {
+                subject: registrar.clone().unwrap_or_else(|| format!("{:?}", cert.subject_name())),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    if state.config.config.manual_approval {
+        match state.approvals.check(&serial_number, registrar.as_deref()).await {
+            Some(Decision::Approved) => {
+                event!(Level::INFO, "Voucher requests for {} are approved", serial_number);
+            }
+            Some(Decision::Denied) => {
+                state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+                return Err(ServerError::PledgeBlocked {
+                    serial_number: serial_number.clone(),
+                    reason: "voucher requests were denied by the manufacturer".to_string(),
+                });
+            }
+            None => {
+                state.audit_log.record(audit_entry(AuditOutcome::PendingApproval, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+                return Err(ServerError::ApprovalPending { serial_number: serial_number.clone() });
+            }
+        }
+    }
+
+    let signer = match state.voucher_signers.select(&rvr.payload.details) {
+        Ok(signer) => signer,
+        Err(reason) => {
+            event!(Level::WARN, "Voucher request for {} from {:?}: {}", serial_number, registrar, reason);
+            state.audit_log.record(audit_entry(AuditOutcome::Denied, rvr.payload.details.nonce.is_none(), rvr.payload.details.assertion.clone(), None)).await?;
+            return Err(ServerError::BadRequestWithReason(reason));
+        }
+    };
+    event!(Level::INFO, "Signing voucher with {}", signer.algorithm);
+
     event!(Level::INFO, "Building voucher");
+    let created_on = chrono::Utc::now();
+    let expires_on = state.policy.expires_on(&rvr.payload.details, created_on);
     let mut voucher_details = VoucherArtifactDetails::default();
 
     // skip verification for now
     voucher_details.assertion = rvr.payload.details.assertion;
     voucher_details.serial_number = rvr.payload.details.serial_number;
     voucher_details.nonce = rvr.payload.details.nonce;
-    voucher_details.created_on = Some(chrono::Utc::now());
+    voucher_details.created_on = Some(created_on);
+    voucher_details.expires_on = expires_on;
     voucher_details.pinned_domain_cert = Some(cert_to_pin);
+    voucher_details.additional_configuration = state.policy.additional_configuration(&voucher_details.serial_number);
+
+    let issued_entry = audit_entry(AuditOutcome::Issued, voucher_details.nonce.is_none(), voucher_details.assertion.clone(), voucher_details.expires_on);
 
     let voucher_artifact = VoucherArtifact {
         details: voucher_details
     };
 
-    let issued_voucher = IssuedVoucher::new(voucher_artifact, [state.config.masa_certificate.clone()]);
+    let issued_voucher = IssuedVoucher::new(voucher_artifact, [signer.certificate.clone()]);
 
     event!(Level::INFO, "Built Voucher");
     event!(Level::DEBUG, "Issued Voucher: {:#?}", issued_voucher);
@@ -64,10 +203,43 @@ pub async fn handle_requestvoucher(
     event!(Level::INFO, "Encoding Voucher as JWS");
     let jws: IssuedVoucherJWS = issued_voucher.try_into()?;
 
-    let jws = jws.encode(state.config.masa_key.private_key_to_der().unwrap())?;
-    jws.verify()?;
+    let masa_key = signer.key_der()?;
+    let jws = state
+        .verification
+        .run(move || {
+            let jws = jws.encode(masa_key)?;
+            jws.verify()?;
+            Ok::<_, josekit::JoseError>(jws)
+        })
+        .await??;
+    let jws = timestamp_voucher(state, jws).await?;
     event!(Level::DEBUG, "IssuedVoucherJWS: {:#?}", jws);
 
+    // recorded once nothing can keep the voucher from being issued anymore
+    state.audit_log.record(issued_entry.clone()).await?;
+    state.device_cloud.report(issued_entry);
+
     event!(Level::INFO, "Issued voucher!");
     Ok(jws)
 }
+
+/// Embeds an RFC 3161 time-stamp token over the MASA signature value into the unprotected header of the signature,
+/// base64 encoded like `x5c`. The voucher stays verifiable after the MASA certificate expired.
+async fn timestamp_voucher(state: &ServerState, jws: IssuedVoucherJWS) -> Result<IssuedVoucherJWS, ServerError> {
+    let Some(timestamps) = &state.timestamps else {
+        return Ok(jws);
+    };
+
+    let signature = jws.signature_values()?.into_iter().next().ok_or(ServerError::BadResponse("voucher is not signed".to_string()))?;
+    match timestamps.timestamp(&openssl::sha::sha256(&signature)).await {
+        Ok(token) => {
+            event!(Level::INFO, "Time-stamped voucher at {}", token.time);
+            Ok(jws.set_unprotected_header(0, TIMESTAMP_TOKEN_HEADER, openssl::base64::encode_block(&token.der).into())?)
+        }
+        Err(err) if state.config.config.timestamping.required => Err(ServerError::TimestampUnavailable { reason: err.to_string() }),
+        Err(err) => {
+            event!(Level::WARN, "Issuing voucher without time-stamp: {}", err);
+            Ok(jws)
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse, Json};
+use brski_prm_artifacts::content_type::PKIX_CRL;
+use common::server_error::ServerError;
+use openssl::x509::X509;
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::{
+    crl::{RevocationReason, RevokedCertificate},
+    server::server::ServerState,
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RevocationRequest {
+    /// PEM encoded registrar certificate
+    certificate: String,
+    #[serde(default)]
+    reason: RevocationReason,
+}
+
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_revocations(State(state): State<ServerState>) -> Json<Vec<RevokedCertificate>> {
+    event!(Level::INFO, "Received revoked registrar certificates request");
+
+    Json(state.crl.revoked().await)
+}
+
+#[tracing::instrument(target = "MASA", skip(state, request))]
+pub async fn handle_revoke(
+    State(state): State<ServerState>,
+    Json(request): Json<RevocationRequest>,
+) -> Result<Json<RevokedCertificate>, ServerError> {
+    event!(Level::INFO, "Received registrar certificate revocation");
+
+    let certificate = X509::from_pem(request.certificate.as_bytes())
+        .map_err(|err| ServerError::BadRequestWithReason(format!("certificate is not a PEM encoded certificate: {}", err)))?;
+    Ok(Json(state.crl.revoke(&certificate, request.reason).await?))
+}
+
+/// The CRL of the revoked registrar certificates, served at the configured `crl.path` without authentication
+#[tracing::instrument(target = "MASA", skip(state))]
+pub async fn handle_crl(State(state): State<ServerState>) -> impl IntoResponse {
+    event!(Level::DEBUG, "Received CRL request");
+
+    ([(CONTENT_TYPE, PKIX_CRL)], state.crl.crl().await)
+}
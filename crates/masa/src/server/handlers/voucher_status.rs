@@ -0,0 +1,66 @@
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+};
+use brski_prm_artifacts::{ietf_voucher::serial_number, jws::JWS, status::voucher::response::vStatus_JWS};
+use common::{chain::verify_chain, server_error::ServerError, util::is_jose};
+use openssl::x509::X509;
+use tracing::{event, Level};
+
+use crate::server::server::ServerState;
+
+/// The registrar relays the voucher status telemetry of a pledge as the pledge signed it. The status is only
+/// accepted if it is signed by an IDevID issued by the MASA CA and is recorded next to the latest voucher issued for
+/// the pledge, see [`crate::audit_log::AuditLog::record_voucher_status`] for the statuses that are rejected.
+#[tracing::instrument(target = "MASA", skip(state, headers, body))]
+pub async fn handle_voucher_status(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(), ServerError> {
+    event!(Level::INFO, "Received voucher_status request");
+
+    let content_type = headers.get(CONTENT_TYPE).ok_or(ServerError::BadRequest)?.to_str().map_err(|_| ServerError::BadRequest)?;
+    is_jose(CONTENT_TYPE, content_type)?;
+
+    // decoding checks the signature against the first certificate of the x5c header
+    let jws: vStatus_JWS = JWS::Encoded(body);
+    let decoded = jws.decode()?.try_decoded_data()?;
+
+    let chain = decoded
+        .header
+        .as_ref()
+        .and_then(|header| header.x509_certificate_chain())
+        .ok_or(ServerError::BadRequestWithReason("Voucher status carries no IDevID".to_string()))?
+        .iter()
+        .map(|der| X509::from_der(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (idevid, intermediates) = chain
+        .split_first()
+        .ok_or(ServerError::BadRequestWithReason("Voucher status carries no IDevID".to_string()))?;
+
+    if !issued_by(idevid, intermediates, &state.config.ca_certificate)? {
+        return Err(ServerError::BadRequestWithReason("Voucher status is not signed by an IDevID of this manufacturer".to_string()));
+    }
+
+    let serial_number = serial_number::from_name(idevid.subject_name())
+        .map_err(|err| ServerError::BadRequestWithReason(format!("IDevID: {}", err)))?;
+
+    let status = decoded.payload;
+    event!(Level::INFO, "Voucher status of {}: {}", serial_number, status.status);
+
+    state
+        .audit_log
+        .record_voucher_status(&serial_number, status.status, status.reason, status.reason_code, status.attestation)
+        .await
+}
+
+fn issued_by(idevid: &X509, intermediates: &[X509], ca: &X509) -> Result<bool, openssl::error::ErrorStack> {
+    match verify_chain(idevid, std::slice::from_ref(ca), intermediates)? {
+        Ok(()) => Ok(true),
+        Err(error) => {
+            event!(Level::WARN, "IDevID of the voucher status does not chain to the MASA CA: {}", error);
+            Ok(false)
+        }
+    }
+}
@@ -1,28 +1,143 @@
+use std::{sync::Arc, time::Duration};
+
 use crate::{
+    anomalies::AnomalyDetector,
+    approvals::Approvals,
+    audit_log::AuditLog,
+    crl::RegistrarCrl,
+    device_cloud::DeviceCloud,
+    device_registry::DeviceRegistry,
+    metrics::VoucherMetrics,
     parsed_config::{ParsedConfig},
+    policy::VoucherPolicy,
+    voucher_signing::VoucherSigners,
+};
+use axum::{middleware, Router};
+use common::{
+    auth::{require_scope, Authenticator},
+    error::AppError,
+    expiry::ExpiryMonitor,
+    idempotency::IdempotencyCache,
+    limits::{enforce_body_limit, Artifact},
+    revocation::RevocationChecker,
+    timestamp::TimestampClient,
+    verification::VerificationPool,
 };
-use axum::{Router};
-use common::error::AppError;
 use reqwest::Client;
 use tower_http::trace::TraceLayer;
+use tracing::{event, Level};
 
-use super::handlers::brski_routes;
+use super::handlers::{admin_routes, brski_routes, console_routes, crl_routes};
 
 #[derive(Clone)]
 pub struct ServerState {
     pub config: ParsedConfig,
     pub client: reqwest::Client,
+    pub(crate) audit_log: Arc<AuditLog>,
+    pub(crate) approvals: Arc<Approvals>,
+    pub(crate) device_registry: Arc<DeviceRegistry>,
+    pub(crate) policy: Arc<VoucherPolicy>,
+    pub(crate) revocation: Arc<RevocationChecker>,
+    pub(crate) expiry: Arc<ExpiryMonitor>,
+    pub(crate) timestamps: Option<Arc<TimestampClient>>,
+    pub(crate) verification: VerificationPool,
+    pub(crate) crl: Arc<RegistrarCrl>,
+    pub(crate) device_cloud: DeviceCloud,
+    pub(crate) voucher_metrics: Option<Arc<VoucherMetrics>>,
+    pub(crate) voucher_signers: Arc<VoucherSigners>,
 }
 
 pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppError> {
     let client = Client::new();
 
+    let revocation = Arc::new(RevocationChecker::new(config.config.registrar_revocation, client.clone()));
+    revocation.spawn_refresh(Duration::from_secs(config.config.revocation_refresh_secs));
+
+    let expiry = Arc::new(ExpiryMonitor::new(
+        config.config.expiry_warning_days,
+        config.config.expiry_webhook_url.clone(),
+        client.clone(),
+    ));
+    expiry.track("ca", &config.ca_certificate).await;
+    expiry.track("masa", &config.masa_certificate).await;
+    let voucher_signers = Arc::new(VoucherSigners::load(config)?);
+    for (index, signer) in voucher_signers.additional().iter().enumerate() {
+        expiry.track(format!("voucher-signer:{}", index), &signer.certificate).await;
+    }
+    for (index, cert) in config.integrator_ca_certificates.iter().enumerate() {
+        expiry.track(format!("integrator-ca:{}", index), cert).await;
+    }
+    expiry.spawn(Duration::from_secs(config.config.expiry_check_secs));
+
+    let timestamping = &config.config.timestamping;
+    let timestamps = match &timestamping.tsa_url {
+        Some(url) => Some(Arc::new(
+            TimestampClient::new(url.clone(), timestamping.policy.clone(), &config.tsa_ca_certificates, client.clone())?,
+        )),
+        None => None,
+    };
+
+    let audit_log = Arc::new(AuditLog::open(
+        AnomalyDetector::new(config.config.anomaly_registrars_per_serial, config.config.anomaly_nonceless_burst)
+            .with_webhook(config.config.anomaly_webhook_url.clone(), client.clone()),
+        config.config.audit_log_file.as_ref().map(|path| path.relative()).as_deref(),
+        config.config.audit_log_database.as_ref().map(|path| path.relative()).as_deref(),
+    )?);
+    if let (Some(timestamps), 1..) = (&timestamps, timestamping.checkpoint_secs) {
+        audit_log.spawn_checkpoints(timestamps.clone(), Duration::from_secs(timestamping.checkpoint_secs));
+    }
+
+    let crl = Arc::new(RegistrarCrl::open(&config.ca_certificate, &config.ca_key, &config.config.crl).await?);
+    crl.spawn_publish(Duration::from_secs(config.config.crl.publish_secs));
+
+    if config.config.policy_dry_run {
+        event!(target: "MASA::Policy", Level::WARN, "Policy dry run, voucher requests violating the voucher policy are only logged");
+    }
+
     let state = ServerState {
         config: config.clone(),
         client: client.clone(),
+        audit_log,
+        approvals: Arc::new(Approvals::default()),
+        device_registry: Arc::new(DeviceRegistry::open(
+            config.config.device_registry.as_ref().map(|path| path.relative()).as_deref(),
+        )?),
+        policy: Arc::new(VoucherPolicy::new(
+            config.integrator_ca_certificates.clone(),
+            config.config.additional_configuration.clone(),
+            config.config.voucher_validity.clone(),
+        )?),
+        revocation,
+        expiry,
+        timestamps,
+        verification: VerificationPool::new(&config.config.verification),
+        crl,
+        device_cloud: DeviceCloud::new(&config.config.device_cloud, client.clone())?,
+        voucher_metrics: config.config.voucher_metrics.then(|| Arc::new(VoucherMetrics::default())),
+        voucher_signers,
     };
 
-    let routes = Router::new().nest("/.well-known/brski", brski_routes()).layer(TraceLayer::new_for_http());
+    let authenticator = Arc::new(Authenticator::new(
+        config.config.admin_api_keys.clone(),
+        config.config.oidc_issuer.clone(),
+        config.config.oidc_audience.clone(),
+        state.client.clone(),
+    ));
+
+    let limits = &config.config.artifact_limits;
+    let admin = admin_routes()
+        .route_layer(middleware::from_fn_with_state(authenticator, require_scope))
+        .merge(console_routes())
+        .route_layer(middleware::from_fn_with_state(limits.guard(Artifact::Other), enforce_body_limit));
+
+    let routes = Router::new()
+        .nest(
+            "/.well-known/brski",
+            brski_routes(limits, Arc::new(IdempotencyCache::new(config.config.idempotency_key_ttl_secs, config.config.idempotency_key_capacity))),
+        )
+        .nest("/admin", admin)
+        .merge(crl_routes(config.config.crl.path.as_deref()))
+        .layer(TraceLayer::new_for_http());
 
     let app = routes.with_state(state);
 
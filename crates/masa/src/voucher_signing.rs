@@ -0,0 +1,317 @@
+//! Signing keys of the vouchers and the negotiation of their algorithm with the pledge.
+//! What a pledge supports is taken from its voucher request: ES256, which BRSKI (RFC 8995) makes mandatory to implement,
+//! and the algorithm of its own signature on the prior-signed voucher request.
+
+use anyhow::anyhow;
+use brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifactDetails;
+use cli::config::{VoucherAlgorithm, VoucherSigningConfig};
+use common::{
+    error::AppError,
+    idevid::{issuer_identifiers, normalize_issuer},
+    key_usage::{check_key_usage, KeyRole},
+    util::decode_jws_claims,
+};
+use openssl::{
+    nid::Nid,
+    pkey::{Id, PKey, PKeyRef, Private},
+    x509::X509,
+};
+use tracing::{event, Level};
+
+use crate::parsed_config::ParsedConfig;
+
+#[derive(Debug)]
+pub(crate) struct VoucherSigner {
+    pub(crate) algorithm: VoucherAlgorithm,
+    pub(crate) certificate: X509,
+    key: PKey<Private>,
+}
+
+impl VoucherSigner {
+    fn new(certificate: X509, key: PKey<Private>) -> anyhow::Result<Self> {
+        let algorithm = algorithm_of(&key).ok_or(anyhow!("voucher signing keys must be P-256, P-384 or Ed25519 keys"))?;
+        if !certificate.public_key()?.public_eq(&key) {
+            return Err(anyhow!("voucher signing certificate does not belong to its key"));
+        }
+        Ok(Self { algorithm, certificate, key })
+    }
+
+    /// DER encoded private key, as `JWS::encode` takes it
+    pub(crate) fn key_der(&self) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+        self.key.private_key_to_der()
+    }
+}
+
+fn algorithm_of(key: &PKeyRef<Private>) -> Option<VoucherAlgorithm> {
+    match key.id() {
+        Id::ED25519 => Some(VoucherAlgorithm::EdDSA),
+        Id::EC => match key.ec_key().ok()?.group().curve_name()? {
+            Nid::X9_62_PRIME256V1 => Some(VoucherAlgorithm::ES256),
+            Nid::SECP384R1 => Some(VoucherAlgorithm::ES384),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Algorithm and IDevID of the pledge signature on a prior-signed voucher request
+#[derive(Debug, Default)]
+struct PledgeSignature {
+    algorithm: Option<String>,
+    idevid: Option<X509>,
+}
+
+impl PledgeSignature {
+    fn of(details: &VoucherRequestArtifactDetails) -> Option<Self> {
+        let pvr = std::str::from_utf8(details.prior_signed_voucher_request.as_deref()?).ok()?;
+        let claims = decode_jws_claims(pvr)?;
+        let protected = claims.get("protected")?.get(0)?;
+
+        Some(Self {
+            algorithm: protected.get("alg").and_then(|alg| alg.as_str()).map(str::to_string),
+            idevid: protected
+                .get("x5c")
+                .and_then(|x5c| x5c.get(0)?.as_str())
+                .and_then(|der| openssl::base64::decode_block(der).ok())
+                .and_then(|der| X509::from_der(&der).ok()),
+        })
+    }
+
+    fn supports(&self, algorithm: VoucherAlgorithm) -> bool {
+        algorithm == VoucherAlgorithm::ES256 || self.algorithm.as_deref() == Some(algorithm.name())
+    }
+}
+
+#[derive(Debug)]
+struct Manufacturer {
+    name: String,
+    issuers: Vec<String>,
+    algorithms: Vec<VoucherAlgorithm>,
+}
+
+#[derive(Debug)]
+pub(crate) struct VoucherSigners {
+    signers: Vec<VoucherSigner>,
+    algorithms: Vec<VoucherAlgorithm>,
+    manufacturers: Vec<Manufacturer>,
+}
+
+impl VoucherSigners {
+    pub(crate) fn load(config: &ParsedConfig) -> anyhow::Result<Self, AppError> {
+        let ca_key = PKey::from_ec_key(config.ca_key.clone())?;
+        let mut signers = vec![VoucherSigner::new(config.masa_certificate.clone(), PKey::from_ec_key(config.masa_key.clone())?)?];
+        for signer in &config.config.voucher_signing.signers {
+            let certificate = X509::from_pem(&std::fs::read(signer.certificate.relative())?)?;
+            let key = PKey::private_key_from_pem(&std::fs::read(signer.key.relative())?)?;
+            check_key_usage(&certificate, KeyRole::VoucherSigning).map_err(|err| anyhow!("voucher_signing certificate: {}", err))?;
+            if !certificate.verify(&ca_key)? {
+                return Err(anyhow!("voucher_signing certificate {:?} is not issued by the MASA CA", signer.certificate.relative()).into());
+            }
+            signers.push(VoucherSigner::new(certificate, key)?);
+        }
+
+        Ok(Self::new(signers, &config.config.voucher_signing)?)
+    }
+
+    fn new(signers: Vec<VoucherSigner>, config: &VoucherSigningConfig) -> anyhow::Result<Self> {
+        let algorithms = match config.algorithms.is_empty() {
+            true => signers.iter().map(|signer| signer.algorithm).collect(),
+            false => config.algorithms.clone(),
+        };
+        let manufacturers: Vec<_> = config
+            .manufacturers
+            .iter()
+            .map(|(name, manufacturer)| Manufacturer {
+                name: name.clone(),
+                issuers: manufacturer.idevid_issuers.iter().map(|issuer| normalize_issuer(issuer)).collect(),
+                algorithms: manufacturer.algorithms.clone(),
+            })
+            .collect();
+
+        let preferred = algorithms.iter().chain(manufacturers.iter().flat_map(|manufacturer| &manufacturer.algorithms));
+        for algorithm in preferred {
+            if !signers.iter().any(|signer| signer.algorithm == *algorithm) {
+                return Err(anyhow!("voucher_signing has no signer for {}", algorithm));
+            }
+        }
+
+        Ok(Self { signers, algorithms, manufacturers })
+    }
+
+    /// The signers of `voucher_signing`, without the one of `masa_key`
+    pub(crate) fn additional(&self) -> &[VoucherSigner] {
+        &self.signers[1..]
+    }
+
+    /// The signer of the first preferred algorithm the pledge supports, the preferences of its manufacturer if it has
+    /// a section. Requests without a pledge voucher request get the first preferred algorithm.
+    pub(crate) fn select(&self, details: &VoucherRequestArtifactDetails) -> Result<&VoucherSigner, String> {
+        let pledge = PledgeSignature::of(details);
+        let manufacturer = pledge.as_ref().and_then(|pledge| pledge.idevid.as_ref()).and_then(|idevid| {
+            let identifiers = issuer_identifiers(idevid);
+            self.manufacturers
+                .iter()
+                .find(|manufacturer| manufacturer.issuers.iter().any(|issuer| identifiers.contains(issuer)))
+        });
+        let algorithms = match manufacturer {
+            Some(manufacturer) => {
+                event!(Level::DEBUG, "Using voucher algorithms of manufacturer {}", manufacturer.name);
+                &manufacturer.algorithms
+            }
+            None => &self.algorithms,
+        };
+
+        let algorithm = algorithms
+            .iter()
+            .find(|algorithm| pledge.as_ref().is_none_or(|pledge| pledge.supports(**algorithm)))
+            .ok_or_else(|| {
+                let names: Vec<_> = algorithms.iter().map(|algorithm| algorithm.name()).collect();
+                format!("the pledge supports none of the voucher algorithms {}", names.join(", "))
+            })?;
+
+        self.signers
+            .iter()
+            .find(|signer| signer.algorithm == *algorithm)
+            .ok_or_else(|| format!("no signer for {}", algorithm))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cli::config::VoucherManufacturerConfig;
+    use openssl::{
+        asn1::Asn1Time,
+        ec::EcKey,
+        hash::MessageDigest,
+        x509::{X509Builder, X509NameBuilder},
+    };
+
+    use super::*;
+
+    fn signer(key: PKey<Private>) -> VoucherSigner {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "MASA").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let digest = match key.id() {
+            Id::ED25519 => MessageDigest::null(),
+            _ => MessageDigest::sha256(),
+        };
+        builder.sign(&key, digest).unwrap();
+
+        VoucherSigner::new(builder.build(), key).unwrap()
+    }
+
+    fn ec_key(curve: Nid) -> PKey<Private> {
+        let group = openssl::ec::EcGroup::from_curve_name(curve).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn signers(config: &VoucherSigningConfig) -> anyhow::Result<VoucherSigners> {
+        VoucherSigners::new(
+            vec![
+                signer(ec_key(Nid::X9_62_PRIME256V1)),
+                signer(ec_key(Nid::SECP384R1)),
+                signer(PKey::generate_ed25519().unwrap()),
+            ],
+            config,
+        )
+    }
+
+    fn signers_with(algorithms: &[VoucherAlgorithm]) -> VoucherSigners {
+        signers(&VoucherSigningConfig {
+            algorithms: algorithms.to_vec(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// A voucher request carrying a pledge voucher request signed with `alg` by `idevid`
+    fn request(alg: &str, idevid: &X509) -> VoucherRequestArtifactDetails {
+        let protected = serde_json::json!({ "alg": alg, "x5c": [openssl::base64::encode_block(&idevid.to_der().unwrap())] });
+        let encode = |value: &serde_json::Value| {
+            openssl::base64::encode_block(value.to_string().as_bytes())
+                .replace('+', "-")
+                .replace('/', "_")
+                .trim_end_matches('=')
+                .to_string()
+        };
+        let pvr = serde_json::json!({
+            "payload": encode(&serde_json::json!({ "ietf-voucher-request:voucher": { "serial-number": "00-D0-E5-F2-00-02" } })),
+            "signatures": [{ "protected": encode(&protected), "signature": "c2ln" }],
+        });
+
+        let mut details = VoucherRequestArtifactDetails::default();
+        details.prior_signed_voucher_request = Some(pvr.to_string().into_bytes());
+        details
+    }
+
+    #[test]
+    fn test_negotiates_with_the_pledge() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (idevid, _) = &certs.pledge;
+        let signers = signers_with(&[VoucherAlgorithm::EdDSA, VoucherAlgorithm::ES384, VoucherAlgorithm::ES256]);
+
+        assert_eq!(signers.select(&request("EdDSA", idevid)).unwrap().algorithm, VoucherAlgorithm::EdDSA);
+        assert_eq!(signers.select(&request("ES384", idevid)).unwrap().algorithm, VoucherAlgorithm::ES384);
+        // ES256 is mandatory to implement, a pledge signing with another algorithm still supports it
+        assert_eq!(signers.select(&request("ES512", idevid)).unwrap().algorithm, VoucherAlgorithm::ES256);
+        // without a pledge voucher request the first preference is used
+        assert_eq!(signers.select(&VoucherRequestArtifactDetails::default()).unwrap().algorithm, VoucherAlgorithm::EdDSA);
+
+        // a pledge that only supports ES256 and ES384 gets no EdDSA voucher
+        let signers = signers_with(&[VoucherAlgorithm::EdDSA]);
+        assert!(signers.select(&request("ES384", idevid)).is_err());
+    }
+
+    #[test]
+    fn test_manufacturer_preferences() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (idevid, _) = &certs.pledge;
+        let (registrar, _) = &certs.registrar;
+        let issuer = issuer_identifiers(idevid).remove(0);
+
+        let signers = signers(&VoucherSigningConfig {
+            algorithms: vec![VoucherAlgorithm::ES256],
+            manufacturers: HashMap::from([(
+                "constrained".to_string(),
+                VoucherManufacturerConfig {
+                    idevid_issuers: vec![issuer],
+                    algorithms: vec![VoucherAlgorithm::EdDSA, VoucherAlgorithm::ES256],
+                },
+            )]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(signers.select(&request("EdDSA", idevid)).unwrap().algorithm, VoucherAlgorithm::EdDSA);
+        assert_eq!(signers.select(&request("ES256", idevid)).unwrap().algorithm, VoucherAlgorithm::ES256);
+        assert_eq!(signers.select(&request("EdDSA", registrar)).unwrap().algorithm, VoucherAlgorithm::ES256);
+    }
+
+    #[test]
+    fn test_preferences_need_a_signer() {
+        let config = VoucherSigningConfig {
+            algorithms: vec![VoucherAlgorithm::ES384],
+            ..Default::default()
+        };
+        assert!(VoucherSigners::new(vec![signer(ec_key(Nid::X9_62_PRIME256V1))], &config).is_err());
+
+        // the default preferences are the signers in order
+        let signers = VoucherSigners::new(
+            vec![signer(ec_key(Nid::SECP384R1)), signer(ec_key(Nid::X9_62_PRIME256V1))],
+            &VoucherSigningConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(signers.algorithms, vec![VoucherAlgorithm::ES384, VoucherAlgorithm::ES256]);
+    }
+}
@@ -1,2 +1,4 @@
+#[cfg(feature = "state-machine")]
+pub mod state_machine;
 pub mod tpvr;
-pub mod tper;
\ No newline at end of file
+pub mod tper;
@@ -0,0 +1,442 @@
+use std::time::Duration;
+
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+
+/// Steps of a pledge initiated onboarding, RFC 8995 Section 5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PledgeState {
+    /// Looking for a join proxy that reaches a registrar
+    Discovery,
+    VoucherRequest,
+    VoucherValidation,
+    /// Reports the outcome of the voucher validation, also when it failed
+    VoucherTelemetry,
+    Enrollment,
+    /// Reports the outcome of the enrollment, also when it failed
+    EnrollTelemetry,
+    Enrolled,
+    /// Gave up after `max_rounds` attempts
+    Failed,
+}
+
+impl PledgeState {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, PledgeState::Enrolled | PledgeState::Failed)
+    }
+}
+
+/// Failure of a single step. The reason code is reported in voucher and enroll status telemetry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepError {
+    pub reason_code: ReasonCode,
+    pub reason: String,
+    /// Whether the same step can succeed when tried again, e.g. after a timeout.
+    /// Other failures go back to discovery, so another registrar may be used.
+    pub retryable: bool,
+}
+
+impl StepError {
+    pub fn retryable(reason_code: ReasonCode, reason: impl Into<String>) -> Self {
+        Self {
+            reason_code,
+            reason: reason.into(),
+            retryable: true,
+        }
+    }
+
+    pub fn fatal(reason_code: ReasonCode, reason: impl Into<String>) -> Self {
+        Self {
+            reason_code,
+            reason: reason.into(),
+            retryable: false,
+        }
+    }
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.reason, self.reason_code)
+    }
+}
+
+impl std::error::Error for StepError {}
+
+/// Platform part of the onboarding: transport, artifact handling and timers.
+/// The Linux pledge talks to the registrar over HTTPS, the ESP32 firmware has its own network stack.
+// Futures are not required to be `Send`, the ESP32 runs the onboarding on a single threaded runtime
+#[allow(async_fn_in_trait)]
+pub trait PledgeBackend {
+    /// Registrar found by discovery, used for all following steps of a round
+    type Registrar;
+    /// Voucher as received, before validation
+    type Voucher;
+    /// What a validated voucher establishes, usually the pinned domain certificate
+    type DomainTrust;
+    type LDevId;
+
+    async fn discover(&mut self) -> Result<Self::Registrar, StepError>;
+
+    async fn request_voucher(&mut self, registrar: &Self::Registrar) -> Result<Self::Voucher, StepError>;
+
+    async fn validate_voucher(
+        &mut self,
+        registrar: &Self::Registrar,
+        voucher: &Self::Voucher,
+    ) -> Result<Self::DomainTrust, StepError>;
+
+    async fn voucher_status(&mut self, registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError>;
+
+    async fn enroll(&mut self, registrar: &Self::Registrar, trust: &Self::DomainTrust) -> Result<Self::LDevId, StepError>;
+
+    async fn enroll_status(&mut self, registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError>;
+
+    async fn sleep(&mut self, duration: Duration);
+}
+
+/// Notified about the progress of the onboarding, e.g. to drive a status LED or to log
+pub trait PledgeObserver {
+    fn transition(&mut self, _from: PledgeState, _to: PledgeState) {}
+
+    fn retrying(&mut self, _state: PledgeState, _attempt: u32, _delay: Duration, _error: &StepError) {}
+}
+
+impl PledgeObserver for () {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Tries of a step before the round is given up and the pledge starts over with discovery
+    pub attempts: u32,
+    /// Rounds before the pledge gives up for good, `None` keeps trying as RFC 8995 Section 4.1 expects
+    pub max_rounds: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            attempts: 3,
+            max_rounds: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Doubles with each attempt, starting at `initial_backoff`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Drives discovery, voucher request, voucher validation, telemetry and enrollment as explicit states.
+/// Failed steps are retried with exponential backoff, after `attempts` tries the pledge starts over with discovery.
+pub struct PledgeStateMachine<B: PledgeBackend, O: PledgeObserver = ()> {
+    backend: B,
+    observer: O,
+    policy: RetryPolicy,
+    state: PledgeState,
+    attempt: u32,
+    round: u32,
+    registrar: Option<B::Registrar>,
+    voucher: Option<B::Voucher>,
+    trust: Option<B::DomainTrust>,
+    outcome: Result<(), StepError>,
+    ldevid: Option<B::LDevId>,
+}
+
+impl<B: PledgeBackend> PledgeStateMachine<B> {
+    pub fn new(backend: B, policy: RetryPolicy) -> Self {
+        Self::with_observer(backend, (), policy)
+    }
+}
+
+impl<B: PledgeBackend, O: PledgeObserver> PledgeStateMachine<B, O> {
+    pub fn with_observer(backend: B, observer: O, policy: RetryPolicy) -> Self {
+        Self {
+            backend,
+            observer,
+            policy,
+            state: PledgeState::Discovery,
+            attempt: 0,
+            round: 0,
+            registrar: None,
+            voucher: None,
+            trust: None,
+            outcome: Ok(()),
+            ldevid: None,
+        }
+    }
+
+    pub fn state(&self) -> PledgeState {
+        self.state
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Steps until the pledge is enrolled or gave up, returning the LDevID or the last failure
+    pub async fn run(mut self) -> Result<B::LDevId, StepError> {
+        while !self.state.is_terminal() {
+            self.step().await;
+        }
+
+        match self.ldevid {
+            Some(ldevid) => Ok(ldevid),
+            None => Err(self.outcome.err().unwrap_or_else(|| StepError::fatal(ReasonCode::InternalError, "onboarding failed"))),
+        }
+    }
+
+    /// Runs the current state once and moves on, for platforms that interleave the onboarding with other work
+    pub async fn step(&mut self) {
+        match self.state {
+            PledgeState::Discovery => match self.backend.discover().await {
+                Ok(registrar) => {
+                    self.registrar = Some(registrar);
+                    self.transition(PledgeState::VoucherRequest);
+                }
+                Err(err) => self.retry_or_restart(err).await,
+            },
+            PledgeState::VoucherRequest => {
+                let Some(registrar) = &self.registrar else {
+                    return self.restart(StepError::fatal(ReasonCode::InternalError, "no registrar")).await;
+                };
+                match self.backend.request_voucher(registrar).await {
+                    Ok(voucher) => {
+                        self.voucher = Some(voucher);
+                        self.transition(PledgeState::VoucherValidation);
+                    }
+                    Err(err) => self.retry_or_restart(err).await,
+                }
+            }
+            PledgeState::VoucherValidation => {
+                let (Some(registrar), Some(voucher)) = (&self.registrar, &self.voucher) else {
+                    return self.restart(StepError::fatal(ReasonCode::InternalError, "no voucher")).await;
+                };
+                self.outcome = self.backend.validate_voucher(registrar, voucher).await.map(|trust| {
+                    self.trust = Some(trust);
+                });
+                self.transition(PledgeState::VoucherTelemetry);
+            }
+            PledgeState::VoucherTelemetry => {
+                let Some(registrar) = &self.registrar else {
+                    return self.restart(StepError::fatal(ReasonCode::InternalError, "no registrar")).await;
+                };
+                let reported = self.backend.voucher_status(registrar, self.outcome.as_ref().map(|_| ())).await;
+                if let Err(err) = reported {
+                    if self.retry(&err).await {
+                        return;
+                    }
+                }
+
+                // telemetry is best effort, only the outcome of the validation decides how to go on
+                match self.outcome.clone() {
+                    Ok(()) => self.transition(PledgeState::Enrollment),
+                    Err(err) => self.restart(err).await,
+                }
+            }
+            PledgeState::Enrollment => {
+                let (Some(registrar), Some(trust)) = (&self.registrar, &self.trust) else {
+                    return self.restart(StepError::fatal(ReasonCode::InternalError, "no trusted domain")).await;
+                };
+                match self.backend.enroll(registrar, trust).await {
+                    Ok(ldevid) => {
+                        self.ldevid = Some(ldevid);
+                        self.outcome = Ok(());
+                        self.transition(PledgeState::EnrollTelemetry);
+                    }
+                    Err(err) => {
+                        if self.retry(&err).await {
+                            return;
+                        }
+                        self.outcome = Err(err);
+                        self.transition(PledgeState::EnrollTelemetry);
+                    }
+                }
+            }
+            PledgeState::EnrollTelemetry => {
+                let Some(registrar) = &self.registrar else {
+                    return self.restart(StepError::fatal(ReasonCode::InternalError, "no registrar")).await;
+                };
+                let reported = self.backend.enroll_status(registrar, self.outcome.as_ref().map(|_| ())).await;
+                if let Err(err) = reported {
+                    if self.retry(&err).await {
+                        return;
+                    }
+                }
+
+                match self.outcome.clone() {
+                    Ok(()) => self.transition(PledgeState::Enrolled),
+                    Err(err) => {
+                        self.ldevid = None;
+                        self.restart(err).await
+                    }
+                }
+            }
+            PledgeState::Enrolled | PledgeState::Failed => {}
+        }
+    }
+
+    fn transition(&mut self, to: PledgeState) {
+        self.observer.transition(self.state, to);
+        self.state = to;
+        self.attempt = 0;
+    }
+
+    /// Waits before the next try of the current state, `false` once all attempts are used up
+    async fn retry(&mut self, err: &StepError) -> bool {
+        self.attempt += 1;
+        if !err.retryable || self.attempt >= self.policy.attempts {
+            return false;
+        }
+
+        let delay = self.policy.backoff(self.attempt);
+        self.observer.retrying(self.state, self.attempt, delay, err);
+        self.backend.sleep(delay).await;
+        true
+    }
+
+    async fn retry_or_restart(&mut self, err: StepError) {
+        if !self.retry(&err).await {
+            self.restart(err).await;
+        }
+    }
+
+    /// Starts a new round with discovery, dropping everything learned from the previous registrar
+    async fn restart(&mut self, err: StepError) {
+        self.registrar = None;
+        self.voucher = None;
+        self.trust = None;
+        self.round += 1;
+        self.outcome = Err(err);
+
+        if self.policy.max_rounds.is_some_and(|max_rounds| self.round >= max_rounds) {
+            self.transition(PledgeState::Failed);
+            return;
+        }
+
+        let delay = self.policy.backoff(self.round);
+        if let Err(err) = &self.outcome {
+            self.observer.retrying(PledgeState::Discovery, self.round, delay, err);
+        }
+        self.backend.sleep(delay).await;
+        self.transition(PledgeState::Discovery);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rejects the first voucher and fails the first enroll attempt
+    #[derive(Default)]
+    struct TestBackend {
+        vouchers: u32,
+        enrolls: u32,
+        voucher_status: Vec<Option<ReasonCode>>,
+        enroll_status: Vec<Option<ReasonCode>>,
+        slept: Duration,
+    }
+
+    impl PledgeBackend for TestBackend {
+        type Registrar = &'static str;
+        type Voucher = u32;
+        type DomainTrust = &'static str;
+        type LDevId = String;
+
+        async fn discover(&mut self) -> Result<Self::Registrar, StepError> {
+            Ok("registrar")
+        }
+
+        async fn request_voucher(&mut self, _registrar: &Self::Registrar) -> Result<Self::Voucher, StepError> {
+            self.vouchers += 1;
+            Ok(self.vouchers)
+        }
+
+        async fn validate_voucher(&mut self, _registrar: &Self::Registrar, voucher: &Self::Voucher) -> Result<Self::DomainTrust, StepError> {
+            match voucher {
+                1 => Err(StepError::fatal(ReasonCode::NonceMismatch, "nonce differs")),
+                _ => Ok("domain"),
+            }
+        }
+
+        async fn voucher_status(&mut self, _registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError> {
+            self.voucher_status.push(outcome.err().map(|err| err.reason_code));
+            Ok(())
+        }
+
+        async fn enroll(&mut self, _registrar: &Self::Registrar, trust: &Self::DomainTrust) -> Result<Self::LDevId, StepError> {
+            self.enrolls += 1;
+            match self.enrolls {
+                1 => Err(StepError::retryable(ReasonCode::InternalError, "timeout")),
+                _ => Ok(format!("ldevid issued by {}", trust)),
+            }
+        }
+
+        async fn enroll_status(&mut self, _registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError> {
+            self.enroll_status.push(outcome.err().map(|err| err.reason_code));
+            Ok(())
+        }
+
+        async fn sleep(&mut self, duration: Duration) {
+            self.slept += duration;
+        }
+    }
+
+    #[derive(Default)]
+    struct Transitions(Vec<PledgeState>);
+
+    impl PledgeObserver for &mut Transitions {
+        fn transition(&mut self, _from: PledgeState, to: PledgeState) {
+            self.0.push(to);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restarts_after_rejected_voucher() {
+        let mut transitions = Transitions::default();
+        let mut machine = PledgeStateMachine::with_observer(TestBackend::default(), &mut transitions, RetryPolicy::default());
+
+        while !machine.state().is_terminal() {
+            machine.step().await;
+        }
+
+        assert_eq!(machine.state(), PledgeState::Enrolled);
+        assert_eq!(machine.backend().voucher_status, vec![Some(ReasonCode::NonceMismatch), None]);
+        assert_eq!(machine.backend().enroll_status, vec![None]);
+        // one new round and one enroll retry, each after the initial backoff
+        assert_eq!(machine.backend().slept, Duration::from_secs(2));
+        assert_eq!(machine.run().await.unwrap(), "ldevid issued by domain");
+
+        use PledgeState::*;
+        assert_eq!(
+            transitions.0,
+            vec![
+                VoucherRequest, VoucherValidation, VoucherTelemetry, Discovery,
+                VoucherRequest, VoucherValidation, VoucherTelemetry, Enrollment, EnrollTelemetry, Enrolled,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_rounds() {
+        let policy = RetryPolicy {
+            max_rounds: Some(1),
+            ..Default::default()
+        };
+
+        let err = PledgeStateMachine::new(TestBackend::default(), policy).run().await.unwrap_err();
+        assert_eq!(err.reason_code, ReasonCode::NonceMismatch);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(40), Duration::from_secs(60));
+    }
+}
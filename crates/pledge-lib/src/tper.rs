@@ -4,7 +4,8 @@ pub fn create_per(x509_req: impl Into<X509Req>, pledge_idevid_certs: impl IntoIt
     let payload = ResponsePayload {
         csr: ResponsePayloadInner {
             p10_csr: x509_req.into(),
-        }
+        },
+        ssh_host_key: None,
     };
     Response::new(payload, pledge_idevid_certs)
 }
\ No newline at end of file
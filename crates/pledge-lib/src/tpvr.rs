@@ -1,4 +1,4 @@
-use brski_prm_artifacts::ietf_voucher::VoucherRequest;
+use brski_prm_artifacts::ietf_voucher::{pki::X509, VoucherRequest};
 
 pub fn create_pvr(trigger: brski_prm_artifacts::pvr::trigger::Trigger, serial_number: String, ) -> VoucherRequest {
 
@@ -26,10 +26,29 @@ pub fn create_pvr(trigger: brski_prm_artifacts::pvr::trigger::Trigger, serial_nu
         Some(trigger.agent_signed_proximity_cert);
     voucher_request_details.agent_signed_data = Some(trigger.agent_signed_data);
 
-    let voucher_request =
-        brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifact {
+    
+
+    brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifact {
             details: voucher_request_details,
-        };
+        } 
+}
+
+/// Voucher request of a pledge talking to the registrar itself, RFC 8995 Section 5.2.
+/// The registrar certificate is the one of the provisional TLS connection.
+pub fn create_registrar_pvr(serial_number: String, registrar_cert: Option<X509>) -> VoucherRequest {
+    let mut voucher_request_details =
+        brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifactDetails::default();
+
+    #[cfg(feature = "clock")]
+    {
+        voucher_request_details.created_on = Some(chrono::Utc::now());
+    }
+    voucher_request_details.nonce = Some(rand::random::<u32>().to_string().into_bytes());
+    voucher_request_details.serial_number = serial_number;
+    voucher_request_details.assertion = Some(brski_prm_artifacts::ietf_voucher::assertion::Assertion::Proximity);
+    voucher_request_details.proximity_registrar_cert = registrar_cert;
 
-    voucher_request 
-}
\ No newline at end of file
+    brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifact {
+        details: voucher_request_details,
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use brski_prm_artifacts::status::attestation::CounterAttestation;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct PersistedCounters {
+    boot_count: u64,
+    monotonic_counter: u64,
+}
+
+/// Boot count and telemetry counter of the pledge, persisted so they survive restarts.
+/// Every value is written before it is handed out, a crash may skip a value but never repeats one.
+#[derive(Debug)]
+pub(crate) struct AttestationCounter {
+    path: PathBuf,
+    counters: Mutex<PersistedCounters>,
+}
+
+impl AttestationCounter {
+    /// Counts this start of the pledge. An unreadable file is an error rather than a reset,
+    /// as starting over would look like a rollback to the registrar.
+    pub(crate) fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let mut counters: PersistedCounters = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => PersistedCounters::default(),
+            Err(err) => return Err(err.into()),
+        };
+        counters.boot_count += 1;
+        write_counters(&path, &counters)?;
+
+        event!(Level::INFO, "Boot {} of the pledge", counters.boot_count);
+        Ok(Self {
+            path,
+            counters: Mutex::new(counters),
+        })
+    }
+
+    /// Attestation for the next status message
+    pub(crate) fn next(&self) -> anyhow::Result<CounterAttestation> {
+        let mut counters = self.counters.lock().unwrap();
+        counters.monotonic_counter += 1;
+        write_counters(&self.path, &counters)?;
+
+        Ok(CounterAttestation {
+            boot_count: counters.boot_count,
+            monotonic_counter: counters.monotonic_counter,
+        })
+    }
+}
+
+/// Replaces the file through a temporary file, so an interrupted write does not lose the counters
+fn write_counters(path: &PathBuf, counters: &PersistedCounters) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_vec(counters)?)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("open-brski-attestation-{}.json", std::process::id()));
+
+        let counter = AttestationCounter::open(path.clone()).unwrap();
+        let first = counter.next().unwrap();
+        let second = counter.next().unwrap();
+        assert_eq!(first, CounterAttestation { boot_count: 1, monotonic_counter: 1 });
+        assert!(second.follows(&first));
+
+        let restarted = AttestationCounter::open(path.clone()).unwrap();
+        let third = restarted.next().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(third, CounterAttestation { boot_count: 2, monotonic_counter: 3 });
+        assert!(third.follows(&second));
+        assert!(!second.follows(&third));
+        assert!(!third.follows(&third));
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use reqwest::{header::LOCATION, redirect, StatusCode};
+use tracing::{event, Level};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of fetching the connectivity check URL on the bootstrap network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Connectivity {
+    Open,
+    /// Requests are intercepted, `detail` names the portal or the unexpected answer
+    CaptivePortal { detail: String },
+}
+
+/// Fetches the check URL without following redirects. Captive portals answer plain HTTP with a redirect
+/// or their login page instead of the expected `204 No Content`, while they make TLS to the registrar fail
+/// with errors that do not tell what is going on.
+#[tracing::instrument(target = "Pledge", name = "Pledge::captive_portal")]
+pub(crate) async fn detect(url: &str) -> anyhow::Result<Connectivity> {
+    let client = reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .timeout(CHECK_TIMEOUT)
+        .build()?;
+
+    let response = client.get(url).send().await?;
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(str::to_string);
+
+    let connectivity = classify(response.status(), location.as_deref());
+    if let Connectivity::CaptivePortal { detail } = &connectivity {
+        event!(Level::WARN, "Bootstrap network is behind a captive portal: {}", detail);
+    }
+    Ok(connectivity)
+}
+
+fn classify(status: StatusCode, location: Option<&str>) -> Connectivity {
+    match (status, location) {
+        (StatusCode::NO_CONTENT, _) => Connectivity::Open,
+        (status, Some(location)) if status.is_redirection() => Connectivity::CaptivePortal {
+            detail: format!("redirected to {}", location),
+        },
+        (StatusCode::NETWORK_AUTHENTICATION_REQUIRED, _) => Connectivity::CaptivePortal {
+            detail: "network authentication required".to_string(),
+        },
+        (status, _) => Connectivity::CaptivePortal {
+            detail: format!("connectivity check answered {} instead of 204", status),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_portal_responses() {
+        assert_eq!(classify(StatusCode::NO_CONTENT, None), Connectivity::Open);
+        assert_eq!(
+            classify(StatusCode::FOUND, Some("http://portal.example/login")),
+            Connectivity::CaptivePortal {
+                detail: "redirected to http://portal.example/login".to_string()
+            }
+        );
+        assert!(matches!(classify(StatusCode::OK, None), Connectivity::CaptivePortal { .. }));
+        assert!(matches!(
+            classify(StatusCode::NETWORK_AUTHENTICATION_REQUIRED, None),
+            Connectivity::CaptivePortal { .. }
+        ));
+    }
+}
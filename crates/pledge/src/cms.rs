@@ -0,0 +1,58 @@
+use brski_prm_artifacts::ietf_voucher::VoucherRequest;
+use common::asn1::{signed_data, ID_CT_ANIMA_JSON_VOUCHER};
+use openssl::ec::EcKey;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+
+/// Wraps the JSON voucher-request in a CMS SignedData (RFC 5652) signed by the IDevID, the format of RFC 8995 Section 3.3.
+/// The IDevID certificate is carried in the `certificates` field so the registrar can verify it without prior knowledge,
+/// the encapsulated content is labeled id-ct-animaJSONVoucher.
+pub(crate) fn sign_voucher_request(
+    voucher_request: &VoucherRequest,
+    idevid_certificate: &X509,
+    idevid_privkey: &EcKey<Private>,
+) -> anyhow::Result<Vec<u8>> {
+    let content = serde_json::to_vec(voucher_request)?;
+    let key = PKey::from_ec_key(idevid_privkey.clone())?;
+
+    Ok(signed_data(ID_CT_ANIMA_JSON_VOUCHER, &content, idevid_certificate, &key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use brski_prm_artifacts::ietf_voucher::{assertion::Assertion, request_artifact::VoucherRequestArtifactDetails};
+    use openssl::cms::{CMSOptions, CmsContentInfo};
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_sign_voucher_request() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, pledge_key) = certs.pledge;
+
+        let mut details: VoucherRequestArtifactDetails = Default::default();
+        details.serial_number = "00-D0-E5-F2-00-02".to_string();
+        details.assertion = Some(Assertion::AgentProximity);
+        details.nonce = Some(b"123456789".to_vec());
+        let voucher_request = VoucherRequest { details };
+
+        let der = sign_voucher_request(&voucher_request, &pledge_cert, &pledge_key.ec_key().unwrap()).unwrap();
+
+        let mut cms = CmsContentInfo::from_der(&der).unwrap();
+        let mut content = vec![];
+        // The signer certificate is taken from the SignedData, the chain to the manufacturer is not part of this test
+        cms.verify(
+            Some(&Stack::new().unwrap()),
+            Some(&X509StoreBuilder::new().unwrap().build()),
+            None,
+            Some(&mut content),
+            CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY,
+        )
+        .unwrap();
+
+        let decoded: VoucherRequest = serde_json::from_slice(&content).unwrap();
+        assert_eq!(decoded, voucher_request);
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use cli::config::PledgeConfig;
+use common::chain;
+use openssl::{
+    asn1::Asn1Time,
+    ec::EcKey,
+    pkey::{PKey, Private},
+    sign::{Signer, Verifier},
+    x509::X509,
+};
+
+use crate::{
+    captive::{self, Connectivity},
+    grasp::{self, JoinProxies},
+    transport,
+};
+
+/// How long the doctor listens for join proxy announcements
+const DISCOVERY_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.status != CheckStatus::Failed)
+    }
+
+    fn push(&mut self, name: &'static str, result: anyhow::Result<(CheckStatus, String)>) {
+        let (status, detail) = result.unwrap_or_else(|err| (CheckStatus::Failed, format!("{:#}", err)));
+        self.checks.push(Check { name, status, detail });
+    }
+}
+
+impl Display for DoctorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warning => "warn",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "skip",
+            };
+            writeln!(f, "[{:>4}] {:<16} {}", status, check.name, check.detail)?;
+        }
+        write!(f, "{}", if self.is_healthy() { "Pledge is ready for onboarding" } else { "Pledge is not ready for onboarding" })
+    }
+}
+
+/// Runs all self checks of the pledge. Checks depending on the identity are skipped when it can not be loaded.
+#[tracing::instrument(skip(config), target = "Pledge", name = "Pledge::doctor")]
+pub async fn doctor(config: PledgeConfig) -> DoctorReport {
+    let mut report = DoctorReport::default();
+
+    let identity = load_identity(&config);
+    let identity = match identity {
+        Ok((cert, key)) => {
+            report.push("idevid", Ok((CheckStatus::Ok, subject(&cert))));
+            Some((cert, key))
+        }
+        Err(err) => {
+            report.push("idevid", Err(err));
+            None
+        }
+    };
+
+    match &identity {
+        Some((cert, key)) => {
+            report.push("idevid-chain", check_chain(cert, &config));
+            report.push("idevid-key", check_key(cert, key));
+            report.push("clock", check_clock(cert));
+        }
+        None => {
+            for name in ["idevid-chain", "idevid-key", "clock"] {
+                report.push(name, Ok((CheckStatus::Skipped, "IDevID could not be loaded".to_string())));
+            }
+        }
+    }
+
+    report.push("captive-portal", check_captive_portal(&config).await);
+    report.push("registrar", check_registrars(&config).await);
+
+    report
+}
+
+fn load_identity(config: &PledgeConfig) -> anyhow::Result<(X509, EcKey<Private>)> {
+    let cert_path = config.idevid_certificate.relative();
+    let cert = X509::from_pem(&std::fs::read(&cert_path).with_context(|| format!("{:?}", cert_path))?)?;
+    let key_path = config.idevid_privkey.relative();
+    let key = EcKey::private_key_from_pem(&std::fs::read(&key_path).with_context(|| format!("{:?}", key_path))?)?;
+    Ok((cert, key))
+}
+
+fn check_chain(cert: &X509, config: &PledgeConfig) -> anyhow::Result<(CheckStatus, String)> {
+    if config.manufacturer_anchors.is_empty() {
+        return Ok((CheckStatus::Warning, "no manufacturer anchors configured".to_string()));
+    }
+
+    let anchors = config
+        .manufacturer_anchors
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative()).with_context(|| format!("{:?}", path.relative()))?)?))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    verify_chain(cert, &anchors)
+}
+
+fn verify_chain(cert: &X509, anchors: &[X509]) -> anyhow::Result<(CheckStatus, String)> {
+    match chain::verify_chain(cert, anchors, &[])? {
+        Ok(()) => Ok((CheckStatus::Ok, "chains to a manufacturer anchor".to_string())),
+        Err(error) => Err(anyhow!("does not chain to a manufacturer anchor: {}", error)),
+    }
+}
+
+/// Signs test data, so a key that does not belong to the certificate or can not be used is noticed before onboarding
+fn check_key(cert: &X509, key: &EcKey<Private>) -> anyhow::Result<(CheckStatus, String)> {
+    let key = PKey::from_ec_key(key.clone())?;
+    let public_key = cert.public_key()?;
+
+    if !public_key.public_eq(&key) {
+        return Err(anyhow!("private key does not belong to the IDevID"));
+    }
+
+    let data = b"open-brski pledge doctor";
+    let digest = brski_prm_artifacts::digest::message_digest(&key);
+    let mut signer = Signer::new(digest, &key)?;
+    signer.update(data)?;
+    let signature = signer.sign_to_vec()?;
+
+    let mut verifier = Verifier::new(digest, &public_key)?;
+    verifier.update(data)?;
+    if !verifier.verify(&signature)? {
+        return Err(anyhow!("test signature does not verify"));
+    }
+
+    Ok((CheckStatus::Ok, "test signature verified".to_string()))
+}
+
+/// The clock is only known to be wrong when it lies outside the IDevID validity
+fn check_clock(cert: &X509) -> anyhow::Result<(CheckStatus, String)> {
+    let now = Asn1Time::days_from_now(0)?;
+
+    if cert.not_before() > now {
+        return Err(anyhow!("clock is before the IDevID not-before {}", cert.not_before()));
+    }
+    if cert.not_after() < now {
+        return Err(anyhow!("IDevID expired on {} or the clock is wrong", cert.not_after()));
+    }
+
+    Ok((CheckStatus::Ok, format!("within IDevID validity, not-after {}", cert.not_after())))
+}
+
+async fn check_captive_portal(config: &PledgeConfig) -> anyhow::Result<(CheckStatus, String)> {
+    let Some(url) = &config.captive_portal_check_url else {
+        return Ok((CheckStatus::Skipped, "captive_portal_check_url is not configured".to_string()));
+    };
+
+    match captive::detect(url).await {
+        Ok(Connectivity::Open) => Ok((CheckStatus::Ok, format!("{} is reachable without a portal", url))),
+        Ok(Connectivity::CaptivePortal { detail }) => Err(anyhow!("bootstrap network is behind a captive portal, {}", detail)),
+        Err(err) => Ok((CheckStatus::Warning, format!("{} is not reachable: {}", url, err))),
+    }
+}
+
+async fn check_registrars(config: &PledgeConfig) -> anyhow::Result<(CheckStatus, String)> {
+    if !config.grasp_discovery {
+        return Ok((CheckStatus::Skipped, "grasp_discovery is disabled".to_string()));
+    }
+
+    let proxies = Arc::new(JoinProxies::default());
+    let listen = grasp::listen(Arc::clone(&proxies), grasp::interfaces(config)?);
+    if let Ok(Err(err)) = tokio::time::timeout(DISCOVERY_WINDOW, listen).await {
+        return Err(anyhow!("GRASP discovery failed: {}", err));
+    }
+
+    let discovered = proxies.candidates().await.len();
+    match transport::probe(&proxies).await {
+        Some(proxy) => Ok((CheckStatus::Ok, format!("{} join proxies discovered, using {}", discovered, proxy.url()))),
+        None if discovered == 0 => Err(anyhow!("no join proxy announced within {:?}", DISCOVERY_WINDOW)),
+        None => Err(anyhow!("none of {} discovered join proxies reaches a registrar", discovered)),
+    }
+}
+
+fn subject(cert: &X509) -> String {
+    cert.subject_name()
+        .entries()
+        .filter_map(|entry| entry.data().to_string().ok())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_checks() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, pledge_key) = &certs.pledge;
+        let (vendor_ca, _) = &certs.vendor_ca;
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let (_, registrar_key) = &certs.registrar;
+
+        assert!(verify_chain(pledge_cert, std::slice::from_ref(vendor_ca)).is_ok());
+        assert!(verify_chain(pledge_cert, std::slice::from_ref(registrar_ca)).is_err());
+
+        assert!(check_key(pledge_cert, &pledge_key.ec_key().unwrap()).is_ok());
+        assert!(check_key(pledge_cert, &registrar_key.ec_key().unwrap()).is_err());
+
+        assert_eq!(check_clock(pledge_cert).unwrap().0, CheckStatus::Ok);
+    }
+}
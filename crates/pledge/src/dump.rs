@@ -0,0 +1,101 @@
+//! Artifacts of a dry run, written to a directory for interop debugging. Files are numbered in the order the
+//! artifacts were exchanged, JWS are written as sent and with their decoded headers and payload as JSON,
+//! certificates and CSRs as DER and PEM.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Context;
+use openssl::x509::{X509Ref, X509Req};
+
+pub(crate) struct ArtifactDump {
+    directory: PathBuf,
+    sequence: AtomicUsize,
+}
+
+impl ArtifactDump {
+    pub(crate) fn create(directory: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(directory).with_context(|| format!("{:?}", directory))?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            sequence: AtomicUsize::new(1),
+        })
+    }
+
+    /// `<sequence>-<name>`, the extension is added per file
+    fn path(&self, name: &str) -> PathBuf {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        self.directory.join(format!("{:02}-{}", sequence, name))
+    }
+
+    fn write(path: &Path, extension: &str, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+        let path = path.with_extension(extension);
+        fs::write(&path, contents).with_context(|| format!("{:?}", path))
+    }
+
+    /// The JWS as sent or received and, if it decodes, its protected headers and payload. The signature is not verified.
+    pub(crate) fn jws(&self, name: &str, encoded: &str) -> anyhow::Result<()> {
+        let path = self.path(name);
+        Self::write(&path, "jws", encoded)?;
+        if let Some(claims) = common::util::decode_jws_claims(encoded) {
+            Self::write(&path, "json", serde_json::to_string_pretty(&claims)?)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn certificate(&self, name: &str, certificate: &X509Ref) -> anyhow::Result<()> {
+        let path = self.path(name);
+        Self::write(&path, "der", certificate.to_der()?)?;
+        Self::write(&path, "pem", certificate.to_pem()?)
+    }
+
+    /// All certificates as one PEM bundle, each as DER next to it
+    pub(crate) fn certificates(&self, name: &str, certificates: &[impl AsRef<X509Ref>]) -> anyhow::Result<()> {
+        let path = self.path(name);
+        let mut bundle = Vec::new();
+        for (index, certificate) in certificates.iter().enumerate() {
+            let certificate = certificate.as_ref();
+            Self::write(&path, &format!("{}.der", index), certificate.to_der()?)?;
+            bundle.extend(certificate.to_pem()?);
+        }
+        Self::write(&path, "pem", bundle)
+    }
+
+    pub(crate) fn csr(&self, name: &str, csr: &X509Req) -> anyhow::Result<()> {
+        let path = self.path(name);
+        Self::write(&path, "der", csr.to_der()?)?;
+        Self::write(&path, "pem", csr.to_pem()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_artifacts() {
+        let directory = std::env::temp_dir().join(format!("open-brski-dump-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let dump = ArtifactDump::create(&directory).unwrap();
+
+        // {"alg":"ES256"}.{"serial-number":"00-D0-E5-F2-00-02"}.signature
+        dump.jws("voucher-request", "eyJhbGciOiJFUzI1NiJ9.eyJzZXJpYWwtbnVtYmVyIjoiMDAtRDAtRTUtRjItMDAtMDIifQ.c2ln")
+            .unwrap();
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        dump.certificates("cacerts", &[certs.registrar_ca.0.clone(), certs.vendor_ca.0.clone()]).unwrap();
+
+        let claims: serde_json::Value =
+            serde_json::from_slice(&fs::read(directory.join("01-voucher-request.json")).unwrap()).unwrap();
+        assert_eq!(claims["payload"]["serial-number"], "00-D0-E5-F2-00-02");
+        assert_eq!(claims["protected"][0]["alg"], "ES256");
+        assert!(directory.join("01-voucher-request.jws").exists());
+        assert!(directory.join("02-cacerts.1.der").exists());
+        let bundle = openssl::x509::X509::stack_from_pem(&fs::read(directory.join("02-cacerts.pem")).unwrap()).unwrap();
+        assert_eq!(bundle.len(), 2);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+//! Firmware updates of the Linux pledge, described by SUIT manifests (RFC 9019)
+//!
+//! A manifest accepted on `/.well-known/brski/ssm` describes the next image. An image posted to
+//! `/.well-known/brski/sfi` is only installed, i.e. written to `firmware_image`, if it passes
+//! [`Manifest::check_image`] for that manifest. The sequence number of the installed manifest is kept in
+//! `firmware_state`, so manifests that are not newer than the installed firmware are rejected after a restart as well.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::suit::Manifest;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct PersistedFirmware {
+    sequence_number: u64,
+}
+
+/// Sequence number of the installed firmware, `None` if no image was installed yet
+pub(crate) fn installed_sequence_number(path: &Path) -> anyhow::Result<Option<u64>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(Some(serde_json::from_slice::<PersistedFirmware>(&content)?.sequence_number)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes the image and then the sequence number of its manifest, each through a temporary file.
+/// The image is checked against the manifest before, see [`Manifest::check_image`].
+pub(crate) fn install(image_path: &Path, state_path: &Path, manifest: &Manifest, image: &[u8]) -> anyhow::Result<()> {
+    replace(image_path, image)?;
+    replace(state_path, &serde_json::to_vec(&PersistedFirmware {
+        sequence_number: manifest.sequence_number,
+    })?)?;
+
+    event!(Level::INFO, "Installed firmware image of manifest {} to {:?}", manifest.sequence_number, image_path);
+    Ok(())
+}
+
+fn replace(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, content)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::suit::Digest;
+
+    #[test]
+    fn test_sequence_number_survives_restarts() {
+        let directory = std::env::temp_dir().join(format!("open-brski-firmware-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (image_path, state_path) = (directory.join("firmware.bin"), directory.join("firmware.json"));
+
+        assert_eq!(installed_sequence_number(&state_path).unwrap(), None);
+
+        let manifest = Manifest {
+            sequence_number: 7,
+            vendor_id: None,
+            class_id: None,
+            image_digest: Digest { algorithm: -16, bytes: vec![] },
+            image_size: None,
+        };
+        install(&image_path, &state_path, &manifest, b"pledge firmware 1.1.0").unwrap();
+
+        assert_eq!(std::fs::read(&image_path).unwrap(), b"pledge firmware 1.1.0");
+        assert_eq!(installed_sequence_number(&state_path).unwrap(), Some(7));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use ciborium::Value;
+use cli::config::PledgeConfig;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::zone::{interface_index, is_link_local};
+
+/// ALL_GRASP_NEIGHBORS, see RFC 8990 Section 2.5.4.3
+pub(crate) const ALL_GRASP_NEIGHBORS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x13);
+pub(crate) const GRASP_LISTEN_PORT: u16 = 7017;
+
+/// Objective flooded by join proxies, see RFC 8995 Section 4.1.1
+const AN_PROXY: &str = "AN_Proxy";
+
+const M_FLOOD: u64 = 9;
+const O_IPV6_LOCATOR: u64 = 103;
+const O_IPV4_LOCATOR: u64 = 104;
+const O_FQDN_LOCATOR: u64 = 105;
+const IPPROTO_TCP: u64 = 6;
+
+/// How long a proxy that failed is skipped while other proxies are available
+const FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Transport {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct JoinProxy {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) transport: Transport,
+    pub(crate) loop_count: u64,
+}
+
+impl JoinProxy {
+    pub(crate) fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    pub(crate) fn url(&self) -> String {
+        format!("https://{}", self.authority())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Announcement {
+    proxy: JoinProxy,
+    seen: Instant,
+    expires: Instant,
+    failed: Option<Instant>,
+    /// The registrar answered through this proxy on the last probe
+    reachable: bool,
+    /// Expiry as last written to the discovery cache
+    persisted_expires: Instant,
+}
+
+/// Entry of the discovery cache. Times are milliseconds since the Unix epoch, as instants do not survive a reboot.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedAnnouncement {
+    proxy: JoinProxy,
+    expires: u64,
+    failed: Option<u64>,
+    reachable: bool,
+}
+
+/// Join proxies learned from GRASP M_FLOOD messages.
+/// Proxies reachable over TCP are preferred, as the pledge talks HTTPS, then proxies the registrar answered through
+/// before, then proxies fewer hops away.
+/// Proxies that failed are skipped until [`FAILURE_BACKOFF`] passed, unless no other proxy is left.
+#[derive(Debug, Default)]
+pub(crate) struct JoinProxies {
+    announcements: RwLock<Vec<Announcement>>,
+    /// See `discovery_cache` in the pledge configuration
+    cache: Option<PathBuf>,
+}
+
+impl JoinProxies {
+    /// Starts with the proxies of the discovery cache that did not expire yet, including their failures
+    pub(crate) fn load(cache: Option<PathBuf>) -> Self {
+        let announcements = cache.as_ref().map(read_cache).unwrap_or_default();
+        if !announcements.is_empty() {
+            event!(Level::INFO, "Restored {} join proxies from the discovery cache", announcements.len());
+        }
+
+        Self {
+            announcements: RwLock::new(announcements),
+            cache,
+        }
+    }
+
+    pub(crate) async fn announce(&self, proxy: JoinProxy, ttl: Duration) {
+        let now = Instant::now();
+        let mut announcements = self.announcements.write().await;
+
+        let before = announcements.len();
+        announcements.retain(|announcement| announcement.expires > now);
+        let mut changed = announcements.len() != before;
+
+        match announcements.iter_mut().find(|announcement| announcement.proxy == proxy) {
+            Some(announcement) => {
+                announcement.expires = now + ttl;
+                // floods repeat well within their lifetime, only persist once the cached expiry got noticeably stale
+                changed |= announcement.expires.duration_since(announcement.persisted_expires) > ttl / 2;
+            }
+            None => {
+                event!(Level::INFO, "Discovered join proxy {}", proxy.url());
+                announcements.push(Announcement {
+                    proxy,
+                    seen: now,
+                    expires: now + ttl,
+                    failed: None,
+                    reachable: false,
+                    persisted_expires: now + ttl,
+                });
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.persist(&mut announcements);
+        }
+    }
+
+    /// Usable proxies, most preferred first
+    pub(crate) async fn candidates(&self) -> Vec<JoinProxy> {
+        let now = Instant::now();
+        let mut announcements: Vec<_> = self
+            .announcements
+            .read()
+            .await
+            .iter()
+            .filter(|announcement| announcement.expires > now)
+            .cloned()
+            .collect();
+
+        announcements.sort_by_key(|announcement| {
+            let backing_off = announcement
+                .failed
+                .is_some_and(|failed| now.duration_since(failed) < FAILURE_BACKOFF);
+            (
+                backing_off,
+                announcement.proxy.transport,
+                !announcement.reachable,
+                std::cmp::Reverse(announcement.proxy.loop_count),
+                announcement.seen,
+            )
+        });
+
+        announcements.into_iter().map(|announcement| announcement.proxy).collect()
+    }
+
+    pub(crate) async fn select(&self) -> Option<JoinProxy> {
+        self.candidates().await.into_iter().next()
+    }
+
+    /// Fails over to the next proxy on the following [`JoinProxies::select`]
+    pub(crate) async fn mark_failed(&self, proxy: &JoinProxy) {
+        event!(Level::WARN, "Join proxy {} failed", proxy.url());
+
+        let mut announcements = self.announcements.write().await;
+        if let Some(announcement) = announcements.iter_mut().find(|announcement| &announcement.proxy == proxy) {
+            announcement.failed = Some(Instant::now());
+            announcement.reachable = false;
+            self.persist(&mut announcements);
+        }
+    }
+
+    /// Records that the registrar answered through the proxy, which clears an earlier failure
+    pub(crate) async fn mark_reachable(&self, proxy: &JoinProxy) {
+        let mut announcements = self.announcements.write().await;
+        if let Some(announcement) = announcements.iter_mut().find(|announcement| &announcement.proxy == proxy) {
+            if announcement.reachable && announcement.failed.is_none() {
+                return;
+            }
+            announcement.failed = None;
+            announcement.reachable = true;
+            self.persist(&mut announcements);
+        }
+    }
+
+    fn persist(&self, announcements: &mut [Announcement]) {
+        let Some(path) = &self.cache else {
+            return;
+        };
+
+        let clock = Clock::now();
+        let cached: Vec<_> = announcements
+            .iter_mut()
+            .map(|announcement| {
+                announcement.persisted_expires = announcement.expires;
+                CachedAnnouncement {
+                    proxy: announcement.proxy.clone(),
+                    expires: clock.unix_millis_at(announcement.expires),
+                    failed: announcement.failed.map(|failed| clock.unix_millis_at(failed)),
+                    reachable: announcement.reachable,
+                }
+            })
+            .collect();
+
+        if let Err(err) = write_cache(path, &cached) {
+            event!(Level::WARN, "Writing discovery cache {:?} failed: {}", path, err);
+        }
+    }
+}
+
+/// Replaces the cache through a temporary file, so an interrupted write does not leave a truncated cache behind
+fn write_cache(path: &PathBuf, cached: &[CachedAnnouncement]) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_vec(cached)?)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+fn read_cache(path: &PathBuf) -> Vec<Announcement> {
+    let cached: Vec<CachedAnnouncement> = match std::fs::read(path) {
+        Ok(content) => match serde_json::from_slice(&content) {
+            Ok(cached) => cached,
+            Err(err) => {
+                event!(Level::WARN, "Ignoring unreadable discovery cache {:?}: {}", path, err);
+                return vec![];
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return vec![],
+        Err(err) => {
+            event!(Level::WARN, "Ignoring unreadable discovery cache {:?}: {}", path, err);
+            return vec![];
+        }
+    };
+
+    let clock = Clock::now();
+    cached
+        .into_iter()
+        .filter_map(|cached| {
+            let expires = clock.instant_at(cached.expires).filter(|expires| *expires > clock.instant)?;
+            Some(Announcement {
+                proxy: cached.proxy,
+                seen: clock.instant,
+                expires,
+                // failures older than the monotonic clock, e.g. right after a reboot, are treated as just now
+                failed: cached.failed.map(|failed| clock.instant_at(failed).unwrap_or(clock.instant)),
+                reachable: cached.reachable,
+                persisted_expires: expires,
+            })
+        })
+        .collect()
+}
+
+/// Maps between instants and wall clock time
+struct Clock {
+    instant: Instant,
+    unix_millis: u64,
+}
+
+impl Clock {
+    fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            unix_millis: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+        }
+    }
+
+    fn unix_millis_at(&self, instant: Instant) -> u64 {
+        match instant.checked_duration_since(self.instant) {
+            Some(ahead) => self.unix_millis + ahead.as_millis() as u64,
+            None => self.unix_millis.saturating_sub(self.instant.duration_since(instant).as_millis() as u64),
+        }
+    }
+
+    fn instant_at(&self, unix_millis: u64) -> Option<Instant> {
+        match unix_millis.checked_sub(self.unix_millis) {
+            Some(ahead) => self.instant.checked_add(Duration::from_millis(ahead)),
+            None => self.instant.checked_sub(Duration::from_millis(self.unix_millis - unix_millis)),
+        }
+    }
+}
+
+/// Indices of the interfaces to discover join proxies on, from `grasp_interfaces` or else `grasp_interface`
+pub(crate) fn interfaces(config: &PledgeConfig) -> anyhow::Result<Vec<u32>> {
+    if config.grasp_interfaces.is_empty() {
+        return Ok(vec![config.grasp_interface]);
+    }
+
+    config.grasp_interfaces.iter().map(|interface| interface_index(interface)).collect()
+}
+
+/// Listens for M_FLOOD messages on all given interfaces at once and records announced join proxies.
+/// The interface a flood arrived on is taken from the scope of its sender, so link-local proxies keep their zone.
+pub(crate) async fn listen(proxies: Arc<JoinProxies>, interfaces: Vec<u32>) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, GRASP_LISTEN_PORT)).await?;
+    for interface in &interfaces {
+        socket.join_multicast_v6(&ALL_GRASP_NEIGHBORS, *interface)?;
+        event!(Level::INFO, "Listening for GRASP flood messages on [{}]:{} at interface {}", ALL_GRASP_NEIGHBORS, GRASP_LISTEN_PORT, interface);
+    }
+
+    // senders without a scope, e.g. with a global address, can only be attributed to an interface if there is just one
+    let fallback_scope = match interfaces.as_slice() {
+        [interface] if *interface != 0 => Some(*interface),
+        _ => None,
+    };
+
+    let mut buffer = vec![0u8; 2048];
+    loop {
+        let (length, mut sender) = socket.recv_from(&mut buffer).await?;
+        if let (SocketAddr::V6(sender), Some(scope)) = (&mut sender, fallback_scope) {
+            if sender.scope_id() == 0 {
+                sender.set_scope_id(scope);
+            }
+        }
+
+        match parse_flood(&buffer[..length], &sender) {
+            Ok((announced, ttl)) => {
+                for proxy in announced {
+                    proxies.announce(proxy, ttl).await;
+                }
+            }
+            Err(err) => {
+                event!(Level::DEBUG, "Ignoring GRASP message from {}: {}", sender, err);
+            }
+        }
+    }
+}
+
+/// Returns the join proxies of an M_FLOOD message together with the announcement lifetime
+pub(crate) fn parse_flood(message: &[u8], sender: &SocketAddr) -> anyhow::Result<(Vec<JoinProxy>, Duration)> {
+    let message: Value = ciborium::de::from_reader(message)?;
+    let items = message.as_array().ok_or(anyhow!("message is not an array"))?;
+
+    let [message_type, _session_id, _initiator, ttl, objectives @ ..] = items.as_slice() else {
+        return Err(anyhow!("message is too short"));
+    };
+
+    if as_u64(message_type) != Some(M_FLOOD) {
+        return Err(anyhow!("not an M_FLOOD message"));
+    }
+
+    let ttl = Duration::from_millis(as_u64(ttl).ok_or(anyhow!("invalid ttl"))?);
+
+    let proxies = objectives
+        .iter()
+        .filter_map(|entry| parse_proxy(entry, sender))
+        .collect();
+
+    Ok((proxies, ttl))
+}
+
+/// Parses `[objective, locator-option]`, anything but an `AN_Proxy` objective is skipped
+fn parse_proxy(entry: &Value, sender: &SocketAddr) -> Option<JoinProxy> {
+    let [objective, locator] = entry.as_array()?.as_slice() else {
+        return None;
+    };
+
+    let objective = objective.as_array()?;
+    if objective.first()?.as_text()? != AN_PROXY {
+        return None;
+    }
+    let loop_count = objective.get(2).and_then(as_u64).unwrap_or(0);
+
+    let [option, address, protocol, port] = locator.as_array()?.as_slice() else {
+        return None;
+    };
+
+    let host = match as_u64(option)? {
+        O_IPV6_LOCATOR => {
+            let octets: [u8; 16] = address.as_bytes()?.as_slice().try_into().ok()?;
+            let address = Ipv6Addr::from(octets);
+            // link-local locators are only usable on the interface the flood was received on
+            match sender {
+                SocketAddr::V6(sender) if is_link_local(&address) && sender.scope_id() != 0 => {
+                    format!("{}%{}", address, sender.scope_id())
+                }
+                _ => address.to_string(),
+            }
+        }
+        O_IPV4_LOCATOR => {
+            let octets: [u8; 4] = address.as_bytes()?.as_slice().try_into().ok()?;
+            IpAddr::V4(Ipv4Addr::from(octets)).to_string()
+        }
+        O_FQDN_LOCATOR => address.as_text()?.to_string(),
+        _ => return None,
+    };
+
+    let transport = if as_u64(protocol)? == IPPROTO_TCP {
+        Transport::Tcp
+    } else {
+        Transport::Udp
+    };
+
+    Some(JoinProxy {
+        host,
+        port: u16::try_from(as_u64(port)?).ok()?,
+        transport,
+        loop_count,
+    })
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    value.as_integer().and_then(|integer| u64::try_from(integer).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flood(objectives: Vec<Value>) -> Vec<u8> {
+        let mut message = vec![
+            Value::from(M_FLOOD),
+            Value::from(1234u64),
+            Value::Bytes(Ipv6Addr::LOCALHOST.octets().to_vec()),
+            Value::from(180_000u64),
+        ];
+        message.extend(objectives);
+
+        let mut encoded = vec![];
+        ciborium::ser::into_writer(&Value::Array(message), &mut encoded).unwrap();
+        encoded
+    }
+
+    fn proxy_objective(name: &str, address: Ipv6Addr, protocol: u64, port: u64) -> Value {
+        Value::Array(vec![
+            Value::Array(vec![Value::from(name), Value::from(4u64), Value::from(1u64), Value::from("")]),
+            Value::Array(vec![
+                Value::from(O_IPV6_LOCATOR),
+                Value::Bytes(address.octets().to_vec()),
+                Value::from(protocol),
+                Value::from(port),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn test_parses_proxy_announcement() {
+        let sender: SocketAddr = "[fe80::1%3]:7017".parse().unwrap();
+        let message = flood(vec![
+            proxy_objective(AN_PROXY, "fe80::1".parse().unwrap(), IPPROTO_TCP, 8443),
+            proxy_objective("AN_join_registrar", "2001:db8::1".parse().unwrap(), IPPROTO_TCP, 443),
+        ]);
+
+        let (proxies, ttl) = parse_flood(&message, &sender).unwrap();
+
+        assert_eq!(ttl, Duration::from_secs(180));
+        assert_eq!(proxies.len(), 1);
+        assert_eq!(proxies[0].host, "fe80::1%3");
+        assert_eq!(proxies[0].transport, Transport::Tcp);
+        assert_eq!(proxies[0].url(), "https://[fe80::1%3]:8443");
+    }
+
+    #[tokio::test]
+    async fn test_prefers_tcp_and_fails_over() {
+        let proxies = JoinProxies::default();
+        let udp = JoinProxy {
+            host: "2001:db8::1".to_string(),
+            port: 5684,
+            transport: Transport::Udp,
+            loop_count: 1,
+        };
+        let first = JoinProxy {
+            host: "2001:db8::2".to_string(),
+            port: 8443,
+            transport: Transport::Tcp,
+            loop_count: 1,
+        };
+        let second = JoinProxy {
+            host: "2001:db8::3".to_string(),
+            port: 8443,
+            transport: Transport::Tcp,
+            loop_count: 1,
+        };
+
+        proxies.announce(udp.clone(), Duration::from_secs(60)).await;
+        proxies.announce(first.clone(), Duration::from_secs(60)).await;
+        proxies.announce(second.clone(), Duration::from_secs(60)).await;
+
+        assert_eq!(proxies.select().await, Some(first.clone()));
+
+        proxies.mark_failed(&first).await;
+        assert_eq!(proxies.candidates().await, vec![second, udp, first]);
+    }
+
+    #[tokio::test]
+    async fn test_restores_discovery_cache() {
+        let path = std::env::temp_dir().join(format!("open-brski-discovery-{}.json", std::process::id()));
+        let proxy = |host: &str| JoinProxy {
+            host: host.to_string(),
+            port: 8443,
+            transport: Transport::Tcp,
+            loop_count: 1,
+        };
+
+        let proxies = JoinProxies::load(Some(path.clone()));
+        proxies.announce(proxy("2001:db8::1"), Duration::from_secs(60)).await;
+        proxies.announce(proxy("2001:db8::2"), Duration::from_secs(60)).await;
+        proxies.announce(proxy("2001:db8::3"), Duration::from_millis(1)).await;
+        proxies.mark_failed(&proxy("2001:db8::1")).await;
+        proxies.mark_reachable(&proxy("2001:db8::3")).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let restored = JoinProxies::load(Some(path.clone()));
+        std::fs::remove_file(&path).unwrap();
+
+        // the expired proxy is gone and the failed one is still backed off
+        assert_eq!(restored.candidates().await, vec![proxy("2001:db8::2"), proxy("2001:db8::1")]);
+    }
+}
@@ -2,19 +2,28 @@ mod tper;
 mod tpvr;
 mod svr;
 mod scac;
-mod ser;
+pub(crate) mod ser;
 mod qps;
-use axum::{routing::post, Router};
+mod ssm;
+mod sfi;
+mod sshc;
+use axum::{middleware, routing::post, Router};
+use common::limits::{enforce_body_limit, Artifact, ArtifactLimits};
 
 use crate::server::ServerState;
 
 #[tracing::instrument(target = "Pledge")]
-pub(crate) fn brski_routes() -> Router<ServerState> {
+pub(crate) fn brski_routes(limits: &ArtifactLimits) -> Router<ServerState> {
+    let guard = |artifact| middleware::from_fn_with_state(limits.guard(artifact), enforce_body_limit);
+
     Router::new()
-        .route("/tpvr", post(tpvr::handle_tpvr))
-        .route("/tper", post(tper::handle_tper))
-        .route("/svr", post(svr::handle_svr))
-        .route("/scac", post(scac::handle_scac))
-        .route("/ser", post(ser::handle_ser))
-        .route("/qps", post(qps::handle_qps))
+        .route("/tpvr", post(tpvr::handle_tpvr).layer(guard(Artifact::Other)))
+        .route("/tper", post(tper::handle_tper).layer(guard(Artifact::Other)))
+        .route("/svr", post(svr::handle_svr).layer(guard(Artifact::Voucher)))
+        .route("/scac", post(scac::handle_scac).layer(guard(Artifact::Other)))
+        .route("/ser", post(ser::handle_ser).layer(guard(Artifact::Other)))
+        .route("/qps", post(qps::handle_qps).layer(guard(Artifact::Other)))
+        .route("/ssm", post(ssm::handle_ssm).layer(guard(Artifact::Other)))
+        .route("/sfi", post(sfi::handle_sfi).layer(guard(Artifact::Firmware)))
+        .route("/sshc", post(sshc::handle_sshc).layer(guard(Artifact::Other)))
 }
@@ -5,19 +5,22 @@ use axum::{
         header::{ACCEPT, CONTENT_TYPE},
         HeaderMap,
     },
-    Json,
-};
-use brski_prm_artifacts::{
-    per::response::PER_JWS,
-    status::pledge::{request::StatusQueryJWS, response::PledgeStatusJWS},
 };
+use brski_prm_artifacts::status::{
+        pledge::{
+            request::StatusQueryJWS,
+            response::PledgeStatusJWS,
+            status::{PledgeStatus, PledgeStatusDetails, StatusContext},
+        },
+        reason_code::ReasonCode,
+    };
 use common::{
     server_error::ServerError,
-    util::{is_jose, is_json},
+    util::is_jose,
 };
 use tracing::{event, Level};
 
-use crate::{parsed_config::ParsedConfig, server::ServerState};
+use crate::{captive::Connectivity, server::ServerState};
 
 #[tracing::instrument(target = "Pledge", skip(state, headers, body))]
 #[debug_handler]
@@ -49,10 +52,25 @@ pub async fn handle_qps(
 
     event!(Level::INFO, "Decoding Status Query JWS");
 
-    let decoded = jws.decode()?;
+    let _decoded = jws.decode()?;
 
     event!(Level::INFO, "Building Pledge Status");
-    let pledge_status = brski_prm_artifacts::status::pledge::status::PledgeStatus::default();
+    let pledge_status = match &state.read().await.connectivity {
+        Some(Connectivity::CaptivePortal { detail }) => PledgeStatus {
+            status: PledgeStatusDetails::ConnectError,
+            reason: Some(ReasonCode::CaptivePortal.to_string()),
+            reason_code: Some(ReasonCode::CaptivePortal),
+            reason_context: Some(StatusContext {
+                pvs_details: detail.clone(),
+            }),
+            ..Default::default()
+        },
+        _ => PledgeStatus::default(),
+    };
+    let pledge_status = PledgeStatus {
+        attestation: state.read().await.attestation(),
+        ..pledge_status
+    };
 
     let pledge_idevid_cert = state.read().await.config.idevid_certificate.clone();
     let plege_idevid_key = state.read().await.config.idevid_privkey.clone();
@@ -74,12 +92,12 @@ pub async fn handle_qps(
 mod tests {
     use axum::body::Body;
     use axum::http::Request;
-    use axum::{routing::post, Router};
+    
     use brski_prm_artifacts::content_type::JOSE;
 
     use super::*;
     use crate::util::get_test_app;
-    use crate::{parsed_config::ParsedConfig, server::get_app};
+    
     use tower::util::ServiceExt;
 
     #[tokio::test]
@@ -4,17 +4,16 @@ use axum::{
         header::{ACCEPT, CONTENT_TYPE},
         HeaderMap,
     },
-    Json,
 };
-use brski_prm_artifacts::{cacerts::response::CACERTS_JWS, ietf_voucher::VoucherRequest, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, status::voucher::{response::vStatus_JWS, status::ReasonContext}};
+use brski_prm_artifacts::cacerts::response::CACERTS_JWS;
 use common::{
     server_error::ServerError,
-    util::{is_jose, is_json, is_jws_voucher},
+    util::is_jose,
 };
 use tracing::{event, Level};
 
-use crate::parsed_config::ParsedConfig;
 use crate::server::ServerState;
+use crate::trust_store;
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Pledge", skip(state, headers, body))]
@@ -52,9 +51,22 @@ pub async fn handle_scac(
 
     let ca_certs = decoded.try_decoded_data()?.payload.x5bag;
 
-    // Install the trust anchor, whatever that means...
+    // only the wrapped CA certificates on the path to the pinned-domain-cert of the voucher are installed
+    let cacerts: Vec<openssl::x509::X509> = ca_certs.iter().map(|cert| (**cert).clone()).collect();
+    let mut state = state.write().await;
+    let Some(trust_anchor) = &state.trust_anchor else {
+        return Err(ServerError::BadRequestWithReason("no voucher pinned a domain yet".to_string()));
+    };
+    let Some(cacerts) = trust_store::anchored_chain(trust_anchor, &cacerts)? else {
+        return Err(ServerError::BadRequestWithReason("cacerts are not anchored in the pinned-domain-cert".to_string()));
+    };
 
-    state.write().await.cacerts = Some(ca_certs);
+    if let Some(path) = &state.config.config.trust_store {
+        if let Err(err) = trust_store::persist(&path.relative(), &cacerts) {
+            event!(Level::ERROR, "Writing trust store failed: {}", err);
+        }
+    }
+    state.cacerts = Some(cacerts.into_iter().map(Into::into).collect());
 
     Ok(())
 }
@@ -1,19 +1,19 @@
 use axum::{
     body::Body, extract::State, http::{
-        header::{ACCEPT, CONTENT_TYPE},
+        header::CONTENT_TYPE,
         HeaderMap,
-    }, Json
+    }
 };
-use brski_prm_artifacts::{cacerts::response::CACERTS_JWS, ietf_voucher::VoucherRequest, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, status::{enroll::response::EnrollStatusJWS, voucher::{response::vStatus_JWS, status::ReasonContext}}};
+use brski_prm_artifacts::status::enroll::response::EnrollStatusJWS;
 use common::{
+    pkcs12,
     server_error::ServerError,
-    util::{is_jose, is_json, is_jws_voucher, is_pkcs7},
+    util::is_pkcs7,
 };
 use brski_prm_artifacts::ietf_voucher::pki::X509;
 use tracing::{event, Level};
 
-use crate::parsed_config::ParsedConfig;
-use crate::server::ServerState;
+use crate::server::{self, ServerState};
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Pledge", skip(state, headers, body))]
 pub async fn handle_ser(
@@ -40,13 +40,22 @@ pub async fn handle_ser(
 
     let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|_| ServerError::BadRequest)?;
 
-    let pledge_ldevid_cert: X509 = openssl::x509::X509::from_der(&body_bytes.to_vec())?.into();
+    let pledge_ldevid_cert: X509 = common::asn1::certificate(&body_bytes)
+        .map_err(|err| ServerError::BadRequestWithReason(format!("LDevID: {}", err)))?
+        .into();
 
-    state.write().await.ldevid_cert = Some(pledge_ldevid_cert);
+    state.write().await.ldevid_cert = Some(pledge_ldevid_cert.clone());
+
+    if let Err(err) = write_ldevid_pkcs12(&*state.read().await, &pledge_ldevid_cert) {
+        event!(Level::ERROR, "Writing LDevID PKCS#12 failed: {}", err);
+    }
     // Install the trust anchor, whatever that means...
 
     event!(Level::INFO, "Building enroll status");
-    let enroll_status = brski_prm_artifacts::status::enroll::status::Status::default();
+    let enroll_status = brski_prm_artifacts::status::enroll::status::Status {
+        attestation: state.read().await.attestation(),
+        ..Default::default()
+    };
 
     let idevid_sign_cert = state.read().await.config.idevid_certificate.clone();
     let idevid_sign_key = state.read().await.config.idevid_privkey.clone();
@@ -60,3 +69,18 @@ pub async fn handle_ser(
     encoded.verify()?;
     Ok(encoded)
 }
+
+/// The CA certificates were delivered before the LDevID, so they complete the chain
+pub(crate) fn write_ldevid_pkcs12(state: &server::State, ldevid: &X509) -> Result<(), ServerError> {
+    let (Some(path), Some(password)) = (&state.config.config.ldevid_pkcs12, &state.config.config.ldevid_pkcs12_password) else {
+        return Ok(());
+    };
+
+    let key = openssl::pkey::PKey::from_ec_key(state.config.idevid_privkey.clone())?;
+    let chain: Vec<openssl::x509::X509> = state.cacerts.iter().flatten().map(|cert| (**cert).clone()).collect();
+    let der = pkcs12::bundle(&state.config.config.idev_id, Some(&key), ldevid, &chain, password)?;
+
+    std::fs::write(path.relative(), der)?;
+    event!(Level::INFO, "Wrote LDevID PKCS#12 to {:?}", path.relative());
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+};
+use brski_prm_artifacts::content_type::OCTET_STREAM;
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{firmware, server::ServerState};
+
+// An image is only installed if it is described by the manifest accepted on /ssm, which is consumed by the update.
+#[tracing::instrument(target = "Pledge", skip(state, headers, body))]
+pub async fn handle_sfi(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(), ServerError> {
+    event!(Level::INFO, "Received sfi request");
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .ok_or(ServerError::BadRequest)?
+        .to_str()?;
+    if content_type != OCTET_STREAM {
+        return Err(ServerError::UnsupportedMediaType);
+    }
+
+    let mut state = state.write().await;
+    let (Some(image_path), Some(state_path)) = (state.config.config.firmware_image.clone(), state.config.config.firmware_state.clone()) else {
+        return Err(ServerError::BadRequestWithReason("No firmware_image configured".to_string()));
+    };
+    let Some(manifest) = state.firmware_manifest.clone() else {
+        return Err(ServerError::BadRequestWithReason("No firmware manifest was accepted".to_string()));
+    };
+
+    manifest
+        .check_image(&body)
+        .map_err(|err| ServerError::BadRequestWithReason(err.to_string()))?;
+    firmware::install(&image_path.relative(), &state_path.relative(), &manifest, &body)?;
+
+    state.installed_sequence_number = Some(manifest.sequence_number);
+    state.firmware_manifest = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+
+    use super::*;
+    use crate::util::get_test_app;
+    use tower::util::ServiceExt;
+
+    #[tokio::test]
+    async fn test_rejects_image_without_manifest() {
+        let app = get_test_app().await.unwrap();
+
+        let request = |content_type| Request::builder().method("POST").uri("/.well-known/brski/sfi").header(CONTENT_TYPE, content_type).body(Body::from("pledge firmware 1.1.0")).unwrap();
+
+        let response = app.clone().oneshot(request("application/text")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = app.oneshot(request(OCTET_STREAM)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,30 @@
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+};
+use brski_prm_artifacts::content_type::SSH_CERTIFICATE;
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{server::ServerState, ssh};
+
+#[tracing::instrument(target = "Pledge", skip(state, headers, body))]
+pub async fn handle_sshc(State(state): State<ServerState>, headers: HeaderMap, body: String) -> Result<(), ServerError> {
+    event!(Level::INFO, "Received sshc request");
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .ok_or(ServerError::BadRequest)?
+        .to_str()
+        .map_err(|_| ServerError::BadRequest)?;
+    if content_type != SSH_CERTIFICATE {
+        return Err(ServerError::UnsupportedMediaType);
+    }
+
+    let config = state.read().await.config.config.clone();
+    let (Some(host_key), Some(ca_public_key)) = (config.ssh_host_key, config.ssh_ca_public_key) else {
+        return Err(ServerError::BadRequestWithReason("Pledge has no SSH host key".to_string()));
+    };
+
+    ssh::install_certificate(&host_key.relative(), &ca_public_key.relative(), &config.idev_id, &body)
+}
@@ -0,0 +1,85 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+};
+use common::{server_error::ServerError, util::is_suit_envelope};
+use tracing::{event, Level};
+
+use crate::server::ServerState;
+use crate::suit::Manifest;
+
+// Firmware manifests are only accepted from manufacturer keys, so onboarding can not be used to install foreign images.
+#[tracing::instrument(target = "Pledge", skip(state, headers, body))]
+pub async fn handle_ssm(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(), ServerError> {
+    event!(Level::INFO, "Received ssm request");
+    event!(Level::DEBUG, "Headers: {:#?}", headers);
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .ok_or(ServerError::BadRequest)?
+        .to_str()?;
+
+    is_suit_envelope(content_type)?;
+
+    let signers = state.read().await.config.firmware_signers.clone();
+    if signers.is_empty() {
+        return Err(ServerError::BadRequestWithReason("No firmware signers configured".to_string()));
+    }
+
+    event!(Level::INFO, "Verifying SUIT manifest");
+    let manifest = Manifest::verify(&body, &signers)
+        .map_err(|err| ServerError::BadRequestWithReason(err.to_string()))?;
+
+    let mut state = state.write().await;
+    manifest
+        .check_device(state.config.firmware_vendor_id.as_deref(), state.config.firmware_class_id.as_deref())
+        .map_err(|err| ServerError::BadRequestWithReason(err.to_string()))?;
+
+    // rollback protection against the installed firmware and the manifest accepted before, see RFC 9019 Section 3
+    let latest = state.installed_sequence_number.max(state.firmware_manifest.as_ref().map(|accepted| accepted.sequence_number));
+    if let Some(latest) = latest {
+        if manifest.sequence_number <= latest {
+            return Err(ServerError::BadRequestWithReason(format!(
+                "Manifest sequence number {} is not newer than {}",
+                manifest.sequence_number, latest
+            )));
+        }
+    }
+
+    event!(Level::INFO, "Accepted SUIT manifest with sequence number {}", manifest.sequence_number);
+    state.firmware_manifest = Some(manifest);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use brski_prm_artifacts::content_type::SUIT_ENVELOPE;
+
+    use super::*;
+    use crate::util::get_test_app;
+    use tower::util::ServiceExt;
+
+    #[tokio::test]
+    async fn test_illegal_content_type() {
+        let app = get_test_app().await.unwrap();
+
+        let response = app.oneshot(Request::builder().method("POST").uri("/.well-known/brski/ssm").header(CONTENT_TYPE, "application/text").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_envelope() {
+        let app = get_test_app().await.unwrap();
+
+        let response = app.oneshot(Request::builder().method("POST").uri("/.well-known/brski/ssm").header(CONTENT_TYPE, SUIT_ENVELOPE).body(Body::from(vec![0xd8, 0x6b, 0xa0])).unwrap()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+}
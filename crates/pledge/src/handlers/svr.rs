@@ -1,20 +1,19 @@
 use axum::{
     extract::State,
     http::{
-        header::{ACCEPT, CONTENT_TYPE},
+        header::CONTENT_TYPE,
         HeaderMap,
     },
-    Json,
 };
-use brski_prm_artifacts::{ietf_voucher::VoucherRequest, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, status::voucher::{response::vStatus_JWS, status::ReasonContext}};
+use brski_prm_artifacts::{issued_voucher::IssuedVoucherJWS, status::voucher::{response::vStatus_JWS, status::{ReasonContext, Status}}};
 use common::{
     server_error::ServerError,
-    util::{is_jose, is_json, is_jws_voucher},
+    util::is_jws_voucher,
 };
 use tracing::{event, Level};
 
-use crate::parsed_config::ParsedConfig;
 use crate::server::ServerState;
+use crate::voucher::verify::{verify, Expected};
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Pledge", skip(state, headers, body))]
 pub async fn handle_svr(
@@ -35,32 +34,57 @@ pub async fn handle_svr(
 
     is_jws_voucher(content_type)?;
 
-    // Verification needs to be taking place later
-
-        
     let jws = IssuedVoucherJWS::Encoded(body);
 
-    event!(Level::INFO, "Decoding issued voucher JWS");
-    let decoded = jws.decode()?;
-
-    let voucher = decoded.try_decoded_data()?.payload;
-
-    event!(Level::INFO, "Drawing trust anchor from received voucher");
-    let trust_anchor = voucher.details.pinned_domain_cert.ok_or(ServerError::BadRequest)?;
+    event!(Level::INFO, "Verifying issued voucher");
+    let verified = {
+        let state = state.read().await;
+        let expected = Expected {
+            serial_number: &state.config.config.idev_id,
+            nonce: state.voucher_request_nonce.as_deref(),
+            now: chrono::Utc::now(),
+        };
+        verify(&jws, &state.config.masa_anchors, &expected)
+    };
 
-    // Install the trust anchor, whatever that means...
-    state.write().await.trust_anchor = Some(trust_anchor);
+    // Only a verified voucher pins the domain CA, a rejected one is reported in the status
+    let outcome = match verified {
+        Ok(verified) => {
+            event!(Level::INFO, "Installing trust anchor from received voucher");
+            verified.pin(&mut *state.write().await);
+            Ok(())
+        }
+        Err(err) => {
+            event!(Level::WARN, "Rejected voucher: {}", err);
+            Err(err)
+        }
+    };
 
     let pledge_idevid_cert = state.read().await.config.idevid_certificate.clone();
     let pledge_idevid_key = state.read().await.config.idevid_privkey.clone();
 
     event!(Level::INFO, "Building voucher response");
-    let status = brski_prm_artifacts::status::voucher::status::Status {
-        reason: Some("Voucher successfully processed".to_string()),
-        reason_context: ReasonContext {
-            pvs_details: "JSON".to_string(),
+    let status = match outcome {
+        Ok(()) => Status {
+            reason: Some("Voucher successfully processed".to_string()),
+            reason_context: ReasonContext {
+                pvs_details: "JSON".to_string(),
+            },
+            ..Default::default()
+        },
+        Err(err) => Status {
+            status: false,
+            reason: Some(err.reason),
+            reason_code: Some(err.reason_code),
+            reason_context: ReasonContext {
+                pvs_details: err.reason_code.to_string(),
+            },
+            ..Default::default()
         },
-        ..Default::default()
+    };
+    let status = Status {
+        attestation: state.read().await.attestation(),
+        ..status
     };
 
     let response = brski_prm_artifacts::status::voucher::response::Response::new(
@@ -57,9 +57,14 @@ pub async fn handle_tper(
         openssl::pkey::PKey::private_key_from_der(private_key.private_key_to_der()?.as_slice())?;
 
     event!(Level::INFO, "Building tPER response payload");
-    let per_response_payload =
+    let mut per_response_payload =
         brski_prm_artifacts::per::response_payload::ResponsePayload::try_new(&private_key)?;
 
+    if let Some(path) = state.read().await.config.config.ssh_host_key.clone() {
+        let host_key = crate::ssh::host_key(&path.relative())?;
+        per_response_payload.ssh_host_key = Some(host_key.public_key().to_openssh().map_err(|err| ServerError::BadResponse(err.to_string()))?);
+    }
+
 
     event!(Level::INFO, "Building tPER response");    
     let per_response = brski_prm_artifacts::per::response::Response::new(
@@ -83,13 +88,13 @@ pub async fn handle_tper(
 mod tests {
     use axum::body::Body;
     use axum::http::Request;
-    use axum::{routing::post, Router};
+    
     use brski_prm_artifacts::content_type::{JOSE, JSON};
-    use brski_prm_artifacts::ietf_voucher::agent_signed_data;
+    
 
     use super::*;
     use crate::util::get_test_app;
-    use crate::{parsed_config::ParsedConfig, server::get_app};
+    
     use tower::util::ServiceExt;
 
     #[tokio::test]
@@ -4,16 +4,18 @@ use axum::{
         header::{ACCEPT, CONTENT_TYPE},
         HeaderMap,
     },
+    response::{IntoResponse, Response},
     Json,
 };
-use brski_prm_artifacts::{ietf_voucher::VoucherRequest, jws::JWS, pvr::response::PVR_JWS};
+use brski_prm_artifacts::{content_type::CMS_VOUCHER, pvr::response::PVR_JWS};
+use cli::config::ArtifactFormat;
 use common::{
     server_error::ServerError,
     util::{is_jose, is_json},
 };
 use tracing::event;
 
-use crate::{server::ServerState};
+use crate::{cms::sign_voucher_request, server::ServerState};
 use pledge_lib::tpvr::create_pvr;
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Pledge", skip(state, headers, payload))]
@@ -22,7 +24,7 @@ pub async fn handle_tpvr(
     State(state): State<ServerState>,
     headers: HeaderMap,
     Json(payload): Json<brski_prm_artifacts::pvr::trigger::Trigger>,
-) -> Result<PVR_JWS, ServerError> {
+) -> Result<Response, ServerError> {
     
     event!(tracing::Level::INFO, "Received tPVR request");
 
@@ -38,7 +40,12 @@ pub async fn handle_tpvr(
         .ok_or(ServerError::BadRequest)?
         .to_str()?;
 
-    is_jose(ACCEPT, accept)?;
+    let artifact_format = state.read().await.config.config.artifact_format;
+    match artifact_format {
+        ArtifactFormat::Jose => is_jose(ACCEPT, accept)?,
+        ArtifactFormat::Cms if accept != CMS_VOUCHER => return Err(ServerError::NotAcceptible),
+        ArtifactFormat::Cms => {}
+    }
 
     // at this point in time, we can not verify the PVR Trigger. We also can not verify the agent-signed-data in the PVR Trigger.
 
@@ -47,12 +54,24 @@ pub async fn handle_tpvr(
     let voucher_request = create_pvr(payload, state.read().await.config.config.idev_id.clone());
     event!(tracing::Level::INFO, "Timestamp: {:?}", voucher_request.details.created_on);
     event!(tracing::Level::INFO, "Nonce: {:?}", voucher_request.details.nonce);
+    state.write().await.voucher_request_nonce = voucher_request.details.nonce.clone();
 
 
     event!(tracing::Level::INFO, "Built Voucher Request");
     event!(tracing::Level::DEBUG, "Voucher Request: {:#?}", voucher_request);
 
 
+    if artifact_format == ArtifactFormat::Cms {
+        event!(tracing::Level::INFO, "Signing tPVR response as CMS");
+        let state = state.read().await;
+        let signed = sign_voucher_request(
+            &voucher_request,
+            &state.config.idevid_certificate,
+            &state.config.idevid_privkey,
+        )?;
+        return Ok(([(CONTENT_TYPE, CMS_VOUCHER)], signed).into_response());
+    }
+
     event!(tracing::Level::INFO, "Building tPVR response");
     let pvr_response = brski_prm_artifacts::pvr::response::Response::new(
         voucher_request,
@@ -71,19 +90,19 @@ pub async fn handle_tpvr(
     let jws = jws.encode(private_key)?;
     jws.verify()?;
 
-    Ok(jws)
+    Ok(jws.into_response())
 }
 
 #[cfg(test)]
 mod tests {
-    use axum::body::Body;
-    use axum::http::Request;
-    use axum::{routing::post, Router};
-    use brski_prm_artifacts::content_type::{JOSE, JSON};
-    use brski_prm_artifacts::ietf_voucher::agent_signed_data;
-
-    use super::*;
-    use crate::util::get_test_app;
-    use crate::{parsed_config::ParsedConfig, server::get_app};
-    use tower::util::ServiceExt;
+    
+    
+    
+    
+    
+
+    
+    
+    
+    
 }
\ No newline at end of file
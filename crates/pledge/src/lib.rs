@@ -1,19 +1,57 @@
+mod attestation;
+mod captive;
+mod cms;
+mod doctor;
+mod dump;
+mod firmware;
+mod grasp;
 mod handlers;
+mod onboarding;
 mod parsed_config;
 mod server;
-use axum::{
-    Router,
-};
-use handlers::brski_routes;
+mod ssh;
+mod suit;
+mod transport;
+mod trust_store;
+mod voucher;
+mod zone;
 use parsed_config::{parse_config};
+use pledge_lib::state_machine::{PledgeStateMachine, RetryPolicy};
 
 use cli::config::PledgeConfig;
 use common::error::AppError;
+use std::path::PathBuf;
 use tokio::task::JoinHandle;
-use tower_http::trace::TraceLayer;
 use tracing::{event, Level};
 mod util;
 
+pub use doctor::{doctor, DoctorReport};
+
+/// Discovery rounds of a dry run before it gives up, a pledge onboarding for real keeps trying
+const DRY_RUN_ROUNDS: u32 = 3;
+
+/// Onboards once through a discovered join proxy like a pledge-initiated pledge, but installs neither the domain
+/// CA certificates nor the LDevID. With `dump_artifacts` every artifact exchanged with the registrar is written there.
+#[tracing::instrument(skip(config), target = "Pledge", name = "Pledge::dry_run")]
+pub async fn dry_run(config: PledgeConfig, dump_artifacts: Option<PathBuf>) -> anyhow::Result<(), AppError> {
+    let parsed_config = parse_config(config)?;
+    let dump = dump_artifacts.as_deref().map(dump::ArtifactDump::create).transpose()?;
+
+    let state = server::dry_run_state(&parsed_config)?;
+    let backend = onboarding::RegistrarBackend::dry_run(state, dump).await?;
+    let policy = RetryPolicy {
+        max_rounds: Some(DRY_RUN_ROUNDS),
+        ..Default::default()
+    };
+    let ldevid = PledgeStateMachine::with_observer(backend, onboarding::LogObserver, policy).run().await?;
+
+    event!(Level::INFO, "Dry run finished, the registrar issued LDevID {:?}", ldevid.subject_name());
+    if let Some(directory) = dump_artifacts {
+        event!(Level::INFO, "Artifacts were written to {:?}", directory);
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip(config), target = "Pledge", name = "Pledge::start")]
 pub async fn start(config: PledgeConfig) -> anyhow::Result<JoinHandle<()>, AppError> {
     let address = "0.0.0.0:".to_owned() + &config.port;
@@ -0,0 +1,393 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use brski_client::{ClientError, RegistrarClient};
+use brski_prm_artifacts::{
+    digest::message_digest,
+    issued_voucher::IssuedVoucherJWS,
+    per::{
+        response::PER_JWS,
+        response_payload::{ResponsePayload, ResponsePayloadInner},
+    },
+    pvr::response::PVR_JWS,
+    status::{enroll::response::EnrollStatusJWS, reason_code::ReasonCode, voucher::response::vStatus_JWS},
+};
+use common::{asn1, chain::chains_to};
+use openssl::{pkey::PKey, x509::X509};
+use pledge_lib::{
+    state_machine::{PledgeBackend, PledgeObserver, PledgeState, StepError},
+    tpvr::create_registrar_pvr,
+};
+use tracing::{event, Level};
+
+use crate::{
+    dump::ArtifactDump,
+    grasp::{JoinProxies, JoinProxy},
+    handlers::ser::write_ldevid_pkcs12,
+    server::ServerState,
+    transport::{self, BoundSession, ProxiedRegistrar, PROBE_TIMEOUT},
+    trust_store,
+    voucher::verify::{self, Expected},
+};
+
+/// Registrar reached through a join proxy, with the certificate it presented on the provisional TLS connection
+#[derive(Debug, Clone)]
+pub(crate) struct Registrar {
+    proxy: JoinProxy,
+    client: RegistrarClient,
+    certificate: Option<X509>,
+}
+
+/// Onboards the Linux pledge over HTTPS through join proxies found by GRASP discovery.
+/// Accepted vouchers and the LDevID are installed in the pledge state, like the registrar-agent driven handlers do.
+/// A dry run keeps them in memory only and writes neither the trust store nor the LDevID PKCS#12.
+pub(crate) struct RegistrarBackend {
+    state: ServerState,
+    join_proxies: Arc<JoinProxies>,
+    /// Nonce of the last voucher request, the voucher has to carry it
+    nonce: Option<Vec<u8>>,
+    /// Voucher request the registrar holds until the MASA is reachable, it is sent again to pick up the voucher
+    held_pvr: Option<PVR_JWS>,
+    dry_run: bool,
+    /// Only set for a dry run
+    dump: Option<ArtifactDump>,
+}
+
+impl RegistrarBackend {
+    pub(crate) async fn new(state: ServerState) -> anyhow::Result<Self> {
+        let join_proxies = Arc::clone(&state.read().await.join_proxies);
+
+        Ok(Self {
+            state,
+            join_proxies,
+            nonce: None,
+            held_pvr: None,
+            dry_run: false,
+            dump: None,
+        })
+    }
+
+    pub(crate) async fn dry_run(state: ServerState, dump: Option<ArtifactDump>) -> anyhow::Result<Self> {
+        Ok(Self {
+            dry_run: true,
+            dump,
+            ..Self::new(state).await?
+        })
+    }
+
+    /// Writes an artifact of a dry run. A failed write fails the step, so a dump is never missing artifacts.
+    fn dump(&self, write: impl FnOnce(&ArtifactDump) -> anyhow::Result<()>) -> Result<(), StepError> {
+        match &self.dump {
+            Some(dump) => write(dump).map_err(internal_error),
+            None => Ok(()),
+        }
+    }
+
+    /// IDevID certificate and DER encoded key, which sign all artifacts of the pledge
+    async fn idevid(&self) -> Result<(X509, Vec<u8>), StepError> {
+        let state = self.state.read().await;
+        let key = state.config.idevid_privkey.private_key_to_der().map_err(internal_error)?;
+        Ok((state.config.idevid_certificate.clone(), key))
+    }
+
+    /// Fetches the domain CA certificates and installs those on the path to the pinned-domain-cert
+    async fn install_cacerts(&self, registrar: &Registrar, trust: &X509) -> Result<Vec<X509>, StepError> {
+        let bundle = registrar.client.ca_certs().await.map_err(client_error)?;
+        self.dump(|dump| dump.certificates("cacerts", &bundle))?;
+        let Some(cacerts) = trust_store::anchored_chain(trust, &bundle).map_err(internal_error)? else {
+            return Err(StepError::fatal(ReasonCode::UntrustedCertificate, "cacerts are not anchored in the pinned-domain-cert"));
+        };
+        if cacerts.len() < bundle.len() {
+            event!(Level::WARN, "Dropped {} cacerts not on the path to the pinned-domain-cert", bundle.len() - cacerts.len());
+        }
+
+        let mut state = self.state.write().await;
+        if let Some(path) = state.config.config.trust_store.as_ref().filter(|_| !self.dry_run) {
+            if let Err(err) = trust_store::persist(&path.relative(), &cacerts) {
+                event!(Level::ERROR, "Writing trust store failed: {}", err);
+            }
+        }
+        state.cacerts = Some(cacerts.iter().cloned().map(Into::into).collect());
+        Ok(cacerts)
+    }
+}
+
+impl PledgeBackend for RegistrarBackend {
+    type Registrar = Registrar;
+    type Voucher = IssuedVoucherJWS;
+    type DomainTrust = X509;
+    type LDevId = X509;
+
+    async fn discover(&mut self) -> Result<Self::Registrar, StepError> {
+        let proxy = transport::probe(&self.join_proxies)
+            .await
+            .ok_or_else(|| StepError::retryable(ReasonCode::RegistrarUnreachable, "no join proxy discovered yet"))?;
+
+        let client = ProxiedRegistrar::new(&proxy)
+            .map_err(|err| StepError::fatal(ReasonCode::RegistrarUnreachable, err.to_string()))?
+            .registrar;
+
+        let certificate = match client.peer_certificate(PROBE_TIMEOUT).await {
+            Ok(certificate) => certificate,
+            Err(err) => {
+                self.join_proxies.mark_failed(&proxy).await;
+                return Err(StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string()));
+            }
+        };
+
+        if certificate.is_none() {
+            event!(Level::WARN, "Registrar at {} did not present a TLS certificate, it can not be checked against the voucher", proxy.url());
+        }
+
+        Ok(Registrar { proxy, client, certificate })
+    }
+
+    async fn request_voucher(&mut self, registrar: &Self::Registrar) -> Result<Self::Voucher, StepError> {
+        let pvr = match self.held_pvr.take() {
+            Some(pvr) => pvr,
+            None => {
+                let serial_number = self.state.read().await.config.config.idev_id.clone();
+                let (idevid_certificate, idevid_privkey) = self.idevid().await?;
+
+                let voucher_request = create_registrar_pvr(serial_number, registrar.certificate.clone().map(Into::into));
+                self.nonce = voucher_request.details.nonce.clone();
+
+                let pvr: PVR_JWS = brski_prm_artifacts::pvr::response::Response::new(voucher_request, [idevid_certificate])
+                    .try_into()
+                    .map_err(internal_error)?;
+                pvr.encode(idevid_privkey).map_err(internal_error)?
+            }
+        };
+
+        self.dump(|dump| dump.jws("voucher-request", &pvr.clone().try_encoded_data()?))?;
+
+        event!(Level::INFO, "Requesting voucher through join proxy {}", registrar.proxy.authority());
+        let voucher = match registrar.client.request_voucher(pvr.clone()).await {
+            Err(err @ ClientError::Accepted { .. }) => {
+                self.held_pvr = Some(pvr);
+                return Err(client_error(err));
+            }
+            result => result.map_err(client_error)?,
+        };
+        self.dump(|dump| dump.jws("voucher", &voucher.clone().try_encoded_data()?))?;
+        Ok(voucher)
+    }
+
+    async fn validate_voucher(&mut self, registrar: &Self::Registrar, voucher: &Self::Voucher) -> Result<Self::DomainTrust, StepError> {
+        let verified = {
+            let state = self.state.read().await;
+            let expected = Expected {
+                serial_number: &state.config.config.idev_id,
+                nonce: self.nonce.as_deref(),
+                now: chrono::Utc::now(),
+            };
+            verify::verify(voucher, &state.config.masa_anchors, &expected)?
+        };
+
+        // RFC 8995 Section 5.6.2, the provisional TLS connection is only trusted now
+        if let Some(certificate) = &registrar.certificate {
+            match chains_to(certificate, std::slice::from_ref(&verified.pinned_domain_cert), &[]) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(StepError::fatal(
+                        ReasonCode::UntrustedCertificate,
+                        "registrar TLS certificate does not chain to the pinned-domain-cert",
+                    ))
+                }
+                Err(err) => return Err(internal_error(err)),
+            }
+        }
+
+        Ok(verified.pin(&mut *self.state.write().await))
+    }
+
+    async fn voucher_status(&mut self, registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError> {
+        use brski_prm_artifacts::status::voucher::status::{ReasonContext, Status};
+
+        let status = match outcome {
+            Ok(()) => Status {
+                reason: Some("Voucher successfully processed".to_string()),
+                reason_context: ReasonContext {
+                    pvs_details: "JSON".to_string(),
+                },
+                ..Default::default()
+            },
+            Err(err) => Status {
+                status: false,
+                reason: Some(err.reason.clone()),
+                reason_code: Some(err.reason_code),
+                reason_context: ReasonContext {
+                    pvs_details: err.reason_code.to_string(),
+                },
+                ..Default::default()
+            },
+        };
+        let status = Status {
+            attestation: self.state.read().await.attestation(),
+            ..status
+        };
+
+        let (idevid_certificate, idevid_privkey) = self.idevid().await?;
+        let jws = vStatus_JWS::try_from(brski_prm_artifacts::status::voucher::response::Response::new(status, vec![idevid_certificate]))
+            .map_err(internal_error)?;
+        let jws = jws.encode(idevid_privkey).map_err(internal_error)?;
+        self.dump(|dump| dump.jws("voucher-status", &jws.clone().try_encoded_data()?))?;
+
+        registrar.client.voucher_status(jws).await.map_err(client_error)
+    }
+
+    async fn enroll(&mut self, registrar: &Self::Registrar, trust: &Self::DomainTrust) -> Result<Self::LDevId, StepError> {
+        let (idevid_certificate, idevid_privkey) = {
+            let state = self.state.read().await;
+            (state.config.idevid_certificate.clone(), state.config.idevid_privkey.clone())
+        };
+        let key = PKey::from_ec_key(idevid_privkey).map_err(internal_error)?;
+
+        let cacerts = self.install_cacerts(registrar, trust).await?;
+
+        // the PER goes over its own session, authenticated with the IDevID, whose channel binding the CSR carries
+        let session = BoundSession::connect(&registrar.proxy, &idevid_certificate, &key)
+            .await
+            .map_err(|err| StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string()))?;
+        if !chains_to(&session.registrar_certificate, std::slice::from_ref(trust), &[]).map_err(internal_error)? {
+            return Err(StepError::fatal(
+                ReasonCode::UntrustedCertificate,
+                "registrar TLS certificate does not chain to the pinned-domain-cert",
+            ));
+        }
+
+        let payload = ResponsePayload::try_new(&key).map_err(internal_error)?;
+        let csr = asn1::with_challenge_password(&payload.csr.p10_csr, &session.binding, &key, message_digest(&key)).map_err(internal_error)?;
+        let payload = ResponsePayload {
+            csr: ResponsePayloadInner { p10_csr: csr.into() },
+            ..payload
+        };
+        self.dump(|dump| dump.csr("csr", &payload.csr.p10_csr))?;
+        let per: PER_JWS = brski_prm_artifacts::per::response::Response::new(payload, [idevid_certificate])
+            .try_into()
+            .map_err(internal_error)?;
+        let per = per.encode(key.private_key_to_der().map_err(internal_error)?).map_err(internal_error)?;
+        self.dump(|dump| dump.jws("enroll-request", &per.clone().try_encoded_data()?))?;
+
+        let ldevid = session.request_enroll(per.try_encoded_data().map_err(internal_error)?).await.map_err(|err| match err.downcast::<ClientError>() {
+            Ok(err) => client_error(err),
+            Err(err) => StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string()),
+        })?;
+        self.dump(|dump| dump.certificate("ldevid", &ldevid))?;
+
+        let public_key = ldevid.public_key().map_err(internal_error)?;
+        if !public_key.public_eq(&key) {
+            return Err(StepError::fatal(ReasonCode::KeyMismatch, "LDevID is not issued for the IDevID key"));
+        }
+        if !chains_to(&ldevid, std::slice::from_ref(trust), &cacerts).map_err(internal_error)? {
+            return Err(StepError::fatal(ReasonCode::UntrustedCertificate, "LDevID does not chain to the pinned-domain-cert"));
+        }
+
+        let mut state = self.state.write().await;
+        state.ldevid_cert = Some(ldevid.clone().into());
+        if self.dry_run {
+            return Ok(ldevid);
+        }
+        if let Err(err) = write_ldevid_pkcs12(&state, &ldevid.clone().into()) {
+            event!(Level::ERROR, "Writing LDevID PKCS#12 failed: {}", err);
+        }
+
+        Ok(ldevid)
+    }
+
+    async fn enroll_status(&mut self, registrar: &Self::Registrar, outcome: Result<(), &StepError>) -> Result<(), StepError> {
+        use brski_prm_artifacts::status::enroll::status::{ReasonContext, Status};
+
+        let status = match outcome {
+            Ok(()) => Status::default(),
+            Err(err) => Status {
+                status: false,
+                reason: err.reason.clone(),
+                reason_code: Some(err.reason_code),
+                reason_context: ReasonContext {
+                    pes_details: err.reason_code.to_string(),
+                },
+                ..Default::default()
+            },
+        };
+        let status = Status {
+            attestation: self.state.read().await.attestation(),
+            ..status
+        };
+
+        let (idevid_certificate, idevid_privkey) = self.idevid().await?;
+        let jws: EnrollStatusJWS = brski_prm_artifacts::status::enroll::response::Response::new(status, [idevid_certificate])
+            .try_into()
+            .map_err(internal_error)?;
+        let jws = jws.encode(idevid_privkey).map_err(internal_error)?;
+        self.dump(|dump| dump.jws("enroll-status", &jws.clone().try_encoded_data()?))?;
+
+        registrar.client.enroll_status(jws).await.map_err(client_error)
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Logs the progress of the onboarding
+pub(crate) struct LogObserver;
+
+impl PledgeObserver for LogObserver {
+    fn transition(&mut self, from: PledgeState, to: PledgeState) {
+        event!(Level::INFO, "Onboarding: {:?} -> {:?}", from, to);
+    }
+
+    fn retrying(&mut self, state: PledgeState, attempt: u32, delay: Duration, error: &StepError) {
+        event!(Level::WARN, "Onboarding: {:?} failed ({}), attempt {} in {:?}", state, error, attempt + 1, delay);
+    }
+}
+
+fn internal_error(err: impl std::fmt::Display) -> StepError {
+    StepError::fatal(ReasonCode::InternalError, err.to_string())
+}
+
+/// Unreachable registrars and server errors are worth another try, rejections are not
+fn client_error(err: ClientError) -> StepError {
+    match &err {
+        ClientError::Transport(_) => StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string()),
+        // e.g. a voucher request the registrar holds while the MASA is unreachable
+        ClientError::Accepted { .. } => StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string()),
+        ClientError::Status { status, .. } if status.is_server_error() => {
+            StepError::retryable(ReasonCode::RegistrarUnreachable, err.to_string())
+        }
+        _ => StepError::fatal(ReasonCode::InternalError, err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_client_errors() {
+        let unreachable = client_error(ClientError::Status {
+            endpoint: "requestvoucher".to_string(),
+            status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+        });
+        assert!(unreachable.retryable);
+        let rejected = client_error(ClientError::Status {
+            endpoint: "requestvoucher".to_string(),
+            status: reqwest::StatusCode::FORBIDDEN,
+            body: String::new(),
+        });
+        assert!(!rejected.retryable);
+    }
+
+    #[test]
+    fn test_ldevid_chains_to_pinned_domain_cert() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let (vendor_ca, _) = &certs.vendor_ca;
+        let (registrar, _) = &certs.registrar;
+
+        assert!(chains_to(registrar, std::slice::from_ref(registrar_ca), &[]).unwrap());
+        assert!(!chains_to(registrar, std::slice::from_ref(vendor_ca), &[]).unwrap());
+    }
+}
@@ -1,5 +1,6 @@
 use cli::config::PledgeConfig;
-use common::error::AppError;
+use anyhow::anyhow;
+use common::{error::AppError, util::parse_uuid};
 use openssl::{
     ec::{self, EcKey},
     pkey::Private,
@@ -11,6 +12,13 @@ pub(crate) struct ParsedConfig {
     pub(crate) config: PledgeConfig,
     pub(crate) idevid_certificate: X509,
     pub(crate) idevid_privkey: EcKey<Private>,
+    /// Anchors the MASA signing vouchers for this pledge chains to, see [`PledgeConfig::masa_anchors`]
+    pub(crate) masa_anchors: Vec<X509>,
+    /// Certificates of the manufacturer keys that sign SUIT manifests
+    pub(crate) firmware_signers: Vec<X509>,
+    /// Vendor and class ID that SUIT manifests have to name, see [`PledgeConfig::firmware_vendor_id`]
+    pub(crate) firmware_vendor_id: Option<Vec<u8>>,
+    pub(crate) firmware_class_id: Option<Vec<u8>>,
 }
 
 pub(crate) fn parse_config(config: PledgeConfig) -> anyhow::Result<ParsedConfig, AppError> {
@@ -19,9 +27,38 @@ pub(crate) fn parse_config(config: PledgeConfig) -> anyhow::Result<ParsedConfig,
 
     let unparsed_idevid_cert = std::fs::read(config.idevid_certificate.relative())?;
     let idevid_cert = X509::from_pem(&unparsed_idevid_cert)?;
+
+    // an anchor pinned for the MASA named in the IDevID replaces the manufacturer anchors
+    let masa_anchors = match common::idevid::masa_uri(&idevid_cert).and_then(|masa_uri| config.masa_anchors.get(&masa_uri)) {
+        Some(anchor) => vec![X509::from_pem(&std::fs::read(anchor.relative())?)?],
+        None => config
+            .manufacturer_anchors
+            .iter()
+            .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+            .collect::<anyhow::Result<Vec<_>, AppError>>()?,
+    };
+
+    let firmware_signers = config
+        .firmware_signers
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+        .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+    let uuid = |uuid: &Option<String>| -> anyhow::Result<Option<Vec<u8>>, AppError> {
+        uuid.as_deref()
+            .map(|uuid| parse_uuid(uuid).ok_or_else(|| anyhow!("{} is not a UUID", uuid).into()))
+            .transpose()
+    };
+    let firmware_vendor_id = uuid(&config.firmware_vendor_id)?;
+    let firmware_class_id = uuid(&config.firmware_class_id)?;
+
     Ok(ParsedConfig {
         config,
         idevid_certificate: idevid_cert,
         idevid_privkey: ee_key,
+        masa_anchors,
+        firmware_signers,
+        firmware_vendor_id,
+        firmware_class_id,
     })
 }
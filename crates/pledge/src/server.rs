@@ -1,16 +1,24 @@
 use std::{fmt::Debug, sync::Arc};
 
 use crate::{
+    attestation::AttestationCounter,
+    captive::{self, Connectivity},
+    firmware,
+    grasp::{self, JoinProxies},
+    onboarding::{LogObserver, RegistrarBackend},
+    transport, trust_store,
     parsed_config::{ParsedConfig},
+    suit::Manifest,
 };
 use axum::{Router};
-use brski_prm_artifacts::ietf_voucher::artifact::VoucherArtifact;
+use brski_prm_artifacts::status::attestation::CounterAttestation;
 use common::error::AppError;
 use brski_prm_artifacts::ietf_voucher::pki::X509;
 use tower_http::trace::TraceLayer;
 use tracing::{event, Level};
 
 use super::handlers::brski_routes;
+use pledge_lib::state_machine::{PledgeStateMachine, RetryPolicy};
 
 use tokio::{sync::RwLock};
 use tokio::time::{self, Duration, Instant};
@@ -20,31 +28,111 @@ pub struct State {
     pub config: ParsedConfig,
     pub cacerts: Option<Vec<X509>>,
     pub ldevid_cert: Option<X509>,
-    pub trust_anchor: Option<X509>
+    pub trust_anchor: Option<X509>,
+    /// Nonce of the last tPVR response, the voucher answering it has to carry it
+    pub(crate) voucher_request_nonce: Option<Vec<u8>>,
+    pub additional_configuration: Option<String>,
+    /// Latest verified firmware manifest, an image is only applied if it passes [`Manifest::check_image`]
+    pub(crate) firmware_manifest: Option<Manifest>,
+    /// Sequence number of the installed firmware, kept in `firmware_state`
+    pub(crate) installed_sequence_number: Option<u64>,
+    pub(crate) join_proxies: Arc<JoinProxies>,
+    /// Result of the last connectivity check, `None` until one succeeded or when no check url is configured
+    pub(crate) connectivity: Option<Connectivity>,
+    /// Only set with `attestation_counter`
+    pub(crate) attestation_counter: Option<Arc<AttestationCounter>>,
+}
+
+impl State {
+    /// Counter attestation for the next status message, `None` without a counter file or if it can not be written
+    pub(crate) fn attestation(&self) -> Option<CounterAttestation> {
+        let counter = self.attestation_counter.as_ref()?;
+        match counter.next() {
+            Ok(attestation) => Some(attestation),
+            Err(err) => {
+                event!(Level::ERROR, "Writing attestation counter failed, sending status without it: {}", err);
+                None
+            }
+        }
+    }
 }
 
 impl Debug for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ServerState {{ cacerts: {:?}, ldevid_cert: {:?}, trust_anchor: {:?} }}", self.cacerts, self.ldevid_cert, self.trust_anchor)
+        write!(f, "ServerState {{ cacerts: {:?}, ldevid_cert: {:?}, trust_anchor: {:?}, additional_configuration: {:?}, firmware_manifest: {:?}, installed_sequence_number: {:?} }}", self.cacerts, self.ldevid_cert, self.trust_anchor, self.additional_configuration, self.firmware_manifest, self.installed_sequence_number)
     }
 }
 
 pub type ServerState = Arc<RwLock<State>>;
 
-pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppError> {
-
-    let state = State {
+/// State on start, with the domain CA certificates of the trust store and the cached join proxies
+fn initial_state(config: &ParsedConfig) -> anyhow::Result<State, AppError> {
+    Ok(State {
         config: config.clone(),
-        cacerts: None,
+        cacerts: match &config.config.trust_store {
+            Some(path) => trust_store::load(&path.relative())?.map(|cacerts| cacerts.into_iter().map(Into::into).collect()),
+            None => None,
+        },
         ldevid_cert: None,
-        trust_anchor: None
-    };
+        trust_anchor: None,
+        voucher_request_nonce: None,
+        additional_configuration: None,
+        firmware_manifest: None,
+        installed_sequence_number: match &config.config.firmware_state {
+            Some(path) => firmware::installed_sequence_number(&path.relative())?,
+            None => None,
+        },
+        join_proxies: Arc::new(JoinProxies::load(config.config.discovery_cache.as_ref().map(|path| path.relative()))),
+        connectivity: None,
+        attestation_counter: match &config.config.attestation_counter {
+            Some(path) => Some(Arc::new(AttestationCounter::open(path.relative())?)),
+            None => None,
+        },
+    })
+}
+
+fn spawn_discovery(config: &ParsedConfig, join_proxies: &Arc<JoinProxies>) -> anyhow::Result<(), AppError> {
+    if config.config.grasp_discovery {
+        let join_proxies = Arc::clone(join_proxies);
+        let interfaces = grasp::interfaces(&config.config)?;
+        tokio::spawn(async move {
+            if let Err(err) = grasp::listen(join_proxies, interfaces).await {
+                event!(Level::ERROR, "GRASP discovery stopped: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// State of a dry run, which only onboards and serves no registrar-agent
+pub(crate) fn dry_run_state(config: &ParsedConfig) -> anyhow::Result<ServerState, AppError> {
+    let state = initial_state(config)?;
+    spawn_discovery(config, &state.join_proxies)?;
+    Ok(Arc::new(RwLock::new(state)))
+}
+
+pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppError> {
+    let state = initial_state(config)?;
+    spawn_discovery(config, &state.join_proxies)?;
 
     let server_state = Arc::new(RwLock::new(state));
 
-    let routes = Router::new().nest("/.well-known/brski", brski_routes());
+    if config.config.pledge_initiated {
+        let backend = RegistrarBackend::new(Arc::clone(&server_state)).await?;
+        tokio::spawn(async move {
+            match PledgeStateMachine::with_observer(backend, LogObserver, RetryPolicy::default()).run().await {
+                Ok(ldevid) => event!(Level::INFO, "Onboarding finished, enrolled with LDevID {:?}", ldevid.subject_name()),
+                Err(err) => event!(Level::ERROR, "Onboarding failed: {}", err),
+            }
+        });
+    }
+
+    let routes = Router::new().nest("/.well-known/brski", brski_routes(&config.config.artifact_limits));
 
-    let app = routes.with_state(Arc::clone(&server_state)).layer(TraceLayer::new_for_http());
+    let app = routes
+        .with_state(Arc::clone(&server_state))
+        .layer(axum::middleware::from_fn(common::problem::problem_details))
+        .layer(TraceLayer::new_for_http());
 
     tokio::spawn(async move {
         let sleep = time::sleep(Duration::from_millis(10));
@@ -56,6 +144,17 @@ pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppErr
                     println!("timer elapsed");
                     sleep.as_mut().reset(Instant::now() + Duration::from_secs(30));
                     event!(Level::INFO, "Server State: {:?}", server_state.read().await);
+                    let join_proxies = Arc::clone(&server_state.read().await.join_proxies);
+                    if let Some(proxy) = transport::probe(&join_proxies).await {
+                        event!(Level::INFO, "Preferred join proxy: {}", proxy.url());
+                    }
+                    let check_url = server_state.read().await.config.config.captive_portal_check_url.clone();
+                    if let Some(url) = check_url {
+                        match captive::detect(&url).await {
+                            Ok(connectivity) => server_state.write().await.connectivity = Some(connectivity),
+                            Err(err) => event!(Level::DEBUG, "Connectivity check failed: {}", err),
+                        }
+                    }
                 },
             }
         }
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use common::server_error::ServerError;
+use ssh_key::{certificate::CertType, rand_core::OsRng, Algorithm, Certificate, HashAlg, LineEnding, PrivateKey, PublicKey};
+use tracing::{event, Level};
+
+fn ssh_error(err: ssh_key::Error) -> ServerError {
+    ServerError::BadRequestWithReason(format!("SSH host key: {}", err))
+}
+
+/// Loads the host key, generating an Ed25519 key on first use
+pub(crate) fn host_key(path: &Path) -> Result<PrivateKey, ServerError> {
+    if path.exists() {
+        return PrivateKey::read_openssh_file(path).map_err(ssh_error);
+    }
+
+    event!(Level::INFO, "Generating SSH host key {:?}", path);
+    let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).map_err(ssh_error)?;
+    key.write_openssh_file(path, LineEnding::LF).map_err(ssh_error)?;
+    Ok(key)
+}
+
+fn certificate_path(host_key: &Path) -> PathBuf {
+    let mut path = host_key.as_os_str().to_owned();
+    path.push("-cert.pub");
+    PathBuf::from(path)
+}
+
+/// Installs the host certificate, after checking that it is a currently valid host certificate signed by the SSH CA
+/// and that it was issued for the host key with the serial-number of the pledge as principal
+pub(crate) fn install_certificate(
+    host_key_path: &Path,
+    ca_public_key_path: &Path,
+    serial_number: &str,
+    certificate: &str,
+) -> Result<(), ServerError> {
+    let certificate = Certificate::from_openssh(certificate.trim()).map_err(ssh_error)?;
+    let ca_public_key = PublicKey::read_openssh_file(ca_public_key_path).map_err(ssh_error)?;
+    let host_key = host_key(host_key_path)?;
+
+    // checks the CA signature and the validity period, the principals and critical options are checked below
+    certificate
+        .validate([&ca_public_key.fingerprint(HashAlg::Sha256)])
+        .map_err(|_| ServerError::BadRequestWithReason("SSH host certificate is not signed by the SSH CA or not valid now".to_string()))?;
+    if certificate.cert_type() != CertType::Host {
+        return Err(ServerError::BadRequestWithReason("SSH certificate is not a host certificate".to_string()));
+    }
+    if !certificate.valid_principals().iter().any(|principal| principal == serial_number) {
+        return Err(ServerError::BadRequestWithReason(format!("SSH host certificate is not valid for {}", serial_number)));
+    }
+    if !certificate.critical_options().is_empty() {
+        return Err(ServerError::BadRequestWithReason("SSH host certificate has critical options".to_string()));
+    }
+    if certificate.public_key() != host_key.public_key().key_data() {
+        return Err(ServerError::BadRequestWithReason("SSH host certificate was not issued for the host key".to_string()));
+    }
+
+    let path = certificate_path(host_key_path);
+    std::fs::write(&path, certificate.to_openssh().map_err(ssh_error)? + "\n")?;
+    event!(Level::INFO, "Installed SSH host certificate {:?}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use ssh_key::certificate::Builder;
+
+    use super::*;
+
+    const SERIAL_NUMBER: &str = "00-D0-E5-F2-00-02";
+
+    fn sign(ca_key: &PrivateKey, host_key: &PrivateKey, cert_type: CertType, principal: &str) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut builder = Builder::new_with_random_nonce(&mut OsRng, host_key.public_key().clone(), now - 60, now + 3600).unwrap();
+        builder.cert_type(cert_type).unwrap().key_id(principal).unwrap().valid_principal(principal).unwrap();
+        builder.sign(ca_key).unwrap().to_openssh().unwrap()
+    }
+
+    #[test]
+    fn test_installs_certificates_of_the_ssh_ca_only() {
+        let directory = std::env::temp_dir().join(format!("open-brski-ssh-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (host_key_path, ca_path) = (directory.join("ssh_host_ed25519_key"), directory.join("ssh_ca.pub"));
+
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        ca_key.public_key().write_openssh_file(&ca_path).unwrap();
+        let host_key = host_key(&host_key_path).unwrap();
+        let other_ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+
+        let install = |certificate: &str| install_certificate(&host_key_path, &ca_path, SERIAL_NUMBER, certificate);
+        assert!(install(&sign(&other_ca_key, &host_key, CertType::Host, SERIAL_NUMBER)).is_err());
+        assert!(install(&sign(&ca_key, &host_key, CertType::User, SERIAL_NUMBER)).is_err());
+        assert!(install(&sign(&ca_key, &host_key, CertType::Host, "00-D0-E5-F2-00-03")).is_err());
+        assert!(!certificate_path(&host_key_path).exists());
+
+        install(&sign(&ca_key, &host_key, CertType::Host, SERIAL_NUMBER)).unwrap();
+        assert!(certificate_path(&host_key_path).exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+use ciborium::Value;
+use openssl::{
+    bn::BigNum,
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    sign::Verifier,
+    x509::X509,
+};
+use thiserror::Error;
+
+/// CBOR tag of a SUIT envelope
+const SUIT_ENVELOPE_TAG: u64 = 107;
+const COSE_SIGN1_TAG: u64 = 18;
+
+const ENVELOPE_AUTHENTICATION_WRAPPER: u64 = 2;
+const ENVELOPE_MANIFEST: u64 = 3;
+
+const MANIFEST_VERSION: u64 = 1;
+const MANIFEST_SEQUENCE_NUMBER: u64 = 2;
+const MANIFEST_COMMON: u64 = 3;
+const COMMON_SHARED_SEQUENCE: u64 = 4;
+
+const DIRECTIVE_SET_PARAMETERS: u64 = 19;
+const DIRECTIVE_OVERRIDE_PARAMETERS: u64 = 20;
+
+const PARAMETER_VENDOR_ID: u64 = 1;
+const PARAMETER_CLASS_ID: u64 = 2;
+const PARAMETER_IMAGE_DIGEST: u64 = 3;
+const PARAMETER_IMAGE_SIZE: u64 = 14;
+
+const COSE_HEADER_ALG: u64 = 1;
+const COSE_ES256: i64 = -7;
+const COSE_ES384: i64 = -35;
+
+#[derive(Debug, Error)]
+pub(crate) enum SuitError {
+    #[error("Malformed SUIT envelope: {0}")]
+    Malformed(&'static str),
+    #[error("Unsupported algorithm {0}")]
+    UnsupportedAlgorithm(i64),
+    #[error("Manifest digest does not match the authentication wrapper")]
+    ManifestDigest,
+    #[error("Manifest is not signed by a manufacturer key")]
+    Untrusted,
+    #[error("Manifest is not for this device - Reason: {0}")]
+    OtherDevice(&'static str),
+    #[error("Image does not match the manifest - Reason: {0}")]
+    ImageMismatch(String),
+    #[error(transparent)]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+    #[error(transparent)]
+    CborSer(#[from] ciborium::ser::Error<std::io::Error>),
+    #[error(transparent)]
+    OpensslError(#[from] openssl::error::ErrorStack),
+}
+
+/// SUIT_Digest, `[algorithm, bytes]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Digest {
+    pub(crate) algorithm: i64,
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Digest {
+    fn parse(value: &Value) -> Result<Self, SuitError> {
+        let [algorithm, Value::Bytes(bytes)] = value.as_array().ok_or(SuitError::Malformed("digest is not an array"))?.as_slice() else {
+            return Err(SuitError::Malformed("digest is not [algorithm, bytes]"));
+        };
+        let algorithm = as_i64(algorithm).ok_or(SuitError::Malformed("digest algorithm is not an integer"))?;
+        Ok(Digest { algorithm, bytes: bytes.clone() })
+    }
+
+    fn matches(&self, data: &[u8]) -> Result<bool, SuitError> {
+        let digest = match self.algorithm {
+            -16 => MessageDigest::sha256(),
+            -43 => MessageDigest::sha384(),
+            -44 => MessageDigest::sha512(),
+            algorithm => return Err(SuitError::UnsupportedAlgorithm(algorithm)),
+        };
+        Ok(hash(digest, data)?.as_ref() == self.bytes.as_slice())
+    }
+}
+
+/// Firmware manifest (draft-ietf-suit-manifest) that was verified against the manufacturer keys.
+/// Only the shared sequence of the common section is evaluated, which describes the image of the first component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pub(crate) sequence_number: u64,
+    pub(crate) vendor_id: Option<Vec<u8>>,
+    pub(crate) class_id: Option<Vec<u8>>,
+    pub(crate) image_digest: Digest,
+    pub(crate) image_size: Option<u64>,
+}
+
+impl Manifest {
+    /// Verifies the envelope against the signer certificates and parses the manifest
+    pub(crate) fn verify(envelope: &[u8], signers: &[X509]) -> Result<Self, SuitError> {
+        let envelope: Value = ciborium::de::from_reader(envelope)?;
+        let envelope = untag(envelope, SUIT_ENVELOPE_TAG);
+
+        let authentication = map_entry(&envelope, ENVELOPE_AUTHENTICATION_WRAPPER)
+            .and_then(Value::as_bytes)
+            .ok_or(SuitError::Malformed("envelope has no authentication wrapper"))?;
+        let manifest = map_entry(&envelope, ENVELOPE_MANIFEST)
+            .and_then(Value::as_bytes)
+            .ok_or(SuitError::Malformed("envelope has no manifest"))?;
+
+        let authentication: Value = ciborium::de::from_reader(authentication.as_slice())?;
+        let [Value::Bytes(digest), blocks @ ..] = authentication.as_array().ok_or(SuitError::Malformed("authentication wrapper is not an array"))?.as_slice() else {
+            return Err(SuitError::Malformed("authentication wrapper has no manifest digest"));
+        };
+
+        // the digest covers the bstr wrapped manifest
+        let mut wrapped = vec![];
+        ciborium::ser::into_writer(&Value::Bytes(manifest.clone()), &mut wrapped)?;
+        if !Digest::parse(&ciborium::de::from_reader(digest.as_slice())?)?.matches(&wrapped)? {
+            return Err(SuitError::ManifestDigest);
+        }
+
+        let mut signed = false;
+        for block in blocks {
+            let block = block.as_bytes().ok_or(SuitError::Malformed("authentication block is not a byte string"))?;
+            if verify_sign1(&ciborium::de::from_reader(block.as_slice())?, digest, signers)? {
+                signed = true;
+                break;
+            }
+        }
+        if !signed {
+            return Err(SuitError::Untrusted);
+        }
+
+        Self::parse(&ciborium::de::from_reader(manifest.as_slice())?)
+    }
+
+    fn parse(manifest: &Value) -> Result<Self, SuitError> {
+        if map_entry(manifest, MANIFEST_VERSION).and_then(as_u64) != Some(1) {
+            return Err(SuitError::Malformed("manifest version is not 1"));
+        }
+        let sequence_number = map_entry(manifest, MANIFEST_SEQUENCE_NUMBER)
+            .and_then(as_u64)
+            .ok_or(SuitError::Malformed("manifest has no sequence number"))?;
+
+        let common = map_entry(manifest, MANIFEST_COMMON)
+            .and_then(Value::as_bytes)
+            .ok_or(SuitError::Malformed("manifest has no common section"))?;
+        let common: Value = ciborium::de::from_reader(common.as_slice())?;
+        let shared_sequence = map_entry(&common, COMMON_SHARED_SEQUENCE)
+            .and_then(Value::as_bytes)
+            .ok_or(SuitError::Malformed("common section has no shared sequence"))?;
+        let shared_sequence: Value = ciborium::de::from_reader(shared_sequence.as_slice())?;
+        let commands = shared_sequence.as_array().ok_or(SuitError::Malformed("shared sequence is not an array"))?;
+
+        let mut vendor_id = None;
+        let mut class_id = None;
+        let mut image_digest = None;
+        let mut image_size = None;
+
+        // the sequence alternates between commands and their arguments
+        for command in commands.chunks(2) {
+            let [command, argument] = command else {
+                return Err(SuitError::Malformed("command without argument"));
+            };
+            if !matches!(as_u64(command), Some(DIRECTIVE_SET_PARAMETERS | DIRECTIVE_OVERRIDE_PARAMETERS)) {
+                continue;
+            }
+
+            for (key, value) in argument.as_map().ok_or(SuitError::Malformed("parameters are not a map"))? {
+                match as_u64(key) {
+                    Some(PARAMETER_VENDOR_ID) => vendor_id = value.as_bytes().cloned(),
+                    Some(PARAMETER_CLASS_ID) => class_id = value.as_bytes().cloned(),
+                    Some(PARAMETER_IMAGE_DIGEST) => {
+                        let digest = value.as_bytes().ok_or(SuitError::Malformed("image digest is not a byte string"))?;
+                        image_digest = Some(Digest::parse(&ciborium::de::from_reader(digest.as_slice())?)?);
+                    }
+                    Some(PARAMETER_IMAGE_SIZE) => image_size = as_u64(value),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Manifest {
+            sequence_number,
+            vendor_id,
+            class_id,
+            image_digest: image_digest.ok_or(SuitError::Malformed("manifest has no image digest"))?,
+            image_size,
+        })
+    }
+
+    /// Checks the vendor and class ID of the manifest against the configured ones of the pledge (RFC 9019 Section 3).
+    /// A configured ID has to be named by the manifest, the check is skipped for IDs that are not configured.
+    pub(crate) fn check_device(&self, vendor_id: Option<&[u8]>, class_id: Option<&[u8]>) -> Result<(), SuitError> {
+        if vendor_id.is_some_and(|vendor_id| self.vendor_id.as_deref() != Some(vendor_id)) {
+            return Err(SuitError::OtherDevice("vendor ID differs"));
+        }
+        if class_id.is_some_and(|class_id| self.class_id.as_deref() != Some(class_id)) {
+            return Err(SuitError::OtherDevice("class ID differs"));
+        }
+        Ok(())
+    }
+
+    /// Must pass before an image is applied, so only images described by a verified manifest are installed
+    pub(crate) fn check_image(&self, image: &[u8]) -> Result<(), SuitError> {
+        if let Some(size) = self.image_size {
+            if size != image.len() as u64 {
+                return Err(SuitError::ImageMismatch(format!("size is {} instead of {}", image.len(), size)));
+            }
+        }
+        if !self.image_digest.matches(image)? {
+            return Err(SuitError::ImageMismatch("digest differs".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Verifies a COSE_Sign1 with detached payload against any of the signers
+fn verify_sign1(sign1: &Value, payload: &[u8], signers: &[X509]) -> Result<bool, SuitError> {
+    let sign1 = untag(sign1.clone(), COSE_SIGN1_TAG);
+    let [Value::Bytes(protected), _, _, Value::Bytes(signature)] = sign1.as_array().ok_or(SuitError::Malformed("authentication block is not a COSE_Sign1"))?.as_slice() else {
+        return Err(SuitError::Malformed("authentication block is not a COSE_Sign1"));
+    };
+
+    let header: Value = ciborium::de::from_reader(protected.as_slice())?;
+    let (digest, length) = match map_entry(&header, COSE_HEADER_ALG).and_then(as_i64) {
+        Some(COSE_ES256) => (MessageDigest::sha256(), 32),
+        Some(COSE_ES384) => (MessageDigest::sha384(), 48),
+        Some(algorithm) => return Err(SuitError::UnsupportedAlgorithm(algorithm)),
+        None => return Err(SuitError::Malformed("authentication block has no algorithm")),
+    };
+    if signature.len() != 2 * length {
+        return Ok(false);
+    }
+    let (r, s) = signature.split_at(length);
+    let signature = EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?.to_der()?;
+
+    let mut to_be_signed = vec![];
+    ciborium::ser::into_writer(
+        &Value::Array(vec![
+            Value::from("Signature1"),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(vec![]),
+            Value::Bytes(payload.to_vec()),
+        ]),
+        &mut to_be_signed,
+    )?;
+
+    for signer in signers {
+        let key = signer.public_key()?;
+        let mut verifier = Verifier::new(digest, &key)?;
+        verifier.update(&to_be_signed)?;
+        // a key of another curve fails to verify instead of failing the manifest
+        if verifier.verify(&signature).unwrap_or(false) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn untag(value: Value, tag: u64) -> Value {
+    match value {
+        Value::Tag(found, value) if found == tag => *value,
+        value => value,
+    }
+}
+
+fn map_entry(map: &Value, key: u64) -> Option<&Value> {
+    map.as_map()?.iter().find(|(found, _)| as_u64(found) == Some(key)).map(|(_, value)| value)
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    value.as_integer().and_then(|integer| u64::try_from(integer).ok())
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    value.as_integer().and_then(|integer| i64::try_from(integer).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{pkey::{PKey, Private}, sign::Signer};
+
+    use super::*;
+
+    const IMAGE: &[u8] = b"pledge firmware 1.1.0";
+    const VENDOR_ID: [u8; 16] = [0xfa, 0x6b, 0x4a, 0x53, 0xd5, 0xad, 0x5f, 0xdf, 0xbe, 0x9d, 0xe6, 0x63, 0xe4, 0xd4, 0x1f, 0xfe];
+    const CLASS_ID: [u8; 16] = [0x14, 0x92, 0xaf, 0x14, 0x25, 0x69, 0x5e, 0x48, 0xbf, 0x42, 0x9b, 0x2d, 0x51, 0xf2, 0xab, 0x45];
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut encoded = vec![];
+        ciborium::ser::into_writer(value, &mut encoded).unwrap();
+        encoded
+    }
+
+    fn envelope(key: &PKey<Private>, sequence_number: u64, image: &[u8]) -> Vec<u8> {
+        let image_digest = Value::Array(vec![Value::from(-16), Value::Bytes(hash(MessageDigest::sha256(), image).unwrap().to_vec())]);
+        let shared_sequence = Value::Array(vec![
+            Value::from(DIRECTIVE_OVERRIDE_PARAMETERS),
+            Value::Map(vec![
+                (Value::from(PARAMETER_VENDOR_ID), Value::Bytes(VENDOR_ID.to_vec())),
+                (Value::from(PARAMETER_CLASS_ID), Value::Bytes(CLASS_ID.to_vec())),
+                (Value::from(PARAMETER_IMAGE_DIGEST), Value::Bytes(cbor(&image_digest))),
+                (Value::from(PARAMETER_IMAGE_SIZE), Value::from(image.len() as u64)),
+            ]),
+        ]);
+        let common = Value::Map(vec![(Value::from(COMMON_SHARED_SEQUENCE), Value::Bytes(cbor(&shared_sequence)))]);
+        let manifest = cbor(&Value::Map(vec![
+            (Value::from(MANIFEST_VERSION), Value::from(1)),
+            (Value::from(MANIFEST_SEQUENCE_NUMBER), Value::from(sequence_number)),
+            (Value::from(MANIFEST_COMMON), Value::Bytes(cbor(&common))),
+        ]));
+
+        let manifest_digest = cbor(&Value::Array(vec![
+            Value::from(-16),
+            Value::Bytes(hash(MessageDigest::sha256(), &cbor(&Value::Bytes(manifest.clone()))).unwrap().to_vec()),
+        ]));
+
+        let protected = cbor(&Value::Map(vec![(Value::from(COSE_HEADER_ALG), Value::from(COSE_ES256))]));
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(&cbor(&Value::Array(vec![
+            Value::from("Signature1"),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(vec![]),
+            Value::Bytes(manifest_digest.clone()),
+        ]))).unwrap();
+        let signature = EcdsaSig::from_der(&signer.sign_to_vec().unwrap()).unwrap();
+        let signature = [signature.r().to_vec_padded(32).unwrap(), signature.s().to_vec_padded(32).unwrap()].concat();
+
+        let sign1 = Value::Tag(COSE_SIGN1_TAG, Box::new(Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(vec![]),
+            Value::Null,
+            Value::Bytes(signature),
+        ])));
+        let authentication = Value::Array(vec![Value::Bytes(manifest_digest), Value::Bytes(cbor(&sign1))]);
+
+        cbor(&Value::Tag(SUIT_ENVELOPE_TAG, Box::new(Value::Map(vec![
+            (Value::from(ENVELOPE_AUTHENTICATION_WRAPPER), Value::Bytes(cbor(&authentication))),
+            (Value::from(ENVELOPE_MANIFEST), Value::Bytes(manifest)),
+        ]))))
+    }
+
+    #[test]
+    fn test_verifies_manifest_and_image() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (vendor_cert, vendor_key) = &certs.vendor;
+
+        let manifest = Manifest::verify(&envelope(vendor_key, 3, IMAGE), std::slice::from_ref(vendor_cert)).unwrap();
+        assert_eq!(manifest.sequence_number, 3);
+        assert_eq!(manifest.image_size, Some(IMAGE.len() as u64));
+
+        assert!(manifest.check_image(IMAGE).is_ok());
+        assert!(matches!(manifest.check_image(b"pledge firmware 6.6.6"), Err(SuitError::ImageMismatch(_))));
+    }
+
+    #[test]
+    fn test_checks_vendor_and_class_id() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (vendor_cert, vendor_key) = &certs.vendor;
+
+        let manifest = Manifest::verify(&envelope(vendor_key, 3, IMAGE), std::slice::from_ref(vendor_cert)).unwrap();
+        assert!(manifest.check_device(None, None).is_ok());
+        assert!(manifest.check_device(Some(&VENDOR_ID), Some(&CLASS_ID)).is_ok());
+        assert!(matches!(manifest.check_device(Some(&CLASS_ID), None), Err(SuitError::OtherDevice(_))));
+        assert!(matches!(manifest.check_device(Some(&VENDOR_ID), Some(&VENDOR_ID)), Err(SuitError::OtherDevice(_))));
+
+        let anonymous = Manifest { vendor_id: None, ..manifest };
+        assert!(matches!(anonymous.check_device(Some(&VENDOR_ID), None), Err(SuitError::OtherDevice(_))));
+    }
+
+    #[test]
+    fn test_rejects_untrusted_and_modified_manifests() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (vendor_cert, vendor_key) = &certs.vendor;
+        let (_, registrar_key) = &certs.registrar;
+
+        let untrusted = envelope(registrar_key, 3, IMAGE);
+        assert!(matches!(Manifest::verify(&untrusted, std::slice::from_ref(vendor_cert)), Err(SuitError::Untrusted)));
+
+        // swap the manifest of a validly signed envelope
+        let mut signed: Value = ciborium::de::from_reader(envelope(vendor_key, 3, IMAGE).as_slice()).unwrap();
+        let other: Value = ciborium::de::from_reader(envelope(vendor_key, 4, IMAGE).as_slice()).unwrap();
+        if let (Value::Tag(_, signed), Value::Tag(_, other)) = (&mut signed, &other) {
+            signed.as_map_mut().unwrap()[1] = other.as_map().unwrap()[1].clone();
+        }
+        assert!(matches!(Manifest::verify(&cbor(&signed), std::slice::from_ref(vendor_cert)), Err(SuitError::ManifestDigest)));
+    }
+}
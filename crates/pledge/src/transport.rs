@@ -0,0 +1,211 @@
+use std::net::{SocketAddr, SocketAddrV6};
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::body::Bytes;
+use brski_client::{ClientError, RegistrarClient};
+use brski_prm_artifacts::content_type::JWS_VOUCHER;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    client::conn::http1::{self, SendRequest},
+    header::{ACCEPT, CONTENT_TYPE, HOST},
+    Request,
+};
+use hyper_util::rt::TokioIo;
+use openssl::{
+    pkey::{PKeyRef, Private},
+    ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion},
+    x509::X509,
+};
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+use tracing::{event, Level};
+
+use crate::grasp::{JoinProxies, JoinProxy, Transport};
+use crate::zone::scoped_address;
+
+/// Host name the pledge uses for the join proxy, so link-local destinations keep their zone id,
+/// which urls can not carry
+const JOIN_PROXY_HOST: &str = "brski-join-proxy";
+
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// HTTPS connection to the registrar through a circuit join proxy.
+/// TLS runs end-to-end with the registrar, the proxy only relays the connection.
+#[derive(Debug, Clone)]
+pub(crate) struct ProxiedRegistrar {
+    pub(crate) registrar: RegistrarClient,
+}
+
+impl ProxiedRegistrar {
+    /// The registrar is not authenticated yet (provisional TLS, RFC 8995 Section 5.1),
+    /// its certificate is checked against the voucher later on
+    pub(crate) fn new(proxy: &JoinProxy) -> anyhow::Result<Self> {
+        if proxy.transport != Transport::Tcp {
+            return Err(anyhow!(
+                "join proxy {} is a constrained proxy, which needs CoAP over DTLS and is not supported by this pledge",
+                proxy.authority()
+            ));
+        }
+
+        let builder = reqwest::Client::builder().danger_accept_invalid_certs(true).tls_info(true);
+
+        let (builder, base_url) = match link_local_destination(proxy) {
+            Some(destination) => {
+                event!(Level::DEBUG, "Connecting to link-local join proxy {}", destination);
+                (
+                    builder.resolve(JOIN_PROXY_HOST, destination),
+                    format!("https://{}:{}", JOIN_PROXY_HOST, proxy.port),
+                )
+            }
+            None => (builder, proxy.url()),
+        };
+
+        Ok(Self {
+            registrar: RegistrarClient::new(builder.build()?, base_url),
+        })
+    }
+}
+
+/// TLS connection to the registrar through a join proxy on which the pledge authenticates with its IDevID.
+/// The CSR of a PER sent on it carries the tls-exporter channel binding of the session (RFC 9266) as challengePassword,
+/// so the registrar can tell that the PER was not taken over from another session.
+pub(crate) struct BoundSession {
+    sender: SendRequest<Full<Bytes>>,
+    authority: String,
+    /// Base64 encoded channel binding, the challengePassword of the CSR
+    pub(crate) binding: String,
+    pub(crate) registrar_certificate: X509,
+}
+
+impl BoundSession {
+    const LABEL: &'static str = "EXPORTER-Channel-Binding";
+    const LENGTH: usize = 32;
+
+    /// Like [`ProxiedRegistrar`] the registrar is not authenticated by the handshake, its certificate has to be checked
+    /// against the pinned-domain-cert before anything is sent
+    pub(crate) async fn connect(proxy: &JoinProxy, idevid: &X509, key: &PKeyRef<Private>) -> anyhow::Result<Self> {
+        let (stream, authority) = match link_local_destination(proxy) {
+            Some(destination) => (TcpStream::connect(destination).await?, format!("{}:{}", JOIN_PROXY_HOST, proxy.port)),
+            None => (TcpStream::connect((proxy.host.as_str(), proxy.port)).await?, proxy.authority()),
+        };
+
+        let mut connector = SslConnector::builder(SslMethod::tls_client())?;
+        connector.set_min_proto_version(Some(SslVersion::TLS1_2))?;
+        connector.set_verify(SslVerifyMode::NONE);
+        connector.set_certificate(idevid)?;
+        connector.set_private_key(key)?;
+        let ssl = connector.build().configure()?.verify_hostname(false).into_ssl(JOIN_PROXY_HOST)?;
+
+        let mut stream = SslStream::new(ssl, stream)?;
+        tokio::time::timeout(PROBE_TIMEOUT, Pin::new(&mut stream).connect()).await??;
+
+        // without the extended master secret the exported value of a TLS 1.2 session is not unique to it
+        let ssl = stream.ssl();
+        if ssl.version2() != Some(SslVersion::TLS1_3) && ssl.extms_support() != Some(true) {
+            return Err(anyhow!("registrar negotiated {} without extended master secret", ssl.version_str()));
+        }
+        let mut binding = vec![0; Self::LENGTH];
+        ssl.export_keying_material(&mut binding, Self::LABEL, Some(&[]))?;
+        let registrar_certificate = ssl.peer_certificate().ok_or(anyhow!("registrar presented no TLS certificate"))?;
+
+        let (sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                event!(Level::DEBUG, "Bound registrar connection failed: {}", err);
+            }
+        });
+
+        Ok(Self {
+            sender,
+            authority,
+            binding: openssl::base64::encode_block(&binding),
+            registrar_certificate,
+        })
+    }
+
+    /// Sends the PER and returns the LDevID, like [`RegistrarClient::request_enroll`]. Rejections are [`ClientError`]s.
+    pub(crate) async fn request_enroll(mut self, per: String) -> anyhow::Result<X509> {
+        event!(Level::INFO, "Sending PER to registrar at {} on the bound session", self.authority);
+        let request = Request::post("/.well-known/brski/requestenroll")
+            .header(HOST, &self.authority)
+            .header(ACCEPT, JWS_VOUCHER)
+            .header(CONTENT_TYPE, JWS_VOUCHER)
+            .body(Full::new(Bytes::from(per)))?;
+
+        let response = self.sender.send_request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(ClientError::Status {
+                endpoint: "requestenroll".to_string(),
+                status: reqwest::StatusCode::from_u16(status.as_u16())?,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            }
+            .into());
+        }
+
+        Ok(common::asn1::certificate(&body).map_err(ClientError::from)?)
+    }
+}
+
+/// Checks that the registrar answers through the preferred proxy, failing over to the next one otherwise
+pub(crate) async fn probe(proxies: &JoinProxies) -> Option<JoinProxy> {
+    let proxy = proxies.select().await?;
+
+    let registrar = match ProxiedRegistrar::new(&proxy) {
+        Ok(registrar) => registrar,
+        Err(err) => {
+            event!(Level::WARN, "{}", err);
+            return Some(proxy);
+        }
+    };
+
+    match registrar.registrar.probe(PROBE_TIMEOUT).await {
+        Ok(_) => {
+            proxies.mark_reachable(&proxy).await;
+            Some(proxy)
+        }
+        Err(err) => {
+            event!(Level::DEBUG, "Probing join proxy {} failed: {}", proxy.authority(), err);
+            proxies.mark_failed(&proxy).await;
+            proxies.select().await
+        }
+    }
+}
+
+/// Parses `fe80::…%<zone>` hosts of proxies discovered on a link, the zone is an interface index or name
+fn link_local_destination(proxy: &JoinProxy) -> Option<SocketAddr> {
+    let (address, zone) = scoped_address(&proxy.host)?;
+
+    Some(SocketAddr::V6(SocketAddrV6::new(address, proxy.port, 0, zone)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_local_proxy_keeps_zone() {
+        let proxy = JoinProxy {
+            host: "fe80::1%3".to_string(),
+            port: 8443,
+            transport: Transport::Tcp,
+            loop_count: 1,
+        };
+
+        let registrar = ProxiedRegistrar::new(&proxy).unwrap();
+        assert_eq!(registrar.registrar.url("requestvoucher"), "https://brski-join-proxy:8443/.well-known/brski/requestvoucher");
+        assert_eq!(
+            link_local_destination(&proxy),
+            Some("[fe80::1%3]:8443".parse().unwrap())
+        );
+
+        let constrained = JoinProxy {
+            transport: Transport::Udp,
+            ..proxy
+        };
+        assert!(ProxiedRegistrar::new(&constrained).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Domain CA certificates of the pledge (RFC 8995 Section 5.9.1)
+//!
+//! The pinned-domain-cert of the voucher only covers the registrar. Before enrolling, the pledge fetches the full CA
+//! bundle from `/.well-known/est/cacerts` and installs the certificates of the bundle on the validated path to the
+//! pinned-domain-cert, so later connections into the domain can be validated. Any other certificate of the bundle is
+//! dropped. With `trust_store` set, the installed certificates are written there as PEM and installed again on the
+//! next start.
+
+use std::path::Path;
+
+use openssl::{
+    error::ErrorStack,
+    x509::{X509VerifyResult, X509},
+};
+use tracing::{event, Level};
+
+use common::chain::verified_chain;
+
+/// The certificates of the bundle anchored in the pinned-domain-cert: the pinned-domain-cert itself if the bundle
+/// contains it, otherwise the path from the pinned-domain-cert to a root of the bundle, e.g. when the voucher pinned the
+/// registrar certificate instead of the domain CA. `None` if the bundle is not anchored.
+pub(crate) fn anchored_chain(pinned_domain_cert: &X509, cacerts: &[X509]) -> Result<Option<Vec<X509>>, ErrorStack> {
+    for certificate in cacerts {
+        if certificate.to_der()? == pinned_domain_cert.to_der()? {
+            return Ok(Some(vec![certificate.clone()]));
+        }
+    }
+
+    let roots = cacerts
+        .iter()
+        .filter(|certificate| certificate.issued(certificate) == X509VerifyResult::OK)
+        .cloned()
+        .collect::<Vec<_>>();
+    if roots.is_empty() {
+        return Ok(None);
+    }
+    // the path starts with the pinned-domain-cert, which is not part of the bundle
+    Ok(verified_chain(pinned_domain_cert, &roots, cacerts)?.ok().map(|path| path.into_iter().skip(1).collect()))
+}
+
+/// Replaces the trust store at `path`, the previous one is kept if writing fails
+pub(crate) fn persist(path: &Path, cacerts: &[X509]) -> anyhow::Result<()> {
+    let mut pem = Vec::new();
+    for certificate in cacerts {
+        pem.extend(certificate.to_pem()?);
+    }
+
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, pem)?;
+    std::fs::rename(&temporary, path)?;
+    event!(Level::INFO, "Wrote {} domain CA certificates to {:?}", cacerts.len(), path);
+    Ok(())
+}
+
+/// The trust store written by [`persist`], `None` if there is none yet
+pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Vec<X509>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let cacerts = X509::stack_from_pem(&std::fs::read(path)?)?;
+    event!(Level::INFO, "Loaded {} domain CA certificates from {:?}", cacerts.len(), path);
+    Ok(Some(cacerts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_anchored_in_pinned_domain_cert() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_ca, _) = &certs.registrar_ca;
+        let (registrar, _) = &certs.registrar;
+        let (vendor_ca, _) = &certs.vendor_ca;
+
+        // the voucher pinned the domain CA, or the registrar certificate it issued
+        let der = |certificates: Vec<X509>| certificates.iter().map(|certificate| certificate.to_der().unwrap()).collect::<Vec<_>>();
+        let anchored = anchored_chain(registrar_ca, &[vendor_ca.clone(), registrar_ca.clone()]).unwrap().unwrap();
+        assert_eq!(der(anchored), der(vec![registrar_ca.clone()]));
+        // the vendor CA is not on the path to the pinned-domain-cert and is dropped
+        let anchored = anchored_chain(registrar, &[vendor_ca.clone(), registrar_ca.clone()]).unwrap().unwrap();
+        assert_eq!(der(anchored), der(vec![registrar_ca.clone()]));
+        assert!(anchored_chain(registrar, std::slice::from_ref(vendor_ca)).unwrap().is_none());
+        assert!(anchored_chain(registrar_ca, &[]).unwrap().is_none());
+
+        let path = std::env::temp_dir().join(format!("open-brski-trust-store-{}.pem", std::process::id()));
+        assert!(load(&path).unwrap().is_none());
+        persist(&path, &[registrar_ca.clone(), vendor_ca.clone()]).unwrap();
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].to_der().unwrap(), registrar_ca.to_der().unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,6 +12,10 @@ pub async fn get_test_app() -> anyhow::Result<Router<()>, AppError> {
     let config = ParsedConfig {
         idevid_certificate: certs.pledge.0,
         idevid_privkey: openssl::ec::EcKey::private_key_from_der(&certs.pledge.1.private_key_to_der()?)?,
+        masa_anchors: vec![certs.vendor_ca.0],
+        firmware_signers: vec![certs.vendor.0],
+        firmware_vendor_id: None,
+        firmware_class_id: None,
         config: pledge_config
     };
 
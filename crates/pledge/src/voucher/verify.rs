@@ -0,0 +1,232 @@
+use brski_prm_artifacts::{
+    ietf_voucher::{artifact::VoucherArtifactDetails, error::VoucherError},
+    issued_voucher::IssuedVoucherJWS,
+    status::reason_code::ReasonCode,
+};
+use chrono::{DateTime, Utc};
+use common::chain::chains_to;
+use openssl::x509::X509;
+use pledge_lib::state_machine::StepError;
+use tracing::{event, Level};
+
+use crate::server::State;
+
+/// What the voucher answering a voucher-request of this pledge has to match
+#[derive(Debug, Clone)]
+pub(crate) struct Expected<'a> {
+    pub(crate) serial_number: &'a str,
+    /// Nonce of the voucher-request, a voucher carrying a nonce has to carry this one
+    pub(crate) nonce: Option<&'a [u8]>,
+    pub(crate) now: DateTime<Utc>,
+}
+
+/// Voucher that passed [`verify`]. The domain CA is only pinned from such a voucher.
+#[derive(Debug)]
+pub(crate) struct VerifiedVoucher {
+    pub(crate) details: VoucherArtifactDetails,
+    pub(crate) pinned_domain_cert: X509,
+}
+
+impl VerifiedVoucher {
+    /// Installs the pinned-domain-cert as trust anchor of the pledge and applies the additional configuration
+    pub(crate) fn pin(self, state: &mut State) -> X509 {
+        state.trust_anchor = Some(self.pinned_domain_cert.clone().into());
+        if let Some(additional_configuration) = self.details.additional_configuration {
+            event!(Level::INFO, "Applying additional configuration from voucher: {}", additional_configuration);
+            state.additional_configuration = Some(additional_configuration);
+        }
+        self.pinned_domain_cert
+    }
+}
+
+/// Verifies the MASA signature of `voucher`, that its signer chains to one of `masa_anchors` and that it was issued
+/// for `expected` (RFC 8995 Section 5.6.1). Vouchers are rejected if no MASA anchor is configured.
+pub(crate) fn verify(voucher: &IssuedVoucherJWS, masa_anchors: &[X509], expected: &Expected) -> Result<VerifiedVoucher, StepError> {
+    if masa_anchors.is_empty() {
+        return Err(StepError::fatal(ReasonCode::UntrustedMasa, "no MASA trust anchor configured"));
+    }
+
+    // the signature is checked against the first certificate of the x5c header
+    let decoded = voucher
+        .clone()
+        .decode()
+        .and_then(|decoded| decoded.try_decoded_data())
+        .map_err(|err| StepError::fatal(ReasonCode::InvalidSignature, err.to_string()))?;
+
+    let chain = decoded
+        .header
+        .as_ref()
+        .and_then(|header| header.x509_certificate_chain())
+        .ok_or_else(|| StepError::fatal(ReasonCode::MalformedArtifact, "voucher carries no signer certificate"))?;
+    let certificates = chain
+        .iter()
+        .map(|der| X509::from_der(der))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| StepError::fatal(ReasonCode::MalformedArtifact, err.to_string()))?;
+    let (signer, intermediates) = certificates
+        .split_first()
+        .ok_or_else(|| StepError::fatal(ReasonCode::MalformedArtifact, "voucher carries no signer certificate"))?;
+
+    match chains_to(signer, masa_anchors, intermediates) {
+        Ok(true) => {}
+        Ok(false) => return Err(StepError::fatal(ReasonCode::UntrustedMasa, "voucher signer does not chain to a MASA anchor")),
+        Err(err) => return Err(StepError::fatal(ReasonCode::InternalError, err.to_string())),
+    }
+
+    let details = decoded.payload.details;
+    check_details(&details, expected).map_err(voucher_error)?;
+
+    let pinned_domain_cert = details
+        .pinned_domain_cert
+        .as_ref()
+        .map(|cert| (**cert).clone())
+        .ok_or_else(|| voucher_error(VoucherError::MissingPinnedDomainCert))?;
+
+    Ok(VerifiedVoucher { details, pinned_domain_cert })
+}
+
+fn check_details(details: &VoucherArtifactDetails, expected: &Expected) -> Result<(), VoucherError> {
+    if details.serial_number != expected.serial_number {
+        return Err(VoucherError::SerialMismatch);
+    }
+
+    match &details.nonce {
+        Some(nonce) => match expected.nonce {
+            Some(expected) if expected == nonce.as_slice() => {}
+            Some(_) => return Err(VoucherError::NonceMismatch),
+            None => return Err(VoucherError::NonceRequired),
+        },
+        // without a nonce, only the expiry keeps a voucher from being replayed forever
+        None if details.expires_on.is_none() => {
+            return Err(VoucherError::MalformedVoucher("a voucher without nonce has to carry expires-on".to_string()))
+        }
+        None => {}
+    }
+
+    if let (Some(created_on), Some(expires_on)) = (details.created_on, details.expires_on) {
+        if expires_on < created_on {
+            return Err(VoucherError::MalformedVoucher("expires-on is before created-on".to_string()));
+        }
+    }
+    if details.expires_on.is_some_and(|expires_on| expires_on < expected.now) {
+        return Err(VoucherError::ExpiredVoucher);
+    }
+
+    Ok(())
+}
+
+fn voucher_error(err: VoucherError) -> StepError {
+    let reason_code = match err {
+        VoucherError::SerialMismatch => ReasonCode::SerialNumberMismatch,
+        VoucherError::NonceMismatch | VoucherError::NonceRequired => ReasonCode::NonceMismatch,
+        VoucherError::ExpiredVoucher => ReasonCode::VoucherExpired,
+        VoucherError::MissingPinnedDomainCert => ReasonCode::MissingPinnedDomainCert,
+        _ => ReasonCode::MalformedArtifact,
+    };
+    StepError::fatal(reason_code, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use brski_prm_artifacts::{ietf_voucher::artifact::VoucherArtifact, issued_voucher::IssuedVoucher};
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    const SERIAL_NUMBER: &str = "00-D0-E5-F2-00-02";
+
+    fn issue(certs: &OpensslTestCerts, details: VoucherArtifactDetails) -> IssuedVoucherJWS {
+        let (vendor_cert, vendor_key) = &certs.vendor;
+        let voucher = IssuedVoucher::new(VoucherArtifact { details }, [vendor_cert.clone()]);
+        let jws: IssuedVoucherJWS = voucher.try_into().unwrap();
+        jws.encode(vendor_key.private_key_to_der().unwrap()).unwrap()
+    }
+
+    fn details(certs: &OpensslTestCerts, nonce: Option<&[u8]>) -> VoucherArtifactDetails {
+        VoucherArtifactDetails {
+            serial_number: SERIAL_NUMBER.to_string(),
+            created_on: Some(Utc::now()),
+            nonce: nonce.map(<[u8]>::to_vec),
+            pinned_domain_cert: Some(certs.registrar_ca.0.clone().into()),
+            ..Default::default()
+        }
+    }
+
+    fn expected(nonce: Option<&[u8]>) -> Expected<'_> {
+        Expected {
+            serial_number: SERIAL_NUMBER,
+            nonce,
+            now: Utc::now(),
+        }
+    }
+
+    fn reason_code(result: Result<VerifiedVoucher, StepError>) -> ReasonCode {
+        result.unwrap_err().reason_code
+    }
+
+    #[test]
+    fn test_accepts_voucher_of_trusted_masa() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let anchors = [certs.vendor_ca.0.clone()];
+
+        let voucher = issue(&certs, details(&certs, Some(b"1234")));
+        let verified = verify(&voucher, &anchors, &expected(Some(b"1234"))).unwrap();
+        assert_eq!(verified.pinned_domain_cert.to_der().unwrap(), certs.registrar_ca.0.to_der().unwrap());
+
+        // nonceless vouchers are accepted until they expire
+        let mut nonceless = details(&certs, None);
+        nonceless.expires_on = Some(Utc::now() + chrono::Duration::days(1));
+        assert!(verify(&issue(&certs, nonceless.clone()), &anchors, &expected(Some(b"1234"))).is_ok());
+        let later = Expected {
+            now: Utc::now() + chrono::Duration::days(2),
+            ..expected(None)
+        };
+        assert_eq!(reason_code(verify(&issue(&certs, nonceless), &anchors, &later)), ReasonCode::VoucherExpired);
+        assert!(matches!(check_details(&details(&certs, None), &expected(None)), Err(VoucherError::MalformedVoucher(_))));
+    }
+
+    #[test]
+    fn test_rejects_untrusted_masa() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let voucher = issue(&certs, details(&certs, Some(b"1234")));
+
+        assert_eq!(reason_code(verify(&voucher, &[], &expected(Some(b"1234")))), ReasonCode::UntrustedMasa);
+        assert_eq!(
+            reason_code(verify(&voucher, std::slice::from_ref(&certs.registrar_ca.0), &expected(Some(b"1234")))),
+            ReasonCode::UntrustedMasa
+        );
+
+        // swapping the payload breaks the MASA signature
+        let other = issue(&certs, details(&certs, Some(b"5678")));
+        let mut tampered: serde_json::Value = serde_json::from_str(&voucher.try_encoded_data().unwrap()).unwrap();
+        let other: serde_json::Value = serde_json::from_str(&other.try_encoded_data().unwrap()).unwrap();
+        tampered["payload"] = other["payload"].clone();
+        assert_eq!(
+            reason_code(verify(&IssuedVoucherJWS::Encoded(tampered.to_string()), std::slice::from_ref(&certs.vendor_ca.0), &expected(Some(b"5678")))),
+            ReasonCode::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn test_rejects_voucher_for_other_request() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let anchors = [certs.vendor_ca.0.clone()];
+        let voucher = issue(&certs, details(&certs, Some(b"1234")));
+
+        assert_eq!(reason_code(verify(&voucher, &anchors, &expected(Some(b"5678")))), ReasonCode::NonceMismatch);
+        assert_eq!(reason_code(verify(&voucher, &anchors, &expected(None))), ReasonCode::NonceMismatch);
+        let other_pledge = Expected {
+            serial_number: "00-D0-E5-F2-00-03",
+            ..expected(Some(b"1234"))
+        };
+        assert_eq!(reason_code(verify(&voucher, &anchors, &other_pledge)), ReasonCode::SerialNumberMismatch);
+    }
+
+    #[test]
+    fn test_maps_voucher_errors() {
+        assert_eq!(voucher_error(VoucherError::NonceMismatch).reason_code, ReasonCode::NonceMismatch);
+        assert_eq!(voucher_error(VoucherError::SerialMismatch).reason_code, ReasonCode::SerialNumberMismatch);
+        assert_eq!(voucher_error(VoucherError::MissingPinnedDomainCert).reason_code, ReasonCode::MissingPinnedDomainCert);
+        assert!(!voucher_error(VoucherError::ExpiredVoucher).retryable);
+    }
+}
@@ -0,0 +1,53 @@
+//! Zones of IPv6 link-local addresses (RFC 4007 Section 11). A link-local address like `fe80::1` is only usable
+//! together with the interface it was learned on, given as `fe80::1%3` or `fe80::1%eth0`.
+
+use std::net::Ipv6Addr;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+pub(crate) fn is_link_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Interface index of a zone, which is either numeric or an interface name looked up in sysfs
+pub(crate) fn interface_index(zone: &str) -> anyhow::Result<u32> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+
+    if zone.is_empty() || zone.contains(['/', '.']) {
+        return Err(anyhow!("invalid interface name {:?}", zone));
+    }
+
+    let ifindex = Path::new("/sys/class/net").join(zone).join("ifindex");
+    let index = std::fs::read_to_string(&ifindex).map_err(|err| anyhow!("unknown interface {}: {}", zone, err))?;
+    Ok(index.trim().parse()?)
+}
+
+/// Splits a scoped host like `fe80::1%eth0` into its address and interface index.
+/// Hosts without a zone or with a zone that does not resolve yield `None`.
+pub(crate) fn scoped_address(host: &str) -> Option<(Ipv6Addr, u32)> {
+    let (address, zone) = host.split_once('%')?;
+    Some((address.parse().ok()?, interface_index(zone).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scoped_address() {
+        assert_eq!(scoped_address("fe80::1%3"), Some(("fe80::1".parse().unwrap(), 3)));
+        assert_eq!(scoped_address("fe80::1"), None);
+        assert_eq!(scoped_address("fe80::1%../lo"), None);
+        assert!(interface_index("").is_err());
+
+        if Path::new("/sys/class/net/lo").exists() {
+            assert_eq!(scoped_address("fe80::1%lo"), Some(("fe80::1".parse().unwrap(), 1)));
+        }
+
+        assert!(is_link_local(&"fe80::1".parse().unwrap()));
+        assert!(!is_link_local(&"2001:db8::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,156 @@
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use brski_prm_artifacts::ietf_voucher;
+use chrono::{DateTime, Utc};
+use common::server_error::ServerError;
+use futures::StreamExt;
+use josekit::jws::JwsHeaderSet;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    client, parsed_config::ParsedConfig, pledge_communicator::PledgeCtx, server::bootstrap_pledge, server::server::ServerState,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BatchResult {
+    Onboarded,
+    Failed,
+    /// The pledge was already being onboarded, e.g. by an installer
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchOutcome {
+    pub serial_number: String,
+    pub result: BatchResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub finished_on: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchReport {
+    pub started_on: DateTime<Utc>,
+    pub finished_on: DateTime<Utc>,
+    pub onboarded: usize,
+    pub failed: usize,
+    pub pledges: Vec<BatchOutcome>,
+}
+
+impl BatchReport {
+    fn new(started_on: DateTime<Utc>, mut pledges: Vec<BatchOutcome>) -> Self {
+        pledges.sort_by(|a, b| a.serial_number.cmp(&b.serial_number));
+
+        let count = |result: BatchResult| pledges.iter().filter(|outcome| outcome.result == result).count();
+        Self {
+            started_on,
+            finished_on: Utc::now(),
+            onboarded: count(BatchResult::Onboarded),
+            failed: count(BatchResult::Failed),
+            pledges,
+        }
+    }
+
+    /// General JWS signed with the EE key of the agent, identified by its SKID like the agent-signed data
+    pub fn sign(&self, config: &ParsedConfig) -> Result<String, ServerError> {
+        let skid = config
+            .ee_certificate
+            .subject_key_id()
+            .ok_or(ServerError::BadResponse("No SKID in EE certificate".to_string()))?;
+        let key = config.ee_key.private_key_to_der()?;
+
+        let signer = ietf_voucher::signing_algorithm(&key)?.signer_from_der(&key)?;
+        let mut header_set = JwsHeaderSet::new();
+        header_set.set_algorithm(signer.algorithm().name(), true);
+        header_set.set_key_id(URL_SAFE.encode(skid.as_slice()), true);
+
+        let payload = serde_json::to_vec(self)?;
+        Ok(josekit::jws::serialize_general_json(&payload, &[(&header_set, &signer)])?)
+    }
+}
+
+/// Onboards all discovered pledges with at most `workers` running at the same time
+#[tracing::instrument(skip(state), target = "RegistrarAgent", name = "run_batch")]
+pub async fn run_batch(state: &ServerState, workers: usize) -> Result<BatchReport, ServerError> {
+    let started_on = Utc::now();
+    let pledges = client::discover_pledges(state).await?;
+
+    event!(Level::INFO, "Onboarding batch of {} pledges with {} workers", pledges.len(), workers);
+
+    let outcomes = futures::stream::iter(pledges)
+        .map(|pledge| onboard(state, pledge))
+        .buffer_unordered(workers.max(1))
+        .collect()
+        .await;
+
+    let report = BatchReport::new(started_on, outcomes);
+    event!(Level::INFO, "Batch finished, {} onboarded and {} failed", report.onboarded, report.failed);
+    Ok(report)
+}
+
+async fn onboard(state: &ServerState, pledge: PledgeCtx) -> BatchOutcome {
+    let (result, error) = if !state.progress.start(&pledge.pledge_serial).await {
+        (BatchResult::Skipped, None)
+    } else {
+        match bootstrap_pledge(state, &pledge).await {
+            Ok(()) => (BatchResult::Onboarded, None),
+            Err(err) => (BatchResult::Failed, Some(err.to_string())),
+        }
+    };
+
+    BatchOutcome {
+        serial_number: pledge.pledge_serial,
+        result,
+        error,
+        finished_on: Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cli::config::RegistrarAgentConfig;
+    use example_certs::OpensslTestCerts;
+    use josekit::jws::ES256;
+
+    use super::*;
+
+    #[test]
+    fn test_report_is_signed_by_agent() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let config = ParsedConfig {
+            config: RegistrarAgentConfig::default(),
+            ee_certificate: certs.registrar_agent.0.clone(),
+            ee_key: certs.registrar_agent.1.ec_key().unwrap(),
+            registrar_certificate: certs.registrar.0.clone(),
+        };
+
+        let outcome = |serial_number: &str, result| BatchOutcome {
+            serial_number: serial_number.to_string(),
+            result,
+            error: None,
+            finished_on: Utc::now(),
+        };
+        let report = BatchReport::new(
+            Utc::now(),
+            vec![
+                outcome("00-D0-E5-F2-00-03", BatchResult::Failed),
+                outcome("00-D0-E5-F2-00-02", BatchResult::Onboarded),
+                outcome("00-D0-E5-F2-00-04", BatchResult::Skipped),
+            ],
+        );
+        assert_eq!((report.onboarded, report.failed), (1, 1));
+        assert_eq!(report.pledges[0].serial_number, "00-D0-E5-F2-00-02");
+
+        let jws = report.sign(&config).unwrap();
+        let public_key = certs.registrar_agent.0.public_key().unwrap().public_key_to_pem().unwrap();
+        let verifier = ES256.verifier_from_pem(public_key).unwrap();
+        let (payload, _) = josekit::jws::deserialize_json(&jws, &verifier).unwrap();
+
+        let verified: BatchReport = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(verified.pledges.len(), 3);
+        assert_eq!(verified.pledges[2].result, BatchResult::Skipped);
+    }
+}
@@ -1,11 +1,15 @@
-use std::default;
 
-use common::{error::AppError, server_error::ServerError};
+use common::server_error::ServerError;
 
-use crate::{parsed_config::ParsedConfig, pledge_communicator::PledgeCtx};
+use crate::{pledge_communicator::PledgeCtx, server::server::ServerState};
+
+/// Communicators that can scan for pledges themselves, e.g. over BLE, are asked first
+/// todo add discovery logic for HTTP
+pub async fn discover_pledges(state: &ServerState) -> Result<Vec<PledgeCtx>, ServerError> {
+    if let Some(pledges) = state.communicator.discover().await? {
+        return Ok(pledges);
+    }
 
-/// todo add discovery logic
-pub async fn discover_pledges(_config: &ParsedConfig) -> Result<Vec<PledgeCtx>, ServerError> {
     Ok(
         vec![
             PledgeCtx {
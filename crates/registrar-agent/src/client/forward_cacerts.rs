@@ -1,6 +1,5 @@
 use brski_prm_artifacts::cacerts::response::CACERTS_JWS;
 use common::server_error::ServerError;
-use tracing::event;
 
 use crate::{pledge_communicator::PledgeCtx, server::server::ServerState};
 
@@ -1,7 +1,6 @@
 use brski_prm_artifacts::rer;
 use brski_prm_artifacts::status::enroll::response::EnrollStatusJWS;
 use common::server_error::ServerError;
-use tracing::event;
 
 use crate::pledge_communicator::PledgeCtx;
 use crate::server::server::ServerState;
@@ -0,0 +1,14 @@
+use brski_prm_artifacts::network::NetworkProfile;
+use common::server_error::ServerError;
+
+use crate::{pledge_communicator::PledgeCtx, server::server::ServerState};
+
+#[tracing::instrument(skip(state, profile), target = "RegistrarAgent", name="send_network_profile_to_pledge")]
+pub async fn send_network_profile_to_pledge(
+    state: &ServerState,
+    profile: &NetworkProfile,
+    pledge: &PledgeCtx,
+) -> Result<(), ServerError> {
+    let profile = serde_json::to_string(profile)?;
+    state.communicator.send_network_profile(profile, pledge.clone()).await
+}
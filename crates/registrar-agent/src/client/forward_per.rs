@@ -1,4 +1,3 @@
-use brski_prm_artifacts::content_type::{PKCS7, JWS_VOUCHER};
 use brski_prm_artifacts::per::response::PER_JWS;
 use brski_prm_artifacts::rer;
 use common::server_error::ServerError;
@@ -6,10 +5,9 @@ use tracing::event;
 
 use crate::parsed_config::{ParsedConfig};
 
-
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
 use reqwest::Client;
 
+use super::registrar_client;
 
 #[tracing::instrument(skip(client, parsed_config, per), target = "RegistrarAgent", name="send_per_to_registrar")]
 pub async fn send_per_to_registrar(
@@ -18,52 +16,12 @@ pub async fn send_per_to_registrar(
     client: &Client,
 ) -> Result<rer::response::Response, ServerError> {
 
-    let data = per.try_encoded_data()?;
-    
-    let request_enroll_registrar_url = format!(
-        "{}/.well-known/brski/requestenroll",
-        parsed_config.config.registrar_url
-    );
-
-    event!(tracing::Level::INFO, "Sending PER to registrar at: {}", request_enroll_registrar_url);
-
-    let response = client
-        .post(request_enroll_registrar_url)
-        .header(ACCEPT, JWS_VOUCHER)
-        .header(CONTENT_TYPE, JWS_VOUCHER)
-        .body(data)
-        .send()
-        .await?;
-
-    event!(tracing::Level::INFO, "Received PER response");
-
-    event!(tracing::Level::INFO, "Verifying PER response");
-
-    if !response.status().is_success() {
-        return Err(ServerError::BadResponse("Sending PER to registrar failed".to_string()))
-    }
-
-    if response.headers().get(CONTENT_TYPE).is_none() {
-        return Err(ServerError::BadResponse("No content type in response".to_string()))
-    }
-
-    if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
-        if content_type != PKCS7 {
-            return Err(ServerError::BadResponse("Wrong content type in response".to_string()))
-        }
-    }
-
-    let response_data = response.bytes().await?.to_vec();
-
-    event!(tracing::Level::INFO, "Received PER response_data - Issued Certificate");
-    event!(tracing::Level::DEBUG, "PER Response Data: Bytes with length {}", response_data.len());
-
-    let certificate = openssl::x509::X509::from_der(&response_data)?;
+    let certificate = registrar_client(parsed_config, client).request_enroll(per).await?;
 
-    event!(tracing::Level::INFO, "Parsed PER Response Data");
+    event!(tracing::Level::INFO, "Received PER response - Issued Certificate");
     event!(tracing::Level::DEBUG, "PER Response Data: {:?}", certificate);
 
     let res = rer::response::Response(certificate.into());
 
     Ok(res)
-}
\ No newline at end of file
+}
@@ -1,4 +1,6 @@
-use brski_prm_artifacts::content_type::JWS_VOUCHER;
+use std::time::Duration;
+
+use brski_client::ClientError;
 use brski_prm_artifacts::issued_voucher::IssuedVoucherJWS;
 use brski_prm_artifacts::pvr::response::PVR_JWS;
 use common::server_error::ServerError;
@@ -6,62 +8,39 @@ use tracing::event;
 
 use crate::parsed_config::{ParsedConfig};
 
-
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
 use reqwest::Client;
 
+use super::registrar_client;
+
+/// How long the agent keeps asking for a voucher the registrar holds, before the onboarding of the pledge fails
+const MAX_HELD_WAIT: Duration = Duration::from_secs(600);
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
 #[tracing::instrument(skip(client, parsed_config, pvr), target = "RegistrarAgent", name="send_pvr_to_registrar")]
 pub async fn send_pvr_to_registrar(
     parsed_config: &ParsedConfig,
     pvr: PVR_JWS,
     client: &Client,
 ) -> Result<IssuedVoucherJWS, ServerError> {
-    let request_enroll_registrar_url = format!(
-        "{}/.well-known/brski/requestvoucher",
-        parsed_config.config.registrar_url
-    );
-
-    event!(tracing::Level::INFO, "Sending PVR to registrar at: {}", request_enroll_registrar_url);
     event!(tracing::Level::DEBUG, "PVR: {}", pvr);
 
-    let data = pvr.try_encoded_data()?;
-
-    let response = client
-        .post(request_enroll_registrar_url)
-        .header(ACCEPT, JWS_VOUCHER)
-        .header(CONTENT_TYPE, JWS_VOUCHER)
-        .body(data)
-        .send()
-        .await?;
-
-    event!(tracing::Level::INFO, "Received response");
-    event!(tracing::Level::DEBUG, "Response: {:#?}", response);
-
-    if !response.status().is_success() {
-        event!(tracing::Level::ERROR, "Sending PVR to registrar failed: {:#?}", response);
-        return Err(ServerError::BadResponse("Sending PVR to registrar failed".to_string()))
-    }
-
-    if response.headers().get(CONTENT_TYPE).is_none() {
-        event!(tracing::Level::ERROR, "No content type in response: {:#?}", response);
-        return Err(ServerError::BadResponse("No content type in response".to_string()))
-    }
-
-    if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
-        if content_type != JWS_VOUCHER {
-            event!(tracing::Level::ERROR, "Wrong content type in response: {:#?}", response);
-            return Err(ServerError::BadResponse("Wrong content type in response".to_string()))
+    let registrar = registrar_client(parsed_config, client);
+    let mut waited = Duration::ZERO;
+    let jws = loop {
+        match registrar.request_voucher(pvr.clone()).await {
+            // the registrar holds the voucher request until the MASA is reachable, the same PVR picks up the voucher
+            Err(ClientError::Accepted { retry_after, .. }) if waited < MAX_HELD_WAIT => {
+                let retry_after = retry_after.unwrap_or(DEFAULT_RETRY_AFTER).min(MAX_HELD_WAIT - waited);
+                event!(tracing::Level::INFO, "Registrar holds the voucher request, asking again in {:?}", retry_after);
+                tokio::time::sleep(retry_after).await;
+                waited += retry_after;
+            }
+            result => break result?,
         }
-    }
-
-    let response_data = response.text().await?;
-    event!(tracing::Level::INFO, "Received issued voucher response_data");
-    event!(tracing::Level::DEBUG, "Issued Voucher Response Data: {}", response_data);
-    // TODO convert this to jws
+    };
 
-    event!(tracing::Level::INFO, "Parsing IssuedVoucher response data into JWS");
-    let jws = IssuedVoucherJWS::Encoded(response_data);
+    event!(tracing::Level::INFO, "Received issued voucher");
     event!(tracing::Level::DEBUG, "Issued Voucher JWS: {:#?}", jws);
 
     Ok(jws)
-}
\ No newline at end of file
+}
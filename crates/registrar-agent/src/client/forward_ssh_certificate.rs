@@ -0,0 +1,30 @@
+use brski_prm_artifacts::per::response::PER_JWS;
+use common::server_error::ServerError;
+use reqwest::Client;
+
+use crate::{parsed_config::ParsedConfig, pledge_communicator::PledgeCtx, server::server::ServerState};
+
+use super::registrar_client;
+
+/// Host key the pledge included in its PER, if it wants an SSH host certificate
+pub fn requested_ssh_host_key(per: &PER_JWS) -> Result<Option<String>, ServerError> {
+    Ok(per.clone().decode()?.try_decoded_data()?.payload.ssh_host_key)
+}
+
+#[tracing::instrument(skip(client, parsed_config, per), target = "RegistrarAgent", name="request_ssh_certificate_from_registrar")]
+pub async fn request_ssh_certificate_from_registrar(
+    parsed_config: &ParsedConfig,
+    per: PER_JWS,
+    client: &Client,
+) -> Result<String, ServerError> {
+    Ok(registrar_client(parsed_config, client).request_ssh_certificate(per).await?)
+}
+
+#[tracing::instrument(skip(state, certificate), target = "RegistrarAgent", name="send_ssh_certificate_to_pledge")]
+pub async fn send_ssh_certificate_to_pledge(
+    state: &ServerState,
+    certificate: String,
+    pledge: &PledgeCtx,
+) -> Result<(), ServerError> {
+    state.communicator.send_ssh_certificate(certificate, pledge.clone()).await
+}
@@ -11,6 +11,8 @@ mod forward_cacerts;
 mod forward_enroll_response;
 mod forward_voucher_status;
 mod forward_enroll_status;
+mod forward_ssh_certificate;
+mod forward_network_profile;
 
 pub use forward_pvr::send_pvr_to_registrar;
 pub use trigger_per::trigger_per;
@@ -23,6 +25,13 @@ pub use forward_cacerts::send_cacerts_to_pledge;
 pub use forward_enroll_response::send_enroll_response_to_pledge;
 pub use forward_voucher_status::send_voucher_status_to_registrar;
 pub use forward_enroll_status::send_enroll_status_to_registrar;
+pub use forward_ssh_certificate::{request_ssh_certificate_from_registrar, requested_ssh_host_key, send_ssh_certificate_to_pledge};
+pub use forward_network_profile::send_network_profile_to_pledge;
+
+/// Typed client for the registrar this agent forwards to
+fn registrar_client(parsed_config: &crate::parsed_config::ParsedConfig, client: &reqwest::Client) -> brski_client::RegistrarClient {
+    brski_client::RegistrarClient::new(client.clone(), parsed_config.config.registrar_url.clone())
+}
 
 // bridge
 pub use trigger_pvr::get_pvr_trigger;
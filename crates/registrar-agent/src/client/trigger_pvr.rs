@@ -1,5 +1,4 @@
 use brski_prm_artifacts::ietf_voucher::agent_signed_data;
-use brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifact;
 use brski_prm_artifacts::pvr::response::PVR_JWS;
 use common::server_error::ServerError;
 use tracing::{event, Level};
@@ -10,7 +9,6 @@ use crate::server::server::ServerState;
 
 
 
-const SERVICE_GLOB: &'static str = "_brski-pledge._tcp.local";
 
 #[tracing::instrument(skip(parsed_config), target = "RegistrarAgent", name="get_pvr_trigger")]
 pub fn get_pvr_trigger(parsed_config: &ParsedConfig, serial_number: String) -> Result<brski_prm_artifacts::pvr::trigger::Trigger, ServerError>{
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use brski_prm_artifacts::ietf_voucher::serial_number::{self, SerialNumberError};
+use openssl::{sha::sha256, x509::X509};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+const LABEL_PREFIX: &str = "BRSKI:";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum IdentificationError {
+    #[error("QR code is not a BRSKI pledge label")]
+    NotALabel,
+    #[error("pledge label lacks the {0} field")]
+    MissingField(&'static str),
+    #[error("public-key hash of the pledge label is not a base64url encoded SHA-256 hash")]
+    InvalidHash,
+    #[error("IDevID serial-number: {0}")]
+    InvalidSerialNumber(#[from] SerialNumberError),
+    #[error("IDevID serial-number {presented} does not match the label {labeled}")]
+    SerialNumberMismatch { labeled: String, presented: String },
+    #[error("IDevID public key does not match the label")]
+    PublicKeyMismatch,
+}
+
+/// Content of the QR code printed on the pledge, modeled after the `WIFI:` codes of access points:
+/// `BRSKI:SN:<serial-number>;PKH:<base64url SHA-256 of the DER SubjectPublicKeyInfo of the IDevID>;;`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct PledgeLabel {
+    pub serial_number: String,
+    #[serde(skip)]
+    pub public_key_hash: [u8; 32],
+}
+
+impl FromStr for PledgeLabel {
+    type Err = IdentificationError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let fields = content.trim().strip_prefix(LABEL_PREFIX).ok_or(IdentificationError::NotALabel)?;
+
+        let mut serial_number = None;
+        let mut public_key_hash = None;
+        for field in fields.split(';').filter(|field| !field.is_empty()) {
+            match field.split_once(':') {
+                Some(("SN", value)) => serial_number = Some(value.to_string()),
+                Some(("PKH", value)) => public_key_hash = Some(value),
+                // unknown fields are left for future versions of the label
+                _ => {}
+            }
+        }
+
+        let serial_number = serial_number.filter(|serial| !serial.is_empty()).ok_or(IdentificationError::MissingField("SN"))?;
+        let public_key_hash = URL_SAFE_NO_PAD
+            .decode(public_key_hash.ok_or(IdentificationError::MissingField("PKH"))?)
+            .map_err(|_| IdentificationError::InvalidHash)?
+            .try_into()
+            .map_err(|_| IdentificationError::InvalidHash)?;
+
+        Ok(Self {
+            serial_number,
+            public_key_hash,
+        })
+    }
+}
+
+impl PledgeLabel {
+    pub fn for_idevid(idevid: &X509) -> Result<Self, IdentificationError> {
+        Ok(Self {
+            serial_number: serial_number(idevid)?,
+            public_key_hash: public_key_hash(idevid)?,
+        })
+    }
+
+    /// Content of the QR code for the pledge with this label
+    pub fn encode(&self) -> String {
+        format!("{}SN:{};PKH:{};;", LABEL_PREFIX, self.serial_number, URL_SAFE_NO_PAD.encode(self.public_key_hash))
+    }
+
+    /// Checks the IDevID the pledge presented in its PVR against the label
+    pub fn verify(&self, idevid: &X509) -> Result<(), IdentificationError> {
+        let presented = serial_number(idevid)?;
+        if presented != self.serial_number {
+            return Err(IdentificationError::SerialNumberMismatch {
+                labeled: self.serial_number.clone(),
+                presented,
+            });
+        }
+
+        if public_key_hash(idevid)? != self.public_key_hash {
+            return Err(IdentificationError::PublicKeyMismatch);
+        }
+        Ok(())
+    }
+}
+
+fn serial_number(idevid: &X509) -> Result<String, IdentificationError> {
+    Ok(serial_number::from_name(idevid.subject_name())?)
+}
+
+fn public_key_hash(idevid: &X509) -> Result<[u8; 32], IdentificationError> {
+    let spki = idevid
+        .public_key()
+        .and_then(|key| key.public_key_to_der())
+        .map_err(|_| IdentificationError::PublicKeyMismatch)?;
+    Ok(sha256(&spki))
+}
+
+/// Labels scanned by installers, kept until the pledge was onboarded
+#[derive(Debug, Default)]
+pub struct Identifications {
+    labels: RwLock<HashMap<String, PledgeLabel>>,
+}
+
+impl Identifications {
+    pub async fn register(&self, label: PledgeLabel) {
+        event!(target: "RegistrarAgent::Identification", Level::INFO, "Registered label of pledge {}", label.serial_number);
+        self.labels.write().await.insert(label.serial_number.clone(), label);
+    }
+
+    pub async fn get(&self, serial_number: &str) -> Option<PledgeLabel> {
+        self.labels.read().await.get(serial_number).cloned()
+    }
+
+    pub async fn remove(&self, serial_number: &str) {
+        self.labels.write().await.remove(serial_number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    #[test]
+    fn test_label_verifies_idevid() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let idevid = &certs.pledge.0;
+        let other: OpensslTestCerts = example_certs::generate_certs().into();
+
+        let label: PledgeLabel = PledgeLabel::for_idevid(idevid).unwrap().encode().parse().unwrap();
+        assert_eq!(label.serial_number, "00-D0-E5-F2-00-02");
+        assert_eq!(label.verify(idevid), Ok(()));
+        assert_eq!(label.verify(&other.pledge.0), Err(IdentificationError::PublicKeyMismatch));
+
+        let substituted = PledgeLabel {
+            serial_number: "00-D0-E5-F2-00-03".to_string(),
+            ..label
+        };
+        assert!(matches!(substituted.verify(idevid), Err(IdentificationError::SerialNumberMismatch { .. })));
+
+        assert_eq!("WIFI:S:brski;;".parse::<PledgeLabel>(), Err(IdentificationError::NotALabel));
+        assert_eq!("BRSKI:SN:00-D0-E5-F2-00-02;;".parse::<PledgeLabel>(), Err(IdentificationError::MissingField("PKH")));
+        assert_eq!("BRSKI:SN:00-D0-E5-F2-00-02;PKH:AAAA;;".parse::<PledgeLabel>(), Err(IdentificationError::InvalidHash));
+    }
+}
@@ -1,5 +1,8 @@
+mod batch;
 mod client;
+mod identification;
 mod parsed_config;
+mod progress;
 mod server;
 mod pledge_communicator;
 
@@ -16,6 +19,9 @@ pub use pledge_communicator::PledgeCommunicator;
 pub use server::bootstrap_pledge;
 pub use server::server::ServerState;
 pub use pledge_communicator::PledgeCtx;
+pub use progress::{OnboardingStep, Progress, ProgressUpdate};
+pub use batch::{run_batch, BatchOutcome, BatchReport, BatchResult};
+pub use identification::{IdentificationError, Identifications, PledgeLabel};
 
 #[tracing::instrument(skip(config), target = "RegistrarAgent", name = "RegistrarAgent::start")]
 pub async fn start(config: RegistrarAgentConfig) -> anyhow::Result<JoinHandle<()>, AppError> {
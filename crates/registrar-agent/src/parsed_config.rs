@@ -32,7 +32,7 @@ pub(crate) fn parse_config(config: RegistrarAgentConfig) -> anyhow::Result<Parse
 
     let registrar_pubkey = registrar_cert.public_key()?;
     let result = ee_cert.verify(&registrar_pubkey).unwrap();
-    if result == false {
+    if !result {
         return Err(anyhow!("Unable to verify CA signage of RegAgt Certificate").into());
     }
     Ok(ParsedConfig {
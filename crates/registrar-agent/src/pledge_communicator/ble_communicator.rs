@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use bluer::{gatt::remote::Characteristic, Adapter, AdapterEvent, Address, Device, Session};
+use common::server_error::ServerError;
+use consts::ble::{
+    CA_CERTS_READ_UUID, CA_CERTS_UUID, CA_CERTS_WRITE_UUID, ENROLL_RESPONSE_READ_UUID, ENROLL_RESPONSE_UUID,
+    ENROLL_RESPONSE_WRITE_UUID, NETWORK_PROFILE_READ_UUID, NETWORK_PROFILE_UUID, NETWORK_PROFILE_WRITE_UUID,
+    TPER_READ_UUID, TPER_UUID, TPER_WRITE_UUID, TPVR_READ_UUID, TPVR_UUID, TPVR_WRITE_UUID, VOUCHER_READ_UUID,
+    VOUCHER_UUID, VOUCHER_WRITE_UUID,
+};
+use futures::StreamExt;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use super::{
+    ble_framing::{split, Reassembler},
+    PledgeCommunicator, PledgeCtx,
+};
+
+/// How long the agent scans for advertising pledges
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the pledge may take to compute a response, e.g. to sign the PVR
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// GATT service of one PRM exchange, the agent writes the request and reads the response
+struct Exchange {
+    service: &'static str,
+    write: &'static str,
+    read: &'static str,
+}
+
+const TPVR: Exchange = Exchange { service: TPVR_UUID, write: TPVR_WRITE_UUID, read: TPVR_READ_UUID };
+const TPER: Exchange = Exchange { service: TPER_UUID, write: TPER_WRITE_UUID, read: TPER_READ_UUID };
+const VOUCHER: Exchange = Exchange { service: VOUCHER_UUID, write: VOUCHER_WRITE_UUID, read: VOUCHER_READ_UUID };
+const CA_CERTS: Exchange = Exchange { service: CA_CERTS_UUID, write: CA_CERTS_WRITE_UUID, read: CA_CERTS_READ_UUID };
+const ENROLL_RESPONSE: Exchange = Exchange {
+    service: ENROLL_RESPONSE_UUID,
+    write: ENROLL_RESPONSE_WRITE_UUID,
+    read: ENROLL_RESPONSE_READ_UUID,
+};
+const NETWORK_PROFILE: Exchange = Exchange {
+    service: NETWORK_PROFILE_UUID,
+    write: NETWORK_PROFILE_WRITE_UUID,
+    read: NETWORK_PROFILE_READ_UUID,
+};
+
+fn ble_error(err: impl std::fmt::Display) -> ServerError {
+    ServerError::BadResponse(format!("BLE: {}", err))
+}
+
+/// BLE central talking to pledges over GATT through BlueZ, the counterpart of the ESP32 responder.
+/// Pledges are found by the TPVR service they advertise, [`PledgeCtx::ctx`] holds the device address.
+#[derive(Clone)]
+pub struct BLECommunicator {
+    adapter: Adapter,
+}
+
+impl BLECommunicator {
+    pub async fn new() -> Result<Self, ServerError> {
+        let session = Session::new().await.map_err(ble_error)?;
+        let adapter = session.default_adapter().await.map_err(ble_error)?;
+        adapter.set_powered(true).await.map_err(ble_error)?;
+
+        event!(Level::INFO, "Using bluetooth adapter {}", adapter.name());
+        Ok(Self { adapter })
+    }
+
+    /// Pledges have to advertise their serial-number as device name
+    #[tracing::instrument(skip(self), target = "RegistrarAgent", name = "ble_discover")]
+    pub async fn discover_pledges(&self) -> Result<Vec<PledgeCtx>, ServerError> {
+        let service = Uuid::parse_str(TPVR_UUID).map_err(ble_error)?;
+        let events = self.adapter.discover_devices().await.map_err(ble_error)?;
+        let mut events = std::pin::pin!(events.take_until(tokio::time::sleep(DISCOVERY_TIMEOUT)));
+
+        let mut pledges = vec![];
+        while let Some(event) = events.next().await {
+            let AdapterEvent::DeviceAdded(address) = event else {
+                continue;
+            };
+            let device = self.adapter.device(address).map_err(ble_error)?;
+
+            let advertises_service = device.uuids().await.ok().flatten().is_some_and(|uuids| uuids.contains(&service));
+            if !advertises_service {
+                continue;
+            }
+
+            match device.name().await.ok().flatten() {
+                Some(serial_number) => {
+                    event!(Level::INFO, "Discovered pledge {} at {}", serial_number, address);
+                    pledges.push(PledgeCtx {
+                        ctx: address.to_string(),
+                        pledge_serial: serial_number,
+                        pledge_url: String::new(),
+                    });
+                }
+                None => event!(Level::WARN, "Ignoring pledge at {} without a name", address),
+            }
+        }
+
+        Ok(pledges)
+    }
+
+    async fn connect(&self, ctx: &PledgeCtx) -> Result<Device, ServerError> {
+        let address: Address = ctx.ctx.parse().map_err(ble_error)?;
+        let device = self.adapter.device(address).map_err(ble_error)?;
+
+        if !device.is_connected().await.map_err(ble_error)? {
+            event!(Level::INFO, "Connecting to pledge {} at {}", ctx.pledge_serial, address);
+            device.connect().await.map_err(ble_error)?;
+        }
+        Ok(device)
+    }
+
+    async fn characteristics(device: &Device, exchange: &Exchange) -> Result<(Characteristic, Characteristic), ServerError> {
+        let uuid = |uuid: &str| Uuid::parse_str(uuid).map_err(ble_error);
+        let (service_uuid, write_uuid, read_uuid) = (uuid(exchange.service)?, uuid(exchange.write)?, uuid(exchange.read)?);
+
+        for service in device.services().await.map_err(ble_error)? {
+            if service.uuid().await.map_err(ble_error)? != service_uuid {
+                continue;
+            }
+
+            let (mut write, mut read) = (None, None);
+            for characteristic in service.characteristics().await.map_err(ble_error)? {
+                let uuid = characteristic.uuid().await.map_err(ble_error)?;
+                if uuid == write_uuid {
+                    write = Some(characteristic);
+                } else if uuid == read_uuid {
+                    read = Some(characteristic);
+                }
+            }
+            return write.zip(read).ok_or(ble_error(format!("service {} lacks its characteristics", exchange.service)));
+        }
+
+        Err(ble_error(format!("pledge does not offer service {}", exchange.service)))
+    }
+
+    /// Writes the request in MTU sized chunks, waits for the pledge to notify and reads the response
+    async fn exchange(&self, ctx: &PledgeCtx, exchange: &Exchange, request: &[u8]) -> Result<Vec<u8>, ServerError> {
+        let device = self.connect(ctx).await?;
+        let (write, read) = Self::characteristics(&device, exchange).await?;
+
+        let mut notifications = std::pin::pin!(read.notify().await.map_err(ble_error)?);
+
+        let mtu = write.mtu().await.map_err(ble_error)?;
+        for chunk in split(request, mtu) {
+            write.write(chunk).await.map_err(ble_error)?;
+        }
+
+        tokio::time::timeout(RESPONSE_TIMEOUT, notifications.next())
+            .await
+            .map_err(|_| ble_error("pledge did not respond in time"))?;
+
+        let mut response = Reassembler::default();
+        while !response.push(&read.read().await.map_err(ble_error)?) {}
+        Ok(response.into_message())
+    }
+
+    async fn exchange_string(&self, ctx: &PledgeCtx, exchange: &Exchange, request: &[u8]) -> Result<String, ServerError> {
+        let response = self.exchange(ctx, exchange, request).await?;
+        String::from_utf8(response).map_err(ble_error)
+    }
+}
+
+#[async_trait::async_trait]
+impl PledgeCommunicator for BLECommunicator {
+    async fn discover(&self) -> Result<Option<Vec<PledgeCtx>>, ServerError> {
+        self.discover_pledges().await.map(Some)
+    }
+
+    #[tracing::instrument(skip(self, trigger, ctx), target = "RegistrarAgent", name = "ble_send_pvr_trigger")]
+    async fn send_pvr_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, &TPVR, trigger.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, trigger, ctx), target = "RegistrarAgent", name = "ble_send_per_trigger")]
+    async fn send_per_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, &TPER, trigger.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, voucher, ctx), target = "RegistrarAgent", name = "ble_send_voucher")]
+    async fn send_voucher(&self, voucher: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, &VOUCHER, voucher.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, cacerts, ctx), target = "RegistrarAgent", name = "ble_send_ca_certs")]
+    async fn send_ca_certs(&self, cacerts: String, ctx: PledgeCtx) -> Result<(), ServerError> {
+        self.exchange(&ctx, &CA_CERTS, cacerts.as_bytes()).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, response, ctx), target = "RegistrarAgent", name = "ble_send_enroll_response")]
+    async fn send_enroll_response(&self, response: Vec<u8>, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, &ENROLL_RESPONSE, &response).await
+    }
+
+    #[tracing::instrument(skip(self, profile, ctx), target = "RegistrarAgent", name = "ble_send_network_profile")]
+    async fn send_network_profile(&self, profile: String, ctx: PledgeCtx) -> Result<(), ServerError> {
+        self.exchange(&ctx, &NETWORK_PROFILE, profile.as_bytes()).await?;
+        Ok(())
+    }
+}
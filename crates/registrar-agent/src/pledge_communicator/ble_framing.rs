@@ -0,0 +1,59 @@
+/// ATT header bytes that are not available for the value of a write or read
+const ATT_OVERHEAD: usize = 3;
+
+/// Payload bytes per GATT write or read for the negotiated MTU
+pub fn payload_size(mtu: usize) -> usize {
+    mtu.saturating_sub(ATT_OVERHEAD).max(1)
+}
+
+/// Splits a message into GATT writes. The pledge treats a write shorter than the payload size as the
+/// end of the message, so a message filling the last write completely is terminated by an empty write.
+pub fn split(message: &[u8], mtu: usize) -> Vec<&[u8]> {
+    let mut writes: Vec<&[u8]> = message.chunks(payload_size(mtu)).collect();
+    if writes.last().is_none_or(|last| last.len() == payload_size(mtu)) {
+        writes.push(&[]);
+    }
+    writes
+}
+
+/// Collects the chunks the pledge hands out on repeated reads, an empty read marks the end of the message
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    message: Vec<u8>,
+}
+
+impl Reassembler {
+    /// Returns `true` once the message is complete
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        self.message.extend_from_slice(chunk);
+        chunk.is_empty()
+    }
+
+    pub fn into_message(self) -> Vec<u8> {
+        self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let message: Vec<u8> = (0..20).collect();
+
+        let writes = split(&message, 10);
+        assert_eq!(writes.iter().map(|write| write.len()).collect::<Vec<_>>(), vec![7, 7, 6]);
+
+        let writes = split(&message[..14], 10);
+        assert_eq!(writes.iter().map(|write| write.len()).collect::<Vec<_>>(), vec![7, 7, 0]);
+        assert_eq!(split(&[], 10), vec![&[] as &[u8]]);
+
+        let mut reassembler = Reassembler::default();
+        for chunk in message.chunks(7) {
+            assert!(!reassembler.push(chunk));
+        }
+        assert!(reassembler.push(&[]));
+        assert_eq!(reassembler.into_message(), message);
+    }
+}
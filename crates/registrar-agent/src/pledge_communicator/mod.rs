@@ -2,9 +2,25 @@ use common::server_error::ServerError;
 use dyn_clone::DynClone;
 
 pub mod http_communicator;
+#[cfg(any(feature = "ble", test))]
+pub mod ble_framing;
+#[cfg(feature = "ble")]
+pub mod ble_communicator;
+#[cfg(any(feature = "nfc", test))]
+pub mod ndef;
+#[cfg(feature = "nfc")]
+pub mod nfc_communicator;
+#[cfg(any(feature = "usb", test))]
+pub mod usb_framing;
+#[cfg(feature = "usb")]
+pub mod usb_communicator;
 
 #[async_trait::async_trait]
 pub trait PledgeCommunicator: Send + Sync + DynClone {
+    /// Pledges found by the communicator itself, `None` if it cannot scan for pledges
+    async fn discover(&self) -> Result<Option<Vec<PledgeCtx>>, ServerError> {
+        Ok(None)
+    }
 
     async fn send_pvr_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError>;
 
@@ -15,6 +31,16 @@ pub trait PledgeCommunicator: Send + Sync + DynClone {
     async fn send_ca_certs(&self, cacerts: String, ctx: PledgeCtx) -> Result<(), ServerError>;
 
     async fn send_enroll_response(&self, cacerts: Vec<u8>, ctx: PledgeCtx) -> Result<String, ServerError>;
+
+    /// Only called for pledges that sent an SSH host key in their PER
+    async fn send_ssh_certificate(&self, _certificate: String, _ctx: PledgeCtx) -> Result<(), ServerError> {
+        Err(ServerError::BadRequestWithReason("Transport does not support SSH host certificates".to_string()))
+    }
+
+    /// Hands the production network to an enrolled pledge, the profile is JSON
+    async fn send_network_profile(&self, _profile: String, _ctx: PledgeCtx) -> Result<(), ServerError> {
+        Err(ServerError::BadRequestWithReason("Transport does not support network profiles".to_string()))
+    }
 }
 
 impl Clone for Box<dyn PledgeCommunicator> {
@@ -0,0 +1,141 @@
+use thiserror::Error;
+
+const MESSAGE_BEGIN: u8 = 0x80;
+const MESSAGE_END: u8 = 0x40;
+const CHUNKED: u8 = 0x20;
+const SHORT_RECORD: u8 = 0x10;
+const ID_LENGTH_PRESENT: u8 = 0x08;
+const TNF_MASK: u8 = 0x07;
+
+/// Type name format of records whose type is a media type as in RFC 2046
+const TNF_MEDIA_TYPE: u8 = 0x02;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum NdefError {
+    #[error("NDEF message is truncated")]
+    Truncated,
+    #[error("chunked NDEF records are not supported")]
+    Chunked,
+    #[error("NDEF record type is not valid UTF-8")]
+    InvalidType,
+}
+
+/// Media type record of an NDEF message, other type name formats are skipped when decoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NdefRecord {
+    pub media_type: String,
+    pub payload: Vec<u8>,
+}
+
+impl NdefRecord {
+    pub fn new(media_type: &str, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            payload: payload.into(),
+        }
+    }
+}
+
+pub fn encode(records: &[NdefRecord]) -> Vec<u8> {
+    let mut message = vec![];
+
+    for (index, record) in records.iter().enumerate() {
+        let mut header = TNF_MEDIA_TYPE;
+        if index == 0 {
+            header |= MESSAGE_BEGIN;
+        }
+        if index == records.len() - 1 {
+            header |= MESSAGE_END;
+        }
+
+        let short = record.payload.len() <= u8::MAX as usize;
+        if short {
+            header |= SHORT_RECORD;
+        }
+
+        message.push(header);
+        message.push(record.media_type.len() as u8);
+        if short {
+            message.push(record.payload.len() as u8);
+        } else {
+            message.extend_from_slice(&(record.payload.len() as u32).to_be_bytes());
+        }
+        message.extend_from_slice(record.media_type.as_bytes());
+        message.extend_from_slice(&record.payload);
+    }
+
+    message
+}
+
+pub fn decode(message: &[u8]) -> Result<Vec<NdefRecord>, NdefError> {
+    let mut records = vec![];
+    let mut rest = message;
+
+    while let Some((&header, tail)) = rest.split_first() {
+        if header & CHUNKED != 0 {
+            return Err(NdefError::Chunked);
+        }
+
+        let (&type_length, tail) = tail.split_first().ok_or(NdefError::Truncated)?;
+        let (payload_length, tail) = if header & SHORT_RECORD != 0 {
+            let (&length, tail) = tail.split_first().ok_or(NdefError::Truncated)?;
+            (length as usize, tail)
+        } else {
+            let (length, tail) = split_at(tail, 4)?;
+            (u32::from_be_bytes(length.try_into().unwrap()) as usize, tail)
+        };
+        let (id_length, tail) = if header & ID_LENGTH_PRESENT != 0 {
+            let (&length, tail) = tail.split_first().ok_or(NdefError::Truncated)?;
+            (length as usize, tail)
+        } else {
+            (0, tail)
+        };
+
+        let (record_type, tail) = split_at(tail, type_length as usize)?;
+        let (_id, tail) = split_at(tail, id_length)?;
+        let (payload, tail) = split_at(tail, payload_length)?;
+        rest = tail;
+
+        if header & TNF_MASK == TNF_MEDIA_TYPE {
+            records.push(NdefRecord {
+                media_type: String::from_utf8(record_type.to_vec()).map_err(|_| NdefError::InvalidType)?,
+                payload: payload.to_vec(),
+            });
+        }
+
+        if header & MESSAGE_END != 0 {
+            break;
+        }
+    }
+
+    Ok(records)
+}
+
+fn split_at(bytes: &[u8], at: usize) -> Result<(&[u8], &[u8]), NdefError> {
+    if bytes.len() < at {
+        return Err(NdefError::Truncated);
+    }
+    Ok(bytes.split_at(at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_decode() {
+        let records = vec![
+            NdefRecord::new("application/json", b"{}".to_vec()),
+            NdefRecord::new("application/jose+json", vec![b'a'; 300]),
+        ];
+
+        let message = encode(&records);
+        assert_eq!(&message[..3], &[0x92, 16, 2]);
+        assert_eq!(decode(&message).unwrap(), records);
+
+        assert_eq!(decode(&message[..message.len() - 1]), Err(NdefError::Truncated));
+        assert_eq!(decode(&[0xD1 | CHUNKED, 1, 0, b'T']), Err(NdefError::Chunked));
+        // well-known text records are skipped
+        assert_eq!(decode(&[0xD1, 1, 0, b'T']).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    ffi::CString,
+    time::{Duration, Instant},
+};
+
+use common::server_error::ServerError;
+use consts::nfc::{
+    CAPABILITY_CONTAINER_FILE_ID, NDEF_APPLICATION_AID, PVR_MEDIA_TYPE, SERIAL_NUMBER_MEDIA_TYPE, TRIGGER_MEDIA_TYPE,
+};
+use pcsc::{Card, Context, Protocols, Scope, ShareMode, MAX_BUFFER_SIZE};
+use tracing::{event, Level};
+
+use super::{
+    ndef::{self, NdefRecord},
+    PledgeCommunicator, PledgeCtx,
+};
+
+/// How long the pledge may take to replace the trigger on its tag with the PVR
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Status word of a successful APDU
+const SW_SUCCESS: [u8; 2] = [0x90, 0x00];
+
+fn nfc_error(err: impl std::fmt::Display) -> ServerError {
+    ServerError::BadResponse(format!("NFC: {}", err))
+}
+
+/// NFC Forum Type 4 tag behind a PC/SC reader, with its NDEF file selected
+struct Tag {
+    card: Card,
+    /// Maximum bytes of a single READ BINARY
+    max_read: usize,
+    /// Maximum bytes of a single UPDATE BINARY
+    max_write: usize,
+    /// Size of the NDEF file including the two length bytes
+    max_size: usize,
+}
+
+impl Tag {
+    fn connect(context: &Context, reader: &CString) -> Result<Self, ServerError> {
+        let card = context.connect(reader, ShareMode::Shared, Protocols::ANY).map_err(nfc_error)?;
+
+        let mut select_application = vec![0x00, 0xA4, 0x04, 0x00, NDEF_APPLICATION_AID.len() as u8];
+        select_application.extend_from_slice(&NDEF_APPLICATION_AID);
+        select_application.push(0x00);
+        transmit(&card, &select_application)?;
+
+        select_file(&card, CAPABILITY_CONTAINER_FILE_ID)?;
+        let capabilities = transmit(&card, &[0x00, 0xB0, 0x00, 0x00, 0x0F])?;
+        if capabilities.len() < 15 {
+            return Err(nfc_error("capability container is truncated"));
+        }
+        let word = |index: usize| u16::from_be_bytes([capabilities[index], capabilities[index + 1]]) as usize;
+
+        select_file(&card, [capabilities[9], capabilities[10]])?;
+
+        Ok(Self {
+            card,
+            // short APDUs only
+            max_read: word(3).clamp(1, 0xFF),
+            max_write: word(5).clamp(1, 0xFF),
+            max_size: word(11),
+        })
+    }
+
+    fn read_binary(&self, offset: usize, length: usize) -> Result<Vec<u8>, ServerError> {
+        let [offset_high, offset_low] = (offset as u16).to_be_bytes();
+        transmit(&self.card, &[0x00, 0xB0, offset_high, offset_low, length as u8])
+    }
+
+    fn update_binary(&self, offset: usize, data: &[u8]) -> Result<(), ServerError> {
+        let [offset_high, offset_low] = (offset as u16).to_be_bytes();
+        let mut apdu = vec![0x00, 0xD6, offset_high, offset_low, data.len() as u8];
+        apdu.extend_from_slice(data);
+        transmit(&self.card, &apdu)?;
+        Ok(())
+    }
+
+    fn read_message(&self) -> Result<Vec<NdefRecord>, ServerError> {
+        let length = self.read_binary(0, 2)?;
+        if length.len() < 2 {
+            return Err(nfc_error("NDEF file is truncated"));
+        }
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+
+        let mut message = Vec::with_capacity(length);
+        while message.len() < length {
+            let chunk = self.read_binary(2 + message.len(), self.max_read.min(length - message.len()))?;
+            if chunk.is_empty() {
+                return Err(nfc_error("NDEF file is truncated"));
+            }
+            message.extend_from_slice(&chunk);
+        }
+
+        ndef::decode(&message).map_err(nfc_error)
+    }
+
+    /// Clears the length first, so the pledge never reads a partially written message
+    fn write_message(&self, records: &[NdefRecord]) -> Result<(), ServerError> {
+        let message = ndef::encode(records);
+        if message.len() + 2 > self.max_size {
+            return Err(nfc_error(format!(
+                "message of {} bytes exceeds the NDEF file of {} bytes",
+                message.len(),
+                self.max_size
+            )));
+        }
+
+        self.update_binary(0, &[0x00, 0x00])?;
+        for (index, chunk) in message.chunks(self.max_write).enumerate() {
+            self.update_binary(2 + index * self.max_write, chunk)?;
+        }
+        self.update_binary(0, &(message.len() as u16).to_be_bytes())
+    }
+}
+
+fn select_file(card: &Card, file_id: [u8; 2]) -> Result<(), ServerError> {
+    transmit(card, &[0x00, 0xA4, 0x00, 0x0C, 0x02, file_id[0], file_id[1]])?;
+    Ok(())
+}
+
+/// Sends an APDU and returns the response data without the status word
+fn transmit(card: &Card, apdu: &[u8]) -> Result<Vec<u8>, ServerError> {
+    let mut buffer = [0; MAX_BUFFER_SIZE];
+    let response = card.transmit(apdu, &mut buffer).map_err(nfc_error)?;
+
+    match response.split_last_chunk::<2>() {
+        Some((data, status)) if *status == SW_SUCCESS => Ok(data.to_vec()),
+        Some((_, status)) => Err(nfc_error(format!("tag answered {:02X}{:02X}", status[0], status[1]))),
+        None => Err(nfc_error("tag answered without status word")),
+    }
+}
+
+fn serial_number(records: &[NdefRecord]) -> Option<String> {
+    records
+        .iter()
+        .find(|record| record.media_type == SERIAL_NUMBER_MEDIA_TYPE)
+        .and_then(|record| String::from_utf8(record.payload.clone()).ok())
+}
+
+/// Provisioning by tapping the pledge on a PC/SC reader. The pledge emulates an NFC Forum Type 4 tag that
+/// carries its serial-number, the agent replaces it with the trigger and the pledge answers with the PVR.
+/// [`PledgeCtx::ctx`] holds the name of the reader the pledge was tapped on.
+#[derive(Debug, Clone)]
+pub struct NFCCommunicator {
+    reader: Option<String>,
+}
+
+impl NFCCommunicator {
+    /// Without a reader name, every reader of the system is used
+    pub fn new(reader: Option<String>) -> Self {
+        Self { reader }
+    }
+
+    fn readers(&self, context: &Context) -> Result<Vec<CString>, ServerError> {
+        let readers = context.list_readers_owned().map_err(nfc_error)?;
+
+        Ok(match &self.reader {
+            Some(name) => readers.into_iter().filter(|reader| reader.to_string_lossy() == *name).collect(),
+            None => readers,
+        })
+    }
+
+    fn scan(&self) -> Result<Vec<PledgeCtx>, ServerError> {
+        let context = Context::establish(Scope::User).map_err(nfc_error)?;
+
+        let mut pledges = vec![];
+        for reader in self.readers(&context)? {
+            let records = match Tag::connect(&context, &reader).and_then(|tag| tag.read_message()) {
+                Ok(records) => records,
+                Err(err) => {
+                    event!(Level::DEBUG, "No pledge on reader {:?}: {}", reader, err);
+                    continue;
+                }
+            };
+
+            match serial_number(&records) {
+                Some(serial_number) => {
+                    event!(Level::INFO, "Discovered pledge {} on reader {:?}", serial_number, reader);
+                    pledges.push(PledgeCtx {
+                        ctx: reader.to_string_lossy().to_string(),
+                        pledge_serial: serial_number,
+                        pledge_url: String::new(),
+                    });
+                }
+                None => event!(Level::WARN, "Ignoring tag on reader {:?} without serial-number", reader),
+            }
+        }
+
+        Ok(pledges)
+    }
+
+    fn exchange_trigger(ctx: &PledgeCtx, trigger: &str) -> Result<String, ServerError> {
+        let context = Context::establish(Scope::User).map_err(nfc_error)?;
+        let reader = CString::new(ctx.ctx.clone()).map_err(nfc_error)?;
+        let tag = Tag::connect(&context, &reader)?;
+
+        // the tag might have been swapped since discovery
+        if serial_number(&tag.read_message()?).as_ref() != Some(&ctx.pledge_serial) {
+            return Err(nfc_error(format!("pledge {} is no longer on the reader", ctx.pledge_serial)));
+        }
+
+        tag.write_message(&[NdefRecord::new(TRIGGER_MEDIA_TYPE, trigger)])?;
+
+        let started = Instant::now();
+        loop {
+            let records = tag.read_message()?;
+            if let Some(pvr) = records.into_iter().find(|record| record.media_type == PVR_MEDIA_TYPE) {
+                return String::from_utf8(pvr.payload).map_err(nfc_error);
+            }
+            if started.elapsed() > RESPONSE_TIMEOUT {
+                return Err(nfc_error("pledge did not respond in time"));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn unsupported(step: &str) -> ServerError {
+        ServerError::BadRequestWithReason(format!("NFC transport does not support {}", step))
+    }
+}
+
+#[async_trait::async_trait]
+impl PledgeCommunicator for NFCCommunicator {
+    async fn discover(&self) -> Result<Option<Vec<PledgeCtx>>, ServerError> {
+        let communicator = self.clone();
+        tokio::task::spawn_blocking(move || communicator.scan())
+            .await
+            .map_err(nfc_error)?
+            .map(Some)
+    }
+
+    #[tracing::instrument(skip(self, trigger, ctx), target = "RegistrarAgent", name = "nfc_send_pvr_trigger")]
+    async fn send_pvr_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        tokio::task::spawn_blocking(move || Self::exchange_trigger(&ctx, &trigger))
+            .await
+            .map_err(nfc_error)?
+    }
+
+    async fn send_per_trigger(&self, _trigger: String, _ctx: PledgeCtx) -> Result<String, ServerError> {
+        Err(Self::unsupported("the PER trigger"))
+    }
+
+    async fn send_voucher(&self, _voucher: String, _ctx: PledgeCtx) -> Result<String, ServerError> {
+        Err(Self::unsupported("voucher delivery"))
+    }
+
+    async fn send_ca_certs(&self, _cacerts: String, _ctx: PledgeCtx) -> Result<(), ServerError> {
+        Err(Self::unsupported("CA certificate delivery"))
+    }
+
+    async fn send_enroll_response(&self, _response: Vec<u8>, _ctx: PledgeCtx) -> Result<String, ServerError> {
+        Err(Self::unsupported("enroll response delivery"))
+    }
+}
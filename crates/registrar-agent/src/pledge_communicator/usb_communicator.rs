@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use common::server_error::ServerError;
+use consts::usb::{CA_CERTS, ENROLL_RESPONSE, ERROR_FLAG, IDENTIFY, TPER, TPVR, VOUCHER};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::SerialPortBuilderExt;
+use tracing::{event, Level};
+
+use super::{
+    usb_framing::{Deframer, Frame},
+    PledgeCommunicator, PledgeCtx,
+};
+
+/// Ignored by USB CDC, but required to open the port
+const BAUD_RATE: u32 = 115_200;
+
+/// How long a pledge on a port may take to tell its serial-number
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the pledge may take to compute a response, e.g. to sign the PVR
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn usb_error(err: impl std::fmt::Display) -> ServerError {
+    ServerError::BadResponse(format!("USB: {}", err))
+}
+
+/// Provisioning over a USB cable, as used by production line fixtures. Every port is connected to one pledge,
+/// the PRM exchanges of the BLE services are sent as frames over the serial line.
+/// [`PledgeCtx::ctx`] holds the path of the port the pledge is connected to.
+#[derive(Debug, Clone)]
+pub struct USBCommunicator {
+    ports: Vec<String>,
+}
+
+impl USBCommunicator {
+    pub fn new(ports: Vec<String>) -> Self {
+        Self { ports }
+    }
+
+    /// Sends the request frame and waits for the answer of the same exchange. The port is opened per
+    /// exchange, so a pledge that was replaced on the fixture in between is picked up.
+    async fn exchange(port: &str, exchange: u8, request: &[u8], timeout: Duration) -> Result<Vec<u8>, ServerError> {
+        let mut serial = tokio_serial::new(port, BAUD_RATE).open_native_async().map_err(usb_error)?;
+        serial.write_all(&Frame::new(exchange, request).encode()).await.map_err(usb_error)?;
+        serial.flush().await.map_err(usb_error)?;
+
+        let receive = async {
+            let mut deframer = Deframer::default();
+            let mut buffer = [0u8; 1024];
+            loop {
+                while let Some(frame) = deframer.next_frame() {
+                    if frame.exchange & !ERROR_FLAG != exchange {
+                        event!(Level::DEBUG, "Ignoring frame of exchange {} on {}", frame.exchange, port);
+                        continue;
+                    }
+                    if frame.is_error() {
+                        return Err(usb_error(format!("pledge rejected the request: {}", String::from_utf8_lossy(&frame.payload))));
+                    }
+                    return Ok(frame.payload);
+                }
+
+                let read = serial.read(&mut buffer).await.map_err(usb_error)?;
+                if read == 0 {
+                    return Err(usb_error(format!("{} was closed", port)));
+                }
+                deframer.push(&buffer[..read]);
+            }
+        };
+
+        tokio::time::timeout(timeout, receive)
+            .await
+            .map_err(|_| usb_error("pledge did not respond in time"))?
+    }
+
+    /// The pledge might have been swapped since discovery
+    async fn exchange_checked(&self, ctx: &PledgeCtx, exchange: u8, request: &[u8]) -> Result<Vec<u8>, ServerError> {
+        let serial_number = Self::exchange(&ctx.ctx, IDENTIFY, &[], IDENTIFY_TIMEOUT).await?;
+        if serial_number != ctx.pledge_serial.as_bytes() {
+            return Err(usb_error(format!("pledge {} is no longer connected to {}", ctx.pledge_serial, ctx.ctx)));
+        }
+
+        Self::exchange(&ctx.ctx, exchange, request, RESPONSE_TIMEOUT).await
+    }
+
+    async fn exchange_string(&self, ctx: &PledgeCtx, exchange: u8, request: &[u8]) -> Result<String, ServerError> {
+        let response = self.exchange_checked(ctx, exchange, request).await?;
+        String::from_utf8(response).map_err(usb_error)
+    }
+}
+
+#[async_trait::async_trait]
+impl PledgeCommunicator for USBCommunicator {
+    #[tracing::instrument(skip(self), target = "RegistrarAgent", name = "usb_discover")]
+    async fn discover(&self) -> Result<Option<Vec<PledgeCtx>>, ServerError> {
+        let mut pledges = vec![];
+        for port in &self.ports {
+            let serial_number = match Self::exchange(port, IDENTIFY, &[], IDENTIFY_TIMEOUT).await {
+                Ok(serial_number) => serial_number,
+                Err(err) => {
+                    event!(Level::DEBUG, "No pledge on {}: {}", port, err);
+                    continue;
+                }
+            };
+
+            match String::from_utf8(serial_number) {
+                Ok(serial_number) => {
+                    event!(Level::INFO, "Discovered pledge {} on {}", serial_number, port);
+                    pledges.push(PledgeCtx {
+                        ctx: port.clone(),
+                        pledge_serial: serial_number,
+                        pledge_url: String::new(),
+                    });
+                }
+                Err(_) => event!(Level::WARN, "Ignoring pledge on {} with a malformed serial-number", port),
+            }
+        }
+
+        Ok(Some(pledges))
+    }
+
+    #[tracing::instrument(skip(self, trigger, ctx), target = "RegistrarAgent", name = "usb_send_pvr_trigger")]
+    async fn send_pvr_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, TPVR, trigger.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, trigger, ctx), target = "RegistrarAgent", name = "usb_send_per_trigger")]
+    async fn send_per_trigger(&self, trigger: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, TPER, trigger.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, voucher, ctx), target = "RegistrarAgent", name = "usb_send_voucher")]
+    async fn send_voucher(&self, voucher: String, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, VOUCHER, voucher.as_bytes()).await
+    }
+
+    #[tracing::instrument(skip(self, cacerts, ctx), target = "RegistrarAgent", name = "usb_send_ca_certs")]
+    async fn send_ca_certs(&self, cacerts: String, ctx: PledgeCtx) -> Result<(), ServerError> {
+        self.exchange_checked(&ctx, CA_CERTS, cacerts.as_bytes()).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, response, ctx), target = "RegistrarAgent", name = "usb_send_enroll_response")]
+    async fn send_enroll_response(&self, response: Vec<u8>, ctx: PledgeCtx) -> Result<String, ServerError> {
+        self.exchange_string(&ctx, ENROLL_RESPONSE, &response).await
+    }
+}
@@ -0,0 +1,102 @@
+use consts::usb::{ERROR_FLAG, FRAME_MAGIC, HEADER_LENGTH, MAX_PAYLOAD_LENGTH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub exchange: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(exchange: u8, payload: &[u8]) -> Self {
+        Self { exchange, payload: payload.to_vec() }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.exchange & ERROR_FLAG != 0
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER_LENGTH + self.payload.len());
+        encoded.push(FRAME_MAGIC);
+        encoded.push(self.exchange);
+        encoded.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(&self.payload);
+        encoded
+    }
+}
+
+/// Collects frames from the bytes read off the serial line. Bytes before a frame, such as log output of the
+/// pledge, are dropped, as are headers announcing more than [`MAX_PAYLOAD_LENGTH`] bytes.
+#[derive(Debug, Default)]
+pub struct Deframer {
+    buffer: Vec<u8>,
+}
+
+impl Deframer {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, `None` if more bytes are needed
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        loop {
+            let start = self.buffer.iter().position(|byte| *byte == FRAME_MAGIC).unwrap_or(self.buffer.len());
+            self.buffer.drain(..start);
+
+            if self.buffer.len() < HEADER_LENGTH {
+                return None;
+            }
+
+            let length = u32::from_be_bytes([self.buffer[2], self.buffer[3], self.buffer[4], self.buffer[5]]) as usize;
+            if length > MAX_PAYLOAD_LENGTH {
+                // not a frame header after all, resynchronize on the next magic byte
+                self.buffer.drain(..1);
+                continue;
+            }
+
+            if self.buffer.len() < HEADER_LENGTH + length {
+                return None;
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..HEADER_LENGTH + length).collect();
+            return Some(Frame { exchange: frame[1], payload: frame[HEADER_LENGTH..].to_vec() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deframe_between_log_output() {
+        let frame = Frame::new(consts::usb::TPVR, b"{\"payload\":\"...\"}");
+
+        let mut line = b"I (1234) esp32::usb_async: Request received\r\n".to_vec();
+        line.extend(frame.encode());
+        line.extend(b"I (1240) esp32::usb_async: Response sent\r\n");
+
+        let mut deframer = Deframer::default();
+        for chunk in line.chunks(5) {
+            deframer.push(chunk);
+            if let Some(received) = deframer.next_frame() {
+                assert_eq!(received, frame);
+                assert!(!received.is_error());
+                return;
+            }
+        }
+        panic!("frame was not received");
+    }
+
+    #[test]
+    fn test_skips_oversized_header() {
+        let mut deframer = Deframer::default();
+        deframer.push(&[FRAME_MAGIC, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]);
+        deframer.push(&Frame::new(consts::usb::TPVR | ERROR_FLAG, b"no trigger").encode());
+
+        let frame = deframer.next_frame().unwrap();
+        assert!(frame.is_error());
+        assert_eq!(frame.payload, b"no trigger");
+        assert_eq!(deframer.next_frame(), None);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{event, Level};
+
+/// Number of updates a slow subscriber may lag behind before it misses updates
+const PROGRESS_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnboardingStep {
+    Started,
+    RequestingVoucher,
+    RequestingCertificate,
+    DeliveringVoucher,
+    DeliveringCaCerts,
+    DeliveringCertificate,
+    DeliveringSshCertificate,
+    DeliveringNetworkProfile,
+    ReportingStatus,
+    Completed,
+    Failed,
+}
+
+impl OnboardingStep {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, OnboardingStep::Completed | OnboardingStep::Failed)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProgressUpdate {
+    pub serial_number: String,
+    pub step: OnboardingStep,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Tracks the onboarding runs of the agent, so a front end can follow them while they happen
+#[derive(Debug)]
+pub struct Progress {
+    latest: RwLock<HashMap<String, ProgressUpdate>>,
+    updates: broadcast::Sender<ProgressUpdate>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            latest: RwLock::new(HashMap::new()),
+            updates: broadcast::channel(PROGRESS_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl Progress {
+    pub async fn update(&self, serial_number: &str, step: OnboardingStep) {
+        self.publish(serial_number, step, None).await;
+    }
+
+    pub async fn fail(&self, serial_number: &str, error: impl ToString) {
+        self.publish(serial_number, OnboardingStep::Failed, Some(error.to_string())).await;
+    }
+
+    async fn publish(&self, serial_number: &str, step: OnboardingStep, error: Option<String>) {
+        let mut latest = self.latest.write().await;
+        self.publish_locked(&mut latest, serial_number, step, error);
+    }
+
+    fn publish_locked(&self, latest: &mut HashMap<String, ProgressUpdate>, serial_number: &str, step: OnboardingStep, error: Option<String>) {
+        event!(target: "RegistrarAgent::Progress", Level::DEBUG, "Onboarding of {} at {:?}", serial_number, step);
+
+        let update = ProgressUpdate {
+            serial_number: serial_number.to_string(),
+            step,
+            error,
+            timestamp: Utc::now(),
+        };
+        latest.insert(serial_number.to_string(), update.clone());
+        // nobody listening is fine
+        let _ = self.updates.send(update);
+    }
+
+    /// Marks the onboarding as started, `false` if it is already running
+    pub async fn start(&self, serial_number: &str) -> bool {
+        let mut latest = self.latest.write().await;
+        if latest.get(serial_number).is_some_and(|update| !update.step.is_finished()) {
+            return false;
+        }
+        self.publish_locked(&mut latest, serial_number, OnboardingStep::Started, None);
+        true
+    }
+
+    pub async fn is_running(&self, serial_number: &str) -> bool {
+        self.latest(serial_number).await.is_some_and(|update| !update.step.is_finished())
+    }
+
+    pub async fn latest(&self, serial_number: &str) -> Option<ProgressUpdate> {
+        self.latest.read().await.get(serial_number).cloned()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressUpdate> {
+        self.updates.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress_updates() {
+        let progress = Progress::default();
+        let mut updates = progress.subscribe();
+
+        assert!(progress.start("00-D0-E5-F2-00-02").await);
+        assert!(!progress.start("00-D0-E5-F2-00-02").await);
+        progress.update("00-D0-E5-F2-00-02", OnboardingStep::RequestingVoucher).await;
+        progress.fail("00-D0-E5-F2-00-02", "registrar unreachable").await;
+
+        assert_eq!(updates.recv().await.unwrap().step, OnboardingStep::Started);
+        assert_eq!(updates.recv().await.unwrap().step, OnboardingStep::RequestingVoucher);
+        let failed = updates.recv().await.unwrap();
+        assert_eq!(failed.step, OnboardingStep::Failed);
+        assert_eq!(failed.error.as_deref(), Some("registrar unreachable"));
+
+        assert!(!progress.is_running("00-D0-E5-F2-00-02").await);
+        assert!(progress.start("00-D0-E5-F2-00-02").await);
+    }
+}
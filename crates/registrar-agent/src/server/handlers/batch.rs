@@ -0,0 +1,16 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+use brski_prm_artifacts::content_type::JOSE;
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{batch::run_batch, server::server::ServerState};
+
+/// Onboards all pledges in range and answers with the report signed by the agent
+#[tracing::instrument(skip(state), target = "RegistrarAgent", name = "batch")]
+pub async fn batch(State(state): State<ServerState>) -> Result<impl IntoResponse, ServerError> {
+    event!(Level::INFO, "Received batch request");
+
+    let report = run_batch(&state, state.config.config.batch_workers).await?;
+
+    Ok(([(CONTENT_TYPE, JOSE)], report.sign(&state.config)?))
+}
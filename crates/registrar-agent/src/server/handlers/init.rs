@@ -3,14 +3,17 @@ use brski_prm_artifacts::{
     cacerts::response::CACERTS_JWS, issued_voucher::IssuedVoucherJWS, per::response::PER_JWS, pvr::response::PVR_JWS, rer, status::{enroll::response::EnrollStatusJWS, voucher::response::vStatus_JWS}
 };
 use common::server_error::ServerError;
+use openssl::x509::X509;
 use tracing::{event, info, Level};
 
-use crate::{client, pledge_communicator::PledgeCtx, server::server::ServerState};
+use crate::{client, pledge_communicator::PledgeCtx, progress::OnboardingStep, server::server::ServerState};
 
 struct BootstrappingObjects {
     issued_voucher: IssuedVoucherJWS,
     signed_cert: rer::response::Response,
-    wrapped_cacerts: CACERTS_JWS
+    wrapped_cacerts: CACERTS_JWS,
+    /// Only for pledges that sent an SSH host key
+    ssh_certificate: Option<String>,
 }
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
@@ -18,7 +21,7 @@ struct BootstrappingObjects {
 pub async fn init(State(state): State<ServerState>) -> Result<(), ServerError> {
     event!(Level::INFO, "Received init request");
 
-    let pledges = client::discover_pledges(&state.config).await?;
+    let pledges = client::discover_pledges(&state).await?;
 
     event!(Level::INFO, "Discovered pledges: {:#?}", pledges);
 
@@ -32,22 +35,55 @@ pub async fn init(State(state): State<ServerState>) -> Result<(), ServerError> {
 
 #[tracing::instrument(skip(state), target = "RegistrarAgent", name = "bootstrap_pledge")]
 pub async fn bootstrap_pledge(state: &ServerState, pledge: &PledgeCtx) -> Result<(), ServerError> {
+    let result = run_bootstrap(state, pledge).await;
+
+    match &result {
+        Ok(()) => {
+            state.identifications.remove(&pledge.pledge_serial).await;
+            state.progress.update(&pledge.pledge_serial, OnboardingStep::Completed).await
+        }
+        Err(err) => state.progress.fail(&pledge.pledge_serial, err).await,
+    }
+    result
+}
+
+async fn run_bootstrap(state: &ServerState, pledge: &PledgeCtx) -> Result<(), ServerError> {
     let bootstrapping_objects = get_bootstrapping_objects(state, pledge).await?;
 
     // first we send the voucher to the pledge
 
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::DeliveringVoucher).await;
+
     let voucher_status: vStatus_JWS = client::send_voucher_to_pledge(state, bootstrapping_objects.issued_voucher, pledge).await?;
 
     // next we send the cacerts to the pledge
 
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::DeliveringCaCerts).await;
+
     client::send_cacerts_to_pledge(state, bootstrapping_objects.wrapped_cacerts, pledge).await?;
 
     // then, we send the signed certificate to the pledge
 
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::DeliveringCertificate).await;
+
     let enroll_status: EnrollStatusJWS = client::send_enroll_response_to_pledge(state, bootstrapping_objects.signed_cert, pledge).await?;
 
+    if let Some(ssh_certificate) = bootstrapping_objects.ssh_certificate {
+        state.progress.update(&pledge.pledge_serial, OnboardingStep::DeliveringSshCertificate).await;
+
+        client::send_ssh_certificate_to_pledge(state, ssh_certificate, pledge).await?;
+    }
+
+    if let Some(network_profile) = &state.config.config.network_profile {
+        state.progress.update(&pledge.pledge_serial, OnboardingStep::DeliveringNetworkProfile).await;
+
+        client::send_network_profile_to_pledge(state, network_profile, pledge).await?;
+    }
+
     // if all this is successful, we can now send the voucher status to the registrar
 
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::ReportingStatus).await;
+
     client::send_voucher_status_to_registrar(&state.config, voucher_status, &state.client).await?;
 
     // we also send the enroll status to the registrar
@@ -57,12 +93,23 @@ pub async fn bootstrap_pledge(state: &ServerState, pledge: &PledgeCtx) -> Result
 
 #[tracing::instrument(skip(state), target = "RegistrarAgent", name = "get_bootstrapping_objects")]
 async fn get_bootstrapping_objects(state: &ServerState, pledge: &PledgeCtx) -> Result<BootstrappingObjects, ServerError> {
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::RequestingVoucher).await;
+
     let (pvr, per) = get_pvr_per_pair_for_pledge(&state.clone(), pledge).await?;
 
     let issued_voucher_jws: IssuedVoucherJWS =
         client::send_pvr_to_registrar(&state.config, pvr, &state.client).await?;
 
-    let signed_cert_jws = client::send_per_to_registrar(&state.config, per, &state.client).await?;
+    state.progress.update(&pledge.pledge_serial, OnboardingStep::RequestingCertificate).await;
+
+    let ssh_host_key = client::requested_ssh_host_key(&per)?;
+
+    let signed_cert_jws = client::send_per_to_registrar(&state.config, per.clone(), &state.client).await?;
+
+    let ssh_certificate = match ssh_host_key {
+        Some(_) => Some(client::request_ssh_certificate_from_registrar(&state.config, per, &state.client).await?),
+        None => None,
+    };
 
     info!("Received signed certificate from registrar");
 
@@ -71,7 +118,8 @@ async fn get_bootstrapping_objects(state: &ServerState, pledge: &PledgeCtx) -> R
     Ok(BootstrappingObjects {
         issued_voucher: issued_voucher_jws,
         signed_cert: signed_cert_jws,
-        wrapped_cacerts: wrapped_cacerts
+        wrapped_cacerts,
+        ssh_certificate,
     })
 }
 
@@ -85,9 +133,44 @@ async fn get_pvr_per_pair_for_pledge(
 
     event!(Level::INFO, "Done receiving PVR");
 
+    verify_identification(state, pledge, &pvr).await?;
+
     let per = client::trigger_per(state, pledge).await?;
 
     event!(Level::INFO, "Done receiving PER");
 
     Ok((pvr, per))
 }
+
+/// Cross-checks the IDevID of the PVR against the label the installer scanned, so a substituted device
+/// is not onboarded in place of the one in front of the installer
+#[tracing::instrument(skip(state, pvr), target = "RegistrarAgent", name = "verify_identification")]
+async fn verify_identification(state: &ServerState, pledge: &PledgeCtx, pvr: &PVR_JWS) -> Result<(), ServerError> {
+    let Some(label) = state.identifications.get(&pledge.pledge_serial).await else {
+        if state.config.config.require_identification {
+            return Err(ServerError::BadRequestWithReason(format!(
+                "Pledge {} has not been identified by its label",
+                pledge.pledge_serial
+            )));
+        }
+        return Ok(());
+    };
+
+    let header = pvr.clone().decode()?.try_decoded_data()?.header.ok_or(ServerError::BadResponse("PVR without header".to_string()))?;
+    let idevid = header
+        .x509_certificate_chain()
+        .and_then(|chain| chain.first().cloned())
+        .ok_or(ServerError::BadResponse("PVR without IDevID".to_string()))?;
+    let idevid = X509::from_der(&idevid)?;
+
+    label.verify(&idevid).map_err(|err| {
+        event!(Level::WARN, "Pledge {} does not match its label: {}", pledge.pledge_serial, err);
+        ServerError::IdentificationFailed {
+            serial_number: pledge.pledge_serial.clone(),
+            reason: err.to_string(),
+        }
+    })?;
+
+    event!(Level::INFO, "Pledge {} matches its label", pledge.pledge_serial);
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use common::server_error::ServerError;
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{event, Level};
+
+use crate::{client, identification::PledgeLabel, progress::ProgressUpdate, server::server::ServerState};
+
+use super::bootstrap_pledge;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct NearbyPledge {
+    serial_number: String,
+    progress: Option<ProgressUpdate>,
+}
+
+#[tracing::instrument(skip(state), target = "RegistrarAgent")]
+pub async fn handle_pledges(State(state): State<ServerState>) -> Result<Json<Vec<NearbyPledge>>, ServerError> {
+    event!(Level::INFO, "Received nearby pledges request");
+
+    let mut pledges = vec![];
+    for pledge in client::discover_pledges(&state).await? {
+        pledges.push(NearbyPledge {
+            progress: state.progress.latest(&pledge.pledge_serial).await,
+            serial_number: pledge.pledge_serial,
+        });
+    }
+
+    Ok(Json(pledges))
+}
+
+/// Onboarding runs in the background, its progress is streamed by [`handle_progress`]
+#[tracing::instrument(skip(state), target = "RegistrarAgent")]
+pub async fn handle_onboard(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+) -> Result<(StatusCode, Json<Option<ProgressUpdate>>), ServerError> {
+    event!(Level::INFO, "Received onboarding request for {}", serial_number);
+
+    let pledge = client::discover_pledges(&state)
+        .await?
+        .into_iter()
+        .find(|pledge| pledge.pledge_serial == serial_number)
+        .ok_or(ServerError::BadRequestWithReason(format!("Pledge {} is not nearby", serial_number)))?;
+
+    if !state.progress.start(&serial_number).await {
+        return Ok((StatusCode::CONFLICT, Json(state.progress.latest(&serial_number).await)));
+    }
+
+    let background_state = state.clone();
+    tokio::spawn(async move {
+        if let Err(err) = bootstrap_pledge(&background_state, &pledge).await {
+            event!(Level::WARN, "Onboarding of {} failed: {}", pledge.pledge_serial, err);
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(state.progress.latest(&serial_number).await)))
+}
+
+/// Takes the content of the QR code label on the pledge, which is checked against the IDevID during onboarding
+#[tracing::instrument(skip(state, body), target = "RegistrarAgent")]
+pub async fn handle_identify(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+    body: String,
+) -> Result<Json<PledgeLabel>, ServerError> {
+    event!(Level::INFO, "Received label of pledge {}", serial_number);
+
+    let label: PledgeLabel = body.parse().map_err(|err| ServerError::BadRequestWithReason(format!("{}", err)))?;
+    if label.serial_number != serial_number {
+        return Err(ServerError::IdentificationFailed {
+            serial_number,
+            reason: format!("label belongs to pledge {}", label.serial_number),
+        });
+    }
+
+    state.identifications.register(label.clone()).await;
+    Ok(Json(label))
+}
+
+/// Server-sent events with the progress of the pledge, starting with the latest known step.
+/// The stream ends once the onboarding completed or failed.
+#[tracing::instrument(skip(state), target = "RegistrarAgent")]
+pub async fn handle_progress(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    event!(Level::INFO, "Received progress subscription for {}", serial_number);
+
+    // subscribe first, so no update between reading the latest step and subscribing is lost
+    let updates = state.progress.subscribe();
+    let latest = state.progress.latest(&serial_number).await;
+
+    let stream = futures::stream::unfold((updates, latest, false), move |(mut updates, pending, finished)| {
+        let serial_number = serial_number.clone();
+        async move {
+            if finished {
+                return None;
+            }
+
+            let update = match pending {
+                Some(update) => update,
+                None => loop {
+                    match updates.recv().await {
+                        Ok(update) if update.serial_number == serial_number => break update,
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                },
+            };
+
+            let finished = update.step.is_finished();
+            Some((Event::default().json_data(&update), (updates, None, finished)))
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
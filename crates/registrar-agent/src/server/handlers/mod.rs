@@ -1,9 +1,22 @@
+mod batch;
 mod init;
-use axum::{routing::post, Router};
+mod installer;
+use axum::{routing::{get, post}, Router};
 pub use init::bootstrap_pledge;
 
 use super::server::ServerState;
 
 pub(crate) fn brski_routes() -> Router<ServerState> {
-    Router::new().route("/init", post(init::init))
+    Router::new()
+        .route("/init", post(init::init))
+        .route("/batch", post(batch::batch))
+}
+
+/// Simplified API for the phone or tablet front end of field installers
+pub(crate) fn installer_routes() -> Router<ServerState> {
+    Router::new()
+        .route("/pledges", get(installer::handle_pledges))
+        .route("/pledges/:serial_number/identify", post(installer::handle_identify))
+        .route("/pledges/:serial_number/onboard", post(installer::handle_onboard))
+        .route("/pledges/:serial_number/progress", get(installer::handle_progress))
 }
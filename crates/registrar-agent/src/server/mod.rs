@@ -1,4 +1,5 @@
 mod handlers;
+#[allow(clippy::module_inception)]
 pub mod server;
 pub use server::get_app;
 pub use handlers::bootstrap_pledge;
\ No newline at end of file
@@ -1,27 +1,65 @@
+use std::sync::Arc;
+
 use crate::{
-    parsed_config::ParsedConfig, pledge_communicator::{http_communicator::HTTPCommunicator, PledgeCommunicator},
+    parsed_config::ParsedConfig, pledge_communicator::{http_communicator::HTTPCommunicator, PledgeCommunicator}, progress::Progress,
+    identification::Identifications,
+};
+#[cfg(feature = "ble")]
+use crate::pledge_communicator::ble_communicator::BLECommunicator;
+#[cfg(feature = "nfc")]
+use crate::pledge_communicator::nfc_communicator::NFCCommunicator;
+#[cfg(feature = "usb")]
+use crate::pledge_communicator::usb_communicator::USBCommunicator;
+#[cfg(not(all(feature = "ble", feature = "nfc", feature = "usb")))]
+use anyhow::anyhow;
+use axum::{middleware, Router};
+use cli::config::PledgeTransport;
+use common::{
+    auth::{require_scope, Authenticator},
+    error::AppError,
+    limits::{enforce_body_limit, Artifact},
 };
-use axum::{Router};
-use common::error::AppError;
 use reqwest::Client;
 use tower_http::trace::TraceLayer;
 
-use super::handlers::brski_routes;
+use super::handlers::{brski_routes, installer_routes};
 
 #[derive(Clone)]
 pub struct ServerState {
     pub config: ParsedConfig,
     pub client: reqwest::Client,
-    pub communicator: Box<dyn PledgeCommunicator>
+    pub communicator: Box<dyn PledgeCommunicator>,
+    pub progress: Arc<Progress>,
+    pub identifications: Arc<Identifications>,
+}
+
+async fn get_communicator(config: &ParsedConfig, client: &Client) -> anyhow::Result<Box<dyn PledgeCommunicator>, AppError> {
+    match config.config.transport {
+        PledgeTransport::Http => Ok(Box::new(HTTPCommunicator::new(client.clone()))),
+        #[cfg(feature = "ble")]
+        PledgeTransport::Ble => Ok(Box::new(BLECommunicator::new().await?)),
+        #[cfg(not(feature = "ble"))]
+        PledgeTransport::Ble => Err(anyhow!("registrar-agent was built without the ble feature").into()),
+        #[cfg(feature = "nfc")]
+        PledgeTransport::Nfc => Ok(Box::new(NFCCommunicator::new(config.config.nfc_reader.clone()))),
+        #[cfg(not(feature = "nfc"))]
+        PledgeTransport::Nfc => Err(anyhow!("registrar-agent was built without the nfc feature").into()),
+        #[cfg(feature = "usb")]
+        PledgeTransport::Usb => Ok(Box::new(USBCommunicator::new(config.config.usb_ports.clone()))),
+        #[cfg(not(feature = "usb"))]
+        PledgeTransport::Usb => Err(anyhow!("registrar-agent was built without the usb feature").into()),
+    }
 }
 
-fn get_server_state(config: &ParsedConfig) -> anyhow::Result<ServerState, AppError> {
+async fn get_server_state(config: &ParsedConfig) -> anyhow::Result<ServerState, AppError> {
     let client = Client::new();
 
     Ok(ServerState {
         config: config.clone(),
         client: client.clone(),
-        communicator: Box::new(HTTPCommunicator::new(client))
+        communicator: get_communicator(config, &client).await?,
+        progress: Arc::new(Progress::default()),
+        identifications: Arc::new(Identifications::default()),
     })
 }
 
@@ -29,17 +67,38 @@ pub fn get_state(config: &ParsedConfig, communicator: Box<dyn PledgeCommunicator
     Ok(ServerState {
         config: config.clone(),
         client: Client::new(),
-        communicator
+        communicator,
+        progress: Arc::new(Progress::default()),
+        identifications: Arc::new(Identifications::default()),
     })
 }
 
 pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppError> {
     
-    let state = get_server_state(config)?;
+    let state = get_server_state(config).await?;
+
+    let mut routes = Router::new().nest("/.well-known/brski", brski_routes());
+
+    if !config.config.installer_api_keys.is_empty() {
+        let authenticator = Arc::new(Authenticator::new(
+            config.config.installer_api_keys.clone(),
+            None,
+            None,
+            state.client.clone(),
+        ));
+        routes = routes.nest(
+            "/installer",
+            installer_routes().route_layer(middleware::from_fn_with_state(authenticator, require_scope)),
+        );
+    }
 
-    let routes = Router::new().nest("/.well-known/brski", brski_routes());
+    let limits = &config.config.artifact_limits;
+    let routes = routes.route_layer(middleware::from_fn_with_state(limits.guard(Artifact::Other), enforce_body_limit));
 
-    let app = routes.with_state(state).layer(TraceLayer::new_for_http());
+    let app = routes
+        .with_state(state)
+        .layer(middleware::from_fn(common::problem::problem_details))
+        .layer(TraceLayer::new_for_http());
 
     Ok(app)
 }
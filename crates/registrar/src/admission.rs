@@ -0,0 +1,213 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use cli::config::{AdmissionWindowConfig, RegistrarConfig};
+use common::server_error::ServerError;
+use serde::Serialize;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AdmissionWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl AdmissionWindow {
+    fn parse(config: &AdmissionWindowConfig) -> anyhow::Result<Self> {
+        let days = config
+            .days
+            .iter()
+            .map(|day| day.parse::<Weekday>().map_err(|_| anyhow!("Invalid admission window day {}", day)))
+            .collect::<anyhow::Result<_>>()?;
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| anyhow!("Invalid admission window time {}, expected HH:MM", time))
+        };
+
+        Ok(Self {
+            days,
+            start: time(&config.start)?,
+            end: time(&config.end)?,
+        })
+    }
+
+    fn applies_to(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let (day, time) = (now.weekday(), now.time());
+
+        if self.start < self.end {
+            return self.applies_to(day) && self.start <= time && time < self.end;
+        }
+
+        // spans midnight, the morning part belongs to the window of the previous day
+        (self.applies_to(day) && self.start <= time) || (self.applies_to(day.pred()) && time < self.end)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AdmissionStatus {
+    pub(crate) maintenance_mode: bool,
+    pub(crate) open: bool,
+    pub(crate) policy_dry_run: bool,
+    pub(crate) admission_windows: Vec<AdmissionWindowConfig>,
+}
+
+/// Decides whether new pledges may start onboarding.
+/// Pledges that were already admitted, including pledges re-enrolling for a new LDevID, are never turned away.
+/// In a policy dry run, pledges arriving outside of the admission windows are only logged.
+#[derive(Debug)]
+pub(crate) struct Admission {
+    windows: Vec<AdmissionWindow>,
+    window_configs: Vec<AdmissionWindowConfig>,
+    maintenance_mode: AtomicBool,
+    dry_run: bool,
+}
+
+impl Admission {
+    pub(crate) fn new(config: &RegistrarConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            windows: config.admission_windows.iter().map(AdmissionWindow::parse).collect::<anyhow::Result<_>>()?,
+            window_configs: config.admission_windows.clone(),
+            maintenance_mode: AtomicBool::new(config.maintenance_mode),
+            dry_run: config.policy_dry_run,
+        })
+    }
+
+    pub(crate) fn set_maintenance_mode(&self, enabled: bool) {
+        event!(target: "Registrar::Admission", Level::WARN, "Maintenance mode {}", if enabled { "enabled" } else { "disabled" });
+        self.maintenance_mode.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Returns the reason new pledges are rejected at the given time, `None` if onboarding is open
+    pub(crate) fn closed_reason(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        if self.maintenance_mode.load(Ordering::SeqCst) {
+            return Some("registrar is in maintenance mode");
+        }
+
+        self.outside_windows(now).filter(|_| !self.dry_run)
+    }
+
+    fn outside_windows(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        (!self.windows.is_empty() && !self.windows.iter().any(|window| window.contains(now))).then_some("outside of the admission windows")
+    }
+
+    pub(crate) fn enforce(&self, serial_number: &str, admitted: bool) -> Result<(), ServerError> {
+        if admitted {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        if self.dry_run {
+            if let Some(reason) = self.outside_windows(now) {
+                event!(target: "Registrar::Admission", Level::WARN, "Dry run: new pledge {} would be rejected: {}", serial_number, reason);
+            }
+        }
+
+        match self.closed_reason(now) {
+            Some(reason) => {
+                event!(target: "Registrar::Admission", Level::INFO, "Rejected new pledge {}: {}", serial_number, reason);
+                Err(ServerError::OnboardingClosed {
+                    serial_number: serial_number.to_string(),
+                    reason: reason.to_string(),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn status(&self) -> AdmissionStatus {
+        AdmissionStatus {
+            maintenance_mode: self.maintenance_mode.load(Ordering::SeqCst),
+            open: self.closed_reason(Utc::now()).is_none(),
+            policy_dry_run: self.dry_run,
+            admission_windows: self.window_configs.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn admission(windows: Vec<AdmissionWindowConfig>) -> Admission {
+        Admission::new(&RegistrarConfig {
+            admission_windows: windows,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn window(days: &[&str], start: &str, end: &str) -> AdmissionWindowConfig {
+        AdmissionWindowConfig {
+            days: days.iter().map(|day| day.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_admission_windows() {
+        // 2024-07-01 is a Monday
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2024, 7, day, hour, 30, 0).unwrap();
+
+        let office_hours = admission(vec![window(&["mon", "tue"], "08:00", "18:00")]);
+        assert_eq!(office_hours.closed_reason(at(1, 9)), None);
+        assert!(office_hours.closed_reason(at(1, 18)).is_some());
+        assert!(office_hours.closed_reason(at(3, 9)).is_some());
+
+        let night_shift = admission(vec![window(&["fri"], "22:00", "06:00")]);
+        assert_eq!(night_shift.closed_reason(at(5, 23)), None);
+        assert_eq!(night_shift.closed_reason(at(6, 5)), None);
+        assert!(night_shift.closed_reason(at(5, 5)).is_some());
+
+        assert_eq!(admission(vec![]).closed_reason(at(6, 3)), None);
+        assert!(Admission::new(&RegistrarConfig {
+            admission_windows: vec![window(&["someday"], "08:00", "18:00")],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_maintenance_mode_only_rejects_new_pledges() {
+        let admission = admission(vec![]);
+        assert!(admission.enforce("00-D0-E5-F2-00-02", false).is_ok());
+
+        admission.set_maintenance_mode(true);
+        assert!(matches!(
+            admission.enforce("00-D0-E5-F2-00-02", false),
+            Err(ServerError::OnboardingClosed { .. })
+        ));
+        assert!(admission.enforce("00-D0-E5-F2-00-02", true).is_ok());
+
+        admission.set_maintenance_mode(false);
+        assert!(admission.status().open);
+    }
+
+    #[test]
+    fn test_dry_run_only_logs_closed_windows() {
+        // a window that never contains the current time
+        let now = Utc::now().time();
+        let start = (now + chrono::Duration::hours(2)).format("%H:%M").to_string();
+        let end = (now + chrono::Duration::hours(3)).format("%H:%M").to_string();
+        let admission = Admission::new(&RegistrarConfig {
+            admission_windows: vec![window(&[], &start, &end)],
+            policy_dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(admission.enforce("00-D0-E5-F2-00-02", false).is_ok());
+        assert!(admission.status().open);
+
+        // the maintenance mode is not part of the dry run
+        admission.set_maintenance_mode(true);
+        assert!(admission.enforce("00-D0-E5-F2-00-02", false).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+//! CA of the registrar that issues LDevIDs
+//!
+//! Handlers only see [`CertificateAuthority`], which takes and returns DER. The local CA signs with `ca_key`:
+//! [`OpensslCa`] is the default, [`RcgenCa`] issues the same certificates in pure Rust with rcgen and loads its CA
+//! without openssl. [`EstCa`] and [`CmpCa`] forward the CSRs to an upstream CA instead, the registrar
+//! then only acts as RA and has no CA key of its own.
+
+use std::fmt;
+use std::time::{Duration as StdDuration, Instant};
+
+use brski_client::EstClient;
+use cli::config::UpstreamCaConfig;
+use common::{asn1, server_error::ServerError};
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rcgen::{CertificateParams, CertificateSigningRequestParams, IsCa, KeyPair, KeyUsagePurpose, SanType, SerialNumber};
+use reqwest::header::{CONTENT_TYPE, ACCEPT};
+use serde::Deserialize;
+use ssh_key::rand_core::{OsRng, RngCore};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::RwLock;
+use x509_cert::ext::pkix::CrlReason;
+
+use crate::cmp::{CmpResponse, RaIdentity, Transaction, PKIXCMP};
+use crate::sign_cert::{mk_ca_signed_cert, LDEVID_DNS_NAMES};
+
+/// CA certificates of an upstream CA are fetched again after this time
+const UPSTREAM_CACERTS_MAX_AGE: StdDuration = StdDuration::from_secs(3600);
+
+/// The reason codes of RFC 5280 Section 5.3.1 that apply to LDevIDs
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RevocationReason {
+    #[default]
+    Unspecified,
+    KeyCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl From<RevocationReason> for CrlReason {
+    fn from(reason: RevocationReason) -> Self {
+        match reason {
+            RevocationReason::Unspecified => Self::Unspecified,
+            RevocationReason::KeyCompromise => Self::KeyCompromise,
+            RevocationReason::AffiliationChanged => Self::AffiliationChanged,
+            RevocationReason::Superseded => Self::Superseded,
+            RevocationReason::CessationOfOperation => Self::CessationOfOperation,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub(crate) trait CertificateAuthority: fmt::Debug + Send + Sync {
+    /// Issues the LDevID for the subject and key of the DER encoded PKCS#10 `csr`, valid for `validity_days`, and
+    /// returns it DER encoded. The CSR has to pass [`crate::sign_cert::check_csr`] first. An upstream CA decides
+    /// the validity itself.
+    async fn issue(&self, csr: &[u8], validity_days: u32) -> Result<Vec<u8>, ServerError>;
+
+    /// DER encoded CA certificates that LDevIDs chain to, for `/cacerts` and `/wrappedcacerts`
+    async fn cacerts(&self) -> Result<Vec<Vec<u8>>, ServerError>;
+
+    /// Revokes the DER encoded LDevID `certificate`
+    async fn revoke(&self, certificate: &[u8], reason: RevocationReason) -> Result<(), ServerError>;
+}
+
+fn no_revocation(backend: &str) -> ServerError {
+    ServerError::BadRequestWithReason(format!("LDevIDs issued by the {} CA can not be revoked through the registrar", backend))
+}
+
+#[derive(Debug)]
+pub(crate) struct OpensslCa {
+    certificate: X509,
+    key: PKey<Private>,
+}
+
+impl OpensslCa {
+    pub(crate) fn new(certificate: X509, key: PKey<Private>) -> Self {
+        Self { certificate, key }
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateAuthority for OpensslCa {
+    async fn issue(&self, csr: &[u8], validity_days: u32) -> Result<Vec<u8>, ServerError> {
+        let csr = asn1::certification_request(csr).map_err(|err| ServerError::BadRequestWithReason(format!("malformed CSR: {}", err)))?;
+        Ok(mk_ca_signed_cert(&self.certificate, &self.key, &csr, validity_days)?.to_der()?)
+    }
+
+    async fn cacerts(&self) -> Result<Vec<Vec<u8>>, ServerError> {
+        Ok(vec![self.certificate.to_der()?])
+    }
+
+    /// The local CA publishes no CRL
+    async fn revoke(&self, _certificate: &[u8], _reason: RevocationReason) -> Result<(), ServerError> {
+        Err(no_revocation("local"))
+    }
+}
+
+pub(crate) struct RcgenCa {
+    /// DER encoded CA certificate as configured
+    certificate: Vec<u8>,
+    /// The CA certificate as rcgen sees it, only its subject and key identifier end up in issued certificates
+    issuer: rcgen::Certificate,
+    key: KeyPair,
+}
+
+impl fmt::Debug for RcgenCa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RcgenCa").field("issuer", &self.issuer.params().distinguished_name).finish_non_exhaustive()
+    }
+}
+
+impl RcgenCa {
+    /// `certificate` is the PEM encoded CA certificate and `key` its PEM encoded P-256 key, SEC1 or PKCS#8
+    pub(crate) fn from_pem(certificate: &[u8], key: &str) -> anyhow::Result<Self> {
+        let (_, certificate) = x509_parser::pem::parse_x509_pem(certificate).map_err(|err| anyhow::anyhow!("CA certificate: {}", err))?;
+        let key = p256::SecretKey::from_sec1_pem(key)
+            .or_else(|_| p256::SecretKey::from_pkcs8_pem(key))
+            .map_err(|_| anyhow::anyhow!("CA key is no P-256 key"))?;
+        Self::new(&certificate.contents, key.to_pkcs8_der()?.as_bytes())
+    }
+
+    /// `certificate` is the DER encoded CA certificate and `key` its PKCS#8 DER encoded ECDSA key
+    pub(crate) fn new(certificate: &[u8], key: &[u8]) -> anyhow::Result<Self> {
+        let key = KeyPair::try_from(key)?;
+        let issuer = CertificateParams::from_ca_cert_der(&certificate.to_vec().into())?.self_signed(&key)?;
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(certificate).map_err(|err| anyhow::anyhow!("CA certificate: {}", err))?;
+        // LDevIDs carry it as authority key identifier, like with the openssl backend
+        if !parsed.extensions().iter().any(|extension| extension.oid == x509_parser::oid_registry::OID_X509_EXT_SUBJECT_KEY_IDENTIFIER) {
+            anyhow::bail!("CA certificate has no SubjectKeyIdentifier");
+        }
+
+        // rcgen writes the issuer name from the subject it parsed, which has to encode to the same bytes to chain
+        let subject = |der: &[u8]| {
+            x509_parser::parse_x509_certificate(der)
+                .map(|(_, certificate)| certificate.subject().as_raw().to_vec())
+                .map_err(|err| anyhow::anyhow!("CA certificate: {}", err))
+        };
+        if subject(certificate)? != subject(issuer.der())? {
+            anyhow::bail!("rcgen can not reproduce the subject of the CA certificate");
+        }
+
+        Ok(Self {
+            certificate: certificate.to_vec(),
+            issuer,
+            key,
+        })
+    }
+
+    /// Whether the DER encoded `certificate` is signed by the CA key
+    pub(crate) fn issued(&self, certificate: &[u8]) -> anyhow::Result<bool> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate).map_err(|err| anyhow::anyhow!("{}", err))?;
+        let (_, ca) = x509_parser::parse_x509_certificate(&self.certificate).map_err(|err| anyhow::anyhow!("{}", err))?;
+        Ok(certificate.verify_signature(Some(ca.public_key())).is_ok())
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateAuthority for RcgenCa {
+    async fn issue(&self, csr: &[u8], validity_days: u32) -> Result<Vec<u8>, ServerError> {
+        let rcgen_error = |err: rcgen::Error| ServerError::BadResponse(format!("rcgen: {}", err));
+
+        let mut csr = CertificateSigningRequestParams::from_der(&csr.to_vec().into()).map_err(rcgen_error)?;
+
+        // as for openssl, 159 random bits
+        let mut serial_number = [0u8; 20];
+        OsRng.fill_bytes(&mut serial_number);
+        serial_number[0] &= 0x7f;
+
+        let now = OffsetDateTime::now_utc();
+        let params = &mut csr.params;
+        params.serial_number = Some(SerialNumber::from_slice(&serial_number));
+        params.not_before = now;
+        params.not_after = now + Duration::days(validity_days.into());
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::ContentCommitment,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        // like openssl, extensions requested in the CSR are not copied
+        params.extended_key_usages.clear();
+        params.subject_alt_names = LDEVID_DNS_NAMES
+            .iter()
+            .map(|name| Ok(SanType::DnsName((*name).try_into()?)))
+            .collect::<Result<_, rcgen::Error>>()
+            .map_err(rcgen_error)?;
+        params.use_authority_key_identifier_extension = true;
+
+        Ok(csr.signed_by(&self.issuer, &self.key).map_err(rcgen_error)?.der().to_vec())
+    }
+
+    async fn cacerts(&self) -> Result<Vec<Vec<u8>>, ServerError> {
+        Ok(vec![self.certificate.clone()])
+    }
+
+    /// The local CA publishes no CRL
+    async fn revoke(&self, _certificate: &[u8], _reason: RevocationReason) -> Result<(), ServerError> {
+        Err(no_revocation("local"))
+    }
+}
+
+/// CA certificates of an upstream CA, fetched on first use and again after [`UPSTREAM_CACERTS_MAX_AGE`]
+#[derive(Debug, Default)]
+struct CachedCacerts(RwLock<Option<(Instant, Vec<Vec<u8>>)>>);
+
+impl CachedCacerts {
+    async fn get<F>(&self, fetch: F) -> Result<Vec<Vec<u8>>, ServerError>
+    where
+        F: std::future::Future<Output = Result<Vec<Vec<u8>>, ServerError>>,
+    {
+        if let Some((fetched, certificates)) = &*self.0.read().await {
+            if fetched.elapsed() < UPSTREAM_CACERTS_MAX_AGE {
+                return Ok(certificates.clone());
+            }
+        }
+
+        let certificates = fetch.await?;
+        if certificates.is_empty() {
+            return Err(ServerError::BadResponse("Upstream CA returned no CA certificates".to_string()));
+        }
+        *self.0.write().await = Some((Instant::now(), certificates.clone()));
+        Ok(certificates)
+    }
+}
+
+/// HTTP client for the upstream CA, trusting `tls_trust_anchors` besides the system roots
+fn upstream_client(config: &UpstreamCaConfig) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    for anchor in &config.tls_trust_anchors {
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&std::fs::read(anchor.relative())?)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Forwards `/simpleenroll` to an upstream EST server (RFC 7030)
+#[derive(Debug)]
+pub(crate) struct EstCa {
+    client: EstClient,
+    cacerts: CachedCacerts,
+}
+
+impl EstCa {
+    pub(crate) fn new(config: &UpstreamCaConfig) -> anyhow::Result<Self> {
+        let url = config.url.clone().ok_or(anyhow::anyhow!("upstream_ca url is not set"))?;
+        let mut client = EstClient::new(upstream_client(config)?, url);
+        if let Some(username) = &config.username {
+            client = client.with_basic_auth(username, config.password.clone());
+        }
+        Ok(Self {
+            client,
+            cacerts: CachedCacerts::default(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateAuthority for EstCa {
+    async fn issue(&self, csr: &[u8], _validity_days: u32) -> Result<Vec<u8>, ServerError> {
+        Ok(self.client.simple_enroll(csr).await?.to_der()?)
+    }
+
+    async fn cacerts(&self) -> Result<Vec<Vec<u8>>, ServerError> {
+        self.cacerts
+            .get(async {
+                let certificates = self.client.ca_certs().await?;
+                Ok(certificates.iter().map(|certificate| certificate.to_der()).collect::<Result<_, _>>()?)
+            })
+            .await
+    }
+
+    /// EST has no revocation operation
+    async fn revoke(&self, _certificate: &[u8], _reason: RevocationReason) -> Result<(), ServerError> {
+        Err(no_revocation("EST"))
+    }
+}
+
+/// Forwards the CSRs to an upstream CMP server as lightweight CMP (RFC 9483) over HTTP (RFC 6712)
+#[derive(Debug)]
+pub(crate) struct CmpCa {
+    client: reqwest::Client,
+    url: String,
+    identity: RaIdentity,
+    server_certificate: X509,
+    cacerts: CachedCacerts,
+}
+
+impl CmpCa {
+    pub(crate) fn new(config: &UpstreamCaConfig, identity: RaIdentity) -> anyhow::Result<Self> {
+        let url = config.url.clone().ok_or(anyhow::anyhow!("upstream_ca url is not set"))?;
+        let server_certificate = config
+            .cmp_server_certificate
+            .as_ref()
+            .ok_or(anyhow::anyhow!("upstream_ca cmp_server_certificate is not set"))?;
+        Ok(Self {
+            client: upstream_client(config)?,
+            url,
+            identity,
+            server_certificate: X509::from_pem(&std::fs::read(server_certificate.relative())?)?,
+            cacerts: CachedCacerts::default(),
+        })
+    }
+
+    /// Posts a request of `transaction` and verifies the response. CMP errors may come with any HTTP status.
+    async fn exchange(&self, transaction: &mut Transaction<'_>, request: Vec<u8>) -> Result<CmpResponse, ServerError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, PKIXCMP)
+            .header(ACCEPT, PKIXCMP)
+            .body(request)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let content_type = response.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        if !content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(PKIXCMP) {
+            return Err(ServerError::BadResponse(format!("CMP server answered with {} and no CMP message", status)));
+        }
+
+        transaction.response(&response.bytes().await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl CertificateAuthority for CmpCa {
+    async fn issue(&self, csr: &[u8], _validity_days: u32) -> Result<Vec<u8>, ServerError> {
+        let mut transaction = Transaction::new(&self.identity, &self.server_certificate);
+
+        let request = transaction.p10cr(csr)?;
+        let CmpResponse::Certificate { certificate, cert_req_id, implicit_confirm } =
+            self.exchange(&mut transaction, request).await?
+        else {
+            return Err(ServerError::BadResponse("CMP server did not answer p10cr with a certificate".to_string()));
+        };
+
+        // the server did not grant implicit confirmation, the certificate only counts once confirmed
+        if !implicit_confirm {
+            let request = transaction.cert_conf(&certificate, cert_req_id)?;
+            if self.exchange(&mut transaction, request).await? != CmpResponse::Confirmed {
+                return Err(ServerError::BadResponse("CMP server did not answer certConf with pkiConf".to_string()));
+            }
+        }
+
+        Ok(certificate)
+    }
+
+    async fn cacerts(&self) -> Result<Vec<Vec<u8>>, ServerError> {
+        self.cacerts
+            .get(async {
+                let mut transaction = Transaction::new(&self.identity, &self.server_certificate);
+                let request = transaction.genm_ca_certs()?;
+                match self.exchange(&mut transaction, request).await? {
+                    CmpResponse::CaCertificates(certificates) => Ok(certificates),
+                    _ => Err(ServerError::BadResponse("CMP server did not answer genm with caCerts".to_string())),
+                }
+            })
+            .await
+    }
+
+    async fn revoke(&self, certificate: &[u8], reason: RevocationReason) -> Result<(), ServerError> {
+        let mut transaction = Transaction::new(&self.identity, &self.server_certificate);
+        let request = transaction.rr(certificate, reason.into())?;
+        match self.exchange(&mut transaction, request).await? {
+            CmpResponse::Revoked => Ok(()),
+            _ => Err(ServerError::BadResponse("CMP server did not answer rr with rp".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use brski_prm_artifacts::{digest::message_digest, ietf_voucher::serial_number};
+    use std::sync::Arc;
+
+    use axum::{
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            HeaderMap, StatusCode,
+        },
+        routing::{get, post},
+        Router,
+    };
+    use brski_prm_artifacts::content_type::{PKCS7, PKCS7_CERTS_ONLY};
+    use openssl::{asn1::Asn1Type, nid::Nid, pkey::PKeyRef, x509::X509ReqBuilder};
+
+    use super::*;
+    use common::asn1::encode_certs_only;
+
+    fn csr(key: &PKeyRef<Private>, serial_number: &str) -> Vec<u8> {
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid_with_type(Nid::SERIALNUMBER, serial_number, Asn1Type::UTF8STRING).unwrap();
+
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_version(0).unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, message_digest(key)).unwrap();
+        builder.build().to_der().unwrap()
+    }
+
+    // loaded from PEM like parse_config does
+    fn rcgen_ca(certs: &example_certs::OpensslTestCerts) -> RcgenCa {
+        let (ca_certificate, ca_key) = &certs.registrar_ca;
+        let key = String::from_utf8(ca_key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        RcgenCa::from_pem(&ca_certificate.to_pem().unwrap(), &key).unwrap()
+    }
+
+    #[test]
+    fn test_rcgen_ca_checks_issued_certificates() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs_for_serial("00-D0-E5-F2-00-02").into();
+        let ca = rcgen_ca(&certs);
+
+        assert!(ca.issued(&certs.registrar.0.to_der().unwrap()).unwrap());
+        assert!(!ca.issued(&certs.pledge.0.to_der().unwrap()).unwrap());
+    }
+
+    fn authorities(certs: &example_certs::OpensslTestCerts) -> Vec<Box<dyn CertificateAuthority>> {
+        let (ca_certificate, ca_key) = &certs.registrar_ca;
+        vec![
+            Box::new(OpensslCa::new(ca_certificate.clone(), ca_key.clone())),
+            Box::new(rcgen_ca(certs)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_backends_issue_equivalent_ldevids() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs_for_serial("Gerät-製造-0002").into();
+        let (ca_certificate, _) = &certs.registrar_ca;
+        let (_, pledge_key) = &certs.pledge;
+        let csr = csr(pledge_key, "Gerät-製造-0002");
+
+        for authority in authorities(&certs) {
+            let ldevid = X509::from_der(&authority.issue(&csr, 30).await.unwrap()).unwrap();
+
+            assert!(ldevid.verify(&ca_certificate.public_key().unwrap()).unwrap(), "{:?}", authority);
+            assert!(ldevid.public_key().unwrap().public_eq(pledge_key));
+            assert_eq!(ldevid.issuer_name().to_der().unwrap(), ca_certificate.subject_name().to_der().unwrap());
+            assert_eq!(
+                ldevid.authority_key_id().unwrap().as_slice(),
+                ca_certificate.subject_key_id().unwrap().as_slice()
+            );
+            assert_eq!(serial_number::from_name(ldevid.subject_name()).unwrap(), "Gerät-製造-0002");
+
+            let dns_names: Vec<_> =
+                ldevid.subject_alt_names().unwrap().iter().filter_map(|name| name.dnsname().map(str::to_string)).collect();
+            assert_eq!(dns_names, LDEVID_DNS_NAMES);
+            let validity = ldevid.not_before().diff(ldevid.not_after()).unwrap();
+            assert_eq!(validity.days, 30);
+
+            let again = X509::from_der(&authority.issue(&csr, 30).await.unwrap()).unwrap();
+            assert_ne!(ldevid.serial_number().to_bn().unwrap(), again.serial_number().to_bn().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_tampered_csr() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (_, pledge_key) = &certs.pledge;
+        let mut csr = csr(pledge_key, "00-D0-E5-F2-00-02");
+        let last = csr.len() - 1;
+        csr[last] ^= 0x01;
+
+        // openssl leaves the signature check to check_csr, rcgen checks it itself
+        let [_, rcgen] = <[_; 2]>::try_from(authorities(&certs)).unwrap();
+        assert!(rcgen.issue(&csr, 30).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forwards_to_upstream_est() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (ca_certificate, ca_key) = &certs.registrar_ca;
+        let (_, pledge_key) = &certs.pledge;
+        let upstream = Arc::new(OpensslCa::new(ca_certificate.clone(), ca_key.clone()));
+
+        let enroll = upstream.clone();
+        let app = Router::new()
+            .route(
+                "/est/cacerts",
+                get(move || {
+                    let upstream = upstream.clone();
+                    async move {
+                        let certificates: Vec<_> =
+                            upstream.cacerts().await.unwrap().iter().map(|der| X509::from_der(der).unwrap()).collect();
+                        ([(CONTENT_TYPE, PKCS7)], openssl::base64::encode_block(&encode_certs_only(&certificates).unwrap()))
+                    }
+                }),
+            )
+            .route(
+                "/est/simpleenroll",
+                post(move |headers: HeaderMap, body: String| {
+                    let enroll = enroll.clone();
+                    async move {
+                        if headers.get(AUTHORIZATION).is_none() {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        let csr = openssl::base64::decode_block(&body.split_whitespace().collect::<String>()).unwrap();
+                        let ldevid = X509::from_der(&enroll.issue(&csr, 30).await.unwrap()).unwrap();
+                        let body = openssl::base64::encode_block(&encode_certs_only(&[ldevid]).unwrap());
+                        Ok(([(CONTENT_TYPE, PKCS7_CERTS_ONLY)], body))
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let authority = EstCa::new(&UpstreamCaConfig {
+            url: Some(format!("http://{}/est", address)),
+            username: Some("registrar".to_string()),
+            password: Some("secret".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let ldevid = X509::from_der(&authority.issue(&csr(pledge_key, "00-D0-E5-F2-00-02"), 30).await.unwrap()).unwrap();
+        assert!(ldevid.verify(&ca_certificate.public_key().unwrap()).unwrap());
+        assert_eq!(authority.cacerts().await.unwrap(), vec![ca_certificate.to_der().unwrap()]);
+        assert!(authority.revoke(&ldevid.to_der().unwrap(), RevocationReason::KeyCompromise).await.is_err());
+    }
+}
@@ -0,0 +1,571 @@
+//! Debug capture of the artifacts exchanged with pledges, for interop troubleshooting with third-party pledges.
+//!
+//! With `[registrar.capture]` enabled, [`record_exchange`] wraps the BRSKI and EST endpoints and records the headers,
+//! the encoded bodies and the decoded JWS claims of every request and its response. Claims are decoded without
+//! verifying the signature, so artifacts the registrar rejected show up as well. Exchanges are grouped into sessions
+//! per pledge serial-number, and a voucher request starts a new session. Requests without a serial-number, like
+//! `/cacerts`, belong to the last pledge seen from the same peer.
+//!
+//! Without `encryption_key`, every exchange is a JSON file in a directory per session. With it, the exchanges of a
+//! session are appended to a bundle of compact JWEs, one per line, that only the operator can decrypt.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE},
+        HeaderMap,
+    },
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use cli::config::CaptureConfig;
+use common::{limits::Artifact, server_error::ServerError, util::decode_jws_claims};
+use josekit::jwe::{JweEncrypter, JweHeader, ECDH_ES_A256KW, RSA_OAEP_256};
+use openssl::pkey::{Id, PKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::{event, Level};
+
+/// Session of the exchanges that can not be attributed to a pledge
+const UNIDENTIFIED: &str = "unidentified";
+const BUNDLE_EXTENSION: &str = "jwe";
+/// Upper bound of peers whose last pledge is remembered
+const MAX_PEERS: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Exchange {
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) peer: Option<SocketAddr>,
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) status: u16,
+    pub(crate) request: Message,
+    pub(crate) response: Message,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Message {
+    /// Credentials are redacted
+    pub(crate) headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body: Option<String>,
+    /// Bodies that are not UTF-8
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body_base64: Option<String>,
+    /// Protected headers and payload of a JWS body, not verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) claims: Option<Value>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CaptureSession {
+    pub(crate) session: String,
+    pub(crate) encrypted: bool,
+    pub(crate) exchanges: usize,
+    pub(crate) updated: Option<DateTime<Utc>>,
+}
+
+pub(crate) enum Download {
+    Exchanges(Vec<Exchange>),
+    Bundle(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    /// Current session per pledge serial-number
+    sessions: HashMap<String, String>,
+    /// Exchanges per session
+    exchanges: HashMap<String, usize>,
+    /// Last pledge serial-number per peer
+    peers: HashMap<IpAddr, String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Capture {
+    directory: PathBuf,
+    encrypter: Option<Box<dyn JweEncrypter>>,
+    max_exchanges: usize,
+    /// Largest body limit of the routes. Requests announcing a larger body are passed on without capturing them,
+    /// the body limit of the route rejects them anyway, larger bodies without a `Content-Length` are rejected here.
+    max_body: usize,
+    recording: Mutex<Recording>,
+}
+
+impl Capture {
+    pub(crate) fn new(config: &CaptureConfig, max_body: usize) -> anyhow::Result<Self> {
+        let directory = config.directory.relative();
+        std::fs::create_dir_all(&directory)?;
+
+        let encrypter = match &config.encryption_key {
+            Some(path) => Some(encrypter(&std::fs::read(path.relative())?)?),
+            None => None,
+        };
+
+        event!(
+            Level::WARN,
+            "Capturing the artifacts exchanged with pledges to {:?}{}",
+            directory,
+            if encrypter.is_some() { ", encrypted" } else { "" }
+        );
+
+        Ok(Self {
+            directory,
+            encrypter,
+            max_exchanges: config.max_exchanges,
+            max_body,
+            recording: Mutex::new(Recording::default()),
+        })
+    }
+
+    pub(crate) async fn record(&self, exchange: &Exchange) -> anyhow::Result<()> {
+        let serial_number = find_serial_number(&exchange.request).or_else(|| find_serial_number(&exchange.response));
+        let peer = exchange.peer.map(|peer| peer.ip());
+
+        // held until written, so the exchanges of a session are numbered in order
+        let mut recording = self.recording.lock().await;
+
+        let serial_number = match (serial_number, peer) {
+            (Some(serial_number), Some(peer)) => {
+                if recording.peers.len() >= MAX_PEERS {
+                    recording.peers.clear();
+                }
+                recording.peers.insert(peer, serial_number.clone());
+                Some(serial_number)
+            }
+            (Some(serial_number), None) => Some(serial_number),
+            (None, Some(peer)) => recording.peers.get(&peer).cloned(),
+            (None, None) => None,
+        };
+
+        let session = match serial_number {
+            Some(serial_number) => {
+                let starts_session = exchange.path.ends_with("/requestvoucher");
+                match recording.sessions.get(&serial_number) {
+                    Some(session) if !starts_session => session.clone(),
+                    _ => {
+                        let session = format!("{}-{}", sanitize(&serial_number), exchange.timestamp.format("%Y%m%dT%H%M%SZ"));
+                        recording.sessions.insert(serial_number, session.clone());
+                        session
+                    }
+                }
+            }
+            None => UNIDENTIFIED.to_string(),
+        };
+
+        let recorded = match recording.exchanges.get(&session) {
+            Some(recorded) => *recorded,
+            // continues the numbering of a session written before a restart
+            None => self.stored_exchanges(&session).await?,
+        };
+        if recorded >= self.max_exchanges {
+            event!(Level::DEBUG, "Capture session {} is full, dropping {} {}", session, exchange.method, exchange.path);
+            return Ok(());
+        }
+        recording.exchanges.insert(session.clone(), recorded + 1);
+
+        self.write(&session, recorded + 1, exchange).await
+    }
+
+    async fn write(&self, session: &str, sequence: usize, exchange: &Exchange) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(exchange)?;
+
+        match &self.encrypter {
+            None => {
+                let directory = self.directory.join(session);
+                tokio::fs::create_dir_all(&directory).await?;
+                let endpoint = sanitize(exchange.path.rsplit('/').next().unwrap_or_default());
+                let name = format!("{:03}-{}-{}.json", sequence, exchange.method, endpoint);
+                tokio::fs::write(directory.join(name), json).await?;
+            }
+            Some(encrypter) => {
+                let mut header = JweHeader::new();
+                header.set_content_encryption("A256GCM");
+                header.set_content_type("json");
+                let jwe = josekit::jwe::serialize_compact(&json, &header, encrypter.as_ref())?;
+                let mut bundle = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.bundle_path(session))
+                    .await?;
+                bundle.write_all(format!("{}\n", jwe).as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn stored_exchanges(&self, session: &str) -> anyhow::Result<usize> {
+        let stored = match &self.encrypter {
+            None => match tokio::fs::read_dir(self.directory.join(session)).await {
+                Ok(mut entries) => {
+                    let mut count = 0;
+                    while entries.next_entry().await?.is_some() {
+                        count += 1;
+                    }
+                    count
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err.into()),
+            },
+            Some(_) => match tokio::fs::read(self.bundle_path(session)).await {
+                Ok(bundle) => bundle.iter().filter(|byte| **byte == b'\n').count(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err.into()),
+            },
+        };
+        Ok(stored)
+    }
+
+    fn bundle_path(&self, session: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", session, BUNDLE_EXTENSION))
+    }
+
+    /// Sessions on disk, including those of earlier runs, most recent first
+    pub(crate) async fn sessions(&self) -> anyhow::Result<Vec<CaptureSession>> {
+        let mut sessions = vec![];
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let metadata = entry.metadata().await?;
+            let updated = metadata.modified().ok().map(DateTime::<Utc>::from);
+
+            let (session, encrypted) = if metadata.is_dir() {
+                (entry.file_name().to_string_lossy().to_string(), false)
+            } else if path.extension().is_some_and(|extension| extension == BUNDLE_EXTENSION) {
+                let stem = path.file_stem().unwrap_or_default();
+                (stem.to_string_lossy().to_string(), true)
+            } else {
+                continue;
+            };
+
+            // only the sessions of the current mode, the exchanges of the other one can not be counted
+            if encrypted != self.encrypter.is_some() {
+                continue;
+            }
+            let exchanges = self.stored_exchanges(&session).await?;
+            sessions.push(CaptureSession { session, encrypted, exchanges, updated });
+        }
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated));
+        Ok(sessions)
+    }
+
+    /// All exchanges of a session, or its encrypted bundle as is. `None` if there is no such session.
+    pub(crate) async fn download(&self, session: &str) -> anyhow::Result<Option<Download>> {
+        if session.is_empty() || sanitize(session) != session {
+            return Ok(None);
+        }
+
+        if self.encrypter.is_some() {
+            return match tokio::fs::read(self.bundle_path(session)).await {
+                Ok(bundle) => Ok(Some(Download::Bundle(bundle))),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            };
+        }
+
+        let mut entries = match tokio::fs::read_dir(self.directory.join(session)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut paths = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        // file names start with the sequence number
+        paths.sort();
+
+        let mut exchanges = vec![];
+        for path in paths {
+            exchanges.push(serde_json::from_slice(&tokio::fs::read(path).await?)?);
+        }
+        Ok(Some(Download::Exchanges(exchanges)))
+    }
+}
+
+/// Middleware recording the request and the response. Failing to capture is logged, it never fails the request.
+pub(crate) async fn record_exchange(
+    State(capture): State<Arc<Capture>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > capture.max_body) {
+        return Ok(next.run(request).await);
+    }
+
+    let timestamp = Utc::now();
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| *peer);
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, capture.max_body).await.map_err(|err| {
+        match err.into_inner().downcast_ref::<http_body_util::LengthLimitError>() {
+            Some(_) => ServerError::PayloadTooLarge {
+                artifact: Artifact::Other.to_string(),
+                limit: capture.max_body,
+            },
+            None => ServerError::BadRequestWithReason("Request body could not be read".to_string()),
+        }
+    })?;
+    let request = Message::new(&parts.headers, &body);
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|err| ServerError::InternalError { source: anyhow!("Response body could not be read: {}", err) })?;
+
+    let exchange = Exchange {
+        timestamp,
+        peer,
+        method,
+        path,
+        status: parts.status.as_u16(),
+        request,
+        response: Message::new(&parts.headers, &body),
+    };
+    if let Err(err) = capture.record(&exchange).await {
+        event!(Level::WARN, "Could not capture {} {}: {}", exchange.method, exchange.path, err);
+    }
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+impl Message {
+    fn new(headers: &HeaderMap, body: &Bytes) -> Self {
+        let mut captured: BTreeMap<String, String> = BTreeMap::new();
+        for (name, value) in headers {
+            let value = if [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE].contains(name) {
+                "<redacted>".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).to_string()
+            };
+            captured
+                .entry(name.to_string())
+                .and_modify(|values| {
+                    values.push_str(", ");
+                    values.push_str(&value);
+                })
+                .or_insert(value);
+        }
+
+        let mut message = Message {
+            headers: captured,
+            ..Default::default()
+        };
+        if body.is_empty() {
+            return message;
+        }
+        match std::str::from_utf8(body) {
+            Ok(text) => {
+                message.claims = decode_jws_claims(text);
+                message.body = Some(text.to_string());
+            }
+            Err(_) => message.body_base64 = Some(openssl::base64::encode_block(body)),
+        }
+        message
+    }
+}
+
+fn find_serial_number(message: &Message) -> Option<String> {
+    fn find(value: &Value) -> Option<String> {
+        match value {
+            Value::Object(object) => match object.get("serial-number") {
+                Some(Value::String(serial_number)) => Some(serial_number.clone()),
+                _ => object.values().find_map(find),
+            },
+            Value::Array(values) => values.iter().find_map(find),
+            _ => None,
+        }
+    }
+    find(message.claims.as_ref()?.get("payload")?)
+}
+
+/// serial-numbers may contain any UTF-8 characters, session and file names only ASCII letters, digits, `-` and `_`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn encrypter(pem: &[u8]) -> anyhow::Result<Box<dyn JweEncrypter>> {
+    let key = PKey::public_key_from_pem(pem)?;
+    match key.id() {
+        Id::EC | Id::X25519 => Ok(Box::new(ECDH_ES_A256KW.encrypter_from_pem(pem)?)),
+        Id::RSA => Ok(Box::new(RSA_OAEP_256.encrypter_from_pem(pem)?)),
+        _ => Err(anyhow!("capture encryption_key must be an EC or RSA public key")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header::CONTENT_TYPE, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use openssl::{ec::EcKey, nid::Nid};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn compact_jws(payload: Value) -> String {
+        let encode = |value: Value| {
+            openssl::base64::encode_block(value.to_string().as_bytes())
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        format!("{}.{}.c2lnbmF0dXJl", encode(json!({ "alg": "ES256" })), encode(payload))
+    }
+
+    fn capture(directory: &std::path::Path, encryption_key: Option<PathBuf>) -> Arc<Capture> {
+        let config = CaptureConfig {
+            enabled: true,
+            directory: directory.to_path_buf().into(),
+            encryption_key: encryption_key.map(Into::into),
+            max_exchanges: 2,
+        };
+        Arc::new(Capture::new(&config, 64 * 1024).unwrap())
+    }
+
+    async fn send(capture: &Arc<Capture>, path: &str, body: String) -> Response {
+        let app = Router::new()
+            .route("/.well-known/brski/:endpoint", post(|| async { (StatusCode::OK, "answer") }))
+            .layer(middleware::from_fn_with_state(capture.clone(), record_exchange));
+        let mut request = Request::post(path)
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::from(body))
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 4000))));
+        app.oneshot(request).await.unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("open-brski-capture-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[tokio::test]
+    async fn test_records_sessions_per_pledge() {
+        let directory = temp_dir("plain");
+        let capture = capture(&directory, None);
+
+        let pvr = compact_jws(json!({ "ietf-voucher-request:voucher": { "serial-number": "00-D0-E5/F2", "nonce": "n" } }));
+        let response = send(&capture, "/.well-known/brski/requestvoucher", pvr.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // no serial-number, attributed to the last pledge of the peer
+        send(&capture, "/.well-known/brski/wrappedcacerts", String::new()).await;
+        // dropped, the session is full
+        send(&capture, "/.well-known/brski/enrollstatus", String::new()).await;
+        // an unknown peer without a serial-number
+        let response = Router::new()
+            .route("/cacerts", post(|| async { "certs" }))
+            .layer(middleware::from_fn_with_state(capture.clone(), record_exchange))
+            .oneshot(Request::post("/cacerts").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let sessions = capture.sessions().await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        let pledge = sessions.iter().find(|session| session.session.starts_with("00-D0-E5_F2-")).unwrap();
+        assert_eq!(pledge.exchanges, 2);
+        assert!(!pledge.encrypted);
+
+        let Some(Download::Exchanges(exchanges)) = capture.download(&pledge.session).await.unwrap() else {
+            panic!("no exchanges of {}", pledge.session);
+        };
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].path, "/.well-known/brski/requestvoucher");
+        assert_eq!(exchanges[0].peer, Some(SocketAddr::from(([192, 0, 2, 1], 4000))));
+        assert_eq!(exchanges[0].request.body.as_deref(), Some(pvr.as_str()));
+        assert_eq!(exchanges[0].request.headers["authorization"], "<redacted>");
+        let claims = exchanges[0].request.claims.as_ref().unwrap();
+        assert_eq!(claims["protected"][0]["alg"], "ES256");
+        assert_eq!(claims["payload"]["ietf-voucher-request:voucher"]["nonce"], "n");
+        assert_eq!(exchanges[0].response.body.as_deref(), Some("answer"));
+        assert_eq!(exchanges[1].path, "/.well-known/brski/wrappedcacerts");
+
+        assert!(capture.download("unidentified").await.unwrap().is_some());
+        assert!(capture.download("../etc").await.unwrap().is_none());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_bodies() {
+        let directory = temp_dir("oversized");
+        let config = CaptureConfig {
+            enabled: true,
+            directory: directory.clone().into(),
+            encryption_key: None,
+            max_exchanges: 2,
+        };
+        let capture = Arc::new(Capture::new(&config, 8).unwrap());
+
+        // without a Content-Length the body is only noticed to be too large while reading it
+        let response = send(&capture, "/.well-known/brski/requestvoucher", "123456789".to_string()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "application/problem+json");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], 413);
+        assert_eq!(problem["limit"], 8);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_encrypts_bundles_to_the_operator() {
+        let directory = temp_dir("encrypted");
+        let key = EcKey::generate(&openssl::ec::EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap();
+        let key = PKey::from_ec_key(key).unwrap();
+        std::fs::create_dir_all(&directory).unwrap();
+        let public_key = directory.join("operator.pem");
+        std::fs::write(&public_key, key.public_key_to_pem().unwrap()).unwrap();
+        let capture = capture(&directory.join("captures"), Some(public_key));
+
+        let pvr = compact_jws(json!({ "ietf-voucher-request:voucher": { "serial-number": "pledge-1" } }));
+        send(&capture, "/.well-known/brski/requestvoucher", pvr.clone()).await;
+
+        let sessions = capture.sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].encrypted);
+        let Some(Download::Bundle(bundle)) = capture.download(&sessions[0].session).await.unwrap() else {
+            panic!("no bundle");
+        };
+        let bundle = String::from_utf8(bundle).unwrap();
+        assert!(!bundle.contains("pledge-1"));
+
+        let decrypter = ECDH_ES_A256KW.decrypter_from_pem(key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let (exchange, _) = josekit::jwe::deserialize_compact(bundle.lines().next().unwrap(), &decrypter).unwrap();
+        let exchange: Exchange = serde_json::from_slice(&exchange).unwrap();
+        assert_eq!(exchange.request.body.as_deref(), Some(pvr.as_str()));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
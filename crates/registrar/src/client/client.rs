@@ -1,65 +1,53 @@
-use brski_prm_artifacts::ietf_voucher::VoucherRequest;
+use brski_client::MasaClient;
+use cli::config::ProxyConfig;
 use brski_prm_artifacts::issued_voucher::IssuedVoucherJWS;
-use reqwest::header::CONTENT_TYPE;
-use brski_prm_artifacts::content_type::JWS_VOUCHER;
 use brski_prm_artifacts::rvr::RVR_JWS;
+use brski_prm_artifacts::status::voucher::response::vStatus_JWS;
 use common::server_error::ServerError;
 use tracing::{event, Level};
 
-use crate::parsed_config::{ParsedConfig};
+use reqwest::{Client, NoProxy, Proxy};
 
-use reqwest::header::ACCEPT;
-use reqwest::Client;
+/// Client for the requests to the MASA, going through the configured egress proxy
+pub fn masa_client(proxy: &ProxyConfig) -> anyhow::Result<Client> {
+    let Some(url) = &proxy.url else {
+        return Ok(Client::new());
+    };
 
-#[tracing::instrument(target = "Registrar", skip(parsed_config, rvr, client))]
+    event!(Level::INFO, "Reaching the MASA through proxy {}", url);
+    let mut masa_proxy = Proxy::all(url)?.no_proxy(NoProxy::from_string(&proxy.no_proxy.join(",")));
+    if let Some(username) = &proxy.username {
+        masa_proxy = masa_proxy.basic_auth(username, proxy.password.as_deref().unwrap_or_default());
+    }
+
+    Ok(Client::builder().proxy(masa_proxy).build()?)
+}
+
+#[tracing::instrument(target = "Registrar", skip(rvr, client))]
 pub async fn get_voucher_from_masa(
-    parsed_config: &ParsedConfig,
+    masa_url: &str,
     rvr: RVR_JWS,
     client: &Client,
 ) -> Result<IssuedVoucherJWS, ServerError> {
 
     event!(Level::DEBUG, "PVR to be sent: {:#?}", rvr);
 
-    let requestvoucher_masa_url = format!(
-        "{}/.well-known/brski/requestvoucher",
-        parsed_config.config.masa_url
-    );
-
-    event!(Level::INFO, "Sending RVR to MASA at {:?}", requestvoucher_masa_url);
-
-    let data = rvr.try_encoded_data()?;
-
-
-    let response = client
-        .post(requestvoucher_masa_url)
-        .header(ACCEPT, JWS_VOUCHER)
-        .header(CONTENT_TYPE, JWS_VOUCHER)
-        .body(data)
-        .send()
-        .await?;
-
-    event!(Level::INFO, "Received response from MASA");
+    let jws = MasaClient::new(client.clone(), masa_url).request_voucher(rvr).await?;
 
-    if !response.status().is_success() {
-        return Err(ServerError::BadResponse(format!("Sending RVR to MASA failed with Status: {}", response.status().to_string())))
-    }
-
-    if response.headers().get(CONTENT_TYPE).is_none() {
-        return Err(ServerError::BadResponse("No content type in response".to_string()))
-    }
-
-    if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
-        if content_type != JWS_VOUCHER {
-            return Err(ServerError::BadResponse("Wrong content type in response".to_string()))
-        }
-    }
+    event!(Level::INFO, "Received issued voucher from MASA");
+    event!(Level::DEBUG, "Issued Voucher Response{:?}", jws);
 
-    let response_data = response.text().await?;
-    event!(Level::INFO, "Received issued voucher response data");
-    event!(Level::DEBUG, "Issued Voucher Response{:?}", response_data);
+    Ok(jws)
+}
 
-    event!(Level::INFO, "Parsing response data as JWS voucher");
-    let jws = IssuedVoucherJWS::Encoded(response_data);
+#[tracing::instrument(target = "Registrar", skip(voucher_status, client))]
+pub async fn send_voucher_status_to_masa(
+    masa_url: &str,
+    voucher_status: vStatus_JWS,
+    client: &Client,
+) -> Result<(), ServerError> {
+    MasaClient::new(client.clone(), masa_url).voucher_status(voucher_status).await?;
 
-    Ok(jws)
-}
\ No newline at end of file
+    event!(Level::INFO, "Relayed voucher status to MASA");
+    Ok(())
+}
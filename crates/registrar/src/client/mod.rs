@@ -1,5 +1,7 @@
 
-
+#[allow(clippy::module_inception)]
 mod client;
+mod parent;
 
-pub use client::get_voucher_from_masa;
\ No newline at end of file
+pub use client::{get_voucher_from_masa, masa_client, send_voucher_status_to_masa};
+pub use parent::{fetch_wrappedcacerts, forward_enroll_request, forward_voucher_request};
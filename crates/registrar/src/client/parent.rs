@@ -0,0 +1,52 @@
+use brski_client::RegistrarClient;
+use brski_prm_artifacts::jws::JWS;
+use common::server_error::ServerError;
+use reqwest::Client;
+use tracing::{event, Level};
+
+use crate::parsed_config::ParsedConfig;
+
+/// Client for the parent registrar, artifacts are only forwarded after they were validated locally
+fn parent(parsed_config: &ParsedConfig, client: &Client) -> Result<RegistrarClient, ServerError> {
+    let parent_url = parsed_config
+        .config
+        .parent_registrar_url
+        .as_ref()
+        .ok_or(ServerError::BadResponse("No parent registrar configured".to_string()))?;
+
+    Ok(RegistrarClient::new(client.clone(), parent_url))
+}
+
+#[tracing::instrument(target = "Registrar", skip(parsed_config, client, pvr))]
+pub async fn forward_voucher_request(
+    parsed_config: &ParsedConfig,
+    client: &Client,
+    pvr: String,
+) -> Result<String, ServerError> {
+    event!(Level::INFO, "Forwarding requestvoucher to parent registrar");
+
+    let issued_voucher = parent(parsed_config, client)?.request_voucher(JWS::Encoded(pvr)).await?;
+
+    Ok(issued_voucher.try_encoded_data()?)
+}
+
+#[tracing::instrument(target = "Registrar", skip(parsed_config, client, per))]
+pub async fn forward_enroll_request(
+    parsed_config: &ParsedConfig,
+    client: &Client,
+    per: String,
+) -> Result<openssl::x509::X509, ServerError> {
+    event!(Level::INFO, "Forwarding requestenroll to parent registrar");
+
+    Ok(parent(parsed_config, client)?.request_enroll(JWS::Encoded(per)).await?)
+}
+
+#[tracing::instrument(target = "Registrar", skip(parsed_config, client))]
+pub async fn fetch_wrappedcacerts(
+    parsed_config: &ParsedConfig,
+    client: &Client,
+) -> Result<String, ServerError> {
+    event!(Level::INFO, "Fetching wrappedcacerts from parent registrar");
+
+    Ok(parent(parsed_config, client)?.wrapped_ca_certs().await?.try_encoded_data()?)
+}
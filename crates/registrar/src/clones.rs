@@ -0,0 +1,266 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+/// Clone alerts kept at most, a new alert replaces the oldest one
+const MAX_ALERTS: usize = 1024;
+
+/// Prefix lengths by which peers are grouped into networks
+const IPV4_NETWORK_PREFIX: u8 = 24;
+const IPV6_NETWORK_PREFIX: u8 = 64;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub(crate) enum CloneEvidence {
+    /// The pledge asked for vouchers from several networks at the same time
+    ConcurrentNetworks { networks: Vec<String> },
+    /// A second onboarding of the pledge started before the first one was enrolled and was enrolled as well
+    OverlappingOnboarding {
+        first_started: DateTime<Utc>,
+        first_enrolled: DateTime<Utc>,
+        second_started: DateTime<Utc>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CloneAlert {
+    pub(crate) serial_number: String,
+    pub(crate) evidence: CloneEvidence,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// Onboarding of a pledge from its voucher request to its LDevID
+#[derive(Debug)]
+struct Attempt {
+    /// Hash of the voucher request that started the attempt
+    request_hash: String,
+    network: Option<String>,
+    started: DateTime<Utc>,
+    enrolled: Option<DateTime<Utc>>,
+}
+
+/// Tracks the usage of IDevIDs to flag pledges whose IDevID was copied to another device.
+/// Only onboarding attempts within the window are kept, a window of 0 disables the detection.
+#[derive(Debug)]
+pub(crate) struct CloneDetector {
+    window: Duration,
+    attempts: RwLock<HashMap<String, Vec<Attempt>>>,
+    alerts: RwLock<Vec<CloneAlert>>,
+}
+
+impl CloneDetector {
+    pub(crate) fn new(window_secs: u64) -> Self {
+        Self {
+            window: Duration::seconds(window_secs as i64),
+            attempts: RwLock::new(HashMap::new()),
+            alerts: RwLock::new(vec![]),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.window > Duration::zero()
+    }
+
+    /// Starts an onboarding attempt of the pledge, `peer` is the address the voucher request came from.
+    /// A voucher request sent again, e.g. a pledge polling for its held voucher, continues the attempt it started.
+    pub(crate) async fn voucher_requested(&self, serial_number: &str, request_hash: &str, peer: Option<IpAddr>) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let now = Utc::now();
+        let network = peer.map(network);
+        let evidence = {
+            let mut attempts = self.attempts.write().await;
+            let attempts = attempts.entry(serial_number.to_string()).or_default();
+            attempts.retain(|attempt| now - attempt.enrolled.unwrap_or(attempt.started) <= self.window);
+            if attempts.iter().any(|attempt| attempt.request_hash == request_hash) {
+                return;
+            }
+
+            let mut networks: BTreeSet<String> = attempts.iter().filter_map(|attempt| attempt.network.clone()).collect();
+            // only raised once, when a new network shows up
+            let is_new_network = network
+                .as_ref()
+                .is_some_and(|network| !networks.is_empty() && !networks.contains(network));
+
+            attempts.push(Attempt {
+                request_hash: request_hash.to_string(),
+                network: network.clone(),
+                started: now,
+                enrolled: None,
+            });
+
+            is_new_network.then(|| {
+                networks.extend(network);
+                CloneEvidence::ConcurrentNetworks {
+                    networks: networks.into_iter().collect(),
+                }
+            })
+        };
+
+        if let Some(evidence) = evidence {
+            self.raise(serial_number, evidence, now).await;
+        }
+    }
+
+    /// Completes the oldest open onboarding attempt of the pledge
+    pub(crate) async fn enrolled(&self, serial_number: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let now = Utc::now();
+        let evidence = {
+            let mut attempts = self.attempts.write().await;
+            let Some(attempts) = attempts.get_mut(serial_number) else {
+                return;
+            };
+            let Some(current) = attempts.iter().position(|attempt| attempt.enrolled.is_none()) else {
+                return;
+            };
+            attempts[current].enrolled = Some(now);
+
+            // sequential onboardings, e.g. after a factory reset, never overlap
+            attempts
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != current)
+                .filter_map(|(_, attempt)| Some((attempt.started, attempt.enrolled?)))
+                .find(|(_, enrolled)| attempts[current].started < *enrolled)
+                .map(|(first_started, first_enrolled)| CloneEvidence::OverlappingOnboarding {
+                    first_started,
+                    first_enrolled,
+                    second_started: attempts[current].started,
+                })
+        };
+
+        if let Some(evidence) = evidence {
+            self.raise(serial_number, evidence, now).await;
+        }
+    }
+
+    async fn raise(&self, serial_number: &str, evidence: CloneEvidence, timestamp: DateTime<Utc>) {
+        event!(target: "Registrar::Security", Level::WARN, "IDevID of {} may be cloned: {:?}", serial_number, evidence);
+
+        let mut alerts = self.alerts.write().await;
+        if alerts.len() >= MAX_ALERTS {
+            alerts.remove(0);
+        }
+        alerts.push(CloneAlert {
+            serial_number: serial_number.to_string(),
+            evidence,
+            timestamp,
+        });
+    }
+
+    /// The kept clone alerts, the most recent one first
+    pub(crate) async fn alerts(&self) -> Vec<CloneAlert> {
+        self.alerts.read().await.iter().rev().cloned().collect()
+    }
+}
+
+/// Network of a peer, e.g. `192.0.2.0/24`
+fn network(peer: IpAddr) -> String {
+    match peer.to_canonical() {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX << (32 - IPV4_NETWORK_PREFIX);
+            format!("{}/{}", std::net::Ipv4Addr::from(u32::from(address) & mask), IPV4_NETWORK_PREFIX)
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX << (128 - IPV6_NETWORK_PREFIX);
+            format!("{}/{}", std::net::Ipv6Addr::from(u128::from(address) & mask), IPV6_NETWORK_PREFIX)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_network() {
+        assert_eq!(network("192.0.2.17".parse().unwrap()), "192.0.2.0/24");
+        assert_eq!(network("::ffff:192.0.2.17".parse().unwrap()), "192.0.2.0/24");
+        assert_eq!(network("2001:db8::1:2".parse().unwrap()), "2001:db8::/64");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_networks() {
+        let clones = CloneDetector::new(3600);
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-1", peer("192.0.2.10")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-2", peer("192.0.2.11")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-03", "request-3", peer("198.51.100.10")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-4", None).await;
+        assert!(clones.alerts().await.is_empty());
+
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-5", peer("198.51.100.10")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-6", peer("198.51.100.11")).await;
+
+        let alerts = clones.alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].serial_number, "00-D0-E5-F2-00-02");
+        assert_eq!(
+            alerts[0].evidence,
+            CloneEvidence::ConcurrentNetworks {
+                networks: vec!["192.0.2.0/24".to_string(), "198.51.100.0/24".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_onboarding() {
+        let clones = CloneDetector::new(3600);
+
+        // re-onboarding after the first one finished is not suspicious
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-7", peer("192.0.2.10")).await;
+        clones.enrolled("00-D0-E5-F2-00-02").await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-8", peer("192.0.2.10")).await;
+        clones.enrolled("00-D0-E5-F2-00-02").await;
+        // nor is re-enrolling without a voucher request
+        clones.enrolled("00-D0-E5-F2-00-02").await;
+        assert!(clones.alerts().await.is_empty());
+
+        clones.voucher_requested("00-D0-E5-F2-00-03", "request-9", peer("192.0.2.10")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-03", "request-10", peer("192.0.2.10")).await;
+        clones.enrolled("00-D0-E5-F2-00-03").await;
+        assert!(clones.alerts().await.is_empty());
+        clones.enrolled("00-D0-E5-F2-00-03").await;
+
+        let alerts = clones.alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].serial_number, "00-D0-E5-F2-00-03");
+        assert!(matches!(alerts[0].evidence, CloneEvidence::OverlappingOnboarding { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_polling_held_voucher_continues_attempt() {
+        let clones = CloneDetector::new(3600);
+
+        // the pledge polls with the same voucher request until the MASA answered, then enrolls once
+        for _ in 0..3 {
+            clones.voucher_requested("00-D0-E5-F2-00-02", "held-request", peer("192.0.2.10")).await;
+        }
+        clones.enrolled("00-D0-E5-F2-00-02").await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "next-request", peer("192.0.2.10")).await;
+        clones.enrolled("00-D0-E5-F2-00-02").await;
+        assert!(clones.alerts().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let clones = CloneDetector::new(0);
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-11", peer("192.0.2.10")).await;
+        clones.voucher_requested("00-D0-E5-F2-00-02", "request-12", peer("198.51.100.10")).await;
+        assert!(clones.alerts().await.is_empty());
+    }
+}
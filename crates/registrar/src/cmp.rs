@@ -0,0 +1,535 @@
+//! Lightweight CMP (RFC 9483) messages the registrar exchanges as RA with an upstream CA: `p10cr` to enroll,
+//! `certConf` to confirm, `rr` to revoke and `genm` for the CA certificates.
+//!
+//! Requests are protected with a signature of the registrar key and carry the registrar certificate in
+//! `extraCerts`. Responses have to be signed with the key of the configured CMP server certificate, answer the
+//! transaction ID and the nonce of the request before them, and are only decoded after that.
+
+use std::time::SystemTime;
+
+use cmpv2::{
+    body::PkiBody,
+    certified_key_pair::CertOrEncCert,
+    gen::InfoTypeAndValue,
+    header::{PkiFreeText, PkiHeader, Pvno},
+    message::{PkiMessage, ProtectedPart},
+    rev::RevDetails,
+    status::{CertStatus, PkiStatus, PkiStatusInfo},
+};
+use common::server_error::ServerError;
+use crmf::request::CertTemplate;
+use der::{
+    asn1::{Any, BitString, GeneralizedTime, Int, Null, ObjectIdentifier, OctetString},
+    Decode, Encode, Sequence,
+};
+use openssl::{
+    hash::{hash, MessageDigest},
+    pkey::{PKey, Private},
+    sign::{Signer, Verifier},
+    x509::{X509NameRef, X509},
+};
+use ssh_key::rand_core::{OsRng, RngCore};
+use x509_cert::{
+    ext::{
+        pkix::{name::GeneralName, CrlReason},
+        Extension,
+    },
+    name::Name,
+    request::CertReq,
+    spki::AlgorithmIdentifierOwned,
+    Certificate,
+};
+
+/// Media type of CMP messages over HTTP, RFC 6712 Section 3.4
+pub(crate) const PKIXCMP: &str = "application/pkixcmp";
+
+const ID_IT_IMPLICIT_CONFIRM: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.4.13");
+const ID_IT_CA_CERTS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.4.17");
+const ID_CE_CRL_REASONS: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.21");
+
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+const ECDSA_WITH_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.4");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+
+fn bad_response(reason: impl std::fmt::Display) -> ServerError {
+    ServerError::BadResponse(format!("CMP: {}", reason))
+}
+
+fn der_error(err: der::Error) -> ServerError {
+    bad_response(err)
+}
+
+/// Hash of a signature algorithm the upstream CA may protect its responses with
+fn protection_digest(algorithm: &ObjectIdentifier) -> Option<MessageDigest> {
+    match *algorithm {
+        ECDSA_WITH_SHA256 | SHA256_WITH_RSA => Some(MessageDigest::sha256()),
+        ECDSA_WITH_SHA384 | SHA384_WITH_RSA => Some(MessageDigest::sha384()),
+        ECDSA_WITH_SHA512 | SHA512_WITH_RSA => Some(MessageDigest::sha512()),
+        _ => None,
+    }
+}
+
+fn name(name: &X509NameRef) -> Result<Name, ServerError> {
+    Name::from_der(&name.to_der()?).map_err(der_error)
+}
+
+fn nonce() -> [u8; 16] {
+    let mut nonce = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Status, texts and failure bits of a rejection, for the error returned to the handler
+fn describe(status: &PkiStatusInfo) -> String {
+    let mut description = format!("{:?}", status.status);
+    if let Some(texts) = &status.status_string {
+        let texts: Vec<_> = texts.iter().map(|text| text.as_str()).collect();
+        description += &format!(" ({})", texts.join(", "));
+    }
+    if let Some(fail_info) = &status.fail_info {
+        description += &format!(" {:?}", fail_info);
+    }
+    description
+}
+
+fn accepted(status: &PkiStatusInfo) -> Result<(), ServerError> {
+    match status.status {
+        PkiStatus::Accepted | PkiStatus::GrantedWithMods => Ok(()),
+        // polling, RFC 9483 Section 4.4, is not supported
+        PkiStatus::Waiting => Err(bad_response("the CA asks to poll for the response, which is not supported")),
+        _ => Err(bad_response(format!("rejected with {}", describe(status)))),
+    }
+}
+
+/// [`cmpv2::status::ErrorMsgContent`] does not expose its fields
+#[derive(Sequence)]
+struct ErrorMessage<'a> {
+    pki_status_info: PkiStatusInfo<'a>,
+    error_code: Option<Int>,
+    error_details: Option<PkiFreeText<'a>>,
+}
+
+/// What a verified response of the upstream CA carries
+#[derive(Debug, PartialEq)]
+pub(crate) enum CmpResponse {
+    /// DER encoded certificate of a `cp`. Without implicit confirmation it has to be confirmed with `certConf`.
+    Certificate {
+        certificate: Vec<u8>,
+        cert_req_id: Int,
+        implicit_confirm: bool,
+    },
+    /// `rp` accepting the revocation
+    Revoked,
+    /// DER encoded certificates of the `caCerts` `genp`
+    CaCertificates(Vec<Vec<u8>>),
+    /// `pkiConf` after `certConf`
+    Confirmed,
+}
+
+/// The registrar as RA, the identity its requests are signed with
+#[derive(Debug, Clone)]
+pub(crate) struct RaIdentity {
+    pub(crate) certificate: X509,
+    pub(crate) key: PKey<Private>,
+}
+
+/// One CMP transaction, RFC 9483 Section 3.1. Its messages share the transaction ID, every response has to answer
+/// the sender nonce of the request before it and the next request answers the sender nonce of that response.
+pub(crate) struct Transaction<'a> {
+    identity: &'a RaIdentity,
+    server_certificate: &'a X509,
+    transaction_id: [u8; 16],
+    /// Sender nonce of the request that awaits its response, a response is only accepted once
+    sender_nonce: Option<[u8; 16]>,
+    recip_nonce: Option<OctetString>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(identity: &'a RaIdentity, server_certificate: &'a X509) -> Self {
+        Self {
+            identity,
+            server_certificate,
+            transaction_id: nonce(),
+            sender_nonce: None,
+            recip_nonce: None,
+        }
+    }
+
+    fn header(&mut self, implicit_confirm: bool) -> Result<PkiHeader<'static>, ServerError> {
+        let digest = brski_prm_artifacts::digest::message_digest(&self.identity.key);
+        let protection_alg = match digest.type_() {
+            nid if nid == MessageDigest::sha384().type_() => ECDSA_WITH_SHA384,
+            nid if nid == MessageDigest::sha512().type_() => ECDSA_WITH_SHA512,
+            _ => ECDSA_WITH_SHA256,
+        };
+        let sender_nonce = nonce();
+        self.sender_nonce = Some(sender_nonce);
+
+        Ok(PkiHeader {
+            pvno: Pvno::Cmp2000,
+            sender: GeneralName::DirectoryName(name(self.identity.certificate.subject_name())?),
+            recipient: GeneralName::DirectoryName(name(self.server_certificate.subject_name())?),
+            message_time: Some(GeneralizedTime::from_system_time(SystemTime::now()).map_err(der_error)?),
+            protection_alg: Some(AlgorithmIdentifierOwned {
+                oid: protection_alg,
+                parameters: None,
+            }),
+            sender_kid: self
+                .identity
+                .certificate
+                .subject_key_id()
+                .map(|id| OctetString::new(id.as_slice()))
+                .transpose()
+                .map_err(der_error)?,
+            recip_kid: None,
+            trans_id: Some(OctetString::new(self.transaction_id).map_err(der_error)?),
+            sender_nonce: Some(OctetString::new(sender_nonce).map_err(der_error)?),
+            recip_nonce: self.recip_nonce.clone(),
+            free_text: None,
+            general_info: implicit_confirm.then(|| {
+                vec![InfoTypeAndValue {
+                    oid: ID_IT_IMPLICIT_CONFIRM,
+                    value: Some(Any::from(Null)),
+                }]
+            }),
+        })
+    }
+
+    fn protect(&mut self, body: PkiBody<'static>, implicit_confirm: bool) -> Result<Vec<u8>, ServerError> {
+        let header = self.header(implicit_confirm)?;
+
+        let protected_part = ProtectedPart {
+            header: header.clone(),
+            body: body.clone(),
+        };
+        let digest = brski_prm_artifacts::digest::message_digest(&self.identity.key);
+        let mut signer = Signer::new(digest, &self.identity.key)?;
+        let signature = signer.sign_oneshot_to_vec(&protected_part.to_der().map_err(der_error)?)?;
+
+        let message = PkiMessage {
+            header,
+            body,
+            protection: Some(BitString::from_bytes(&signature).map_err(der_error)?),
+            extra_certs: Some(vec![Certificate::from_der(&self.identity.certificate.to_der()?).map_err(der_error)?]),
+        };
+        message.to_der().map_err(der_error)
+    }
+
+    /// `p10cr` for the DER encoded PKCS#10 `csr`, asking for implicit confirmation
+    pub(crate) fn p10cr(&mut self, csr: &[u8]) -> Result<Vec<u8>, ServerError> {
+        let csr = CertReq::from_der(csr).map_err(der_error)?;
+        self.protect(PkiBody::P10cr(csr), true)
+    }
+
+    /// `certConf` accepting the DER encoded `certificate` of the last response
+    pub(crate) fn cert_conf(&mut self, certificate: &[u8], cert_req_id: Int) -> Result<Vec<u8>, ServerError> {
+        // hashed with the hash of the certificate signature, RFC 4210 Section 5.3.18
+        let parsed = X509::from_der(certificate)?;
+        let digest = parsed
+            .signature_algorithm()
+            .object()
+            .nid()
+            .signature_algorithms()
+            .and_then(|algorithms| MessageDigest::from_nid(algorithms.digest))
+            .unwrap_or(MessageDigest::sha256());
+
+        let status = CertStatus {
+            cert_hash: OctetString::new(hash(digest, certificate)?.to_vec()).map_err(der_error)?,
+            cert_req_id,
+            status_info: None,
+        };
+        self.protect(PkiBody::CertConf(vec![status]), false)
+    }
+
+    /// `rr` for the DER encoded `certificate`, RFC 9483 Section 4.2
+    pub(crate) fn rr(&mut self, certificate: &[u8], reason: CrlReason) -> Result<Vec<u8>, ServerError> {
+        let certificate = Certificate::from_der(certificate).map_err(der_error)?;
+
+        let details = RevDetails {
+            cert_details: CertTemplate {
+                version: None,
+                serial_number: Some(certificate.tbs_certificate.serial_number),
+                signature: None,
+                issuer: Some(certificate.tbs_certificate.issuer),
+                validity: None,
+                subject: None,
+                subject_public_key_info: None,
+                issuer_unique_id: None,
+                subject_unique_id: None,
+                extensions: None,
+            },
+            crl_entry_details: Some(vec![Extension {
+                extn_id: ID_CE_CRL_REASONS,
+                critical: false,
+                extn_value: OctetString::new(reason.to_der().map_err(der_error)?).map_err(der_error)?,
+            }]),
+        };
+        self.protect(PkiBody::Rr(vec![details]), false)
+    }
+
+    /// `genm` asking for the CA certificates, RFC 9483 Section 4.3.1
+    pub(crate) fn genm_ca_certs(&mut self) -> Result<Vec<u8>, ServerError> {
+        let info = InfoTypeAndValue {
+            oid: ID_IT_CA_CERTS,
+            value: None,
+        };
+        self.protect(PkiBody::GenM(vec![info]), false)
+    }
+
+    /// Verifies the DER encoded response to the last request and returns what it carries
+    pub(crate) fn response(&mut self, der: &[u8]) -> Result<CmpResponse, ServerError> {
+        let message = PkiMessage::from_der(der).map_err(der_error)?;
+        let header = &message.header;
+
+        if header.trans_id.as_ref().map(OctetString::as_bytes) != Some(&self.transaction_id[..]) {
+            return Err(bad_response("response belongs to another transaction"));
+        }
+        let sender_nonce = self.sender_nonce.ok_or(bad_response("response without a pending request"))?;
+        if header.recip_nonce.as_ref().map(OctetString::as_bytes) != Some(&sender_nonce[..]) {
+            return Err(bad_response("response does not answer the nonce of the request"));
+        }
+
+        let protection = message.protection.as_ref().ok_or(bad_response("response is not protected"))?;
+        let digest = header
+            .protection_alg
+            .as_ref()
+            .and_then(|algorithm| protection_digest(&algorithm.oid))
+            .ok_or(bad_response("response is not protected with a supported signature algorithm"))?;
+        let protected_part = ProtectedPart {
+            header: header.clone(),
+            body: message.body.clone(),
+        };
+        let public_key = self.server_certificate.public_key()?;
+        let mut verifier = Verifier::new(digest, &public_key)?;
+        let signature = protection.as_bytes().ok_or(bad_response("protection has unused bits"))?;
+        if !verifier.verify_oneshot(signature, &protected_part.to_der().map_err(der_error)?)? {
+            return Err(bad_response("response is not signed by the CMP server certificate"));
+        }
+
+        self.sender_nonce = None;
+        self.recip_nonce = header.sender_nonce.clone();
+
+        match message.body {
+            PkiBody::Cp(reply) | PkiBody::Ip(reply) => {
+                let response = reply.response.into_iter().next().ok_or(bad_response("cp carries no response"))?;
+                accepted(&response.status)?;
+                let certificate = match response.certified_key_pair.map(|pair| pair.cert_or_enc_cert) {
+                    Some(CertOrEncCert::Certificate(certificate)) => certificate.to_der().map_err(der_error)?,
+                    Some(CertOrEncCert::EncryptedCert(_)) => return Err(bad_response("cp carries an encrypted certificate")),
+                    None => return Err(bad_response("cp carries no certificate")),
+                };
+                let implicit_confirm = header
+                    .general_info
+                    .iter()
+                    .flatten()
+                    .any(|info| info.oid == ID_IT_IMPLICIT_CONFIRM);
+                Ok(CmpResponse::Certificate {
+                    certificate,
+                    cert_req_id: response.cert_req_id,
+                    implicit_confirm,
+                })
+            }
+            PkiBody::Rp(reply) => {
+                accepted(reply.status.first().ok_or(bad_response("rp carries no status"))?)?;
+                Ok(CmpResponse::Revoked)
+            }
+            PkiBody::GenP(infos) => {
+                let certificates = match infos.into_iter().find(|info| info.oid == ID_IT_CA_CERTS) {
+                    Some(InfoTypeAndValue { value: Some(value), .. }) => value.decode_as::<Vec<Certificate>>().map_err(der_error)?,
+                    _ => vec![],
+                };
+                let certificates = certificates
+                    .iter()
+                    .map(|certificate| certificate.to_der().map_err(der_error))
+                    .collect::<Result<_, _>>()?;
+                Ok(CmpResponse::CaCertificates(certificates))
+            }
+            PkiBody::PkiConf(_) => Ok(CmpResponse::Confirmed),
+            PkiBody::Error(content) => {
+                let content = content.to_der().map_err(der_error)?;
+                let error = ErrorMessage::from_der(&content).map_err(der_error)?;
+                Err(bad_response(format!("error {}", describe(&error.pki_status_info))))
+            }
+            _ => Err(bad_response("unexpected response body")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cmpv2::{
+        certified_key_pair::CertifiedKeyPair,
+        response::{CertRepMessage, CertResponse},
+    };
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    fn identity(certs: &OpensslTestCerts) -> RaIdentity {
+        let (certificate, key) = &certs.registrar;
+        RaIdentity {
+            certificate: certificate.clone(),
+            key: key.clone(),
+        }
+    }
+
+    fn status(status: PkiStatus) -> PkiStatusInfo<'static> {
+        PkiStatusInfo {
+            status,
+            status_string: None,
+            fail_info: None,
+        }
+    }
+
+    /// Answers the last request of `transaction` as the CMP server with the `server` identity
+    fn respond(transaction: &Transaction, server: &RaIdentity, body: PkiBody<'static>) -> Vec<u8> {
+        let header = PkiHeader {
+            pvno: Pvno::Cmp2000,
+            sender: GeneralName::DirectoryName(name(server.certificate.subject_name()).unwrap()),
+            recipient: GeneralName::DirectoryName(name(transaction.identity.certificate.subject_name()).unwrap()),
+            message_time: None,
+            protection_alg: Some(AlgorithmIdentifierOwned {
+                oid: ECDSA_WITH_SHA256,
+                parameters: None,
+            }),
+            sender_kid: None,
+            recip_kid: None,
+            trans_id: Some(OctetString::new(transaction.transaction_id).unwrap()),
+            sender_nonce: Some(OctetString::new(nonce()).unwrap()),
+            recip_nonce: Some(OctetString::new(transaction.sender_nonce.unwrap()).unwrap()),
+            free_text: None,
+            general_info: None,
+        };
+        let protected_part = ProtectedPart {
+            header: header.clone(),
+            body: body.clone(),
+        };
+        let mut signer = Signer::new(MessageDigest::sha256(), &server.key).unwrap();
+        let signature = signer.sign_oneshot_to_vec(&protected_part.to_der().unwrap()).unwrap();
+
+        PkiMessage {
+            header,
+            body,
+            protection: Some(BitString::from_bytes(&signature).unwrap()),
+            extra_certs: None,
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    fn csr(certs: &OpensslTestCerts) -> Vec<u8> {
+        let (_, key) = &certs.pledge;
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("serialNumber", "00-D0-E5-F2-00-02").unwrap();
+        let mut builder = openssl::x509::X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build().to_der().unwrap()
+    }
+
+    #[test]
+    fn test_p10cr_is_signed_by_the_registrar() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let identity = identity(&certs);
+        let (server_certificate, _) = &certs.vendor;
+        let mut transaction = Transaction::new(&identity, server_certificate);
+
+        let request = transaction.p10cr(&csr(&certs)).unwrap();
+        let message = PkiMessage::from_der(&request).unwrap();
+        assert!(matches!(message.body, PkiBody::P10cr(_)));
+        assert_eq!(message.header.trans_id.as_ref().unwrap().as_bytes(), transaction.transaction_id);
+        assert!(message.header.general_info.unwrap().iter().any(|info| info.oid == ID_IT_IMPLICIT_CONFIRM));
+
+        let protected_part = ProtectedPart {
+            header: PkiMessage::from_der(&request).unwrap().header,
+            body: message.body,
+        };
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &identity.key).unwrap();
+        let signature = message.protection.unwrap();
+        assert!(verifier.verify_oneshot(signature.as_bytes().unwrap(), &protected_part.to_der().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_verifies_and_decodes_cp() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let identity = identity(&certs);
+        let (server_certificate, server_key) = &certs.vendor;
+        let server = RaIdentity {
+            certificate: server_certificate.clone(),
+            key: server_key.clone(),
+        };
+        let (ldevid, _) = &certs.pledge;
+        let cp = |certificate: &X509| {
+            PkiBody::Cp(CertRepMessage {
+                ca_pubs: None,
+                response: vec![CertResponse {
+                    cert_req_id: Int::new(&[0xff]).unwrap(),
+                    status: status(PkiStatus::Accepted),
+                    certified_key_pair: Some(CertifiedKeyPair {
+                        cert_or_enc_cert: CertOrEncCert::Certificate(Box::new(
+                            Certificate::from_der(&certificate.to_der().unwrap()).unwrap(),
+                        )),
+                        priv_key: None,
+                        publication_info: None,
+                    }),
+                    rsp_info: None,
+                }],
+            })
+        };
+
+        let mut transaction = Transaction::new(&identity, server_certificate);
+        transaction.p10cr(&csr(&certs)).unwrap();
+        let response = respond(&transaction, &server, cp(ldevid));
+        match transaction.response(&response).unwrap() {
+            CmpResponse::Certificate { certificate, implicit_confirm, .. } => {
+                assert_eq!(certificate, ldevid.to_der().unwrap());
+                assert!(!implicit_confirm);
+            }
+            response => panic!("unexpected {:?}", response),
+        }
+
+        // the response to a request that was not sent, or one signed by another key, is rejected
+        assert!(transaction.response(&response).is_err());
+        transaction.p10cr(&csr(&certs)).unwrap();
+        assert!(transaction.response(&respond(&transaction, &identity, cp(ldevid))).is_err());
+
+        let rejection = PkiBody::Rp(cmpv2::rev::RevRepContent {
+            status: vec![status(PkiStatus::Rejection)],
+            rev_certs: None,
+            crls: None,
+        });
+        transaction.rr(&ldevid.to_der().unwrap(), CrlReason::KeyCompromise).unwrap();
+        assert!(matches!(
+            transaction.response(&respond(&transaction, &server, rejection)),
+            Err(ServerError::BadResponse(reason)) if reason.contains("Rejection")
+        ));
+    }
+
+    #[test]
+    fn test_decodes_ca_certs() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let identity = identity(&certs);
+        let (server_certificate, server_key) = &certs.vendor;
+        let server = RaIdentity {
+            certificate: server_certificate.clone(),
+            key: server_key.clone(),
+        };
+        let (ca_certificate, _) = &certs.registrar_ca;
+
+        let mut transaction = Transaction::new(&identity, server_certificate);
+        transaction.genm_ca_certs().unwrap();
+        let ca_certs = vec![Certificate::from_der(&ca_certificate.to_der().unwrap()).unwrap()];
+        let genp = PkiBody::GenP(vec![InfoTypeAndValue {
+            oid: ID_IT_CA_CERTS,
+            value: Some(Any::encode_from(&ca_certs).unwrap()),
+        }]);
+        assert_eq!(
+            transaction.response(&respond(&transaction, &server, genp)).unwrap(),
+            CmpResponse::CaCertificates(vec![ca_certificate.to_der().unwrap()])
+        );
+    }
+}
@@ -0,0 +1,1011 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        Extensions, Method, Request, StatusCode,
+    },
+    Router,
+};
+use brski_prm_artifacts::content_type::{JOSE, JWS_VOUCHER, PKCS10};
+use cli::config::CoapsConfig;
+use common::coap::{self, code, Message, MessageType};
+use common::coap_block::{self, Block, BlockAssembler, BlockError, BLOCK1, BLOCK2};
+use openssl::{
+    ec::EcKey,
+    error::ErrorStack,
+    ex_data::Index,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+    ssl::{Ssl, SslContext, SslMethod, SslOptions, SslVerifyMode},
+    x509::{store::X509StoreBuilder, X509},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::UdpSocket,
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_openssl::SslStream;
+use tower::ServiceExt;
+use tracing::{event, Level};
+
+use crate::est::session_extensions;
+
+/// Concurrent DTLS sessions, datagrams of further peers are dropped until a session ends
+pub(crate) const MAX_SESSIONS: usize = 256;
+/// Datagrams queued per session while it handles a request
+pub(crate) const SESSION_QUEUE: usize = 16;
+pub(crate) const MAX_DATAGRAM: usize = 2048;
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest request body reassembled from Block1 blocks
+const MAX_REQUEST_BODY: usize = 64 * 1024;
+/// Largest response body of the HTTP endpoints
+const MAX_RESPONSE_BODY: usize = 1024 * 1024;
+
+/// A constrained resource and the HTTP endpoint handling it
+#[derive(Debug, PartialEq)]
+struct Resource {
+    method: Method,
+    path: &'static str,
+    /// Content-Format of request payloads, `None` for the JWS artifacts, which have no registered Content-Format
+    request_format: Option<u16>,
+    content_type: Option<&'static str>,
+    accept: Option<&'static str>,
+    response_format: Option<u16>,
+    /// EST over HTTP carries base64 encoded DER, EST-coaps plain DER, see RFC 9148 Section 4
+    base64: bool,
+}
+
+/// Resources by their short names, RFC 9148 Section 5 for EST and the constrained voucher draft for BRSKI.
+/// They are served below `/.well-known/est`, `/.well-known/brski` and the short `/est` and `/b`.
+fn resource(path: &str) -> Option<Resource> {
+    let (prefix, name) = path.rsplit_once('/')?;
+    if ![".well-known/est", ".well-known/brski", "est", "b"].contains(&prefix) {
+        return None;
+    }
+
+    let jws = |path, content_type, accept| Resource {
+        method: Method::POST,
+        path,
+        request_format: None,
+        content_type: Some(content_type),
+        accept,
+        response_format: None,
+        base64: false,
+    };
+    let est = |method, path, request_format, content_type, response_format| Resource {
+        method,
+        path,
+        request_format,
+        content_type,
+        accept: None,
+        response_format: Some(response_format),
+        base64: true,
+    };
+
+    Some(match name {
+        "rv" => jws("/.well-known/brski/requestvoucher", JWS_VOUCHER, Some(JWS_VOUCHER)),
+        "vs" => jws("/.well-known/brski/voucher_status", JOSE, None),
+        "es" => jws("/.well-known/brski/enrollstatus", JOSE, None),
+        "sen" => est(Method::POST, "/.well-known/est/simpleenroll", Some(coap::PKCS10), Some(PKCS10), coap::PKCS7_CERTS_ONLY),
+        "crts" => est(Method::GET, "/.well-known/est/cacerts", None, None, coap::PKCS7_CERTS_ONLY),
+        "att" => est(Method::GET, "/.well-known/est/csrattrs", None, None, coap::CSRATTRS),
+        _ => return None,
+    })
+}
+
+fn request_code(method: &Method) -> u8 {
+    if method == Method::GET {
+        code::GET
+    } else {
+        code::POST
+    }
+}
+
+/// Response code for an HTTP error status, RFC 8075 Section 7
+fn error_code(status: StatusCode) -> u8 {
+    match status {
+        StatusCode::BAD_REQUEST => code::BAD_REQUEST,
+        StatusCode::UNAUTHORIZED => code::UNAUTHORIZED,
+        StatusCode::FORBIDDEN => code::FORBIDDEN,
+        StatusCode::NOT_FOUND => code::NOT_FOUND,
+        StatusCode::METHOD_NOT_ALLOWED => code::METHOD_NOT_ALLOWED,
+        StatusCode::NOT_ACCEPTABLE => code::NOT_ACCEPTABLE,
+        StatusCode::CONFLICT => code::CONFLICT,
+        StatusCode::PAYLOAD_TOO_LARGE => code::REQUEST_ENTITY_TOO_LARGE,
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => code::UNSUPPORTED_CONTENT_FORMAT,
+        StatusCode::BAD_GATEWAY => code::BAD_GATEWAY,
+        StatusCode::SERVICE_UNAVAILABLE => code::SERVICE_UNAVAILABLE,
+        StatusCode::GATEWAY_TIMEOUT => code::GATEWAY_TIMEOUT,
+        status if status.is_client_error() => code::BAD_REQUEST,
+        _ => code::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Response body as sent in Block2 blocks
+#[derive(Debug)]
+struct Download {
+    code: u8,
+    format: Option<u16>,
+    body: Vec<u8>,
+}
+
+/// CoAP side of one DTLS or OSCORE session, requests are translated for the HTTP endpoints
+pub(crate) struct Exchange {
+    app: Router,
+    peer: SocketAddr,
+    /// Channel binding and IDevID of the session, handed to the handlers as request extensions
+    session: Extensions,
+    szx: u8,
+    uploads: HashMap<String, BlockAssembler>,
+    /// Responses larger than a block, kept for the requests of their further blocks
+    downloads: HashMap<String, Download>,
+    /// Last response to a confirmable request, sent again for retransmissions of the request
+    last: Option<(u16, Vec<u8>)>,
+}
+
+impl Exchange {
+    pub(crate) fn new(app: Router, peer: SocketAddr, session: Extensions, block_size: usize) -> Self {
+        Self {
+            app,
+            peer,
+            session,
+            szx: (block_size.trailing_zeros() - 4) as u8,
+            uploads: HashMap::new(),
+            downloads: HashMap::new(),
+            last: None,
+        }
+    }
+
+    async fn handle(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let request = match Message::decode(datagram) {
+            Ok(request) => request,
+            Err(err) => {
+                event!(Level::DEBUG, "Ignoring malformed CoAP message from {}: {}", self.peer, err);
+                return None;
+            }
+        };
+
+        if let Some((message_id, response)) = &self.last {
+            if request.message_type == MessageType::Confirmable && *message_id == request.message_id {
+                return Some(response.clone());
+            }
+        }
+
+        let response = match request.message_type {
+            MessageType::Acknowledgement | MessageType::Reset => return None,
+            // CoAP ping, RFC 7252 Section 4.3
+            _ if request.code == code::EMPTY => Message {
+                message_type: MessageType::Reset,
+                code: code::EMPTY,
+                message_id: request.message_id,
+                token: vec![],
+                options: vec![],
+                payload: vec![],
+            },
+            _ if !code::is_request(request.code) => return None,
+            _ => self.respond(&request).await,
+        }
+        .encode();
+
+        if request.message_type == MessageType::Confirmable {
+            self.last = Some((request.message_id, response.clone()));
+        }
+        Some(response)
+    }
+
+    /// Response to a request, its message type, ID and token are taken over
+    pub(crate) async fn respond(&mut self, request: &Message) -> Message {
+        let path = request.uri_path();
+        let Some(resource) = resource(&path) else {
+            return Message::response(request, code::NOT_FOUND);
+        };
+        if request.code != request_code(&resource.method) {
+            return Message::response(request, code::METHOD_NOT_ALLOWED);
+        }
+
+        let (Ok(block1), Ok(block2)) = (
+            request.option(BLOCK1).map(Block::decode).transpose(),
+            request.option(BLOCK2).map(Block::decode).transpose(),
+        ) else {
+            return Message::response(request, code::BAD_OPTION);
+        };
+
+        if let Some(block2) = block2.filter(|block| block.num > 0) {
+            return match self.downloads.get(&path) {
+                Some(download) => block_response(request, download, block2.num, block2.szx),
+                None => Message::response(request, code::BAD_REQUEST),
+            };
+        }
+
+        let body = match block1 {
+            None => request.payload.clone(),
+            Some(block1) => {
+                if block1.num == 0 {
+                    self.uploads.insert(path.clone(), BlockAssembler::new(MAX_REQUEST_BODY));
+                }
+                let pushed = match self.uploads.get_mut(&path) {
+                    Some(assembler) => assembler.push(block1, &request.payload),
+                    None => Err(BlockError::Incomplete { expected: 0, offset: block1.offset() }),
+                };
+                match pushed {
+                    Ok(Some(body)) => {
+                        self.uploads.remove(&path);
+                        body
+                    }
+                    Ok(None) => {
+                        let mut response = Message::response(request, code::CONTINUE);
+                        response.set_option(BLOCK1, block1.encode());
+                        return response;
+                    }
+                    Err(err) => {
+                        self.uploads.remove(&path);
+                        let code = match err {
+                            BlockError::Incomplete { .. } => code::REQUEST_ENTITY_INCOMPLETE,
+                            BlockError::TooLarge { .. } => code::REQUEST_ENTITY_TOO_LARGE,
+                            _ => code::BAD_REQUEST,
+                        };
+                        let mut response = Message::response(request, code);
+                        response.payload = err.to_string().into_bytes();
+                        return response;
+                    }
+                }
+            }
+        };
+
+        if request
+            .uint_option(coap::CONTENT_FORMAT)
+            .is_some_and(|format| Some(format) != resource.request_format.map(u32::from))
+        {
+            return Message::response(request, code::UNSUPPORTED_CONTENT_FORMAT);
+        }
+        if request
+            .uint_option(coap::ACCEPT)
+            .is_some_and(|format| Some(format) != resource.response_format.map(u32::from))
+        {
+            return Message::response(request, code::NOT_ACCEPTABLE);
+        }
+
+        let download = match self.forward(&resource, body).await {
+            Ok((status, body)) if status.is_success() => {
+                let body = match resource.base64 {
+                    true => decode_base64(&body),
+                    false => Some(body),
+                };
+                match body {
+                    Some(body) => Download {
+                        code: if resource.method == Method::GET { code::CONTENT } else { code::CHANGED },
+                        format: resource.response_format,
+                        body,
+                    },
+                    None => {
+                        event!(Level::ERROR, "{} answered with a body that is not base64", resource.path);
+                        return Message::response(request, code::INTERNAL_SERVER_ERROR);
+                    }
+                }
+            }
+            // the problem details are passed on as diagnostic payload
+            Ok((status, body)) => Download {
+                code: error_code(status),
+                format: None,
+                body,
+            },
+            Err(err) => {
+                event!(Level::ERROR, "Could not forward CoAP request for {}: {}", resource.path, err);
+                return Message::response(request, code::INTERNAL_SERVER_ERROR);
+            }
+        };
+
+        let szx = block2.map_or(self.szx, |block| block.szx.min(self.szx));
+        let mut response = block_response(request, &download, 0, szx);
+        if let Some(block1) = block1 {
+            response.set_option(BLOCK1, block1.encode());
+        }
+
+        if response.option(BLOCK2).is_some() {
+            self.downloads.insert(path, download);
+        } else {
+            self.downloads.remove(&path);
+        }
+        response
+    }
+
+    /// Hands the request to the HTTP endpoint of the resource
+    async fn forward(&self, resource: &Resource, body: Vec<u8>) -> anyhow::Result<(StatusCode, Vec<u8>)> {
+        let body = match resource.base64 && !body.is_empty() {
+            true => openssl::base64::encode_block(&body).into_bytes(),
+            false => body,
+        };
+
+        let mut builder = Request::builder().method(resource.method.clone()).uri(resource.path);
+        if let Some(content_type) = resource.content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        if let Some(accept) = resource.accept {
+            builder = builder.header(ACCEPT, accept);
+        }
+        let mut request = builder.body(Body::from(body))?;
+        // the handlers see the pledge like on the HTTP listener, e.g. for the clone detection
+        request.extensions_mut().insert(ConnectInfo(self.peer));
+        request.extensions_mut().extend(self.session.clone());
+
+        let response = self.app.clone().oneshot(request).await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), MAX_RESPONSE_BODY).await?;
+        Ok((status, body.to_vec()))
+    }
+}
+
+/// Response carrying block `num` of the body, or the whole body if it fits into the first block
+fn block_response(request: &Message, download: &Download, num: u32, szx: u8) -> Message {
+    let mut response = Message::response(request, download.code);
+    if let Some(format) = download.format {
+        response.set_uint_option(coap::CONTENT_FORMAT, u32::from(format));
+    }
+
+    if num == 0 && download.body.len() <= 1 << (szx + 4) {
+        response.payload = download.body.clone();
+        return response;
+    }
+
+    match coap_block::block(&download.body, num, szx) {
+        Ok((block, payload)) => {
+            response.set_option(BLOCK2, block.encode());
+            if num == 0 {
+                response.set_uint_option(coap::SIZE2, download.body.len() as u32);
+            }
+            response.payload = payload.to_vec();
+            response
+        }
+        Err(_) => Message::response(request, code::BAD_OPTION),
+    }
+}
+
+fn decode_base64(body: &[u8]) -> Option<Vec<u8>> {
+    let base64: String = String::from_utf8_lossy(body).chars().filter(|char| !char.is_ascii_whitespace()).collect();
+    openssl::base64::decode_block(&base64).ok()
+}
+
+/// Datagrams of one peer as a stream for OpenSSL, every read yields one datagram and every write sends one
+struct PeerDatagrams {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    /// Datagrams OpenSSL writes that are not sent to the peer
+    discard_writes: usize,
+}
+
+impl AsyncRead for PeerDatagrams {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.incoming.poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                let length = datagram.len().min(buf.remaining());
+                buf.put_slice(&datagram[..length]);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for PeerDatagrams {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.discard_writes > 0 {
+            self.discard_writes -= 1;
+            return Poll::Ready(Ok(buf.len()));
+        }
+        self.socket.poll_send_to(cx, buf, self.peer)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Unfragmented ClientHello alone in a handshake record of epoch 0, RFC 6347 Section 4.2.1.
+/// Only these are answered before a session exists, OpenSSL reassembles everything else.
+struct ClientHello<'a> {
+    datagram: &'a [u8],
+    /// Position of the cookie length byte
+    cookie_offset: usize,
+}
+
+/// Record header, handshake header and the client version and random of a ClientHello
+const RECORD_HEADER: usize = 13;
+const HANDSHAKE_HEADER: usize = 12;
+const HELLO_PREFIX: usize = 2 + 32;
+
+impl<'a> ClientHello<'a> {
+    fn parse(datagram: &'a [u8]) -> Option<Self> {
+        let body = RECORD_HEADER + HANDSHAKE_HEADER;
+        if datagram.len() <= body + HELLO_PREFIX || datagram[0] != 22 || datagram[3..5] != [0, 0] || datagram[13] != 1 {
+            return None;
+        }
+        let length = u24(&datagram[14..17]);
+        let record_length = usize::from(u16::from_be_bytes([datagram[11], datagram[12]]));
+        if u24(&datagram[19..22]) != 0 || u24(&datagram[22..25]) != length || record_length != HANDSHAKE_HEADER + length {
+            return None;
+        }
+        if datagram.len() != RECORD_HEADER + record_length {
+            return None;
+        }
+
+        let session_id = body + HELLO_PREFIX;
+        let cookie_offset = session_id + 1 + usize::from(datagram[session_id]);
+        let cookie_end = cookie_offset + 1 + usize::from(*datagram.get(cookie_offset)?);
+        if cookie_end > datagram.len() {
+            return None;
+        }
+        Some(Self { datagram, cookie_offset })
+    }
+
+    fn cookie(&self) -> &[u8] {
+        let length = usize::from(self.datagram[self.cookie_offset]);
+        &self.datagram[self.cookie_offset + 1..self.cookie_offset + 1 + length]
+    }
+
+    /// HelloVerifyRequest answering this ClientHello, with the record and message sequence numbers echoed
+    fn hello_verify_request(&self, cookie: &[u8]) -> Vec<u8> {
+        let body = [&[0xfe, 0xff, cookie.len() as u8][..], cookie].concat();
+        let mut datagram = Vec::with_capacity(RECORD_HEADER + HANDSHAKE_HEADER + body.len());
+        datagram.extend_from_slice(&[22, 0xfe, 0xff]);
+        datagram.extend_from_slice(&self.datagram[3..11]);
+        datagram.extend_from_slice(&((HANDSHAKE_HEADER + body.len()) as u16).to_be_bytes());
+        datagram.push(3);
+        datagram.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        datagram.extend_from_slice(&self.datagram[17..19]);
+        datagram.extend_from_slice(&[0, 0, 0]);
+        datagram.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        datagram.extend_from_slice(&body);
+        datagram
+    }
+
+    /// The initial ClientHello the peer sent before it got the cookie, RFC 6347 Section 4.2.1 requires the second
+    /// one to repeat it apart from the cookie. OpenSSL is handed both, as it only accepts a ClientHello with a cookie
+    /// after it answered one without.
+    fn initial(&self) -> Vec<u8> {
+        let cookie = self.cookie().len();
+        let mut datagram = [&self.datagram[..self.cookie_offset], &[0], &self.datagram[self.cookie_offset + 1 + cookie..]].concat();
+
+        let sequence = u64::from_be_bytes([0, 0, datagram[5], datagram[6], datagram[7], datagram[8], datagram[9], datagram[10]]);
+        datagram[5..11].copy_from_slice(&sequence.saturating_sub(1).to_be_bytes()[2..]);
+        let length = u24(&datagram[14..17]) - cookie;
+        datagram[11..13].copy_from_slice(&((HANDSHAKE_HEADER + length) as u16).to_be_bytes());
+        datagram[14..17].copy_from_slice(&(length as u32).to_be_bytes()[1..]);
+        datagram[17..19].copy_from_slice(&[0, 0]);
+        datagram[22..25].copy_from_slice(&(length as u32).to_be_bytes()[1..]);
+        datagram
+    }
+}
+
+fn u24(bytes: &[u8]) -> usize {
+    usize::from(bytes[0]) << 16 | usize::from(bytes[1]) << 8 | usize::from(bytes[2])
+}
+
+/// Stateless cookie of a peer for the HelloVerifyRequest, so spoofed addresses neither receive the certificate flight
+/// nor get a session allocated
+fn cookie(key: &PKey<Private>, peer: Option<&SocketAddr>) -> Result<Vec<u8>, ErrorStack> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(peer.map(ToString::to_string).unwrap_or_default().as_bytes())?;
+    signer.sign_to_vec()
+}
+
+/// Pledges have to present an IDevID chaining to a trust anchor of one of the manufacturers
+fn dtls_context(
+    certificate: &X509,
+    key: &PKey<Private>,
+    trust_anchors: &[X509],
+    cookie_key: &PKey<Private>,
+    peer_index: Index<Ssl, SocketAddr>,
+) -> Result<SslContext, ErrorStack> {
+    let generate_key = cookie_key.clone();
+    let verify_key = cookie_key.clone();
+
+    let mut store = X509StoreBuilder::new()?;
+    for anchor in trust_anchors {
+        store.add_cert(anchor.clone())?;
+    }
+
+    let mut builder = SslContext::builder(SslMethod::dtls())?;
+    builder.set_certificate(certificate)?;
+    builder.set_private_key(key)?;
+    builder.check_private_key()?;
+    builder.set_verify_cert_store(store.build())?;
+    builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    builder.set_options(SslOptions::NO_QUERY_MTU | SslOptions::COOKIE_EXCHANGE);
+    builder.set_cookie_generate_cb(move |ssl, buffer| {
+        let cookie = cookie(&generate_key, ssl.ex_data(peer_index))?;
+        buffer[..cookie.len()].copy_from_slice(&cookie);
+        Ok(cookie.len())
+    });
+    builder.set_cookie_verify_cb(move |ssl, received| {
+        cookie(&verify_key, ssl.ex_data(peer_index))
+            .is_ok_and(|cookie| cookie.len() == received.len() && openssl::memcmp::eq(&cookie, received))
+    });
+
+    Ok(builder.build())
+}
+
+/// CoAP over DTLS listener for constrained pledges, see [`CoapsConfig`]
+#[derive(Clone)]
+pub(crate) struct CoapsServer {
+    socket: Arc<UdpSocket>,
+    context: SslContext,
+    cookie_key: PKey<Private>,
+    peer_index: Index<Ssl, SocketAddr>,
+    config: CoapsConfig,
+    app: Router,
+}
+
+impl CoapsServer {
+    /// `trust_anchors` are those of all manufacturers, pledges of other manufacturers can not connect
+    pub(crate) async fn bind(
+        config: &CoapsConfig,
+        certificate: &X509,
+        key: &EcKey<Private>,
+        trust_anchors: &[X509],
+        app: Router,
+    ) -> anyhow::Result<Self> {
+        if trust_anchors.is_empty() {
+            return Err(anyhow::anyhow!("CoAP over DTLS needs the trust_anchors of a [registrar.manufacturers] section to verify pledges"));
+        }
+
+        let mut secret = [0u8; 32];
+        openssl::rand::rand_bytes(&mut secret)?;
+        let cookie_key = PKey::hmac(&secret)?;
+
+        let peer_index = Ssl::new_ex_index()?;
+        let context = dtls_context(certificate, &PKey::from_ec_key(key.clone())?, trust_anchors, &cookie_key, peer_index)?;
+        let socket = UdpSocket::bind(("0.0.0.0", config.port)).await?;
+
+        event!(Level::INFO, "Serving CoAP over DTLS on {}", socket.local_addr()?);
+        Ok(Self {
+            socket: Arc::new(socket),
+            context,
+            cookie_key,
+            peer_index,
+            config: config.clone(),
+            app,
+        })
+    }
+
+    #[cfg(test)]
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub(crate) fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    /// Hands the datagrams to the session of their peer. Peers without a session are answered statelessly with a
+    /// HelloVerifyRequest, a session is only started once a ClientHello returns the cookie of the peer address.
+    /// A session is never replaced, a pledge that lost its session waits for the idle timeout.
+    async fn run(self) {
+        let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+
+        loop {
+            let (length, peer) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(err) => {
+                    event!(Level::WARN, "Could not receive CoAP datagram: {}", err);
+                    continue;
+                }
+            };
+            let datagram = &buffer[..length];
+            sessions.retain(|_, incoming| !incoming.is_closed());
+
+            if let Some(incoming) = sessions.get(&peer) {
+                // a busy session drops datagrams like the network would, the peer retransmits
+                let _ = incoming.try_send(datagram.to_vec());
+                continue;
+            }
+
+            let Some(hello) = ClientHello::parse(datagram) else {
+                event!(Level::DEBUG, "Dropping datagram of {} without a DTLS session", peer);
+                continue;
+            };
+            let expected = match cookie(&self.cookie_key, Some(&peer)) {
+                Ok(expected) => expected,
+                Err(err) => {
+                    event!(Level::WARN, "Could not compute the DTLS cookie of {}: {}", peer, err);
+                    continue;
+                }
+            };
+            let received = hello.cookie();
+            if !(received.len() == expected.len() && openssl::memcmp::eq(received, &expected)) {
+                if let Err(err) = self.socket.send_to(&hello.hello_verify_request(&expected), peer).await {
+                    event!(Level::DEBUG, "Could not send HelloVerifyRequest to {}: {}", peer, err);
+                }
+                continue;
+            }
+
+            if sessions.len() >= MAX_SESSIONS {
+                event!(Level::WARN, "Dropping ClientHello of {}, {} DTLS sessions are open", peer, MAX_SESSIONS);
+                continue;
+            }
+
+            let (incoming, receiver) = mpsc::channel(SESSION_QUEUE);
+            let _ = incoming.try_send(hello.initial());
+            let _ = incoming.try_send(datagram.to_vec());
+            sessions.insert(peer, incoming);
+            tokio::spawn(self.clone().session(peer, receiver));
+        }
+    }
+
+    async fn session(self, peer: SocketAddr, incoming: mpsc::Receiver<Vec<u8>>) {
+        let datagrams = PeerDatagrams {
+            socket: self.socket.clone(),
+            peer,
+            incoming,
+            // the HelloVerifyRequest OpenSSL answers the initial ClientHello with, the peer already got one
+            discard_writes: 1,
+        };
+
+        let result = async {
+            let mut ssl = Ssl::new(&self.context)?;
+            ssl.set_mtu(self.config.mtu)?;
+            ssl.set_ex_data(self.peer_index, peer);
+            let mut stream = SslStream::new(ssl, datagrams)?;
+
+            tokio::time::timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).accept()).await??;
+            event!(Level::INFO, "DTLS session with {} established", peer);
+
+            let session = session_extensions(stream.ssl())?;
+            let mut exchange = Exchange::new(self.app.clone(), peer, session, self.config.block_size);
+            let mut record = vec![0u8; MAX_DATAGRAM];
+            let idle_timeout = Duration::from_secs(self.config.idle_timeout_secs);
+            loop {
+                let Ok(read) = tokio::time::timeout(idle_timeout, stream.read(&mut record)).await else {
+                    break;
+                };
+                let read = read?;
+                if read == 0 {
+                    break;
+                }
+                if let Some(response) = exchange.handle(&record[..read]).await {
+                    stream.write_all(&response).await?;
+                }
+            }
+
+            let _ = stream.shutdown().await;
+            anyhow::Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(()) => event!(Level::INFO, "DTLS session with {} ended", peer),
+            Err(err) => event!(Level::INFO, "DTLS session with {} failed: {}", peer, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::HeaderMap, routing::{get, post}, Extension};
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::est::ChannelBinding;
+
+    const CERTS: &[u8] = b"certs-only PKCS#7";
+
+    fn app() -> Router {
+        let cacerts = CERTS.repeat(100);
+        Router::new()
+            .route("/.well-known/est/cacerts", get(move || async move { openssl::base64::encode_block(&cacerts) }))
+            .route(
+                "/.well-known/brski/requestvoucher",
+                post(|headers: HeaderMap, ConnectInfo(peer): ConnectInfo<SocketAddr>, body: String| async move {
+                    if headers.get(CONTENT_TYPE).map(|value| value.as_bytes()) != Some(JWS_VOUCHER.as_bytes()) {
+                        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                    }
+                    Ok(format!("voucher for {} from {}", body.len(), peer.ip()))
+                }),
+            )
+            .route(
+                "/.well-known/est/simpleenroll",
+                post(|binding: Option<Extension<ChannelBinding>>| async move {
+                    binding.map(|Extension(binding)| openssl::base64::encode_block(&binding.0)).unwrap_or_default()
+                }),
+            )
+    }
+
+    fn request(code: u8, message_id: u16, path: &str) -> Message {
+        let mut request = Message {
+            message_type: MessageType::Confirmable,
+            code,
+            message_id,
+            token: vec![0x42],
+            options: vec![],
+            payload: vec![],
+        };
+        request.set_uri_path(path);
+        request
+    }
+
+    #[test]
+    fn test_resources() {
+        assert_eq!(resource(".well-known/brski/rv").unwrap().path, "/.well-known/brski/requestvoucher");
+        assert_eq!(resource("est/sen").unwrap().path, "/.well-known/est/simpleenroll");
+        assert_eq!(resource("b/crts").unwrap().method, Method::GET);
+        assert!(resource("sen").is_none());
+        assert!(resource("est/requestvoucher").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blockwise_exchange() {
+        let peer: SocketAddr = "192.0.2.10:5684".parse().unwrap();
+        let mut exchange = Exchange::new(app(), peer, Extensions::new(), 64);
+
+        // the voucher request arrives in two Block1 blocks
+        let mut first = request(code::POST, 1, "est/rv");
+        first.set_option(BLOCK1, Block::new(0, true, 2).unwrap().encode());
+        first.payload = vec![b'a'; 64];
+        let response = Message::decode(&exchange.handle(&first.encode()).await.unwrap()).unwrap();
+        assert_eq!(response.code, code::CONTINUE);
+        assert_eq!(response.message_type, MessageType::Acknowledgement);
+
+        let mut last = request(code::POST, 2, "est/rv");
+        last.set_option(BLOCK1, Block::new(1, false, 2).unwrap().encode());
+        last.payload = vec![b'a'; 10];
+        let encoded = exchange.handle(&last.encode()).await.unwrap();
+        let response = Message::decode(&encoded).unwrap();
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.payload, b"voucher for 74 from 192.0.2.10");
+        // a retransmission is answered from the cache
+        assert_eq!(exchange.handle(&last.encode()).await.unwrap(), encoded);
+
+        let mut wrong_format = request(code::POST, 3, "est/rv");
+        wrong_format.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::PKCS10));
+        let response = Message::decode(&exchange.handle(&wrong_format.encode()).await.unwrap()).unwrap();
+        assert_eq!(response.code, code::UNSUPPORTED_CONTENT_FORMAT);
+
+        // the CA certificates are decoded from base64 and sent in Block2 blocks
+        let mut body = vec![];
+        for num in 0.. {
+            let mut crts = request(code::GET, 10 + num as u16, "est/crts");
+            if num > 0 {
+                crts.set_option(BLOCK2, Block::new(num, false, 2).unwrap().encode());
+            }
+            let response = Message::decode(&exchange.handle(&crts.encode()).await.unwrap()).unwrap();
+            assert_eq!(response.code, code::CONTENT);
+            assert_eq!(response.uint_option(coap::CONTENT_FORMAT), Some(u32::from(coap::PKCS7_CERTS_ONLY)));
+            body.extend_from_slice(&response.payload);
+            if !Block::decode(response.option(BLOCK2).unwrap()).unwrap().more {
+                break;
+            }
+        }
+        assert_eq!(body.len(), CERTS.len() * 100);
+
+        let response = Message::decode(&exchange.handle(&request(code::GET, 99, "est/unknown").encode()).await.unwrap()).unwrap();
+        assert_eq!(response.code, code::NOT_FOUND);
+    }
+
+    /// Client side of a DTLS session over a connected socket
+    struct ConnectedDatagrams(UdpSocket);
+
+    impl AsyncRead for ConnectedDatagrams {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            self.0.poll_recv(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for ConnectedDatagrams {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.0.poll_send(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The example registrar certificate is restricted to signing certificates, DTLS needs one without key usage
+    fn server_certificate() -> (X509, EcKey<Private>) {
+        let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let pkey = PKey::from_ec_key(key.clone()).unwrap();
+
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "registrar.example.com").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder.set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    /// Serves `app()` with the vendor CA of the example certificates as trust anchor
+    async fn spawn_server(certs: &example_certs::OpensslTestCerts) -> (u16, JoinHandle<()>) {
+        let (certificate, key) = server_certificate();
+        let config = CoapsConfig {
+            enabled: true,
+            port: 0,
+            ..Default::default()
+        };
+
+        let server = CoapsServer::bind(&config, &certificate, &key, std::slice::from_ref(&certs.vendor_ca.0), app()).await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        (port, server.spawn())
+    }
+
+    async fn connect(
+        port: u16,
+        identity: Option<(&X509, &PKey<Private>)>,
+        options: SslOptions,
+    ) -> Result<SslStream<ConnectedDatagrams>, openssl::ssl::Error> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(("127.0.0.1", port)).await.unwrap();
+        let mut context = SslContext::builder(SslMethod::dtls()).unwrap();
+        context.set_verify(SslVerifyMode::NONE);
+        context.set_options(SslOptions::NO_QUERY_MTU | options);
+        if let Some((certificate, key)) = identity {
+            context.set_certificate(certificate).unwrap();
+            context.set_private_key(key).unwrap();
+        }
+        let mut ssl = Ssl::new(&context.build()).unwrap();
+        ssl.set_mtu(1280).unwrap();
+        let mut stream = SslStream::new(ssl, ConnectedDatagrams(socket)).unwrap();
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).connect()).await.unwrap()?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn test_client_hello_cookie() {
+        let cookie = [7u8; 4];
+        // ClientHello with record sequence 1, message sequence 1, an empty session id and the cookie
+        let body = [&[0xfe, 0xfd][..], &[0x11; 32], &[0, cookie.len() as u8], &cookie, &[0, 2, 0xc0, 0x2b, 1, 0]].concat();
+        let length = body.len() as u32;
+        let datagram = [
+            &[22, 0xfe, 0xff, 0, 0, 0, 0, 0, 0, 0, 1][..],
+            &((HANDSHAKE_HEADER as u32 + length) as u16).to_be_bytes(),
+            &[1],
+            &length.to_be_bytes()[1..],
+            &[0, 1, 0, 0, 0],
+            &length.to_be_bytes()[1..],
+            &body,
+        ]
+        .concat();
+
+        let hello = ClientHello::parse(&datagram).unwrap();
+        assert_eq!(hello.cookie(), cookie);
+
+        let request = hello.hello_verify_request(&[9; 32]);
+        assert_eq!(request[0], 22);
+        assert_eq!(request[5..11], [0, 0, 0, 0, 0, 1]);
+        assert_eq!(request[13], 3);
+        assert_eq!(request[17..19], [0, 1]);
+        assert_eq!(request[RECORD_HEADER + HANDSHAKE_HEADER + 2..], [&[32][..], &[9; 32]].concat());
+
+        let initial = hello.initial();
+        let initial = ClientHello::parse(&initial).unwrap();
+        assert!(initial.cookie().is_empty());
+        assert_eq!(initial.datagram[5..11], [0, 0, 0, 0, 0, 0]);
+        assert_eq!(initial.datagram[17..19], [0, 0]);
+        assert_eq!(initial.datagram.len(), datagram.len() - cookie.len());
+
+        assert!(ClientHello::parse(&datagram[..datagram.len() - 1]).is_none());
+        assert!(ClientHello::parse(&request).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_needs_cookie() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (port, handle) = spawn_server(&certs).await;
+
+        let hello = {
+            let body = [&[0xfe, 0xfd][..], &[0x11; 32], &[0, 0, 0, 2, 0xc0, 0x2b, 1, 0]].concat();
+            let length = body.len() as u32;
+            [
+                &[22, 0xfe, 0xff, 0, 0, 0, 0, 0, 0, 0, 0][..],
+                &((HANDSHAKE_HEADER as u32 + length) as u16).to_be_bytes(),
+                &[1],
+                &length.to_be_bytes()[1..],
+                &[0, 0, 0, 0, 0],
+                &length.to_be_bytes()[1..],
+                &body,
+            ]
+            .concat()
+        };
+
+        // a ClientHello without a cookie, e.g. of a spoofed address, is only answered with a HelloVerifyRequest
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(("127.0.0.1", port)).await.unwrap();
+        socket.send(&hello).await.unwrap();
+        let mut response = vec![0u8; MAX_DATAGRAM];
+        let read = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut response)).await.unwrap().unwrap();
+        assert_eq!(response[13], 3);
+        assert_eq!(read, RECORD_HEADER + HANDSHAKE_HEADER + 3 + 32);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_untrusted_pledges() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (port, handle) = spawn_server(&certs).await;
+
+        assert!(connect(port, None, SslOptions::empty()).await.is_err());
+        let (registrar, registrar_key) = &certs.registrar;
+        assert!(connect(port, Some((registrar, registrar_key)), SslOptions::empty()).await.is_err());
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_dtls_session() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (port, handle) = spawn_server(&certs).await;
+        let (pledge, pledge_key) = &certs.pledge;
+        let mut stream = connect(port, Some((pledge, pledge_key)), SslOptions::empty()).await.unwrap();
+
+        let mut rv = request(code::POST, 7, ".well-known/brski/rv");
+        rv.payload = b"pledge voucher request".to_vec();
+        stream.write_all(&rv.encode()).await.unwrap();
+
+        let mut record = vec![0u8; MAX_DATAGRAM];
+        let read = stream.read(&mut record).await.unwrap();
+        let response = Message::decode(&record[..read]).unwrap();
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.token, vec![0x42]);
+        assert_eq!(response.payload, b"voucher for 22 from 127.0.0.1");
+
+        // the handler sees the keying material the pledge exports for its challengePassword
+        let mut sen = request(code::POST, 8, "est/sen");
+        sen.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::PKCS10));
+        sen.payload = b"csr".to_vec();
+        stream.write_all(&sen.encode()).await.unwrap();
+
+        let read = stream.read(&mut record).await.unwrap();
+        let response = Message::decode(&record[..read]).unwrap();
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.payload, ChannelBinding::export(stream.ssl()).unwrap().0);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_requires_extended_master_secret() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (port, handle) = spawn_server(&certs).await;
+        let (pledge, pledge_key) = &certs.pledge;
+        // SSL_OP_NO_EXTENDED_MASTER_SECRET, which the openssl crate has no constant for
+        let no_extms = SslOptions::from_bits_retain(1);
+        let mut stream = connect(port, Some((pledge, pledge_key)), no_extms).await.unwrap();
+        assert_eq!(stream.ssl().extms_support(), Some(false));
+
+        // the session has no channel binding, the registrar closes it without serving requests
+        stream.write_all(&request(code::POST, 7, ".well-known/brski/rv").encode()).await.unwrap();
+        let mut record = vec![0u8; MAX_DATAGRAM];
+        let read = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut record)).await;
+        assert!(!matches!(read, Ok(Ok(read)) if read > 0));
+
+        handle.abort();
+    }
+}
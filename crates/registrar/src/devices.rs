@@ -0,0 +1,301 @@
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{error::AppError, server_error::ServerError, util::hex};
+use openssl::x509::X509;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use tracing::{event, Level};
+
+use crate::sqlite::{sql_error, Database};
+
+/// A pledge bootstrapped by the registrar, kept across restarts unlike its session
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DeviceRecord {
+    pub(crate) serial_number: String,
+    /// Hex encoded SHA-256 of the DER of the IDevID
+    pub(crate) idevid_fingerprint: Option<String>,
+    /// The issued voucher with the in-flight signature of the registrar
+    pub(crate) voucher: Option<String>,
+    /// PEM of the issued LDevID
+    pub(crate) ldevid: Option<String>,
+    pub(crate) first_seen: DateTime<Utc>,
+    pub(crate) voucher_issued_at: Option<DateTime<Utc>>,
+    pub(crate) enrolled_at: Option<DateTime<Utc>>,
+    /// When the pledge reported a successful enrollment
+    pub(crate) completed_at: Option<DateTime<Utc>>,
+}
+
+/// Inventory of the devices bootstrapped by the registrar
+#[async_trait::async_trait]
+pub(crate) trait DeviceStore: Send + Sync {
+    async fn voucher_issued(&self, serial_number: &str, idevid_fingerprint: &str, voucher: &str) -> Result<(), ServerError>;
+
+    async fn enrolled(&self, serial_number: &str, idevid_fingerprint: Option<&str>, ldevid: &X509) -> Result<(), ServerError>;
+
+    /// Only devices already in the inventory are marked as completed
+    async fn completed(&self, serial_number: &str) -> Result<(), ServerError>;
+
+    async fn devices(&self) -> Result<Vec<DeviceRecord>, ServerError>;
+
+    async fn device(&self, serial_number: &str) -> Result<Option<DeviceRecord>, ServerError>;
+
+    /// Returns whether the device was in the inventory
+    async fn remove(&self, serial_number: &str) -> Result<bool, ServerError>;
+}
+
+/// Hex encoded SHA-256 of the DER of an IDevID
+pub(crate) fn idevid_fingerprint(idevid: &X509) -> Result<String, ServerError> {
+    let digest = openssl::sha::sha256(&idevid.to_der()?);
+    Ok(hex(&digest))
+}
+
+/// Records the voucher issued to a pledge. The pledge gets the voucher even if the inventory cannot be written, so
+/// a failure is only logged.
+pub(crate) async fn record_voucher(devices: &dyn DeviceStore, serial_number: &str, idevid: &X509, voucher: &str) {
+    let recorded = match idevid_fingerprint(idevid) {
+        Ok(fingerprint) => devices.voucher_issued(serial_number, &fingerprint, voucher).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = recorded {
+        event!(target: "Registrar::Devices", Level::ERROR, "Recording the voucher issued to {} failed: {}", serial_number, err);
+    }
+}
+
+/// Records the LDevID issued to a pledge, failures are only logged like in [`record_voucher`]
+pub(crate) async fn record_ldevid(devices: &dyn DeviceStore, serial_number: &str, idevid: &X509, ldevid: &X509) {
+    let recorded = match idevid_fingerprint(idevid) {
+        Ok(fingerprint) => devices.enrolled(serial_number, Some(&fingerprint), ldevid).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = recorded {
+        event!(target: "Registrar::Devices", Level::ERROR, "Recording the LDevID issued to {} failed: {}", serial_number, err);
+    }
+}
+
+fn timestamp(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+/// [`DeviceStore`] in SQLite. Without a database path the inventory lives in memory only.
+pub(crate) struct SqliteDeviceStore {
+    database: Database,
+}
+
+impl SqliteDeviceStore {
+    pub(crate) fn open(path: Option<&Path>) -> anyhow::Result<Self, AppError> {
+        let connection = match path {
+            Some(path) => {
+                event!(target: "Registrar::Devices", Level::INFO, "Opening device inventory {:?}", path);
+                Connection::open(path)?
+            }
+            None => Connection::open_in_memory()?,
+        };
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS devices (
+                serial_number TEXT PRIMARY KEY,
+                idevid_fingerprint TEXT,
+                voucher TEXT,
+                ldevid TEXT,
+                first_seen INTEGER NOT NULL,
+                voucher_issued_at INTEGER,
+                enrolled_at INTEGER,
+                completed_at INTEGER
+            );",
+        )?;
+
+        Ok(Self {
+            database: Database::new(connection),
+        })
+    }
+
+    fn record(row: &Row) -> rusqlite::Result<DeviceRecord> {
+        Ok(DeviceRecord {
+            serial_number: row.get(0)?,
+            idevid_fingerprint: row.get(1)?,
+            voucher: row.get(2)?,
+            ldevid: row.get(3)?,
+            first_seen: timestamp(row.get(4)?),
+            voucher_issued_at: row.get::<_, Option<i64>>(5)?.map(timestamp),
+            enrolled_at: row.get::<_, Option<i64>>(6)?.map(timestamp),
+            completed_at: row.get::<_, Option<i64>>(7)?.map(timestamp),
+        })
+    }
+}
+
+const COLUMNS: &str =
+    "serial_number, idevid_fingerprint, voucher, ldevid, first_seen, voucher_issued_at, enrolled_at, completed_at";
+
+#[async_trait::async_trait]
+impl DeviceStore for SqliteDeviceStore {
+    async fn voucher_issued(&self, serial_number: &str, idevid_fingerprint: &str, voucher: &str) -> Result<(), ServerError> {
+        let (serial, idevid_fingerprint, voucher) = (serial_number.to_string(), idevid_fingerprint.to_string(), voucher.to_string());
+        // a pledge bootstrapped again starts over, its previous LDevID is no longer the current one
+        self.database
+            .run(move |connection| {
+                connection
+                    .execute(
+                        "INSERT INTO devices (serial_number, idevid_fingerprint, voucher, first_seen, voucher_issued_at)
+                         VALUES (?1, ?2, ?3, ?4, ?4)
+                         ON CONFLICT(serial_number) DO UPDATE SET idevid_fingerprint = ?2, voucher = ?3, voucher_issued_at = ?4,
+                             ldevid = NULL, enrolled_at = NULL, completed_at = NULL",
+                        params![serial, idevid_fingerprint, voucher, Utc::now().timestamp_millis()],
+                    )
+                    .map_err(sql_error)
+            })
+            .await?;
+
+        event!(target: "Registrar::Devices", Level::DEBUG, "Recorded voucher issued to {}", serial_number);
+        Ok(())
+    }
+
+    async fn enrolled(&self, serial_number: &str, idevid_fingerprint: Option<&str>, ldevid: &X509) -> Result<(), ServerError> {
+        let ldevid = String::from_utf8(ldevid.to_pem()?).map_err(anyhow::Error::from)?;
+        let (serial, idevid_fingerprint) = (serial_number.to_string(), idevid_fingerprint.map(str::to_string));
+
+        self.database
+            .run(move |connection| {
+                connection
+                    .execute(
+                        "INSERT INTO devices (serial_number, idevid_fingerprint, ldevid, first_seen, enrolled_at)
+                         VALUES (?1, ?2, ?3, ?4, ?4)
+                         ON CONFLICT(serial_number) DO UPDATE SET
+                             idevid_fingerprint = COALESCE(?2, idevid_fingerprint), ldevid = ?3, enrolled_at = ?4, completed_at = NULL",
+                        params![serial, idevid_fingerprint, ldevid, Utc::now().timestamp_millis()],
+                    )
+                    .map_err(sql_error)
+            })
+            .await?;
+
+        event!(target: "Registrar::Devices", Level::DEBUG, "Recorded LDevID issued to {}", serial_number);
+        Ok(())
+    }
+
+    async fn completed(&self, serial_number: &str) -> Result<(), ServerError> {
+        let serial_number = serial_number.to_string();
+        self.database
+            .run(move |connection| {
+                connection
+                    .execute(
+                        "UPDATE devices SET completed_at = ?2 WHERE serial_number = ?1",
+                        params![serial_number, Utc::now().timestamp_millis()],
+                    )
+                    .map_err(sql_error)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn devices(&self) -> Result<Vec<DeviceRecord>, ServerError> {
+        self.database
+            .run(|connection| {
+                let mut statement = connection
+                    .prepare(&format!("SELECT {} FROM devices ORDER BY first_seen, serial_number", COLUMNS))
+                    .map_err(sql_error)?;
+                let records = statement.query_map([], Self::record).map_err(sql_error)?;
+                records.collect::<Result<_, _>>().map_err(sql_error)
+            })
+            .await
+    }
+
+    async fn device(&self, serial_number: &str) -> Result<Option<DeviceRecord>, ServerError> {
+        let serial_number = serial_number.to_string();
+        self.database
+            .run(move |connection| {
+                connection
+                    .query_row(
+                        &format!("SELECT {} FROM devices WHERE serial_number = ?1", COLUMNS),
+                        params![serial_number],
+                        Self::record,
+                    )
+                    .optional()
+                    .map_err(sql_error)
+            })
+            .await
+    }
+
+    async fn remove(&self, serial_number: &str) -> Result<bool, ServerError> {
+        let serial_number = serial_number.to_string();
+        self.database
+            .run(move |connection| {
+                connection
+                    .execute("DELETE FROM devices WHERE serial_number = ?1", params![serial_number])
+                    .map(|removed| removed > 0)
+                    .map_err(sql_error)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{asn1::Asn1Time, ec::{EcGroup, EcKey}, hash::MessageDigest, nid::Nid, pkey::PKey, x509::X509NameBuilder};
+
+    use super::*;
+
+    fn ldevid(serial_number: &str) -> X509 {
+        let key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::SERIALNUMBER, serial_number).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[tokio::test]
+    async fn test_devices_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("open-brski-devices-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = SqliteDeviceStore::open(Some(&path)).unwrap();
+        store.voucher_issued("00-D0-E5-F2-00-02", "ab", "voucher").await.unwrap();
+        store.enrolled("00-D0-E5-F2-00-02", Some("ab"), &ldevid("00-D0-E5-F2-00-02")).await.unwrap();
+        store.completed("00-D0-E5-F2-00-02").await.unwrap();
+        store.voucher_issued("00-D0-E5-F2-00-03", "cd", "voucher").await.unwrap();
+        drop(store);
+
+        let store = SqliteDeviceStore::open(Some(&path)).unwrap();
+        let devices = store.devices().await.unwrap();
+        assert_eq!(devices.len(), 2);
+
+        let enrolled = store.device("00-D0-E5-F2-00-02").await.unwrap().unwrap();
+        assert_eq!(enrolled.idevid_fingerprint.as_deref(), Some("ab"));
+        assert_eq!(enrolled.voucher.as_deref(), Some("voucher"));
+        assert!(enrolled.ldevid.unwrap().starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(enrolled.voucher_issued_at.is_some() && enrolled.enrolled_at.is_some() && enrolled.completed_at.is_some());
+
+        assert!(store.remove("00-D0-E5-F2-00-03").await.unwrap());
+        assert!(!store.remove("00-D0-E5-F2-00-03").await.unwrap());
+        assert!(store.device("00-D0-E5-F2-00-03").await.unwrap().is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_new_voucher_starts_over() {
+        let store = SqliteDeviceStore::open(None).unwrap();
+        store.enrolled("00-D0-E5-F2-00-02", None, &ldevid("00-D0-E5-F2-00-02")).await.unwrap();
+        let enrolled = store.device("00-D0-E5-F2-00-02").await.unwrap().unwrap();
+        assert!(enrolled.idevid_fingerprint.is_none());
+        assert!(enrolled.voucher_issued_at.is_none());
+
+        // completion is only recorded for devices in the inventory
+        store.completed("00-D0-E5-F2-00-04").await.unwrap();
+        assert!(store.device("00-D0-E5-F2-00-04").await.unwrap().is_none());
+
+        store.voucher_issued("00-D0-E5-F2-00-02", "ab", "voucher").await.unwrap();
+        let reissued = store.device("00-D0-E5-F2-00-02").await.unwrap().unwrap();
+        assert_eq!(reissued.first_seen, enrolled.first_seen);
+        assert_eq!(reissued.idevid_fingerprint.as_deref(), Some("ab"));
+        assert!(reissued.ldevid.is_none() && reissued.enrolled_at.is_none());
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::anyhow;
+use axum::http::Extensions;
+use common::{
+    asn1::{self, Asn1Error},
+    edhoc,
+    util::hex,
+};
+use der::asn1::ObjectIdentifier;
+use openssl::sha::Sha256;
+use openssl::ssl::{SslRef, SslVersion};
+use openssl::x509::{X509Req, X509VerifyResult, X509};
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+fn curve_oid(curve: &str) -> Option<ObjectIdentifier> {
+    match curve {
+        "P-256" => Some(ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7")),
+        "P-384" => Some(ObjectIdentifier::new_unwrap("1.3.132.0.34")),
+        "P-521" => Some(ObjectIdentifier::new_unwrap("1.3.132.0.35")),
+        _ => None,
+    }
+}
+
+/// CSR attributes asking for a key on one of the allowed LDevID curves, RFC 7030 Section 4.5.2.
+/// These are the same curves [`crate::sign_cert::check_csr`] enforces.
+pub(crate) fn encode_csrattrs(curves: &[String]) -> Result<Vec<u8>, Asn1Error> {
+    let curves: Vec<ObjectIdentifier> = curves.iter().filter_map(|curve| curve_oid(curve)).collect();
+    asn1::encode_csr_attrs(&curves)
+}
+
+/// Reads the base64 encoded PKCS#10 body of a `/simpleenroll` request. Line breaks within the base64 are allowed.
+pub(crate) fn decode_pkcs10(body: &str) -> Option<X509Req> {
+    let base64: String = body.chars().filter(|char| !char.is_ascii_whitespace()).collect();
+    let der = openssl::base64::decode_block(&base64).ok()?;
+    asn1::certification_request(&der).ok()
+}
+
+/// challengePassword attribute of a CSR, RFC 2985 Section 5.4.1
+pub(crate) fn challenge_password(csr: &X509Req) -> Option<String> {
+    asn1::challenge_password(&csr.to_der().ok()?).ok()?
+}
+
+/// tls-exporter channel binding of the (D)TLS session a request arrived on, RFC 9266, or the exporter of the EDHOC
+/// session of an OSCORE request. Requests of EST-coaps and of the HTTPS listener carry it as request extension,
+/// requests of the plain HTTP listener have none.
+#[derive(Debug, Clone)]
+pub(crate) struct ChannelBinding(pub(crate) Vec<u8>);
+
+impl ChannelBinding {
+    const LABEL: &'static str = "EXPORTER-Channel-Binding";
+    const LENGTH: usize = 32;
+    const EDHOC_LABEL: u64 = 32768;
+
+    /// Without the extended master secret (RFC 7627) the exported value of a (D)TLS 1.2 session is not unique to
+    /// it, RFC 9266 Section 4.2, such sessions have no binding
+    pub(crate) fn export(ssl: &SslRef) -> anyhow::Result<Self> {
+        if ssl.version2() != Some(SslVersion::TLS1_3) && ssl.extms_support() != Some(true) {
+            return Err(anyhow!("{} session without extended master secret", ssl.version_str()));
+        }
+
+        let mut material = vec![0; Self::LENGTH];
+        ssl.export_keying_material(&mut material, Self::LABEL, Some(&[]))?;
+        Ok(Self(material))
+    }
+
+    /// Binding of an EDHOC session that protects OSCORE requests, exported with a label of the private use range as
+    /// RFC 9528 registers none for channel bindings
+    pub(crate) fn from_edhoc(session: &edhoc::Session) -> anyhow::Result<Self> {
+        Ok(Self(session.exporter(Self::EDHOC_LABEL, &[], Self::LENGTH)?))
+    }
+
+    /// Whether the challengePassword of a CSR is the base64 encoded binding, RFC 7030 Section 3.5
+    pub(crate) fn matches(&self, challenge_password: &str) -> bool {
+        match openssl::base64::decode_block(challenge_password.trim()) {
+            Ok(password) => password.len() == self.0.len() && openssl::memcmp::eq(&password, &self.0),
+            Err(_) => false,
+        }
+    }
+}
+
+/// IDevID a pledge authenticated its (D)TLS or EDHOC session with, verified against the trust anchors of the manufacturers
+#[derive(Debug, Clone)]
+pub(crate) struct ClientIdevid {
+    pub(crate) idevid: X509,
+    /// Intermediates the pledge sent along, issuers for the revocation check of the IDevID
+    pub(crate) chain: Vec<X509>,
+}
+
+impl ClientIdevid {
+    /// Client certificate of a session, if OpenSSL verified it during the handshake
+    pub(crate) fn from_ssl(ssl: &SslRef) -> Option<Self> {
+        if ssl.verify_result() != X509VerifyResult::OK {
+            return None;
+        }
+        let idevid = ssl.peer_certificate()?;
+        let chain = ssl
+            .verified_chain()
+            .map(|chain| chain.iter().skip(1).map(|certificate| certificate.to_owned()).collect())
+            .unwrap_or_default();
+        Some(Self { idevid, chain })
+    }
+}
+
+/// Request extensions of an established (D)TLS session: its [`ChannelBinding`] and the [`ClientIdevid`] of a pledge
+/// that authenticated with it. A session without binding is refused.
+pub(crate) fn session_extensions(ssl: &SslRef) -> anyhow::Result<Extensions> {
+    let mut extensions = Extensions::new();
+    extensions.insert(ChannelBinding::export(ssl)?);
+    if let Some(idevid) = ClientIdevid::from_ssl(ssl) {
+        extensions.insert(idevid);
+    }
+    Ok(extensions)
+}
+
+/// Checks that the CSR of a pledge carries the channel binding of the session it authenticated with as base64
+/// encoded challengePassword, RFC 7030 Section 3.5. A CSR taken to another session is rejected.
+pub(crate) fn check_channel_binding(csr: &X509Req, binding: Option<&ChannelBinding>) -> Result<(), &'static str> {
+    let binding = binding.ok_or("session has no channel binding")?;
+    match challenge_password(csr) {
+        Some(password) if binding.matches(&password) => Ok(()),
+        Some(_) => Err("challengePassword is not the channel binding of the TLS session"),
+        None => Err("CSR carries no channel binding"),
+    }
+}
+
+/// Base64 encoded EST response together with its entity tag
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResponse {
+    pub(crate) body: String,
+    pub(crate) etag: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    inputs: [u8; 32],
+    response: CachedResponse,
+}
+
+/// `/cacerts` and `/csrattrs` are requested by every pledge, their responses are only rebuilt once the
+/// inputs they were built from change, i.e. the trust store or the allowed curves
+#[derive(Debug, Default)]
+pub(crate) struct EstCache {
+    cacerts: RwLock<Option<Entry>>,
+    csrattrs: RwLock<Option<Entry>>,
+}
+
+impl EstCache {
+    pub(crate) async fn cacerts(&self, trust_store: &[X509]) -> Result<CachedResponse, Asn1Error> {
+        let mut inputs = Sha256::new();
+        for certificate in trust_store {
+            inputs.update(&certificate.to_der()?);
+        }
+
+        cached(&self.cacerts, "cacerts", inputs.finish(), || asn1::encode_certs_only(trust_store)).await
+    }
+
+    pub(crate) async fn csrattrs(&self, curves: &[String]) -> Result<CachedResponse, Asn1Error> {
+        let mut inputs = Sha256::new();
+        for curve in curves {
+            inputs.update(curve.as_bytes());
+            inputs.update(&[0]);
+        }
+
+        cached(&self.csrattrs, "csrattrs", inputs.finish(), || encode_csrattrs(curves)).await
+    }
+}
+
+async fn cached(
+    slot: &RwLock<Option<Entry>>,
+    name: &str,
+    inputs: [u8; 32],
+    build: impl FnOnce() -> Result<Vec<u8>, Asn1Error>,
+) -> Result<CachedResponse, Asn1Error> {
+    if let Some(entry) = slot.read().await.as_ref().filter(|entry| entry.inputs == inputs) {
+        return Ok(entry.response.clone());
+    }
+
+    let mut slot = slot.write().await;
+    if let Some(entry) = slot.as_ref().filter(|entry| entry.inputs == inputs) {
+        return Ok(entry.response.clone());
+    }
+
+    event!(Level::INFO, "Building {} response", name);
+    let der = build()?;
+    let etag = hex(&openssl::sha::sha256(&der)[..16]);
+    let response = CachedResponse {
+        body: openssl::base64::encode_block(&der),
+        etag: format!("\"{}\"", etag),
+    };
+
+    *slot = Some(Entry {
+        inputs,
+        response: response.clone(),
+    });
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use example_certs::OpensslTestCerts;
+    use openssl::pkcs7::Pkcs7;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cacerts_rebuilt_on_trust_store_change() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let cache = EstCache::default();
+
+        let first = cache.cacerts(std::slice::from_ref(&certs.registrar_ca.0)).await.unwrap();
+        let again = cache.cacerts(std::slice::from_ref(&certs.registrar_ca.0)).await.unwrap();
+        assert_eq!(first.etag, again.etag);
+
+        let der = openssl::base64::decode_block(&first.body).unwrap();
+        let pkcs7 = Pkcs7::from_der(&der).unwrap();
+        let certificates = pkcs7.signed().unwrap().certificates().unwrap();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates.get(0).unwrap().to_der().unwrap(), certs.registrar_ca.0.to_der().unwrap());
+
+        let rotated = cache
+            .cacerts(&[certs.registrar_ca.0.clone(), certs.vendor_ca.0.clone()])
+            .await
+            .unwrap();
+        assert_ne!(first.etag, rotated.etag);
+    }
+
+    #[test]
+    fn test_decodes_wrapped_pkcs10() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let (_, pledge_key) = &certs.pledge;
+
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("serialNumber", "00-D0-E5-F2-00-02").unwrap();
+        let mut builder = openssl::x509::X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(pledge_key).unwrap();
+        builder.sign(pledge_key, openssl::hash::MessageDigest::sha256()).unwrap();
+        let der = builder.build().to_der().unwrap();
+
+        let wrapped = openssl::base64::encode_block(&der)
+            .as_bytes()
+            .chunks(64)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let csr = decode_pkcs10(&wrapped).unwrap();
+        assert_eq!(csr.to_der().unwrap(), der);
+
+        assert!(decode_pkcs10("not base64!").is_none());
+    }
+
+    #[test]
+    fn test_challenge_password_binds_channel() {
+        let binding = ChannelBinding(vec![0x5a; ChannelBinding::LENGTH]);
+        let password = openssl::base64::encode_block(&binding.0);
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let attribute = rcgen::Attribute {
+            oid: &[1, 2, 840, 113549, 1, 9, 7],
+            values: [&[0x31, password.len() as u8 + 2, 0x0c, password.len() as u8][..], password.as_bytes()].concat(),
+        };
+        let der = rcgen::CertificateParams::default()
+            .serialize_request_with_attributes(&key, vec![attribute])
+            .unwrap();
+        let csr = X509Req::from_der(der.der()).unwrap();
+
+        assert_eq!(challenge_password(&csr).unwrap(), password);
+        assert!(binding.matches(&password));
+        assert!(!ChannelBinding(vec![0xa5; ChannelBinding::LENGTH]).matches(&password));
+        assert!(!binding.matches("not base64!"));
+
+        let der = rcgen::CertificateParams::default().serialize_request(&key).unwrap();
+        assert!(challenge_password(&X509Req::from_der(der.der()).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_csrattrs_lists_allowed_curves() {
+        let der = encode_csrattrs(&["P-256".to_string(), "P-384".to_string()]).unwrap();
+
+        let (_, attrs) = x509_parser::der_parser::parse_der(&der).unwrap();
+        let attribute = &attrs.as_sequence().unwrap()[0];
+        let attribute = attribute.as_sequence().unwrap();
+        assert_eq!(attribute[0].as_oid().unwrap().to_id_string(), "1.2.840.10045.2.1");
+
+        let curves: Vec<String> = attribute[1]
+            .as_set()
+            .unwrap()
+            .iter()
+            .map(|curve| curve.as_oid().unwrap().to_id_string())
+            .collect();
+        // sorted by their encoding, as DER requires of a SET OF
+        assert_eq!(curves, ["1.3.132.0.34", "1.2.840.10045.3.1.7"]);
+    }
+}
@@ -0,0 +1,640 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{error::AppError, server_error::ServerError};
+use brski_prm_artifacts::{jws::JWS, rvr::RVR_JWS};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{event, Level};
+
+use crate::{
+    client,
+    sqlite::{sql_error, Database},
+    voucher_cache::is_masa_unreachable,
+};
+
+/// How often idle workers look for jobs whose backoff expired
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Backoff after the first failure, doubled for every further one
+const RETRY_BACKOFF: chrono::Duration = chrono::Duration::seconds(10);
+const MAX_RETRY_BACKOFF: chrono::Duration = chrono::Duration::hours(1);
+
+/// Jobs failing this often are kept as failed for inspection instead of being retried
+const MAX_ATTEMPTS: u32 = 20;
+
+/// Held vouchers that were issued or failed are dropped after this long if the pledge did not ask for them again
+const HELD_VOUCHER_RETENTION: chrono::Duration = chrono::Duration::days(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub(crate) enum Job {
+    /// A voucher request answered from the voucher cache, submitted so the MASA audit log stays complete
+    MasaSubmission {
+        serial_number: String,
+        masa_url: String,
+        rvr: String,
+        served_at: DateTime<Utc>,
+    },
+    /// A voucher request held while the MASA was unreachable. The issued voucher is kept until the pledge asks again
+    /// with the same voucher request, identified by `request_hash`.
+    VoucherRequest {
+        serial_number: String,
+        masa_url: String,
+        rvr: String,
+        request_hash: String,
+        queued_at: DateTime<Utc>,
+    },
+    /// Voucher status telemetry of a pledge relayed to the MASA, see `forward_voucher_status`
+    VoucherStatus {
+        serial_number: String,
+        masa_url: String,
+        voucher_status: String,
+        received_at: DateTime<Utc>,
+    },
+}
+
+/// State of a voucher request held by the registrar, see [`Job::VoucherRequest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HeldVoucher {
+    Issued(String),
+    /// The MASA was not reached yet, the pledge should ask again after `retry_after`
+    Pending { retry_after: Duration },
+    /// The MASA rejected the request, or it was given up on
+    Failed(String),
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::MasaSubmission { .. } => "masa-submission",
+            Job::VoucherRequest { .. } => "voucher-request",
+            Job::VoucherStatus { .. } => "voucher-status",
+        }
+    }
+
+    /// Returns the issued voucher of a held voucher request
+    async fn run(&self, client: &reqwest::Client) -> Result<Option<String>, ServerError> {
+        match self {
+            Job::MasaSubmission { serial_number, masa_url, rvr, .. } => {
+                let rvr: RVR_JWS = JWS::Encoded(rvr.clone());
+                client::get_voucher_from_masa(masa_url, rvr, client).await?;
+                event!(target: "Registrar::Jobs", Level::INFO, "Submitted voucher request for {} to the MASA", serial_number);
+                Ok(None)
+            }
+            Job::VoucherRequest { serial_number, masa_url, rvr, .. } => {
+                let rvr: RVR_JWS = JWS::Encoded(rvr.clone());
+                let voucher = client::get_voucher_from_masa(masa_url, rvr, client).await?;
+                event!(target: "Registrar::Jobs", Level::INFO, "Received held voucher for {} from the MASA", serial_number);
+                Ok(Some(voucher.try_encoded_data()?))
+            }
+            Job::VoucherStatus { serial_number, masa_url, voucher_status, .. } => {
+                client::send_voucher_status_to_masa(masa_url, JWS::Encoded(voucher_status.clone()), client).await?;
+                event!(target: "Registrar::Jobs", Level::INFO, "Relayed voucher status of {} to the MASA", serial_number);
+                Ok(None)
+            }
+        }
+    }
+
+    /// A held voucher request rejected by the MASA is not sent again, the pledge gets the error on its next request.
+    /// A rejected voucher status would be rejected again as well.
+    fn is_final(&self, error: &ServerError) -> bool {
+        matches!(self, Job::VoucherRequest { .. } | Job::VoucherStatus { .. }) && !is_masa_unreachable(error)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum JobStatus {
+    Pending,
+    Running,
+    Failed,
+}
+
+impl JobStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "running" => JobStatus::Running,
+            "failed" => JobStatus::Failed,
+            _ => JobStatus::Pending,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct JobRecord {
+    pub(crate) id: i64,
+    pub(crate) kind: String,
+    pub(crate) status: JobStatus,
+    pub(crate) attempts: u32,
+    pub(crate) run_after: DateTime<Utc>,
+    pub(crate) last_error: Option<String>,
+    #[serde(skip)]
+    pub(crate) job: Job,
+}
+
+/// A voucher request held for the MASA that was not answered yet, as listed on the dashboard
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PendingApproval {
+    pub(crate) serial_number: String,
+    pub(crate) masa_url: String,
+    pub(crate) queued_at: DateTime<Utc>,
+    pub(crate) attempts: u32,
+    pub(crate) retry_at: DateTime<Utc>,
+    pub(crate) last_error: Option<String>,
+}
+
+fn timestamp(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis).single().unwrap_or_default()
+}
+
+/// Background jobs of the registrar, persisted in SQLite so they survive restarts.
+/// Without a database path the queue lives in memory only.
+pub(crate) struct JobQueue {
+    database: Database,
+    enqueued: Notify,
+}
+
+impl JobQueue {
+    pub(crate) fn open(path: Option<&Path>) -> anyhow::Result<Self, AppError> {
+        let connection = match path {
+            Some(path) => {
+                event!(target: "Registrar::Jobs", Level::INFO, "Opening job queue {:?}", path);
+                Connection::open(path)?
+            }
+            None => Connection::open_in_memory()?,
+        };
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                run_after INTEGER NOT NULL,
+                last_error TEXT
+            );
+            CREATE TABLE IF NOT EXISTS held_vouchers (
+                request_hash TEXT PRIMARY KEY,
+                job_id INTEGER,
+                voucher TEXT,
+                updated_at INTEGER NOT NULL DEFAULT 0
+            );",
+        )?;
+        // databases created before held vouchers were pruned
+        if connection.prepare("SELECT updated_at FROM held_vouchers LIMIT 0").is_err() {
+            connection.execute("ALTER TABLE held_vouchers ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0", [])?;
+        }
+        Self::prune_held(&connection, Utc::now())?;
+
+        // jobs that were running when the registrar stopped are picked up again
+        let interrupted = connection.execute("UPDATE jobs SET status = 'pending' WHERE status = 'running'", [])?;
+        if interrupted > 0 {
+            event!(target: "Registrar::Jobs", Level::INFO, "Resuming {} interrupted jobs", interrupted);
+        }
+
+        Ok(Self {
+            database: Database::new(connection),
+            enqueued: Notify::new(),
+        })
+    }
+
+    pub(crate) async fn enqueue(&self, job: &Job) -> Result<i64, ServerError> {
+        let payload = serde_json::to_string(job)?;
+
+        let kind = job.kind();
+        let id = self
+            .database
+            .run(move |connection| {
+                connection
+                    .execute(
+                        "INSERT INTO jobs (kind, payload, status, run_after) VALUES (?1, ?2, ?3, ?4)",
+                        params![kind, payload, JobStatus::Pending.as_str(), Utc::now().timestamp_millis()],
+                    )
+                    .map_err(sql_error)?;
+                Ok(connection.last_insert_rowid())
+            })
+            .await?;
+
+        event!(target: "Registrar::Jobs", Level::DEBUG, "Enqueued {} job {}", job.kind(), id);
+        self.enqueued.notify_one();
+        Ok(id)
+    }
+
+    /// Marks the oldest due job as running and returns it
+    async fn claim(&self, now: DateTime<Utc>) -> Result<Option<JobRecord>, ServerError> {
+        self.database
+            .run(move |connection| {
+                let record = connection
+                    .query_row(
+                        "SELECT id, kind, payload, status, attempts, run_after, last_error FROM jobs
+                         WHERE status = 'pending' AND run_after <= ?1 ORDER BY run_after, id LIMIT 1",
+                        params![now.timestamp_millis()],
+                        Self::record,
+                    )
+                    .optional()
+                    .map_err(sql_error)?;
+
+                let Some(record) = record else {
+                    return Ok(None);
+                };
+
+                connection
+                    .execute("UPDATE jobs SET status = 'running' WHERE id = ?1", params![record.id])
+                    .map_err(sql_error)?;
+
+                Ok(Some(JobRecord {
+                    status: JobStatus::Running,
+                    ..record
+                }))
+            })
+            .await
+    }
+
+    async fn complete(&self, id: i64, voucher: Option<String>) -> Result<(), ServerError> {
+        self.database
+            .run(move |connection| {
+                connection.execute("DELETE FROM jobs WHERE id = ?1", params![id]).map_err(sql_error)?;
+                if let Some(voucher) = voucher {
+                    connection
+                        .execute(
+                            "UPDATE held_vouchers SET voucher = ?2, job_id = NULL, updated_at = ?3 WHERE job_id = ?1",
+                            params![id, voucher, Utc::now().timestamp_millis()],
+                        )
+                        .map_err(sql_error)?;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    async fn retry(&self, record: &JobRecord, error: &ServerError, now: DateTime<Utc>) -> Result<(), ServerError> {
+        let attempts = record.attempts + 1;
+        let status = if attempts >= MAX_ATTEMPTS || record.job.is_final(error) {
+            JobStatus::Failed
+        } else {
+            JobStatus::Pending
+        };
+        let backoff = RETRY_BACKOFF
+            .checked_mul(1 << (attempts - 1).min(16))
+            .map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF));
+
+        let (id, error) = (record.id, error.to_string());
+        self.database
+            .run(move |connection| {
+                connection
+                    .execute(
+                        "UPDATE jobs SET status = ?2, attempts = ?3, run_after = ?4, last_error = ?5 WHERE id = ?1",
+                        params![id, status.as_str(), attempts, (now + backoff).timestamp_millis(), error],
+                    )
+                    .map_err(sql_error)?;
+                if status == JobStatus::Failed {
+                    connection
+                        .execute("UPDATE held_vouchers SET updated_at = ?2 WHERE job_id = ?1", params![id, now.timestamp_millis()])
+                        .map_err(sql_error)?;
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Queues a [`Job::VoucherRequest`], its voucher is kept under the request hash once the MASA issued it
+    pub(crate) async fn hold(&self, job: &Job) -> Result<i64, ServerError> {
+        let Job::VoucherRequest { request_hash, .. } = job else {
+            return Err(anyhow::anyhow!("only voucher requests are held").into());
+        };
+
+        let id = self.enqueue(job).await?;
+        let request_hash = request_hash.clone();
+        self.database
+            .run(move |connection| {
+                let now = Utc::now();
+                Self::prune_held(connection, now).map_err(sql_error)?;
+                connection
+                    .execute(
+                        "INSERT OR REPLACE INTO held_vouchers (request_hash, job_id, voucher, updated_at) VALUES (?1, ?2, NULL, ?3)",
+                        params![request_hash, id, now.timestamp_millis()],
+                    )
+                    .map_err(sql_error)?;
+                Ok(id)
+            })
+            .await
+    }
+
+    /// Drops held vouchers that were issued or failed more than [`HELD_VOUCHER_RETENTION`] ago and never handed out,
+    /// requests still waiting for the MASA are kept
+    fn prune_held(connection: &Connection, now: DateTime<Utc>) -> rusqlite::Result<usize> {
+        let pruned = connection.execute(
+            "DELETE FROM held_vouchers WHERE updated_at < ?1
+             AND (job_id IS NULL OR job_id NOT IN (SELECT id FROM jobs WHERE status != 'failed'))",
+            params![(now - HELD_VOUCHER_RETENTION).timestamp_millis()],
+        )?;
+        if pruned > 0 {
+            event!(target: "Registrar::Jobs", Level::INFO, "Dropped {} held vouchers that were not fetched", pruned);
+        }
+        Ok(pruned)
+    }
+
+    /// Whether the voucher request is held, without handing out its voucher
+    pub(crate) async fn is_held(&self, request_hash: &str) -> Result<bool, ServerError> {
+        let request_hash = request_hash.to_string();
+        self.database
+            .run(move |connection| {
+                connection
+                    .query_row("SELECT 1 FROM held_vouchers WHERE request_hash = ?1", params![request_hash], |_| Ok(()))
+                    .optional()
+                    .map(|held| held.is_some())
+                    .map_err(sql_error)
+            })
+            .await
+    }
+
+    /// State of a held voucher request. An issued voucher or a failure is only returned once.
+    pub(crate) async fn held_voucher(&self, request_hash: &str, now: DateTime<Utc>) -> Result<Option<HeldVoucher>, ServerError> {
+        let request_hash = request_hash.to_string();
+        self.database.run(move |connection| Self::take_held(connection, &request_hash, now)).await
+    }
+
+    fn take_held(connection: &Connection, request_hash: &str, now: DateTime<Utc>) -> Result<Option<HeldVoucher>, ServerError> {
+        let held = connection
+            .query_row(
+                "SELECT held_vouchers.voucher, jobs.status, jobs.run_after, jobs.last_error FROM held_vouchers
+                 LEFT JOIN jobs ON jobs.id = held_vouchers.job_id WHERE held_vouchers.request_hash = ?1",
+                params![request_hash],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(sql_error)?;
+
+        let held = match held {
+            None => return Ok(None),
+            Some((Some(voucher), _, _, _)) => HeldVoucher::Issued(voucher),
+            Some((None, Some(status), run_after, _)) if JobStatus::parse(&status) != JobStatus::Failed => {
+                let due_in = run_after.map(timestamp).unwrap_or(now) - now;
+                let retry_after = due_in.to_std().unwrap_or_default().max(POLL_INTERVAL);
+                return Ok(Some(HeldVoucher::Pending { retry_after }));
+            }
+            Some((None, _, _, last_error)) => {
+                HeldVoucher::Failed(last_error.unwrap_or_else(|| "the voucher request was dropped".to_string()))
+            }
+        };
+
+        connection
+            .execute("DELETE FROM held_vouchers WHERE request_hash = ?1", params![request_hash])
+            .map_err(sql_error)?;
+        Ok(Some(held))
+    }
+
+    /// All jobs, due first
+    pub(crate) async fn jobs(&self) -> Result<Vec<JobRecord>, ServerError> {
+        self.database
+            .run(|connection| {
+                let mut statement = connection
+                    .prepare("SELECT id, kind, payload, status, attempts, run_after, last_error FROM jobs ORDER BY run_after, id")
+                    .map_err(sql_error)?;
+                let records = statement
+                    .query_map([], Self::record)
+                    .map_err(sql_error)?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(sql_error)?;
+                Ok(records)
+            })
+            .await
+    }
+
+    /// Held voucher requests still waiting for the MASA, oldest first
+    pub(crate) async fn pending_approvals(&self) -> Result<Vec<PendingApproval>, ServerError> {
+        let mut pending: Vec<PendingApproval> = self
+            .jobs()
+            .await?
+            .into_iter()
+            .filter(|record| record.status != JobStatus::Failed)
+            .filter_map(|record| match record.job {
+                Job::VoucherRequest { serial_number, masa_url, queued_at, .. } => Some(PendingApproval {
+                    serial_number,
+                    masa_url,
+                    queued_at,
+                    attempts: record.attempts,
+                    retry_at: record.run_after,
+                    last_error: record.last_error,
+                }),
+                _ => None,
+            })
+            .collect();
+        pending.sort_by_key(|approval| approval.queued_at);
+        Ok(pending)
+    }
+
+    fn record(row: &rusqlite::Row) -> rusqlite::Result<JobRecord> {
+        let payload: String = row.get(2)?;
+        let job = serde_json::from_str(&payload)
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(err)))?;
+
+        Ok(JobRecord {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            status: JobStatus::parse(&row.get::<_, String>(3)?),
+            attempts: row.get(4)?,
+            run_after: timestamp(row.get(5)?),
+            last_error: row.get(6)?,
+            job,
+        })
+    }
+
+    /// Starts `workers` tasks, so at most that many jobs run at the same time
+    pub(crate) fn spawn_workers(self: &Arc<Self>, workers: usize, client: reqwest::Client) {
+        for worker in 0..workers {
+            let queue = self.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                event!(target: "Registrar::Jobs", Level::DEBUG, "Started job worker {}", worker);
+                loop {
+                    queue.work(&client).await;
+                }
+            });
+        }
+    }
+
+    async fn work(&self, client: &reqwest::Client) {
+        let record = match self.claim(Utc::now()).await {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                let _ = tokio::time::timeout(POLL_INTERVAL, self.enqueued.notified()).await;
+                return;
+            }
+            Err(err) => {
+                event!(target: "Registrar::Jobs", Level::ERROR, "Claiming job failed: {}", err);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                return;
+            }
+        };
+
+        let result = match record.job.run(client).await {
+            Ok(voucher) => self.complete(record.id, voucher).await,
+            Err(err) => {
+                event!(target: "Registrar::Jobs", Level::WARN, "{} job {} failed: {}", record.kind, record.id, err);
+                self.retry(&record, &err, Utc::now()).await
+            }
+        };
+
+        if let Err(err) = result {
+            event!(target: "Registrar::Jobs", Level::ERROR, "Updating job {} failed: {}", record.id, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(serial_number: &str) -> Job {
+        Job::MasaSubmission {
+            serial_number: serial_number.to_string(),
+            masa_url: "http://localhost:3000".to_string(),
+            rvr: "rvr".to_string(),
+            served_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("open-brski-jobs-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let queue = JobQueue::open(Some(&path)).unwrap();
+        let first = queue.enqueue(&submission("00-D0-E5-F2-00-02")).await.unwrap();
+        queue.enqueue(&submission("00-D0-E5-F2-00-03")).await.unwrap();
+
+        let now = Utc::now();
+        let claimed = queue.claim(now).await.unwrap().unwrap();
+        assert_eq!(claimed.id, first);
+        assert!(matches!(&claimed.job, Job::MasaSubmission { serial_number, .. } if serial_number == "00-D0-E5-F2-00-02"));
+
+        // a failed job waits for its backoff
+        queue.retry(&claimed, &ServerError::BadRequest, now).await.unwrap();
+        let second = queue.claim(now).await.unwrap().unwrap();
+        assert_ne!(second.id, first);
+        assert!(queue.claim(now).await.unwrap().is_none());
+        assert!(queue.claim(now + RETRY_BACKOFF * 2).await.unwrap().is_some());
+        drop(queue);
+
+        // the running jobs are resumed after a restart
+        let queue = JobQueue::open(Some(&path)).unwrap();
+        let jobs = queue.jobs().await.unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|job| job.status == JobStatus::Pending));
+        assert_eq!(jobs.iter().find(|job| job.id == first).unwrap().attempts, 1);
+
+        queue.complete(first, None).await.unwrap();
+        assert_eq!(queue.jobs().await.unwrap().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_holds_voucher_requests() {
+        let queue = JobQueue::open(None).unwrap();
+        let held = |request_hash: &str| Job::VoucherRequest {
+            serial_number: "00-D0-E5-F2-00-02".to_string(),
+            masa_url: "http://localhost:3000".to_string(),
+            rvr: "rvr".to_string(),
+            request_hash: request_hash.to_string(),
+            queued_at: Utc::now(),
+        };
+        assert!(queue.hold(&submission("00-D0-E5-F2-00-02")).await.is_err());
+
+        let now = Utc::now();
+        queue.hold(&held("issued")).await.unwrap();
+        assert!(matches!(queue.held_voucher("issued", now).await.unwrap(), Some(HeldVoucher::Pending { .. })));
+
+        // the voucher is handed out once
+        let record = queue.claim(Utc::now()).await.unwrap().unwrap();
+        queue.complete(record.id, Some("voucher".to_string())).await.unwrap();
+        assert_eq!(queue.held_voucher("issued", now).await.unwrap(), Some(HeldVoucher::Issued("voucher".to_string())));
+        assert_eq!(queue.held_voucher("issued", now).await.unwrap(), None);
+
+        // a rejection by the MASA is final, an unreachable MASA is retried
+        queue.hold(&held("rejected")).await.unwrap();
+        let record = queue.claim(Utc::now()).await.unwrap().unwrap();
+        queue.retry(&record, &ServerError::BadResponse("requestvoucher failed".to_string()), now).await.unwrap();
+        assert!(matches!(queue.held_voucher("rejected", now).await.unwrap(), Some(HeldVoucher::Failed(reason)) if reason.contains("requestvoucher failed")));
+        assert_eq!(queue.held_voucher("unknown", now).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_lists_pending_approvals() {
+        let queue = JobQueue::open(None).unwrap();
+        let held = |serial_number: &str| Job::VoucherRequest {
+            serial_number: serial_number.to_string(),
+            masa_url: "http://localhost:3000".to_string(),
+            rvr: "rvr".to_string(),
+            request_hash: serial_number.to_string(),
+            queued_at: Utc::now(),
+        };
+
+        queue.enqueue(&submission("00-D0-E5-F2-00-02")).await.unwrap();
+        queue.hold(&held("00-D0-E5-F2-00-03")).await.unwrap();
+        queue.hold(&held("00-D0-E5-F2-00-04")).await.unwrap();
+
+        let pending = queue.pending_approvals().await.unwrap();
+        assert_eq!(pending.iter().map(|approval| approval.serial_number.as_str()).collect::<Vec<_>>(), ["00-D0-E5-F2-00-03", "00-D0-E5-F2-00-04"]);
+
+        // answered and rejected requests are no longer pending
+        let now = Utc::now();
+        let submission = queue.claim(now).await.unwrap().unwrap();
+        queue.complete(submission.id, None).await.unwrap();
+        let issued = queue.claim(now).await.unwrap().unwrap();
+        queue.complete(issued.id, Some("voucher".to_string())).await.unwrap();
+        let rejected = queue.claim(now).await.unwrap().unwrap();
+        queue.retry(&rejected, &ServerError::BadResponse("requestvoucher failed".to_string()), now).await.unwrap();
+        assert!(queue.pending_approvals().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prunes_held_vouchers() {
+        let queue = JobQueue::open(None).unwrap();
+        let held = |request_hash: &str| Job::VoucherRequest {
+            serial_number: "00-D0-E5-F2-00-02".to_string(),
+            masa_url: "http://localhost:3000".to_string(),
+            rvr: "rvr".to_string(),
+            request_hash: request_hash.to_string(),
+            queued_at: Utc::now(),
+        };
+
+        queue.hold(&held("issued")).await.unwrap();
+        let record = queue.claim(Utc::now()).await.unwrap().unwrap();
+        queue.complete(record.id, Some("voucher".to_string())).await.unwrap();
+        queue.hold(&held("rejected")).await.unwrap();
+        let record = queue.claim(Utc::now()).await.unwrap().unwrap();
+        queue.retry(&record, &ServerError::BadResponse("requestvoucher failed".to_string()), Utc::now()).await.unwrap();
+        queue.hold(&held("pending")).await.unwrap();
+
+        let prune = |now: DateTime<Utc>| queue.database.run(move |connection| JobQueue::prune_held(connection, now).map_err(sql_error));
+        assert_eq!(prune(Utc::now()).await.unwrap(), 0);
+        // the pledges never came back for the issued and the rejected voucher
+        assert_eq!(prune(Utc::now() + chrono::Duration::days(2)).await.unwrap(), 2);
+
+        assert!(queue.is_held("pending").await.unwrap());
+        assert!(!queue.is_held("issued").await.unwrap());
+        assert!(!queue.is_held("rejected").await.unwrap());
+    }
+}
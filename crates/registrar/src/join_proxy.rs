@@ -0,0 +1,219 @@
+//! Stateless circuit proxy of RFC 8995 Section 4.
+//!
+//! Pledges on the join network can not reach the registrar themselves. The proxy accepts their TCP connections and
+//! forwards the byte stream to the registrar without looking into it, the TLS session is end to end between pledge and
+//! registrar. Every connection gets its own upstream connection, so the proxy keeps no state besides the open circuits.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use cli::config::JoinProxyConfig;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::Instant,
+};
+use tracing::{event, Level};
+
+/// Relays connections accepted on `listener` to the registrar, accept errors are retried like in
+/// [`common::http_server::accept`]
+pub(crate) async fn serve(listener: TcpListener, config: &JoinProxyConfig) {
+    let connections = Arc::new(Semaphore::new(config.max_connections));
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+
+    loop {
+        let permit = Arc::clone(&connections).acquire_owned().await.expect("connection semaphore is never closed");
+        let (pledge, remote) = common::http_server::accept(&listener).await;
+        let registrar_address = config.registrar_address.clone();
+
+        tokio::spawn(async move {
+            match circuit(pledge, &registrar_address, connect_timeout, idle_timeout).await {
+                Ok((upstream, downstream)) => event!(
+                    Level::INFO,
+                    "Closed circuit of {}, relayed {} bytes to and {} bytes from the registrar",
+                    remote,
+                    upstream,
+                    downstream
+                ),
+                Err(err) => event!(Level::WARN, "Circuit of {} failed: {}", remote, err),
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn circuit(
+    pledge: TcpStream,
+    registrar_address: &str,
+    connect_timeout: Duration,
+    idle_timeout: Duration,
+) -> io::Result<(u64, u64)> {
+    let registrar = tokio::time::timeout(connect_timeout, TcpStream::connect(registrar_address))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("connecting to {} timed out", registrar_address)))??;
+    let peer: SocketAddr = registrar.peer_addr()?;
+    event!(Level::DEBUG, "Opened circuit from {} to registrar {}", pledge.peer_addr()?, peer);
+
+    pledge.set_nodelay(true)?;
+    registrar.set_nodelay(true)?;
+    relay(pledge, registrar, idle_timeout).await
+}
+
+/// Copies both directions at the same time until both peers closed their side. Fails once neither direction carried
+/// data within `idle_timeout`, which also bounds a write the peer does not read. Returns the bytes relayed to and from
+/// `registrar`.
+async fn relay<P, R>(pledge: P, registrar: R, idle_timeout: Duration) -> io::Result<(u64, u64)>
+where
+    P: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + AsyncWrite + Unpin,
+{
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let mut pledge = Activity::new(pledge, last_activity.clone());
+    let mut registrar = Activity::new(registrar, last_activity.clone());
+
+    tokio::select! {
+        relayed = tokio::io::copy_bidirectional(&mut pledge, &mut registrar) => relayed,
+        () = idle(&last_activity, idle_timeout) => Err(io::Error::new(io::ErrorKind::TimedOut, "circuit idle timeout")),
+    }
+}
+
+/// Completes once `idle_timeout` passed since the last activity
+async fn idle(last_activity: &Mutex<Instant>, idle_timeout: Duration) {
+    loop {
+        let deadline = *last_activity.lock().unwrap() + idle_timeout;
+        if Instant::now() >= deadline {
+            return;
+        }
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
+/// Records when data was last read from or written to `stream`
+struct Activity<S> {
+    stream: S,
+    last_activity: Arc<Mutex<Instant>>,
+}
+
+impl<S> Activity<S> {
+    fn new(stream: S, last_activity: Arc<Mutex<Instant>>) -> Self {
+        Self { stream, last_activity }
+    }
+
+    fn record(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Activity<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(()))) && buf.filled().len() > filled {
+            self.record();
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Activity<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(written)) if written > 0) {
+            self.record();
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn spawn_echo() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        address
+    }
+
+    async fn spawn_proxy(config: JoinProxyConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, &config).await });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_relays_and_closes_idle_circuits() {
+        let registrar = spawn_echo().await;
+        let proxy = spawn_proxy(JoinProxyConfig {
+            registrar_address: registrar.to_string(),
+            idle_timeout_secs: 1,
+            ..Default::default()
+        })
+        .await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        stream.write_all(b"\x16\x03\x01client hello").await.unwrap();
+        let mut echoed = [0; 15];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"\x16\x03\x01client hello");
+
+        // the proxy closes the circuit once nothing was sent for the idle timeout
+        let mut rest = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest)).await;
+        assert!(matches!(read, Ok(Ok(0))), "{:?}", read);
+    }
+
+    #[tokio::test]
+    async fn test_limits_connections() {
+        let registrar = spawn_echo().await;
+        let proxy = spawn_proxy(JoinProxyConfig {
+            registrar_address: registrar.to_string(),
+            max_connections: 1,
+            ..Default::default()
+        })
+        .await;
+
+        let mut first = TcpStream::connect(proxy).await.unwrap();
+        first.write_all(b"first").await.unwrap();
+        first.read_exact(&mut [0; 5]).await.unwrap();
+
+        // the second connection waits in the backlog until the first one is closed
+        let mut second = TcpStream::connect(proxy).await.unwrap();
+        second.write_all(b"second").await.unwrap();
+        let mut echoed = [0; 6];
+        let waiting = tokio::time::timeout(Duration::from_millis(300), second.read_exact(&mut echoed)).await;
+        assert!(waiting.is_err());
+
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), second.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(&echoed, b"second");
+    }
+}
@@ -1,14 +1,68 @@
+mod admission;
+mod ca;
+mod capture;
 mod client;
+mod clones;
+mod cmp;
+mod coaps;
+mod devices;
+mod est;
+mod join_proxy;
+mod jobs;
+mod manufacturers;
+mod masa_resolver;
+mod mdns;
+mod oscore;
 mod parsed_config;
+mod quarantine;
+mod replay;
 mod server;
+mod sessions;
 mod sign_cert;
+mod snmp;
+mod sqlite;
+mod ssh;
+mod validation;
+mod voucher_cache;
+
+use std::sync::Arc;
 
 use cli::config::{RegistrarConfig};
-use common::error::AppError;
-use parsed_config::parse_config;
+use common::{error::AppError, http_server::TlsListener};
+use coaps::CoapsServer;
+use mdns::MdnsResponder;
+use oscore::OscoreServer;
+use openssl::{
+    error::ErrorStack,
+    ssl::{SslAcceptor, SslMethod, SslVerifyMode},
+    x509::store::X509StoreBuilder,
+};
+use parsed_config::{parse_config, ParsedConfig};
 use tokio::task::JoinHandle;
 use tracing::{event, Level};
 
+/// TLS of the HTTPS listener. Clients are asked for a certificate, but only IDevIDs chaining to the trust anchors of
+/// a manufacturer are taken as [`est::ClientIdevid`]; registrar-agents and pledges without one are still served.
+fn https_listener(parsed_config: &ParsedConfig) -> Result<TlsListener, ErrorStack> {
+    let mut store = X509StoreBuilder::new()?;
+    for anchor in parsed_config.manufacturers.trust_anchors() {
+        store.add_cert(anchor)?;
+    }
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    acceptor.set_certificate(&parsed_config.tls_certificate)?;
+    let key = openssl::pkey::PKey::from_ec_key(parsed_config.tls_key.clone())?;
+    acceptor.set_private_key(&key)?;
+    acceptor.check_private_key()?;
+    acceptor.set_verify_cert_store(store.build())?;
+    acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+
+    Ok(TlsListener {
+        acceptor: acceptor.build(),
+        extensions: Arc::new(est::session_extensions),
+    })
+}
+
 #[tracing::instrument(target = "Registrar", skip(config), name = "Registrar::start")]
 pub async fn start(config: RegistrarConfig) -> anyhow::Result<JoinHandle<()>, AppError> {
     let address = "0.0.0.0:".to_owned() + &config.port;
@@ -28,8 +82,94 @@ pub async fn start(config: RegistrarConfig) -> anyhow::Result<JoinHandle<()>, Ap
     let listener = tokio::net::TcpListener::bind(parsed_address).await?;
 
 
-    let server_handle = tokio::spawn(async {
-        axum::serve(listener, app).await.unwrap()
+    let mdns = if parsed_config.config.mdns.enabled {
+        Some(MdnsResponder::start(&parsed_config.config.mdns, parsed_address.port())?)
+    } else {
+        None
+    };
+
+    let https = match parsed_config.config.https_port {
+        Some(port) => {
+            let listener = tokio::net::TcpListener::bind(std::net::SocketAddr::from(([0, 0, 0, 0], port))).await?;
+            event!(Level::INFO, "Serving HTTPS on port {}", port);
+            let tls = https_listener(&parsed_config)?;
+            let (app, http) = (app.clone(), parsed_config.config.http.clone());
+            Some(tokio::spawn(async move { common::http_server::serve_tls(listener, app, &http, tls).await }))
+        }
+        None => None,
+    };
+
+    let coaps = if parsed_config.config.coaps.enabled {
+        let server = CoapsServer::bind(
+            &parsed_config.config.coaps,
+            &parsed_config.tls_certificate,
+            &parsed_config.tls_key,
+            &parsed_config.manufacturers.trust_anchors(),
+            app.clone(),
+        )
+        .await?;
+        Some(server.spawn())
+    } else {
+        None
+    };
+    let oscore = match parsed_config.config.coaps.oscore_port {
+        Some(port) if parsed_config.config.coaps.enabled => {
+            let server = OscoreServer::bind(
+                &parsed_config.config.coaps,
+                port,
+                &parsed_config.tls_certificate,
+                &parsed_config.tls_key,
+                &parsed_config.manufacturers.trust_anchors(),
+                app.clone(),
+            )
+            .await?;
+            Some(server.spawn())
+        }
+        _ => None,
+    };
+
+    let http = parsed_config.config.http.clone();
+    let server_handle = tokio::spawn(async move {
+        common::http_server::serve(listener, app, &http).await;
+        drop(mdns);
+        if let Some(coaps) = coaps {
+            coaps.abort();
+        }
+        if let Some(oscore) = oscore {
+            oscore.abort();
+        }
+        if let Some(https) = https {
+            https.abort();
+        }
+    });
+
+    Ok(server_handle)
+}
+
+/// Runs only the circuit proxy, on a host of the join network without the keys of the registrar
+#[tracing::instrument(target = "Registrar", skip(config), name = "Registrar::start_join_proxy")]
+pub async fn start_join_proxy(config: RegistrarConfig) -> anyhow::Result<JoinHandle<()>, AppError> {
+    let join_proxy = config.join_proxy;
+    let address = std::net::SocketAddr::from(([0, 0, 0, 0], join_proxy.port));
+
+    event!(
+        Level::INFO,
+        "Starting join proxy on {}, relaying to registrar {}",
+        address,
+        join_proxy.registrar_address
+    );
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+
+    let mdns = if config.mdns.enabled {
+        Some(MdnsResponder::start_join_proxy(&config.mdns, join_proxy.port)?)
+    } else {
+        None
+    };
+
+    let server_handle = tokio::spawn(async move {
+        join_proxy::serve(listener, &join_proxy).await;
+        drop(mdns);
     });
 
     Ok(server_handle)
@@ -0,0 +1,153 @@
+use cli::config::{ManufacturerConfig, RegistrarConfig};
+use common::{chain::chains_to, error::AppError};
+use openssl::x509::{X509Ref, X509};
+use tracing::{event, Level};
+
+use common::idevid::{issuer_identifiers, normalize_issuer};
+
+#[derive(Clone, Debug)]
+pub(crate) struct Manufacturer {
+    pub(crate) name: String,
+    pub(crate) config: ManufacturerConfig,
+    issuers: Vec<String>,
+    trust_anchors: Vec<X509>,
+}
+
+impl Manufacturer {
+    /// Checks that the IDevID chains to a trust anchor of the manufacturer, if any are configured
+    pub(crate) fn verify_idevid(&self, idevid: &X509Ref) -> Result<(), String> {
+        if self.trust_anchors.is_empty() {
+            return Ok(());
+        }
+
+        match chains_to(idevid, &self.trust_anchors, &[]) {
+            Ok(true) => Ok(()),
+            Ok(false) | Err(_) => Err(format!("IDevID does not chain to a trust anchor of manufacturer {}", self.name)),
+        }
+    }
+
+    pub(crate) fn trust_anchors(&self) -> &[X509] {
+        &self.trust_anchors
+    }
+
+    pub(crate) fn ldevid_curves<'a>(&'a self, config: &'a RegistrarConfig) -> &'a [String] {
+        self.config.ldevid_curves.as_deref().unwrap_or(&config.ldevid_curves)
+    }
+
+    pub(crate) fn ldevid_validity_days(&self, config: &RegistrarConfig) -> u32 {
+        self.config.ldevid_validity_days.unwrap_or(config.ldevid_validity_days)
+    }
+}
+
+/// Manufacturer sections of the registrar configuration, pledges without a matching section use the global settings
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Manufacturers {
+    manufacturers: Vec<Manufacturer>,
+}
+
+impl Manufacturers {
+    pub(crate) fn load(config: &RegistrarConfig) -> anyhow::Result<Self, AppError> {
+        let manufacturers = config
+            .manufacturers
+            .iter()
+            .map(|(name, manufacturer)| {
+                let trust_anchors = manufacturer
+                    .trust_anchors
+                    .iter()
+                    .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+                    .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+                Ok(Manufacturer {
+                    name: name.clone(),
+                    config: manufacturer.clone(),
+                    issuers: manufacturer.idevid_issuers.iter().map(|issuer| normalize_issuer(issuer)).collect(),
+                    trust_anchors,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+        Ok(Self { manufacturers })
+    }
+
+    /// Trust anchors of all manufacturers, e.g. for the DTLS client certificates of pledges
+    pub(crate) fn trust_anchors(&self) -> Vec<X509> {
+        self.manufacturers.iter().flat_map(|manufacturer| manufacturer.trust_anchors.iter().cloned()).collect()
+    }
+
+    /// Selects the section by the issuer common name or authority key identifier of the IDevID
+    pub(crate) fn select(&self, idevid: &X509Ref) -> Option<&Manufacturer> {
+        let identifiers = issuer_identifiers(idevid);
+        let manufacturer = self
+            .manufacturers
+            .iter()
+            .find(|manufacturer| manufacturer.issuers.iter().any(|issuer| identifiers.contains(issuer)));
+
+        if let Some(manufacturer) = manufacturer {
+            event!(Level::DEBUG, "Using settings of manufacturer {}", manufacturer.name);
+        }
+        manufacturer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn manufacturers(manufacturer: ManufacturerConfig, trust_anchors: Vec<X509>) -> Manufacturers {
+        let config = RegistrarConfig {
+            manufacturers: HashMap::from([("acme".to_string(), manufacturer)]),
+            ..Default::default()
+        };
+        let mut manufacturers = Manufacturers::load(&config).unwrap();
+        manufacturers.manufacturers[0].trust_anchors = trust_anchors;
+        manufacturers
+    }
+
+    #[test]
+    fn test_selects_manufacturer_by_issuer() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = &certs.pledge;
+        let issuer = issuer_identifiers(pledge_cert).remove(0);
+
+        let matching = manufacturers(
+            ManufacturerConfig {
+                idevid_issuers: vec![issuer],
+                ldevid_validity_days: Some(30),
+                ..Default::default()
+            },
+            vec![],
+        );
+        let manufacturer = matching.select(pledge_cert).unwrap();
+        assert_eq!(manufacturer.name, "acme");
+        assert_eq!(manufacturer.ldevid_validity_days(&RegistrarConfig::default()), 30);
+        assert_eq!(manufacturer.ldevid_curves(&RegistrarConfig::default()), RegistrarConfig::default().ldevid_curves);
+
+        let other = manufacturers(
+            ManufacturerConfig {
+                idevid_issuers: vec!["Other Vendor CA".to_string()],
+                ..Default::default()
+            },
+            vec![],
+        );
+        assert!(other.select(pledge_cert).is_none());
+    }
+
+    #[test]
+    fn test_verifies_idevid_against_trust_anchors() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = &certs.pledge;
+        let issuer = issuer_identifiers(pledge_cert).remove(0);
+        let config = ManufacturerConfig {
+            idevid_issuers: vec![issuer],
+            ..Default::default()
+        };
+
+        let trusted = manufacturers(config.clone(), vec![certs.vendor_ca.0.clone()]);
+        assert!(trusted.select(pledge_cert).unwrap().verify_idevid(pledge_cert).is_ok());
+
+        let untrusted = manufacturers(config, vec![certs.registrar_ca.0.clone()]);
+        assert!(untrusted.select(pledge_cert).unwrap().verify_idevid(pledge_cert).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use hickory_resolver::proto::rr::{rdata::NAPTR, RData, RecordType};
+use hickory_resolver::TokioAsyncResolver;
+use openssl::nid::Nid;
+use openssl::x509::X509Ref;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::manufacturers::Manufacturer;
+
+/// Label prepended to the manufacturer domain for the SRV lookup
+const MASA_SRV_LABEL: &str = "_brski-masa._tcp";
+
+/// S-NAPTR application service tag of the MASA (RFC 3958), matched case-insensitively
+const MASA_NAPTR_SERVICE: &str = "BRSKI-MASA:https";
+
+/// How long a failed SRV lookup is remembered before the domain is queried again
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+struct CachedLookup {
+    masa_url: Option<String>,
+    valid_until: Instant,
+}
+
+/// Where a NAPTR record of the manufacturer domain points to
+#[derive(Debug, PartialEq, Eq)]
+enum NaptrTarget {
+    /// `S` flag, the owner name of SRV records (RFC 3958)
+    Srv(String),
+    /// `A` flag, a host serving the MASA on the default port
+    Host(String),
+    /// `U` flag, the MASA URI produced by the regexp (RFC 4848)
+    Uri(String),
+}
+
+/// Finds the MASA responsible for a pledge.
+/// The MASA-URI extension of the IDevID is preferred, then the MASA url of the manufacturer section,
+/// then the DNS records of the manufacturer domain configured for the IDevID issuer, and finally the statically configured MASA url.
+/// For the domain, NAPTR records with the `BRSKI-MASA:https` service are looked up first, then the `_brski-masa._tcp` SRV records.
+#[derive(Debug)]
+pub(crate) struct MasaResolver {
+    fallback_url: String,
+    srv_domains: HashMap<String, String>,
+    resolver: Option<TokioAsyncResolver>,
+    cache: RwLock<HashMap<String, CachedLookup>>,
+}
+
+impl MasaResolver {
+    /// DNS lookups are only set up if `srv_domains` is not empty or `srv_lookups` is set
+    pub(crate) fn new(fallback_url: String, srv_domains: HashMap<String, String>, srv_lookups: bool) -> Self {
+        let resolver = if srv_domains.is_empty() && !srv_lookups {
+            None
+        } else {
+            match TokioAsyncResolver::tokio_from_system_conf() {
+                Ok(resolver) => Some(resolver),
+                Err(err) => {
+                    event!(Level::WARN, "Could not read system DNS configuration, NAPTR and SRV lookups are disabled: {}", err);
+                    None
+                }
+            }
+        };
+
+        Self {
+            fallback_url,
+            srv_domains,
+            resolver,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    #[tracing::instrument(target = "Registrar", skip(self, idevid, manufacturer))]
+    pub(crate) async fn resolve(&self, idevid: &X509Ref, manufacturer: Option<&Manufacturer>) -> String {
+        if let Some(masa_url) = masa_url_from_idevid(idevid) {
+            event!(Level::INFO, "Using MASA url from IDevID: {}", masa_url);
+            return masa_url;
+        }
+
+        if let Some(masa_url) = manufacturer.and_then(|manufacturer| manufacturer.config.masa_url.as_ref()) {
+            event!(Level::INFO, "Using MASA url of manufacturer section: {}", masa_url);
+            return masa_url.clone();
+        }
+
+        let domain = manufacturer
+            .and_then(|manufacturer| manufacturer.config.masa_srv_domain.as_ref())
+            .or_else(|| issuer_common_name(idevid).and_then(|issuer| self.srv_domains.get(&issuer)));
+        if let Some(domain) = domain {
+            if let Some(masa_url) = self.lookup(domain).await {
+                event!(Level::INFO, "Using MASA url from DNS records of {}: {}", domain, masa_url);
+                return masa_url;
+            }
+        }
+
+        event!(Level::INFO, "Using configured MASA url: {}", self.fallback_url);
+        self.fallback_url.clone()
+    }
+
+    async fn lookup(&self, domain: &str) -> Option<String> {
+        if let Some(cached) = self.cache.read().await.get(domain) {
+            if cached.valid_until > Instant::now() {
+                return cached.masa_url.clone();
+            }
+        }
+
+        let resolver = self.resolver.as_ref()?;
+        let domain_name = format!("{}.", domain.trim_end_matches('.'));
+
+        let found = match lookup_naptr(resolver, &domain_name).await {
+            Some(found) => Some(found),
+            None => lookup_srv(resolver, &format!("{}.{}", MASA_SRV_LABEL, domain_name)).await,
+        };
+        let cached = match found {
+            Some((masa_url, valid_until)) => CachedLookup {
+                masa_url: Some(masa_url),
+                valid_until,
+            },
+            None => CachedLookup {
+                masa_url: None,
+                valid_until: Instant::now() + NEGATIVE_CACHE_TTL,
+            },
+        };
+
+        self.cache.write().await.insert(domain.to_string(), cached.clone());
+        cached.masa_url
+    }
+}
+
+/// The MASA url of the first usable NAPTR record by order and preference, valid until the first of the records used expires
+async fn lookup_naptr(resolver: &TokioAsyncResolver, domain: &str) -> Option<(String, Instant)> {
+    let lookup = match resolver.lookup(domain, RecordType::NAPTR).await {
+        Ok(lookup) => lookup,
+        Err(err) => {
+            event!(Level::DEBUG, "NAPTR lookup for {} failed: {}", domain, err);
+            return None;
+        }
+    };
+
+    let mut records: Vec<&NAPTR> = lookup
+        .iter()
+        .filter_map(|record| match record {
+            RData::NAPTR(naptr) if naptr.services().eq_ignore_ascii_case(MASA_NAPTR_SERVICE.as_bytes()) => Some(naptr),
+            _ => None,
+        })
+        .collect();
+    records.sort_by_key(|naptr| (naptr.order(), naptr.preference()));
+
+    for naptr in records {
+        match naptr_target(naptr) {
+            Some(NaptrTarget::Srv(name)) => {
+                if let Some((masa_url, valid_until)) = lookup_srv(resolver, &name).await {
+                    return Some((masa_url, valid_until.min(lookup.valid_until())));
+                }
+            }
+            Some(NaptrTarget::Host(host)) => return Some((format!("https://{}", host), lookup.valid_until())),
+            Some(NaptrTarget::Uri(uri)) => return Some((uri, lookup.valid_until())),
+            None => event!(Level::WARN, "Ignoring NAPTR record of {} with unsupported flags or regexp", domain),
+        }
+    }
+    None
+}
+
+async fn lookup_srv(resolver: &TokioAsyncResolver, name: &str) -> Option<(String, Instant)> {
+    match resolver.srv_lookup(name).await {
+        Ok(lookup) => lookup
+            .iter()
+            .min_by_key(|srv| (srv.priority(), std::cmp::Reverse(srv.weight())))
+            .map(|srv| (srv_to_url(&srv.target().to_utf8(), srv.port()), lookup.as_lookup().valid_until())),
+        Err(err) => {
+            event!(Level::WARN, "SRV lookup for {} failed: {}", name, err);
+            None
+        }
+    }
+}
+
+/// Terminal records only, a NAPTR record without flags would need another NAPTR lookup of its replacement
+fn naptr_target(naptr: &NAPTR) -> Option<NaptrTarget> {
+    let replacement = naptr.replacement().to_utf8();
+    let replacement = replacement.trim_end_matches('.');
+
+    match naptr.flags().to_ascii_lowercase().as_slice() {
+        b"s" => Some(NaptrTarget::Srv(format!("{}.", replacement))),
+        b"a" => Some(NaptrTarget::Host(replacement.to_string())),
+        b"u" => uri_from_regexp(naptr.regexp()).map(NaptrTarget::Uri),
+        _ => None,
+    }
+}
+
+/// The substitution of a U-NAPTR regexp like `!.*!https://masa.example.com!` (RFC 4848 Section 2.2).
+/// Only substitutions without back-references are supported, the whole domain is replaced by the URI.
+fn uri_from_regexp(regexp: &[u8]) -> Option<String> {
+    let regexp = std::str::from_utf8(regexp).ok()?;
+    let delimiter = regexp.chars().next()?;
+    let [_, _, uri, ""] = regexp.split(delimiter).collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    (uri.starts_with("https://") && !uri.contains('\\')).then(|| uri.to_string())
+}
+
+fn srv_to_url(target: &str, port: u16) -> String {
+    format!("https://{}:{}", target.trim_end_matches('.'), port)
+}
+
+fn issuer_common_name(cert: &X509Ref) -> Option<String> {
+    cert.issuer_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok())
+}
+
+/// Reads the MASA-URI extension, which only carries the authority, so https is assumed when the scheme is missing
+pub(crate) fn masa_url_from_idevid(idevid: &X509Ref) -> Option<String> {
+    let authority = common::idevid::masa_uri(idevid)?;
+
+    if authority.contains("://") {
+        Some(authority)
+    } else {
+        Some(format!("https://{}", authority))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_naptr_targets() {
+        let naptr = |flags: &str, regexp: &str, replacement: &str| {
+            NAPTR::new(
+                10,
+                10,
+                flags.as_bytes().into(),
+                MASA_NAPTR_SERVICE.as_bytes().into(),
+                regexp.as_bytes().into(),
+                hickory_resolver::Name::from_ascii(replacement).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            naptr_target(&naptr("S", "", "_brski-masa._tcp.vendor.example.")),
+            Some(NaptrTarget::Srv("_brski-masa._tcp.vendor.example.".to_string()))
+        );
+        assert_eq!(naptr_target(&naptr("A", "", "masa.vendor.example.")), Some(NaptrTarget::Host("masa.vendor.example".to_string())));
+        assert_eq!(
+            naptr_target(&naptr("U", "!.*!https://masa.vendor.example:8443!", ".")),
+            Some(NaptrTarget::Uri("https://masa.vendor.example:8443".to_string()))
+        );
+        assert_eq!(naptr_target(&naptr("U", "!(.*)!https://\\1!", ".")), None);
+        assert_eq!(naptr_target(&naptr("", "", "vendor.example.")), None);
+    }
+
+    #[test]
+    fn test_srv_target_to_url() {
+        assert_eq!(srv_to_url("masa.example.com.", 443), "https://masa.example.com:443");
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_configured_url() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        assert_eq!(masa_url_from_idevid(&pledge_cert), None);
+
+        let resolver = MasaResolver::new("http://localhost:3000".to_string(), HashMap::new(), false);
+        assert_eq!(resolver.resolve(&pledge_cert, None).await, "http://localhost:3000");
+    }
+
+    #[test]
+    fn test_reads_masa_url_extension() {
+        use openssl::asn1::{Asn1Object, Asn1OctetString};
+        use openssl::x509::{X509Extension, X509NameBuilder, X509};
+
+        let key = openssl::ec::EcKey::generate(
+            &openssl::ec::EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap(),
+        )
+        .unwrap();
+        let key = openssl::pkey::PKey::from_ec_key(key).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "pledge").unwrap();
+        let name = name.build();
+
+        let authority = b"masa.example.com";
+        let mut ia5 = vec![0x16, authority.len() as u8];
+        ia5.extend_from_slice(authority);
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&openssl::bn::BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .append_extension(
+                X509Extension::new_from_der(
+                    &Asn1Object::from_str(common::idevid::MASA_URL_OID).unwrap(),
+                    false,
+                    &Asn1OctetString::new_from_bytes(&ia5).unwrap(),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        builder.sign(&key, openssl::hash::MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+
+        assert_eq!(masa_url_from_idevid(&cert).as_deref(), Some("https://masa.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_uses_cached_negative_result() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+        let issuer = issuer_common_name(&pledge_cert).unwrap();
+
+        let resolver = MasaResolver::new(
+            "http://localhost:3000".to_string(),
+            HashMap::from([(issuer, "vendor.example".to_string())]),
+            false,
+        );
+        resolver.cache.write().await.insert(
+            "vendor.example".to_string(),
+            CachedLookup {
+                masa_url: None,
+                valid_until: Instant::now() + NEGATIVE_CACHE_TTL,
+            },
+        );
+
+        assert_eq!(resolver.resolve(&pledge_cert, None).await, "http://localhost:3000");
+    }
+}
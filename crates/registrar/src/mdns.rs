@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use cli::config::MdnsConfig;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tracing::{event, Level};
+
+/// Service types of the registrar and of a join proxy, see RFC 8995 Section 4.2 and RFC 8995 Section 4.3
+const REGISTRAR_SERVICE: &str = "_brski-registrar._tcp.local.";
+const PROXY_SERVICE: &str = "_brski-proxy._tcp.local.";
+
+/// Well-known path prefix of the BRSKI endpoints, announced as `path` TXT record
+const BRSKI_PATH: &str = "/.well-known/brski";
+
+/// Answers mDNS queries for the registrar services until it is dropped
+pub(crate) struct MdnsResponder {
+    daemon: ServiceDaemon,
+    fullnames: Vec<String>,
+}
+
+impl MdnsResponder {
+    pub(crate) fn start(config: &MdnsConfig, listen_port: u16) -> anyhow::Result<Self> {
+        Self::register(services(config, listen_port)?)
+    }
+
+    /// Advertises only `_brski-proxy._tcp`, for a join proxy in front of a registrar on another host
+    pub(crate) fn start_join_proxy(config: &MdnsConfig, listen_port: u16) -> anyhow::Result<Self> {
+        Self::register(vec![proxy_service(config, listen_port)?])
+    }
+
+    fn register(services: Vec<ServiceInfo>) -> anyhow::Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let mut fullnames = vec![];
+
+        for service in services {
+            event!(
+                Level::INFO,
+                "Advertising {} on port {} via mDNS",
+                service.get_fullname(),
+                service.get_port()
+            );
+            fullnames.push(service.get_fullname().to_owned());
+            daemon.register(service)?;
+        }
+
+        Ok(Self { daemon, fullnames })
+    }
+}
+
+impl Drop for MdnsResponder {
+    fn drop(&mut self) {
+        // announces the removal, so browsers do not keep the services until their TTL expires
+        for fullname in &self.fullnames {
+            if let Err(err) = self.daemon.unregister(fullname) {
+                event!(Level::WARN, "Could not withdraw mDNS service {}: {}", fullname, err);
+            }
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+fn services(config: &MdnsConfig, listen_port: u16) -> anyhow::Result<Vec<ServiceInfo>> {
+    let hostname = format!("{}.local.", config.hostname);
+    let port = config.port.unwrap_or(listen_port);
+
+    let mut services = vec![
+        ServiceInfo::new(REGISTRAR_SERVICE, &config.instance_name, &hostname, (), port, properties(config))?
+            .enable_addr_auto(),
+    ];
+
+    if config.advertise_proxy {
+        services.push(proxy_service(config, port)?);
+    }
+
+    Ok(services)
+}
+
+fn proxy_service(config: &MdnsConfig, port: u16) -> anyhow::Result<ServiceInfo> {
+    let hostname = format!("{}.local.", config.hostname);
+    let proxy_port = config.proxy_port.unwrap_or(port);
+    Ok(
+        ServiceInfo::new(PROXY_SERVICE, &config.instance_name, &hostname, (), proxy_port, properties(config))?
+            .enable_addr_auto(),
+    )
+}
+
+fn properties(config: &MdnsConfig) -> HashMap<String, String> {
+    HashMap::from([
+        ("tls".to_owned(), if config.tls { "1" } else { "0" }.to_owned()),
+        ("path".to_owned(), BRSKI_PATH.to_owned()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_services() {
+        let config = MdnsConfig {
+            enabled: true,
+            port: Some(443),
+            tls: true,
+            ..Default::default()
+        };
+
+        let advertised = services(&config, 3001).unwrap();
+        assert_eq!(advertised.len(), 1);
+        assert_eq!(advertised[0].get_fullname(), "open-brski registrar._brski-registrar._tcp.local.");
+        assert_eq!(advertised[0].get_hostname(), "open-brski-registrar.local.");
+        assert_eq!(advertised[0].get_port(), 443);
+        assert_eq!(advertised[0].get_property_val_str("tls"), Some("1"));
+        assert_eq!(advertised[0].get_property_val_str("path"), Some(BRSKI_PATH));
+
+        let config = MdnsConfig {
+            enabled: true,
+            advertise_proxy: true,
+            ..Default::default()
+        };
+
+        let advertised = services(&config, 3001).unwrap();
+        assert_eq!(advertised.len(), 2);
+        assert_eq!(advertised[0].get_port(), 3001);
+        assert_eq!(advertised[0].get_property_val_str("tls"), Some("0"));
+        assert_eq!(advertised[1].get_type(), PROXY_SERVICE);
+        assert_eq!(advertised[1].get_port(), 3001);
+
+        // a join proxy on another host announces its own port
+        let proxy = proxy_service(&config, 3004).unwrap();
+        assert_eq!(proxy.get_fullname(), "open-brski registrar._brski-proxy._tcp.local.");
+        assert_eq!(proxy.get_port(), 3004);
+    }
+}
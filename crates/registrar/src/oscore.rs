@@ -0,0 +1,511 @@
+//! Plain CoAP listener for constrained pledges that protect their requests with OSCORE (RFC 8613) instead of DTLS.
+//!
+//! A pledge first runs EDHOC (RFC 9528) at `/.well-known/edhoc` as described in RFC 9528 Appendix A.2, authenticating
+//! with its IDevID against the trust anchors of the manufacturers. The OSCORE security context derived from the
+//! session protects its further requests, which are served by the same [`Exchange`] as the DTLS sessions. Sessions are
+//! found by the `kid` of a request, not by the address of the pledge, so a pledge whose address changes behind a NAT
+//! keeps its session.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{http::Extensions, Router};
+use cli::config::CoapsConfig;
+use common::coap::{self, code, Message, MessageType, EDHOC_MESSAGE_1_PREFIX, EDHOC_PATH};
+use common::edhoc::{self, AwaitingMessage3, Credential, Responder};
+use common::oscore::{self, OscoreOption, SecurityContext};
+use openssl::{
+    ec::EcKey,
+    pkey::{PKey, Private},
+    x509::X509,
+};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+use tracing::{event, Level};
+
+use crate::coaps::{Exchange, HANDSHAKE_TIMEOUT, MAX_DATAGRAM, MAX_SESSIONS, SESSION_QUEUE};
+use crate::est::{ChannelBinding, ClientIdevid};
+
+/// Length of the connection identifiers the registrar hands out, which are the OSCORE Sender IDs of the pledges
+const CONNECTION_ID_LENGTH: usize = 2;
+
+/// EDHOC run waiting for message_3, with the time message_2 was sent
+struct Handshake {
+    awaiting: AwaitingMessage3,
+    started: Instant,
+}
+
+/// CoAP with OSCORE listener for constrained pledges, see [`CoapsConfig::oscore_port`]
+#[derive(Clone)]
+pub(crate) struct OscoreServer {
+    socket: Arc<UdpSocket>,
+    certificate: X509,
+    key: PKey<Private>,
+    trust_anchors: Arc<Vec<X509>>,
+    config: CoapsConfig,
+    app: Router,
+}
+
+impl OscoreServer {
+    /// The registrar authenticates with `certificate`, which needs a P-256 key as EDHOC cipher suite 2 only has ES256
+    pub(crate) async fn bind(
+        config: &CoapsConfig,
+        port: u16,
+        certificate: &X509,
+        key: &EcKey<Private>,
+        trust_anchors: &[X509],
+        app: Router,
+    ) -> anyhow::Result<Self> {
+        if trust_anchors.is_empty() {
+            return Err(anyhow::anyhow!("OSCORE needs the trust_anchors of a [registrar.manufacturers] section to verify pledges"));
+        }
+        let key = PKey::from_ec_key(key.clone())?;
+        Credential::new(certificate.clone(), key.clone()).map_err(|err| anyhow::anyhow!("EDHOC credential: {}", err))?;
+
+        let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
+        event!(Level::INFO, "Serving CoAP with OSCORE on {}", socket.local_addr()?);
+        Ok(Self {
+            socket: Arc::new(socket),
+            certificate: certificate.clone(),
+            key,
+            trust_anchors: Arc::new(trust_anchors.to_vec()),
+            config: config.clone(),
+            app,
+        })
+    }
+
+    #[cfg(test)]
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub(crate) fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    /// Answers EDHOC itself and hands OSCORE requests to the task of their session
+    async fn run(self) {
+        let mut handshakes: HashMap<Vec<u8>, Handshake> = HashMap::new();
+        let mut sessions: HashMap<Vec<u8>, mpsc::Sender<(Message, SocketAddr)>> = HashMap::new();
+        // EDHOC responses by peer and message ID, for retransmitted confirmable requests
+        let mut answered: HashMap<(SocketAddr, u16), (Vec<u8>, Instant)> = HashMap::new();
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+
+        loop {
+            let (length, peer) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(err) => {
+                    event!(Level::WARN, "Could not receive CoAP datagram: {}", err);
+                    continue;
+                }
+            };
+            let request = match Message::decode(&buffer[..length]) {
+                Ok(request) => request,
+                Err(err) => {
+                    event!(Level::DEBUG, "Ignoring malformed CoAP message from {}: {}", peer, err);
+                    continue;
+                }
+            };
+            if !code::is_request(request.code) {
+                continue;
+            }
+
+            handshakes.retain(|_, handshake| handshake.started.elapsed() < HANDSHAKE_TIMEOUT);
+            answered.retain(|_, (_, sent)| sent.elapsed() < HANDSHAKE_TIMEOUT);
+            sessions.retain(|_, incoming| !incoming.is_closed());
+
+            if let Some(option) = request.option(coap::OSCORE) {
+                let session = OscoreOption::decode(option).ok().and_then(|option| option.kid).and_then(|kid| sessions.get(&kid));
+                match session {
+                    // a busy session drops requests like the network would, the pledge retransmits
+                    Some(incoming) => {
+                        let _ = incoming.try_send((request, peer));
+                    }
+                    // RFC 8613 Section 8.2, the error is not protected
+                    None => self.send(&Message::response(&request, code::UNAUTHORIZED).encode(), peer).await,
+                }
+                continue;
+            }
+
+            if request.message_type == MessageType::Confirmable {
+                if let Some((response, _)) = answered.get(&(peer, request.message_id)) {
+                    self.send(response, peer).await;
+                    continue;
+                }
+            }
+
+            let response = if request.uri_path() != EDHOC_PATH {
+                Message::response(&request, code::UNAUTHORIZED)
+            } else if request.code != code::POST {
+                Message::response(&request, code::METHOD_NOT_ALLOWED)
+            } else {
+                match request.payload.split_first() {
+                    Some((&EDHOC_MESSAGE_1_PREFIX, message_1)) if handshakes.len() + sessions.len() < MAX_SESSIONS => {
+                        self.message_1(&request, message_1, &mut handshakes, &sessions, peer)
+                    }
+                    Some((&EDHOC_MESSAGE_1_PREFIX, _)) => {
+                        event!(Level::WARN, "Dropping EDHOC message_1 of {}, {} OSCORE sessions are open", peer, MAX_SESSIONS);
+                        Message::response(&request, code::SERVICE_UNAVAILABLE)
+                    }
+                    _ => self.message_3(&request, &mut handshakes, &mut sessions, peer),
+                }
+            };
+
+            let response = response.encode();
+            if request.message_type == MessageType::Confirmable {
+                answered.insert((peer, request.message_id), (response.clone(), Instant::now()));
+            }
+            self.send(&response, peer).await;
+        }
+    }
+
+    async fn send(&self, datagram: &[u8], peer: SocketAddr) {
+        if let Err(err) = self.socket.send_to(datagram, peer).await {
+            event!(Level::DEBUG, "Could not send CoAP response to {}: {}", peer, err);
+        }
+    }
+
+    fn message_1(
+        &self,
+        request: &Message,
+        message_1: &[u8],
+        handshakes: &mut HashMap<Vec<u8>, Handshake>,
+        sessions: &HashMap<Vec<u8>, mpsc::Sender<(Message, SocketAddr)>>,
+        peer: SocketAddr,
+    ) -> Message {
+        let result = (|| {
+            let c_r = loop {
+                let mut c_r = vec![0u8; CONNECTION_ID_LENGTH];
+                openssl::rand::rand_bytes(&mut c_r)?;
+                if !handshakes.contains_key(&c_r) && !sessions.contains_key(&c_r) {
+                    break c_r;
+                }
+            };
+            let responder = Responder::new(Credential::new(self.certificate.clone(), self.key.clone())?, &c_r);
+            let (message_2, awaiting) = responder.process_message_1(message_1)?;
+            anyhow::Ok((c_r, message_2, awaiting))
+        })();
+
+        match result {
+            Ok((c_r, message_2, awaiting)) => {
+                handshakes.insert(c_r, Handshake { awaiting, started: Instant::now() });
+                let mut response = Message::response(request, code::CHANGED);
+                response.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::EDHOC_CBOR_SEQ));
+                response.payload = message_2;
+                response
+            }
+            Err(err) => {
+                event!(Level::INFO, "EDHOC message_1 of {} rejected: {}", peer, err);
+                bad_request(request, err)
+            }
+        }
+    }
+
+    fn message_3(
+        &self,
+        request: &Message,
+        handshakes: &mut HashMap<Vec<u8>, Handshake>,
+        sessions: &mut HashMap<Vec<u8>, mpsc::Sender<(Message, SocketAddr)>>,
+        peer: SocketAddr,
+    ) -> Message {
+        let (c_r, message_3) = match edhoc::split_connection_identifier(&request.payload) {
+            Ok(split) => split,
+            Err(err) => return bad_request(request, err),
+        };
+        let Some(handshake) = handshakes.remove(&c_r) else {
+            return bad_request(request, "no EDHOC session with this connection identifier");
+        };
+
+        let trust_anchors = self.trust_anchors.clone();
+        let trusted = |idevid: &X509| common::chain::chains_to(idevid, &trust_anchors, &[]).unwrap_or(false);
+        let result = handshake.awaiting.process_message_3(message_3, trusted).map_err(anyhow::Error::from).and_then(|session| {
+            let mut extensions = Extensions::new();
+            extensions.insert(ChannelBinding::from_edhoc(&session)?);
+            extensions.insert(ClientIdevid {
+                idevid: session.peer().clone(),
+                chain: vec![],
+            });
+            Ok((session.oscore_context()?, extensions))
+        });
+
+        match result {
+            Ok((context, extensions)) => {
+                event!(Level::INFO, "EDHOC session with {} established", peer);
+                let exchange = Exchange::new(self.app.clone(), peer, extensions, self.config.block_size);
+                let (incoming, receiver) = mpsc::channel(SESSION_QUEUE);
+                sessions.insert(c_r, incoming);
+                tokio::spawn(self.clone().session(context, exchange, receiver));
+                // without message_4 the registrar confirms with an empty response, RFC 9528 Appendix A.2.2
+                Message::response(request, code::CHANGED)
+            }
+            Err(err) => {
+                event!(Level::INFO, "EDHOC message_3 of {} rejected: {}", peer, err);
+                bad_request(request, err)
+            }
+        }
+    }
+
+    async fn session(self, mut context: SecurityContext, mut exchange: Exchange, mut incoming: mpsc::Receiver<(Message, SocketAddr)>) {
+        // last response to a confirmable request, a retransmission would otherwise be rejected as replay
+        let mut last: Option<(u16, Vec<u8>)> = None;
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout_secs);
+
+        while let Ok(Some((request, peer))) = tokio::time::timeout(idle_timeout, incoming.recv()).await {
+            if let Some((message_id, response)) = &last {
+                if request.message_type == MessageType::Confirmable && *message_id == request.message_id {
+                    self.send(response, peer).await;
+                    continue;
+                }
+            }
+
+            let response = match self.unprotect(&mut context, &request) {
+                Ok((partial_iv, inner)) => {
+                    let inner = exchange.respond(&inner).await;
+                    let mut response = Message::response(&request, code::CHANGED);
+                    match context.protect_response(&partial_iv, &oscore::plaintext(&inner)) {
+                        Ok(ciphertext) => {
+                            response.set_option(coap::OSCORE, OscoreOption::default().encode());
+                            response.payload = ciphertext;
+                        }
+                        Err(err) => {
+                            event!(Level::ERROR, "Could not protect the OSCORE response to {}: {}", peer, err);
+                            response.code = code::INTERNAL_SERVER_ERROR;
+                        }
+                    }
+                    response
+                }
+                Err(err) => {
+                    event!(Level::DEBUG, "Rejecting OSCORE request of {}: {}", peer, err);
+                    Message::response(&request, code::UNAUTHORIZED)
+                }
+            }
+            .encode();
+
+            if request.message_type == MessageType::Confirmable {
+                last = Some((request.message_id, response.clone()));
+            }
+            self.send(&response, peer).await;
+        }
+
+        event!(Level::INFO, "OSCORE session ended");
+    }
+
+    /// Decrypts a request, returning its partial IV and the inner request
+    fn unprotect(&self, context: &mut SecurityContext, request: &Message) -> anyhow::Result<(Vec<u8>, Message)> {
+        let option = OscoreOption::decode(request.option(coap::OSCORE).unwrap_or_default())?;
+        let plaintext = context.unprotect_request(&option.partial_iv, &request.payload)?;
+        let inner = oscore::inner_message(request, &plaintext)?;
+        if !code::is_request(inner.code) {
+            return Err(anyhow::anyhow!("inner message is no request"));
+        }
+        Ok((option.partial_iv, inner))
+    }
+}
+
+fn bad_request(request: &Message, err: impl std::fmt::Display) -> Message {
+    let mut response = Message::response(request, code::BAD_REQUEST);
+    response.payload = err.to_string().into_bytes();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Extension};
+    use common::coap_block::BLOCK2;
+    use common::coap_client::CoapClient;
+    use common::edhoc::Initiator;
+
+    use super::*;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/.well-known/brski/requestvoucher", post(|body: String| async move { format!("voucher for {}", body.len()) }))
+            .route(
+                "/.well-known/est/simpleenroll",
+                // EST answers in base64, which the exchange decodes
+                post(|binding: Option<Extension<ChannelBinding>>, idevid: Option<Extension<ClientIdevid>>| async move {
+                    let binding = binding.map(|Extension(binding)| binding.0).unwrap_or_default();
+                    let idevid = idevid.map(|Extension(client)| client.idevid.to_der().unwrap()).unwrap_or_default();
+                    openssl::base64::encode_block(&[binding, idevid].concat())
+                }),
+            )
+    }
+
+    async fn bind_server(certs: &example_certs::OpensslTestCerts, block_size: usize, app: Router) -> (u16, JoinHandle<()>) {
+        let (certificate, key) = &certs.registrar;
+        let config = CoapsConfig {
+            enabled: true,
+            oscore_port: Some(0),
+            block_size,
+            ..Default::default()
+        };
+        let server = OscoreServer::bind(&config, 0, certificate, &key.ec_key().unwrap(), std::slice::from_ref(&certs.vendor_ca.0), app)
+            .await
+            .unwrap();
+        (server.local_addr().unwrap().port(), server.spawn())
+    }
+
+    async fn spawn_server(certs: &example_certs::OpensslTestCerts) -> (UdpSocket, JoinHandle<()>) {
+        let (port, handle) = bind_server(certs, CoapsConfig::default().block_size, app()).await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(("127.0.0.1", port)).await.unwrap();
+        (socket, handle)
+    }
+
+    /// Relays datagrams between one client and the registrar, dropping the requests whose index is in `lost`
+    async fn lossy_link(port: u16, lost: &'static [usize]) -> (u16, JoinHandle<()>) {
+        let link = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        upstream.connect(("127.0.0.1", port)).await.unwrap();
+        let link_port = link.local_addr().unwrap().port();
+
+        let handle = tokio::spawn(async move {
+            let mut client = None;
+            let mut requests = 0;
+            let (mut request, mut response) = (vec![0u8; MAX_DATAGRAM], vec![0u8; MAX_DATAGRAM]);
+            loop {
+                tokio::select! {
+                    Ok((length, peer)) = link.recv_from(&mut request) => {
+                        client = Some(peer);
+                        if !lost.contains(&requests) {
+                            upstream.send(&request[..length]).await.unwrap();
+                        }
+                        requests += 1;
+                    }
+                    Ok(length) = upstream.recv(&mut response) => {
+                        if let Some(client) = client {
+                            link.send_to(&response[..length], client).await.unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        (link_port, handle)
+    }
+
+    async fn exchange(socket: &UdpSocket, request: &Message) -> Message {
+        socket.send(&request.encode()).await.unwrap();
+        let mut datagram = vec![0u8; MAX_DATAGRAM];
+        let read = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut datagram)).await.unwrap().unwrap();
+        Message::decode(&datagram[..read]).unwrap()
+    }
+
+    fn request(code: u8, message_id: u16, path: &str, payload: Vec<u8>) -> Message {
+        let mut request = Message {
+            message_type: MessageType::Confirmable,
+            code,
+            message_id,
+            token: vec![0x42],
+            options: vec![],
+            payload,
+        };
+        request.set_uri_path(path);
+        request
+    }
+
+    /// Runs EDHOC as the pledge, returning its session
+    async fn handshake(socket: &UdpSocket, certs: &example_certs::OpensslTestCerts, pledge: &(X509, PKey<Private>)) -> Result<edhoc::Session, u8> {
+        let (initiator, message_1) = Initiator::new(Credential::new(pledge.0.clone(), pledge.1.clone()).unwrap(), &[0x37]).unwrap();
+        let response = exchange(socket, &request(code::POST, 1, EDHOC_PATH, [&[EDHOC_MESSAGE_1_PREFIX][..], &message_1].concat())).await;
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.uint_option(coap::CONTENT_FORMAT), Some(u32::from(coap::EDHOC_CBOR_SEQ)));
+
+        let registrar = certs.registrar.0.clone();
+        let (message_3, session) = initiator.process_message_2(&response.payload, |cert| cert == &registrar).unwrap();
+        let mut message_3 = request(code::POST, 2, EDHOC_PATH, edhoc::prefix_connection_identifier(session.c_r(), &message_3).unwrap());
+        message_3.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::CID_EDHOC_CBOR_SEQ));
+
+        match exchange(socket, &message_3).await.code {
+            code::CHANGED => Ok(session),
+            code => Err(code),
+        }
+    }
+
+    /// Sends `inner` protected with `context` and returns the decrypted inner response
+    async fn protected(socket: &UdpSocket, context: &mut SecurityContext, message_id: u16, inner: &Message) -> Message {
+        let protected = context.protect_request(&oscore::plaintext(inner)).unwrap();
+        let mut outer = request(code::POST, message_id, "", protected.ciphertext.clone());
+        outer.set_option(coap::OSCORE, OscoreOption::request(&protected).encode());
+
+        let response = exchange(socket, &outer).await;
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.option(coap::OSCORE), Some(&[][..]));
+        let plaintext = context.unprotect_response(&protected.partial_iv, &response.payload).unwrap();
+        oscore::inner_message(&response, &plaintext).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_oscore_session() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (socket, handle) = spawn_server(&certs).await;
+        let session = handshake(&socket, &certs, &certs.pledge).await.unwrap();
+        let mut context = session.oscore_context().unwrap();
+
+        let response = protected(&socket, &mut context, 3, &request(code::POST, 0, "b/rv", b"pledge voucher request".to_vec())).await;
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.payload, b"voucher for 22");
+
+        // the handler sees the IDevID and the binding the pledge exports for its challengePassword
+        let mut sen = request(code::POST, 0, "est/sen", b"csr".to_vec());
+        sen.set_uint_option(coap::CONTENT_FORMAT, u32::from(coap::PKCS10));
+        let response = protected(&socket, &mut context, 4, &sen).await;
+        let binding = session.exporter(32768, &[], 32).unwrap();
+        assert_eq!(response.payload, [binding, certs.pledge.0.to_der().unwrap()].concat());
+
+        // unprotected requests and unknown senders are turned away
+        let response = exchange(&socket, &request(code::POST, 5, "b/rv", b"pledge voucher request".to_vec())).await;
+        assert_eq!(response.code, code::UNAUTHORIZED);
+        let mut unknown = request(code::POST, 6, "", vec![0; 16]);
+        unknown.set_option(coap::OSCORE, vec![0x09, 0x00, 0xff, 0xff]);
+        assert_eq!(exchange(&socket, &unknown).await.code, code::UNAUTHORIZED);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_untrusted_pledges() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (socket, handle) = spawn_server(&certs).await;
+
+        // the registrar certificate does not chain to the vendor CA
+        assert_eq!(handshake(&socket, &certs, &certs.registrar).await.err(), Some(code::BAD_REQUEST));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_client_fetches_multi_block_voucher() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        // the voucher carries the whole voucher request, so both have to be transferred block-wise
+        let app = Router::new().route(
+            "/.well-known/brski/requestvoucher",
+            post(|body: String| async move { format!("{{\"voucher\":\"{}\"}}", body) }),
+        );
+        let (port, server) = bind_server(&certs, 64, app).await;
+        // after message_1 and message_3, the second Block1 and the second Block2 request get lost and are retransmitted
+        let (link_port, link) = lossy_link(port, &[3, 6]).await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(("127.0.0.1", link_port)).await.unwrap();
+        let registrar = certs.registrar.0.clone();
+        let credential = Credential::new(certs.pledge.0.clone(), certs.pledge.1.clone()).unwrap();
+        let mut client = CoapClient::connect(socket, credential, |cert| cert == &registrar)
+            .await
+            .unwrap()
+            .with_block_size(128);
+
+        let pvr = "pledge voucher request ".repeat(20);
+        let response = client.request(&request(code::POST, 0, "b/rv", pvr.clone().into_bytes())).await.unwrap();
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.payload, format!("{{\"voucher\":\"{}\"}}", pvr).into_bytes());
+        assert!(response.option(BLOCK2).is_none());
+
+        // blocks are not mixed up between requests
+        let response = client.request(&request(code::POST, 0, "b/rv", b"short".to_vec())).await.unwrap();
+        assert_eq!(response.payload, br#"{"voucher":"short"}"#);
+
+        link.abort();
+        server.abort();
+    }
+}
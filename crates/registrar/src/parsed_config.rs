@@ -1,40 +1,67 @@
+use std::sync::Arc;
+
 use anyhow::anyhow;
-use cli::config::{RegistrarConfig};
+use cli::config::{CaBackend, RegistrarConfig};
 use common::error::AppError;
+use common::key_usage::{check_key_usage, KeyRole};
 use openssl::ec::{self, EcKey};
-use openssl::pkey::{Private};
+use openssl::pkey::{PKey, Private};
 use openssl::x509::X509;
+use tracing::{event, Level};
+
+use crate::ca::{CertificateAuthority, CmpCa, EstCa, OpensslCa, RcgenCa};
+use crate::cmp::RaIdentity;
+use crate::manufacturers::Manufacturers;
 
 #[derive(Clone, Debug)]
 pub(crate) struct ParsedConfig {
     pub(crate) config: RegistrarConfig,
-    pub(crate) ca_certificate: X509,
-    pub(crate) ca_key: EcKey<Private>,
+    /// Issues LDevIDs, see [`RegistrarConfig::ca_backend`]. Not set when running as a subordinate registrar, see
+    /// [`RegistrarConfig::parent_registrar_url`]
+    pub(crate) certificate_authority: Option<Arc<dyn CertificateAuthority>>,
     pub(crate) registrar_certificate: X509,
     pub(crate) registrar_key: EcKey<Private>,
+    /// DTLS and HTTPS server identity, see [`RegistrarConfig::tls_certificate`]
+    pub(crate) tls_certificate: X509,
+    pub(crate) tls_key: EcKey<Private>,
     pub(crate) reg_agt_ee_cert: X509,
     pub(crate) masa_url: String,
+    pub(crate) manufacturers: Manufacturers,
+    pub(crate) ssh_ca_key: Option<ssh_key::PrivateKey>,
+    /// See [`RegistrarConfig::proximity_registrar_certificates`]
+    pub(crate) proximity_registrar_certificates: Vec<X509>,
 }
 
 pub(crate) fn parse_config(config: RegistrarConfig) -> anyhow::Result<ParsedConfig, AppError> {
 
     let masa_url = config.masa_url.clone();
 
+    let manufacturers = Manufacturers::load(&config)?;
+
     let unparsed_reg_agt_ee_cert = std::fs::read(config.reg_agt_ee_cert.relative())?;
     let reg_agt_ee_cert = X509::from_pem(&unparsed_reg_agt_ee_cert)?;
 
-    let unparsed_ca_cert = std::fs::read(config.ca_certificate.relative())?;
-    let ca_certificate = X509::from_pem(&unparsed_ca_cert)?;
+    // an upstream CA issues LDevIDs without a local CA key, the rcgen backend loads its CA itself
+    let (ca_certificate, ca_key): (_, Option<EcKey<Private>>) = match config.parent_registrar_url {
+        Some(_) => (None, None),
+        None if config.ca_backend != CaBackend::Openssl => (None, None),
+        None => {
+            let unparsed_ca_cert = std::fs::read(config.ca_certificate.relative())?;
+            let ca_certificate = X509::from_pem(&unparsed_ca_cert)?;
 
-    if ca_certificate.subject_key_id().is_none() {
-        return Err(anyhow!(
-            "RegAgt EE Certificate missing critical attribute SubjectKeyIdentifier"
-        )
-        .into());
-    }
+            if ca_certificate.subject_key_id().is_none() {
+                return Err(anyhow!(
+                    "RegAgt EE Certificate missing critical attribute SubjectKeyIdentifier"
+                )
+                .into());
+            }
 
-    let unparsed_ca_key = std::fs::read(config.ca_key.relative())?;
-    let ca_key = ec::EcKey::private_key_from_pem(&unparsed_ca_key)?;
+            let unparsed_ca_key = std::fs::read(config.ca_key.relative())?;
+            let ca_key = ec::EcKey::private_key_from_pem(&unparsed_ca_key)?;
+
+            (Some(ca_certificate), Some(ca_key))
+        }
+    };
 
     let unparsed_registrar_cert = std::fs::read(config.registrar_certificate.relative())?;
     let registrar_certificate = X509::from_pem(&unparsed_registrar_cert)?;
@@ -42,16 +69,85 @@ pub(crate) fn parse_config(config: RegistrarConfig) -> anyhow::Result<ParsedConf
     let unparsed_registrar_key = std::fs::read(config.registrar_key.relative())?;
     let registrar_key = ec::EcKey::private_key_from_pem(&unparsed_registrar_key)?;
 
-    /// This registrar certificate must be signed by the CA certificate 
-    assert!(registrar_certificate.verify(&openssl::pkey::PKey::from_ec_key(ca_key.clone()).unwrap()).unwrap());
+    check_key_usage(&registrar_certificate, KeyRole::RegistrarSigning).map_err(|err| anyhow!("registrar_certificate: {}", err))?;
+
+    let (tls_certificate, tls_key) = match (&config.tls_certificate, &config.tls_key) {
+        (Some(certificate), Some(key)) => {
+            let tls_certificate = X509::from_pem(&std::fs::read(certificate.relative())?)?;
+            let tls_key = ec::EcKey::private_key_from_pem(&std::fs::read(key.relative())?)?;
+
+            check_key_usage(&tls_certificate, KeyRole::TlsServer).map_err(|err| anyhow!("tls_certificate: {}", err))?;
+            let registrar_public_key = registrar_certificate.public_key()?;
+            if tls_certificate.public_key()?.public_eq(&registrar_public_key) {
+                return Err(anyhow!("tls_certificate shares its key with registrar_certificate").into());
+            }
+
+            (tls_certificate, tls_key)
+        }
+        _ => {
+            if config.coaps.enabled || config.https_port.is_some() {
+                event!(Level::WARN, "No tls_certificate configured, DTLS and HTTPS reuse the registrar signing key");
+            }
+            (registrar_certificate.clone(), registrar_key.clone())
+        }
+    };
+
+    let ssh_ca_key = config
+        .ssh_ca_key
+        .as_ref()
+        .map(|path| ssh_key::PrivateKey::read_openssh_file(&path.relative()))
+        .transpose()
+        .map_err(|err| anyhow!("ssh_ca_key: {}", err))?;
+
+    let proximity_registrar_certificates = config
+        .proximity_registrar_certificates
+        .iter()
+        .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+        .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+    // This registrar certificate must be signed by the CA certificate 
+    if let Some(ca_key) = &ca_key {
+        assert!(registrar_certificate.verify(&openssl::pkey::PKey::from_ec_key(ca_key.clone()).unwrap()).unwrap());
+    }
+
+    let certificate_authority: Option<Arc<dyn CertificateAuthority>> = match (config.ca_backend, ca_certificate, ca_key) {
+        _ if config.parent_registrar_url.is_some() => None,
+        (CaBackend::Openssl, Some(ca_certificate), Some(ca_key)) => {
+            Some(Arc::new(OpensslCa::new(ca_certificate, PKey::from_ec_key(ca_key)?)))
+        }
+        (CaBackend::Rcgen, _, _) => {
+            let ca = RcgenCa::from_pem(&std::fs::read(config.ca_certificate.relative())?, &std::fs::read_to_string(config.ca_key.relative())?)
+                .map_err(|err| anyhow!("ca_backend rcgen: {}", err))?;
+            assert!(ca.issued(&registrar_certificate.to_der()?).map_err(|err| anyhow!("registrar_certificate: {}", err))?);
+            Some(Arc::new(ca))
+        }
+        (CaBackend::Est, _, _) => {
+            Some(Arc::new(EstCa::new(&config.upstream_ca).map_err(|err| anyhow!("ca_backend est: {}", err))?))
+        }
+        (CaBackend::Cmp, _, _) => {
+            // the registrar authenticates as RA with its signing identity, like towards the MASA
+            let identity = RaIdentity {
+                certificate: registrar_certificate.clone(),
+                key: PKey::from_ec_key(registrar_key.clone())?,
+            };
+            Some(Arc::new(
+                CmpCa::new(&config.upstream_ca, identity).map_err(|err| anyhow!("ca_backend cmp: {}", err))?,
+            ))
+        }
+        _ => None,
+    };
 
     Ok(ParsedConfig {
         config,
-        ca_certificate,
-        ca_key,
+        certificate_authority,
         registrar_certificate,
         registrar_key,
+        tls_certificate,
+        tls_key,
         reg_agt_ee_cert,
-        masa_url
+        masa_url,
+        manufacturers,
+        ssh_ca_key,
+        proximity_registrar_certificates,
     })
 }
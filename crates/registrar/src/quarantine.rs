@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use cli::config::RegistrarConfig;
+use common::idevid::{issuer_common_name, issuer_identifiers, normalize_issuer};
+use common::serial_pattern::{parse_serial_patterns, SerialPattern, SerialPatternError};
+use common::server_error::ServerError;
+use openssl::x509::X509Ref;
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+/// Upper bound of blocked attempts kept in memory, older attempts are dropped first
+const MAX_RECORDED_ATTEMPTS: usize = 1024;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BlockReason {
+    SerialBlocked,
+    IssuerBlocked,
+}
+
+impl std::fmt::Display for BlockReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockReason::SerialBlocked => write!(f, "serial-number is blocklisted"),
+            BlockReason::IssuerBlocked => write!(f, "IDevID issuer is blocklisted"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct BlockedAttempt {
+    pub(crate) serial_number: String,
+    pub(crate) idevid_issuer: Option<String>,
+    pub(crate) reason: BlockReason,
+    pub(crate) endpoint: String,
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The attempt was let through, as the blocklists were only evaluated in a quarantine dry run
+    pub(crate) dry_run: bool,
+}
+
+/// Keeps pledges from known-compromised batches away from onboarding.
+/// Pledges are matched by serial-number pattern or by their IDevID issuer, which is given either
+/// as the issuer common name or as the hex encoded authority key identifier.
+#[derive(Debug)]
+pub(crate) struct Quarantine {
+    blocked_serials: Vec<SerialPattern>,
+    blocked_issuers: HashSet<String>,
+    /// Serial-numbers blocked by a manufacturer section, only for IDevIDs of the section's issuers
+    manufacturer_serials: Vec<(HashSet<String>, Vec<SerialPattern>)>,
+    attempts: RwLock<Vec<BlockedAttempt>>,
+    dry_run: bool,
+}
+
+impl Quarantine {
+    pub(crate) fn new(config: &RegistrarConfig) -> Result<Self, SerialPatternError> {
+        Ok(Self {
+            blocked_serials: parse_serial_patterns(&config.blocked_serials)?,
+            blocked_issuers: config
+                .blocked_idevid_issuers
+                .iter()
+                .map(|issuer| normalize_issuer(issuer))
+                .collect(),
+            manufacturer_serials: config
+                .manufacturers
+                .values()
+                .filter(|manufacturer| !manufacturer.blocked_serials.is_empty())
+                .map(|manufacturer| {
+                    Ok((
+                        manufacturer.idevid_issuers.iter().map(|issuer| normalize_issuer(issuer)).collect(),
+                        parse_serial_patterns(&manufacturer.blocked_serials)?,
+                    ))
+                })
+                .collect::<Result<_, SerialPatternError>>()?,
+            attempts: RwLock::new(vec![]),
+            dry_run: config.quarantine_dry_run,
+        })
+    }
+
+    pub(crate) fn check(&self, serial_number: &str, idevid: &X509Ref) -> Option<BlockReason> {
+        if self.blocked_serials.iter().any(|pattern| pattern.matches(serial_number)) {
+            return Some(BlockReason::SerialBlocked);
+        }
+
+        let identifiers = issuer_identifiers(idevid);
+        if identifiers.iter().any(|identifier| self.blocked_issuers.contains(identifier)) {
+            return Some(BlockReason::IssuerBlocked);
+        }
+
+        if self.manufacturer_serials.iter().any(|(issuers, serials)| {
+            serials.iter().any(|pattern| pattern.matches(serial_number)) && identifiers.iter().any(|identifier| issuers.contains(identifier))
+        }) {
+            return Some(BlockReason::SerialBlocked);
+        }
+
+        None
+    }
+
+    /// Checks the pledge against the blocklist and records the attempt if it is blocked, in a quarantine dry run
+    /// blocked pledges are recorded but let through
+    pub(crate) async fn enforce(
+        &self,
+        serial_number: &str,
+        idevid: &X509Ref,
+        endpoint: &str,
+    ) -> Result<(), ServerError> {
+        match self.check(serial_number, idevid) {
+            Some(reason) if self.dry_run => {
+                self.record(serial_number, idevid, reason, endpoint).await;
+                Ok(())
+            }
+            Some(reason) => {
+                self.record(serial_number, idevid, reason, endpoint).await;
+                Err(ServerError::PledgeBlocked {
+                    serial_number: serial_number.to_string(),
+                    reason: reason.to_string(),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn record(
+        &self,
+        serial_number: &str,
+        idevid: &X509Ref,
+        reason: BlockReason,
+        endpoint: &str,
+    ) {
+        let attempt = BlockedAttempt {
+            serial_number: serial_number.to_string(),
+            idevid_issuer: issuer_common_name(idevid),
+            reason,
+            endpoint: endpoint.to_string(),
+            timestamp: Utc::now(),
+            dry_run: self.dry_run,
+        };
+
+        if self.dry_run {
+            event!(target: "Registrar::Quarantine", Level::WARN, "Dry run: pledge attempt would be blocked: {:?}", attempt);
+        } else {
+            event!(target: "Registrar::Quarantine", Level::WARN, "Blocked pledge attempt: {:?}", attempt);
+        }
+
+        let mut attempts = self.attempts.write().await;
+        if attempts.len() >= MAX_RECORDED_ATTEMPTS {
+            attempts.remove(0);
+        }
+        attempts.push(attempt);
+    }
+
+    pub(crate) async fn attempts(&self) -> Vec<BlockedAttempt> {
+        self.attempts.read().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quarantine_for(serials: &[&str], issuers: &[&str]) -> Quarantine {
+        let config = RegistrarConfig {
+            blocked_serials: serials.iter().map(|s| s.to_string()).collect(),
+            blocked_idevid_issuers: issuers.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        Quarantine::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_blocks_by_serial() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let quarantine = quarantine_for(&["00-D0-E5-F2-00-02"], &[]);
+
+        assert_eq!(
+            quarantine.check("00-D0-E5-F2-00-02", &pledge_cert),
+            Some(BlockReason::SerialBlocked)
+        );
+        assert_eq!(quarantine.check("00-D0-E5-F2-00-03", &pledge_cert), None);
+
+        let quarantine = quarantine_for(&["00-D0-E5-F2-00-00..00-D0-E5-F2-00-0F"], &[]);
+        assert_eq!(
+            quarantine.check("00-D0-E5-F2-00-02", &pledge_cert),
+            Some(BlockReason::SerialBlocked)
+        );
+        assert_eq!(quarantine.check("00-D0-E5-F2-00-12", &pledge_cert), None);
+    }
+
+    #[test]
+    fn test_blocks_by_issuer() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let issuer = issuer_common_name(&pledge_cert).unwrap();
+        let quarantine = quarantine_for(&[], &[&issuer]);
+
+        assert_eq!(
+            quarantine.check("00-D0-E5-F2-00-02", &pledge_cert),
+            Some(BlockReason::IssuerBlocked)
+        );
+
+        let quarantine = quarantine_for(&[], &["some-other-issuer"]);
+        assert_eq!(quarantine.check("00-D0-E5-F2-00-02", &pledge_cert), None);
+    }
+
+    #[test]
+    fn test_blocks_by_manufacturer_serial() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let manufacturer = |issuer: &str| cli::config::ManufacturerConfig {
+            idevid_issuers: vec![issuer.to_string()],
+            blocked_serials: vec!["00-D0-E5-F2-00-02".to_string()],
+            ..Default::default()
+        };
+        let quarantine_with = |issuer: &str| {
+            Quarantine::new(&RegistrarConfig {
+                manufacturers: std::collections::HashMap::from([("acme".to_string(), manufacturer(issuer))]),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        let issuer = issuer_common_name(&pledge_cert).unwrap();
+        assert_eq!(
+            quarantine_with(&issuer).check("00-D0-E5-F2-00-02", &pledge_cert),
+            Some(BlockReason::SerialBlocked)
+        );
+        assert_eq!(quarantine_with("some-other-issuer").check("00-D0-E5-F2-00-02", &pledge_cert), None);
+    }
+
+    #[tokio::test]
+    async fn test_records_attempts() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let quarantine = quarantine_for(&["00-D0-E5-F2-00-02"], &[]);
+        quarantine
+            .record("00-D0-E5-F2-00-02", &pledge_cert, BlockReason::SerialBlocked, "requestvoucher")
+            .await;
+
+        let attempts = quarantine.attempts().await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].serial_number, "00-D0-E5-F2-00-02");
+        assert_eq!(attempts[0].reason, BlockReason::SerialBlocked);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_records_but_admits() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let quarantine = Quarantine::new(&RegistrarConfig {
+            blocked_serials: vec!["00-D0-E5-F2-00-02".to_string()],
+            policy_dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+        // a policy dry run does not lift the quarantine
+        assert!(quarantine.enforce("00-D0-E5-F2-00-02", &pledge_cert, "requestvoucher").await.is_err());
+
+        let quarantine = Quarantine::new(&RegistrarConfig {
+            blocked_serials: vec!["00-D0-E5-F2-00-02".to_string()],
+            quarantine_dry_run: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(quarantine.enforce("00-D0-E5-F2-00-02", &pledge_cert, "requestvoucher").await.is_ok());
+
+        let attempts = quarantine.attempts().await;
+        assert_eq!(attempts.len(), 1);
+        assert!(attempts[0].dry_run);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::Mutex;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Seen {
+    /// SHA-256 over the signature values of the voucher request
+    Signature([u8; 32]),
+    /// Nonce of the voucher request of the pledge
+    Nonce(String, Vec<u8>),
+}
+
+/// Rejects voucher requests that were already received within the window, a window of 0 disables the check.
+///
+/// Pledges sign a new voucher request with a fresh nonce for every attempt, so the same signature or nonce
+/// arriving again was replayed, usually by a misbehaving proxy or agent. Replays are rejected before they reach the
+/// MASA.
+#[derive(Debug)]
+pub(crate) struct ReplayCache {
+    window: Duration,
+    seen: Mutex<HashMap<Seen, DateTime<Utc>>>,
+}
+
+impl ReplayCache {
+    pub(crate) fn new(window_secs: u64) -> Self {
+        Self {
+            window: Duration::seconds(window_secs as i64),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the voucher request of the pledge, returns the reason it is rejected if it was seen before
+    pub(crate) async fn check(&self, serial_number: &str, signature: &[u8], nonce: Option<&[u8]>) -> Result<(), String> {
+        if self.window <= Duration::zero() {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let mut seen = self.seen.lock().await;
+        seen.retain(|_, received| now - *received <= self.window);
+
+        let signature = Seen::Signature(openssl::sha::sha256(signature));
+        let nonce = nonce.map(|nonce| Seen::Nonce(serial_number.to_string(), nonce.to_vec()));
+
+        let reason = match (seen.get(&signature), nonce.as_ref().and_then(|nonce| seen.get(nonce))) {
+            (Some(received), _) => format!("the voucher request was already received at {}", received),
+            (None, Some(received)) => format!("the nonce of the voucher request was already used at {}", received),
+            (None, None) => {
+                seen.insert(signature, now);
+                seen.extend(nonce.map(|nonce| (nonce, now)));
+                return Ok(());
+            }
+        };
+
+        event!(Level::WARN, "Replayed voucher request of {}: {}", serial_number, reason);
+        Err(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_replays() {
+        let cache = ReplayCache::new(600);
+
+        assert!(cache.check("00-D0-E5-F2-00-02", b"signature", Some(b"nonce")).await.is_ok());
+        assert!(cache.check("00-D0-E5-F2-00-02", b"signature", Some(b"nonce")).await.is_err());
+        // signed again, but with the same nonce
+        assert!(cache.check("00-D0-E5-F2-00-02", b"resigned", Some(b"nonce")).await.is_err());
+        // nonces of other pledges are independent
+        assert!(cache.check("00-D0-E5-F2-00-03", b"other", Some(b"nonce")).await.is_ok());
+
+        // nonceless voucher requests are only matched by their signature
+        assert!(cache.check("00-D0-E5-F2-00-02", b"nonceless", None).await.is_ok());
+        assert!(cache.check("00-D0-E5-F2-00-02", b"nonceless-again", None).await.is_ok());
+        assert!(cache.check("00-D0-E5-F2-00-02", b"nonceless", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_window() {
+        let disabled = ReplayCache::new(0);
+        assert!(disabled.check("00-D0-E5-F2-00-02", b"signature", None).await.is_ok());
+        assert!(disabled.check("00-D0-E5-F2-00-02", b"signature", None).await.is_ok());
+
+        let cache = ReplayCache::new(600);
+        cache.seen.lock().await.insert(Seen::Signature(openssl::sha::sha256(b"signature")), Utc::now() - Duration::seconds(601));
+        assert!(cache.check("00-D0-E5-F2-00-02", b"signature", None).await.is_ok());
+        assert_eq!(cache.seen.lock().await.len(), 1);
+    }
+}
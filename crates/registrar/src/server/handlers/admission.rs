@@ -0,0 +1,27 @@
+use axum::{extract::State, Json};
+use tracing::{event, Level};
+
+use crate::{admission::AdmissionStatus, server::server::ServerState};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_admission(State(state): State<ServerState>) -> Json<AdmissionStatus> {
+    event!(Level::INFO, "Received admission status request");
+
+    Json(state.admission.status())
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_enable_maintenance(State(state): State<ServerState>) -> Json<AdmissionStatus> {
+    event!(Level::INFO, "Enabling maintenance mode");
+
+    state.admission.set_maintenance_mode(true);
+    Json(state.admission.status())
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_disable_maintenance(State(state): State<ServerState>) -> Json<AdmissionStatus> {
+    event!(Level::INFO, "Disabling maintenance mode");
+
+    state.admission.set_maintenance_mode(false);
+    Json(state.admission.status())
+}
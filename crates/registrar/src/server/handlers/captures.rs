@@ -0,0 +1,57 @@
+use axum::{
+    extract::{Path, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    Json,
+};
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{
+    capture::{Capture, CaptureSession, Download},
+    server::server::ServerState,
+};
+
+fn capture(state: &ServerState) -> Result<&Capture, ServerError> {
+    state
+        .capture
+        .as_deref()
+        .ok_or(ServerError::BadRequestWithReason("Capture is not enabled".to_string()))
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_captures(State(state): State<ServerState>) -> Result<Json<Vec<CaptureSession>>, ServerError> {
+    event!(Level::INFO, "Received captures listing request");
+
+    Ok(Json(capture(&state)?.sessions().await?))
+}
+
+/// The exchanges of a session as JSON array, or its bundle of JWEs as is if the capture is encrypted
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_download_capture(
+    State(state): State<ServerState>,
+    Path(session): Path<String>,
+) -> Result<Response, ServerError> {
+    event!(Level::INFO, "Downloading capture session {}", session);
+
+    let download = capture(&state)?
+        .download(&session)
+        .await?
+        .ok_or(ServerError::BadRequestWithReason(format!("No capture session {}", session)))?;
+
+    Ok(match download {
+        Download::Exchanges(exchanges) => (
+            [(CONTENT_DISPOSITION, format!("attachment; filename=\"{}.json\"", session))],
+            Json(exchanges),
+        )
+            .into_response(),
+        Download::Bundle(bundle) => (
+            [
+                (CONTENT_TYPE, "application/octet-stream".to_string()),
+                (CONTENT_DISPOSITION, format!("attachment; filename=\"{}.jwe\"", session)),
+            ],
+            bundle,
+        )
+            .into_response(),
+    })
+}
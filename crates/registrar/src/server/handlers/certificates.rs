@@ -0,0 +1,19 @@
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse, Json};
+use common::expiry::CertificateExpiry;
+use tracing::{event, Level};
+
+use crate::server::server::ServerState;
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_certificates(State(state): State<ServerState>) -> Json<Vec<CertificateExpiry>> {
+    event!(Level::INFO, "Received certificate expiry request");
+
+    Json(state.expiry.certificates().await)
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    event!(Level::DEBUG, "Received metrics request");
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], state.expiry.metrics().await)
+}
@@ -0,0 +1,11 @@
+use axum::{extract::State, Json};
+use tracing::{event, Level};
+
+use crate::{clones::CloneAlert, server::server::ServerState};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_clones(State(state): State<ServerState>) -> Json<Vec<CloneAlert>> {
+    event!(Level::INFO, "Received clone alerts listing request");
+
+    Json(state.clones.alerts().await)
+}
@@ -0,0 +1,12 @@
+use axum::response::Html;
+use tracing::{event, Level};
+
+// The dashboard is a single static page that is compiled into the binary, all data is fetched from the admin API
+const DASHBOARD: &str = include_str!("../../../assets/dashboard.html");
+
+#[tracing::instrument(target = "Registrar")]
+pub async fn handle_dashboard() -> Html<&'static str> {
+    event!(Level::INFO, "Received dashboard request");
+
+    Html(DASHBOARD)
+}
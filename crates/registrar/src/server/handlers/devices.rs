@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{devices::DeviceRecord, server::server::ServerState};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_devices(State(state): State<ServerState>) -> Result<Json<Vec<DeviceRecord>>, ServerError> {
+    event!(Level::INFO, "Received device inventory request");
+
+    Ok(Json(state.devices.devices().await?))
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_device(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+) -> Result<Json<DeviceRecord>, ServerError> {
+    event!(Level::INFO, "Received device request for {}", serial_number);
+
+    let device = state
+        .devices
+        .device(&serial_number)
+        .await?
+        .ok_or(ServerError::NotFound(format!("Device {} is not in the inventory", serial_number)))?;
+
+    Ok(Json(device))
+}
+
+/// Forgets a device, e.g. after it was decommissioned. Its LDevID stays valid, see `/ldevids/:serial_number/revoke`.
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_remove_device(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+) -> Result<StatusCode, ServerError> {
+    event!(Level::INFO, "Removing {} from the device inventory", serial_number);
+
+    if !state.devices.remove(&serial_number).await? {
+        return Err(ServerError::NotFound(format!("Device {} is not in the inventory", serial_number)));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,17 +1,19 @@
 use axum::{
     extract::State,
     http::{
-        header::{ACCEPT, CONTENT_TYPE},
+        header::CONTENT_TYPE,
         HeaderMap,
     },
 };
 use brski_prm_artifacts::{
-    ietf_voucher::request_artifact::VoucherRequestArtifact, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, rvr::RVR_JWS, status::{enroll::response::EnrollStatusJWS, voucher::response::vStatus_JWS}
+    jws::JWS, status::{enroll::response::EnrollStatusJWS, reason_code::ReasonCode}
 };
-use common::{server_error::ServerError, util::{is_jose, is_jws_voucher}};
+use common::{server_error::ServerError, util::is_jose};
 use tracing::{event, Level};
 
-use crate::{client, server::server::ServerState};
+use crate::{server::server::ServerState, sessions::SessionStage};
+
+use super::pledge_serial_number_from_header;
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Registrar", skip(state, headers, body))]
@@ -37,9 +39,24 @@ pub async fn handle_enrollstatus(
 
     let decoded = jws.decode()?;
 
-    let status = decoded.try_decoded_data()?.payload;
+    let decoded = decoded.try_decoded_data()?;
+    let pledge_serial_number = pledge_serial_number_from_header(decoded.header.as_ref());
+    let status = decoded.payload;
 
     event!(Level::INFO, "Enroll Status from Voucher: {:#?}", status);
+
+    if let Some(pledge_serial_number) = pledge_serial_number {
+        if !state.sessions.attested(&pledge_serial_number, "enrollstatus", status.attestation, state.config.config.require_attestation).await {
+            return Ok(());
+        }
+        if status.status {
+            state.devices.completed(&pledge_serial_number).await?;
+            state.sessions.advance(&pledge_serial_number, SessionStage::Completed).await;
+        } else {
+            let reason_code = status.reason_code.unwrap_or(ReasonCode::Unknown);
+            state.sessions.pledge_failed(&pledge_serial_number, "enrollstatus", reason_code, Some(&status.reason)).await;
+        }
+    }
     
     Ok(())
 }
@@ -0,0 +1,186 @@
+use axum::{
+    extract::State,
+    Extension,
+    http::{
+        header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use brski_prm_artifacts::{
+    content_type::{CSRATTRS, PKCS10, PKCS7, PKCS7_CERTS_ONLY},
+    ietf_voucher::serial_number,
+};
+use common::{asn1, server_error::ServerError};
+use tracing::{event, Level};
+
+use crate::{
+    devices::record_ldevid,
+    est::{check_channel_binding, decode_pkcs10, CachedResponse, ChannelBinding, ClientIdevid},
+    server::server::ServerState,
+    sign_cert,
+};
+
+use super::{check_idevid_revocation, pledge_serial_number};
+
+/// Whether one of the entity tags in `If-None-Match` is the current one. Weak tags compare like strong ones.
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn conditional_response(headers: &HeaderMap, cached: CachedResponse, content_type: &'static str) -> Response {
+    if not_modified(headers, &cached.etag) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, cached.etag)]).into_response();
+    }
+
+    (
+        [(CONTENT_TYPE, content_type.to_string()), (ETAG, cached.etag)],
+        cached.body,
+    )
+        .into_response()
+}
+
+/// EST `/cacerts`, the CA certificates of the registrar as base64 encoded certs-only PKCS#7
+#[tracing::instrument(target = "Registrar", skip(state, headers))]
+pub async fn handle_cacerts(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    event!(Level::INFO, "Received cacerts request");
+
+    let ca_certificates = state.ca_certificates().await?;
+
+    let cached = state.est_cache.cacerts(&ca_certificates).await?;
+
+    Ok(conditional_response(&headers, cached, PKCS7))
+}
+
+/// EST `/csrattrs`, the attributes the registrar expects in the CSR of a PER
+#[tracing::instrument(target = "Registrar", skip(state, headers))]
+pub async fn handle_csrattrs(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    event!(Level::INFO, "Received csrattrs request");
+
+    let cached = state.est_cache.csrattrs(&state.config.config.ldevid_curves).await?;
+
+    Ok(conditional_response(&headers, cached, CSRATTRS))
+}
+
+/// EST `/simpleenroll`, issues the LDevID for a base64 encoded PKCS#10 CSR through the CA of the registrar.
+/// Only pledges that authenticated their (D)TLS session with their IDevID are enrolled, the serialNumber of the CSR
+/// subject has to be the one of the IDevID. They pass the same quarantine, admission and revocation checks as on
+/// `/requestenroll`, requests of the plain HTTP listener are refused.
+/// The CSR has to carry the channel binding of that session as challengePassword, a CSR cut from another session
+/// is rejected.
+#[tracing::instrument(target = "Registrar", skip(state, binding, client, headers, body))]
+pub async fn handle_simpleenroll(
+    State(state): State<ServerState>,
+    binding: Option<Extension<ChannelBinding>>,
+    client: Option<Extension<ClientIdevid>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, ServerError> {
+    event!(Level::INFO, "Received simpleenroll request");
+
+    let Some(Extension(ClientIdevid { idevid, chain })) = client else {
+        return Err(ServerError::Unauthenticated {
+            reason: "simpleenroll requires a TLS client certificate with the IDevID of the pledge".to_string(),
+        });
+    };
+
+    let content_type = headers.get(CONTENT_TYPE).ok_or(ServerError::BadRequest)?.to_str().map_err(|_| ServerError::BadRequest)?;
+    if content_type.split(';').next().map(str::trim) != Some(PKCS10) {
+        return Err(ServerError::UnsupportedMediaType);
+    }
+
+    let csr = decode_pkcs10(&body).ok_or(ServerError::BadRequestWithReason("Body is not a base64 encoded PKCS#10 CSR".to_string()))?;
+    let serial_number = pledge_serial_number(&idevid)?;
+    let csr_serial_number = serial_number::from_name(csr.subject_name())
+        .map_err(|err| ServerError::BadRequestWithReason(format!("CSR subject: {}", err)))?;
+    if csr_serial_number != serial_number {
+        return Err(ServerError::PolicyViolation {
+            serial_number,
+            reason: format!("CSR is for {}, not for the pledge of the client certificate", csr_serial_number),
+        });
+    }
+
+    state.quarantine.enforce(&serial_number, &idevid, "simpleenroll").await?;
+
+    let admitted = state.sessions.is_admitted(&serial_number).await;
+    state.admission.enforce(&serial_number, admitted)?;
+    if !admitted {
+        return Err(ServerError::PolicyViolation {
+            serial_number,
+            reason: "no voucher was issued to the pledge".to_string(),
+        });
+    }
+
+    let manufacturer = state.config.manufacturers.select(&idevid);
+    if let Some(Err(reason)) = manufacturer.map(|manufacturer| manufacturer.verify_idevid(&idevid)) {
+        state.sessions.fail(&serial_number, "simpleenroll", &reason).await;
+        return Err(ServerError::PolicyViolation { serial_number, reason });
+    }
+    check_idevid_revocation(&state, &chain, &idevid, manufacturer, &serial_number, "simpleenroll").await?;
+
+    let Some(certificate_authority) = &state.config.certificate_authority else {
+        return Err(ServerError::BadResponse("Registrar has no local CA".to_string()));
+    };
+
+    if let Err(reason) = check_channel_binding(&csr, binding.as_ref().map(|Extension(binding)| binding)) {
+        state.sessions.fail(&serial_number, "simpleenroll", &reason).await;
+        return Err(ServerError::BadRequestWithReason(reason.to_string()));
+    }
+
+    let ldevid_curves = manufacturer.map_or(&state.config.config.ldevid_curves[..], |manufacturer| manufacturer.ldevid_curves(&state.config.config));
+    let validity_days = manufacturer.map_or(state.config.config.ldevid_validity_days, |manufacturer| manufacturer.ldevid_validity_days(&state.config.config));
+
+    if let Err(reason) = sign_cert::check_csr(&csr, ldevid_curves) {
+        state.sessions.fail(&serial_number, "simpleenroll", &reason).await;
+        return Err(ServerError::BadRequestWithReason(reason));
+    }
+
+    event!(Level::INFO, "Signing certificate for {}", serial_number);
+    let signed_cert = match certificate_authority.issue(&csr.to_der()?, validity_days).await {
+        Ok(signed_cert) => openssl::x509::X509::from_der(&signed_cert)?,
+        Err(err) => {
+            state.sessions.fail(&serial_number, "simpleenroll", &err).await;
+            return Err(err);
+        }
+    };
+
+    record_ldevid(state.devices.as_ref(), &serial_number, &idevid, &signed_cert).await;
+    state.sessions.enrolled(&serial_number, &signed_cert).await;
+    state.clones.enrolled(&serial_number).await;
+    state.expiry.track(format!("ldevid:{}", serial_number), &signed_cert).await;
+
+    let body = openssl::base64::encode_block(&asn1::encode_certs_only(&[signed_cert])?);
+    Ok(([(CONTENT_TYPE, PKCS7_CERTS_ONLY)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_not_modified() {
+        let mut headers = HeaderMap::new();
+        assert!(!not_modified(&headers, "\"abc\""));
+
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"other\", W/\"abc\""));
+        assert!(not_modified(&headers, "\"abc\""));
+        assert!(!not_modified(&headers, "\"def\""));
+
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(not_modified(&headers, "\"def\""));
+    }
+}
@@ -0,0 +1,22 @@
+use axum::{extract::State, Json};
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{
+    jobs::{JobRecord, PendingApproval},
+    server::server::ServerState,
+};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_jobs(State(state): State<ServerState>) -> Result<Json<Vec<JobRecord>>, ServerError> {
+    event!(Level::INFO, "Received job queue request");
+
+    Ok(Json(state.jobs.jobs().await?))
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_pending_approvals(State(state): State<ServerState>) -> Result<Json<Vec<PendingApproval>>, ServerError> {
+    event!(Level::INFO, "Received pending approvals request");
+
+    Ok(Json(state.jobs.pending_approvals().await?))
+}
@@ -0,0 +1,88 @@
+use axum::{
+    extract::{Path, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::IntoResponse,
+    Json,
+};
+use common::{pkcs12, server_error::ServerError};
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::{ca::RevocationReason, server::server::ServerState};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateExport {
+    password: String,
+}
+
+/// Certificate-only PKCS#12 of the LDevID and the registrar CA. It has no private key, the registrar never sees the
+/// LDevID key, which stays on the pledge. A bundle with the key is only written by the pledge, see `ldevid_pkcs12`.
+#[tracing::instrument(target = "Registrar", skip(state, export))]
+pub async fn handle_export_certificates(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+    Json(export): Json<CertificateExport>,
+) -> Result<impl IntoResponse, ServerError> {
+    event!(Level::INFO, "Exporting LDevID certificates of {} as PKCS#12", serial_number);
+
+    if export.password.is_empty() {
+        return Err(ServerError::BadRequestWithReason("PKCS#12 password must not be empty".to_string()));
+    }
+
+    let ldevid = state
+        .sessions
+        .ldevid(&serial_number)
+        .await
+        .ok_or(ServerError::BadRequestWithReason(format!("No LDevID was issued to {}", serial_number)))?;
+    // a subordinate registrar has no CA certificates to add
+    let chain = match &state.config.certificate_authority {
+        Some(_) => state.ca_certificates().await?,
+        None => vec![],
+    };
+
+    let der = pkcs12::bundle(&serial_number, None, &ldevid, &chain, &export.password)?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, pkcs12::PKCS12.to_string()),
+            (CONTENT_DISPOSITION, format!("attachment; filename=\"{}-certificates.p12\"", serial_number)),
+        ],
+        der,
+    ))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct LdevidRevocation {
+    reason: RevocationReason,
+}
+
+/// Revokes the LDevID of the pledge at the CA that issued it, only an upstream CMP server supports this
+#[tracing::instrument(target = "Registrar", skip(state, revocation))]
+pub async fn handle_revoke(
+    State(state): State<ServerState>,
+    Path(serial_number): Path<String>,
+    Json(revocation): Json<LdevidRevocation>,
+) -> Result<StatusCode, ServerError> {
+    event!(Level::INFO, "Revoking LDevID of {}", serial_number);
+
+    let ldevid = state
+        .sessions
+        .ldevid(&serial_number)
+        .await
+        .ok_or(ServerError::BadRequestWithReason(format!("No LDevID was issued to {}", serial_number)))?;
+    let certificate_authority = state
+        .config
+        .certificate_authority
+        .as_ref()
+        .ok_or(ServerError::BadRequestWithReason("LDevIDs are issued by the parent registrar".to_string()))?;
+
+    certificate_authority.revoke(&ldevid.to_der()?, revocation.reason).await?;
+    event!(Level::INFO, "Revoked LDevID of {} for {:?}", serial_number, revocation.reason);
+
+    Ok(StatusCode::NO_CONTENT)
+}
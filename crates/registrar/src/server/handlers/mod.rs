@@ -3,19 +3,130 @@ mod requestenroll;
 mod wrappedcacerts;
 mod voucher_status;
 mod enrollstatus;
-use axum::{routing::{get, post}, Router};
+mod quarantine;
+mod sessions;
+mod dashboard;
+mod voucher_cache;
+mod admission;
+mod clones;
+mod jobs;
+mod certificates;
+mod ldevids;
+mod requestsshcert;
+mod est;
+mod captures;
+mod devices;
+use axum::{middleware, routing::{get, post}, Router};
+use brski_prm_artifacts::ietf_voucher::serial_number;
 
+use common::{limits::{enforce_body_limit, Artifact, ArtifactLimits}, server_error::ServerError};
+use openssl::x509::X509;
+
+use crate::manufacturers::Manufacturer;
 
 use super::server::ServerState;
 
 #[tracing::instrument(target = "Registrar")]
-pub(crate) fn brski_routes() -> Router<ServerState> {
+pub(crate) fn brski_routes(limits: &ArtifactLimits) -> Router<ServerState> {
+    let guard = |artifact| middleware::from_fn_with_state(limits.guard(artifact), enforce_body_limit);
+
     Router::new().route(
         "/requestvoucher",
-        post(requestvoucher::handle_requestvoucher),
+        post(requestvoucher::handle_requestvoucher).layer(guard(Artifact::VoucherRequest)),
+
+    ).route("/requestenroll", post(requestenroll::handle_requestenroll).layer(guard(Artifact::Csr)))
+    .route("/requestsshcert", post(requestsshcert::handle_requestsshcert).layer(guard(Artifact::Csr)))
+    .route("/wrappedcacerts", get(wrappedcacerts::handle_wrappedcacerts).layer(guard(Artifact::Other)))
+    .route("/cacerts", get(est::handle_cacerts).layer(guard(Artifact::Other)))
+    .route("/csrattrs", get(est::handle_csrattrs).layer(guard(Artifact::Other)))
+    .route("/voucher_status", post(voucher_status::handle_voucher_status).layer(guard(Artifact::Telemetry)))
+    .route("/enrollstatus", post(enrollstatus::handle_enrollstatus).layer(guard(Artifact::Telemetry)))
+}
+
+/// EST (RFC 7030) operations at `/.well-known/est`. `/cacerts` and `/csrattrs` are also served below `/.well-known/brski`.
+#[tracing::instrument(target = "Registrar")]
+pub(crate) fn est_routes(limits: &ArtifactLimits) -> Router<ServerState> {
+    let guard = |artifact| middleware::from_fn_with_state(limits.guard(artifact), enforce_body_limit);
+
+    Router::new()
+        .route("/simpleenroll", post(est::handle_simpleenroll).layer(guard(Artifact::Csr)))
+        .route("/cacerts", get(est::handle_cacerts).layer(guard(Artifact::Other)))
+        .route("/csrattrs", get(est::handle_csrattrs).layer(guard(Artifact::Other)))
+}
+
+/// The dashboard page itself carries no data, every call it makes goes through the authenticated admin routes
+#[tracing::instrument(target = "Registrar")]
+pub(crate) fn dashboard_routes() -> Router<ServerState> {
+    Router::new().route("/dashboard", get(dashboard::handle_dashboard))
+}
+
+#[tracing::instrument(target = "Registrar")]
+pub(crate) fn admin_routes() -> Router<ServerState> {
+    Router::new()
+        .route("/quarantine", get(quarantine::handle_quarantine))
+        .route("/sessions", get(sessions::handle_sessions))
+        .route("/inventory", get(sessions::handle_inventory))
+        .route("/failures", get(sessions::handle_failures))
+        .route("/failures/reasons", get(sessions::handle_failure_reasons))
+        .route("/clones", get(clones::handle_clones))
+        .route("/voucher-cache", get(voucher_cache::handle_voucher_cache))
+        .route("/jobs", get(jobs::handle_jobs))
+        .route("/pending-approvals", get(jobs::handle_pending_approvals))
+        .route("/devices", get(devices::handle_devices))
+        .route("/devices/:serial_number", get(devices::handle_device).delete(devices::handle_remove_device))
+        .route("/certificates", get(certificates::handle_certificates))
+        .route("/metrics", get(certificates::handle_metrics))
+        .route("/ldevids/:serial_number/certificates.p12", post(ldevids::handle_export_certificates))
+        .route("/ldevids/:serial_number/revoke", post(ldevids::handle_revoke))
+        .route("/admission", get(admission::handle_admission))
+        .route("/admission/maintenance/enable", post(admission::handle_enable_maintenance))
+        .route("/admission/maintenance/disable", post(admission::handle_disable_maintenance))
+        .route("/captures", get(captures::handle_captures))
+        .route("/captures/:session", get(captures::handle_download_capture))
+}
+
+/// Reads the pledge serial-number from the IDevID certificate in the x5c header of a pledge artifact
+pub(crate) fn pledge_serial_number_from_header(header: Option<&josekit::jws::JwsHeader>) -> Option<String> {
+    let idevid = header?.x509_certificate_chain()?.first()?.clone();
+    let idevid = openssl::x509::X509::from_der(&idevid).ok()?;
+    pledge_serial_number(&idevid).ok()
+}
+
+/// serialNumber of the IDevID subject, which may be any valid UTF-8 serial-number
+pub(crate) fn pledge_serial_number(idevid: &X509) -> Result<String, ServerError> {
+    serial_number::from_name(idevid.subject_name()).map_err(|err| ServerError::BadRequestWithReason(format!("IDevID: {}", err)))
+}
+
+/// Certificates of the x5c header following the signer, the intermediates up to its trust anchor
+pub(crate) fn x5c_issuers(header: &josekit::jws::JwsHeader) -> Vec<X509> {
+    header
+        .x509_certificate_chain()
+        .unwrap_or_default()
+        .iter()
+        .skip(1)
+        .filter_map(|der| X509::from_der(der).ok())
+        .collect()
+}
+
+/// Checks the revocation status of the IDevID, issued by a certificate of `chain` or a trust anchor of its manufacturer
+pub(crate) async fn check_idevid_revocation(
+    state: &ServerState,
+    chain: &[X509],
+    idevid: &X509,
+    manufacturer: Option<&Manufacturer>,
+    serial_number: &str,
+    step: &str,
+) -> Result<(), ServerError> {
+    let mut issuers = chain.to_vec();
+    issuers.extend(manufacturer.map(|manufacturer| manufacturer.trust_anchors().to_vec()).unwrap_or_default());
 
-    ).route("/requestenroll", post(requestenroll::handle_requestenroll))
-    .route("/wrappedcacerts", get(wrappedcacerts::handle_wrappedcacerts))
-    .route("/voucher_status", post(voucher_status::handle_voucher_status))
-    .route("/enrollstatus", post(enrollstatus::handle_enrollstatus))
+    if let Err(reason) = state.revocation.check(idevid, &issuers).await {
+        let reason = reason.to_string();
+        state.sessions.fail(serial_number, step, &reason).await;
+        return Err(ServerError::RevocationCheckFailed {
+            subject: serial_number.to_string(),
+            reason,
+        });
+    }
+    Ok(())
 }
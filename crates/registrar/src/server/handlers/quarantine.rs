@@ -0,0 +1,11 @@
+use axum::{extract::State, Json};
+use tracing::{event, Level};
+
+use crate::{quarantine::BlockedAttempt, server::server::ServerState};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_quarantine(State(state): State<ServerState>) -> Json<Vec<BlockedAttempt>> {
+    event!(Level::INFO, "Received quarantine listing request");
+
+    Json(state.quarantine.attempts().await)
+}
@@ -1,22 +1,32 @@
 use axum::{
     extract::State,
+    Extension,
     http::{
         header::{ACCEPT, CONTENT_TYPE},
         HeaderMap,
     },
 };
-use brski_prm_artifacts::{
-    ietf_voucher::{pki::X509Req, request_artifact::VoucherRequestArtifact}, issued_voucher::IssuedVoucherJWS, jws::JWS, per::response::PER_JWS, pvr::response::PVR_JWS, rer, rvr::RVR_JWS
-};
-use common::{server_error::ServerError, util::is_jws_voucher};
+use brski_prm_artifacts::{jws::JWS, per::response::RAW_PER_JWS, rer};
+use common::{asn1, server_error::ServerError, util::is_jws_voucher};
 use tracing::{event, Level};
 
-use crate::{client, server::server::ServerState, sign_cert};
+use crate::{
+    client,
+    devices::record_ldevid,
+    est::{check_channel_binding, ChannelBinding, ClientIdevid},
+    server::server::ServerState,
+};
+
+use super::{check_idevid_revocation, pledge_serial_number, x5c_issuers};
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
-#[tracing::instrument(target = "Registrar", skip(state, headers, body))]
+// A pledge that sends its PER itself over the HTTPS listener, authenticated with the IDevID that signed it, binds its
+// CSR to the TLS session like on `/simpleenroll`. PERs relayed by a registrar-agent arrive on the session of the agent.
+#[tracing::instrument(target = "Registrar", skip(state, binding, client, headers, body))]
 pub async fn handle_requestenroll(
     State(state): State<ServerState>,
+    binding: Option<Extension<ChannelBinding>>,
+    client: Option<Extension<ClientIdevid>>,
     headers: HeaderMap,
     body: String,
 ) -> Result<rer::response::Response, ServerError> {
@@ -33,24 +43,90 @@ pub async fn handle_requestenroll(
     is_jws_voucher(accept)?;
 
     event!(Level::INFO, "Parsing PER JWS from body");
-    let jws: PER_JWS = JWS::Encoded(body.clone());
+    let jws: RAW_PER_JWS = JWS::Encoded(body.clone());
     
     let decoded = jws.decode()?;
     
     event!(Level::DEBUG, "Decoded PER JWS: {:#?}", decoded);
 
-    let csr: X509Req = decoded.try_decoded_data()?.payload.csr.p10_csr;
-
-    let registrar_ca_cert = state.config.ca_certificate.clone();
-    let registrar_ca_key = state.config.ca_key.clone();
-
-    let registrar_sign_cert = state.config.registrar_certificate.clone();
-    let registrar_sign_key = state.config.registrar_key.clone();
-
-    let pkey = openssl::pkey::PKey::from_ec_key(state.config.registrar_key.clone()).unwrap();
+    let per = decoded.try_decoded_data()?;
+
+    // the CSR is decoded in pure Rust before openssl sees it, like the body of `/simpleenroll`
+    let csr = asn1::certification_request(&per.payload.csr.p10_csr)
+        .map_err(|err| ServerError::BadRequestWithReason(format!("PER carries a malformed CSR: {}", err)))?;
+
+    let per_headers = per.header.ok_or(ServerError::BadRequest)?;
+    let pledge_idevid_cert = per_headers.x509_certificate_chain().ok_or(ServerError::BadRequest)?.first().ok_or(ServerError::BadRequest)?.clone();
+    let pledge_idevid_cert = openssl::x509::X509::from_der(&pledge_idevid_cert).map_err(|_| ServerError::BadRequest)?;
+
+    let pledge_serial_number = pledge_serial_number(&pledge_idevid_cert)?;
+
+    state.quarantine.enforce(&pledge_serial_number, &pledge_idevid_cert, "requestenroll").await?;
+
+    let admitted = state.sessions.is_admitted(&pledge_serial_number).await;
+    state.admission.enforce(&pledge_serial_number, admitted)?;
+
+    let manufacturer = state.config.manufacturers.select(&pledge_idevid_cert);
+    if let Some(Err(reason)) = manufacturer.map(|manufacturer| manufacturer.verify_idevid(&pledge_idevid_cert)) {
+        state.sessions.fail(&pledge_serial_number, "requestenroll", &reason).await;
+        return Err(ServerError::PolicyViolation { serial_number: pledge_serial_number, reason });
+    }
+    check_idevid_revocation(&state, &x5c_issuers(&per_headers), &pledge_idevid_cert, manufacturer, &pledge_serial_number, "requestenroll").await?;
+
+    if let Some(Extension(client)) = client {
+        let bound = if client.idevid.to_der()? != pledge_idevid_cert.to_der()? {
+            Err("PER is not signed with the IDevID of the TLS client")
+        } else {
+            check_channel_binding(&csr, binding.as_ref().map(|Extension(binding)| binding))
+        };
+        if let Err(reason) = bound {
+            state.sessions.fail(&pledge_serial_number, "requestenroll", &reason).await;
+            return Err(ServerError::PolicyViolation { serial_number: pledge_serial_number, reason: reason.to_string() });
+        }
+    }
+
+    // As a subordinate registrar there is no local CA, the parent registrar issues the LDevID
+    if state.config.config.parent_registrar_url.is_some() {
+        let signed_cert = match client::forward_enroll_request(&state.config, &state.client, body).await {
+            Ok(signed_cert) => signed_cert,
+            Err(err) => {
+                state.sessions.fail(&pledge_serial_number, "requestenroll", &err).await;
+                return Err(err);
+            }
+        };
+
+        record_ldevid(state.devices.as_ref(), &pledge_serial_number, &pledge_idevid_cert, &signed_cert).await;
+        state.sessions.enrolled(&pledge_serial_number, &signed_cert).await;
+        state.clones.enrolled(&pledge_serial_number).await;
+        state.expiry.track(format!("ldevid:{}", pledge_serial_number), &signed_cert).await;
+
+        event!(Level::INFO, "Returning certificate issued by parent registrar");
+        return Ok(rer::response::Response(signed_cert.into()));
+    }
+
+    let certificate_authority = state.config.certificate_authority.clone().ok_or(ServerError::BadResponse("Registrar has no local CA".to_string()))?;
+
+    let ldevid_curves = manufacturer.map_or(&state.config.config.ldevid_curves[..], |manufacturer| manufacturer.ldevid_curves(&state.config.config));
+    let validity_days = manufacturer.map_or(state.config.config.ldevid_validity_days, |manufacturer| manufacturer.ldevid_validity_days(&state.config.config));
+
+    if let Err(reason) = crate::sign_cert::check_csr(&csr, ldevid_curves) {
+        state.sessions.fail(&pledge_serial_number, "requestenroll", &reason).await;
+        return Err(ServerError::BadRequestWithReason(reason));
+    }
 
     event!(Level::INFO, "Signing certificate");
-    let (signed_cert, signed_cert_pkey) = crate::sign_cert::mk_ca_signed_cert(&registrar_ca_cert, &pkey, &csr)?;
+    let signed_cert = match certificate_authority.issue(&csr.to_der()?, validity_days).await {
+        Ok(signed) => openssl::x509::X509::from_der(&signed)?,
+        Err(err) => {
+            state.sessions.fail(&pledge_serial_number, "requestenroll", &err).await;
+            return Err(err);
+        }
+    };
+
+    record_ldevid(state.devices.as_ref(), &pledge_serial_number, &pledge_idevid_cert, &signed_cert).await;
+    state.sessions.enrolled(&pledge_serial_number, &signed_cert).await;
+    state.clones.enrolled(&pledge_serial_number).await;
+    state.expiry.track(format!("ldevid:{}", pledge_serial_number), &signed_cert).await;
 
     event!(Level::INFO, "Created certificate for pledge");
     event!(Level::DEBUG, "Signed certificate: {:#?}", signed_cert);
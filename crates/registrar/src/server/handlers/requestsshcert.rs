@@ -0,0 +1,78 @@
+use axum::{
+    extract::State,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap,
+    },
+    response::IntoResponse,
+};
+use brski_prm_artifacts::{content_type::SSH_CERTIFICATE, jws::JWS, per::response::RAW_PER_JWS};
+use common::{server_error::ServerError, util::is_jws_voucher};
+use tracing::{event, Level};
+
+use crate::{server::server::ServerState, ssh};
+
+use super::{check_idevid_revocation, pledge_serial_number, x5c_issuers};
+
+/// Takes the same PER as `/requestenroll` and answers with an OpenSSH host certificate for the host key in it.
+/// The IDevID that signed the PER is checked the same way, so the host key is as trustworthy as the LDevID.
+#[tracing::instrument(target = "Registrar", skip(state, headers, body))]
+pub async fn handle_requestsshcert(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<impl IntoResponse, ServerError> {
+    event!(Level::INFO, "Received requestsshcert request");
+
+    let content_type = headers.get(CONTENT_TYPE).ok_or(ServerError::BadRequest)?.to_str().map_err(|_| ServerError::BadRequest)?;
+    is_jws_voucher(content_type)?;
+
+    let accept = headers.get(ACCEPT).ok_or(ServerError::BadRequest)?.to_str().map_err(|_| ServerError::BadRequest)?;
+    if accept != SSH_CERTIFICATE {
+        return Err(ServerError::NotAcceptible);
+    }
+
+    let ca_key = state
+        .config
+        .ssh_ca_key
+        .as_ref()
+        .ok_or(ServerError::BadRequestWithReason("Registrar has no SSH CA".to_string()))?;
+
+    // the CSR of the PER is not used, it is not parsed either
+    let jws: RAW_PER_JWS = JWS::Encoded(body);
+    let per = jws.decode()?.try_decoded_data()?;
+
+    let per_headers = per.header.ok_or(ServerError::BadRequest)?;
+    let pledge_idevid_cert = per_headers.x509_certificate_chain().ok_or(ServerError::BadRequest)?.first().ok_or(ServerError::BadRequest)?.clone();
+    let pledge_idevid_cert = openssl::x509::X509::from_der(&pledge_idevid_cert).map_err(|_| ServerError::BadRequest)?;
+
+    let pledge_serial_number = pledge_serial_number(&pledge_idevid_cert)?;
+
+    state.quarantine.enforce(&pledge_serial_number, &pledge_idevid_cert, "requestsshcert").await?;
+
+    let admitted = state.sessions.is_admitted(&pledge_serial_number).await;
+    state.admission.enforce(&pledge_serial_number, admitted)?;
+
+    let manufacturer = state.config.manufacturers.select(&pledge_idevid_cert);
+    if let Some(Err(reason)) = manufacturer.map(|manufacturer| manufacturer.verify_idevid(&pledge_idevid_cert)) {
+        state.sessions.fail(&pledge_serial_number, "requestsshcert", &reason).await;
+        return Err(ServerError::PolicyViolation { serial_number: pledge_serial_number, reason });
+    }
+    check_idevid_revocation(&state, &x5c_issuers(&per_headers), &pledge_idevid_cert, manufacturer, &pledge_serial_number, "requestsshcert").await?;
+
+    let host_key = per
+        .payload
+        .ssh_host_key
+        .ok_or(ServerError::BadRequestWithReason("PER carries no SSH host key".to_string()))?;
+
+    event!(Level::INFO, "Signing SSH host certificate for {}", pledge_serial_number);
+    let certificate = match ssh::sign_host_certificate(ca_key, &host_key, &pledge_serial_number, state.config.config.ssh_certificate_validity_days) {
+        Ok(certificate) => certificate,
+        Err(err) => {
+            state.sessions.fail(&pledge_serial_number, "requestsshcert", &err).await;
+            return Err(err);
+        }
+    };
+
+    Ok(([(CONTENT_TYPE, SSH_CERTIFICATE)], certificate))
+}
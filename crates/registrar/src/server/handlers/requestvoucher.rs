@@ -1,25 +1,43 @@
+use std::{net::SocketAddr, time::Duration};
+
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{
-        header::{ACCEPT, CONTENT_TYPE},
-        HeaderMap,
+        header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER},
+        HeaderMap, StatusCode,
     },
+    response::{IntoResponse, Response},
+    Json,
 };
 use brski_prm_artifacts::{
     ietf_voucher::request_artifact::VoucherRequestArtifact, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, rvr::RVR_JWS
 };
-use common::{server_error::ServerError, util::is_jws_voucher};
+use common::{
+    server_error::ServerError,
+    util::{hex, is_jws_voucher},
+};
+use openssl::x509::X509;
 use tracing::{event, Level};
 
-use crate::{client, server::server::ServerState};
+use crate::{
+    client,
+    devices::record_voucher,
+    jobs::{HeldVoucher, Job},
+    server::server::ServerState,
+    sessions::SessionStage,
+    voucher_cache::is_masa_unreachable,
+};
+
+use super::{check_idevid_revocation, pledge_serial_number, x5c_issuers};
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
-#[tracing::instrument(target = "Registrar", skip(state, headers, body))]
+#[tracing::instrument(target = "Registrar", skip(state, peer, headers, body))]
 pub async fn handle_requestvoucher(
     State(state): State<ServerState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
     body: String,
-) -> Result<IssuedVoucherJWS, ServerError> {
+) -> Result<Response, ServerError> {
 
     event!(Level::DEBUG, "Headers: {:#?}", headers);
     event!(Level::DEBUG, "Body: {:#?}", body);
@@ -42,22 +60,54 @@ pub async fn handle_requestvoucher(
 
 
     event!(Level::INFO, "Decoding PVR JWS");
-    let decoded = jws.decode()?;
+    let signature = jws.signature_values()?.concat();
+    let decoded = state.verification.run(move || jws.decode()).await??;
 
     let pvr = decoded.try_decoded_data()?;
 
     let headers = pvr.header.unwrap();
 
-    let pledge_idevid_cert = headers.x509_certificate_chain().ok_or(ServerError::BadRequest)?.get(0).ok_or(ServerError::BadRequest)?.clone();
+    let pledge_idevid_cert = headers.x509_certificate_chain().ok_or(ServerError::BadRequest)?.first().ok_or(ServerError::BadRequest)?.clone();
 
     let pledge_idevid_cert = openssl::x509::X509::from_der(&pledge_idevid_cert).map_err(|_| ServerError::BadRequest)?;
 
     event!(Level::INFO, "Pledge IDEVID Cert: {:#?}", pledge_idevid_cert);
 
-    let pvr_signature_pledge_serial_number = pledge_idevid_cert.subject_name().entries_by_nid(openssl::nid::Nid::SERIALNUMBER).next().ok_or(ServerError::BadRequest)?.data().as_utf8().map_err(|_| ServerError::BadRequest)?.to_string();
+    let pvr_signature_pledge_serial_number = pledge_serial_number(&pledge_idevid_cert)?;
 
     event!(Level::INFO, "Serial Number from Pledge IDEVID cert from Signature: {:#?}", pvr_signature_pledge_serial_number);
 
+    // a held voucher request is sent again on purpose, until its voucher is handed out
+    let request_hash = hex(&openssl::sha::sha256(body.as_bytes()));
+    if !(state.config.config.store_and_forward && state.jobs.is_held(&request_hash).await?) {
+        if let Err(reason) = state.replays.check(&pvr_signature_pledge_serial_number, &signature, pvr.payload.details.nonce.as_deref()).await {
+            event!(Level::WARN, "Rejected replayed voucher request from {:?}", peer.as_ref().map(|ConnectInfo(peer)| peer));
+            return Err(ServerError::PolicyViolation { serial_number: pvr_signature_pledge_serial_number, reason });
+        }
+    }
+
+    state.quarantine.enforce(&pvr_signature_pledge_serial_number, &pledge_idevid_cert, "requestvoucher").await?;
+
+    let admitted = state.sessions.is_admitted(&pvr_signature_pledge_serial_number).await;
+    state.admission.enforce(&pvr_signature_pledge_serial_number, admitted)?;
+
+    let manufacturer = state.config.manufacturers.select(&pledge_idevid_cert);
+    let verified = match manufacturer {
+        Some(manufacturer) => {
+            let (manufacturer, idevid) = (manufacturer.clone(), pledge_idevid_cert.clone());
+            state.verification.run(move || manufacturer.verify_idevid(&idevid)).await?
+        }
+        None => Ok(()),
+    };
+    if let Err(reason) = verified {
+        state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &reason).await;
+        return Err(ServerError::PolicyViolation { serial_number: pvr_signature_pledge_serial_number, reason });
+    }
+    check_idevid_revocation(&state, &x5c_issuers(&headers), &pledge_idevid_cert, manufacturer, &pvr_signature_pledge_serial_number, "requestvoucher").await?;
+
+    state.sessions.advance(&pvr_signature_pledge_serial_number, SessionStage::VoucherRequested).await;
+    state.clones.voucher_requested(&pvr_signature_pledge_serial_number, &request_hash, peer.map(|ConnectInfo(peer)| peer.ip())).await;
+
     let pvr_vra = pvr.payload;
 
     if pvr_vra.details.serial_number != pvr_signature_pledge_serial_number {
@@ -67,7 +117,50 @@ pub async fn handle_requestvoucher(
     }
 
     event!(Level::INFO, "PVR Serial Number matches serial number in pledge certificate!");
+
+    if let Err(err) = state.proximity.validate(&pvr_vra.details) {
+        event!(Level::WARN, "PVR of {} is not meant for this registrar: {}", pvr_signature_pledge_serial_number, err);
+        let reason = err.to_string();
+        state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &reason).await;
+        return Err(ServerError::PolicyViolation { serial_number: pvr_signature_pledge_serial_number, reason });
+    }
+
+    // As a subordinate registrar, the PVR was validated locally and the parent registrar handles the MASA exchange
+    if state.config.config.parent_registrar_url.is_some() {
+        let issued_voucher = match client::forward_voucher_request(&state.config, &state.client, body).await {
+            Ok(issued_voucher) => issued_voucher,
+            Err(err) => {
+                state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &err).await;
+                return Err(err);
+            }
+        };
+
+        record_voucher(state.devices.as_ref(), &pvr_signature_pledge_serial_number, &pledge_idevid_cert, &issued_voucher).await;
+        state.sessions.advance(&pvr_signature_pledge_serial_number, SessionStage::VoucherIssued).await;
+
+        event!(Level::INFO, "Returning issued voucher from parent registrar");
+        return Ok(IssuedVoucherJWS::Encoded(issued_voucher).into_response());
+    }
     
+    // a pledge asking again with a voucher request held while the MASA was unreachable
+    if state.config.config.store_and_forward {
+        match state.jobs.held_voucher(&request_hash, chrono::Utc::now()).await? {
+            Some(HeldVoucher::Issued(voucher)) => {
+                event!(Level::INFO, "Returning held voucher for {}", pvr_signature_pledge_serial_number);
+                return issued(&state, &pvr_signature_pledge_serial_number, &pledge_idevid_cert, JWS::Encoded(voucher)).await;
+            }
+            Some(HeldVoucher::Pending { retry_after }) => {
+                return Ok(held_response(&pvr_signature_pledge_serial_number, retry_after));
+            }
+            Some(HeldVoucher::Failed(reason)) => {
+                let err = ServerError::BadResponse(reason);
+                state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &err).await;
+                return Err(err);
+            }
+            None => {}
+        }
+    }
+
     event!(Level::DEBUG, "PVR VoucherRequestArtifact: {:#?}", pvr_vra);
 
     let mut rvr_vra = VoucherRequestArtifact::default();
@@ -79,8 +172,9 @@ pub async fn handle_requestvoucher(
     rvr_vra.details.prior_signed_voucher_request = Some(body.into_bytes());
     rvr_vra.details.serial_number = pvr_vra.details.serial_number;
     rvr_vra.details.agent_sign_cert = Some(vec![(state.config.reg_agt_ee_cert.clone().into())]);
-    // In this implementation, we pin the registrar cert from the PVR
-    rvr_vra.details.agent_provided_proximity_registrar_cert = pvr_vra.details.agent_provided_proximity_registrar_cert;
+    // In this implementation, we pin the registrar cert from the PVR, pledges talking to the registrar themselves
+    // (RFC 8995) put it in proximity-registrar-cert instead
+    rvr_vra.details.agent_provided_proximity_registrar_cert = pvr_vra.details.agent_provided_proximity_registrar_cert.or(pvr_vra.details.proximity_registrar_cert);
 
     let rvr = brski_prm_artifacts::rvr::RVR::new(rvr_vra, [state.config.registrar_certificate.clone()]);
 
@@ -91,17 +185,82 @@ pub async fn handle_requestvoucher(
 
 
     event!(Level::INFO, "Encoding RVR JWS");
-    let encoded = jws
-        .encode(state.config.registrar_key.private_key_to_der().unwrap())?;
-
-    encoded.verify()?;
+    let registrar_key = state.config.registrar_key.private_key_to_der().unwrap();
+    let encoded = state
+        .verification
+        .run(move || {
+            let encoded = jws.encode(registrar_key)?;
+            encoded.verify()?;
+            Ok::<_, josekit::JoseError>(encoded)
+        })
+        .await??;
+
+    let masa_url = state.masa_resolver.resolve(&pledge_idevid_cert, manufacturer).await;
 
     event!(Level::INFO, "Sending RVR JWS to MASA");
-    let issued_voucher: IssuedVoucherJWS = client::get_voucher_from_masa(&state.config, encoded, &state.client).await?;
+    let issued_voucher: IssuedVoucherJWS = match client::get_voucher_from_masa(&masa_url, encoded.clone(), &state.masa_client).await {
+        Ok(issued_voucher) => issued_voucher,
+        Err(err) if is_masa_unreachable(&err) => match state.voucher_cache.lookup(&pvr_signature_pledge_serial_number).await {
+            Some(cached_voucher) => {
+                event!(Level::WARN, "MASA unreachable, serving pre-generated voucher for {}", pvr_signature_pledge_serial_number);
+                let submission = Job::MasaSubmission {
+                    serial_number: pvr_signature_pledge_serial_number.clone(),
+                    masa_url: masa_url.clone(),
+                    rvr: encoded.try_encoded_data()?,
+                    served_at: chrono::Utc::now(),
+                };
+                state.jobs.enqueue(&submission).await?;
+                cached_voucher
+            }
+            None if state.config.config.store_and_forward => {
+                event!(Level::WARN, "MASA unreachable, holding voucher request for {}", pvr_signature_pledge_serial_number);
+                let held = Job::VoucherRequest {
+                    serial_number: pvr_signature_pledge_serial_number.clone(),
+                    masa_url: masa_url.clone(),
+                    rvr: encoded.try_encoded_data()?,
+                    request_hash,
+                    queued_at: chrono::Utc::now(),
+                };
+                state.jobs.hold(&held).await?;
+                // the job is due right away, the worker tries again before the pledge does
+                return Ok(held_response(&pvr_signature_pledge_serial_number, Duration::from_secs(HELD_RETRY_AFTER_SECS)));
+            }
+            None => {
+                state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &err).await;
+                return Err(err);
+            }
+        },
+        Err(err) => {
+            state.sessions.fail(&pvr_signature_pledge_serial_number, "requestvoucher", &err).await;
+            return Err(err);
+        }
+    };
+
+    issued(&state, &pvr_signature_pledge_serial_number, &pledge_idevid_cert, issued_voucher).await
+}
+
+/// Seconds a pledge is asked to wait after its voucher request was held
+const HELD_RETRY_AFTER_SECS: u64 = 30;
 
+async fn issued(state: &ServerState, serial_number: &str, idevid: &X509, issued_voucher: IssuedVoucherJWS) -> Result<Response, ServerError> {
     let issued_voucher = issued_voucher.add_inflight_signature([state.config.registrar_certificate.clone()], state.config.registrar_key.private_key_to_der().unwrap())?; 
+    let issued_voucher = issued_voucher.try_encoded_data()?;
+
+    record_voucher(state.devices.as_ref(), serial_number, idevid, &issued_voucher).await;
+    state.sessions.advance(serial_number, SessionStage::VoucherIssued).await;
 
     event!(Level::INFO, "Returning issued voucher");
 
-    Ok(issued_voucher)
+    Ok(IssuedVoucherJWS::Encoded(issued_voucher).into_response())
+}
+
+/// `202 Accepted`, the pledge sends the same voucher request again after `Retry-After` seconds
+fn held_response(serial_number: &str, retry_after: Duration) -> Response {
+    let retry_after = retry_after.as_secs().max(1);
+    let body = serde_json::json!({
+        "status": "voucher-request-held",
+        "serial-number": serial_number,
+        "retry-after": retry_after,
+    });
+    (StatusCode::ACCEPTED, [(RETRY_AFTER, retry_after.to_string())], Json(body)).into_response()
 }
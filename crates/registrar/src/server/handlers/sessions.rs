@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::State, Json};
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+use tracing::{event, Level};
+
+use crate::{
+    server::server::ServerState,
+    sessions::{OnboardingFailure, OnboardingSession},
+};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_sessions(State(state): State<ServerState>) -> Json<Vec<OnboardingSession>> {
+    event!(Level::INFO, "Received sessions listing request");
+
+    Json(state.sessions.sessions().await)
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_inventory(State(state): State<ServerState>) -> Json<Vec<OnboardingSession>> {
+    event!(Level::INFO, "Received inventory listing request");
+
+    Json(state.sessions.inventory().await)
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_failures(State(state): State<ServerState>) -> Json<Vec<OnboardingFailure>> {
+    event!(Level::INFO, "Received failures listing request");
+
+    Json(state.sessions.failures().await)
+}
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_failure_reasons(State(state): State<ServerState>) -> Json<BTreeMap<ReasonCode, usize>> {
+    event!(Level::INFO, "Received failure reasons request");
+
+    Json(state.sessions.failure_reasons().await)
+}
@@ -0,0 +1,12 @@
+use axum::{extract::State, Json};
+use common::server_error::ServerError;
+use tracing::{event, Level};
+
+use crate::{server::server::ServerState, voucher_cache::VoucherCacheStatus};
+
+#[tracing::instrument(target = "Registrar", skip(state))]
+pub async fn handle_voucher_cache(State(state): State<ServerState>) -> Result<Json<VoucherCacheStatus>, ServerError> {
+    event!(Level::INFO, "Received voucher cache status request");
+
+    Ok(Json(state.voucher_cache.status(&state.jobs).await?))
+}
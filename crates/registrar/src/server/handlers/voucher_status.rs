@@ -1,17 +1,19 @@
 use axum::{
     extract::State,
     http::{
-        header::{ACCEPT, CONTENT_TYPE},
+        header::CONTENT_TYPE,
         HeaderMap,
     },
 };
 use brski_prm_artifacts::{
-    ietf_voucher::request_artifact::VoucherRequestArtifact, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, rvr::RVR_JWS, status::voucher::response::vStatus_JWS
+    jws::JWS, status::{reason_code::ReasonCode, voucher::response::vStatus_JWS}
 };
-use common::{server_error::ServerError, util::{is_jose, is_jws_voucher}};
+use common::{server_error::ServerError, util::is_jose};
 use tracing::{event, Level};
 
-use crate::{client, server::server::ServerState};
+use crate::{jobs::Job, server::server::ServerState, sessions::SessionStage};
+
+use super::pledge_serial_number_from_header;
 
 // We don't trust client's to supply just any base64 encoded data, so we parse it.
 #[tracing::instrument(target = "Registrar", skip(state, headers, body))]
@@ -36,11 +38,44 @@ pub async fn handle_voucher_status(
     event!(Level::DEBUG, "Voucher Status: {:#?}", jws);
 
 
-    let decoded = jws.decode().unwrap();
+    // a malformed status is answered with a 400, like the enroll status
+    let decoded = jws.decode()?;
 
-    let status = decoded.try_decoded_data().unwrap().payload;
+    let decoded = decoded.try_decoded_data()?;
+    let pledge_serial_number = pledge_serial_number_from_header(decoded.header.as_ref());
+    let status = decoded.payload;
 
     event!(Level::INFO, "Voucher Status: {:#?}", status);
+
+    if let Some(pledge_serial_number) = pledge_serial_number {
+        if !state.sessions.attested(&pledge_serial_number, "voucher_status", status.attestation, state.config.config.require_attestation).await {
+            return Ok(());
+        }
+        if status.status {
+            state.sessions.advance(&pledge_serial_number, SessionStage::VoucherAccepted).await;
+        } else {
+            let reason_code = status.reason_code.unwrap_or(ReasonCode::Unknown);
+            state.sessions.pledge_failed(&pledge_serial_number, "voucher_status", reason_code, status.reason.as_deref()).await;
+        }
+
+        if state.config.config.forward_voucher_status {
+            let idevid = decoded
+                .header
+                .as_ref()
+                .and_then(|header| header.x509_certificate_chain())
+                .and_then(|chain| chain.first().and_then(|der| openssl::x509::X509::from_der(der).ok()));
+            if let Some(idevid) = idevid {
+                let manufacturer = state.config.manufacturers.select(&idevid);
+                let forward = Job::VoucherStatus {
+                    serial_number: pledge_serial_number.clone(),
+                    masa_url: state.masa_resolver.resolve(&idevid, manufacturer).await,
+                    voucher_status: body,
+                    received_at: chrono::Utc::now(),
+                };
+                state.jobs.enqueue(&forward).await?;
+            }
+        }
+    }
     
     Ok(())
 }
@@ -1,14 +1,9 @@
 use axum::{
     extract::State,
-    http::{
-        header::{ACCEPT, CONTENT_TYPE},
-        HeaderMap,
-    },
+    http::HeaderMap,
 };
-use brski_prm_artifacts::{
-    cacerts::{self, response::CACERTS_JWS}, ietf_voucher::request_artifact::VoucherRequestArtifact, issued_voucher::IssuedVoucherJWS, jws::JWS, pvr::response::PVR_JWS, rvr::RVR_JWS
-};
-use common::{server_error::ServerError, util::is_jws_voucher};
+use brski_prm_artifacts::cacerts::{self, response::CACERTS_JWS};
+use common::server_error::ServerError;
 use tracing::{event, Level};
 
 use crate::{client, server::server::ServerState};
@@ -24,13 +19,19 @@ pub async fn handle_wrappedcacerts(
 
     event!(Level::INFO, "Received wrappedcacerts request");
     
-    let ca_certificates = &state.config.ca_certificate;
+    if state.config.config.parent_registrar_url.is_some() {
+        event!(Level::INFO, "Fetching wrappedcacerts from parent registrar");
+        let wrapped_cacerts = client::fetch_wrappedcacerts(&state.config, &state.client).await?;
+        return Ok(CACERTS_JWS::Encoded(wrapped_cacerts));
+    }
+
+    let ca_certificates = state.ca_certificates().await?;
     let registrar_ldevid_certs = &state.config.registrar_certificate;
     let registrar_ldevid_key = &state.config.registrar_key;
 
     event!(Level::INFO, "Building wrappedcacerts x5bag");
     let response_payload = cacerts::response_payload::ResponsePayload {
-        x5bag: vec![ca_certificates.clone().into()],
+        x5bag: ca_certificates.into_iter().map(Into::into).collect(),
     };
 
     let response = cacerts::response::Response::new(response_payload, [registrar_ldevid_certs.clone()]);
@@ -1,3 +1,4 @@
 mod handlers;
+#[allow(clippy::module_inception)]
 mod server;
 pub use server::get_app;
@@ -1,28 +1,181 @@
+use std::{sync::Arc, time::Duration};
+
 use crate::{
+    admission::Admission,
+    capture::{record_exchange, Capture},
+    client,
+    clones::CloneDetector,
+    devices::{DeviceStore, SqliteDeviceStore},
+    est::EstCache,
+    jobs::JobQueue,
+    masa_resolver::MasaResolver,
     parsed_config::{ParsedConfig},
+    quarantine::Quarantine,
+    replay::ReplayCache,
+    sessions::Sessions,
+    snmp::SnmpSubagent,
+    validation::ProximityValidator,
+    voucher_cache::VoucherCache,
+};
+use axum::{middleware, Router};
+use common::{
+    auth::{require_scope, Authenticator},
+    error::AppError,
+    expiry::ExpiryMonitor,
+    limits::{enforce_body_limit, Artifact},
+    revocation::RevocationChecker,
+    server_error::ServerError,
+    verification::VerificationPool,
 };
-use axum::{Router};
-use common::error::AppError;
+use openssl::x509::X509;
 use reqwest::Client;
 use tower_http::trace::TraceLayer;
+use tracing::{event, Level};
 
-use super::handlers::brski_routes;
+use super::handlers::{admin_routes, brski_routes, dashboard_routes, est_routes};
 
 #[derive(Clone)]
 pub struct ServerState {
     pub config: ParsedConfig,
     pub client: reqwest::Client,
+    /// Like `client`, but through the egress proxy for the MASA
+    pub masa_client: reqwest::Client,
+    pub(crate) quarantine: Arc<Quarantine>,
+    pub(crate) admission: Arc<Admission>,
+    pub(crate) sessions: Arc<Sessions>,
+    pub(crate) clones: Arc<CloneDetector>,
+    pub(crate) replays: Arc<ReplayCache>,
+    pub(crate) voucher_cache: Arc<VoucherCache>,
+    pub(crate) masa_resolver: Arc<MasaResolver>,
+    pub(crate) jobs: Arc<JobQueue>,
+    pub(crate) devices: Arc<dyn DeviceStore>,
+    pub(crate) revocation: Arc<RevocationChecker>,
+    pub(crate) expiry: Arc<ExpiryMonitor>,
+    pub(crate) est_cache: Arc<EstCache>,
+    pub(crate) proximity: Arc<ProximityValidator>,
+    pub(crate) verification: VerificationPool,
+    /// Only set with `[registrar.capture]` enabled
+    pub(crate) capture: Option<Arc<Capture>>,
+}
+
+impl ServerState {
+    /// CA certificates of the LDevIDs, from the local CA or the upstream CA
+    pub(crate) async fn ca_certificates(&self) -> Result<Vec<X509>, ServerError> {
+        let certificate_authority =
+            self.config.certificate_authority.as_ref().ok_or(ServerError::BadResponse("Registrar has no CA".to_string()))?;
+
+        let certificates = certificate_authority.cacerts().await?;
+        Ok(certificates.iter().map(|der| X509::from_der(der)).collect::<Result<_, _>>()?)
+    }
 }
 
 pub async fn get_app(config: &ParsedConfig) -> anyhow::Result<Router<()>, AppError> {
     let client = Client::new();
+    let masa_client = client::masa_client(&config.config.masa_proxy)?;
+
+    let job_database = config.config.job_database.as_ref().map(|path| path.relative());
+    let jobs = Arc::new(JobQueue::open(job_database.as_deref())?);
+    // the only job submits voucher requests to the MASA
+    jobs.spawn_workers(config.config.job_workers, masa_client.clone());
+
+    let device_database = config.config.device_database.as_ref().map(|path| path.relative());
+    let devices = Arc::new(SqliteDeviceStore::open(device_database.as_deref())?);
+
+    let revocation = Arc::new(RevocationChecker::new(config.config.idevid_revocation, client.clone()));
+    revocation.spawn_refresh(Duration::from_secs(config.config.revocation_refresh_secs));
+
+    let expiry = Arc::new(ExpiryMonitor::new(
+        config.config.expiry_warning_days,
+        config.config.expiry_webhook_url.clone(),
+        client.clone(),
+    ));
+    if let Some(certificate_authority) = &config.certificate_authority {
+        // an unreachable upstream CA must not keep the registrar from starting, its certificates are just not tracked
+        match certificate_authority.cacerts().await {
+            Ok(certificates) => {
+                for (index, certificate) in certificates.iter().filter_map(|der| X509::from_der(der).ok()).enumerate() {
+                    let name = if index == 0 { "ca".to_string() } else { format!("ca:{}", index) };
+                    expiry.track(name, &certificate).await;
+                }
+            }
+            Err(err) => event!(Level::WARN, "CA certificates are not tracked for expiry: {}", err),
+        }
+    }
+    expiry.track("registrar", &config.registrar_certificate).await;
+    if config.config.tls_certificate.is_some() {
+        expiry.track("tls", &config.tls_certificate).await;
+    }
+    expiry.track("registrar-agent", &config.reg_agt_ee_cert).await;
+    expiry.spawn(Duration::from_secs(config.config.expiry_check_secs));
+
+    let limits = &config.config.artifact_limits;
+    let capture = if config.config.capture.enabled {
+        let max_body = [limits.voucher, limits.voucher_request, limits.csr, limits.telemetry, limits.other].into_iter().max();
+        Some(Arc::new(Capture::new(&config.config.capture, max_body.unwrap_or_default())?))
+    } else {
+        None
+    };
+
+    if config.config.policy_dry_run {
+        event!(target: "Registrar::Admission", Level::WARN, "Policy dry run, pledges rejected by the admission windows are only logged");
+    }
+    if config.config.quarantine_dry_run {
+        event!(target: "Registrar::Quarantine", Level::WARN, "Quarantine dry run, blocklisted pledges are only recorded");
+    }
+
+    let sessions = Arc::new(Sessions::default());
+    if config.config.snmp.enabled {
+        SnmpSubagent::new(&config.config.snmp, sessions.clone())?.spawn();
+    }
 
     let state = ServerState {
         config: config.clone(),
         client: client.clone(),
+        masa_client,
+        quarantine: Arc::new(Quarantine::new(&config.config)?),
+        admission: Arc::new(Admission::new(&config.config)?),
+        sessions,
+        clones: Arc::new(CloneDetector::new(config.config.clone_detection_window_secs)),
+        replays: Arc::new(ReplayCache::new(config.config.replay_window_secs)),
+        voucher_cache: Arc::new(VoucherCache::load(config)?),
+        masa_resolver: Arc::new(MasaResolver::new(
+            config.masa_url.clone(),
+            config.config.masa_srv_domains.clone(),
+            config.config.manufacturers.values().any(|manufacturer| manufacturer.masa_srv_domain.is_some()),
+        )),
+        jobs,
+        devices,
+        revocation,
+        expiry,
+        est_cache: Arc::new(EstCache::default()),
+        proximity: Arc::new(ProximityValidator::new(
+            &config.registrar_certificate,
+            &[std::slice::from_ref(&config.tls_certificate), &config.proximity_registrar_certificates].concat(),
+        )?),
+        verification: VerificationPool::new(&config.config.verification),
+        capture: capture.clone(),
     };
 
-    let routes = Router::new().nest("/.well-known/brski", brski_routes());
+    let authenticator = Arc::new(Authenticator::new(
+        config.config.admin_api_keys.clone(),
+        config.config.oidc_issuer.clone(),
+        config.config.oidc_audience.clone(),
+        state.client.clone(),
+    ));
+
+    let admin = admin_routes()
+        .route_layer(middleware::from_fn_with_state(authenticator, require_scope))
+        .merge(dashboard_routes())
+        .route_layer(middleware::from_fn_with_state(limits.guard(Artifact::Other), enforce_body_limit));
+
+    let mut routes = Router::new()
+        .nest("/.well-known/brski", brski_routes(limits))
+        .nest("/.well-known/est", est_routes(limits));
+    // only the exchanges with pledges are captured, not the admin API
+    if let Some(capture) = capture {
+        routes = routes.layer(middleware::from_fn_with_state(capture, record_exchange));
+    }
+    let routes = routes.nest("/admin", admin);
 
     let app = routes.with_state(state).layer(TraceLayer::new_for_http());
 
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use brski_prm_artifacts::status::{attestation::CounterAttestation, reason_code::ReasonCode};
+use chrono::{DateTime, Utc};
+use openssl::x509::{X509Ref, X509};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+/// Upper bound of failures kept in memory, older failures are dropped first
+const MAX_RECORDED_FAILURES: usize = 1024;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SessionStage {
+    VoucherRequested,
+    VoucherIssued,
+    VoucherAccepted,
+    Enrolled,
+    Completed,
+    Failed,
+}
+
+impl SessionStage {
+    fn is_finished(&self) -> bool {
+        matches!(self, SessionStage::Completed | SessionStage::Failed)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct OnboardingSession {
+    pub(crate) serial_number: String,
+    pub(crate) stage: SessionStage,
+    pub(crate) started: DateTime<Utc>,
+    pub(crate) updated: DateTime<Utc>,
+    pub(crate) ldevid_serial: Option<String>,
+    pub(crate) ldevid_not_after: Option<String>,
+    pub(crate) last_error: Option<String>,
+    /// Latest LDevID issued to the pledge, for exports
+    #[serde(skip)]
+    pub(crate) ldevid: Option<X509>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct OnboardingFailure {
+    pub(crate) serial_number: String,
+    pub(crate) endpoint: String,
+    pub(crate) error: String,
+    /// Only set for failures reported by the pledge
+    pub(crate) reason_code: Option<ReasonCode>,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+/// Onboardings since the start of the registrar, unlike the sessions and failures these only ever grow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct OnboardingCounters {
+    /// Sessions started, a new voucher request after a finished session counts again
+    pub(crate) started: u64,
+    pub(crate) enrolled: u64,
+    pub(crate) completed: u64,
+    pub(crate) failed: u64,
+}
+
+#[derive(Debug, Default)]
+struct AtomicCounters {
+    started: AtomicU64,
+    enrolled: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// In-memory view of the onboarding sessions the registrar has seen, keyed by the pledge serial-number.
+/// Backs the admin API and the dashboard.
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    sessions: RwLock<HashMap<String, OnboardingSession>>,
+    failures: RwLock<Vec<OnboardingFailure>>,
+    /// Highest counter attestation each pledge sent in its status telemetry
+    attestations: RwLock<HashMap<String, CounterAttestation>>,
+    counters: AtomicCounters,
+}
+
+impl Sessions {
+    pub(crate) async fn advance(&self, serial_number: &str, stage: SessionStage) {
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().await;
+
+        let previous = sessions.get(serial_number).map(|session| session.stage);
+        if previous.is_none_or(|previous| stage == SessionStage::VoucherRequested && previous.is_finished()) {
+            self.counters.started.fetch_add(1, Ordering::Relaxed);
+        }
+        if stage == SessionStage::Completed && previous != Some(SessionStage::Completed) {
+            self.counters.completed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let session = sessions
+            .entry(serial_number.to_string())
+            .or_insert_with(|| OnboardingSession {
+                serial_number: serial_number.to_string(),
+                stage,
+                started: now,
+                updated: now,
+                ldevid_serial: None,
+                ldevid_not_after: None,
+                last_error: None,
+                ldevid: None,
+            });
+
+        // a new voucher request after a finished session starts over
+        if stage == SessionStage::VoucherRequested && session.stage.is_finished() {
+            session.started = now;
+            session.last_error = None;
+        }
+
+        session.stage = stage;
+        session.updated = now;
+
+        event!(target: "Registrar::Sessions", Level::DEBUG, "Session {} advanced to {:?}", serial_number, stage);
+    }
+
+    pub(crate) async fn enrolled(&self, serial_number: &str, ldevid: &X509Ref) {
+        self.advance(serial_number, SessionStage::Enrolled).await;
+        self.counters.enrolled.fetch_add(1, Ordering::Relaxed);
+
+        let ldevid_serial = ldevid
+            .serial_number()
+            .to_bn()
+            .and_then(|bn| bn.to_hex_str())
+            .map(|hex| hex.to_string())
+            .ok();
+
+        if let Some(session) = self.sessions.write().await.get_mut(serial_number) {
+            session.ldevid_serial = ldevid_serial;
+            session.ldevid_not_after = Some(ldevid.not_after().to_string());
+            session.ldevid = Some(ldevid.to_owned());
+        }
+    }
+
+    pub(crate) async fn ldevid(&self, serial_number: &str) -> Option<X509> {
+        self.sessions.read().await.get(serial_number).and_then(|session| session.ldevid.clone())
+    }
+
+    pub(crate) async fn fail(&self, serial_number: &str, endpoint: &str, error: impl ToString) {
+        self.record_failure(serial_number, endpoint, None, error.to_string()).await;
+    }
+
+    /// Failure reported by the pledge in its voucher or enroll status
+    pub(crate) async fn pledge_failed(&self, serial_number: &str, endpoint: &str, reason_code: ReasonCode, reason: Option<&str>) {
+        let error = reason.map_or_else(|| reason_code.to_string(), |reason| reason.to_string());
+        self.record_failure(serial_number, endpoint, Some(reason_code), error).await;
+    }
+
+    async fn record_failure(&self, serial_number: &str, endpoint: &str, reason_code: Option<ReasonCode>, error: String) {
+
+        event!(target: "Registrar::Sessions", Level::WARN, "Onboarding of {} failed at {}: {}", serial_number, endpoint, error);
+
+        self.advance(serial_number, SessionStage::Failed).await;
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+        if let Some(session) = self.sessions.write().await.get_mut(serial_number) {
+            session.last_error = Some(error.clone());
+        }
+
+        let mut failures = self.failures.write().await;
+        if failures.len() >= MAX_RECORDED_FAILURES {
+            failures.remove(0);
+        }
+        failures.push(OnboardingFailure {
+            serial_number: serial_number.to_string(),
+            endpoint: endpoint.to_string(),
+            error,
+            reason_code,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Checks the counter attestation of a pledge status against the previous one of the same pledge.
+    /// A counter that did not increase means the pledge was rolled back, the status was replayed or the pledge was cloned.
+    /// A status without attestation only passes unless `required`. Failures are recorded and `false` is returned.
+    pub(crate) async fn attested(
+        &self,
+        serial_number: &str,
+        endpoint: &str,
+        attestation: Option<CounterAttestation>,
+        required: bool,
+    ) -> bool {
+        let Some(attestation) = attestation else {
+            if required {
+                self.record_failure(serial_number, endpoint, None, "status carries no counter attestation".to_string()).await;
+            }
+            return !required;
+        };
+
+        let previous = {
+            let mut attestations = self.attestations.write().await;
+            match attestations.get(serial_number) {
+                Some(previous) if !attestation.follows(previous) => Some(*previous),
+                _ => {
+                    attestations.insert(serial_number.to_string(), attestation);
+                    None
+                }
+            }
+        };
+
+        match previous {
+            Some(previous) => {
+                let error = format!(
+                    "counter attestation {:?} does not follow {:?}, the pledge was rolled back or cloned",
+                    attestation, previous
+                );
+                self.record_failure(serial_number, endpoint, None, error).await;
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Pledges that already received a voucher or an LDevID, e.g. pledges that re-enroll
+    pub(crate) async fn is_admitted(&self, serial_number: &str) -> bool {
+        self.sessions.read().await.get(serial_number).is_some_and(|session| {
+            session.ldevid_serial.is_some()
+                || matches!(session.stage, SessionStage::VoucherIssued | SessionStage::VoucherAccepted | SessionStage::Enrolled | SessionStage::Completed)
+        })
+    }
+
+    pub(crate) async fn sessions(&self) -> Vec<OnboardingSession> {
+        let mut sessions: Vec<_> = self.sessions.read().await.values().cloned().collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated));
+        sessions
+    }
+
+    /// Devices that received an LDevID
+    pub(crate) async fn inventory(&self) -> Vec<OnboardingSession> {
+        self.sessions()
+            .await
+            .into_iter()
+            .filter(|session| session.ldevid_serial.is_some())
+            .collect()
+    }
+
+    pub(crate) async fn failures(&self) -> Vec<OnboardingFailure> {
+        self.failures.read().await.iter().rev().cloned().collect()
+    }
+
+    pub(crate) fn counters(&self) -> OnboardingCounters {
+        OnboardingCounters {
+            started: self.counters.started.load(Ordering::Relaxed),
+            enrolled: self.counters.enrolled.load(Ordering::Relaxed),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of recorded pledge failures per reason code
+    pub(crate) async fn failure_reasons(&self) -> BTreeMap<ReasonCode, usize> {
+        let mut reasons = BTreeMap::new();
+        for reason_code in self.failures.read().await.iter().filter_map(|failure| failure.reason_code) {
+            *reasons.entry(reason_code).or_insert(0) += 1;
+        }
+        reasons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (pledge_cert, _) = certs.pledge;
+
+        let sessions = Sessions::default();
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherRequested).await;
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherIssued).await;
+        assert!(sessions.inventory().await.is_empty());
+
+        sessions.enrolled("00-D0-E5-F2-00-02", &pledge_cert).await;
+
+        let inventory = sessions.inventory().await;
+        assert_eq!(inventory.len(), 1);
+        assert_eq!(inventory[0].stage, SessionStage::Enrolled);
+        assert!(inventory[0].ldevid_not_after.is_some());
+        assert!(sessions.ldevid("00-D0-E5-F2-00-02").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_recorded() {
+        let sessions = Sessions::default();
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherRequested).await;
+        sessions.fail("00-D0-E5-F2-00-02", "requestvoucher", "MASA unreachable").await;
+
+        let failures = sessions.failures().await;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].endpoint, "requestvoucher");
+
+        let all = sessions.sessions().await;
+        assert_eq!(all[0].stage, SessionStage::Failed);
+        assert_eq!(all[0].last_error.as_deref(), Some("MASA unreachable"));
+
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherRequested).await;
+        assert_eq!(sessions.sessions().await[0].last_error, None);
+
+        let counters = sessions.counters();
+        assert_eq!((counters.started, counters.failed, counters.enrolled), (2, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_failure_reasons_are_aggregated() {
+        let sessions = Sessions::default();
+        sessions.fail("00-D0-E5-F2-00-02", "requestvoucher", "MASA unreachable").await;
+        sessions.pledge_failed("00-D0-E5-F2-00-02", "voucher_status", ReasonCode::NonceMismatch, None).await;
+        sessions.pledge_failed("00-D0-E5-F2-00-03", "voucher_status", ReasonCode::NonceMismatch, Some("nonce differs")).await;
+        sessions.pledge_failed("00-D0-E5-F2-00-04", "enrollstatus", ReasonCode::KeyMismatch, None).await;
+
+        let reasons = sessions.failure_reasons().await;
+        assert_eq!(reasons.len(), 2);
+        assert_eq!(reasons[&ReasonCode::NonceMismatch], 2);
+        assert_eq!(reasons[&ReasonCode::KeyMismatch], 1);
+
+        assert_eq!(sessions.failures().await[0].error, ReasonCode::KeyMismatch.description());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_counter_attestation_is_recorded() {
+        let attestation = |boot_count, monotonic_counter| CounterAttestation { boot_count, monotonic_counter };
+
+        let sessions = Sessions::default();
+        assert!(sessions.attested("00-D0-E5-F2-00-02", "voucher_status", Some(attestation(1, 4)), false).await);
+        assert!(sessions.attested("00-D0-E5-F2-00-02", "enrollstatus", Some(attestation(2, 5)), false).await);
+        // another pledge has its own counters
+        assert!(sessions.attested("00-D0-E5-F2-00-03", "voucher_status", Some(attestation(1, 1)), false).await);
+        assert!(sessions.failures().await.is_empty());
+
+        // a clone sending the same state, then a pledge restored from an earlier backup
+        assert!(!sessions.attested("00-D0-E5-F2-00-02", "enrollstatus", Some(attestation(2, 5)), false).await);
+        assert!(!sessions.attested("00-D0-E5-F2-00-02", "voucher_status", Some(attestation(1, 9)), false).await);
+        assert_eq!(sessions.failures().await.len(), 2);
+
+        assert!(sessions.attested("00-D0-E5-F2-00-02", "enrollstatus", Some(attestation(2, 6)), false).await);
+    }
+
+    #[tokio::test]
+    async fn test_missing_attestation_fails_if_required() {
+        let sessions = Sessions::default();
+        assert!(sessions.attested("00-D0-E5-F2-00-02", "voucher_status", None, false).await);
+        assert!(sessions.failures().await.is_empty());
+
+        assert!(!sessions.attested("00-D0-E5-F2-00-02", "voucher_status", None, true).await);
+        assert_eq!(sessions.failures().await[0].error, "status carries no counter attestation");
+    }
+}
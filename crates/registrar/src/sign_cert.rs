@@ -1,22 +1,58 @@
+use brski_prm_artifacts::digest::message_digest;
 use openssl::asn1::Asn1Time;
 use openssl::bn::{BigNum, MsbOption};
 use openssl::error::ErrorStack;
-use openssl::hash::MessageDigest;
-use openssl::pkey::{PKey, PKeyRef, Private};
-use openssl::rsa::Rsa;
+use openssl::nid::Nid;
+use openssl::pkey::{PKeyRef, Private};
 use openssl::x509::extension::{
     AuthorityKeyIdentifier, BasicConstraints, KeyUsage, SubjectAlternativeName,
     SubjectKeyIdentifier,
 };
-use openssl::x509::{X509NameBuilder, X509Ref, X509Req, X509ReqBuilder, X509VerifyResult, X509};
+use openssl::x509::{X509Ref, X509Req, X509};
 
+/// dNSName entries of the subjectAltName of every LDevID, whichever [`crate::ca::CertificateAuthority`] issues it
+pub(crate) const LDEVID_DNS_NAMES: [&str; 2] = ["*.example.com", "hello.com"];
+
+/// Curve names as used in the registrar configuration
+fn curve_name(nid: Nid) -> Option<&'static str> {
+    match nid {
+        Nid::X9_62_PRIME256V1 => Some("P-256"),
+        Nid::SECP384R1 => Some("P-384"),
+        Nid::SECP521R1 => Some("P-521"),
+        _ => None,
+    }
+}
+
+/// Checks the CSR signature and that its key uses one of the allowed curves
+pub fn check_csr(req: &X509Req, allowed_curves: &[String]) -> Result<(), String> {
+    let public_key = req.public_key().map_err(|_| "CSR has no usable public key".to_string())?;
+
+    if !req.verify(&public_key).unwrap_or(false) {
+        return Err("CSR signature does not verify".to_string());
+    }
+
+    let curve = public_key
+        .ec_key()
+        .ok()
+        .and_then(|key| key.group().curve_name())
+        .and_then(curve_name)
+        .ok_or("CSR key is not on a supported curve".to_string())?;
+
+    if !allowed_curves.iter().any(|allowed| allowed == curve) {
+        return Err(format!("CSR key curve {} is not allowed", curve));
+    }
+
+    Ok(())
+}
+
+/// Issues the LDevID for the key of the CSR
 pub fn mk_ca_signed_cert(
     ca_cert: &X509Ref,
     ca_key_pair: &PKeyRef<Private>,
-    req: &X509Req
-) -> Result<(X509, PKey<Private>), ErrorStack> {
-    let rsa = Rsa::generate(2048)?;
-    let key_pair = PKey::from_rsa(rsa)?;
+    req: &X509Req,
+    validity_days: u32,
+) -> Result<X509, ErrorStack> {
+    let public_key = req.public_key()?;
 
     let mut cert_builder = X509::builder()?;
     cert_builder.set_version(2)?;
@@ -28,10 +64,10 @@ pub fn mk_ca_signed_cert(
     cert_builder.set_serial_number(&serial_number)?;
     cert_builder.set_subject_name(req.subject_name())?;
     cert_builder.set_issuer_name(ca_cert.subject_name())?;
-    cert_builder.set_pubkey(&key_pair)?;
+    cert_builder.set_pubkey(&public_key)?;
     let not_before = Asn1Time::days_from_now(0)?;
     cert_builder.set_not_before(&not_before)?;
-    let not_after = Asn1Time::days_from_now(365)?;
+    let not_after = Asn1Time::days_from_now(validity_days)?;
     cert_builder.set_not_after(&not_after)?;
 
     cert_builder.append_extension(BasicConstraints::new().build()?)?;
@@ -55,14 +91,73 @@ pub fn mk_ca_signed_cert(
         .build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
     cert_builder.append_extension(auth_key_identifier)?;
 
-    let subject_alt_name = SubjectAlternativeName::new()
-        .dns("*.example.com")
-        .dns("hello.com")
-        .build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
+    let mut subject_alt_name = SubjectAlternativeName::new();
+    for name in LDEVID_DNS_NAMES {
+        subject_alt_name.dns(name);
+    }
+    let subject_alt_name = subject_alt_name.build(&cert_builder.x509v3_context(Some(ca_cert), None))?;
     cert_builder.append_extension(subject_alt_name)?;
 
-    cert_builder.sign(ca_key_pair, MessageDigest::sha256())?;
+    cert_builder.sign(ca_key_pair, message_digest(ca_key_pair))?;
     let cert = cert_builder.build();
 
-    Ok((cert, key_pair))
-}
\ No newline at end of file
+    Ok(cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::x509::X509ReqBuilder;
+
+    use super::*;
+
+    fn csr(key: &PKeyRef<Private>) -> X509Req {
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("serialNumber", "00-D0-E5-F2-00-02").unwrap();
+
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_version(0).unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, message_digest(key)).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_issues_p384_ldevid_for_csr_key() {
+        let certs: example_certs::OpensslTestCerts =
+            example_certs::generate_certs_with_pledge_curve(example_certs::Curve::P384).into();
+        let (registrar_ca, registrar_ca_key) = &certs.registrar_ca;
+        let (_, pledge_key) = &certs.pledge;
+
+        let csr = csr(pledge_key);
+        let allowed = vec!["P-256".to_string(), "P-384".to_string()];
+        assert_eq!(check_csr(&csr, &allowed), Ok(()));
+        assert!(check_csr(&csr, &["P-256".to_string()]).is_err());
+
+        let ldevid = mk_ca_signed_cert(registrar_ca, registrar_ca_key, &csr, 365).unwrap();
+        assert!(ldevid.public_key().unwrap().public_eq(pledge_key));
+        assert!(ldevid.verify(&registrar_ca.public_key().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_ldevid_keeps_utf8_serial_number() {
+        use brski_prm_artifacts::ietf_voucher::serial_number;
+        use openssl::asn1::Asn1Type;
+
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs_for_serial("Gerät-製造-0002").into();
+        let (registrar_ca, registrar_ca_key) = &certs.registrar_ca;
+        let (idevid, pledge_key) = &certs.pledge;
+        assert_eq!(serial_number::from_name(idevid.subject_name()).unwrap(), "Gerät-製造-0002");
+
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid_with_type(Nid::SERIALNUMBER, "Gerät-製造-0002", Asn1Type::UTF8STRING).unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_version(0).unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(pledge_key).unwrap();
+        builder.sign(pledge_key, message_digest(pledge_key)).unwrap();
+
+        let ldevid = mk_ca_signed_cert(registrar_ca, registrar_ca_key, &builder.build(), 365).unwrap();
+        assert_eq!(serial_number::from_name(ldevid.subject_name()).unwrap(), "Gerät-製造-0002");
+    }
+}
@@ -0,0 +1,752 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use cli::config::SnmpConfig;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use crate::sessions::{OnboardingSession, SessionStage, Sessions};
+
+/// AgentX protocol version of RFC 2741
+const VERSION: u8 = 1;
+const HEADER_LENGTH: usize = 20;
+/// Largest PDU accepted from the master agent, requests for a few hundred objects fit easily
+const MAX_PAYLOAD: usize = 64 * 1024;
+/// Limits the response to a GetBulk request with a large max-repetitions
+const MAX_BULK_VARBINDS: usize = 1024;
+/// Object identifiers have at most 128 sub-identifiers, see RFC 2741 Section 5.1
+const MAX_SUBIDS: usize = 128;
+/// Default priority of a registration, lower values take precedence
+const PRIORITY: u8 = 127;
+/// Requests within this long of a snapshot are answered from it, so the GetNext requests of a walk see the same
+/// sessions and do not copy them again
+const SNAPSHOT_TTL: Duration = Duration::from_secs(1);
+
+// PDU types, see RFC 2741 Section 6.1
+const OPEN: u8 = 1;
+const CLOSE: u8 = 2;
+const REGISTER: u8 = 3;
+const GET: u8 = 5;
+const GET_NEXT: u8 = 6;
+const GET_BULK: u8 = 7;
+const TEST_SET: u8 = 8;
+const COMMIT_SET: u8 = 9;
+const UNDO_SET: u8 = 10;
+const CLEANUP_SET: u8 = 11;
+const RESPONSE: u8 = 18;
+
+// header flags
+const NON_DEFAULT_CONTEXT: u8 = 0x08;
+const NETWORK_BYTE_ORDER: u8 = 0x10;
+
+// res.error values, see RFC 2741 Section 6.2.16
+const NO_ERROR: u16 = 0;
+const NOT_WRITABLE: u16 = 17;
+const PARSE_ERROR: u16 = 266;
+
+// VarBind types, see RFC 2741 Section 5.4
+const INTEGER: u16 = 2;
+const OCTET_STRING: u16 = 4;
+const COUNTER32: u16 = 65;
+const GAUGE32: u16 = 66;
+const NO_SUCH_OBJECT: u16 = 128;
+const NO_SUCH_INSTANCE: u16 = 129;
+const END_OF_MIB_VIEW: u16 = 130;
+
+/// Scalars below `base_oid.1`, the number of sessions and of the sessions in each stage are gauges, the rest counters
+const SCALARS: u32 = 11;
+/// Readable columns of the session table `base_oid.2`, column 1 is the serial-number index
+const COLUMNS: std::ops::RangeInclusive<u32> = 2..=6;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    Counter32(u32),
+    Gauge32(u32),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    pdu_type: u8,
+    flags: u8,
+    session_id: u32,
+    transaction_id: u32,
+    packet_id: u32,
+}
+
+/// Range of a Get, GetNext or GetBulk request, `end` is empty if the range is unbounded
+#[derive(Debug, Clone, PartialEq)]
+struct SearchRange {
+    start: Vec<u32>,
+    include: bool,
+    end: Vec<u32>,
+}
+
+/// Serves the onboarding progress of the registrar as AgentX subagent (RFC 2741). The subagent connects to the master
+/// agent, registers `base_oid` and answers Get, GetNext and GetBulk requests below it. Set requests are refused, all
+/// objects are read-only. A lost connection to the master agent is retried every `reconnect_secs`. The objects are
+/// defined in `assets/OPEN-BRSKI-REGISTRAR-MIB.txt`.
+///
+/// Objects below `base_oid`:
+/// - `.1.1.0` sessions known to the registrar and `.1.2.0` to `.1.7.0` those in the stages voucher-requested,
+///   voucher-issued, voucher-accepted, enrolled, completed and failed (Gauge32)
+/// - `.1.8.0` onboardings started, `.1.9.0` enrolled, `.1.10.0` completed and `.1.11.0` failed since the start of the
+///   registrar (Counter32)
+/// - `.2.1.<column>.<index>` the sessions, indexed by the length and bytes of the serial-number: `2` stage as
+///   integer in the order above, `3` start and `4` last update as `DateAndTime`, `5` serial number of the LDevID and
+///   `6` last error, empty if there is none
+pub(crate) struct SnmpSubagent {
+    master_address: String,
+    base: Vec<u32>,
+    reconnect: Duration,
+    sessions: Arc<Sessions>,
+}
+
+impl SnmpSubagent {
+    pub(crate) fn new(config: &SnmpConfig, sessions: Arc<Sessions>) -> anyhow::Result<Self> {
+        Ok(Self {
+            master_address: config.master_address.clone(),
+            base: config.base_oid()?,
+            reconnect: Duration::from_secs(config.reconnect_secs),
+            sessions,
+        })
+    }
+
+    pub(crate) fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.connect().await {
+                    Ok(()) => event!(target: "Registrar::Snmp", Level::INFO, "AgentX master {} closed the session", self.master_address),
+                    Err(err) => event!(target: "Registrar::Snmp", Level::WARN, "AgentX session with {} failed: {}", self.master_address, err),
+                }
+                tokio::time::sleep(self.reconnect).await;
+            }
+        })
+    }
+
+    async fn connect(&self) -> anyhow::Result<()> {
+        match self.master_address.strip_prefix("tcp:") {
+            Some(address) => self.serve(TcpStream::connect(address).await?).await,
+            None => {
+                let path = self.master_address.strip_prefix("unix:").unwrap_or(&self.master_address);
+                self.serve(UnixStream::connect(path).await?).await
+            }
+        }
+    }
+
+    /// Opens a session on `stream`, registers the subtree and answers requests until the master agent closes the session
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S) -> anyhow::Result<()> {
+        let mut header = Header {
+            pdu_type: OPEN,
+            flags: NETWORK_BYTE_ORDER,
+            session_id: 0,
+            transaction_id: 0,
+            packet_id: 1,
+        };
+        let mut open = Writer::default();
+        // the default timeout of the master agent applies
+        open.u32(0);
+        open.oid(&self.base, false);
+        open.octet_string(b"open-brski registrar");
+        header.session_id = self.request(&mut stream, header, open).await?;
+
+        header.pdu_type = REGISTER;
+        header.packet_id += 1;
+        let mut register = Writer::default();
+        register.u8(0);
+        register.u8(PRIORITY);
+        // no range_subid, the subtree is registered as a whole
+        register.u16(0);
+        register.oid(&self.base, false);
+        self.request(&mut stream, header, register).await?;
+
+        event!(target: "Registrar::Snmp", Level::INFO, "Registered {} at AgentX master {}", format_oid(&self.base), self.master_address);
+
+        let mut snapshot: Option<(Instant, Mib)> = None;
+        loop {
+            let (request, payload) = read_pdu(&mut stream).await?;
+            let reply = match request.pdu_type {
+                GET | GET_NEXT | GET_BULK => {
+                    let (taken, mib) = match snapshot.take() {
+                        Some((taken, mib)) if taken.elapsed() < SNAPSHOT_TTL => (taken, mib),
+                        _ => (Instant::now(), Mib::snapshot(&self.base, &self.sessions).await),
+                    };
+                    let answer = mib.answer(request, &payload);
+                    snapshot = Some((taken, mib));
+                    match answer {
+                        Ok(varbinds) => response(NO_ERROR, 0, &varbinds),
+                        Err(err) => {
+                            event!(target: "Registrar::Snmp", Level::WARN, "Could not parse AgentX request: {}", err);
+                            response(PARSE_ERROR, 0, &[])
+                        }
+                    }
+                }
+                TEST_SET => response(NOT_WRITABLE, 1, &[]),
+                // only follow a TestSet that was refused
+                COMMIT_SET | UNDO_SET => response(NO_ERROR, 0, &[]),
+                CLOSE => return Ok(()),
+                CLEANUP_SET | RESPONSE => continue,
+                other => {
+                    event!(target: "Registrar::Snmp", Level::DEBUG, "Ignoring AgentX PDU of type {}", other);
+                    continue;
+                }
+            };
+            write_pdu(&mut stream, Header { pdu_type: RESPONSE, flags: NETWORK_BYTE_ORDER, ..request }, &reply).await?;
+        }
+    }
+
+    /// Sends a PDU of the subagent and waits for its response, returns the session id of the response
+    async fn request<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S, header: Header, payload: Writer) -> anyhow::Result<u32> {
+        write_pdu(stream, header, &payload.0).await?;
+        let (response, payload) = read_pdu(stream).await?;
+        if response.pdu_type != RESPONSE || response.packet_id != header.packet_id {
+            bail!("expected the response to PDU {}, got a PDU of type {}", header.packet_id, response.pdu_type);
+        }
+        let mut reader = Reader::new(&payload, response.flags);
+        let _sys_up_time = reader.u32()?;
+        match reader.u16()? {
+            NO_ERROR => Ok(response.session_id),
+            error => Err(anyhow!("master agent refused PDU of type {} with error {}", header.pdu_type, error)),
+        }
+    }
+}
+
+/// The objects below the base OID at one point in time, ordered as GetNext walks them
+struct Mib {
+    base: Vec<u32>,
+    objects: BTreeMap<Vec<u32>, Value>,
+}
+
+impl Mib {
+    async fn snapshot(base: &[u32], sessions: &Sessions) -> Self {
+        let counters = sessions.counters();
+        let sessions = sessions.sessions().await;
+        let mut objects = BTreeMap::new();
+
+        let scalars = [
+            Value::Gauge32(sessions.len() as u32),
+            stage_count(&sessions, SessionStage::VoucherRequested),
+            stage_count(&sessions, SessionStage::VoucherIssued),
+            stage_count(&sessions, SessionStage::VoucherAccepted),
+            stage_count(&sessions, SessionStage::Enrolled),
+            stage_count(&sessions, SessionStage::Completed),
+            stage_count(&sessions, SessionStage::Failed),
+            // Counter32 wraps around, managers handle that
+            Value::Counter32(counters.started as u32),
+            Value::Counter32(counters.enrolled as u32),
+            Value::Counter32(counters.completed as u32),
+            Value::Counter32(counters.failed as u32),
+        ];
+        for (number, value) in (1..=SCALARS).zip(scalars) {
+            objects.insert([base, &[1, number, 0]].concat(), value);
+        }
+
+        for session in &sessions {
+            let serial_number = session.serial_number.as_bytes();
+            let prefix = base.len() + 4;
+            // the index has to fit into the sub-identifiers left by the base OID
+            if prefix + 1 + serial_number.len() > MAX_SUBIDS {
+                continue;
+            }
+            let index: Vec<u32> = std::iter::once(serial_number.len() as u32)
+                .chain(serial_number.iter().map(|byte| *byte as u32))
+                .collect();
+            let columns = [
+                Value::Integer(stage_number(session.stage)),
+                Value::OctetString(date_and_time(session.started)),
+                Value::OctetString(date_and_time(session.updated)),
+                Value::OctetString(session.ldevid_serial.clone().unwrap_or_default().into_bytes()),
+                Value::OctetString(session.last_error.clone().unwrap_or_default().into_bytes()),
+            ];
+            for (column, value) in COLUMNS.zip(columns) {
+                objects.insert([base, &[2, 1, column], &index].concat(), value);
+            }
+        }
+
+        Self { base: base.to_vec(), objects }
+    }
+
+    /// Varbinds answering a Get, GetNext or GetBulk PDU
+    fn answer(&self, header: Header, payload: &[u8]) -> anyhow::Result<Vec<(Vec<u32>, Value)>> {
+        let mut reader = Reader::new(payload, header.flags);
+        if header.flags & NON_DEFAULT_CONTEXT != 0 {
+            reader.octet_string()?;
+        }
+        let (non_repeaters, max_repetitions) = match header.pdu_type {
+            GET_BULK => (reader.u16()? as usize, reader.u16()? as usize),
+            _ => (0, 0),
+        };
+        let mut ranges = vec![];
+        while !reader.is_empty() {
+            ranges.push(reader.search_range()?);
+        }
+
+        let varbinds = match header.pdu_type {
+            GET => ranges.iter().map(|range| (range.start.clone(), self.get(&range.start))).collect(),
+            GET_NEXT => ranges.iter().map(|range| self.next(range)).collect(),
+            _ => {
+                let non_repeaters = non_repeaters.min(ranges.len());
+                let mut varbinds: Vec<_> = ranges[..non_repeaters].iter().map(|range| self.next(range)).collect();
+                let mut repeaters = ranges[non_repeaters..].to_vec();
+                for _ in 0..max_repetitions {
+                    if repeaters.is_empty() || varbinds.len() + repeaters.len() > MAX_BULK_VARBINDS {
+                        break;
+                    }
+                    let row: Vec<_> = repeaters.iter().map(|range| self.next(range)).collect();
+                    for (range, (oid, _)) in repeaters.iter_mut().zip(&row) {
+                        range.start = oid.clone();
+                        range.include = false;
+                    }
+                    let finished = row.iter().all(|(_, value)| *value == Value::EndOfMibView);
+                    varbinds.extend(row);
+                    if finished {
+                        break;
+                    }
+                }
+                varbinds
+            }
+        };
+        Ok(varbinds)
+    }
+
+    fn get(&self, oid: &[u32]) -> Value {
+        if let Some(value) = self.objects.get(oid) {
+            return value.clone();
+        }
+        match oid.strip_prefix(self.base.as_slice()) {
+            Some([1, scalar, ..]) if (1..=SCALARS).contains(scalar) => Value::NoSuchInstance,
+            Some([2, 1, column, ..]) if COLUMNS.contains(column) => Value::NoSuchInstance,
+            _ => Value::NoSuchObject,
+        }
+    }
+
+    fn next(&self, range: &SearchRange) -> (Vec<u32>, Value) {
+        let lower = if range.include { Bound::Included(range.start.clone()) } else { Bound::Excluded(range.start.clone()) };
+        match self.objects.range((lower, Bound::Unbounded)).next() {
+            Some((oid, value)) if range.end.is_empty() || *oid < range.end => (oid.clone(), value.clone()),
+            _ => (range.start.clone(), Value::EndOfMibView),
+        }
+    }
+}
+
+fn stage_count(sessions: &[OnboardingSession], stage: SessionStage) -> Value {
+    Value::Gauge32(sessions.iter().filter(|session| session.stage == stage).count() as u32)
+}
+
+/// Value of the stage column, in the order of the stage scalars
+fn stage_number(stage: SessionStage) -> i32 {
+    match stage {
+        SessionStage::VoucherRequested => 1,
+        SessionStage::VoucherIssued => 2,
+        SessionStage::VoucherAccepted => 3,
+        SessionStage::Enrolled => 4,
+        SessionStage::Completed => 5,
+        SessionStage::Failed => 6,
+    }
+}
+
+/// `DateAndTime` of SNMPv2-TC in UTC
+fn date_and_time(time: DateTime<Utc>) -> Vec<u8> {
+    let year = time.year() as u16;
+    // a leap second has more than 10^9 nanoseconds
+    let deci_seconds = (time.nanosecond() / 100_000_000).min(9) as u8;
+    vec![
+        (year >> 8) as u8,
+        year as u8,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+        deci_seconds,
+        b'+',
+        0,
+        0,
+    ]
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Payload of a Response-PDU
+fn response(error: u16, index: u16, varbinds: &[(Vec<u32>, Value)]) -> Vec<u8> {
+    let mut writer = Writer::default();
+    // sysUpTime is only meaningful in responses of the master agent
+    writer.u32(0);
+    writer.u16(error);
+    writer.u16(index);
+    for (oid, value) in varbinds {
+        writer.varbind(oid, value);
+    }
+    writer.0
+}
+
+async fn read_pdu<S: AsyncRead + Unpin>(stream: &mut S) -> anyhow::Result<(Header, Vec<u8>)> {
+    let mut header = [0u8; HEADER_LENGTH];
+    stream.read_exact(&mut header).await?;
+
+    let mut reader = Reader::new(&header, header[2]);
+    let version = reader.u8()?;
+    let pdu_type = reader.u8()?;
+    let flags = reader.u8()?;
+    reader.u8()?;
+    let parsed = Header {
+        pdu_type,
+        flags,
+        session_id: reader.u32()?,
+        transaction_id: reader.u32()?,
+        packet_id: reader.u32()?,
+    };
+    let length = reader.u32()? as usize;
+    if version != VERSION {
+        bail!("unsupported AgentX version {}", version);
+    }
+    if length > MAX_PAYLOAD || !length.is_multiple_of(4) {
+        bail!("invalid AgentX payload length {}", length);
+    }
+
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    Ok((parsed, payload))
+}
+
+async fn write_pdu<S: AsyncWrite + Unpin>(stream: &mut S, header: Header, payload: &[u8]) -> anyhow::Result<()> {
+    let mut writer = Writer::default();
+    writer.u8(VERSION);
+    writer.u8(header.pdu_type);
+    writer.u8(header.flags);
+    writer.u8(0);
+    writer.u32(header.session_id);
+    writer.u32(header.transaction_id);
+    writer.u32(header.packet_id);
+    writer.u32(payload.len() as u32);
+    writer.0.extend_from_slice(payload);
+
+    stream.write_all(&writer.0).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Encodes in network byte order, the subagent sets `NETWORK_BYTE_ORDER` on all its PDUs
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Object identifier, OIDs below `1.3.6.1.<n>` are shortened with the prefix field, see RFC 2741 Section 5.1
+    fn oid(&mut self, oid: &[u32], include: bool) {
+        let (prefix, subids) = match oid {
+            [1, 3, 6, 1, prefix @ 1..=255, subids @ ..] => (*prefix as u8, subids),
+            _ => (0, oid),
+        };
+        self.u8(subids.len() as u8);
+        self.u8(prefix);
+        self.u8(include as u8);
+        self.u8(0);
+        for subid in subids {
+            self.u32(*subid);
+        }
+    }
+
+    /// Octet string, padded to a multiple of four bytes
+    fn octet_string(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+    }
+
+    fn varbind(&mut self, oid: &[u32], value: &Value) {
+        let value_type = match value {
+            Value::Integer(_) => INTEGER,
+            Value::OctetString(_) => OCTET_STRING,
+            Value::Counter32(_) => COUNTER32,
+            Value::Gauge32(_) => GAUGE32,
+            Value::NoSuchObject => NO_SUCH_OBJECT,
+            Value::NoSuchInstance => NO_SUCH_INSTANCE,
+            Value::EndOfMibView => END_OF_MIB_VIEW,
+        };
+        self.u16(value_type);
+        self.u16(0);
+        self.oid(oid, false);
+        match value {
+            Value::Integer(value) => self.u32(*value as u32),
+            Value::OctetString(value) => self.octet_string(value),
+            Value::Counter32(value) | Value::Gauge32(value) => self.u32(*value),
+            Value::NoSuchObject | Value::NoSuchInstance | Value::EndOfMibView => {}
+        }
+    }
+}
+
+/// Decodes in the byte order given by the `NETWORK_BYTE_ORDER` flag of the PDU
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], flags: u8) -> Self {
+        Self { data, big_endian: flags & NETWORK_BYTE_ORDER != 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        if self.data.len() < N {
+            bail!("truncated AgentX PDU");
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    /// Object identifier and its include field
+    fn oid(&mut self) -> anyhow::Result<(Vec<u32>, bool)> {
+        let [n_subid, prefix, include, _] = self.take()?;
+        if n_subid as usize > MAX_SUBIDS {
+            bail!("object identifier with {} sub-identifiers", n_subid);
+        }
+        let mut oid = if prefix == 0 { vec![] } else { vec![1, 3, 6, 1, prefix as u32] };
+        for _ in 0..n_subid {
+            oid.push(self.u32()?);
+        }
+        Ok((oid, include != 0))
+    }
+
+    fn octet_string(&mut self) -> anyhow::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        let padded = length.next_multiple_of(4);
+        if self.data.len() < padded {
+            bail!("truncated AgentX octet string");
+        }
+        let (value, rest) = self.data.split_at(padded);
+        self.data = rest;
+        Ok(value[..length].to_vec())
+    }
+
+    fn search_range(&mut self) -> anyhow::Result<SearchRange> {
+        let (start, include) = self.oid()?;
+        let (end, _) = self.oid()?;
+        Ok(SearchRange { start, include, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    const BASE: [u32; 8] = [1, 3, 6, 1, 4, 1, 32473, 1];
+
+    fn subagent(sessions: Arc<Sessions>) -> SnmpSubagent {
+        let config = SnmpConfig {
+            base_oid: "1.3.6.1.4.1.32473.1".to_owned(),
+            ..SnmpConfig::default()
+        };
+        SnmpSubagent::new(&config, sessions).unwrap()
+    }
+
+    fn oid(suffix: &[u32]) -> Vec<u32> {
+        [&BASE[..], suffix].concat()
+    }
+
+    fn parse_response(payload: &[u8]) -> (u16, Vec<(Vec<u32>, Value)>) {
+        let mut reader = Reader::new(payload, NETWORK_BYTE_ORDER);
+        reader.u32().unwrap();
+        let error = reader.u16().unwrap();
+        reader.u16().unwrap();
+
+        let mut varbinds = vec![];
+        while !reader.is_empty() {
+            let value_type = reader.u16().unwrap();
+            reader.u16().unwrap();
+            let (name, _) = reader.oid().unwrap();
+            let value = match value_type {
+                INTEGER => Value::Integer(reader.u32().unwrap() as i32),
+                OCTET_STRING => Value::OctetString(reader.octet_string().unwrap()),
+                COUNTER32 => Value::Counter32(reader.u32().unwrap()),
+                GAUGE32 => Value::Gauge32(reader.u32().unwrap()),
+                NO_SUCH_OBJECT => Value::NoSuchObject,
+                NO_SUCH_INSTANCE => Value::NoSuchInstance,
+                END_OF_MIB_VIEW => Value::EndOfMibView,
+                other => panic!("unexpected varbind type {}", other),
+            };
+            varbinds.push((name, value));
+        }
+        (error, varbinds)
+    }
+
+    async fn respond(master: &mut DuplexStream, request: Header, session_id: u32) {
+        let header = Header { pdu_type: RESPONSE, session_id, ..request };
+        write_pdu(master, header, &response(NO_ERROR, 0, &[])).await.unwrap();
+    }
+
+    #[test]
+    fn test_oid_encoding() {
+        let mut writer = Writer::default();
+        writer.oid(&BASE, true);
+        writer.oid(&[1, 0, 8802], false);
+        writer.octet_string(b"abcde");
+        // 1.3.6.1.4 is shortened to the prefix 4
+        assert_eq!(&writer.0[..4], &[3, 4, 1, 0]);
+
+        let mut reader = Reader::new(&writer.0, NETWORK_BYTE_ORDER);
+        assert_eq!(reader.oid().unwrap(), (BASE.to_vec(), true));
+        assert_eq!(reader.oid().unwrap(), (vec![1, 0, 8802], false));
+        assert_eq!(reader.octet_string().unwrap(), b"abcde");
+        assert!(reader.is_empty());
+
+        // without NETWORK_BYTE_ORDER the master agent sends little endian
+        let mut reader = Reader::new(&[1, 0, 0, 0, 0x39, 0x05, 0, 0], 0);
+        assert_eq!(reader.oid().unwrap(), (vec![1337], false));
+        assert!(Reader::new(&[2, 0, 0, 0, 1, 0, 0, 0], 0).oid().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_walks_the_sessions() {
+        let sessions = Sessions::default();
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherRequested).await;
+        sessions.advance("00-D0-E5-F2-00-03", SessionStage::VoucherRequested).await;
+        sessions.fail("00-D0-E5-F2-00-03", "requestvoucher", "MASA unreachable").await;
+
+        let mib = Mib::snapshot(&BASE, &sessions).await;
+        assert_eq!(mib.get(&oid(&[1, 1, 0])), Value::Gauge32(2));
+        assert_eq!(mib.get(&oid(&[1, 2, 0])), Value::Gauge32(1));
+        assert_eq!(mib.get(&oid(&[1, 7, 0])), Value::Gauge32(1));
+        assert_eq!(mib.get(&oid(&[1, 8, 0])), Value::Counter32(2));
+        assert_eq!(mib.get(&oid(&[1, 11, 0])), Value::Counter32(1));
+        assert_eq!(mib.get(&oid(&[1, 11, 1])), Value::NoSuchInstance);
+        assert_eq!(mib.get(&oid(&[3])), Value::NoSuchObject);
+
+        let index: Vec<u32> = std::iter::once(17).chain(b"00-D0-E5-F2-00-03".iter().map(|byte| *byte as u32)).collect();
+        assert_eq!(mib.get(&[&oid(&[2, 1, 2])[..], &index].concat()), Value::Integer(6));
+        assert_eq!(
+            mib.get(&[&oid(&[2, 1, 6])[..], &index].concat()),
+            Value::OctetString(b"MASA unreachable".to_vec())
+        );
+
+        let mut range = SearchRange { start: BASE.to_vec(), include: false, end: vec![] };
+        let mut walked = vec![];
+        loop {
+            let (name, value) = mib.next(&range);
+            if value == Value::EndOfMibView {
+                break;
+            }
+            range.start = name.clone();
+            walked.push(name);
+        }
+        // eleven scalars and five columns of two sessions, column by column
+        assert_eq!(walked.len(), 11 + 5 * 2);
+        assert!(walked.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(walked[11][..BASE.len() + 3], oid(&[2, 1, 2]));
+        assert_eq!(walked[13][..BASE.len() + 3], oid(&[2, 1, 3]));
+
+        // an end before the next object ends the walk
+        let bounded = SearchRange { start: oid(&[1, 11, 0]), include: false, end: oid(&[2]) };
+        assert_eq!(mib.next(&bounded), (oid(&[1, 11, 0]), Value::EndOfMibView));
+    }
+
+    #[tokio::test]
+    async fn test_serves_a_master_agent() {
+        let sessions = Arc::new(Sessions::default());
+        sessions.advance("00-D0-E5-F2-00-02", SessionStage::VoucherRequested).await;
+        let (mut master, stream) = duplex(64 * 1024);
+        let subagent = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { subagent(sessions).serve(stream).await }
+        });
+
+        let (open, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!(open.pdu_type, OPEN);
+        let mut reader = Reader::new(&payload, open.flags);
+        reader.u32().unwrap();
+        assert_eq!(reader.oid().unwrap().0, BASE.to_vec());
+        respond(&mut master, open, 42).await;
+
+        let (register, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!((register.pdu_type, register.session_id), (REGISTER, 42));
+        let mut reader = Reader::new(&payload, register.flags);
+        reader.u32().unwrap();
+        assert_eq!(reader.oid().unwrap().0, BASE.to_vec());
+        respond(&mut master, register, 42).await;
+
+        // one non-repeater and the session table with up to three repetitions
+        let mut request = Writer::default();
+        request.u16(1);
+        request.u16(3);
+        request.oid(&oid(&[1, 1, 0]), true);
+        request.oid(&[], false);
+        request.oid(&oid(&[2]), false);
+        request.oid(&[], false);
+        let get_bulk = Header { pdu_type: GET_BULK, flags: NETWORK_BYTE_ORDER, session_id: 42, transaction_id: 7, packet_id: 8 };
+        write_pdu(&mut master, get_bulk, &request.0).await.unwrap();
+
+        let (response, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!((response.pdu_type, response.transaction_id, response.packet_id), (RESPONSE, 7, 8));
+        let (error, varbinds) = parse_response(&payload);
+        assert_eq!(error, NO_ERROR);
+        assert_eq!(varbinds.len(), 4);
+        assert_eq!(varbinds[0], (oid(&[1, 1, 0]), Value::Gauge32(1)));
+        assert_eq!(varbinds[1].1, Value::Integer(1));
+        assert!(matches!(&varbinds[2].1, Value::OctetString(started) if started.len() == 11 && started[8..] == *b"+\0\0"));
+        assert_eq!(varbinds[3].0[..BASE.len() + 3], oid(&[2, 1, 4]));
+
+        // a request right after the first one is answered from the same snapshot
+        sessions.advance("00-D0-E5-F2-00-03", SessionStage::VoucherRequested).await;
+        let mut request = Writer::default();
+        request.oid(&oid(&[1, 1, 0]), true);
+        request.oid(&[], false);
+        let get = Header { pdu_type: GET, transaction_id: 8, packet_id: 9, ..get_bulk };
+        write_pdu(&mut master, get, &request.0).await.unwrap();
+        let (_, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!(parse_response(&payload).1, vec![(oid(&[1, 1, 0]), Value::Gauge32(1))]);
+
+        let test_set = Header { pdu_type: TEST_SET, transaction_id: 9, packet_id: 10, ..get_bulk };
+        write_pdu(&mut master, test_set, &[]).await.unwrap();
+        let (_, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!(parse_response(&payload).0, NOT_WRITABLE);
+
+        write_pdu(&mut master, Header { pdu_type: CLOSE, packet_id: 11, ..get_bulk }, &[5, 0, 0, 0]).await.unwrap();
+        subagent.await.unwrap().unwrap();
+    }
+}
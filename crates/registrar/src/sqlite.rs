@@ -0,0 +1,37 @@
+use std::sync::{Arc, Mutex};
+
+use common::server_error::ServerError;
+use rusqlite::Connection;
+
+pub(crate) fn sql_error(err: rusqlite::Error) -> ServerError {
+    anyhow::Error::from(err).into()
+}
+
+/// SQLite connection whose statements run on the blocking thread pool, so a slow disk does not stall the runtime
+#[derive(Clone)]
+pub(crate) struct Database {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    pub(crate) fn new(connection: Connection) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(connection)),
+        }
+    }
+
+    /// Runs `query` with the connection to itself, queries of other tasks wait until it returned
+    pub(crate) async fn run<T, F>(&self, query: F) -> Result<T, ServerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, ServerError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&mut connection)
+        })
+        .await
+        .map_err(anyhow::Error::from)?
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::server_error::ServerError;
+use ssh_key::{
+    certificate::{Builder, CertType},
+    rand_core::OsRng,
+    PrivateKey, PublicKey,
+};
+
+/// Leeway for pledges whose clock runs behind the registrar
+const CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+fn ssh_error(err: ssh_key::Error) -> ServerError {
+    ServerError::BadRequestWithReason(format!("SSH host certificate: {}", err))
+}
+
+/// Signs an OpenSSH host certificate for the host key of a pledge, valid for its serial-number as principal
+pub(crate) fn sign_host_certificate(
+    ca_key: &PrivateKey,
+    host_key: &str,
+    serial_number: &str,
+    validity_days: u32,
+) -> Result<String, ServerError> {
+    let host_key = PublicKey::from_openssh(host_key).map_err(ssh_error)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let valid_after = now.saturating_sub(CLOCK_SKEW).as_secs();
+    let valid_before = now.as_secs() + validity_days as u64 * 86400;
+
+    let mut builder = Builder::new_with_random_nonce(&mut OsRng, host_key, valid_after, valid_before)
+        .map_err(ssh_error)?;
+    builder
+        .cert_type(CertType::Host)
+        .and_then(|builder| builder.key_id(serial_number))
+        .and_then(|builder| builder.valid_principal(serial_number))
+        .map_err(ssh_error)?;
+
+    let certificate = builder.sign(ca_key).map_err(ssh_error)?;
+    certificate.to_openssh().map_err(ssh_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{Algorithm, Certificate, HashAlg};
+
+    use super::*;
+
+    #[test]
+    fn test_signs_host_certificate() {
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let host_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+
+        let certificate =
+            sign_host_certificate(&ca_key, &host_key.public_key().to_openssh().unwrap(), "00-D0-E5-F2-00-02", 365).unwrap();
+        let certificate = Certificate::from_openssh(&certificate).unwrap();
+
+        assert_eq!(certificate.cert_type(), CertType::Host);
+        assert_eq!(certificate.valid_principals(), ["00-D0-E5-F2-00-02".to_string()]);
+        assert_eq!(certificate.public_key(), host_key.public_key().key_data());
+        certificate.validate([&ca_key.public_key().fingerprint(HashAlg::Sha256)]).unwrap();
+
+        assert!(sign_host_certificate(&ca_key, "ssh-ed25519 invalid", "00-D0-E5-F2-00-02", 365).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use brski_prm_artifacts::ietf_voucher::request_artifact::VoucherRequestArtifactDetails;
+use openssl::{nid::Nid, x509::X509};
+
+/// Why a pledge voucher-request does not name this registrar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ProximityError {
+    /// Neither `proximity-registrar-cert` nor `agent-provided-proximity-registrar-cert` is set
+    Missing,
+    /// The named certificate is not one of this registrar
+    Mismatch { field: &'static str, subject: String },
+}
+
+impl std::fmt::Display for ProximityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProximityError::Missing => write!(f, "voucher request names no proximity registrar certificate"),
+            ProximityError::Mismatch { field, subject } => write!(
+                f,
+                "{} of the voucher request is the certificate of {}, not of this registrar",
+                field, subject
+            ),
+        }
+    }
+}
+
+/// Checks that a pledge voucher-request names this registrar before it is forwarded, so a voucher request meant for
+/// another registrar, or one whose registrar certificate was swapped by the registrar-agent, is not turned into a
+/// voucher pinning a foreign domain (RFC 8995 Section 5.5, BRSKI-PRM Section 7.3).
+/// The certificates are compared as DER, every certificate set in the request has to match.
+#[derive(Debug, Clone)]
+pub(crate) struct ProximityValidator {
+    accepted: Vec<Vec<u8>>,
+}
+
+impl ProximityValidator {
+    /// Accepts `registrar_certificate` and the `additional` certificates configured for the registrar
+    pub(crate) fn new(registrar_certificate: &X509, additional: &[X509]) -> Result<Self, openssl::error::ErrorStack> {
+        let accepted = std::iter::once(registrar_certificate)
+            .chain(additional)
+            .map(|cert| cert.to_der())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { accepted })
+    }
+
+    pub(crate) fn validate(&self, details: &VoucherRequestArtifactDetails) -> Result<(), ProximityError> {
+        let named = [
+            ("proximity-registrar-cert", details.proximity_registrar_cert.as_ref()),
+            ("agent-provided-proximity-registrar-cert", details.agent_provided_proximity_registrar_cert.as_ref()),
+        ];
+        if named.iter().all(|(_, cert)| cert.is_none()) {
+            return Err(ProximityError::Missing);
+        }
+
+        for (field, cert) in named {
+            let Some(cert) = cert else {
+                continue;
+            };
+            if !self.accepted.iter().any(|accepted| accepted.as_slice() == cert.as_ref()) {
+                return Err(ProximityError::Mismatch { field, subject: subject(cert) });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn subject(cert: &X509) -> String {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().to_string().ok())
+        .unwrap_or_else(|| format!("{:?}", cert.subject_name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use brski_prm_artifacts::{
+        ietf_voucher::{agent_signed_data::AgentSignedData, assertion::Assertion, VoucherRequest},
+        jws::JWS,
+        pvr::response::{Response, PVR_JWS},
+    };
+    use example_certs::OpensslTestCerts;
+
+    use super::*;
+
+    /// Encoded PVR of the test pledge naming `registrar`
+    fn pvr(certs: &OpensslTestCerts, registrar: &X509) -> String {
+        let created_on = chrono::Utc::now();
+        let serial_number = "00-D0-E5-F2-00-02".to_string();
+        let agent_signed_data = AgentSignedData::new(created_on, serial_number.clone())
+            .sign("test-skid", certs.registrar_agent.1.private_key_to_der().unwrap())
+            .unwrap();
+
+        let mut details = VoucherRequestArtifactDetails::default();
+        details.created_on = Some(created_on);
+        details.serial_number = serial_number;
+        details.agent_signed_data = Some(agent_signed_data);
+        details.nonce = Some(b"123456789".to_vec());
+        details.assertion = Some(Assertion::AgentProximity);
+        details.agent_provided_proximity_registrar_cert = Some(registrar.clone().into());
+
+        let jws: PVR_JWS = Response::new(VoucherRequest { details }, [certs.pledge.0.clone()]).try_into().unwrap();
+        jws.encode(certs.pledge.1.private_key_to_der().unwrap()).unwrap().try_encoded_data().unwrap()
+    }
+
+    fn decoded(encoded: String) -> Result<VoucherRequestArtifactDetails, josekit::JoseError> {
+        let jws: PVR_JWS = JWS::Encoded(encoded);
+        Ok(jws.decode()?.try_decoded_data()?.payload.details)
+    }
+
+    #[test]
+    fn test_accepts_own_certificates() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let validator = ProximityValidator::new(&certs.registrar.0, std::slice::from_ref(&certs.registrar_agent.0)).unwrap();
+
+        let mut details = decoded(pvr(&certs, &certs.registrar.0)).unwrap();
+        assert_eq!(validator.validate(&details), Ok(()));
+
+        // a pledge talking to the registrar itself names it in proximity-registrar-cert
+        details.agent_provided_proximity_registrar_cert = None;
+        details.proximity_registrar_cert = Some(certs.registrar_agent.0.clone().into());
+        assert_eq!(validator.validate(&details), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_foreign_and_missing_certificates() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let validator = ProximityValidator::new(&certs.registrar.0, &[]).unwrap();
+
+        let mut details = decoded(pvr(&certs, &certs.vendor.0)).unwrap();
+        match validator.validate(&details) {
+            Err(err @ ProximityError::Mismatch { field: "agent-provided-proximity-registrar-cert", .. }) => {
+                assert!(err.to_string().contains("not of this registrar"));
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        // every named certificate has to match, not just one of them
+        details.proximity_registrar_cert = Some(certs.registrar.0.clone().into());
+        assert!(matches!(validator.validate(&details), Err(ProximityError::Mismatch { .. })));
+
+        details.proximity_registrar_cert = None;
+        details.agent_provided_proximity_registrar_cert = None;
+        assert_eq!(validator.validate(&details), Err(ProximityError::Missing));
+    }
+
+    #[test]
+    fn test_tampered_request() {
+        let certs: OpensslTestCerts = example_certs::generate_certs().into();
+        let validator = ProximityValidator::new(&certs.registrar.0, &[]).unwrap();
+
+        // the pledge signed a request for another registrar, swapping in the payload naming this one breaks the signature
+        let foreign: serde_json::Value = serde_json::from_str(&pvr(&certs, &certs.vendor.0)).unwrap();
+        let own: serde_json::Value = serde_json::from_str(&pvr(&certs, &certs.registrar.0)).unwrap();
+        let mut tampered = foreign.clone();
+        tampered["payload"] = own["payload"].clone();
+
+        assert!(decoded(tampered.to_string()).is_err());
+        assert!(validator.validate(&decoded(own.to_string()).unwrap()).is_ok());
+        assert!(validator.validate(&decoded(foreign.to_string()).unwrap()).is_err());
+    }
+}
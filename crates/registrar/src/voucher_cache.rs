@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+use brski_prm_artifacts::{issued_voucher::IssuedVoucherJWS, jws::JWS};
+use chrono::{DateTime, Utc};
+use common::{error::AppError, server_error::ServerError};
+use common::chain::verify_chain;
+use openssl::x509::{X509Ref, X509};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::{
+    jobs::{Job, JobQueue},
+    parsed_config::ParsedConfig,
+};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CachedVoucher {
+    pub(crate) serial_number: String,
+    pub(crate) expires_on: DateTime<Utc>,
+    pub(crate) source: String,
+    #[serde(skip)]
+    pub(crate) encoded: String,
+}
+
+/// A voucher request that was answered from the cache and still has to be submitted to the MASA
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DeferredRequest {
+    pub(crate) serial_number: String,
+    pub(crate) served_at: DateTime<Utc>,
+    pub(crate) masa_url: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VoucherCacheStatus {
+    pub(crate) vouchers: Vec<CachedVoucher>,
+    pub(crate) deferred: Vec<DeferredRequest>,
+}
+
+/// Pre-generated nonceless vouchers the registrar serves while the MASA is unreachable.
+/// Requests answered from the cache are queued as [`Job::MasaSubmission`] and submitted to the MASA once it
+/// is reachable again, so the MASA audit log stays complete.
+#[derive(Debug, Default)]
+pub(crate) struct VoucherCache {
+    vouchers: RwLock<HashMap<String, CachedVoucher>>,
+    trust_anchors: Vec<X509>,
+    registrar_certificate: Option<X509>,
+}
+
+impl VoucherCache {
+    /// Imports all vouchers from the configured cache directory. Vouchers that are not usable are skipped.
+    pub(crate) fn load(config: &ParsedConfig) -> anyhow::Result<Self, AppError> {
+        let mut vouchers = HashMap::new();
+
+        let Some(dir) = &config.config.voucher_cache_dir else {
+            return Ok(Self::default());
+        };
+
+        let trust_anchors = config
+            .config
+            .voucher_cache_trust_anchors
+            .iter()
+            .map(|path| Ok(X509::from_pem(&std::fs::read(path.relative())?)?))
+            .collect::<anyhow::Result<Vec<_>, AppError>>()?;
+
+        let dir = dir.relative();
+        event!(Level::INFO, "Importing pre-generated vouchers from {:?}", dir);
+
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            match parse_voucher(&path, &config.registrar_certificate, &trust_anchors, Utc::now()) {
+                Ok(voucher) => {
+                    event!(Level::INFO, "Imported voucher for {} from {:?}", voucher.serial_number, path);
+                    vouchers.insert(voucher.serial_number.clone(), voucher);
+                }
+                Err(err) => {
+                    event!(Level::WARN, "Skipping voucher {:?}: {}", path, err);
+                }
+            }
+        }
+
+        Ok(Self {
+            vouchers: RwLock::new(vouchers),
+            trust_anchors,
+            registrar_certificate: Some(config.registrar_certificate.clone()),
+        })
+    }
+
+    /// Returns the cached voucher for the pledge. It is verified again like on import, so it is not served once it
+    /// expired, and a voucher for another pledge is never served.
+    pub(crate) async fn lookup(&self, serial_number: &str) -> Option<IssuedVoucherJWS> {
+        let vouchers = self.vouchers.read().await;
+        let voucher = vouchers.get(serial_number)?;
+        let registrar_certificate = self.registrar_certificate.as_ref()?;
+
+        match parse_encoded_voucher(voucher.encoded.clone(), registrar_certificate, &self.trust_anchors, Utc::now()) {
+            Ok(verified) if verified.serial_number == serial_number => Some(JWS::Encoded(verified.encoded)),
+            Ok(verified) => {
+                event!(Level::WARN, "Cached voucher of {} is issued for {}", serial_number, verified.serial_number);
+                None
+            }
+            Err(err) => {
+                event!(Level::WARN, "Not serving cached voucher of {}: {}", serial_number, err);
+                None
+            }
+        }
+    }
+
+    /// Requests served from the cache that still wait in the job queue for their submission to the MASA
+    pub(crate) async fn status(&self, jobs: &JobQueue) -> Result<VoucherCacheStatus, ServerError> {
+        let deferred = jobs
+            .jobs()
+            .await?
+            .into_iter()
+            .filter_map(|record| match record.job {
+                Job::MasaSubmission { serial_number, masa_url, served_at, .. } => Some(DeferredRequest {
+                    serial_number,
+                    served_at,
+                    masa_url,
+                }),
+                Job::VoucherRequest { .. } | Job::VoucherStatus { .. } => None,
+            })
+            .collect();
+
+        Ok(VoucherCacheStatus {
+            vouchers: self.vouchers.read().await.values().cloned().collect(),
+            deferred,
+        })
+    }
+}
+
+/// Only connection failures count as an unreachable MASA, rejected requests are never answered from the cache
+pub(crate) fn is_masa_unreachable(err: &ServerError) -> bool {
+    matches!(err, ServerError::ReqwestError { source } if source.is_connect() || source.is_timeout())
+}
+
+fn parse_voucher(
+    path: &Path,
+    registrar_certificate: &X509Ref,
+    trust_anchors: &[X509],
+    now: DateTime<Utc>,
+) -> anyhow::Result<CachedVoucher> {
+    let encoded = std::fs::read_to_string(path)?.trim().to_string();
+    let voucher = parse_encoded_voucher(encoded, registrar_certificate, trust_anchors, now)?;
+
+    Ok(CachedVoucher {
+        source: path.display().to_string(),
+        ..voucher
+    })
+}
+
+/// Decoding verifies the signature with the key of the x5c header, which is only trusted if it chains to one of the
+/// `trust_anchors`
+fn parse_encoded_voucher(
+    encoded: String,
+    registrar_certificate: &X509Ref,
+    trust_anchors: &[X509],
+    now: DateTime<Utc>,
+) -> anyhow::Result<CachedVoucher> {
+    let jws: IssuedVoucherJWS = JWS::Encoded(encoded.clone());
+    let decoded = jws.decode()?.try_decoded_data()?;
+
+    let chain = decoded
+        .header
+        .as_ref()
+        .and_then(|header| header.x509_certificate_chain())
+        .ok_or(anyhow!("voucher has no x5c header"))?
+        .iter()
+        .map(|der| X509::from_der(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    let signer = chain.first().ok_or(anyhow!("voucher has an empty x5c header"))?;
+    if let Err(err) = verify_chain(signer, trust_anchors, &chain[1..])? {
+        return Err(anyhow!("voucher is not signed by a trusted MASA: {}", err));
+    }
+
+    let details = decoded.payload.details;
+
+    if details.nonce.is_some() {
+        return Err(anyhow!("voucher is not nonceless"));
+    }
+
+    let expires_on = details
+        .expires_on
+        .ok_or(anyhow!("nonceless voucher has no expires-on"))?;
+    if expires_on <= now {
+        return Err(anyhow!("voucher expired on {}", expires_on));
+    }
+
+    let pinned = details
+        .pinned_domain_cert
+        .ok_or(anyhow!("voucher has no pinned-domain-cert"))?;
+    if pinned.to_der()? != registrar_certificate.to_der()? {
+        return Err(anyhow!("voucher is pinned to a different domain"));
+    }
+
+    Ok(CachedVoucher {
+        serial_number: details.serial_number,
+        expires_on,
+        source: String::new(),
+        encoded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use brski_prm_artifacts::{
+        ietf_voucher::artifact::{VoucherArtifact, VoucherArtifactDetails},
+        issued_voucher::IssuedVoucher,
+    };
+
+    use super::*;
+
+    fn issue_voucher(
+        certs: &example_certs::OpensslTestCerts,
+        nonce: Option<Vec<u8>>,
+        expires_on: DateTime<Utc>,
+    ) -> String {
+        let (vendor_cert, vendor_key) = &certs.vendor;
+        let (registrar_cert, _) = &certs.registrar;
+
+        let details = VoucherArtifactDetails {
+            serial_number: "00-D0-E5-F2-00-02".to_string(),
+            created_on: Some(Utc::now()),
+            expires_on: Some(expires_on),
+            nonce,
+            pinned_domain_cert: Some(registrar_cert.clone().into()),
+            ..Default::default()
+        };
+
+        let voucher = IssuedVoucher::new(VoucherArtifact { details }, [vendor_cert.clone()]);
+        let jws: IssuedVoucherJWS = voucher.try_into().unwrap();
+        jws.encode(vendor_key.private_key_to_der().unwrap())
+            .unwrap()
+            .try_encoded_data()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_accepts_nonceless_voucher() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_cert, _) = &certs.registrar;
+        let (vendor_ca, _) = &certs.vendor_ca;
+
+        let encoded = issue_voucher(&certs, None, Utc::now() + chrono::Duration::days(30));
+        let voucher = parse_encoded_voucher(encoded, registrar_cert, std::slice::from_ref(vendor_ca), Utc::now()).unwrap();
+
+        assert_eq!(voucher.serial_number, "00-D0-E5-F2-00-02");
+    }
+
+    #[test]
+    fn test_rejects_unusable_vouchers() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_cert, _) = &certs.registrar;
+        let (other_cert, _) = &certs.pledge;
+        let anchors = std::slice::from_ref(&certs.vendor_ca.0);
+
+        let with_nonce = issue_voucher(&certs, Some(b"nonce".to_vec()), Utc::now() + chrono::Duration::days(30));
+        assert!(parse_encoded_voucher(with_nonce, registrar_cert, anchors, Utc::now()).is_err());
+
+        let expires_on = Utc::now() + chrono::Duration::days(1);
+        let expired = issue_voucher(&certs, None, expires_on);
+        assert!(parse_encoded_voucher(expired, registrar_cert, anchors, expires_on + chrono::Duration::seconds(1)).is_err());
+
+        let other_domain = issue_voucher(&certs, None, Utc::now() + chrono::Duration::days(30));
+        assert!(parse_encoded_voucher(other_domain, other_cert, anchors, Utc::now()).is_err());
+
+        // signed by a key that is not a trusted MASA
+        let untrusted = issue_voucher(&certs, None, Utc::now() + chrono::Duration::days(30));
+        assert!(parse_encoded_voucher(untrusted, registrar_cert, std::slice::from_ref(other_cert), Utc::now()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verifies_again_before_serving() {
+        let certs: example_certs::OpensslTestCerts = example_certs::generate_certs().into();
+        let (registrar_cert, _) = &certs.registrar;
+        let (vendor_ca, _) = &certs.vendor_ca;
+
+        let encoded = issue_voucher(&certs, None, Utc::now() + chrono::Duration::days(30));
+        let voucher = parse_encoded_voucher(encoded, registrar_cert, std::slice::from_ref(vendor_ca), Utc::now()).unwrap();
+        let cache = |trust_anchors: Vec<X509>| VoucherCache {
+            vouchers: RwLock::new(HashMap::from([(voucher.serial_number.clone(), voucher.clone())])),
+            trust_anchors,
+            registrar_certificate: Some(registrar_cert.clone()),
+        };
+
+        assert!(cache(vec![vendor_ca.clone()]).lookup("00-D0-E5-F2-00-02").await.is_some());
+        assert!(cache(vec![vendor_ca.clone()]).lookup("00-D0-E5-F2-00-03").await.is_none());
+        // the trust anchors changed since the import
+        assert!(cache(vec![certs.pledge.0.clone()]).lookup("00-D0-E5-F2-00-02").await.is_none());
+    }
+}
@@ -0,0 +1,45 @@
+#!/usr/bin/env bash
+# Builds the firmware with the full and a minimal feature set and reports the size of the flash image.
+# The report is kept in target/size-report.txt, a rerun prints the change against the previous one.
+#
+#   ./size-report.sh                # full and minimal
+#   ./size-report.sh "jwe usb"      # additionally the minimal build plus the given features
+set -euo pipefail
+
+cd "$(dirname "$0")"
+
+TARGET=xtensa-esp32s3-espidf
+CHIP=esp32s3
+BASE_FEATURES="std embassy esp-idf-svc/native"
+REPORT=target/size-report.txt
+
+declare -A BUILDS=(
+    [full]="$BASE_FEATURES full"
+    [minimal]="$BASE_FEATURES"
+)
+if [ $# -gt 0 ]; then
+    BUILDS[custom]="$BASE_FEATURES $1"
+fi
+
+mkdir -p target
+previous=$(cat "$REPORT" 2>/dev/null || true)
+: > "$REPORT.new"
+
+for name in full minimal custom; do
+    [ -n "${BUILDS[$name]+set}" ] || continue
+
+    cargo build --release --no-default-features --features "${BUILDS[$name]}" --target-dir "target/size-$name" >&2
+    image="target/size-$name/size-report.bin"
+    espflash save-image --chip "$CHIP" "target/size-$name/$TARGET/release/esp32" "$image" >&2
+    size=$(stat -c %s "$image")
+
+    before=$(awk -v name="$name" '$1 == name { print $2 }' <<< "$previous")
+    if [ -n "$before" ]; then
+        printf '%-8s %9d bytes (%+d)\n' "$name" "$size" "$((size - before))"
+    else
+        printf '%-8s %9d bytes\n' "$name" "$size"
+    fi
+    echo "$name $size" >> "$REPORT.new"
+done
+
+mv "$REPORT.new" "$REPORT"
@@ -0,0 +1,204 @@
+//! AES Key Wrap as defined in [RFC 3394](https://tools.ietf.org/html/rfc3394), used by the
+//! `ECDH-ES+A128KW` and `ECDH-ES+A256KW` key management algorithms.
+//!
+//! `ring` does not expose the AES block cipher, so this is a plain software implementation of the forward
+//! cipher. It is not constant time. It only ever wraps freshly generated content encryption keys under key
+//! encryption keys derived from an ephemeral key agreement, so no key is used for more than one wrap.
+
+use crate::biscuit::errors::Error;
+
+/// Default initial value, RFC 3394 Section 2.2.3.1
+const INITIAL_VALUE: [u8; 8] = [0xa6; 8];
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// Wraps `key` with the key encryption key `kek`, which must be 128 or 256 bits long.
+/// `key` must consist of at least two 64 bit blocks.
+pub(crate) fn wrap(kek: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+    if key.len() < 16 || key.len() % 8 != 0 {
+        return Err(Error::UnsupportedOperation);
+    }
+    let round_keys = expand_key(kek)?;
+
+    let n = key.len() / 8;
+    let mut a = INITIAL_VALUE;
+    let mut r: Vec<[u8; 8]> = key
+        .chunks_exact(8)
+        .map(|block| block.try_into().expect("chunks are 8 bytes"))
+        .collect();
+
+    for j in 0..6 {
+        for (i, r_i) in r.iter_mut().enumerate() {
+            let mut b = [0u8; 16];
+            b[..8].copy_from_slice(&a);
+            b[8..].copy_from_slice(r_i);
+            encrypt_block(&round_keys, &mut b);
+
+            let t = ((n * j + i + 1) as u64).to_be_bytes();
+            for (k, a_k) in a.iter_mut().enumerate() {
+                *a_k = b[k] ^ t[k];
+            }
+            r_i.copy_from_slice(&b[8..]);
+        }
+    }
+
+    let mut wrapped = Vec::with_capacity(key.len() + 8);
+    wrapped.extend_from_slice(&a);
+    for r_i in r {
+        wrapped.extend_from_slice(&r_i);
+    }
+    Ok(wrapped)
+}
+
+/// FIPS 197 key expansion for AES-128 and AES-256
+fn expand_key(key: &[u8]) -> Result<Vec<[u8; 16]>, Error> {
+    let (nk, rounds) = match key.len() {
+        16 => (4, 10),
+        32 => (8, 14),
+        _ => return Err(Error::UnsupportedOperation),
+    };
+
+    let mut words: Vec<[u8; 4]> = key
+        .chunks_exact(4)
+        .map(|word| word.try_into().expect("chunks are 4 bytes"))
+        .collect();
+    let mut rcon = 1u8;
+    for i in nk..4 * (rounds + 1) {
+        let mut temp = words[i - 1];
+        if i % nk == 0 {
+            temp.rotate_left(1);
+            temp = temp.map(|byte| SBOX[byte as usize]);
+            temp[0] ^= rcon;
+            rcon = xtime(rcon);
+        } else if nk > 6 && i % nk == 4 {
+            temp = temp.map(|byte| SBOX[byte as usize]);
+        }
+        let previous = words[i - nk];
+        words.push([0, 1, 2, 3].map(|k| previous[k] ^ temp[k]));
+    }
+
+    Ok(words
+        .chunks_exact(4)
+        .map(|round_key| {
+            let mut bytes = [0u8; 16];
+            for (k, word) in round_key.iter().enumerate() {
+                bytes[4 * k..4 * k + 4].copy_from_slice(word);
+            }
+            bytes
+        })
+        .collect())
+}
+
+/// AES forward cipher, the state holds the block column by column
+fn encrypt_block(round_keys: &[[u8; 16]], block: &mut [u8; 16]) {
+    let rounds = round_keys.len() - 1;
+    add_round_key(block, &round_keys[0]);
+
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        for byte in block.iter_mut() {
+            *byte = SBOX[*byte as usize];
+        }
+        shift_rows(block);
+        if round != rounds {
+            mix_columns(block);
+        }
+        add_round_key(block, round_key);
+    }
+}
+
+fn add_round_key(block: &mut [u8; 16], round_key: &[u8; 16]) {
+    for (byte, key) in block.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+/// Row `r` is rotated left by `r` columns
+fn shift_rows(block: &mut [u8; 16]) {
+    let state = *block;
+    for row in 1..4 {
+        for column in 0..4 {
+            block[row + 4 * column] = state[row + 4 * ((column + row) % 4)];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; 16]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+/// Multiplication by `x` in GF(2^8)
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::HEXUPPER;
+
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        HEXUPPER.decode(value.as_bytes()).unwrap()
+    }
+
+    /// FIPS 197 Appendix C.1
+    #[test]
+    fn aes_128_block() {
+        let round_keys = expand_key(&hex("000102030405060708090A0B0C0D0E0F")).unwrap();
+        let mut block: [u8; 16] = hex("00112233445566778899AABBCCDDEEFF").try_into().unwrap();
+        encrypt_block(&round_keys, &mut block);
+        assert_eq!(block.to_vec(), hex("69C4E0D86A7B0430D8CDB78070B4C55A"));
+    }
+
+    /// RFC 3394 Section 4.1
+    #[test]
+    fn wrap_128_bit_key_with_128_bit_kek() {
+        let wrapped = wrap(&hex("000102030405060708090A0B0C0D0E0F"), &hex("00112233445566778899AABBCCDDEEFF")).unwrap();
+        assert_eq!(wrapped, hex("1FA68B0A8112B447AEF34BD8FB5A7B829D3E862371D2CFE5"));
+    }
+
+    /// RFC 3394 Section 4.6
+    #[test]
+    fn wrap_256_bit_key_with_256_bit_kek() {
+        let wrapped = wrap(
+            &hex("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F"),
+            &hex("00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F"),
+        )
+        .unwrap();
+        assert_eq!(
+            wrapped,
+            hex("28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21")
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_lengths() {
+        assert!(wrap(&[0; 24], &[0; 16]).is_err());
+        assert!(wrap(&[0; 16], &[0; 12]).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 //! Errors returned will be converted to one of the structs in this module.
-use crate::biscuit::SingleOrMultiple;
+use crate::biscuit::{DecodeDiagnostic, SingleOrMultiple};
 use chrono::Duration;
 use std::{error, fmt, io, str, string};
 
@@ -59,6 +59,15 @@ pub enum DecodeError {
         /// Actual number of parts
         actual: usize,
     },
+    /// The token is out of spec, the parameter lists every deviation found
+    OutOfSpec(Vec<DecodeDiagnostic>),
+    /// The encoded token is longer than [`crate::biscuit::DecodeOptions::max_token_length`]
+    TooLarge {
+        /// Longest token accepted
+        limit: usize,
+        /// Length of the token
+        actual: usize,
+    },
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -95,6 +104,17 @@ pub enum ValidationError {
     UnsupportedKeyAlgorithm,
     /// An algorithm is needed for verification but was not provided
     MissingAlgorithm,
+    /// The header carries no `x5c` certificate chain
+    MissingCertificateChain,
+    /// A certificate of the `x5c` chain or trust store can not be parsed or uses an unsupported algorithm
+    /// The parameter describes what is wrong with it
+    MalformedCertificate(String),
+    /// A certificate of the chain is not valid at the time of verification
+    CertificateNotValid,
+    /// The chain does not lead to a certificate of the trust store
+    UntrustedCertificateChain,
+    /// The `x5t` or `x5t#S256` thumbprint does not match the signer certificate
+    CertificateThumbprintMismatch,
 }
 
 macro_rules! impl_from_error {
@@ -190,6 +210,18 @@ impl fmt::Display for DecodeError {
                 "Expected {} parts in Compact JSON representation but got {}",
                 expected, actual
             ),
+            TooLarge { limit, actual } => write!(
+                f,
+                "Token of {} bytes exceeds the limit of {} bytes",
+                actual, limit
+            ),
+            OutOfSpec(ref diagnostics) => {
+                write!(f, "Token is out of spec:")?;
+                for diagnostic in diagnostics {
+                    write!(f, " {};", diagnostic)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -235,6 +267,18 @@ impl fmt::Display for ValidationError {
                 f,
                 "An algorithm is needed for verification but was not provided"
             ),
+            MissingCertificateChain => write!(f, "Header is missing x5c"),
+            MalformedCertificate(ref reason) => write!(f, "Malformed certificate: {}", reason),
+            CertificateNotValid => write!(f, "Certificate is expired or not yet valid"),
+            UntrustedCertificateChain => {
+                write!(f, "Certificate chain does not lead to a trust anchor")
+            }
+            CertificateThumbprintMismatch => {
+                write!(
+                    f,
+                    "Certificate thumbprint does not match the signer certificate"
+                )
+            }
         }
     }
 }
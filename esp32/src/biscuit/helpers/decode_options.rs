@@ -0,0 +1,252 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Defines whether a deviation from the JOSE specifications rejects the token
+#[derive(Default)]
+pub enum Strictness {
+    /// The token is rejected
+    #[default]
+    Strict,
+    /// The token is decoded anyway and the deviation is only reported
+    Lenient,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Options for decoding compact JWS and JWE
+///
+/// By default, every check is strict and tokens that are out of spec are rejected with
+/// [`crate::biscuit::errors::DecodeError::OutOfSpec`] listing all deviations.
+/// [`DecodeOptions::lenient`] decodes such tokens anyway, which helps debugging interop issues,
+/// and returns the deviations as [`DecodeDiagnostic`]s.
+pub struct DecodeOptions {
+    /// `crit` lists a header parameter that is not in `understood_critical_headers` (RFC 7515 Section 4.1.11)
+    pub unknown_critical_headers: Strictness,
+
+    /// The protected header contains a parameter more than once (RFC 7515 Section 4)
+    pub duplicate_header_parameters: Strictness,
+
+    /// A part is base64url encoded with `=` padding (RFC 7515 Section 2)
+    pub base64_padding: Strictness,
+
+    /// A part that carries data is empty, e.g. the header or the signature
+    pub empty_parts: Strictness,
+
+    /// Extension header parameters the application understands and may be listed in `crit`
+    pub understood_critical_headers: Vec<String>,
+
+    /// Longest encoded token in bytes that is decoded at all, larger ones are rejected with
+    /// [`crate::biscuit::errors::DecodeError::TooLarge`] regardless of strictness
+    pub max_token_length: usize,
+}
+
+/// Matches the default voucher limit of the registrar and the MASA
+const MAX_TOKEN_LENGTH: usize = 64 * 1024;
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+impl DecodeOptions {
+    /// Reject tokens that are out of spec
+    pub fn strict() -> Self {
+        DecodeOptions {
+            unknown_critical_headers: Strictness::Strict,
+            duplicate_header_parameters: Strictness::Strict,
+            base64_padding: Strictness::Strict,
+            empty_parts: Strictness::Strict,
+            understood_critical_headers: vec![],
+            max_token_length: MAX_TOKEN_LENGTH,
+        }
+    }
+
+    /// Decode tokens that are out of spec, for interop debugging only
+    pub fn lenient() -> Self {
+        DecodeOptions {
+            unknown_critical_headers: Strictness::Lenient,
+            duplicate_header_parameters: Strictness::Lenient,
+            base64_padding: Strictness::Lenient,
+            empty_parts: Strictness::Lenient,
+            understood_critical_headers: vec![],
+            max_token_length: MAX_TOKEN_LENGTH,
+        }
+    }
+
+    /// Strictness of the check that produced the diagnostic
+    pub(crate) fn strictness(&self, diagnostic: &DecodeDiagnostic) -> Strictness {
+        match diagnostic {
+            DecodeDiagnostic::UnknownCriticalHeader(_) => self.unknown_critical_headers,
+            DecodeDiagnostic::DuplicateHeaderParameter(_) => self.duplicate_header_parameters,
+            DecodeDiagnostic::Base64Padding { .. } => self.base64_padding,
+            DecodeDiagnostic::EmptyPart { .. } => self.empty_parts,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A deviation from the JOSE specifications found while decoding a token
+pub enum DecodeDiagnostic {
+    /// The header parameter listed in `crit` is not understood
+    UnknownCriticalHeader(String),
+    /// The header parameter appears more than once, the last occurrence is used
+    DuplicateHeaderParameter(String),
+    /// The part at the index is padded
+    Base64Padding {
+        /// Index of the part in the compact serialization
+        part: usize,
+    },
+    /// The part at the index is empty
+    EmptyPart {
+        /// Index of the part in the compact serialization
+        part: usize,
+    },
+}
+
+impl fmt::Display for DecodeDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DecodeDiagnostic::UnknownCriticalHeader(ref name) => write!(
+                f,
+                "critical header parameter {:?} is not understood (RFC 7515 Section 4.1.11)",
+                name
+            ),
+            DecodeDiagnostic::DuplicateHeaderParameter(ref name) => write!(
+                f,
+                "header parameter {:?} appears more than once (RFC 7515 Section 4)",
+                name
+            ),
+            DecodeDiagnostic::Base64Padding { part } => write!(
+                f,
+                "part {} uses base64url padding, which must be omitted (RFC 7515 Section 2)",
+                part
+            ),
+            DecodeDiagnostic::EmptyPart { part } => write!(f, "part {} is empty", part),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE64URL_NOPAD;
+
+    use super::*;
+    use crate::biscuit::errors::{DecodeError, Error};
+    use crate::biscuit::jwa::SignatureAlgorithm;
+    use crate::biscuit::jws::{self, RegisteredHeader, Secret};
+    use crate::biscuit::{Base64Url, Compact, Empty};
+
+    fn unsecured(registered: RegisteredHeader) -> Compact {
+        let jws = jws::Compact::<Vec<u8>, Empty>::new_decoded(
+            From::from(RegisteredHeader {
+                algorithm: SignatureAlgorithm::None,
+                ..registered
+            }),
+            b"voucher".to_vec(),
+        );
+        jws.into_encoded(&Secret::None).unwrap().unwrap_encoded()
+    }
+
+    fn decode(token: Compact, options: &DecodeOptions) -> Result<Vec<DecodeDiagnostic>, Error> {
+        let (decoded, diagnostics) = jws::Compact::<Vec<u8>, Empty>::Encoded(token)
+            .decode_with_options(&Secret::None, SignatureAlgorithm::None, options)?;
+        assert_eq!(decoded.payload().unwrap(), b"voucher");
+        Ok(diagnostics)
+    }
+
+    fn out_of_spec(result: Result<Vec<DecodeDiagnostic>, Error>) -> Vec<DecodeDiagnostic> {
+        match result {
+            Err(Error::DecodeError(DecodeError::OutOfSpec(diagnostics))) => diagnostics,
+            other => panic!("expected an out of spec error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn padding_is_rejected_unless_lenient() {
+        let mut token = unsecured(Default::default());
+        token.parts[1] = Base64Url(format!("{}==", token.parts[1].str()));
+
+        let expected = vec![DecodeDiagnostic::Base64Padding { part: 1 }];
+        assert_eq!(
+            out_of_spec(decode(token.clone(), &DecodeOptions::strict())),
+            expected
+        );
+        assert_eq!(decode(token, &DecodeOptions::lenient()).unwrap(), expected);
+    }
+
+    #[test]
+    fn unknown_critical_headers_are_rejected() {
+        let token = unsecured(RegisteredHeader {
+            critical: Some(vec!["b64".to_string()]),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            out_of_spec(decode(token.clone(), &DecodeOptions::strict())),
+            vec![DecodeDiagnostic::UnknownCriticalHeader("b64".to_string())]
+        );
+
+        let options = DecodeOptions {
+            understood_critical_headers: vec!["b64".to_string()],
+            ..DecodeOptions::strict()
+        };
+        assert!(decode(token, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn oversized_tokens_are_rejected() {
+        let token = unsecured(Default::default());
+        let length = token.encode().len();
+
+        let options = DecodeOptions {
+            max_token_length: length - 1,
+            ..DecodeOptions::lenient()
+        };
+        match decode(token.clone(), &options) {
+            Err(Error::DecodeError(DecodeError::TooLarge { limit, actual })) => {
+                assert_eq!((limit, actual), (length - 1, length))
+            }
+            other => panic!("expected a too large error, got {:?}", other),
+        }
+
+        let options = DecodeOptions {
+            max_token_length: length,
+            ..DecodeOptions::strict()
+        };
+        assert!(decode(token, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicate_header_parameters_use_the_last_occurrence() {
+        let mut token = unsecured(Default::default());
+        token.parts[0] = Base64Url(BASE64URL_NOPAD.encode(br#"{"alg":"HS256","alg":"none"}"#));
+
+        let expected = vec![DecodeDiagnostic::DuplicateHeaderParameter(
+            "alg".to_string(),
+        )];
+        assert_eq!(
+            out_of_spec(decode(token.clone(), &DecodeOptions::strict())),
+            expected
+        );
+        assert_eq!(decode(token, &DecodeOptions::lenient()).unwrap(), expected);
+    }
+
+    #[test]
+    fn empty_signature_is_reported() {
+        let token = unsecured(Default::default());
+        let options = DecodeOptions::strict();
+
+        let (normalized, diagnostics) = token.inspect(&options, &[0]).unwrap();
+        assert_eq!(normalized, token);
+        assert!(diagnostics.is_empty());
+
+        assert_eq!(
+            out_of_spec(
+                token
+                    .inspect(&options, &[0, 2])
+                    .map(|(_, diagnostics)| diagnostics)
+            ),
+            vec![DecodeDiagnostic::EmptyPart { part: 2 }]
+        );
+    }
+}
@@ -1,7 +1,9 @@
+mod decode_options;
 mod presence;
 mod temporal_options;
 mod validation;
 
+pub use self::decode_options::*;
 pub use self::presence::*;
 pub use self::temporal_options::*;
 pub use self::validation::*;
@@ -0,0 +1,457 @@
+//! Interop tests against JOSE implementations other than biscuit.
+//!
+//! The fixtures in `interop/` are produced by `interop/node.mjs` with node:crypto
+//! and `interop/python.py` with pyca/cryptography, which share no code with biscuit.
+//! They differ on purpose in header member order and whitespace, as the signatures
+//! and the additional authenticated data cover the headers as received.
+//!
+//! `interop/jose.mjs`, `interop/jwcrypto.py` and `interop/go-jose` produce the same
+//! fixtures with the JOSE libraries jose, jwcrypto and go-jose. Every `*.json` in
+//! `interop/` is tested, so their output only has to be written there.
+//!
+//! The other direction is covered by [`export`]: with `BISCUIT_INTEROP_OUT` set it
+//! writes biscuit-produced artifacts in the fixture format, which the scripts check
+//! with `verify <file>`.
+
+use std::sync::Arc;
+
+use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::biscuit::jwa::SignatureAlgorithm;
+use crate::biscuit::jwk::RSAKeyParameters;
+use crate::biscuit::jws::{self, General, RegisteredHeader, Secret};
+use crate::biscuit::{serde_custom, DecodeOptions, Empty};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/biscuit/interop");
+
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    generator: String,
+    keys: Keys,
+    #[serde(with = "serde_custom::byte_sequence")]
+    payload: Vec<u8>,
+    jws: JwsArtifacts,
+    jwe: JweArtifacts,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Keys {
+    es256: EcdsaKey,
+    hs256: SymmetricKey,
+    a256gcmkw: SymmetricKey,
+    dir: SymmetricKey,
+    /// Private JWK, for RS256 and RSA-OAEP
+    rsa: RSAKeyParameters,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EcdsaKey {
+    #[serde(with = "serde_custom::byte_sequence")]
+    pkcs8: Vec<u8>,
+    /// Uncompressed SEC1 point
+    #[serde(with = "serde_custom::byte_sequence")]
+    public: Vec<u8>,
+    kid: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SymmetricKey {
+    #[serde(with = "serde_custom::byte_sequence")]
+    k: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JwsArtifacts {
+    es256_compact: String,
+    hs256_compact: String,
+    rs256_compact: String,
+    es256_flattened: Value,
+    /// ES256 and HS256 signature, the latter with the `kid` in the unprotected header
+    general: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JweArtifacts {
+    a256gcmkw_a256gcm_compact: String,
+    dir_a128gcm_compact: String,
+    rsa_oaep_a256gcm_compact: String,
+    rsa_oaep_256_a256gcm_compact: String,
+    /// Carries an `aad` member
+    a256gcmkw_a256gcm_flattened: Value,
+}
+
+/// The fixtures in `interop/`, the node:crypto one first
+fn fixtures() -> Vec<Fixture> {
+    let mut paths: Vec<_> = not_err!(std::fs::read_dir(FIXTURES))
+        .map(|entry| not_err!(entry).path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter(|path| !path.ends_with("package.json"))
+        .collect();
+    paths.sort_by_key(|path| !path.ends_with("node.json"));
+    assert!(
+        paths.len() >= 2,
+        "the node:crypto and pyca/cryptography fixtures are missing"
+    );
+    paths
+        .iter()
+        .map(|path| not_err!(serde_json::from_slice(&not_err!(std::fs::read(path)))))
+        .collect()
+}
+
+impl Keys {
+    fn es256_private(&self) -> Secret {
+        let key_pair = not_err!(EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &self.es256.pkcs8,
+            &ring::rand::SystemRandom::new(),
+        ));
+        Secret::EcdsaKeyPair(Arc::new(key_pair))
+    }
+
+    fn es256_public(&self) -> Secret {
+        Secret::PublicKey(self.es256.public.clone())
+    }
+
+    fn hs256(&self) -> Secret {
+        Secret::Bytes(self.hs256.k.clone())
+    }
+
+    fn rs256_private(&self) -> Secret {
+        not_err!(self.rsa.jws_private_key_secret())
+    }
+
+    fn rs256_public(&self) -> Secret {
+        self.rsa.jws_public_key_secret()
+    }
+}
+
+fn kid(kid: &str) -> Map<String, Value> {
+    let mut header = Map::new();
+    header.insert("kid".to_string(), Value::from(kid));
+    header
+}
+
+#[test]
+fn compact_jws() {
+    for fixture in fixtures() {
+        let tokens = [
+            (
+                &fixture.jws.es256_compact,
+                SignatureAlgorithm::ES256,
+                fixture.keys.es256_public(),
+            ),
+            (
+                &fixture.jws.hs256_compact,
+                SignatureAlgorithm::HS256,
+                fixture.keys.hs256(),
+            ),
+            (
+                &fixture.jws.rs256_compact,
+                SignatureAlgorithm::RS256,
+                fixture.keys.rs256_public(),
+            ),
+        ];
+        for (token, algorithm, secret) in tokens {
+            let (decoded, diagnostics) = not_err!(jws::Compact::<Vec<u8>, Empty>::new_encoded(
+                token
+            )
+            .decode_with_options(&secret, algorithm, &DecodeOptions::strict()));
+            assert!(
+                diagnostics.is_empty(),
+                "{}: {:?}",
+                fixture.generator,
+                diagnostics
+            );
+            assert_eq!(
+                not_err!(decoded.payload()),
+                &fixture.payload,
+                "{}",
+                fixture.generator
+            );
+            assert_eq!(
+                not_err!(decoded.header()).registered.media_type.as_deref(),
+                Some("voucher-jws+json")
+            );
+        }
+
+        // a signature of the other algorithm does not verify
+        let token = jws::Compact::<Vec<u8>, Empty>::new_encoded(&fixture.jws.hs256_compact);
+        assert!(token
+            .decode(&fixture.keys.es256_public(), SignatureAlgorithm::ES256)
+            .is_err());
+    }
+}
+
+#[test]
+fn json_jws() {
+    for fixture in fixtures() {
+        let flattened = not_err!(General::deserialize(
+            fixture.jws.es256_flattened.to_string().as_bytes()
+        ));
+        let signature =
+            not_err!(flattened.verify(0, &fixture.keys.es256_public(), SignatureAlgorithm::ES256));
+        assert_eq!(
+            signature.unprotected_header(),
+            Some(&kid(&fixture.keys.es256.kid))
+        );
+        assert_eq!(flattened.payload(), fixture.payload.as_slice());
+        // serializing again must not change a byte of what the other implementation signed
+        let serialized: Value = not_err!(serde_json::from_str(&not_err!(
+            flattened.serialize_flattened()
+        )));
+        assert_eq!(
+            serialized, fixture.jws.es256_flattened,
+            "{}",
+            fixture.generator
+        );
+
+        let general = not_err!(General::deserialize(
+            fixture.jws.general.to_string().as_bytes()
+        ));
+        not_err!(general.verify(0, &fixture.keys.es256_public(), SignatureAlgorithm::ES256));
+        let signature =
+            not_err!(general.verify(1, &fixture.keys.hs256(), SignatureAlgorithm::HS256));
+        assert_eq!(
+            signature.unprotected_header(),
+            fixture.keys.hs256.kid.as_deref().map(kid).as_ref()
+        );
+        assert!(general
+            .verify(1, &fixture.keys.es256_public(), SignatureAlgorithm::ES256)
+            .is_err());
+        let serialized: Value = not_err!(serde_json::from_str(&general.serialize()));
+        assert_eq!(serialized, fixture.jws.general, "{}", fixture.generator);
+    }
+}
+
+#[cfg(feature = "jwe")]
+mod jwe {
+    use super::*;
+    use crate::biscuit::jwa::{
+        self, ContentEncryptionAlgorithm, EncryptionOptions, KeyManagementAlgorithm,
+    };
+    use crate::biscuit::jwe;
+    use crate::biscuit::jwk::{AlgorithmParameters, JWK};
+
+    fn octet_key(key: &SymmetricKey) -> JWK<Empty> {
+        JWK::new_octet_key(&key.k, Default::default())
+    }
+
+    fn rsa_key(key: &RSAKeyParameters) -> JWK<Empty> {
+        JWK {
+            algorithm: AlgorithmParameters::RSA(key.clone()),
+            common: Default::default(),
+            additional: Default::default(),
+        }
+    }
+
+    fn header(
+        cek_algorithm: KeyManagementAlgorithm,
+        enc_algorithm: ContentEncryptionAlgorithm,
+    ) -> jwe::Header<Empty> {
+        jwe::Header::from(jwe::RegisteredHeader {
+            cek_algorithm,
+            enc_algorithm,
+            ..Default::default()
+        })
+    }
+
+    fn aes_gcm() -> EncryptionOptions {
+        EncryptionOptions::AES_GCM {
+            nonce: not_err!(jwa::random_aes_gcm_nonce()),
+        }
+    }
+
+    #[test]
+    fn compact_and_flattened_jwe() {
+        for fixture in fixtures() {
+            let tokens = [
+                (
+                    &fixture.jwe.a256gcmkw_a256gcm_compact,
+                    octet_key(&fixture.keys.a256gcmkw),
+                    KeyManagementAlgorithm::A256GCMKW,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+                (
+                    &fixture.jwe.dir_a128gcm_compact,
+                    octet_key(&fixture.keys.dir),
+                    KeyManagementAlgorithm::DirectSymmetricKey,
+                    ContentEncryptionAlgorithm::A128GCM,
+                ),
+                (
+                    &fixture.jwe.rsa_oaep_a256gcm_compact,
+                    rsa_key(&fixture.keys.rsa),
+                    KeyManagementAlgorithm::RSA_OAEP,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+                (
+                    &fixture.jwe.rsa_oaep_256_a256gcm_compact,
+                    rsa_key(&fixture.keys.rsa),
+                    KeyManagementAlgorithm::RSA_OAEP_256,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+            ];
+            for (token, key, cek_algorithm, enc_algorithm) in tokens {
+                let decrypted = not_err!(jwe::Compact::<Vec<u8>, Empty>::new_encrypted(token)
+                    .decrypt(&key, cek_algorithm, enc_algorithm));
+                assert_eq!(
+                    not_err!(decrypted.payload()),
+                    &fixture.payload,
+                    "{}",
+                    fixture.generator
+                );
+            }
+
+            let flattened = fixture.jwe.a256gcmkw_a256gcm_flattened.to_string();
+            let (decrypted, aad) = not_err!(jwe::Compact::<Vec<u8>, Empty>::decrypt_flattened(
+                flattened.as_bytes(),
+                &octet_key(&fixture.keys.a256gcmkw),
+                KeyManagementAlgorithm::A256GCMKW,
+                ContentEncryptionAlgorithm::A256GCM,
+            ));
+            assert_eq!(
+                not_err!(decrypted.payload()),
+                &fixture.payload,
+                "{}",
+                fixture.generator
+            );
+            assert_eq!(
+                aad.as_deref(),
+                Some(&b"serial-number=00-D0-E5-F2-00-02"[..])
+            );
+        }
+    }
+
+    /// Writes artifacts biscuit produced with the keys of the node fixture to `BISCUIT_INTEROP_OUT`:
+    ///
+    /// ```sh
+    /// BISCUIT_INTEROP_OUT=/tmp/biscuit.json cargo test --features jwe interop::jwe::export
+    /// node interop/node.mjs verify /tmp/biscuit.json
+    /// python3 interop/python.py verify /tmp/biscuit.json
+    /// node interop/jose.mjs verify /tmp/biscuit.json
+    /// python3 interop/jwcrypto.py verify /tmp/biscuit.json
+    /// (cd interop/go-jose && go run . verify /tmp/biscuit.json)
+    /// ```
+    #[test]
+    fn export() {
+        let Ok(path) = std::env::var("BISCUIT_INTEROP_OUT") else {
+            return;
+        };
+        let Fixture { keys, payload, .. } = fixtures().remove(0);
+
+        let compact_jws = |registered: RegisteredHeader, secret: &Secret| {
+            let token = jws::Compact::<Vec<u8>, Empty>::new_decoded(
+                jws::Header::from(registered),
+                payload.clone(),
+            );
+            not_err!(token.encode(secret)).unwrap_encoded().encode()
+        };
+        let es256 = jws::Header::<Empty>::from(RegisteredHeader {
+            algorithm: SignatureAlgorithm::ES256,
+            ..Default::default()
+        });
+        let hs256 = jws::Header::<Empty>::from(RegisteredHeader {
+            algorithm: SignatureAlgorithm::HS256,
+            ..Default::default()
+        });
+
+        let mut flattened = General::new(payload.clone());
+        not_err!(flattened.sign(
+            es256.clone(),
+            Some(kid(&keys.es256.kid)),
+            &keys.es256_private()
+        ));
+        let mut general = General::new(payload.clone());
+        not_err!(general.sign(es256, None, &keys.es256_private()));
+        not_err!(general.sign(hs256, keys.hs256.kid.as_deref().map(kid), &keys.hs256()));
+
+        let compact_jwe = |key: JWK<Empty>, cek_algorithm, enc_algorithm| {
+            let token = jwe::Compact::<Vec<u8>, Empty>::new_decrypted(
+                header(cek_algorithm, enc_algorithm),
+                payload.clone(),
+            );
+            not_err!(token.encrypt(&key, &aes_gcm()))
+                .unwrap_encrypted()
+                .encode()
+        };
+        let flattened_jwe = not_err!(jwe::Compact::<Vec<u8>, Empty>::new_decrypted(
+            header(
+                KeyManagementAlgorithm::A256GCMKW,
+                ContentEncryptionAlgorithm::A256GCM
+            ),
+            payload.clone(),
+        )
+        .encrypt_flattened(
+            &octet_key(&keys.a256gcmkw),
+            &aes_gcm(),
+            Some(b"serial-number=00-D0-E5-F2-00-02"),
+        ));
+
+        let artifacts = Fixture {
+            generator: "biscuit".to_string(),
+            jws: JwsArtifacts {
+                es256_compact: compact_jws(
+                    RegisteredHeader {
+                        algorithm: SignatureAlgorithm::ES256,
+                        media_type: Some("voucher-jws+json".to_string()),
+                        key_id: Some(keys.es256.kid.clone()),
+                        ..Default::default()
+                    },
+                    &keys.es256_private(),
+                ),
+                hs256_compact: compact_jws(
+                    RegisteredHeader {
+                        algorithm: SignatureAlgorithm::HS256,
+                        media_type: Some("voucher-jws+json".to_string()),
+                        ..Default::default()
+                    },
+                    &keys.hs256(),
+                ),
+                rs256_compact: compact_jws(
+                    RegisteredHeader {
+                        algorithm: SignatureAlgorithm::RS256,
+                        media_type: Some("voucher-jws+json".to_string()),
+                        ..Default::default()
+                    },
+                    &keys.rs256_private(),
+                ),
+                es256_flattened: not_err!(serde_json::from_str(&not_err!(
+                    flattened.serialize_flattened()
+                ))),
+                general: not_err!(serde_json::from_str(&general.serialize())),
+            },
+            jwe: JweArtifacts {
+                a256gcmkw_a256gcm_compact: compact_jwe(
+                    octet_key(&keys.a256gcmkw),
+                    KeyManagementAlgorithm::A256GCMKW,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+                dir_a128gcm_compact: compact_jwe(
+                    octet_key(&keys.dir),
+                    KeyManagementAlgorithm::DirectSymmetricKey,
+                    ContentEncryptionAlgorithm::A128GCM,
+                ),
+                rsa_oaep_a256gcm_compact: compact_jwe(
+                    rsa_key(&keys.rsa),
+                    KeyManagementAlgorithm::RSA_OAEP,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+                rsa_oaep_256_a256gcm_compact: compact_jwe(
+                    rsa_key(&keys.rsa),
+                    KeyManagementAlgorithm::RSA_OAEP_256,
+                    ContentEncryptionAlgorithm::A256GCM,
+                ),
+                a256gcmkw_a256gcm_flattened: not_err!(serde_json::from_str(&flattened_jwe)),
+            },
+            keys,
+            payload,
+        };
+        not_err!(std::fs::write(
+            path,
+            not_err!(serde_json::to_vec_pretty(&artifacts))
+        ));
+    }
+}
@@ -0,0 +1,5 @@
+module biscuit-interop
+
+go 1.22
+
+require github.com/go-jose/go-jose/v4 v4.0.4
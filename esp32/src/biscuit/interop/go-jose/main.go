@@ -0,0 +1,298 @@
+// Generates and verifies the JOSE interop fixtures with go-jose.
+//
+//	go mod tidy
+//	go run . generate > ../go-jose.json
+//	go run . verify biscuit.json
+//
+// See interop.rs for the fixture format. go-jose cannot sign with an unprotected
+// header, so the flattened and general JWS are assembled from signatures go-jose
+// produced in compact serialization; the unprotected kid is not signed anyway.
+package main
+
+import (
+	"bytes"
+	"crypto/ecdsa"
+	"crypto/elliptic"
+	"crypto/rand"
+	"crypto/rsa"
+	"crypto/x509"
+	"encoding/base64"
+	"encoding/json"
+	"fmt"
+	"os"
+	"strings"
+
+	"github.com/go-jose/go-jose/v4"
+)
+
+var payload, _ = json.Marshal(map[string]any{
+	"ietf-voucher-request-prm:voucher": map[string]string{
+		"created-on":        "2024-06-20T13:05:06.000Z",
+		"nonce":             "eDs++/FuDHGUnRxN3E14CQ==",
+		"serial-number":     "00-D0-E5-F2-00-02",
+		"assertion":         "agent-proximity",
+		"agent-signed-data": "München/Garching",
+	},
+})
+
+var aad = []byte("serial-number=00-D0-E5-F2-00-02")
+
+var (
+	signatureAlgorithms = []jose.SignatureAlgorithm{jose.ES256, jose.HS256, jose.RS256}
+	keyAlgorithms       = []jose.KeyAlgorithm{jose.A256GCMKW, jose.DIRECT, jose.RSA_OAEP, jose.RSA_OAEP_256}
+	contentEncryptions  = []jose.ContentEncryption{jose.A128GCM, jose.A256GCM}
+)
+
+func b64u(data []byte) string {
+	return base64.RawURLEncoding.EncodeToString(data)
+}
+
+func fromB64u(text string) []byte {
+	data, err := base64.RawURLEncoding.DecodeString(text)
+	check("base64url", err)
+	return data
+}
+
+func check(what string, err error) {
+	if err != nil {
+		fmt.Fprintf(os.Stderr, "%s: %v\n", what, err)
+		os.Exit(1)
+	}
+}
+
+type symmetricKey struct {
+	K   string `json:"k"`
+	Kid string `json:"kid,omitempty"`
+}
+
+type fixture struct {
+	Generator string `json:"generator"`
+	Keys      struct {
+		ES256 struct {
+			PKCS8  string `json:"pkcs8"`
+			Public string `json:"public"`
+			Kid    string `json:"kid"`
+		} `json:"es256"`
+		HS256     symmetricKey    `json:"hs256"`
+		A256GCMKW symmetricKey    `json:"a256gcmkw"`
+		Dir       symmetricKey    `json:"dir"`
+		RSA       json.RawMessage `json:"rsa"`
+	} `json:"keys"`
+	Payload string `json:"payload"`
+	JWS     struct {
+		ES256Compact   string         `json:"es256_compact"`
+		HS256Compact   string         `json:"hs256_compact"`
+		RS256Compact   string         `json:"rs256_compact"`
+		ES256Flattened map[string]any `json:"es256_flattened"`
+		General        struct {
+			Payload    string           `json:"payload"`
+			Signatures []map[string]any `json:"signatures"`
+		} `json:"general"`
+	} `json:"jws"`
+	JWE struct {
+		A256GCMKWCompact  string          `json:"a256gcmkw_a256gcm_compact"`
+		DirCompact        string          `json:"dir_a128gcm_compact"`
+		RSAOAEPCompact    string          `json:"rsa_oaep_a256gcm_compact"`
+		RSAOAEP256Compact string          `json:"rsa_oaep_256_a256gcm_compact"`
+		A256GCMKWFlat     json.RawMessage `json:"a256gcmkw_a256gcm_flattened"`
+	} `json:"jwe"`
+}
+
+type keys struct {
+	es256     *ecdsa.PrivateKey
+	hs256     []byte
+	a256gcmkw []byte
+	dir       []byte
+	rsa       *rsa.PrivateKey
+}
+
+func (f *fixture) keys() keys {
+	es256, err := x509.ParsePKCS8PrivateKey(fromB64u(f.Keys.ES256.PKCS8))
+	check("es256 key", err)
+	var rsaKey jose.JSONWebKey
+	check("rsa key", rsaKey.UnmarshalJSON(f.Keys.RSA))
+	return keys{
+		es256:     es256.(*ecdsa.PrivateKey),
+		hs256:     fromB64u(f.Keys.HS256.K),
+		a256gcmkw: fromB64u(f.Keys.A256GCMKW.K),
+		dir:       fromB64u(f.Keys.Dir.K),
+		rsa:       rsaKey.Key.(*rsa.PrivateKey),
+	}
+}
+
+// compactJWS signs the payload, extra headers go into the protected header
+func compactJWS(algorithm jose.SignatureAlgorithm, key any, typ string, headers map[jose.HeaderKey]any) string {
+	options := &jose.SignerOptions{}
+	if typ != "" {
+		options = options.WithType(jose.ContentType(typ))
+	}
+	for name, value := range headers {
+		options = options.WithHeader(name, value)
+	}
+	signer, err := jose.NewSigner(jose.SigningKey{Algorithm: algorithm, Key: key}, options)
+	check("signer", err)
+	signed, err := signer.Sign(payload)
+	check("sign", err)
+	token, err := signed.CompactSerialize()
+	check("serialize", err)
+	return token
+}
+
+// signature turns a compact JWS into a signature of the JSON serialization
+func signature(token string, unprotected map[string]any) map[string]any {
+	parts := strings.Split(token, ".")
+	value := map[string]any{"protected": parts[0], "signature": parts[2]}
+	if unprotected != nil {
+		value["header"] = unprotected
+	}
+	return value
+}
+
+func encrypt(algorithm jose.KeyAlgorithm, enc jose.ContentEncryption, key any, authData []byte) *jose.JSONWebEncryption {
+	encrypter, err := jose.NewEncrypter(enc, jose.Recipient{Algorithm: algorithm, Key: key}, nil)
+	check("encrypter", err)
+	encrypted, err := encrypter.EncryptWithAuthData(payload, authData)
+	check("encrypt", err)
+	return encrypted
+}
+
+func compactJWE(algorithm jose.KeyAlgorithm, enc jose.ContentEncryption, key any) string {
+	token, err := encrypt(algorithm, enc, key, nil).CompactSerialize()
+	check("serialize", err)
+	return token
+}
+
+func random(size int) []byte {
+	data := make([]byte, size)
+	_, err := rand.Read(data)
+	check("random", err)
+	return data
+}
+
+func generate() {
+	es256, err := ecdsa.GenerateKey(elliptic.P256(), rand.Reader)
+	check("es256 key", err)
+	rsaKey, err := rsa.GenerateKey(rand.Reader, 2048)
+	check("rsa key", err)
+	pkcs8, err := x509.MarshalPKCS8PrivateKey(es256)
+	check("pkcs8", err)
+	point, err := es256.PublicKey.ECDH()
+	check("public key", err)
+	rsaJWK, err := jose.JSONWebKey{Key: rsaKey}.MarshalJSON()
+	check("rsa jwk", err)
+
+	var f fixture
+	f.Generator = "go-jose v4"
+	f.Keys.ES256.PKCS8 = b64u(pkcs8)
+	f.Keys.ES256.Public = b64u(point.Bytes())
+	f.Keys.ES256.Kid = "pledge"
+	f.Keys.HS256 = symmetricKey{K: b64u(random(32)), Kid: "registrar-agent"}
+	f.Keys.A256GCMKW = symmetricKey{K: b64u(random(32))}
+	f.Keys.Dir = symmetricKey{K: b64u(random(16))}
+	f.Keys.RSA = rsaJWK
+	f.Payload = b64u(payload)
+	k := f.keys()
+
+	kid := map[jose.HeaderKey]any{"kid": "pledge"}
+	f.JWS.ES256Compact = compactJWS(jose.ES256, k.es256, "voucher-jws+json", kid)
+	f.JWS.HS256Compact = compactJWS(jose.HS256, k.hs256, "voucher-jws+json", nil)
+	f.JWS.RS256Compact = compactJWS(jose.RS256, k.rsa, "voucher-jws+json", nil)
+	f.JWS.ES256Flattened = signature(compactJWS(jose.ES256, k.es256, "", nil), map[string]any{"kid": "pledge"})
+	f.JWS.ES256Flattened["payload"] = f.Payload
+	f.JWS.General.Payload = f.Payload
+	f.JWS.General.Signatures = []map[string]any{
+		signature(compactJWS(jose.ES256, k.es256, "", kid), nil),
+		signature(compactJWS(jose.HS256, k.hs256, "", nil), map[string]any{"kid": "registrar-agent"}),
+	}
+
+	f.JWE.A256GCMKWCompact = compactJWE(jose.A256GCMKW, jose.A256GCM, k.a256gcmkw)
+	f.JWE.DirCompact = compactJWE(jose.DIRECT, jose.A128GCM, k.dir)
+	f.JWE.RSAOAEPCompact = compactJWE(jose.RSA_OAEP, jose.A256GCM, &k.rsa.PublicKey)
+	f.JWE.RSAOAEP256Compact = compactJWE(jose.RSA_OAEP_256, jose.A256GCM, &k.rsa.PublicKey)
+	f.JWE.A256GCMKWFlat = json.RawMessage(encrypt(jose.A256GCMKW, jose.A256GCM, k.a256gcmkw, aad).FullSerialize())
+
+	output, err := json.MarshalIndent(f, "", "  ")
+	check("fixture", err)
+	fmt.Println(string(output))
+}
+
+func verified(name string, plaintext []byte, err error) {
+	if err == nil && !bytes.Equal(plaintext, payload) {
+		err = fmt.Errorf("payload differs")
+	}
+	check(name+" does not verify", err)
+	fmt.Printf("%s: ok\n", name)
+}
+
+func verifyJWS(name, serialized string, key any) {
+	parsed, err := jose.ParseSigned(serialized, signatureAlgorithms)
+	check(name, err)
+	plaintext, err := parsed.Verify(key)
+	verified(name, plaintext, err)
+}
+
+func decryptJWE(name, serialized string, key any) {
+	parsed, err := jose.ParseEncrypted(serialized, keyAlgorithms, contentEncryptions)
+	check(name, err)
+	plaintext, err := parsed.Decrypt(key)
+	verified(name, plaintext, err)
+}
+
+func verify(path string) {
+	data, err := os.ReadFile(path)
+	check("fixture", err)
+	var f fixture
+	check("fixture", json.Unmarshal(data, &f))
+	k := f.keys()
+	keyFor := func(protected string) any {
+		var header struct {
+			Alg string `json:"alg"`
+		}
+		check("protected header", json.Unmarshal(fromB64u(protected), &header))
+		switch header.Alg {
+		case "ES256":
+			return &k.es256.PublicKey
+		case "RS256":
+			return &k.rsa.PublicKey
+		}
+		return k.hs256
+	}
+
+	for name, token := range map[string]string{
+		"es256_compact": f.JWS.ES256Compact,
+		"hs256_compact": f.JWS.HS256Compact,
+		"rs256_compact": f.JWS.RS256Compact,
+	} {
+		verifyJWS(name, token, keyFor(strings.Split(token, ".")[0]))
+	}
+	flattened, err := json.Marshal(f.JWS.ES256Flattened)
+	check("es256_flattened", err)
+	verifyJWS("es256_flattened", string(flattened), &k.es256.PublicKey)
+	for index, sig := range f.JWS.General.Signatures {
+		single := map[string]any{"payload": f.JWS.General.Payload}
+		for name, value := range sig {
+			single[name] = value
+		}
+		serialized, err := json.Marshal(single)
+		check("general", err)
+		verifyJWS(fmt.Sprintf("general[%d]", index), string(serialized), keyFor(sig["protected"].(string)))
+	}
+
+	decryptJWE("a256gcmkw_a256gcm_compact", f.JWE.A256GCMKWCompact, k.a256gcmkw)
+	decryptJWE("dir_a128gcm_compact", f.JWE.DirCompact, k.dir)
+	decryptJWE("rsa_oaep_a256gcm_compact", f.JWE.RSAOAEPCompact, k.rsa)
+	decryptJWE("rsa_oaep_256_a256gcm_compact", f.JWE.RSAOAEP256Compact, k.rsa)
+	decryptJWE("a256gcmkw_a256gcm_flattened", string(f.JWE.A256GCMKWFlat), k.a256gcmkw)
+}
+
+func main() {
+	switch {
+	case len(os.Args) == 2 && os.Args[1] == "generate":
+		generate()
+	case len(os.Args) == 3 && os.Args[1] == "verify":
+		verify(os.Args[2])
+	default:
+		fmt.Fprintln(os.Stderr, "usage: go run . generate | verify <fixture.json>")
+		os.Exit(2)
+	}
+}
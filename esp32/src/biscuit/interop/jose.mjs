@@ -0,0 +1,140 @@
+// Generates and verifies the JOSE interop fixtures with jose (panva/jose).
+//
+//   npm install
+//   node jose.mjs generate > jose.json
+//   node jose.mjs verify biscuit.json
+//
+// See interop.rs for the fixture format. Unlike node.mjs, every JOSE structure is
+// produced and checked by the library, only the keys come from node:crypto.
+import crypto from "node:crypto";
+import fs from "node:fs";
+import * as jose from "jose";
+
+const b64u = (bytes) => Buffer.from(bytes).toString("base64url");
+const fromB64u = (text) => Buffer.from(text, "base64url");
+
+const PAYLOAD = Buffer.from(
+  JSON.stringify({
+    "ietf-voucher-request-prm:voucher": {
+      "created-on": "2024-06-20T13:05:06.000Z",
+      "nonce": "eDs++/FuDHGUnRxN3E14CQ==",
+      "serial-number": "00-D0-E5-F2-00-02",
+      "assertion": "agent-proximity",
+      "agent-signed-data": "München/Garching",
+    },
+  }),
+);
+const AAD = Buffer.from("serial-number=00-D0-E5-F2-00-02");
+
+function keyObjects(keys) {
+  const point = fromB64u(keys.es256.public);
+  const rsa = crypto.createPrivateKey({ key: keys.rsa, format: "jwk" });
+  return {
+    es256: crypto.createPrivateKey({ key: fromB64u(keys.es256.pkcs8), format: "der", type: "pkcs8" }),
+    es256Public: crypto.createPublicKey({
+      key: { kty: "EC", crv: "P-256", x: b64u(point.subarray(1, 33)), y: b64u(point.subarray(33)) },
+      format: "jwk",
+    }),
+    hs256: fromB64u(keys.hs256.k),
+    a256gcmkw: fromB64u(keys.a256gcmkw.k),
+    dir: fromB64u(keys.dir.k),
+    rsa,
+    rsaPublic: crypto.createPublicKey(rsa),
+  };
+}
+
+async function generate() {
+  const { privateKey, publicKey } = crypto.generateKeyPairSync("ec", { namedCurve: "P-256" });
+  const point = publicKey.export({ format: "jwk" });
+  const keys = {
+    es256: {
+      pkcs8: b64u(privateKey.export({ format: "der", type: "pkcs8" })),
+      public: b64u(Buffer.concat([Buffer.from([4]), fromB64u(point.x), fromB64u(point.y)])),
+      kid: "pledge",
+    },
+    hs256: { k: b64u(crypto.randomBytes(32)), kid: "registrar-agent" },
+    a256gcmkw: { k: b64u(crypto.randomBytes(32)) },
+    dir: { k: b64u(crypto.randomBytes(16)) },
+    rsa: crypto.generateKeyPairSync("rsa", { modulusLength: 2048 }).privateKey.export({ format: "jwk" }),
+  };
+  const key = keyObjects(keys);
+
+  const compactJws = (header, signingKey) => new jose.CompactSign(PAYLOAD).setProtectedHeader(header).sign(signingKey);
+  const general = new jose.GeneralSign(PAYLOAD);
+  general.addSignature(key.es256).setProtectedHeader({ alg: "ES256", kid: keys.es256.kid });
+  general.addSignature(key.hs256).setProtectedHeader({ alg: "HS256" }).setUnprotectedHeader({ kid: keys.hs256.kid });
+
+  const compactJwe = (alg, enc, encryptionKey) =>
+    new jose.CompactEncrypt(PAYLOAD).setProtectedHeader({ alg, enc }).encrypt(encryptionKey);
+
+  return {
+    generator: `jose ${JSON.parse(fs.readFileSync(new URL("node_modules/jose/package.json", import.meta.url))).version}`,
+    keys,
+    payload: b64u(PAYLOAD),
+    jws: {
+      es256_compact: await compactJws({ alg: "ES256", typ: "voucher-jws+json", kid: keys.es256.kid }, key.es256),
+      hs256_compact: await compactJws({ typ: "voucher-jws+json", alg: "HS256" }, key.hs256),
+      rs256_compact: await compactJws({ alg: "RS256", typ: "voucher-jws+json" }, key.rsa),
+      es256_flattened: await new jose.FlattenedSign(PAYLOAD)
+        .setProtectedHeader({ alg: "ES256" })
+        .setUnprotectedHeader({ kid: keys.es256.kid })
+        .sign(key.es256),
+      general: await general.sign(),
+    },
+    jwe: {
+      a256gcmkw_a256gcm_compact: await compactJwe("A256GCMKW", "A256GCM", key.a256gcmkw),
+      dir_a128gcm_compact: await compactJwe("dir", "A128GCM", key.dir),
+      rsa_oaep_a256gcm_compact: await compactJwe("RSA-OAEP", "A256GCM", key.rsaPublic),
+      rsa_oaep_256_a256gcm_compact: await compactJwe("RSA-OAEP-256", "A256GCM", key.rsaPublic),
+      a256gcmkw_a256gcm_flattened: await new jose.FlattenedEncrypt(PAYLOAD)
+        .setProtectedHeader({ alg: "A256GCMKW", enc: "A256GCM" })
+        .setAdditionalAuthenticatedData(AAD)
+        .encrypt(key.a256gcmkw),
+    },
+  };
+}
+
+async function check(name, verified) {
+  try {
+    const { payload, plaintext } = await verified;
+    if (!Buffer.from(payload ?? plaintext).equals(PAYLOAD)) {
+      throw new Error("payload differs");
+    }
+  } catch (err) {
+    throw new Error(`${name} does not verify: ${err.message}`);
+  }
+  console.log(`${name}: ok`);
+}
+
+async function verifyFixture(fixture) {
+  const key = keyObjects(fixture.keys);
+  const verificationKey = (protectedHeader) =>
+    ({ ES256: key.es256Public, HS256: key.hs256, RS256: key.rsaPublic })[JSON.parse(fromB64u(protectedHeader)).alg];
+
+  for (const name of ["es256_compact", "hs256_compact", "rs256_compact"]) {
+    const token = fixture.jws[name];
+    await check(name, jose.compactVerify(token, verificationKey(token.split(".")[0])));
+  }
+  const flattened = fixture.jws.es256_flattened;
+  await check("es256_flattened", jose.flattenedVerify(flattened, key.es256Public));
+  const { payload, signatures } = fixture.jws.general;
+  for (const [index, signature] of signatures.entries()) {
+    await check(`general[${index}]`, jose.flattenedVerify({ payload, ...signature }, verificationKey(signature.protected)));
+  }
+
+  await check("a256gcmkw_a256gcm_compact", jose.compactDecrypt(fixture.jwe.a256gcmkw_a256gcm_compact, key.a256gcmkw));
+  await check("dir_a128gcm_compact", jose.compactDecrypt(fixture.jwe.dir_a128gcm_compact, key.dir));
+  await check("rsa_oaep_a256gcm_compact", jose.compactDecrypt(fixture.jwe.rsa_oaep_a256gcm_compact, key.rsa));
+  await check("rsa_oaep_256_a256gcm_compact", jose.compactDecrypt(fixture.jwe.rsa_oaep_256_a256gcm_compact, key.rsa));
+  await check("a256gcmkw_a256gcm_flattened", jose.flattenedDecrypt(fixture.jwe.a256gcmkw_a256gcm_flattened, key.a256gcmkw));
+}
+
+const [mode, file] = process.argv.slice(2);
+if (mode === "generate") {
+  console.log(JSON.stringify(await generate(), null, 2));
+} else if (mode === "verify" && file) {
+  await verifyFixture(JSON.parse(fs.readFileSync(file)));
+} else {
+  console.error("usage: node jose.mjs generate | verify <fixture.json>");
+  process.exit(2);
+}
@@ -0,0 +1,169 @@
+"""Generates and verifies the JOSE interop fixtures with jwcrypto.
+
+    pip install jwcrypto
+    python3 jwcrypto.py generate > jwcrypto.json
+    python3 jwcrypto.py verify biscuit.json
+
+See interop.rs for the fixture format. Unlike python.py, every JOSE structure is
+produced and checked by jwcrypto, only the keys are converted by hand.
+"""
+
+import base64
+import json
+import sys
+
+import jwcrypto
+from jwcrypto import jwe, jwk, jws
+from jwcrypto.common import JWException, json_encode
+
+PAYLOAD = json.dumps(
+    {
+        "ietf-voucher-request-prm:voucher": {
+            "created-on": "2024-06-20T13:05:06.000Z",
+            "nonce": "eDs++/FuDHGUnRxN3E14CQ==",
+            "serial-number": "00-D0-E5-F2-00-02",
+            "assertion": "agent-proximity",
+            "agent-signed-data": "München/Garching",
+        }
+    },
+    ensure_ascii=False,
+).encode()
+AAD = b"serial-number=00-D0-E5-F2-00-02"
+RSA_MEMBERS = ("kty", "n", "e", "d", "p", "q", "dp", "dq", "qi")
+
+
+def b64u(data: bytes) -> str:
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+def from_b64u(text: str) -> bytes:
+    return base64.urlsafe_b64decode(text + "=" * (-len(text) % 4))
+
+
+def octet_key(fixture_key: dict) -> jwk.JWK:
+    return jwk.JWK(kty="oct", k=fixture_key["k"])
+
+
+def es256_public(keys: dict) -> jwk.JWK:
+    point = from_b64u(keys["es256"]["public"])
+    return jwk.JWK(kty="EC", crv="P-256", x=b64u(point[1:33]), y=b64u(point[33:]))
+
+
+def generate() -> dict:
+    es256 = jwk.JWK.generate(kty="EC", crv="P-256")
+    hs256 = jwk.JWK.generate(kty="oct", size=256)
+    a256gcmkw = jwk.JWK.generate(kty="oct", size=256)
+    direct = jwk.JWK.generate(kty="oct", size=128)
+    rsa = jwk.JWK.generate(kty="RSA", size=2048)
+
+    pem = es256.export_to_pem(private_key=True, password=None).decode()
+    point = es256.export_public(as_dict=True)
+    rsa_private = rsa.export_private(as_dict=True)
+    keys = {
+        "es256": {
+            "pkcs8": b64u(base64.b64decode("".join(line for line in pem.splitlines() if not line.startswith("-----")))),
+            "public": b64u(b"\x04" + from_b64u(point["x"]) + from_b64u(point["y"])),
+            "kid": "pledge",
+        },
+        "hs256": {"k": hs256.export(as_dict=True)["k"], "kid": "registrar-agent"},
+        "a256gcmkw": {"k": a256gcmkw.export(as_dict=True)["k"]},
+        "dir": {"k": direct.export(as_dict=True)["k"]},
+        "rsa": {name: rsa_private[name] for name in RSA_MEMBERS},
+    }
+
+    def compact_jws(key: jwk.JWK, header: dict) -> str:
+        token = jws.JWS(PAYLOAD)
+        token.add_signature(key, None, json_encode(header))
+        return token.serialize(compact=True)
+
+    flattened = jws.JWS(PAYLOAD)
+    flattened.add_signature(es256, None, json_encode({"alg": "ES256"}), json_encode({"kid": "pledge"}))
+    general = jws.JWS(PAYLOAD)
+    general.add_signature(es256, None, json_encode({"alg": "ES256", "kid": "pledge"}))
+    general.add_signature(hs256, None, json_encode({"alg": "HS256"}), json_encode({"kid": "registrar-agent"}))
+
+    def encrypt(key: jwk.JWK, alg: str, enc: str, aad: bytes | None = None) -> jwe.JWE:
+        token = jwe.JWE(PAYLOAD, json_encode({"alg": alg, "enc": enc}), aad=aad)
+        token.add_recipient(key)
+        return token
+
+    return {
+        "generator": f"jwcrypto {jwcrypto.__version__}" if hasattr(jwcrypto, "__version__") else "jwcrypto",
+        "keys": keys,
+        "payload": b64u(PAYLOAD),
+        "jws": {
+            "es256_compact": compact_jws(es256, {"alg": "ES256", "typ": "voucher-jws+json", "kid": "pledge"}),
+            "hs256_compact": compact_jws(hs256, {"typ": "voucher-jws+json", "alg": "HS256"}),
+            "rs256_compact": compact_jws(rsa, {"alg": "RS256", "typ": "voucher-jws+json"}),
+            "es256_flattened": json.loads(flattened.serialize()),
+            "general": json.loads(general.serialize()),
+        },
+        "jwe": {
+            "a256gcmkw_a256gcm_compact": encrypt(a256gcmkw, "A256GCMKW", "A256GCM").serialize(compact=True),
+            "dir_a128gcm_compact": encrypt(direct, "dir", "A128GCM").serialize(compact=True),
+            "rsa_oaep_a256gcm_compact": encrypt(rsa, "RSA-OAEP", "A256GCM").serialize(compact=True),
+            "rsa_oaep_256_a256gcm_compact": encrypt(rsa, "RSA-OAEP-256", "A256GCM").serialize(compact=True),
+            "a256gcmkw_a256gcm_flattened": json.loads(encrypt(a256gcmkw, "A256GCMKW", "A256GCM", AAD).serialize()),
+        },
+    }
+
+
+def check(name: str, verify) -> None:
+    try:
+        payload = verify()
+    except JWException as err:
+        raise SystemExit(f"{name} does not verify: {err}")
+    if payload != PAYLOAD:
+        raise SystemExit(f"{name} does not verify: payload differs")
+    print(f"{name}: ok")
+
+
+def verify_fixture(fixture: dict) -> None:
+    keys = fixture["keys"]
+    rsa = jwk.JWK(**keys["rsa"])
+    verification_keys = {"ES256": es256_public(keys), "HS256": octet_key(keys["hs256"]), "RS256": rsa}
+
+    def verify_jws(serialized: str, protected: str):
+        def verify():
+            token = jws.JWS()
+            token.deserialize(serialized)
+            alg = json.loads(from_b64u(protected))["alg"]
+            token.verify(verification_keys[alg], alg)
+            return token.payload
+
+        return verify
+
+    for name in ("es256_compact", "hs256_compact", "rs256_compact"):
+        token = fixture["jws"][name]
+        check(name, verify_jws(token, token.split(".")[0]))
+    flattened = fixture["jws"]["es256_flattened"]
+    check("es256_flattened", verify_jws(json.dumps(flattened), flattened["protected"]))
+    general = fixture["jws"]["general"]
+    for index, signature in enumerate(general["signatures"]):
+        single = json.dumps({"payload": general["payload"], **signature})
+        check(f"general[{index}]", verify_jws(single, signature["protected"]))
+
+    def decrypt(serialized: str, key: jwk.JWK):
+        def verify():
+            token = jwe.JWE()
+            token.deserialize(serialized, key)
+            return token.payload
+
+        return verify
+
+    check("a256gcmkw_a256gcm_compact", decrypt(fixture["jwe"]["a256gcmkw_a256gcm_compact"], octet_key(keys["a256gcmkw"])))
+    check("dir_a128gcm_compact", decrypt(fixture["jwe"]["dir_a128gcm_compact"], octet_key(keys["dir"])))
+    check("rsa_oaep_a256gcm_compact", decrypt(fixture["jwe"]["rsa_oaep_a256gcm_compact"], rsa))
+    check("rsa_oaep_256_a256gcm_compact", decrypt(fixture["jwe"]["rsa_oaep_256_a256gcm_compact"], rsa))
+    flattened = json.dumps(fixture["jwe"]["a256gcmkw_a256gcm_flattened"])
+    check("a256gcmkw_a256gcm_flattened", decrypt(flattened, octet_key(keys["a256gcmkw"])))
+
+
+if __name__ == "__main__":
+    if sys.argv[1:2] == ["generate"]:
+        print(json.dumps(generate(), indent=2, ensure_ascii=False))
+    elif sys.argv[1:2] == ["verify"] and len(sys.argv) == 3:
+        with open(sys.argv[2], "rb") as file:
+            verify_fixture(json.load(file))
+    else:
+        raise SystemExit("usage: python3 jwcrypto.py generate | verify <fixture.json>")
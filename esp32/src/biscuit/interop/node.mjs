@@ -0,0 +1,219 @@
+// Generates and verifies the JOSE interop fixtures with node:crypto.
+//
+//   node node.mjs generate > node.json
+//   node node.mjs verify biscuit.json
+//
+// See interop.rs for the fixture format.
+import crypto from "node:crypto";
+import fs from "node:fs";
+
+const b64u = (bytes) => Buffer.from(bytes).toString("base64url");
+const fromB64u = (text) => Buffer.from(text, "base64url");
+const json = (value) => Buffer.from(JSON.stringify(value));
+
+const PAYLOAD = json({
+  "ietf-voucher-request-prm:voucher": {
+    "created-on": "2024-06-20T13:05:06.000Z",
+    "nonce": "eDs++/FuDHGUnRxN3E14CQ==",
+    "serial-number": "00-D0-E5-F2-00-02",
+    "assertion": "agent-proximity",
+    "agent-signed-data": "München/Garching",
+  },
+});
+
+function signingInput(protectedHeader, payload) {
+  return Buffer.from(`${protectedHeader}.${b64u(payload)}`);
+}
+
+const rsaPrivateKey = (keys) => crypto.createPrivateKey({ key: keys.rsa, format: "jwk" });
+
+function oaep(alg, key) {
+  return { key, padding: crypto.constants.RSA_PKCS1_OAEP_PADDING, oaepHash: alg === "RSA-OAEP-256" ? "sha256" : "sha1" };
+}
+
+function sign(alg, input, keys) {
+  if (alg === "RS256") {
+    return crypto.sign("sha256", input, rsaPrivateKey(keys));
+  }
+  if (alg === "ES256") {
+    return crypto.sign("sha256", input, {
+      key: crypto.createPrivateKey({ key: fromB64u(keys.es256.pkcs8), format: "der", type: "pkcs8" }),
+      dsaEncoding: "ieee-p1363",
+    });
+  }
+  return crypto.createHmac("sha256", fromB64u(keys.hs256.k)).update(input).digest();
+}
+
+function verify(alg, input, signature, keys) {
+  if (alg === "RS256") {
+    return crypto.verify("sha256", input, crypto.createPublicKey(rsaPrivateKey(keys)), signature);
+  }
+  if (alg === "ES256") {
+    const point = fromB64u(keys.es256.public);
+    const key = crypto.createPublicKey({
+      key: { kty: "EC", crv: "P-256", x: b64u(point.subarray(1, 33)), y: b64u(point.subarray(33)) },
+      format: "jwk",
+    });
+    return crypto.verify("sha256", input, { key, dsaEncoding: "ieee-p1363" }, signature);
+  }
+  const expected = crypto.createHmac("sha256", fromB64u(keys.hs256.k)).update(input).digest();
+  return expected.length === signature.length && crypto.timingSafeEqual(expected, signature);
+}
+
+function gcm(bits, key, iv, plaintext, aad) {
+  const cipher = crypto.createCipheriv(`aes-${bits}-gcm`, key, iv);
+  cipher.setAAD(aad);
+  const ciphertext = Buffer.concat([cipher.update(plaintext), cipher.final()]);
+  return { ciphertext, tag: cipher.getAuthTag() };
+}
+
+function ungcm(bits, key, iv, ciphertext, tag, aad) {
+  const decipher = crypto.createDecipheriv(`aes-${bits}-gcm`, key, iv);
+  decipher.setAAD(aad);
+  decipher.setAuthTag(tag);
+  return Buffer.concat([decipher.update(ciphertext), decipher.final()]);
+}
+
+function encrypt(alg, enc, keys, plaintext, aad) {
+  let cek;
+  let encryptedKey = Buffer.alloc(0);
+  let header = { alg, enc };
+  if (alg === "dir") {
+    cek = fromB64u(keys.dir.k);
+  } else if (alg.startsWith("RSA-OAEP")) {
+    cek = crypto.randomBytes(32);
+    encryptedKey = crypto.publicEncrypt(oaep(alg, crypto.createPublicKey(rsaPrivateKey(keys))), cek);
+  } else {
+    cek = crypto.randomBytes(32);
+    const iv = crypto.randomBytes(12);
+    const wrapped = gcm(256, fromB64u(keys.a256gcmkw.k), iv, cek, Buffer.alloc(0));
+    encryptedKey = wrapped.ciphertext;
+    header = { alg, enc, iv: b64u(iv), tag: b64u(wrapped.tag) };
+  }
+  const protectedHeader = b64u(json(header));
+  const iv = crypto.randomBytes(12);
+  const additional = aad ? `${protectedHeader}.${b64u(aad)}` : protectedHeader;
+  const { ciphertext, tag } = gcm(cek.length * 8, cek, iv, plaintext, Buffer.from(additional));
+  return { protected: protectedHeader, encrypted_key: b64u(encryptedKey), iv: b64u(iv), ciphertext: b64u(ciphertext), tag: b64u(tag) };
+}
+
+function decrypt(jwe, keys) {
+  const header = JSON.parse(fromB64u(jwe.protected));
+  let cek;
+  if (header.alg === "dir") {
+    cek = fromB64u(keys.dir.k);
+  } else if (header.alg.startsWith("RSA-OAEP")) {
+    cek = crypto.privateDecrypt(oaep(header.alg, rsaPrivateKey(keys)), fromB64u(jwe.encrypted_key));
+  } else {
+    cek = ungcm(256, fromB64u(keys.a256gcmkw.k), fromB64u(header.iv), fromB64u(jwe.encrypted_key), fromB64u(header.tag), Buffer.alloc(0));
+  }
+  const additional = jwe.aad ? `${jwe.protected}.${jwe.aad}` : jwe.protected;
+  return ungcm(cek.length * 8, cek, fromB64u(jwe.iv), fromB64u(jwe.ciphertext), fromB64u(jwe.tag), Buffer.from(additional));
+}
+
+function compact(jwe) {
+  return [jwe.protected, jwe.encrypted_key, jwe.iv, jwe.ciphertext, jwe.tag].join(".");
+}
+
+function fromCompact(token) {
+  const [protectedHeader, encrypted_key, iv, ciphertext, tag] = token.split(".");
+  return { protected: protectedHeader, encrypted_key, iv, ciphertext, tag };
+}
+
+function generate() {
+  const { privateKey, publicKey } = crypto.generateKeyPairSync("ec", { namedCurve: "P-256" });
+  const point = publicKey.export({ format: "jwk" });
+  const rsa = crypto.generateKeyPairSync("rsa", { modulusLength: 2048 }).privateKey.export({ format: "jwk" });
+  const keys = {
+    es256: {
+      pkcs8: b64u(privateKey.export({ format: "der", type: "pkcs8" })),
+      public: b64u(Buffer.concat([Buffer.from([4]), fromB64u(point.x), fromB64u(point.y)])),
+      kid: "pledge",
+    },
+    hs256: { k: b64u(crypto.randomBytes(32)), kid: "registrar-agent" },
+    a256gcmkw: { k: b64u(crypto.randomBytes(32)) },
+    dir: { k: b64u(crypto.randomBytes(16)) },
+    rsa,
+  };
+
+  const signature = (alg, header, unprotected) => {
+    const protectedHeader = b64u(json(header));
+    const value = { protected: protectedHeader, signature: b64u(sign(alg, signingInput(protectedHeader, PAYLOAD), keys)) };
+    return unprotected ? { protected: protectedHeader, header: unprotected, signature: value.signature } : value;
+  };
+  const compactJws = (alg, header) => {
+    const { protected: protectedHeader, signature: value } = signature(alg, header);
+    return `${protectedHeader}.${b64u(PAYLOAD)}.${value}`;
+  };
+
+  const flattenedJwe = encrypt("A256GCMKW", "A256GCM", keys, PAYLOAD, Buffer.from("serial-number=00-D0-E5-F2-00-02"));
+  flattenedJwe.aad = b64u(Buffer.from("serial-number=00-D0-E5-F2-00-02"));
+
+  return {
+    generator: `node:crypto ${process.version}`,
+    keys,
+    payload: b64u(PAYLOAD),
+    jws: {
+      es256_compact: compactJws("ES256", { alg: "ES256", typ: "voucher-jws+json", kid: keys.es256.kid }),
+      hs256_compact: compactJws("HS256", { typ: "voucher-jws+json", alg: "HS256" }),
+      rs256_compact: compactJws("RS256", { alg: "RS256", typ: "voucher-jws+json" }),
+      es256_flattened: { payload: b64u(PAYLOAD), ...signature("ES256", { alg: "ES256" }, { kid: keys.es256.kid }) },
+      general: {
+        payload: b64u(PAYLOAD),
+        signatures: [
+          signature("ES256", { alg: "ES256", kid: keys.es256.kid }),
+          signature("HS256", { alg: "HS256" }, { kid: keys.hs256.kid }),
+        ],
+      },
+    },
+    jwe: {
+      a256gcmkw_a256gcm_compact: compact(encrypt("A256GCMKW", "A256GCM", keys, PAYLOAD)),
+      dir_a128gcm_compact: compact(encrypt("dir", "A128GCM", keys, PAYLOAD)),
+      rsa_oaep_a256gcm_compact: compact(encrypt("RSA-OAEP", "A256GCM", keys, PAYLOAD)),
+      rsa_oaep_256_a256gcm_compact: compact(encrypt("RSA-OAEP-256", "A256GCM", keys, PAYLOAD)),
+      a256gcmkw_a256gcm_flattened: flattenedJwe,
+    },
+  };
+}
+
+function check(name, condition) {
+  if (!condition) {
+    throw new Error(`${name} does not verify`);
+  }
+  console.log(`${name}: ok`);
+}
+
+function verifyFixture(fixture) {
+  const { keys } = fixture;
+  const payload = fromB64u(fixture.payload);
+  const verifySignature = (name, protectedHeader, encodedPayload, value) => {
+    const { alg } = JSON.parse(fromB64u(protectedHeader));
+    const input = Buffer.from(`${protectedHeader}.${encodedPayload}`);
+    check(name, verify(alg, input, fromB64u(value), keys) && fromB64u(encodedPayload).equals(payload));
+  };
+
+  for (const name of ["es256_compact", "hs256_compact", "rs256_compact"]) {
+    const [protectedHeader, encodedPayload, value] = fixture.jws[name].split(".");
+    verifySignature(name, protectedHeader, encodedPayload, value);
+  }
+  const flattened = fixture.jws.es256_flattened;
+  verifySignature("es256_flattened", flattened.protected, flattened.payload, flattened.signature);
+  fixture.jws.general.signatures.forEach((signature, index) =>
+    verifySignature(`general[${index}]`, signature.protected, fixture.jws.general.payload, signature.signature),
+  );
+
+  for (const name of ["a256gcmkw_a256gcm_compact", "dir_a128gcm_compact", "rsa_oaep_a256gcm_compact", "rsa_oaep_256_a256gcm_compact"]) {
+    check(name, decrypt(fromCompact(fixture.jwe[name]), keys).equals(payload));
+  }
+  check("a256gcmkw_a256gcm_flattened", decrypt(fixture.jwe.a256gcmkw_a256gcm_flattened, keys).equals(payload));
+}
+
+const [mode, file] = process.argv.slice(2);
+if (mode === "generate") {
+  console.log(JSON.stringify(generate(), null, 2));
+} else if (mode === "verify" && file) {
+  verifyFixture(JSON.parse(fs.readFileSync(file)));
+} else {
+  console.error("usage: node node.mjs generate | verify <fixture.json>");
+  process.exit(2);
+}
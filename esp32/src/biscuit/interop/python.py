@@ -0,0 +1,259 @@
+"""Generates and verifies the JOSE interop fixtures with pyca/cryptography.
+
+    python3 python.py generate > python.json
+    python3 python.py verify biscuit.json
+
+See interop.rs for the fixture format. Headers are serialized with json.dumps
+defaults, so unlike node.json they contain whitespace.
+"""
+
+import base64
+import hashlib
+import hmac
+import json
+import os
+import sys
+
+from cryptography.exceptions import InvalidSignature, InvalidTag
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, padding, rsa
+from cryptography.hazmat.primitives.asymmetric.utils import decode_dss_signature, encode_dss_signature
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM
+
+PAYLOAD = json.dumps(
+    {
+        "ietf-voucher-request-prm:voucher": {
+            "created-on": "2024-06-20T13:05:06.000Z",
+            "nonce": "eDs++/FuDHGUnRxN3E14CQ==",
+            "serial-number": "00-D0-E5-F2-00-02",
+            "assertion": "agent-proximity",
+            "agent-signed-data": "München/Garching",
+        }
+    },
+    ensure_ascii=False,
+).encode()
+
+
+def b64u(data: bytes) -> str:
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+def from_b64u(text: str) -> bytes:
+    return base64.urlsafe_b64decode(text + "=" * (-len(text) % 4))
+
+
+def int_b64u(value: int) -> str:
+    return b64u(value.to_bytes((value.bit_length() + 7) // 8, "big"))
+
+
+def rsa_private_key(jwk: dict) -> rsa.RSAPrivateKey:
+    n, e, d, p, q, dp, dq, qi = (
+        int.from_bytes(from_b64u(jwk[name]), "big") for name in ("n", "e", "d", "p", "q", "dp", "dq", "qi")
+    )
+    return rsa.RSAPrivateNumbers(p, q, d, dp, dq, qi, rsa.RSAPublicNumbers(e, n)).private_key()
+
+
+def oaep(alg: str) -> padding.OAEP:
+    hash = hashes.SHA256() if alg == "RSA-OAEP-256" else hashes.SHA1()
+    return padding.OAEP(mgf=padding.MGF1(hash), algorithm=hash, label=None)
+
+
+def sign(alg: str, data: bytes, keys: dict) -> bytes:
+    if alg == "RS256":
+        return rsa_private_key(keys["rsa"]).sign(data, padding.PKCS1v15(), hashes.SHA256())
+    if alg == "ES256":
+        key = serialization.load_der_private_key(from_b64u(keys["es256"]["pkcs8"]), None)
+        r, s = decode_dss_signature(key.sign(data, ec.ECDSA(hashes.SHA256())))
+        return r.to_bytes(32, "big") + s.to_bytes(32, "big")
+    return hmac.new(from_b64u(keys["hs256"]["k"]), data, hashlib.sha256).digest()
+
+
+def verify(alg: str, data: bytes, signature: bytes, keys: dict) -> bool:
+    if alg == "RS256":
+        try:
+            rsa_private_key(keys["rsa"]).public_key().verify(signature, data, padding.PKCS1v15(), hashes.SHA256())
+            return True
+        except InvalidSignature:
+            return False
+    if alg == "ES256":
+        if len(signature) != 64:
+            return False
+        key = ec.EllipticCurvePublicKey.from_encoded_point(ec.SECP256R1(), from_b64u(keys["es256"]["public"]))
+        der = encode_dss_signature(int.from_bytes(signature[:32], "big"), int.from_bytes(signature[32:], "big"))
+        try:
+            key.verify(der, data, ec.ECDSA(hashes.SHA256()))
+            return True
+        except InvalidSignature:
+            return False
+    return hmac.compare_digest(sign(alg, data, keys), signature)
+
+
+def encrypt(alg: str, enc: str, keys: dict, plaintext: bytes, aad: bytes | None = None) -> dict:
+    header = {"alg": alg, "enc": enc}
+    encrypted_key = b""
+    if alg == "dir":
+        cek = from_b64u(keys["dir"]["k"])
+    elif alg.startswith("RSA-OAEP"):
+        cek = os.urandom(32)
+        encrypted_key = rsa_private_key(keys["rsa"]).public_key().encrypt(cek, oaep(alg))
+    else:
+        cek = os.urandom(32)
+        iv = os.urandom(12)
+        wrapped = AESGCM(from_b64u(keys["a256gcmkw"]["k"])).encrypt(iv, cek, None)
+        encrypted_key, tag = wrapped[:-16], wrapped[-16:]
+        header.update(iv=b64u(iv), tag=b64u(tag))
+
+    protected = b64u(json.dumps(header).encode())
+    additional = protected if aad is None else f"{protected}.{b64u(aad)}"
+    iv = os.urandom(12)
+    sealed = AESGCM(cek).encrypt(iv, plaintext, additional.encode())
+    jwe = {
+        "protected": protected,
+        "encrypted_key": b64u(encrypted_key),
+        "iv": b64u(iv),
+        "ciphertext": b64u(sealed[:-16]),
+        "tag": b64u(sealed[-16:]),
+    }
+    if aad is not None:
+        jwe["aad"] = b64u(aad)
+    return jwe
+
+
+def decrypt(jwe: dict, keys: dict) -> bytes:
+    header = json.loads(from_b64u(jwe["protected"]))
+    if header["alg"] == "dir":
+        cek = from_b64u(keys["dir"]["k"])
+    elif header["alg"].startswith("RSA-OAEP"):
+        cek = rsa_private_key(keys["rsa"]).decrypt(from_b64u(jwe["encrypted_key"]), oaep(header["alg"]))
+    else:
+        wrapped = from_b64u(jwe["encrypted_key"]) + from_b64u(header["tag"])
+        cek = AESGCM(from_b64u(keys["a256gcmkw"]["k"])).decrypt(from_b64u(header["iv"]), wrapped, None)
+    additional = jwe["protected"] if "aad" not in jwe else f"{jwe['protected']}.{jwe['aad']}"
+    sealed = from_b64u(jwe["ciphertext"]) + from_b64u(jwe["tag"])
+    return AESGCM(cek).decrypt(from_b64u(jwe["iv"]), sealed, additional.encode())
+
+
+def compact(jwe: dict) -> str:
+    return ".".join(jwe[part] for part in ("protected", "encrypted_key", "iv", "ciphertext", "tag"))
+
+
+def from_compact(token: str) -> dict:
+    return dict(zip(("protected", "encrypted_key", "iv", "ciphertext", "tag"), token.split(".")))
+
+
+def generate() -> dict:
+    private_key = ec.generate_private_key(ec.SECP256R1())
+    rsa_numbers = rsa.generate_private_key(65537, 2048).private_numbers()
+    keys = {
+        "es256": {
+            "pkcs8": b64u(
+                private_key.private_bytes(
+                    serialization.Encoding.DER, serialization.PrivateFormat.PKCS8, serialization.NoEncryption()
+                )
+            ),
+            "public": b64u(
+                private_key.public_key().public_bytes(
+                    serialization.Encoding.X962, serialization.PublicFormat.UncompressedPoint
+                )
+            ),
+            "kid": "pledge",
+        },
+        "hs256": {"k": b64u(os.urandom(32)), "kid": "registrar-agent"},
+        "a256gcmkw": {"k": b64u(os.urandom(32))},
+        "dir": {"k": b64u(os.urandom(16))},
+        "rsa": {
+            "kty": "RSA",
+            "n": int_b64u(rsa_numbers.public_numbers.n),
+            "e": int_b64u(rsa_numbers.public_numbers.e),
+            "d": int_b64u(rsa_numbers.d),
+            "p": int_b64u(rsa_numbers.p),
+            "q": int_b64u(rsa_numbers.q),
+            "dp": int_b64u(rsa_numbers.dmp1),
+            "dq": int_b64u(rsa_numbers.dmq1),
+            "qi": int_b64u(rsa_numbers.iqmp),
+        },
+    }
+
+    def signature(alg: str, header: dict, unprotected: dict | None = None) -> dict:
+        protected = b64u(json.dumps(header).encode())
+        value = {"protected": protected}
+        if unprotected is not None:
+            value["header"] = unprotected
+        value["signature"] = b64u(sign(alg, f"{protected}.{b64u(PAYLOAD)}".encode(), keys))
+        return value
+
+    def compact_jws(alg: str, header: dict) -> str:
+        value = signature(alg, header)
+        return f"{value['protected']}.{b64u(PAYLOAD)}.{value['signature']}"
+
+    aad = b"serial-number=00-D0-E5-F2-00-02"
+    return {
+        "generator": f"pyca/cryptography {__import__('cryptography').__version__}",
+        "keys": keys,
+        "payload": b64u(PAYLOAD),
+        "jws": {
+            "es256_compact": compact_jws("ES256", {"alg": "ES256", "typ": "voucher-jws+json", "kid": "pledge"}),
+            "hs256_compact": compact_jws("HS256", {"typ": "voucher-jws+json", "alg": "HS256"}),
+            "rs256_compact": compact_jws("RS256", {"alg": "RS256", "typ": "voucher-jws+json"}),
+            "es256_flattened": {"payload": b64u(PAYLOAD), **signature("ES256", {"alg": "ES256"}, {"kid": "pledge"})},
+            "general": {
+                "payload": b64u(PAYLOAD),
+                "signatures": [
+                    signature("ES256", {"alg": "ES256", "kid": "pledge"}),
+                    signature("HS256", {"alg": "HS256"}, {"kid": "registrar-agent"}),
+                ],
+            },
+        },
+        "jwe": {
+            "a256gcmkw_a256gcm_compact": compact(encrypt("A256GCMKW", "A256GCM", keys, PAYLOAD)),
+            "dir_a128gcm_compact": compact(encrypt("dir", "A128GCM", keys, PAYLOAD)),
+            "rsa_oaep_a256gcm_compact": compact(encrypt("RSA-OAEP", "A256GCM", keys, PAYLOAD)),
+            "rsa_oaep_256_a256gcm_compact": compact(encrypt("RSA-OAEP-256", "A256GCM", keys, PAYLOAD)),
+            "a256gcmkw_a256gcm_flattened": encrypt("A256GCMKW", "A256GCM", keys, PAYLOAD, aad),
+        },
+    }
+
+
+def check(name: str, condition: bool) -> None:
+    if not condition:
+        raise SystemExit(f"{name} does not verify")
+    print(f"{name}: ok")
+
+
+def verify_fixture(fixture: dict) -> None:
+    keys = fixture["keys"]
+    payload = from_b64u(fixture["payload"])
+
+    def verify_signature(name: str, protected: str, encoded_payload: str, value: str) -> None:
+        alg = json.loads(from_b64u(protected))["alg"]
+        data = f"{protected}.{encoded_payload}".encode()
+        check(name, verify(alg, data, from_b64u(value), keys) and from_b64u(encoded_payload) == payload)
+
+    for name in ("es256_compact", "hs256_compact", "rs256_compact"):
+        verify_signature(name, *fixture["jws"][name].split("."))
+    flattened = fixture["jws"]["es256_flattened"]
+    verify_signature("es256_flattened", flattened["protected"], flattened["payload"], flattened["signature"])
+    general = fixture["jws"]["general"]
+    for index, signature in enumerate(general["signatures"]):
+        verify_signature(f"general[{index}]", signature["protected"], general["payload"], signature["signature"])
+
+    compact_names = ("a256gcmkw_a256gcm_compact", "dir_a128gcm_compact")
+    for name in (*compact_names, "rsa_oaep_a256gcm_compact", "rsa_oaep_256_a256gcm_compact"):
+        try:
+            check(name, decrypt(from_compact(fixture["jwe"][name]), keys) == payload)
+        except (InvalidTag, ValueError):
+            check(name, False)
+    try:
+        check("a256gcmkw_a256gcm_flattened", decrypt(fixture["jwe"]["a256gcmkw_a256gcm_flattened"], keys) == payload)
+    except InvalidTag:
+        check("a256gcmkw_a256gcm_flattened", False)
+
+
+if __name__ == "__main__":
+    if sys.argv[1:2] == ["generate"]:
+        print(json.dumps(generate(), indent=2, ensure_ascii=False))
+    elif sys.argv[1:2] == ["verify"] and len(sys.argv) == 3:
+        with open(sys.argv[2], "rb") as file:
+            verify_fixture(json.load(file))
+    else:
+        raise SystemExit("usage: python3 python.py generate | verify <fixture.json>")
@@ -5,16 +5,21 @@
 //! Typically, you will not use these directly, but as part of a JWS or JWE.
 
 use std::fmt;
+use std::sync::OnceLock;
 
 
 use once_cell::sync::Lazy;
 use ring::constant_time::verify_slices_are_equal;
 use ring::rand::SystemRandom;
 use ring::signature::KeyPair;
-use ring::{aead, hmac, rand, signature};
+use ring::{aead, hmac, signature};
+#[cfg(feature = "jwe")]
+use ring::{agreement, digest};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "jwe")]
+use crate::biscuit::{aes_kw, rsa_oaep};
 use crate::biscuit::errors::Error;
 use crate::biscuit::jwk;
 use crate::biscuit::jws::Secret;
@@ -33,6 +38,7 @@ static AES_GCM_ZEROED_NONCE: Lazy<EncryptionOptions> = Lazy::new(|| EncryptionOp
 });
 
 /// A default `None` `EncryptionOptions`
+#[cfg(feature = "jwe")]
 pub(crate) const NONE_ENCRYPTION_OPTIONS: &EncryptionOptions = &EncryptionOptions::None;
 
 /// Options to be passed in while performing an encryption operation, if required by the algorithm.
@@ -110,6 +116,8 @@ pub enum SignatureAlgorithm {
     /// RSASSA-PSS using SHA-512 and MGF1 with SHA-512
     /// The size of the salt value is the same size as the hash function output.
     PS512,
+    /// EdDSA using Ed25519, see [RFC8037#3.1](https://tools.ietf.org/html/rfc8037#section-3.1)
+    EdDSA,
 }
 
 /// Algorithms for key management as defined in [RFC7518#4](https://tools.ietf.org/html/rfc7518#section-4)
@@ -221,6 +229,18 @@ pub struct EncryptionResult {
     pub additional_data: Vec<u8>,
 }
 
+/// The result of an ECDH-ES key agreement with a recipient's public key
+#[cfg(feature = "jwe")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyAgreement {
+    /// The Content Encryption Key
+    pub cek: jwk::JWK<Empty>,
+    /// The CEK wrapped with the agreed key. Empty for direct key agreement with `ECDH-ES`.
+    pub encrypted_cek: Vec<u8>,
+    /// The ephemeral public key the recipient needs to repeat the agreement, sent as the `epk` header
+    pub ephemeral_public_key: jwk::JWK<Empty>,
+}
+
 impl EncryptionOptions {
     /// Description of the type of key
     pub fn description(&self) -> &'static str {
@@ -247,6 +267,7 @@ impl SignatureAlgorithm {
             HS256 | HS384 | HS512 => Self::sign_hmac(data, secret, self),
             RS256 | RS384 | RS512 | PS256 | PS384 | PS512 => Self::sign_rsa(data, secret, self),
             ES256 | ES384 | ES512 => Self::sign_ecdsa(data, secret, self),
+            EdDSA => Self::sign_eddsa(data, secret),
         }
     }
 
@@ -262,7 +283,7 @@ impl SignatureAlgorithm {
         match self {
             None => Self::verify_none(expected_signature, secret),
             HS256 | HS384 | HS512 => Self::verify_hmac(expected_signature, data, secret, self),
-            RS256 | RS384 | RS512 | PS256 | PS384 | PS512 | ES256 | ES384 | ES512 => {
+            RS256 | RS384 | RS512 | PS256 | PS384 | PS512 | ES256 | ES384 | ES512 | EdDSA => {
                 Self::verify_public_key(expected_signature, data, secret, self)
             }
         }
@@ -307,7 +328,7 @@ impl SignatureAlgorithm {
             _ => Err("Invalid secret type. A RsaKeyPair is required".to_string())?,
         };
 
-        let rng = rand::SystemRandom::new();
+        let rng = checked_rng()?;
         let mut signature = vec![0; key_pair.public().modulus_len()];
         let padding_algorithm: &dyn signature::RsaEncoding = match algorithm {
             SignatureAlgorithm::RS256 => &signature::RSA_PKCS1_SHA256,
@@ -319,7 +340,7 @@ impl SignatureAlgorithm {
             _ => unreachable!("Should not happen"),
         };
 
-        key_pair.sign(padding_algorithm, &rng, data, &mut signature)?;
+        key_pair.sign(padding_algorithm, rng, data, &mut signature)?;
         Ok(signature)
     }
 
@@ -330,18 +351,26 @@ impl SignatureAlgorithm {
     ) -> Result<Vec<u8>, Error> {
         let key_pair = match *secret {
             Secret::EcdsaKeyPair(ref key_pair) => key_pair,
+            Secret::EcdsaSigner(ref signer) => return signer.sign(data, algorithm),
             _ => Err("Invalid secret type. An EcdsaKeyPair is required".to_string())?,
         };
         if let SignatureAlgorithm::ES512 = algorithm {
             // See https://github.com/briansmith/ring/issues/268
             Err(Error::UnsupportedOperation)
         } else {
-            let rng = rand::SystemRandom::new();
-            let sig = key_pair.as_ref().sign(&rng, data)?;
+            let sig = key_pair.as_ref().sign(checked_rng()?, data)?;
             Ok(sig.as_ref().to_vec())
         }
     }
 
+    fn sign_eddsa(data: &[u8], secret: &Secret) -> Result<Vec<u8>, Error> {
+        let key_pair = match *secret {
+            Secret::Ed25519KeyPair(ref key_pair) => key_pair,
+            _ => Err("Invalid secret type. An Ed25519KeyPair is required".to_string())?,
+        };
+        Ok(key_pair.sign(data).as_ref().to_vec())
+    }
+
     fn verify_none(expected_signature: &[u8], secret: &Secret) -> Result<(), Error> {
         match *secret {
             Secret::None => {}
@@ -385,6 +414,7 @@ impl SignatureAlgorithm {
                     SignatureAlgorithm::ES256 => &signature::ECDSA_P256_SHA256_FIXED,
                     SignatureAlgorithm::ES384 => &signature::ECDSA_P384_SHA384_FIXED,
                     SignatureAlgorithm::ES512 => Err(Error::UnsupportedOperation)?,
+                    SignatureAlgorithm::EdDSA => &signature::ED25519,
                     _ => unreachable!("Should not happen"),
                 };
 
@@ -404,7 +434,7 @@ impl SignatureAlgorithm {
                     SignatureAlgorithm::PS256 => &signature::RSA_PSS_2048_8192_SHA256,
                     SignatureAlgorithm::PS384 => &signature::RSA_PSS_2048_8192_SHA384,
                     SignatureAlgorithm::PS512 => &signature::RSA_PSS_2048_8192_SHA512,
-                    _ => unreachable!("Should not happen"),
+                    _ => Err(Error::UnsupportedOperation)?,
                 };
 
                 let public_key =
@@ -420,7 +450,7 @@ impl SignatureAlgorithm {
                     SignatureAlgorithm::PS256 => &signature::RSA_PSS_2048_8192_SHA256,
                     SignatureAlgorithm::PS384 => &signature::RSA_PSS_2048_8192_SHA384,
                     SignatureAlgorithm::PS512 => &signature::RSA_PSS_2048_8192_SHA512,
-                    _ => unreachable!("(n,e) secret with a non-rsa algorithm should not happen"),
+                    _ => Err(Error::UnsupportedOperation)?,
                 };
 
                 let n_big_endian = n.to_bytes_be();
@@ -437,8 +467,7 @@ impl SignatureAlgorithm {
                 {
                     SignatureAlgorithm::ES256 => &signature::ECDSA_P256_SHA256_FIXED,
                     SignatureAlgorithm::ES384 => &signature::ECDSA_P384_SHA384_FIXED,
-                    SignatureAlgorithm::ES512 => Err(Error::UnsupportedOperation)?,
-                    _ => unreachable!("Should not happen"),
+                    _ => Err(Error::UnsupportedOperation)?,
                 };
 
                 let public_key =
@@ -446,6 +475,29 @@ impl SignatureAlgorithm {
                 public_key.verify(data, expected_signature)?;
                 Ok(())
             }
+            Secret::EcdsaSigner(ref signer) => {
+                let verification_algorithm: &dyn signature::VerificationAlgorithm = match algorithm
+                {
+                    SignatureAlgorithm::ES256 => &signature::ECDSA_P256_SHA256_FIXED,
+                    SignatureAlgorithm::ES384 => &signature::ECDSA_P384_SHA384_FIXED,
+                    _ => Err(Error::UnsupportedOperation)?,
+                };
+
+                let public_key =
+                    signature::UnparsedPublicKey::new(verification_algorithm, signer.public_key());
+                public_key.verify(data, expected_signature)?;
+                Ok(())
+            }
+            Secret::Ed25519KeyPair(ref keypair) => {
+                if algorithm != SignatureAlgorithm::EdDSA {
+                    Err(Error::UnsupportedOperation)?;
+                }
+
+                let public_key =
+                    signature::UnparsedPublicKey::new(&signature::ED25519, keypair.public_key());
+                public_key.verify(data, expected_signature)?;
+                Ok(())
+            }
             _ => unreachable!("This is a private method and should not be called erroneously."),
         }
     }
@@ -487,7 +539,7 @@ impl KeyManagementAlgorithm {
 
         match self {
             DirectSymmetricKey => self.cek_direct(key),
-            A128GCMKW | A256GCMKW => self.cek_aes_gcm(content_alg),
+            A128GCMKW | A256GCMKW | RSA_OAEP | RSA_OAEP_256 => self.cek_random(content_alg),
             _ => Err(Error::UnsupportedOperation),
         }
     }
@@ -502,7 +554,7 @@ impl KeyManagementAlgorithm {
         }
     }
 
-    fn cek_aes_gcm(
+    fn cek_random(
         self,
         content_alg: ContentEncryptionAlgorithm,
     ) -> Result<jwk::JWK<Empty>, Error> {
@@ -521,7 +573,101 @@ impl KeyManagementAlgorithm {
         })
     }
 
+    /// Agree on the Content Encryption Key with the recipient's EC public `key` using an ephemeral key pair
+    ///
+    /// `apu` and `apv` are the agreement PartyUInfo and PartyVInfo which end up in the `apu` and `apv`
+    /// headers. Only the sending side is supported, as `ring` does not perform ECDH with static private keys.
+    #[cfg(feature = "jwe")]
+    pub fn agree<T>(
+        self,
+        content_alg: ContentEncryptionAlgorithm,
+        key: &jwk::JWK<T>,
+        apu: &[u8],
+        apv: &[u8],
+    ) -> Result<KeyAgreement, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        use self::KeyManagementAlgorithm::*;
+
+        let (algorithm_id, key_length) = match self {
+            ECDH_ES => match content_alg {
+                ContentEncryptionAlgorithm::A128GCM => ("A128GCM", 128 / 8),
+                ContentEncryptionAlgorithm::A256GCM => ("A256GCM", 256 / 8),
+                _ => Err(Error::UnsupportedOperation)?,
+            },
+            ECDH_ES_A128KW => ("ECDH-ES+A128KW", 128 / 8),
+            ECDH_ES_A256KW => ("ECDH-ES+A256KW", 256 / 8),
+            _ => Err(Error::UnsupportedOperation)?,
+        };
+
+        let recipient = match key.algorithm {
+            jwk::AlgorithmParameters::EllipticCurve(ref recipient) => recipient,
+            ref others => Err(unexpected_key_type_error!(jwk::KeyType::EllipticCurve, others.key_type()))?,
+        };
+        let agreement_alg = match recipient.curve {
+            jwk::EllipticCurve::P256 => &agreement::ECDH_P256,
+            jwk::EllipticCurve::P384 => &agreement::ECDH_P384,
+            _ => Err(Error::UnsupportedOperation)?,
+        };
+
+        let mut peer_public_key = Vec::with_capacity(1 + recipient.x.len() + recipient.y.len());
+        peer_public_key.push(0x04);
+        peer_public_key.extend_from_slice(&recipient.x);
+        peer_public_key.extend_from_slice(&recipient.y);
+
+        let private_key = agreement::EphemeralPrivateKey::generate(agreement_alg, checked_rng()?)?;
+        let public_key = private_key.compute_public_key()?;
+        let agreed = agreement::agree_ephemeral(
+            private_key,
+            &agreement::UnparsedPublicKey::new(agreement_alg, peer_public_key),
+            |z| concat_kdf(z, algorithm_id, apu, apv, key_length),
+        )?;
+
+        let (cek, encrypted_cek) = match self {
+            ECDH_ES => (agreed, vec![]),
+            _ => {
+                let cek = content_alg.generate_key()?;
+                let encrypted_cek = aes_kw::wrap(&agreed, &cek)?;
+                (cek, encrypted_cek)
+            }
+        };
+
+        // Uncompressed SEC1 point, split into its coordinates
+        let coordinates = &public_key.as_ref()[1..];
+        let (x, y) = coordinates.split_at(coordinates.len() / 2);
+
+        Ok(KeyAgreement {
+            cek: jwk::JWK {
+                algorithm: jwk::AlgorithmParameters::OctetKey(jwk::OctetKeyParameters {
+                    value: cek,
+                    key_type: Default::default(),
+                }),
+                common: jwk::CommonParameters {
+                    public_key_use: Some(jwk::PublicKeyUse::Encryption),
+                    algorithm: Some(Algorithm::ContentEncryption(content_alg)),
+                    ..Default::default()
+                },
+                additional: Default::default(),
+            },
+            encrypted_cek,
+            ephemeral_public_key: jwk::JWK {
+                algorithm: jwk::AlgorithmParameters::EllipticCurve(jwk::EllipticCurveKeyParameters {
+                    key_type: Default::default(),
+                    curve: recipient.curve.clone(),
+                    x: x.to_vec(),
+                    y: y.to_vec(),
+                    d: None,
+                }),
+                common: Default::default(),
+                additional: Default::default(),
+            },
+        })
+    }
+
     /// Encrypt or wrap a Content Encryption Key with the provided algorithm
+    ///
+    /// `RSA-OAEP` and `RSA-OAEP-256` encrypt to the public parameters of an RSA `key` and ignore `options`.
     pub fn wrap_key<T: Serialize + DeserializeOwned>(
         self,
         payload: &[u8],
@@ -532,6 +678,11 @@ impl KeyManagementAlgorithm {
 
         match self {
             A128GCMKW | A192GCMKW | A256GCMKW => self.aes_gcm_encrypt(payload, key, options),
+            #[cfg(feature = "jwe")]
+            RSA_OAEP | RSA_OAEP_256 => Ok(EncryptionResult {
+                encrypted: rsa_oaep::encrypt(self.oaep_digest(), rsa_key(key)?, payload)?,
+                ..Default::default()
+            }),
             DirectSymmetricKey => match *options {
                 EncryptionOptions::None => Ok(Default::default()),
                 ref other => Err(unexpected_encryption_options_error!(
@@ -554,11 +705,36 @@ impl KeyManagementAlgorithm {
 
         match self {
             A128GCMKW | A192GCMKW | A256GCMKW => self.aes_gcm_decrypt(encrypted, content_alg, key),
+            #[cfg(feature = "jwe")]
+            RSA_OAEP | RSA_OAEP_256 => {
+                let cek = rsa_oaep::decrypt(self.oaep_digest(), rsa_key(key)?, &encrypted.encrypted)?;
+                Ok(jwk::JWK {
+                    algorithm: jwk::AlgorithmParameters::OctetKey(jwk::OctetKeyParameters {
+                        value: cek,
+                        key_type: Default::default(),
+                    }),
+                    common: jwk::CommonParameters {
+                        public_key_use: Some(jwk::PublicKeyUse::Encryption),
+                        algorithm: Some(Algorithm::ContentEncryption(content_alg)),
+                        ..Default::default()
+                    },
+                    additional: Default::default(),
+                })
+            }
             DirectSymmetricKey => Ok(key.clone_without_additional()),
             _ => Err(Error::UnsupportedOperation),
         }
     }
 
+    /// Hash of the OAEP padding and MGF1, SHA-1 for `RSA-OAEP` as required by RFC 7518 Section 4.3
+    #[cfg(feature = "jwe")]
+    fn oaep_digest(self) -> &'static digest::Algorithm {
+        match self {
+            KeyManagementAlgorithm::RSA_OAEP_256 => &digest::SHA256,
+            _ => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        }
+    }
+
     fn aes_gcm_encrypt<T: Serialize + DeserializeOwned>(
         self,
         payload: &[u8],
@@ -627,7 +803,7 @@ impl ContentEncryptionAlgorithm {
         };
 
         let mut key: Vec<u8> = vec![0; length];
-        rng().fill(&mut key)?;
+        fill_random(&mut key)?;
         Ok(key)
     }
 
@@ -662,6 +838,7 @@ impl ContentEncryptionAlgorithm {
     }
 
     /// Generate a new random `EncryptionOptions` based on the algorithm
+    #[cfg(feature = "jwe")]
     pub(crate) fn random_encryption_options(self) -> Result<EncryptionOptions, Error> {
         use self::ContentEncryptionAlgorithm::*;
         match self {
@@ -715,6 +892,44 @@ impl ContentEncryptionAlgorithm {
     }
 }
 
+/// Source of the random bytes for content encryption keys and nonces, see [`set_random_source`]
+pub trait RandomSource: Send + Sync {
+    /// Fills `dest` with random bytes, fails instead of handing out bytes of a source that is not healthy
+    fn fill(&self, dest: &mut [u8]) -> Result<(), Error>;
+
+    /// Checked before ring draws from its own `SystemRandom`, which can not be replaced
+    fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+static RANDOM_SOURCE: OnceLock<&'static dyn RandomSource> = OnceLock::new();
+
+/// Replaces ring's `SystemRandom` for content encryption keys and nonces, e.g. with a health tested hardware RNG.
+/// It can only be set once. Signatures and ephemeral agreement keys are generated by ring with `SystemRandom`,
+/// but only once the source passed its [`RandomSource::check`].
+pub fn set_random_source(source: &'static dyn RandomSource) -> Result<(), Error> {
+    RANDOM_SOURCE
+        .set(source)
+        .map_err(|_| Error::GenericError("Random source is already set".to_string()))
+}
+
+/// Random bytes from the source set with [`set_random_source`], from ring's `SystemRandom` otherwise
+pub(crate) fn fill_random(dest: &mut [u8]) -> Result<(), Error> {
+    match RANDOM_SOURCE.get() {
+        Some(source) => source.fill(dest),
+        None => Ok(rng().fill(dest)?),
+    }
+}
+
+/// Return ring's random number generator, after checking the source set with [`set_random_source`]
+pub(crate) fn checked_rng() -> Result<&'static SystemRandom, Error> {
+    if let Some(source) = RANDOM_SOURCE.get() {
+        source.check()?;
+    }
+    Ok(rng())
+}
+
 /// Return a pseudo random number generator
 pub(crate) fn rng() -> &'static SystemRandom {
     use std::ops::Deref;
@@ -724,6 +939,45 @@ pub(crate) fn rng() -> &'static SystemRandom {
     RANDOM.deref()
 }
 
+/// Concat KDF with SHA-256 as used by ECDH-ES, see [RFC7518#4.6.2](https://tools.ietf.org/html/rfc7518#section-4.6.2)
+#[cfg(feature = "jwe")]
+fn concat_kdf(
+    z: &[u8],
+    algorithm_id: &str,
+    apu: &[u8],
+    apv: &[u8],
+    key_length: usize,
+) -> Vec<u8> {
+    let mut other_info = Vec::new();
+    for field in [algorithm_id.as_bytes(), apu, apv] {
+        other_info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        other_info.extend_from_slice(field);
+    }
+    other_info.extend_from_slice(&((key_length * 8) as u32).to_be_bytes());
+
+    let mut derived = Vec::with_capacity(key_length);
+    let mut counter: u32 = 1;
+    while derived.len() < key_length {
+        let mut round = digest::Context::new(&digest::SHA256);
+        round.update(&counter.to_be_bytes());
+        round.update(z);
+        round.update(&other_info);
+        derived.extend_from_slice(round.finish().as_ref());
+        counter += 1;
+    }
+    derived.truncate(key_length);
+    derived
+}
+
+/// The RSA parameters of `key` for `RSA-OAEP` and `RSA-OAEP-256`
+#[cfg(feature = "jwe")]
+fn rsa_key<T: Serialize + DeserializeOwned>(key: &jwk::JWK<T>) -> Result<&jwk::RSAKeyParameters, Error> {
+    match key.algorithm {
+        jwk::AlgorithmParameters::RSA(ref rsa) => Ok(rsa),
+        ref others => Err(unexpected_key_type_error!(jwk::KeyType::RSA, others.key_type())),
+    }
+}
+
 /// Encrypt a payload with AES GCM
 fn aes_gcm_encrypt<T: Serialize + DeserializeOwned>(
     algorithm: &'static aead::Algorithm,
@@ -782,9 +1036,10 @@ fn aes_gcm_decrypt<T: Serialize + DeserializeOwned>(
     Ok(plaintext.to_vec())
 }
 
+#[cfg(feature = "jwe")]
 pub(crate) fn random_aes_gcm_nonce() -> Result<Vec<u8>, Error> {
     let mut nonce: Vec<u8> = vec![0; AES_GCM_NONCE_LENGTH];
-    rng().fill(&mut nonce)?;
+    fill_random(&mut nonce)?;
     Ok(nonce)
 }
 
@@ -901,6 +1156,59 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn sign_and_verify_rs256_jwk() {
+        let fixture: serde_json::Value =
+            not_err!(serde_json::from_str(include_str!("interop/python.json")));
+        let key: jwk::RSAKeyParameters = not_err!(serde_json::from_value(fixture["keys"]["rsa"].clone()));
+        let payload_bytes = "payload".as_bytes();
+
+        let private_key = not_err!(key.jws_private_key_secret());
+        for algorithm in [SignatureAlgorithm::RS256, SignatureAlgorithm::RS384, SignatureAlgorithm::RS512] {
+            let signature = not_err!(algorithm.sign(payload_bytes, &private_key));
+            not_err!(algorithm.verify(&signature, payload_bytes, &key.jws_public_key_secret()));
+        }
+
+        let public_only = jwk::RSAKeyParameters {
+            n: key.n.clone(),
+            e: key.e.clone(),
+            ..Default::default()
+        };
+        assert!(public_only.jws_private_key_secret().is_err());
+    }
+
+    /// Test case from [RFC8037#A.4](https://tools.ietf.org/html/rfc8037#appendix-A.4)
+    #[test]
+    fn sign_and_verify_eddsa_jwk() {
+        let key: jwk::OctetKeyPairParameters = not_err!(serde_json::from_str(
+            r#"{"kty":"OKP","crv":"Ed25519",
+                "d":"nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A",
+                "x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#
+        ));
+        let payload_bytes = "eyJhbGciOiJFZERTQSJ9.RXhhbXBsZSBvZiBFZDI1NTE5IHNpZ25pbmc".as_bytes();
+        let expected_signature = "hgyY0il_MGCjP0JzlnLWG1PPOt7-09PGcvMg3AIbQR6dWbhijcNR4ki4iylGjg5BhVsPt9g7sVvpAr_MuM0KAg";
+
+        let private_key = not_err!(key.jws_private_key_secret());
+        let actual_signature = not_err!(SignatureAlgorithm::EdDSA.sign(payload_bytes, &private_key));
+        assert_eq!(&*not_err!(actual_signature.to_base64()), expected_signature);
+        not_err!(SignatureAlgorithm::EdDSA.verify(
+            actual_signature.as_slice(),
+            payload_bytes,
+            &key.jws_public_key_secret(),
+        ));
+
+        let public_only = jwk::OctetKeyPairParameters {
+            d: None,
+            ..key.clone()
+        };
+        assert!(public_only.jws_private_key_secret().is_err());
+        let ed448 = jwk::OctetKeyPairParameters {
+            curve: jwk::EllipticCurve::Curve448,
+            ..key
+        };
+        assert!(ed448.jws_private_key_secret().is_err());
+    }
+
     /// This signature is non-deterministic.
     #[test]
     fn sign_and_verify_ps256_round_trip() {
@@ -1048,6 +1356,49 @@ mod tests {
         ));
     }
 
+    /// Test vector 1 of [RFC8032#7.1](https://tools.ietf.org/html/rfc8032#section-7.1)
+    #[test]
+    fn verify_eddsa() {
+        use data_encoding::HEXLOWER;
+
+        let public_key = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+        let public_key = Secret::PublicKey(not_err!(HEXLOWER.decode(public_key.as_bytes())));
+        let signature = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                         5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+        let signature_bytes: Vec<u8> = not_err!(HEXLOWER.decode(signature.as_bytes()));
+        not_err!(SignatureAlgorithm::EdDSA.verify(
+            signature_bytes.as_slice(),
+            &[],
+            &public_key,
+        ));
+    }
+
+    #[test]
+    fn sign_and_verify_eddsa_round_trip_with_keypair() {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = not_err!(signature::Ed25519KeyPair::generate_pkcs8(&rng));
+        let key_pair = not_err!(signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()));
+        let public_key = Secret::PublicKey(key_pair.public_key().as_ref().to_vec());
+        let key = Secret::Ed25519KeyPair(std::sync::Arc::new(key_pair));
+        let payload_bytes = "payload".as_bytes();
+
+        let actual_signature = not_err!(SignatureAlgorithm::EdDSA.sign(payload_bytes, &key));
+
+        not_err!(SignatureAlgorithm::EdDSA.verify(
+            actual_signature.as_slice(),
+            payload_bytes,
+            &key,
+        ));
+        not_err!(SignatureAlgorithm::EdDSA.verify(
+            actual_signature.as_slice(),
+            payload_bytes,
+            &public_key,
+        ));
+        assert!(SignatureAlgorithm::ES256
+            .verify(actual_signature.as_slice(), payload_bytes, &key)
+            .is_err());
+    }
+
     #[test]
     #[should_panic(expected = "UnsupportedOperation")]
     fn verify_es512() {
@@ -1330,6 +1681,67 @@ mod tests {
         );
     }
 
+    /// Test vector from [RFC 7518 Appendix C](https://tools.ietf.org/html/rfc7518#appendix-C)
+    #[cfg(feature = "jwe")]
+    #[test]
+    fn concat_kdf_rfc7518_vector() {
+        let z: Vec<u8> = vec![
+            158, 86, 217, 29, 129, 113, 53, 211, 114, 131, 66, 131, 191, 132, 38, 156, 251, 49,
+            110, 163, 218, 128, 106, 72, 246, 218, 167, 121, 140, 254, 144, 196,
+        ];
+
+        let derived = concat_kdf(&z, "A128GCM", b"Alice", b"Bob", 128 / 8);
+        assert_eq!(&*not_err!(derived.to_base64()), "VqqN6vgjbSBcIijNcacQGg");
+    }
+
+    #[cfg(feature = "jwe")]
+    #[test]
+    fn ecdh_es_agreement_returns_right_key_lengths() {
+        // Bob's public key from RFC 7518 Appendix C
+        let key = jwk::JWK::<Empty> {
+            common: Default::default(),
+            additional: Default::default(),
+            algorithm: jwk::AlgorithmParameters::EllipticCurve(jwk::EllipticCurveKeyParameters {
+                key_type: Default::default(),
+                curve: jwk::EllipticCurve::P256,
+                x: not_err!(CompactPart::from_base64(
+                    &"weNJy2HscCSM6AEDTDg04biOvhFhyyWvOHQfeF_PxMQ"
+                )),
+                y: not_err!(CompactPart::from_base64(
+                    &"e8lnCO-AlStT-NJVX-crhB7QRYhiix03illJOVAOyck"
+                )),
+                d: None,
+            }),
+        };
+
+        let agreement = not_err!(KeyManagementAlgorithm::ECDH_ES.agree(
+            jwa::ContentEncryptionAlgorithm::A256GCM,
+            &key,
+            b"Alice",
+            b"Bob"
+        ));
+        assert_eq!(agreement.cek.octet_key().unwrap().len(), 256 / 8);
+        assert!(agreement.encrypted_cek.is_empty());
+        match agreement.ephemeral_public_key.algorithm {
+            jwk::AlgorithmParameters::EllipticCurve(ref epk) => {
+                assert_eq!(epk.curve, jwk::EllipticCurve::P256);
+                assert_eq!(epk.x.len(), 32);
+                assert_eq!(epk.y.len(), 32);
+                assert!(epk.d.is_none());
+            }
+            ref others => panic!("Unexpected epk {:?}", others),
+        }
+
+        let agreement = not_err!(KeyManagementAlgorithm::ECDH_ES_A128KW.agree(
+            jwa::ContentEncryptionAlgorithm::A128GCM,
+            &key,
+            &[],
+            &[]
+        ));
+        assert_eq!(agreement.cek.octet_key().unwrap().len(), 128 / 8);
+        assert_eq!(agreement.encrypted_cek.len(), 128 / 8 + 8);
+    }
+
     #[test]
     fn aes128gcmkw_key_encryption_round_trip() {
         let mut key: Vec<u8> = vec![0; 128 / 8];
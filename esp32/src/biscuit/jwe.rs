@@ -13,9 +13,17 @@ use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
 use crate::biscuit::errors::{DecodeError, Error, ValidationError};
 use crate::biscuit::jwa::{
     self, ContentEncryptionAlgorithm, EncryptionOptions, EncryptionResult, KeyManagementAlgorithm,
+    KeyManagementAlgorithmType,
 };
 use crate::biscuit::jwk;
-use crate::biscuit::{CompactJson, CompactPart, Empty};
+use crate::biscuit::serde_custom;
+use crate::biscuit::{CompactJson, CompactPart, DecodeDiagnostic, DecodeOptions, Empty};
+
+mod flattened;
+mod general;
+
+use flattened::FlattenedRaw;
+pub use general::{Recipient, RecipientHeader};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 /// Compression algorithm applied to plaintext before encryption.
@@ -159,13 +167,45 @@ pub struct RegisteredHeader {
 pub struct CekAlgorithmHeader {
     /// Header for AES GCM Keywrap algorithm.
     /// The initialization vector, or nonce used in the encryption
-    #[serde(rename = "iv", skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "iv",
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub nonce: Option<Vec<u8>>,
 
     /// Header for AES GCM Keywrap algorithm.
     /// The authentication tag resulting from the encryption
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
     pub tag: Option<Vec<u8>>,
+
+    /// Header for ECDH-ES algorithms.
+    /// The ephemeral public key the sender created for the key agreement
+    #[serde(rename = "epk", skip_serializing_if = "Option::is_none", default)]
+    pub ephemeral_public_key: Option<jwk::JWK<Empty>>,
+
+    /// Header for ECDH-ES algorithms.
+    /// Agreement PartyUInfo, information about the producer
+    #[serde(
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub apu: Option<Vec<u8>>,
+
+    /// Header for ECDH-ES algorithms.
+    /// Agreement PartyVInfo, information about the recipient
+    #[serde(
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub apv: Option<Vec<u8>>,
 }
 
 /// JWE Header, consisting of the registered fields and other custom fields
@@ -360,6 +400,10 @@ where
     ///
     /// If your `cek_algorithm` is `dir` or Direct, then the options will be used to encrypt
     /// your content directly.
+    ///
+    /// If your `cek_algorithm` is one of the ECDH-ES algorithms, `key` is the recipient's EC public key
+    /// and the options are not used. Set the `apu` and `apv` headers before encrypting if needed.
+    /// Decrypting these tokens is not supported.
     pub fn encrypt<K: Serialize + DeserializeOwned>(
         &self,
         key: &jwk::JWK<K>,
@@ -371,59 +415,8 @@ where
                 ref header,
                 ref payload,
             } => {
-                use std::borrow::Cow;
-
-                // Resolve encryption option
-                let (key_option, content_option): (_, Cow<'_, _>) =
-                    match header.registered.cek_algorithm {
-                        KeyManagementAlgorithm::DirectSymmetricKey => {
-                            (jwa::NONE_ENCRYPTION_OPTIONS, Cow::Borrowed(options))
-                        }
-                        _ => (
-                            options,
-                            Cow::Owned(
-                                header
-                                    .registered
-                                    .enc_algorithm
-                                    .random_encryption_options()?,
-                            ),
-                        ),
-                    };
-
-                // RFC 7516 Section 5.1 describes the steps involved in encryption.
-                // From steps 1 to 8, we will first determine the CEK, and then encrypt the CEK.
-                let cek = header
-                    .registered
-                    .cek_algorithm
-                    .cek(header.registered.enc_algorithm, key)?;
-                let encrypted_cek = header.registered.cek_algorithm.wrap_key(
-                    cek.algorithm.octet_key()?,
-                    key,
-                    key_option,
-                )?;
-                // Update header
-                let mut header = header.clone();
-                header.update_cek_algorithm(&encrypted_cek);
-
-                // Steps 9 and 10 involves calculating an initialization vector (nonce) for content encryption. We do
-                // this as part of the encryption process later
-
-                // Step 11 involves compressing the payload, which we do not support at the moment
-                let payload = payload.to_bytes()?;
-                if header.registered.compression_algorithm.is_some() {
-                    Err(Error::UnsupportedOperation)?
-                }
-
-                // Steps 12 to 14 involves the calculation of `Additional Authenticated Data` for encryption. In
-                // our compact example, our header is the AAD.
-                let encoded_protected_header = BASE64URL_NOPAD.encode(&header.to_bytes()?);
-                // Step 15 involves the actual encryption.
-                let encrypted_payload = header.registered.enc_algorithm.encrypt(
-                    &payload,
-                    encoded_protected_header.as_bytes(),
-                    &cek,
-                    &content_option,
-                )?;
+                let (header, encrypted_cek, encrypted_payload) =
+                    Self::encrypt_parts(header, payload, key, options, None)?;
 
                 // Finally create the JWE
                 let mut compact = crate::biscuit::Compact::with_capacity(5);
@@ -438,6 +431,91 @@ where
         }
     }
 
+    /// RFC 7516 Section 5.1 steps shared by the compact and the JSON serialization.
+    /// Returns the protected header together with the encrypted CEK and payload.
+    fn encrypt_parts<K: Serialize + DeserializeOwned>(
+        header: &Header<H>,
+        payload: &T,
+        key: &jwk::JWK<K>,
+        options: &EncryptionOptions,
+        aad: Option<&[u8]>,
+    ) -> Result<(Header<H>, EncryptionResult, EncryptionResult), Error> {
+        use std::borrow::Cow;
+
+        // Resolve encryption option
+        let (key_option, content_option): (_, Cow<'_, _>) = match header.registered.cek_algorithm {
+            KeyManagementAlgorithm::DirectSymmetricKey => {
+                (jwa::NONE_ENCRYPTION_OPTIONS, Cow::Borrowed(options))
+            }
+            _ => (
+                options,
+                Cow::Owned(
+                    header
+                        .registered
+                        .enc_algorithm
+                        .random_encryption_options()?,
+                ),
+            ),
+        };
+
+        // RFC 7516 Section 5.1 describes the steps involved in encryption.
+        // From steps 1 to 8, we will first determine the CEK, and then encrypt the CEK.
+        let mut header = header.clone();
+        let (cek, encrypted_cek) = match header.registered.cek_algorithm.algorithm_type() {
+            KeyManagementAlgorithmType::DirectKeyAgreement
+            | KeyManagementAlgorithmType::KeyAgreementWithKeyWrapping => {
+                let agreement = header.registered.cek_algorithm.agree(
+                    header.registered.enc_algorithm,
+                    key,
+                    header.cek_algorithm.apu.as_deref().unwrap_or_default(),
+                    header.cek_algorithm.apv.as_deref().unwrap_or_default(),
+                )?;
+                header.cek_algorithm.ephemeral_public_key = Some(agreement.ephemeral_public_key);
+                let encrypted_cek = EncryptionResult {
+                    encrypted: agreement.encrypted_cek,
+                    ..Default::default()
+                };
+                (agreement.cek, encrypted_cek)
+            }
+            _ => {
+                let cek = header
+                    .registered
+                    .cek_algorithm
+                    .cek(header.registered.enc_algorithm, key)?;
+                let encrypted_cek = header.registered.cek_algorithm.wrap_key(
+                    cek.algorithm.octet_key()?,
+                    key,
+                    key_option,
+                )?;
+                (cek, encrypted_cek)
+            }
+        };
+        // Update header
+        header.update_cek_algorithm(&encrypted_cek);
+
+        // Steps 9 and 10 involves calculating an initialization vector (nonce) for content encryption. We do
+        // this as part of the encryption process later
+
+        // Step 11 involves compressing the payload, which we do not support at the moment
+        let payload = payload.to_bytes()?;
+        if header.registered.compression_algorithm.is_some() {
+            Err(Error::UnsupportedOperation)?
+        }
+
+        // Steps 12 to 14 involves the calculation of `Additional Authenticated Data` for encryption.
+        // The protected header is always part of it, the JSON serialization may add `aad`.
+        let additional_data = additional_data(&header.to_bytes()?, aad);
+        // Step 15 involves the actual encryption.
+        let encrypted_payload = header.registered.enc_algorithm.encrypt(
+            &payload,
+            &additional_data,
+            &cek,
+            &content_option,
+        )?;
+
+        Ok((header, encrypted_cek, encrypted_payload))
+    }
+
     /// Consumes self and decrypt it. If the token is already decrypted,
     /// this is a no-op.
     pub fn into_decrypted<K: Serialize + DeserializeOwned>(
@@ -454,12 +532,28 @@ where
 
     /// Decrypt an encrypted JWE. Provide the expected algorithms to mitigate an attacker modifying the
     /// fields
+    ///
+    /// Tokens that are out of spec are rejected, see [`DecodeOptions::strict`]
     pub fn decrypt<K: Serialize + DeserializeOwned>(
         &self,
         key: &jwk::JWK<K>,
         cek_alg: KeyManagementAlgorithm,
         enc_alg: ContentEncryptionAlgorithm,
     ) -> Result<Self, Error> {
+        let (decrypted, _) =
+            self.decrypt_with_options(key, cek_alg, enc_alg, &DecodeOptions::default())?;
+        Ok(decrypted)
+    }
+
+    /// Decrypt an encrypted JWE like [`Compact::decrypt`], checking it against the JOSE specifications
+    /// as set in `options`. Returns the decrypted JWE together with the deviations `options` tolerated
+    pub fn decrypt_with_options<K: Serialize + DeserializeOwned>(
+        &self,
+        key: &jwk::JWK<K>,
+        cek_alg: KeyManagementAlgorithm,
+        enc_alg: ContentEncryptionAlgorithm,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeDiagnostic>), Error> {
         match *self {
             Compact::Encrypted(ref encrypted) => {
                 if encrypted.len() != 5 {
@@ -468,61 +562,147 @@ where
                         expected: 5,
                     })?
                 }
+                // the header, the initialization vector and the tag are never empty
+                let (normalized, diagnostics) = encrypted.inspect(options, &[0, 2, 4])?;
+
                 // RFC 7516 Section 5.2 describes the steps involved in decryption.
                 // Steps 1-3
-                let mut header: Header<H> = encrypted.part(0)?;
-                let encrypted_cek: Vec<u8> = encrypted.part(1)?;
-                let nonce: Vec<u8> = encrypted.part(2)?;
-                let encrypted_payload: Vec<u8> = encrypted.part(3)?;
-                let tag: Vec<u8> = encrypted.part(4)?;
-
-                // Verify that the algorithms are expected
-                if header.registered.cek_algorithm != cek_alg
-                    || header.registered.enc_algorithm != enc_alg
-                {
-                    Err(Error::ValidationError(
-                        ValidationError::WrongAlgorithmHeader,
-                    ))?;
-                }
-
-                // TODO: Steps 4-5 not implemented at the moment.
+                let header: Header<H> = normalized.part(0)?;
+                let encrypted_cek: Vec<u8> = normalized.part(1)?;
+                // the additional authenticated data covers the protected header as received
+                let encrypted_payload = EncryptionResult {
+                    nonce: normalized.part(2)?,
+                    encrypted: normalized.part(3)?,
+                    tag: normalized.part(4)?,
+                    additional_data: encrypted.parts[0].as_bytes().to_vec(),
+                };
 
-                // Steps 6-13 involve the computation of the cek
-                let cek_encryption_result = header.extract_cek_encryption_result(&encrypted_cek);
-                let cek = header.registered.cek_algorithm.unwrap_key(
-                    &cek_encryption_result,
-                    header.registered.enc_algorithm,
+                let decrypted = Self::decrypt_parts(
+                    header,
+                    &encrypted_cek,
+                    &encrypted_payload,
                     key,
+                    cek_alg,
+                    enc_alg,
                 )?;
+                Ok((decrypted, diagnostics))
+            }
+            Compact::Decrypted { .. } => Err(Error::UnsupportedOperation),
+        }
+    }
 
-                // Build encryption result as per steps 14-15
-                let protected_header: Vec<u8> = encrypted.part(0)?;
-                let encoded_protected_header = BASE64URL_NOPAD.encode(protected_header.as_ref());
-                let encrypted_payload_result = EncryptionResult {
-                    nonce,
-                    tag,
-                    encrypted: encrypted_payload,
-                    additional_data: encoded_protected_header.as_bytes().to_vec(),
-                };
+    /// RFC 7516 Section 5.2 steps shared by the compact and the JSON serialization.
+    /// `encrypted_payload` already carries the additional authenticated data.
+    fn decrypt_parts<K: Serialize + DeserializeOwned>(
+        mut header: Header<H>,
+        encrypted_cek: &[u8],
+        encrypted_payload: &EncryptionResult,
+        key: &jwk::JWK<K>,
+        cek_alg: KeyManagementAlgorithm,
+        enc_alg: ContentEncryptionAlgorithm,
+    ) -> Result<Self, Error> {
+        // Verify that the algorithms are expected
+        if header.registered.cek_algorithm != cek_alg || header.registered.enc_algorithm != enc_alg
+        {
+            Err(Error::ValidationError(
+                ValidationError::WrongAlgorithmHeader,
+            ))?;
+        }
 
-                let payload = header
-                    .registered
-                    .enc_algorithm
-                    .decrypt(&encrypted_payload_result, &cek)?;
+        // TODO: Steps 4-5 not implemented at the moment.
+
+        // Steps 6-13 involve the computation of the cek
+        let cek_encryption_result = header.extract_cek_encryption_result(encrypted_cek);
+        let cek = header.registered.cek_algorithm.unwrap_key(
+            &cek_encryption_result,
+            header.registered.enc_algorithm,
+            key,
+        )?;
+
+        // Steps 14-15
+        let payload = header
+            .registered
+            .enc_algorithm
+            .decrypt(encrypted_payload, &cek)?;
+
+        // Decompression is not supported at the moment
+        if header.registered.compression_algorithm.is_some() {
+            Err(Error::UnsupportedOperation)?
+        }
 
-                // Decompression is not supported at the moment
-                if header.registered.compression_algorithm.is_some() {
-                    Err(Error::UnsupportedOperation)?
-                }
+        let payload = T::from_bytes(&payload)?;
 
-                let payload = T::from_bytes(&payload)?;
+        Ok(Compact::new_decrypted(header, payload))
+    }
 
-                Ok(Compact::new_decrypted(header, payload))
+    /// Encrypt a Decrypted JWE into the Flattened JWE JSON Serialization.
+    ///
+    /// `aad` is integrity protected together with the protected header, but not encrypted.
+    /// It is carried in the `aad` member, see
+    /// [RFC 7516 section 7.2.2](https://tools.ietf.org/html/rfc7516#section-7.2.2).
+    pub fn encrypt_flattened<K: Serialize + DeserializeOwned>(
+        &self,
+        key: &jwk::JWK<K>,
+        options: &EncryptionOptions,
+        aad: Option<&[u8]>,
+    ) -> Result<String, Error> {
+        match *self {
+            Compact::Encrypted(_) => Err(Error::UnsupportedOperation),
+            Compact::Decrypted {
+                ref header,
+                ref payload,
+            } => {
+                let (header, encrypted_cek, encrypted_payload) =
+                    Self::encrypt_parts(header, payload, key, options, aad)?;
+
+                let raw = FlattenedRaw {
+                    protected_header: header.to_bytes()?,
+                    encrypted_key: encrypted_cek.encrypted,
+                    nonce: encrypted_payload.nonce,
+                    ciphertext: encrypted_payload.encrypted,
+                    tag: encrypted_payload.tag,
+                    aad: aad.map(<[u8]>::to_vec),
+                    unprotected_header: (),
+                    recipient_header: (),
+                    recipients: (),
+                };
+
+                Ok(serde_json::to_string(&raw)?)
             }
-            Compact::Decrypted { .. } => Err(Error::UnsupportedOperation),
         }
     }
 
+    /// Decrypt a JWE in the Flattened JWE JSON Serialization.
+    /// Returns the decrypted JWE together with the `aad` member, if present.
+    ///
+    /// Provide the expected algorithms to mitigate an attacker modifying the fields
+    pub fn decrypt_flattened<K: Serialize + DeserializeOwned>(
+        data: &[u8],
+        key: &jwk::JWK<K>,
+        cek_alg: KeyManagementAlgorithm,
+        enc_alg: ContentEncryptionAlgorithm,
+    ) -> Result<(Self, Option<Vec<u8>>), Error> {
+        let raw: FlattenedRaw = serde_json::from_slice(data)?;
+        let header: Header<H> = serde_json::from_slice(&raw.protected_header)?;
+
+        let encrypted_payload = EncryptionResult {
+            additional_data: additional_data(&raw.protected_header, raw.aad.as_deref()),
+            nonce: raw.nonce,
+            encrypted: raw.ciphertext,
+            tag: raw.tag,
+        };
+
+        let decrypted = Self::decrypt_parts(
+            header,
+            &raw.encrypted_key,
+            &encrypted_payload,
+            key,
+            cek_alg,
+            enc_alg,
+        )?;
+        Ok((decrypted, raw.aad))
+    }
+
     /// Convenience method to get a reference to the encrypted payload
     pub fn encrypted(&self) -> Result<&crate::biscuit::Compact, Error> {
         match *self {
@@ -596,6 +776,17 @@ where
     }
 }
 
+/// Additional Authenticated Data of RFC 7516 Section 5.1 step 14:
+/// `BASE64URL(protected header)`, followed by `.BASE64URL(aad)` when the JSON serialization carries `aad`
+fn additional_data(protected_header: &[u8], aad: Option<&[u8]>) -> Vec<u8> {
+    let mut additional_data = BASE64URL_NOPAD.encode(protected_header).into_bytes();
+    if let Some(aad) = aad {
+        additional_data.push(b'.');
+        additional_data.extend_from_slice(BASE64URL_NOPAD.encode(aad).as_bytes());
+    }
+    additional_data
+}
+
 /// Convenience implementation for a Compact that contains a `ClaimsSet`
 impl<P, H> Compact<crate::biscuit::ClaimsSet<P>, H>
 where
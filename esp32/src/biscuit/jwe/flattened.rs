@@ -0,0 +1,143 @@
+//! Flattened JWE: see RFC 7516 section 7.2.2
+//! The flattened serialization is JSON (unlike the compact serialization)
+//! and can carry additional authenticated data in the `aad` member,
+//! which is integrity protected but not encrypted.
+//!
+//! The RFC specifies shared and per-recipient unprotected headers as well,
+//! but this implementation doesn't support them.
+
+use crate::biscuit::jws::util::deserialize_reject;
+use crate::biscuit::serde_custom;
+
+use serde::{Deserialize, Serialize};
+
+/// This is for serialization, and deserialisation before decryption,
+/// not exposed externally
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FlattenedRaw {
+    #[serde(rename = "protected", with = "serde_custom::byte_sequence")]
+    pub(crate) protected_header: Vec<u8>,
+
+    #[serde(default, with = "serde_custom::byte_sequence")]
+    pub(crate) encrypted_key: Vec<u8>,
+
+    #[serde(rename = "iv", with = "serde_custom::byte_sequence")]
+    pub(crate) nonce: Vec<u8>,
+
+    #[serde(with = "serde_custom::byte_sequence")]
+    pub(crate) ciphertext: Vec<u8>,
+
+    #[serde(with = "serde_custom::byte_sequence")]
+    pub(crate) tag: Vec<u8>,
+
+    #[serde(
+        default,
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) aad: Option<Vec<u8>>,
+
+    // These fields must be understood and rejected
+    // (unlike unknown fields, which must be ignored)
+    // Headers unprotected by the encryption are rejected
+    #[serde(
+        rename = "unprotected",
+        default,
+        deserialize_with = "deserialize_reject",
+        skip_serializing
+    )]
+    #[allow(dead_code)]
+    pub(crate) unprotected_header: (),
+
+    #[serde(
+        rename = "header",
+        default,
+        deserialize_with = "deserialize_reject",
+        skip_serializing
+    )]
+    #[allow(dead_code)]
+    pub(crate) recipient_header: (),
+
+    // This member indicates the general serialization with multiple recipients
+    #[serde(default, deserialize_with = "deserialize_reject", skip_serializing)]
+    #[allow(dead_code)]
+    pub(crate) recipients: (),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::biscuit::jwa::{
+        ContentEncryptionAlgorithm, EncryptionOptions, KeyManagementAlgorithm,
+    };
+    use crate::biscuit::jwe::{Compact, RegisteredHeader};
+    use crate::biscuit::jwk::JWK;
+    use crate::biscuit::Empty;
+
+    fn encrypted(aad: Option<&[u8]>) -> (JWK<Empty>, String) {
+        let key: JWK<Empty> = JWK::new_octet_key(&[0; 256 / 8], Default::default());
+        let jwe = Compact::<Vec<u8>, Empty>::new_decrypted(
+            From::from(RegisteredHeader {
+                cek_algorithm: KeyManagementAlgorithm::A256GCMKW,
+                enc_algorithm: ContentEncryptionAlgorithm::A256GCM,
+                ..Default::default()
+            }),
+            b"voucher".to_vec(),
+        );
+        let options = EncryptionOptions::AES_GCM {
+            nonce: vec![0; 96 / 8],
+        };
+
+        let token = not_err!(jwe.encrypt_flattened(&key, &options, aad));
+        (key, token)
+    }
+
+    #[test]
+    fn flattened_jwe_round_trip_with_aad() {
+        let (key, token) = encrypted(Some(b"pledge serial-number"));
+
+        let value: serde_json::Value = not_err!(serde_json::from_str(&token));
+        assert_eq!(value["aad"], "cGxlZGdlIHNlcmlhbC1udW1iZXI");
+
+        let (decrypted, aad) = not_err!(Compact::<Vec<u8>, Empty>::decrypt_flattened(
+            token.as_bytes(),
+            &key,
+            KeyManagementAlgorithm::A256GCMKW,
+            ContentEncryptionAlgorithm::A256GCM,
+        ));
+        assert_eq!(not_err!(decrypted.payload()), b"voucher");
+        assert_eq!(aad.as_deref(), Some(&b"pledge serial-number"[..]));
+    }
+
+    #[test]
+    fn flattened_jwe_without_aad() {
+        let (key, token) = encrypted(None);
+
+        let value: serde_json::Value = not_err!(serde_json::from_str(&token));
+        assert!(value.get("aad").is_none());
+
+        let (_, aad) = not_err!(Compact::<Vec<u8>, Empty>::decrypt_flattened(
+            token.as_bytes(),
+            &key,
+            KeyManagementAlgorithm::A256GCMKW,
+            ContentEncryptionAlgorithm::A256GCM,
+        ));
+        assert_eq!(aad, None);
+    }
+
+    #[test]
+    fn flattened_jwe_rejects_modified_aad() {
+        let (key, token) = encrypted(Some(b"pledge serial-number"));
+
+        let mut value: serde_json::Value = not_err!(serde_json::from_str(&token));
+        value["aad"] = "b3RoZXIgcGxlZGdl".into();
+        let token = not_err!(serde_json::to_string(&value));
+
+        assert!(Compact::<Vec<u8>, Empty>::decrypt_flattened(
+            token.as_bytes(),
+            &key,
+            KeyManagementAlgorithm::A256GCMKW,
+            ContentEncryptionAlgorithm::A256GCM,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! General JWE: see RFC 7516 section 7.2.1
+//! The general serialization is JSON and encrypts the content once for any
+//! number of recipients. The Content Encryption Key is shared and wrapped
+//! for every recipient on its own, the `alg` and `kid` of a recipient are
+//! carried in its per-recipient unprotected header.
+//!
+//! Only key management algorithms that wrap or encrypt a random CEK can be
+//! used, `dir` and `ECDH-ES` determine the CEK from a single recipient key.
+//! The shared unprotected header is not supported.
+
+use super::{additional_data, CekAlgorithmHeader, Compact, Header};
+use crate::biscuit::errors::{DecodeError, Error, ValidationError};
+use crate::biscuit::jwa::{
+    ContentEncryptionAlgorithm, EncryptionOptions, EncryptionResult, KeyManagementAlgorithm,
+    KeyManagementAlgorithmType,
+};
+use crate::biscuit::jws::util::deserialize_reject;
+use crate::biscuit::{jwk, serde_custom, CompactPart};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// This is for serialization, and deserialisation before decryption,
+/// not exposed externally
+#[derive(Serialize, Deserialize)]
+pub(crate) struct GeneralRaw {
+    #[serde(rename = "protected", with = "serde_custom::byte_sequence")]
+    pub(crate) protected_header: Vec<u8>,
+
+    pub(crate) recipients: Vec<RecipientRaw>,
+
+    #[serde(rename = "iv", with = "serde_custom::byte_sequence")]
+    pub(crate) nonce: Vec<u8>,
+
+    #[serde(with = "serde_custom::byte_sequence")]
+    pub(crate) ciphertext: Vec<u8>,
+
+    #[serde(with = "serde_custom::byte_sequence")]
+    pub(crate) tag: Vec<u8>,
+
+    #[serde(
+        default,
+        with = "serde_custom::option_byte_sequence",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) aad: Option<Vec<u8>>,
+
+    // Headers shared by all recipients but unprotected by the encryption are rejected
+    #[serde(
+        rename = "unprotected",
+        default,
+        deserialize_with = "deserialize_reject",
+        skip_serializing
+    )]
+    #[allow(dead_code)]
+    pub(crate) unprotected_header: (),
+}
+
+/// One entry of the `recipients` member
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RecipientRaw {
+    #[serde(default)]
+    pub(crate) header: Map<String, Value>,
+
+    #[serde(default, with = "serde_custom::byte_sequence")]
+    pub(crate) encrypted_key: Vec<u8>,
+}
+
+/// Per-recipient unprotected header
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct RecipientHeader {
+    /// Algorithm the CEK is wrapped with for this recipient
+    #[serde(rename = "alg")]
+    pub cek_algorithm: KeyManagementAlgorithm,
+
+    /// Key ID of the recipient key, used to find the entry of a recipient when decrypting
+    #[serde(rename = "kid", skip_serializing_if = "Option::is_none", default)]
+    pub key_id: Option<String>,
+
+    /// Key management algorithm specific headers, filled in when encrypting
+    #[serde(flatten)]
+    pub cek_algorithm_header: CekAlgorithmHeader,
+}
+
+/// A recipient of [`Compact::encrypt_general`]
+#[derive(Debug)]
+pub struct Recipient<'a, K> {
+    /// Header of the recipient, `kid` should be set for [`Compact::decrypt_general`]
+    pub header: RecipientHeader,
+    /// Key the CEK is wrapped with
+    pub key: &'a jwk::JWK<K>,
+    /// Options to wrap the CEK, e.g. the nonce for `A256GCMKW`
+    pub options: EncryptionOptions,
+}
+
+impl<T, H> Compact<T, H>
+where
+    T: CompactPart,
+    H: Serialize + DeserializeOwned + Clone,
+{
+    /// Encrypt a Decrypted JWE into the General JWE JSON Serialization, readable by each of `recipients`.
+    ///
+    /// The header of the JWE becomes the shared protected header, without its `alg`, which is taken from
+    /// each recipient instead. `aad` is integrity protected but not encrypted, as in
+    /// [`Compact::encrypt_flattened`]. See
+    /// [RFC 7516 section 7.2.1](https://tools.ietf.org/html/rfc7516#section-7.2.1).
+    pub fn encrypt_general<K: Serialize + DeserializeOwned>(
+        &self,
+        recipients: &[Recipient<'_, K>],
+        aad: Option<&[u8]>,
+    ) -> Result<String, Error> {
+        let Compact::Decrypted { header, payload } = self else {
+            return Err(Error::UnsupportedOperation);
+        };
+        let [first, ..] = recipients else {
+            return Err(Error::UnsupportedOperation);
+        };
+        if recipients.iter().any(|recipient| {
+            !matches!(
+                recipient.header.cek_algorithm.algorithm_type(),
+                KeyManagementAlgorithmType::SymmetricKeyWrapping
+                    | KeyManagementAlgorithmType::AsymmetricKeyEncryption
+            )
+        }) {
+            Err(Error::UnsupportedOperation)?
+        }
+        // Compression is not supported at the moment
+        if header.registered.compression_algorithm.is_some() {
+            Err(Error::UnsupportedOperation)?
+        }
+
+        let mut protected_header = to_object(header)?;
+        protected_header.remove("alg");
+
+        // RFC 7516 Section 5.1 steps 1 to 8, once per recipient with the same random CEK
+        let enc_algorithm = header.registered.enc_algorithm;
+        let cek = first.header.cek_algorithm.cek(enc_algorithm, first.key)?;
+        let recipients = recipients
+            .iter()
+            .map(|recipient| {
+                let encrypted_cek = recipient.header.cek_algorithm.wrap_key(
+                    cek.algorithm.octet_key()?,
+                    recipient.key,
+                    &recipient.options,
+                )?;
+
+                let mut header = recipient.header.clone();
+                if !encrypted_cek.nonce.is_empty() {
+                    header.cek_algorithm_header.nonce = Some(encrypted_cek.nonce);
+                }
+                if !encrypted_cek.tag.is_empty() {
+                    header.cek_algorithm_header.tag = Some(encrypted_cek.tag);
+                }
+                let header = to_object(&header)?;
+                check_disjoint(&protected_header, &header)?;
+
+                Ok(RecipientRaw {
+                    header,
+                    encrypted_key: encrypted_cek.encrypted,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Steps 9 to 15 as for a single recipient
+        let protected_header = serde_json::to_vec(&protected_header)?;
+        let encrypted_payload = enc_algorithm.encrypt(
+            &payload.to_bytes()?,
+            &additional_data(&protected_header, aad),
+            &cek,
+            &enc_algorithm.random_encryption_options()?,
+        )?;
+
+        let raw = GeneralRaw {
+            protected_header,
+            recipients,
+            nonce: encrypted_payload.nonce,
+            ciphertext: encrypted_payload.encrypted,
+            tag: encrypted_payload.tag,
+            aad: aad.map(<[u8]>::to_vec),
+            unprotected_header: (),
+        };
+        Ok(serde_json::to_string(&raw)?)
+    }
+
+    /// Decrypt a JWE in the General JWE JSON Serialization with the key of the recipient whose header
+    /// carries `key_id` as `kid`. Returns the decrypted JWE together with the `aad` member, if present.
+    ///
+    /// The header of the decrypted JWE combines the protected header with the header of the recipient.
+    /// Provide the expected algorithms to mitigate an attacker modifying the fields
+    pub fn decrypt_general<K: Serialize + DeserializeOwned>(
+        data: &[u8],
+        key: &jwk::JWK<K>,
+        key_id: &str,
+        cek_alg: KeyManagementAlgorithm,
+        enc_alg: ContentEncryptionAlgorithm,
+    ) -> Result<(Self, Option<Vec<u8>>), Error> {
+        let raw: GeneralRaw = serde_json::from_slice(data)?;
+        let recipient = raw
+            .recipients
+            .iter()
+            .find(|recipient| recipient.header.get("kid").and_then(Value::as_str) == Some(key_id))
+            .ok_or(ValidationError::KeyNotFound)?;
+
+        let mut header: Map<String, Value> = serde_json::from_slice(&raw.protected_header)?;
+        check_disjoint(&header, &recipient.header)?;
+        header.extend(recipient.header.clone());
+        let header: Header<H> = serde_json::from_value(Value::Object(header))?;
+
+        let encrypted_payload = EncryptionResult {
+            additional_data: additional_data(&raw.protected_header, raw.aad.as_deref()),
+            nonce: raw.nonce,
+            encrypted: raw.ciphertext,
+            tag: raw.tag,
+        };
+
+        let decrypted = Self::decrypt_parts(
+            header,
+            &recipient.encrypted_key,
+            &encrypted_payload,
+            key,
+            cek_alg,
+            enc_alg,
+        )?;
+        Ok((decrypted, raw.aad))
+    }
+}
+
+fn to_object<S: Serialize>(value: &S) -> Result<Map<String, Value>, Error> {
+    match serde_json::to_value(value)? {
+        Value::Object(object) => Ok(object),
+        _ => Err(DecodeError::InvalidToken)?,
+    }
+}
+
+/// The header parameter names of the protected and the recipient header must be disjoint
+fn check_disjoint(
+    protected_header: &Map<String, Value>,
+    recipient_header: &Map<String, Value>,
+) -> Result<(), Error> {
+    if recipient_header
+        .keys()
+        .any(|name| protected_header.contains_key(name))
+    {
+        Err(DecodeError::InvalidToken)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biscuit::jwe::RegisteredHeader;
+    use crate::biscuit::jwk::JWK;
+    use crate::biscuit::Empty;
+
+    fn recipient<'a>(key: &'a JWK<Empty>, key_id: &str) -> Recipient<'a, Empty> {
+        Recipient {
+            header: RecipientHeader {
+                cek_algorithm: KeyManagementAlgorithm::A256GCMKW,
+                key_id: Some(key_id.to_string()),
+                ..Default::default()
+            },
+            key,
+            options: EncryptionOptions::AES_GCM {
+                nonce: vec![0; 96 / 8],
+            },
+        }
+    }
+
+    fn jwe(key_id: Option<&str>) -> Compact<Vec<u8>, Empty> {
+        Compact::new_decrypted(
+            From::from(RegisteredHeader {
+                cek_algorithm: KeyManagementAlgorithm::A256GCMKW,
+                enc_algorithm: ContentEncryptionAlgorithm::A256GCM,
+                key_id: key_id.map(str::to_string),
+                ..Default::default()
+            }),
+            b"voucher".to_vec(),
+        )
+    }
+
+    fn decrypt(
+        token: &str,
+        key: &JWK<Empty>,
+        key_id: &str,
+    ) -> Result<(Compact<Vec<u8>, Empty>, Option<Vec<u8>>), Error> {
+        Compact::<Vec<u8>, Empty>::decrypt_general(
+            token.as_bytes(),
+            key,
+            key_id,
+            KeyManagementAlgorithm::A256GCMKW,
+            ContentEncryptionAlgorithm::A256GCM,
+        )
+    }
+
+    #[test]
+    fn general_jwe_round_trip_for_each_recipient() {
+        let registrar: JWK<Empty> = JWK::new_octet_key(&[0; 256 / 8], Default::default());
+        let escrow: JWK<Empty> = JWK::new_octet_key(&[1; 256 / 8], Default::default());
+
+        let token = not_err!(jwe(None).encrypt_general(
+            &[recipient(&registrar, "registrar"), recipient(&escrow, "escrow")],
+            Some(b"pledge serial-number"),
+        ));
+
+        let value: serde_json::Value = not_err!(serde_json::from_str(&token));
+        assert_eq!(value["recipients"].as_array().unwrap().len(), 2);
+        assert_eq!(value["recipients"][1]["header"]["kid"], "escrow");
+        assert_eq!(value["recipients"][1]["header"]["alg"], "A256GCMKW");
+
+        for (key, key_id) in [(&registrar, "registrar"), (&escrow, "escrow")] {
+            let (decrypted, aad) = not_err!(decrypt(&token, key, key_id));
+            assert_eq!(not_err!(decrypted.payload()), b"voucher");
+            assert_eq!(not_err!(decrypted.header()).registered.key_id.as_deref(), Some(key_id));
+            assert_eq!(aad.as_deref(), Some(&b"pledge serial-number"[..]));
+        }
+
+        // the entry is picked by kid, the key of another recipient does not unwrap it
+        assert!(decrypt(&token, &escrow, "registrar").is_err());
+        assert!(matches!(
+            decrypt(&token, &registrar, "unknown"),
+            Err(Error::ValidationError(ValidationError::KeyNotFound))
+        ));
+    }
+
+    #[test]
+    fn general_jwe_rejects_overlapping_headers() {
+        let key: JWK<Empty> = JWK::new_octet_key(&[0; 256 / 8], Default::default());
+        assert!(jwe(Some("shared"))
+            .encrypt_general(&[recipient(&key, "registrar")], None)
+            .is_err());
+
+        let token = not_err!(jwe(None).encrypt_general(&[recipient(&key, "registrar")], None));
+        let mut value: serde_json::Value = not_err!(serde_json::from_str(&token));
+        value["recipients"][0]["header"]["enc"] = "A128GCM".into();
+        let token = not_err!(serde_json::to_string(&value));
+        assert!(decrypt(&token, &key, "registrar").is_err());
+    }
+
+    #[test]
+    fn general_jwe_needs_key_wrapping() {
+        let key: JWK<Empty> = JWK::new_octet_key(&[0; 256 / 8], Default::default());
+        let mut direct = recipient(&key, "registrar");
+        direct.header.cek_algorithm = KeyManagementAlgorithm::DirectSymmetricKey;
+        direct.options = EncryptionOptions::None;
+
+        assert!(jwe(None).encrypt_general(&[direct], None).is_err());
+        assert!(jwe(None)
+            .encrypt_general::<Empty>(&[], None)
+            .is_err());
+    }
+}
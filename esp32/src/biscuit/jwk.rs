@@ -473,6 +473,36 @@ impl RSAKeyParameters {
             e: self.e.clone(),
         }
     }
+
+    /// Construct a `jws::Secret` RSA key pair for signing from the private key parameters.
+    /// Only two prime keys with the CRT parameters are supported.
+    pub fn jws_private_key_secret(&self) -> Result<jws::Secret, Error> {
+        let (Some(d), Some(p), Some(q), Some(dp), Some(dq), Some(qi), None) = (
+            &self.d,
+            &self.p,
+            &self.q,
+            &self.dp,
+            &self.dq,
+            &self.qi,
+            &self.other_primes_info,
+        ) else {
+            Err("Invalid key. A RSA private key with two primes and the CRT parameters is required".to_string())?
+        };
+
+        let key_pair = ring::signature::RsaKeyPair::from_components(&ring::rsa::KeyPairComponents {
+            public_key: ring::rsa::PublicKeyComponents {
+                n: self.n.to_bytes_be(),
+                e: self.e.to_bytes_be(),
+            },
+            d: d.to_bytes_be(),
+            p: p.to_bytes_be(),
+            q: q.to_bytes_be(),
+            dP: dp.to_bytes_be(),
+            dQ: dq.to_bytes_be(),
+            qInv: qi.to_bytes_be(),
+        })?;
+        Ok(jws::Secret::RsaKeyPair(std::sync::Arc::new(key_pair)))
+    }
 }
 
 /// The "oth" (other primes info) parameter contains an array of
@@ -540,6 +570,24 @@ pub struct OctetKeyPairParameters {
     pub d: Option<Vec<u8>>,
 }
 
+impl OctetKeyPairParameters {
+    /// Construct a `jws::Secret` Ed25519 public key for signature verification
+    pub fn jws_public_key_secret(&self) -> jws::Secret {
+        jws::Secret::PublicKey(self.x.clone())
+    }
+
+    /// Construct a `jws::Secret` Ed25519 key pair for signing from the private key parameters.
+    /// Only the Ed25519 curve is supported.
+    pub fn jws_private_key_secret(&self) -> Result<jws::Secret, Error> {
+        let (EllipticCurve::Curve25519, Some(d)) = (&self.curve, &self.d) else {
+            Err("Invalid key. An Ed25519 private key is required".to_string())?
+        };
+
+        let key_pair = ring::signature::Ed25519KeyPair::from_seed_and_public_key(d, &self.x)?;
+        Ok(jws::Secret::Ed25519KeyPair(std::sync::Arc::new(key_pair)))
+    }
+}
+
 /// Key type value for an Elliptic Curve Key.
 /// This single value enum is a workaround for Rust not supporting associated constants.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Default)]
@@ -9,11 +9,13 @@ pub(crate) mod util;
 mod signeable;
 
 pub use compact::Compact;
+pub use general::{General, GeneralSignature};
 pub use signeable::Signable;
 pub use signeable::SignedData;
 use crate::biscuit::errors::Error;
 use crate::biscuit::jwa::SignatureAlgorithm;
 use crate::biscuit::jwk;
+use crate::biscuit::x509;
 use crate::biscuit::{CompactJson, Empty};
 
 use num_bigint::BigUint;
@@ -78,6 +80,23 @@ pub enum Secret {
     /// let secret = Secret::ecdsa_keypair_from_file(biscuit::jwa::SignatureAlgorithm::ES256, "test/fixtures/ecdsa_private_key.p8");
     /// ```
     EcdsaKeyPair(Arc<signature::EcdsaKeyPair>),
+    /// An ECDSA key that can not be exported, e.g. one kept in a secure element
+    EcdsaSigner(Arc<dyn EcdsaSigner>),
+    /// An Ed25519 Key pair constructed from a PKCS8 DER encoded private key
+    ///
+    /// To generate a private key, use
+    ///
+    /// ```sh
+    /// openssl genpkey -algorithm ed25519 -outform DER -out ed25519_private_key.p8
+    /// ```
+    ///
+    /// # Examples
+    /// ```
+    /// use biscuit::jws::Secret;
+    ///
+    /// let secret = Secret::ed25519_keypair_from_file("test/fixtures/ed25519_private_key.p8");
+    /// ```
+    Ed25519KeyPair(Arc<signature::Ed25519KeyPair>),
     /// Bytes of a DER encoded RSA Public Key
     ///
     /// To generate the public key from your DER-encoded private key
@@ -167,6 +186,15 @@ pub enum Secret {
     },
 }
 
+/// Signs with an ECDSA private key held outside of ring
+pub trait EcdsaSigner: Send + Sync {
+    /// Signs `data` with `algorithm`, the signature is `r || s` in the fixed encoding of JWS
+    fn sign(&self, data: &[u8], algorithm: SignatureAlgorithm) -> Result<Vec<u8>, Error>;
+
+    /// The public key as uncompressed SEC1 point
+    fn public_key(&self) -> &[u8];
+}
+
 impl Secret {
     fn read_bytes(path: &str) -> Result<Vec<u8>, Error> {
         use std::fs::File;
@@ -207,12 +235,21 @@ impl Secret {
         let key_pair = signature::EcdsaKeyPair::from_pkcs8(
             ring_algorithm,
             der.as_slice(),
-            &ring::rand::SystemRandom::new(),
+            crate::biscuit::jwa::checked_rng()?,
         )?;
         Ok(Secret::EcdsaKeyPair(Arc::new(key_pair)))
     }
 
-    /// Convenience function to create a Public key from a DER encoded RSA or ECDSA public key
+    /// Convenience function to get the Ed25519 Keypair from a PKCS8-DER encoded private key.
+    /// See example in the [`Secret::Ed25519KeyPair`] variant documentation for usage.
+    pub fn ed25519_keypair_from_file(path: &str) -> Result<Self, Error> {
+        let der = Self::read_bytes(path)?;
+        // OpenSSL writes PKCS#8 v1 without the public key, which `from_pkcs8` rejects
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.as_slice())?;
+        Ok(Secret::Ed25519KeyPair(Arc::new(key_pair)))
+    }
+
+    /// Convenience function to create a Public key from a DER encoded RSA, ECDSA or Ed25519 public key
     /// See examples in the [`Secret::PublicKey`] variant documentation for usage.
     pub fn public_key_from_file(path: &str) -> Result<Self, Error> {
         let der = Self::read_bytes(path)?;
@@ -313,20 +350,25 @@ pub struct RegisteredHeader {
     #[serde(rename = "x5u", skip_serializing_if = "Option::is_none")]
     pub x509_url: Option<String>,
 
-    /// X.509 public key certificate chain. This is currently not implemented (correctly).
+    /// X.509 public key certificate chain, base64 (not base64url) encoded DER with the signer certificate first.
+    /// Set it with [`RegisteredHeader::with_x509_chain`] and verify it with [`x509::verify_header`].
     /// Serialized to `x5c`.
     /// Defined in [RFC7515#4.1.6](https://tools.ietf.org/html/rfc7515#section-4.1.6).
     #[serde(rename = "x5c", skip_serializing_if = "Option::is_none")]
     pub x509_chain: Option<Vec<String>>,
 
-    /// X.509 Certificate thumbprint. This is currently not implemented (correctly).
-    /// Also not implemented, is the SHA-256 thumbprint variant of this header.
+    /// SHA-1 thumbprint of the X.509 certificate of the signer. Only checked against `x5c`, never set by biscuit.
     /// Serialized to `x5t`.
     /// Defined in [RFC7515#4.1.7](https://tools.ietf.org/html/rfc7515#section-4.1.7).
-    // TODO: How to make sure the headers are mutually exclusive?
     #[serde(rename = "x5t", skip_serializing_if = "Option::is_none")]
     pub x509_fingerprint: Option<String>,
 
+    /// SHA-256 thumbprint of the X.509 certificate of the signer, checked against `x5c`.
+    /// Serialized to `x5t#S256`.
+    /// Defined in [RFC7515#4.1.8](https://tools.ietf.org/html/rfc7515#section-4.1.8).
+    #[serde(rename = "x5t#S256", skip_serializing_if = "Option::is_none")]
+    pub x509_fingerprint_sha256: Option<String>,
+
     /// List of critical extended headers.
     /// This is currently not implemented (correctly).
     /// Serialized to `crit`.
@@ -347,11 +389,26 @@ impl Default for RegisteredHeader {
             x509_url: None,
             x509_chain: None,
             x509_fingerprint: None,
+            x509_fingerprint_sha256: None,
             critical: None,
         }
     }
 }
 
+impl RegisteredHeader {
+    /// Sets `x5c` to `chain`, the DER encoded certificate of the signer followed by the certificates certifying it,
+    /// and `x5t#S256` to the thumbprint of the signer certificate
+    pub fn with_x509_chain<C: AsRef<[u8]>>(self, chain: &[C]) -> Self {
+        Self {
+            x509_chain: Some(x509::encode_chain(chain)),
+            x509_fingerprint_sha256: chain
+                .first()
+                .map(|signer| x509::thumbprint_sha256(signer.as_ref())),
+            ..self
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::RegisteredHeader;
@@ -5,7 +5,7 @@ use std::str;
 use crate::biscuit::errors::{DecodeError, Error, ValidationError};
 use crate::biscuit::jwa::{Algorithm, SignatureAlgorithm};
 use crate::biscuit::jwk::{AlgorithmParameters, JWKSet};
-use crate::biscuit::CompactPart;
+use crate::biscuit::{CompactPart, DecodeDiagnostic, DecodeOptions};
 
 use super::{Header, Secret};
 
@@ -101,7 +101,22 @@ where
 
     /// Decode a token into the JWT struct and verify its signature using the concrete Secret
     /// If the token or its signature is invalid, it will return an error
+    ///
+    /// Tokens that are out of spec are rejected, see [`DecodeOptions::strict`]
     pub fn decode(&self, secret: &Secret, algorithm: SignatureAlgorithm) -> Result<Self, Error> {
+        let (decoded, _) =
+            self.decode_with_options(secret, algorithm, &DecodeOptions::default())?;
+        Ok(decoded)
+    }
+
+    /// Decode a token like [`Compact::decode`], checking it against the JOSE specifications as set in `options`.
+    /// Returns the decoded token together with the deviations `options` tolerated
+    pub fn decode_with_options(
+        &self,
+        secret: &Secret,
+        algorithm: SignatureAlgorithm,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeDiagnostic>), Error> {
         match *self {
             Compact::Decoded { .. } => Err(Error::UnsupportedOperation),
             Compact::Encoded(ref encoded) => {
@@ -112,20 +127,28 @@ where
                     })?
                 }
 
-                let signature: Vec<u8> = encoded.part(2)?;
+                // only unsecured tokens come without a signature
+                let required: &[usize] = match algorithm {
+                    SignatureAlgorithm::None => &[0],
+                    _ => &[0, 2],
+                };
+                let (normalized, diagnostics) = encoded.inspect(options, required)?;
+
+                // the signature covers the parts as received
+                let signature: Vec<u8> = normalized.part(2)?;
                 let payload = &encoded.parts[0..2].join(".");
 
                 algorithm
                     .verify(signature.as_ref(), payload.as_ref(), secret)
                     .map_err(|_| ValidationError::InvalidSignature)?;
 
-                let header: Header<H> = encoded.part(0)?;
+                let header: Header<H> = normalized.part(0)?;
                 if header.registered.algorithm != algorithm {
                     Err(ValidationError::WrongAlgorithmHeader)?;
                 }
-                let decoded_claims: T = encoded.part(1)?;
+                let decoded_claims: T = normalized.part(1)?;
 
-                Ok(Self::new_decoded(header, decoded_claims))
+                Ok((Self::new_decoded(header, decoded_claims), diagnostics))
             }
         }
     }
@@ -1,20 +1,22 @@
-//! General JWS signatures: see RFC 7515 section 7.2.2
+//! General JWS signatures: see RFC 7515 section 7.2.1
 //! General signatures are JSON (unlike compact signatures),
-//! and support a single signature protecting a set of headers and a
-//! payload.
+//! and support several signatures over the same payload, each
+//! protecting its own set of headers.
 //!
-//! The RFC specifies unprotected headers as well, but this implementation
-//! doesn't support them.
+//! Every signature may carry an unprotected header as well, e.g. to
+//! tell which signer it belongs to.
 
 use super::util::{serialize_header, signing_input};
-use super::{Header, RegisteredHeader, Secret};
-use crate::biscuit::errors::{Error, ValidationError};
+use super::{Header, RegisteredHeader, Secret, SignedData};
+use crate::biscuit::errors::{DecodeError, Error, ValidationError};
 use crate::biscuit::jwa::SignatureAlgorithm;
 use crate::biscuit::serde_custom;
-use data_encoding::BASE64URL_NOPAD;
+use crate::biscuit::x509::{self, Certificate, TrustStore};
 
-use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
-use super::util::deserialize_reject;
+use chrono::{DateTime, Utc};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 /// This is for serialization, and deserialisation when the signature
 /// hasn't been verified, not exposed externally
@@ -47,13 +49,377 @@ pub(crate) struct Signature {
     #[serde(with = "serde_custom::byte_sequence")]
     pub(crate) signature: Vec<u8>,
 
-    // Headers unprotected by the signature are rejected
-    #[serde(
-        rename = "header",
-        default,
-        deserialize_with = "deserialize_reject",
-        skip_serializing
-    )]
-    #[allow(dead_code)]
-    pub(crate) unprotected_header: (),
-}
\ No newline at end of file
+    #[serde(rename = "header", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) unprotected_header: Option<Map<String, Value>>,
+}
+
+/// Flattened form of a single [`Signature`], see RFC 7515 section 7.2.2
+#[derive(Serialize, Deserialize)]
+struct FlattenedSignatureRaw {
+    #[serde(with = "serde_custom::byte_sequence")]
+    payload: Vec<u8>,
+
+    #[serde(flatten)]
+    signature: Signature,
+}
+
+/// A payload with any number of signatures, in General JWS JSON Serialization
+///
+/// BRSKI-PRM artifacts may be signed by more than one party, e.g. by the
+/// pledge and by the registrar-agent relaying them. Each signature protects
+/// its own header; signatures are checked one at a time as the signers
+/// usually do not share a key.
+///
+/// # Examples
+/// ```
+/// use biscuit::jwa::SignatureAlgorithm;
+/// use biscuit::jws::{General, Header, RegisteredHeader, Secret};
+/// use biscuit::Empty;
+///
+/// let header = Header::<Empty>::from(RegisteredHeader {
+///     algorithm: SignatureAlgorithm::HS256,
+///     ..Default::default()
+/// });
+/// let mut general = General::new(b"These bytes cannot be altered".to_vec());
+/// general.sign(header, None, &Secret::bytes_from_str("secret"))?;
+/// let serialized = general.serialize();
+/// # Ok::<(), biscuit::errors::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct General {
+    payload: Vec<u8>,
+    signatures: Vec<GeneralSignature>,
+}
+
+/// One of the signatures of a [`General`] JWS
+#[derive(Debug, Clone)]
+pub struct GeneralSignature {
+    // as in Signable, both are kept so the signed bytes stay stable
+    protected_header_registered: RegisteredHeader,
+    protected_header_serialized: Vec<u8>,
+    unprotected_header: Option<Map<String, Value>>,
+    signature: Vec<u8>,
+}
+
+impl GeneralSignature {
+    fn from_raw(raw: Signature) -> Result<Self, Error> {
+        let protected_header_registered: RegisteredHeader =
+            serde_json::from_slice(&raw.protected_header)?;
+        check_disjoint(&raw.protected_header, raw.unprotected_header.as_ref())?;
+        Ok(Self {
+            protected_header_registered,
+            protected_header_serialized: raw.protected_header,
+            unprotected_header: raw.unprotected_header,
+            signature: raw.signature,
+        })
+    }
+
+    fn to_raw(&self) -> Signature {
+        Signature {
+            protected_header: self.protected_header_serialized.clone(),
+            signature: self.signature.clone(),
+            unprotected_header: self.unprotected_header.clone(),
+        }
+    }
+
+    /// Return a reference to the registered (known to biscuit)
+    /// protected headers
+    pub fn protected_header_registered(&self) -> &RegisteredHeader {
+        &self.protected_header_registered
+    }
+
+    /// Return a reference to protected headers as they were serialized
+    pub fn protected_header_serialized(&self) -> &[u8] {
+        &self.protected_header_serialized
+    }
+
+    /// Deserialize protected headers
+    ///
+    /// This allows access to protected headers beyond those
+    /// that are recognized with RegisteredHeader
+    pub fn deserialize_protected_header<H: DeserializeOwned>(
+        &self,
+    ) -> serde_json::Result<Header<H>> {
+        serde_json::from_slice(&self.protected_header_serialized)
+    }
+
+    /// Header parameters not protected by the signature
+    pub fn unprotected_header(&self) -> Option<&Map<String, Value>> {
+        self.unprotected_header.as_ref()
+    }
+
+    /// Return a reference to the signature bytes
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+}
+
+impl General {
+    /// A payload without signatures yet, see [`General::sign`]
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            payload,
+            signatures: vec![],
+        }
+    }
+
+    /// Adds a signature over the payload and `header`
+    ///
+    /// `unprotected_header` is serialized alongside the signature but not
+    /// covered by it, its parameters must not repeat those of `header`.
+    pub fn sign<H: Serialize>(
+        &mut self,
+        header: Header<H>,
+        unprotected_header: Option<Map<String, Value>>,
+        secret: &Secret,
+    ) -> Result<&GeneralSignature, Error> {
+        let protected_header_serialized = serialize_header(&header)?;
+        check_disjoint(&protected_header_serialized, unprotected_header.as_ref())?;
+        let signature = header.registered.algorithm.sign(
+            &signing_input(&protected_header_serialized, &self.payload),
+            secret,
+        )?;
+
+        self.signatures.push(GeneralSignature {
+            protected_header_registered: header.registered,
+            protected_header_serialized,
+            unprotected_header,
+            signature,
+        });
+        Ok(self.signatures.last().unwrap())
+    }
+
+    /// Parses a General or Flattened JWS JSON Serialization without
+    /// verifying any of the signatures
+    pub fn deserialize(data: &[u8]) -> Result<Self, Error> {
+        let value: Value = serde_json::from_slice(data)?;
+        let is_general = value
+            .as_object()
+            .ok_or(DecodeError::InvalidToken)?
+            .contains_key("signatures");
+
+        let (payload, signatures) = if is_general {
+            let raw: GeneralRaw = serde_json::from_value(value)?;
+            (raw.payload, raw.signatures)
+        } else {
+            let raw: FlattenedSignatureRaw = serde_json::from_value(value)?;
+            (raw.payload, vec![raw.signature])
+        };
+        if signatures.is_empty() {
+            Err(DecodeError::InvalidToken)?;
+        }
+
+        Ok(Self {
+            payload,
+            signatures: signatures
+                .into_iter()
+                .map(GeneralSignature::from_raw)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Serialize using General JWS JSON Serialization
+    ///
+    /// See [RFC 7515 section 7.2.1](https://tools.ietf.org/html/rfc7515#section-7.2.1)
+    pub fn serialize(&self) -> String {
+        let s = GeneralRaw {
+            payload: self.payload.clone(),
+            signatures: self.signatures.iter().map(GeneralSignature::to_raw).collect(),
+        };
+        // This shouldn't fail, because General strucs are
+        // always representable in JSON
+        serde_json::to_string(&s).expect("Failed to serialize GeneralRaw to JSON")
+    }
+
+    /// Serialize using Flattened JWS JSON Serialization, which can only
+    /// carry a single signature
+    ///
+    /// See [RFC 7515 section 7.2.2](https://tools.ietf.org/html/rfc7515#section-7.2.2)
+    pub fn serialize_flattened(&self) -> Result<String, Error> {
+        let [signature] = self.signatures.as_slice() else {
+            return Err(Error::UnsupportedOperation);
+        };
+        let s = FlattenedSignatureRaw {
+            payload: self.payload.clone(),
+            signature: signature.to_raw(),
+        };
+        Ok(serde_json::to_string(&s)?)
+    }
+
+    /// Verify the signature at `index`
+    pub fn verify(
+        &self,
+        index: usize,
+        secret: &Secret,
+        algorithm: SignatureAlgorithm,
+    ) -> Result<&GeneralSignature, Error> {
+        let signature = self
+            .signatures
+            .get(index)
+            .ok_or(ValidationError::InvalidSignature)?;
+        if signature.protected_header_registered.algorithm != algorithm {
+            Err(ValidationError::WrongAlgorithmHeader)?;
+        }
+        algorithm
+            .verify(
+                &signature.signature,
+                &signing_input(&signature.protected_header_serialized, &self.payload),
+                secret,
+            )
+            .map_err(|_| ValidationError::InvalidSignature)?;
+        Ok(signature)
+    }
+
+    /// Verify that any of the signatures was made with `secret`
+    pub fn verify_any(
+        &self,
+        secret: &Secret,
+        algorithm: SignatureAlgorithm,
+    ) -> Result<&GeneralSignature, Error> {
+        (0..self.signatures.len())
+            .find_map(|index| self.verify(index, secret, algorithm).ok())
+            .ok_or_else(|| ValidationError::InvalidSignature.into())
+    }
+
+    /// Verify the signature at `index` with the key of the signer certificate in its `x5c` header, after building
+    /// the chain of that header to one of the anchors of `trust_store` at `now`
+    pub fn verify_x509(
+        &self,
+        index: usize,
+        trust_store: &TrustStore,
+        now: DateTime<Utc>,
+    ) -> Result<(&GeneralSignature, Certificate), Error> {
+        let header = &self
+            .signatures
+            .get(index)
+            .ok_or(ValidationError::InvalidSignature)?
+            .protected_header_registered;
+        let signer = x509::verify_header(header, trust_store, now)?;
+        let secret = signer.public_key_secret(header.algorithm)?;
+        let signature = self.verify(index, &secret, header.algorithm)?;
+        Ok((signature, signer))
+    }
+
+    /// Return a reference to the payload bytes
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Deserialize a JSON payload
+    pub fn deserialize_json_payload<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.payload)
+    }
+
+    /// The signatures, none of which are verified unless this was built with [`General::sign`]
+    pub fn signatures(&self) -> &[GeneralSignature] {
+        &self.signatures
+    }
+}
+
+impl From<SignedData> for General {
+    fn from(signed: SignedData) -> Self {
+        let data = signed.data();
+        Self {
+            payload: data.payload().to_vec(),
+            signatures: vec![GeneralSignature {
+                protected_header_registered: data.protected_header_registered().clone(),
+                protected_header_serialized: data.protected_header_serialized().to_vec(),
+                unprotected_header: None,
+                signature: signed.signature().to_vec(),
+            }],
+        }
+    }
+}
+
+/// The protected and unprotected header parameters must be disjoint, see RFC 7515 section 7.2.1
+fn check_disjoint(
+    protected_header: &[u8],
+    unprotected_header: Option<&Map<String, Value>>,
+) -> Result<(), Error> {
+    let Some(unprotected_header) = unprotected_header else {
+        return Ok(());
+    };
+    let protected_header: Map<String, Value> = serde_json::from_slice(protected_header)?;
+    if unprotected_header
+        .keys()
+        .any(|name| protected_header.contains_key(name))
+    {
+        Err(DecodeError::InvalidToken)?;
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biscuit::Empty;
+
+    fn header(algorithm: SignatureAlgorithm) -> Header<Empty> {
+        Header::from(RegisteredHeader {
+            algorithm,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn multiple_signatures_round_trip() {
+        let pledge = Secret::bytes_from_str("pledge");
+        let agent = Secret::bytes_from_str("registrar-agent");
+        let mut kid = Map::new();
+        kid.insert("kid".to_string(), Value::from("agent"));
+
+        let mut general = General::new(b"{\"status\":true}".to_vec());
+        general.sign(header(SignatureAlgorithm::HS256), None, &pledge).unwrap();
+        general
+            .sign(header(SignatureAlgorithm::HS512), Some(kid.clone()), &agent)
+            .unwrap();
+        assert!(general.serialize_flattened().is_err());
+
+        let parsed = General::deserialize(general.serialize().as_bytes()).unwrap();
+        assert_eq!(parsed.payload(), general.payload());
+        assert_eq!(parsed.signatures().len(), 2);
+        assert_eq!(parsed.signatures()[1].unprotected_header(), Some(&kid));
+
+        parsed.verify(0, &pledge, SignatureAlgorithm::HS256).unwrap();
+        parsed.verify(1, &agent, SignatureAlgorithm::HS512).unwrap();
+        assert!(parsed.verify(0, &agent, SignatureAlgorithm::HS256).is_err());
+        assert!(parsed.verify(1, &agent, SignatureAlgorithm::HS256).is_err());
+        assert!(parsed.verify(2, &agent, SignatureAlgorithm::HS512).is_err());
+
+        let signature = parsed.verify_any(&agent, SignatureAlgorithm::HS512).unwrap();
+        assert_eq!(signature.signature(), parsed.signatures()[1].signature());
+    }
+
+    #[test]
+    fn flattened_round_trip() {
+        let secret = Secret::bytes_from_str("secret");
+        let mut kid = Map::new();
+        kid.insert("kid".to_string(), Value::from("pledge"));
+
+        let mut general = General::new(b"payload".to_vec());
+        general
+            .sign(header(SignatureAlgorithm::HS256), Some(kid.clone()), &secret)
+            .unwrap();
+        let flattened = general.serialize_flattened().unwrap();
+        assert!(!flattened.contains("signatures"));
+
+        let parsed = General::deserialize(flattened.as_bytes()).unwrap();
+        assert_eq!(parsed.signatures()[0].unprotected_header(), Some(&kid));
+        parsed.verify(0, &secret, SignatureAlgorithm::HS256).unwrap();
+    }
+
+    #[test]
+    fn overlapping_headers_are_rejected() {
+        let mut alg = Map::new();
+        alg.insert("alg".to_string(), Value::from("none"));
+
+        let mut general = General::new(b"payload".to_vec());
+        assert!(general
+            .sign(
+                header(SignatureAlgorithm::HS256),
+                Some(alg),
+                &Secret::bytes_from_str("secret")
+            )
+            .is_err());
+        assert!(General::deserialize(br#"{"payload":"cGF5bG9hZA","signatures":[]}"#).is_err());
+    }
+}
@@ -206,7 +206,7 @@ impl SignedData {
         let sig = Signature {
             protected_header,
             signature,
-            unprotected_header: (),
+            unprotected_header: None,
         };
 
         let s = GeneralRaw {
@@ -270,4 +270,9 @@ impl SignedData {
     pub fn data(&self) -> &Signable {
         &self.data
     }
+
+    /// Return a reference to the signature bytes
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
 }
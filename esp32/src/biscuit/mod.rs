@@ -86,21 +86,31 @@ pub use crate::biscuit::helpers::*;
 #[macro_use]
 mod test;
 
+#[cfg(test)]
+mod interop;
+
 #[macro_use]
 mod serde_custom;
 
 #[macro_use]
 mod macros;
 
+#[cfg(feature = "jwe")]
+mod aes_kw;
+#[cfg(feature = "jwe")]
+mod rsa_oaep;
+
 pub mod errors;
 pub mod jwa;
+#[cfg(feature = "jwe")]
 pub mod jwe;
 pub mod jwk;
 pub mod jws;
 
 pub mod digest;
+pub mod x509;
 
-use crate::biscuit::errors::{Error, ValidationError};
+use crate::biscuit::errors::{DecodeError, Error, ValidationError};
 
 /// A convenience type alias of the common "JWT" which is a secured/unsecured compact JWS.
 /// Type `T` is the type of the private claims, and type `H` is the type of private header fields
@@ -283,6 +293,7 @@ pub type JWT<T, H> = jws::Compact<ClaimsSet<T>, H>;
 /// nonce_counter = nonce_counter + 1u8;
 /// # }
 /// ```
+#[cfg(feature = "jwe")]
 pub type JWE<T, H, I> = jwe::Compact<JWT<T, H>, I>;
 
 /// An empty struct that derives Serialize and Deserialize. Can be used, for example, in places where a type
@@ -511,6 +522,127 @@ impl Compact {
             .ok_or_else(|| "Out of bounds".to_string())?;
         CompactPart::from_base64(part)
     }
+
+    /// Checks the parts against the JOSE specifications before they are decoded.
+    /// `required` are the indices of the parts that must not be empty, the first part is the protected header.
+    ///
+    /// Returns the parts normalized for decoding, that is without padding and with each header parameter once,
+    /// together with the deviations tolerated by `options`. If any deviation is not tolerated,
+    /// [`DecodeError::OutOfSpec`] lists all of them.
+    pub fn inspect(
+        &self,
+        options: &DecodeOptions,
+        required: &[usize],
+    ) -> Result<(Self, Vec<DecodeDiagnostic>), Error> {
+        // checked before anything is decoded, so oversized tokens do not allocate
+        let length = self.parts.iter().map(|part| part.len()).sum::<usize>() + self.parts.len().saturating_sub(1);
+        if length > options.max_token_length {
+            Err(DecodeError::TooLarge {
+                limit: options.max_token_length,
+                actual: length,
+            })?
+        }
+
+        let mut diagnostics = vec![];
+        let mut parts = Vec::with_capacity(self.parts.len());
+
+        for (index, part) in self.parts.iter().enumerate() {
+            if part.is_empty() && required.contains(&index) {
+                diagnostics.push(DecodeDiagnostic::EmptyPart { part: index });
+            }
+
+            let unpadded = part.trim_end_matches('=');
+            if unpadded.len() != part.len() {
+                diagnostics.push(DecodeDiagnostic::Base64Padding { part: index });
+            }
+            parts.push(Base64Url(unpadded.to_string()));
+        }
+
+        if let Some(encoded_header) = parts.first_mut().filter(|header| !header.is_empty()) {
+            let HeaderParameters(parameters) = CompactPart::from_base64(encoded_header)?;
+
+            // the last occurrence of a parameter wins, like in most JSON parsers
+            let mut header = serde_json::Map::new();
+            let mut duplicates = false;
+            for (name, value) in parameters {
+                if header.insert(name.clone(), value).is_some() {
+                    duplicates = true;
+                    let duplicate = DecodeDiagnostic::DuplicateHeaderParameter(name);
+                    if !diagnostics.contains(&duplicate) {
+                        diagnostics.push(duplicate);
+                    }
+                }
+            }
+
+            let critical = header
+                .get("crit")
+                .and_then(serde_json::Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(serde_json::Value::as_str);
+            for name in critical {
+                if !options
+                    .understood_critical_headers
+                    .iter()
+                    .any(|understood| understood == name)
+                {
+                    diagnostics.push(DecodeDiagnostic::UnknownCriticalHeader(name.to_string()));
+                }
+            }
+
+            if duplicates {
+                let header = serde_json::to_vec(&header)?;
+                *encoded_header = Base64Url(BASE64URL_NOPAD.encode(&header));
+            }
+        }
+
+        if diagnostics
+            .iter()
+            .any(|diagnostic| options.strictness(diagnostic) == Strictness::Strict)
+        {
+            return Err(DecodeError::OutOfSpec(diagnostics).into());
+        }
+
+        Ok((Self { parts }, diagnostics))
+    }
+}
+
+/// Parameters of a JSON header in their order of appearance, including duplicates
+struct HeaderParameters(Vec<(String, serde_json::Value)>);
+
+impl CompactPart for HeaderParameters {
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Err(Error::UnsupportedOperation)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        struct HeaderParametersVisitor;
+
+        impl<'de> de::Visitor<'de> for HeaderParametersVisitor {
+            type Value = HeaderParameters;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(formatter, "a JSON object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                let mut parameters = vec![];
+                while let Some(parameter) = map.next_entry()? {
+                    parameters.push(parameter);
+                }
+                Ok(HeaderParameters(parameters))
+            }
+        }
+
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        Ok(de::Deserializer::deserialize_map(
+            &mut deserializer,
+            HeaderParametersVisitor,
+        )?)
+    }
 }
 
 impl Default for Compact {
@@ -0,0 +1,152 @@
+//! RSAES-OAEP as defined in [RFC 8017 section 7.1](https://tools.ietf.org/html/rfc8017#section-7.1), used by
+//! the `RSA-OAEP` and `RSA-OAEP-256` key management algorithms.
+//!
+//! `ring` only implements RSA signatures, so OAEP is left to the `rsa` crate. Decryption blinds the private key
+//! operation and checks its result against the public key before the padding is removed, so a faulty CRT
+//! computation does not leak a factor of the modulus. All decryption failures are reported as the same error.
+
+use num_bigint::BigUint;
+use rand::rngs::OsRng;
+use ring::digest;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+
+use crate::biscuit::errors::Error;
+use crate::biscuit::jwk::RSAKeyParameters;
+
+/// RFC 7518 Section 4.3 requires keys of at least 2048 bits
+const MIN_MODULUS_BITS: u64 = 2048;
+
+/// Encrypts `message` to the public key `key`, with `hash` for the empty label and MGF1
+pub(crate) fn encrypt(
+    hash: &'static digest::Algorithm,
+    key: &RSAKeyParameters,
+    message: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let k = modulus_len(key)?;
+    let h_len = hash.output_len();
+    if message.len() + 2 * h_len + 2 > k {
+        return Err(Error::GenericError(format!(
+            "RSA-OAEP can encrypt at most {} bytes with this key",
+            k.saturating_sub(2 * h_len + 2)
+        )));
+    }
+
+    let public = RsaPublicKey::new(uint(&key.n), uint(&key.e))
+        .map_err(|err| Error::GenericError(format!("Invalid RSA public key: {err}")))?;
+    public
+        .encrypt(&mut OsRng, padding(hash)?, message)
+        .map_err(|err| Error::GenericError(format!("RSA-OAEP encryption failed: {err}")))
+}
+
+/// Decrypts `ciphertext` with the private key `key`, which needs `d`. The primes are recovered from `d` if the key
+/// does not carry them.
+pub(crate) fn decrypt(
+    hash: &'static digest::Algorithm,
+    key: &RSAKeyParameters,
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let k = modulus_len(key)?;
+    if ciphertext.len() != k {
+        return Err(Error::UnspecifiedCryptographicError);
+    }
+    if key.other_primes_info.is_some() {
+        return Err(Error::UnsupportedOperation);
+    }
+
+    let d = match key.d {
+        Some(ref d) => uint(d),
+        None => Err("Invalid key. A RSA private key is required".to_string())?,
+    };
+    let primes = match (&key.p, &key.q) {
+        (Some(p), Some(q)) => vec![uint(p), uint(q)],
+        _ => vec![],
+    };
+    let private = RsaPrivateKey::from_components(uint(&key.n), uint(&key.e), d, primes)
+        .map_err(|_| Error::UnspecifiedCryptographicError)?;
+
+    private
+        .decrypt_blinded(&mut OsRng, padding(hash)?, ciphertext)
+        .map_err(|_| Error::UnspecifiedCryptographicError)
+}
+
+fn modulus_len(key: &RSAKeyParameters) -> Result<usize, Error> {
+    if key.n.bits() < MIN_MODULUS_BITS || key.e.bits() == 0 {
+        return Err(Error::GenericError(format!(
+            "RSA keys must be at least {MIN_MODULUS_BITS} bits long"
+        )));
+    }
+    Ok(key.n.bits().div_ceil(8) as usize)
+}
+
+/// OAEP with `hash` for the label and MGF1, only the digests of the two JWA algorithms are supported
+fn padding(hash: &'static digest::Algorithm) -> Result<Oaep, Error> {
+    if hash == &digest::SHA256 {
+        Ok(Oaep::new::<sha2::Sha256>())
+    } else if hash == &digest::SHA1_FOR_LEGACY_USE_ONLY {
+        Ok(Oaep::new::<sha1::Sha1>())
+    } else {
+        Err(Error::UnsupportedOperation)
+    }
+}
+
+/// `rsa` has its own big integer type
+fn uint(value: &BigUint) -> rsa::BigUint {
+    rsa::BigUint::from_bytes_be(&value.to_bytes_be())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 2048 bit key of the pyca/cryptography interop fixture
+    fn key() -> RSAKeyParameters {
+        let fixture: serde_json::Value = not_err!(serde_json::from_str(include_str!("interop/python.json")));
+        not_err!(serde_json::from_value(fixture["keys"]["rsa"].clone()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = key();
+        for hash in [&digest::SHA1_FOR_LEGACY_USE_ONLY, &digest::SHA256] {
+            let cek = [0x42; 32];
+            let encrypted = not_err!(encrypt(hash, &key, &cek));
+            assert_eq!(encrypted.len(), 256);
+            assert_eq!(not_err!(decrypt(hash, &key, &encrypted)), cek);
+
+            // only d, without the CRT parameters
+            let d_only = RSAKeyParameters {
+                p: None,
+                q: None,
+                dp: None,
+                dq: None,
+                qi: None,
+                ..key.clone()
+            };
+            assert_eq!(not_err!(decrypt(hash, &d_only, &encrypted)), cek);
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext_and_wrong_hash() {
+        let key = key();
+        let mut encrypted = not_err!(encrypt(&digest::SHA256, &key, &[1; 16]));
+        assert!(decrypt(&digest::SHA1_FOR_LEGACY_USE_ONLY, &key, &encrypted).is_err());
+        encrypted[100] ^= 1;
+        assert!(decrypt(&digest::SHA256, &key, &encrypted).is_err());
+        assert!(decrypt(&digest::SHA256, &key, &encrypted[1..]).is_err());
+    }
+
+    #[test]
+    fn rejects_long_messages_and_public_keys() {
+        let key = key();
+        assert!(encrypt(&digest::SHA256, &key, &[0; 256 - 2 * 32 - 1]).is_err());
+        let encrypted = not_err!(encrypt(&digest::SHA256, &key, &[0; 256 - 2 * 32 - 2]));
+
+        let public = RSAKeyParameters {
+            n: key.n.clone(),
+            e: key.e.clone(),
+            ..Default::default()
+        };
+        assert!(decrypt(&digest::SHA256, &public, &encrypted).is_err());
+    }
+}
@@ -0,0 +1,806 @@
+//! X.509 certificate chains carried in JWS headers
+//!
+//! BRSKI artifacts carry the certificate of their signer, followed by the certificates certifying it, in the `x5c`
+//! header ([RFC7515#4.1.6](https://tools.ietf.org/html/rfc7515#section-4.1.6)). *ring* has no X.509 support, so this
+//! module parses just enough DER to build a path from the signer certificate to a trust anchor of a [`TrustStore`],
+//! checks the validity period and signature of every certificate on the way and hands out the key of the signer.
+//!
+//! Only what the BRSKI components issue is supported: ECDSA with P-256 or P-384, Ed25519 and RSA PKCS#1 v1.5
+//! signatures.
+//! Revocation, name constraints and policies are not checked.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use ring::{digest, signature};
+
+use crate::biscuit::errors::{Error, ValidationError};
+use crate::biscuit::jwa::SignatureAlgorithm;
+use crate::biscuit::jws::{RegisteredHeader, Secret};
+
+/// Longest path from the signer certificate to a trust anchor, the anchor not counted
+const MAX_PATH_LENGTH: usize = 8;
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+const ISSUER_UNIQUE_ID: u8 = 0x81;
+const SUBJECT_UNIQUE_ID: u8 = 0x82;
+const EXTENSIONS: u8 = 0xa3;
+
+const ID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// Identifies both Ed25519 keys and signatures, see [RFC8410#3](https://tools.ietf.org/html/rfc8410#section-3)
+const ID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const SHA256_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const SHA384_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const SHA512_WITH_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+/// Type of the public key of a certificate
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KeyAlgorithm {
+    /// ECDSA key on P-256
+    EcP256,
+    /// ECDSA key on P-384
+    EcP384,
+    /// RSA key
+    Rsa,
+    /// Ed25519 key
+    Ed25519,
+}
+
+/// A parsed DER encoded X.509 certificate
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    der: Vec<u8>,
+    tbs_certificate: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+    signature: Vec<u8>,
+    issuer: Vec<u8>,
+    subject: Vec<u8>,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+    key_algorithm: KeyAlgorithm,
+    public_key: Vec<u8>,
+    is_ca: bool,
+    may_sign_certificates: bool,
+}
+
+impl Certificate {
+    /// Parses a DER encoded certificate, see [RFC5280#4.1](https://tools.ietf.org/html/rfc5280#section-4.1)
+    pub fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let mut input = Der::new(der);
+        let mut certificate = Der::new(input.read(SEQUENCE)?);
+        input.finish()?;
+
+        let (_, tbs_certificate, tbs_certificate_der) = certificate.read_any(SEQUENCE)?;
+        let signature_algorithm = algorithm_identifier(&mut certificate)?.0;
+        let signature = bit_string(certificate.read(BIT_STRING)?)?;
+        certificate.finish()?;
+
+        let mut tbs = Der::new(tbs_certificate);
+        tbs.read_optional(VERSION)?;
+        tbs.read(INTEGER)?;
+        if algorithm_identifier(&mut tbs)?.0 != signature_algorithm {
+            Err(malformed(
+                "signature algorithms of the certificate and its content differ",
+            ))?;
+        }
+        let issuer = tbs.read_any(SEQUENCE)?.2;
+        let mut validity = Der::new(tbs.read(SEQUENCE)?);
+        let not_before = time(&mut validity)?;
+        let not_after = time(&mut validity)?;
+        validity.finish()?;
+        let subject = tbs.read_any(SEQUENCE)?.2;
+
+        let mut public_key_info = Der::new(tbs.read(SEQUENCE)?);
+        let key_algorithm = match algorithm_identifier(&mut public_key_info)? {
+            (ID_EC_PUBLIC_KEY, Some((OID, SECP256R1))) => KeyAlgorithm::EcP256,
+            (ID_EC_PUBLIC_KEY, Some((OID, SECP384R1))) => KeyAlgorithm::EcP384,
+            (RSA_ENCRYPTION, _) => KeyAlgorithm::Rsa,
+            (ID_ED25519, None) => KeyAlgorithm::Ed25519,
+            _ => Err(malformed("unsupported public key algorithm"))?,
+        };
+        let public_key = bit_string(public_key_info.read(BIT_STRING)?)?;
+        public_key_info.finish()?;
+
+        tbs.read_optional(ISSUER_UNIQUE_ID)?;
+        tbs.read_optional(SUBJECT_UNIQUE_ID)?;
+        let mut is_ca = false;
+        let mut may_sign_certificates = true;
+        if let Some(extensions) = tbs.read_optional(EXTENSIONS)? {
+            let mut extensions = Der::new(extensions);
+            let mut list = Der::new(extensions.read(SEQUENCE)?);
+            extensions.finish()?;
+            while !list.is_empty() {
+                let mut extension = Der::new(list.read(SEQUENCE)?);
+                let id = extension.read(OID)?;
+                let critical = match extension.read_optional(BOOLEAN)? {
+                    Some([value]) => *value != 0,
+                    Some(_) => Err(malformed("invalid boolean"))?,
+                    None => false,
+                };
+                let value = extension.read(OCTET_STRING)?;
+                extension.finish()?;
+
+                match id {
+                    BASIC_CONSTRAINTS => {
+                        let mut constraints = Der::new(value);
+                        let mut constraints = Der::new(constraints.read(SEQUENCE)?);
+                        is_ca = matches!(constraints.read_optional(BOOLEAN)?, Some([value]) if *value != 0);
+                    }
+                    KEY_USAGE => {
+                        let usage = bit_string_with_unused_bits(Der::new(value).read(BIT_STRING)?)?;
+                        // keyCertSign is bit 5
+                        may_sign_certificates = usage.first().is_some_and(|bits| bits & 0x04 != 0);
+                    }
+                    SUBJECT_ALT_NAME | EXT_KEY_USAGE => {}
+                    _ if critical => Err(malformed("unsupported critical extension"))?,
+                    _ => {}
+                }
+            }
+        }
+        tbs.finish()?;
+
+        Ok(Self {
+            der: der.to_vec(),
+            tbs_certificate: tbs_certificate_der.to_vec(),
+            signature_algorithm: signature_algorithm.to_vec(),
+            signature,
+            issuer: issuer.to_vec(),
+            subject: subject.to_vec(),
+            not_before,
+            not_after,
+            key_algorithm,
+            public_key,
+            is_ca,
+            may_sign_certificates,
+        })
+    }
+
+    /// The certificate as it was parsed
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// DER encoded subject name
+    pub fn subject(&self) -> &[u8] {
+        &self.subject
+    }
+
+    /// DER encoded issuer name
+    pub fn issuer(&self) -> &[u8] {
+        &self.issuer
+    }
+
+    /// Start of the validity period
+    pub fn not_before(&self) -> DateTime<Utc> {
+        self.not_before
+    }
+
+    /// End of the validity period
+    pub fn not_after(&self) -> DateTime<Utc> {
+        self.not_after
+    }
+
+    /// Type of the public key
+    pub fn key_algorithm(&self) -> KeyAlgorithm {
+        self.key_algorithm
+    }
+
+    /// Whether the certificate is valid at `now`
+    pub fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+
+    /// The public key of the certificate as secret to verify JWS signed with `algorithm`
+    pub fn public_key_secret(&self, algorithm: SignatureAlgorithm) -> Result<Secret, Error> {
+        use SignatureAlgorithm::*;
+
+        match (algorithm, self.key_algorithm) {
+            (ES256, KeyAlgorithm::EcP256)
+            | (ES384, KeyAlgorithm::EcP384)
+            | (EdDSA, KeyAlgorithm::Ed25519)
+            | (RS256 | RS384 | RS512 | PS256 | PS384 | PS512, KeyAlgorithm::Rsa) => {
+                Ok(Secret::PublicKey(self.public_key.clone()))
+            }
+            _ => Err(ValidationError::WrongAlgorithmHeader)?,
+        }
+    }
+
+    /// Verifies a detached signature of the key of this certificate over `message`, ASN.1 encoded as
+    /// `openssl dgst -sign` writes it. ECDSA keys sign with the hash of their curve, RSA keys with PKCS#1 v1.5 and
+    /// SHA-256, Ed25519 keys sign `message` itself.
+    pub fn verify_signature(&self, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let algorithm: &dyn signature::VerificationAlgorithm = match self.key_algorithm {
+            KeyAlgorithm::EcP256 => &signature::ECDSA_P256_SHA256_ASN1,
+            KeyAlgorithm::EcP384 => &signature::ECDSA_P384_SHA384_ASN1,
+            KeyAlgorithm::Rsa => &signature::RSA_PKCS1_2048_8192_SHA256,
+            KeyAlgorithm::Ed25519 => &signature::ED25519,
+        };
+        signature::UnparsedPublicKey::new(algorithm, &self.public_key)
+            .verify(message, signature)
+            .map_err(|_| ValidationError::InvalidSignature.into())
+    }
+
+    /// Whether `self` is issued by `issuer`, the names have to match and `issuer` has to have signed `self`
+    fn is_issued_by(&self, issuer: &Certificate) -> bool {
+        self.issuer == issuer.subject
+            && issuer
+                .verification_algorithm(&self.signature_algorithm)
+                .and_then(|algorithm| {
+                    signature::UnparsedPublicKey::new(algorithm, &issuer.public_key)
+                        .verify(&self.tbs_certificate, &self.signature)
+                        .ok()
+                })
+                .is_some()
+    }
+
+    /// Algorithm to verify a signature of the key of this certificate
+    fn verification_algorithm(
+        &self,
+        signature_algorithm: &[u8],
+    ) -> Option<&'static dyn signature::VerificationAlgorithm> {
+        Some(match (signature_algorithm, self.key_algorithm) {
+            (ECDSA_WITH_SHA256, KeyAlgorithm::EcP256) => &signature::ECDSA_P256_SHA256_ASN1,
+            (ECDSA_WITH_SHA384, KeyAlgorithm::EcP256) => &signature::ECDSA_P256_SHA384_ASN1,
+            (ECDSA_WITH_SHA256, KeyAlgorithm::EcP384) => &signature::ECDSA_P384_SHA256_ASN1,
+            (ECDSA_WITH_SHA384, KeyAlgorithm::EcP384) => &signature::ECDSA_P384_SHA384_ASN1,
+            (SHA256_WITH_RSA_ENCRYPTION, KeyAlgorithm::Rsa) => {
+                &signature::RSA_PKCS1_2048_8192_SHA256
+            }
+            (SHA384_WITH_RSA_ENCRYPTION, KeyAlgorithm::Rsa) => {
+                &signature::RSA_PKCS1_2048_8192_SHA384
+            }
+            (SHA512_WITH_RSA_ENCRYPTION, KeyAlgorithm::Rsa) => {
+                &signature::RSA_PKCS1_2048_8192_SHA512
+            }
+            (ID_ED25519, KeyAlgorithm::Ed25519) => &signature::ED25519,
+            _ => return None,
+        })
+    }
+}
+
+/// Trust anchors a certificate chain has to lead to
+///
+/// Anchors are trusted as they are: neither their validity period nor their
+/// own signature is checked.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    anchors: Vec<Certificate>,
+}
+
+impl TrustStore {
+    /// A trust store without anchors, every chain is rejected until one is added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A trust store with the DER encoded `anchors`
+    pub fn from_der<A: AsRef<[u8]>>(anchors: impl IntoIterator<Item = A>) -> Result<Self, Error> {
+        let mut store = Self::new();
+        for anchor in anchors {
+            store.add_der(anchor.as_ref())?;
+        }
+        Ok(store)
+    }
+
+    /// Adds a DER encoded trust anchor
+    pub fn add_der(&mut self, der: &[u8]) -> Result<(), Error> {
+        self.anchors.push(Certificate::from_der(der)?);
+        Ok(())
+    }
+
+    /// Whether there are no anchors
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Builds the path from `signer` over `intermediates` to one of the anchors. The intermediates may come in any
+    /// order, superfluous ones are ignored. Every certificate on the path but the anchor has to be valid at `now` and
+    /// every issuer has to be a CA allowed to sign certificates.
+    pub fn verify(
+        &self,
+        signer: &Certificate,
+        intermediates: &[Certificate],
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.verify_path(signer, intermediates, now).map(|_| ())
+    }
+
+    /// [`TrustStore::verify`] returning the path above `signer`: the intermediates it was built over, followed by the
+    /// anchor unless the last of them is the anchor itself
+    pub fn verify_path(
+        &self,
+        signer: &Certificate,
+        intermediates: &[Certificate],
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Certificate>, Error> {
+        if !signer.is_valid_at(now) {
+            Err(ValidationError::CertificateNotValid)?;
+        }
+
+        let mut remaining: Vec<&Certificate> = intermediates.iter().collect();
+        let mut path = Vec::new();
+        let mut current = signer;
+        for _ in 0..=MAX_PATH_LENGTH {
+            // a chain that includes the anchor itself ends there as well
+            if let Some(anchor) = self.anchors.iter().find(|anchor| {
+                anchor.der == current.der || (can_issue(anchor) && current.is_issued_by(anchor))
+            }) {
+                if anchor.der != current.der {
+                    path.push(anchor.clone());
+                }
+                return Ok(path);
+            }
+
+            let Some(position) = remaining
+                .iter()
+                .position(|issuer| can_issue(issuer) && current.is_issued_by(issuer))
+            else {
+                break;
+            };
+            current = remaining.swap_remove(position);
+            if !current.is_valid_at(now) {
+                Err(ValidationError::CertificateNotValid)?;
+            }
+            path.push(current.clone());
+        }
+        Err(ValidationError::UntrustedCertificateChain.into())
+    }
+}
+
+fn can_issue(certificate: &Certificate) -> bool {
+    certificate.is_ca && certificate.may_sign_certificates
+}
+
+/// Encodes a chain of DER encoded certificates for the `x5c` header, which uses base64 with the standard alphabet
+/// and padding, not base64url
+pub fn encode_chain<C: AsRef<[u8]>>(chain: &[C]) -> Vec<String> {
+    chain
+        .iter()
+        .map(|der| BASE64.encode(der.as_ref()))
+        .collect()
+}
+
+/// Decodes and parses the certificates of an `x5c` header
+pub fn decode_chain(chain: &[String]) -> Result<Vec<Certificate>, Error> {
+    chain
+        .iter()
+        .map(|encoded| Certificate::from_der(&BASE64.decode(encoded.as_bytes())?))
+        .collect()
+}
+
+/// SHA-256 thumbprint of a DER encoded certificate for the `x5t#S256` header
+pub fn thumbprint_sha256(der: &[u8]) -> String {
+    BASE64URL_NOPAD.encode(digest::digest(&digest::SHA256, der).as_ref())
+}
+
+/// Verifies the `x5c` chain of `header` against `trust_store` at `now` and returns the signer certificate. If the
+/// header carries `x5t` or `x5t#S256` as well, they have to match the signer certificate.
+///
+/// The JWS signature itself is not checked, verify it with [`Certificate::public_key_secret`] of the signer.
+pub fn verify_header(
+    header: &RegisteredHeader,
+    trust_store: &TrustStore,
+    now: DateTime<Utc>,
+) -> Result<Certificate, Error> {
+    let chain = header
+        .x509_chain
+        .as_deref()
+        .ok_or(ValidationError::MissingCertificateChain)?;
+    let mut chain = decode_chain(chain)?;
+    if chain.is_empty() {
+        Err(ValidationError::MissingCertificateChain)?;
+    }
+    let signer = chain.remove(0);
+
+    let thumbprints = [
+        (&header.x509_fingerprint, &digest::SHA1_FOR_LEGACY_USE_ONLY),
+        (&header.x509_fingerprint_sha256, &digest::SHA256),
+    ];
+    for (thumbprint, algorithm) in thumbprints {
+        if let Some(thumbprint) = thumbprint {
+            if BASE64URL_NOPAD.decode(thumbprint.as_bytes())?
+                != digest::digest(algorithm, &signer.der).as_ref()
+            {
+                Err(ValidationError::CertificateThumbprintMismatch)?;
+            }
+        }
+    }
+
+    trust_store.verify(&signer, &chain, now)?;
+    Ok(signer)
+}
+
+fn malformed(reason: &str) -> Error {
+    ValidationError::MalformedCertificate(reason.to_string()).into()
+}
+
+/// Tag and content of the parameters of an `AlgorithmIdentifier`
+type Parameters<'a> = (u8, &'a [u8]);
+
+/// Reads `AlgorithmIdentifier`, returns the algorithm and the tag and content of the parameters if there are any
+fn algorithm_identifier<'a>(
+    der: &mut Der<'a>,
+) -> Result<(&'a [u8], Option<Parameters<'a>>), Error> {
+    let mut identifier = Der::new(der.read(SEQUENCE)?);
+    let algorithm = identifier.read(OID)?;
+    let parameters = if identifier.is_empty() {
+        None
+    } else {
+        let (tag, content, _) = identifier.read_tlv()?;
+        Some((tag, content))
+    };
+    identifier.finish()?;
+    Ok((algorithm, parameters))
+}
+
+/// Content of a BIT STRING that has to be a whole number of bytes, as signatures and keys are
+fn bit_string(content: &[u8]) -> Result<Vec<u8>, Error> {
+    match content {
+        [0, bits @ ..] => Ok(bits.to_vec()),
+        _ => Err(malformed("bit string is not a whole number of bytes")),
+    }
+}
+
+fn bit_string_with_unused_bits(content: &[u8]) -> Result<&[u8], Error> {
+    match content {
+        [unused, bits @ ..] if *unused < 8 => Ok(bits),
+        _ => Err(malformed("invalid bit string")),
+    }
+}
+
+/// Reads `Time`, UTCTime until 2049 and GeneralizedTime after, see [RFC5280#4.1.2.5](https://tools.ietf.org/html/rfc5280#section-4.1.2.5)
+fn time(der: &mut Der<'_>) -> Result<DateTime<Utc>, Error> {
+    let (tag, content, _) = der.read_tlv()?;
+    let time = std::str::from_utf8(content).map_err(|_| malformed("invalid time"))?;
+    let time = match (tag, time.len()) {
+        (UTC_TIME, 13) => {
+            let year: u8 = time[..2].parse().map_err(|_| malformed("invalid time"))?;
+            format!("{}{}", if year >= 50 { "19" } else { "20" }, time)
+        }
+        (GENERALIZED_TIME, 15) => time.to_string(),
+        _ => Err(malformed("invalid time"))?,
+    };
+    NaiveDateTime::parse_from_str(&time, "%Y%m%d%H%M%SZ")
+        .map(|time| time.and_utc())
+        .map_err(|_| malformed("invalid time"))
+}
+
+/// Reader over a sequence of DER encoded TLVs
+struct Der<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Fails if anything is left
+    fn finish(&self) -> Result<(), Error> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(malformed("trailing data"))
+        }
+    }
+
+    /// Reads the next TLV, returns its tag, content and the complete encoding
+    fn read_tlv(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), Error> {
+        let truncated = || malformed("truncated");
+        let (&tag, rest) = self.input.split_first().ok_or_else(truncated)?;
+        let (&first, rest) = rest.split_first().ok_or_else(truncated)?;
+        let (length, rest) = match first {
+            0..=0x7f => (first as usize, rest),
+            0x81..=0x83 => {
+                let count = (first & 0x7f) as usize;
+                if rest.len() < count {
+                    Err(truncated())?;
+                }
+                let (bytes, rest) = rest.split_at(count);
+                let length = bytes
+                    .iter()
+                    .fold(0, |length, byte| length << 8 | *byte as usize);
+                // DER requires the shortest form
+                if length < 0x80 || bytes[0] == 0 {
+                    Err(malformed("length is not minimally encoded"))?;
+                }
+                (length, rest)
+            }
+            _ => Err(malformed("unsupported length"))?,
+        };
+        if rest.len() < length {
+            Err(truncated())?;
+        }
+        let header_length = self.input.len() - rest.len();
+        let (tlv, rest) = self.input.split_at(header_length + length);
+        self.input = rest;
+        Ok((tag, &tlv[header_length..], tlv))
+    }
+
+    /// Reads the next TLV that has to be a `tag`, see [`Der::read_tlv`]
+    fn read_any(&mut self, tag: u8) -> Result<(u8, &'a [u8], &'a [u8]), Error> {
+        let tlv = self.read_tlv()?;
+        if tlv.0 != tag {
+            Err(malformed("unexpected tag"))?;
+        }
+        Ok(tlv)
+    }
+
+    /// Reads the content of the next TLV that has to be a `tag`
+    fn read(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        Ok(self.read_any(tag)?.1)
+    }
+
+    /// Reads the content of the next TLV if it is a `tag`
+    fn read_optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, Error> {
+        if self.input.first() == Some(&tag) {
+            self.read(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biscuit::jws::{General, Header};
+    use crate::biscuit::Empty;
+    use chrono::Duration;
+    use std::sync::Arc;
+
+    const ROOT_CA: &[u8] = include_bytes!("x509/root_ca.der");
+    const ISSUING_CA: &[u8] = include_bytes!("x509/issuing_ca.der");
+    const PLEDGE: &[u8] = include_bytes!("x509/pledge.der");
+    const PLEDGE_KEY: &[u8] = include_bytes!("x509/pledge_key.p8");
+    const ROGUE_CA: &[u8] = include_bytes!("x509/rogue_ca.der");
+    const ED25519_CA: &[u8] = include_bytes!("x509/ed25519_ca.der");
+    const ED25519_PLEDGE: &[u8] = include_bytes!("x509/ed25519_pledge.der");
+    const ED25519_PLEDGE_KEY: &[u8] = include_bytes!("x509/ed25519_pledge_key.p8");
+
+    fn certificate(der: &[u8]) -> Certificate {
+        not_err!(Certificate::from_der(der))
+    }
+
+    fn validation_error(result: Result<impl std::fmt::Debug, Error>) -> ValidationError {
+        match result {
+            Err(Error::ValidationError(err)) => err,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    fn signed(chain: &[&[u8]]) -> General {
+        let header = Header::<Empty>::from(
+            RegisteredHeader {
+                algorithm: SignatureAlgorithm::ES256,
+                ..Default::default()
+            }
+            .with_x509_chain(chain),
+        );
+        let secret = Secret::EcdsaKeyPair(Arc::new(not_err!(signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            PLEDGE_KEY,
+            &ring::rand::SystemRandom::new()
+        ))));
+
+        let mut general = General::new(b"{\"status\":true}".to_vec());
+        not_err!(general.sign(header, None, &secret));
+        not_err!(General::deserialize(general.serialize().as_bytes()))
+    }
+
+    #[test]
+    fn parses_certificates() {
+        let root = certificate(ROOT_CA);
+        let issuing = certificate(ISSUING_CA);
+        let pledge = certificate(PLEDGE);
+
+        assert_eq!(root.key_algorithm(), KeyAlgorithm::EcP256);
+        assert_eq!(issuing.key_algorithm(), KeyAlgorithm::EcP384);
+        assert!(root.is_ca && issuing.is_ca && !pledge.is_ca);
+        assert_eq!(root.subject(), root.issuer());
+        assert_eq!(pledge.issuer(), issuing.subject());
+        assert!(pledge.not_before() < pledge.not_after());
+        assert!(pledge.is_issued_by(&issuing));
+        assert!(issuing.is_issued_by(&root));
+        assert!(!issuing.is_issued_by(&certificate(ROGUE_CA)));
+
+        assert!(Certificate::from_der(&PLEDGE[..PLEDGE.len() - 1]).is_err());
+        let mut trailing = PLEDGE.to_vec();
+        trailing.push(0);
+        assert!(Certificate::from_der(&trailing).is_err());
+    }
+
+    #[test]
+    fn builds_chain_to_trust_anchor() {
+        let store = not_err!(TrustStore::from_der([ROOT_CA]));
+        let pledge = certificate(PLEDGE);
+        let now = Utc::now();
+
+        not_err!(store.verify(&pledge, &[certificate(ISSUING_CA)], now));
+        // order of the intermediates, superfluous ones and the anchor in the chain do not matter
+        not_err!(store.verify(
+            &pledge,
+            &[
+                certificate(ROGUE_CA),
+                certificate(ROOT_CA),
+                certificate(ISSUING_CA)
+            ],
+            now
+        ));
+
+        let path = not_err!(store.verify_path(
+            &pledge,
+            &[certificate(ROGUE_CA), certificate(ISSUING_CA)],
+            now
+        ));
+        let path: Vec<&[u8]> = path.iter().map(Certificate::der).collect();
+        assert_eq!(path, vec![ISSUING_CA, ROOT_CA]);
+        let path = not_err!(store.verify_path(
+            &certificate(ISSUING_CA),
+            &[certificate(ROOT_CA)],
+            now
+        ));
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].der(), ROOT_CA);
+
+        assert_eq!(
+            validation_error(store.verify(&pledge, &[], now)),
+            ValidationError::UntrustedCertificateChain
+        );
+        let rogue = not_err!(TrustStore::from_der([ROGUE_CA]));
+        assert_eq!(
+            validation_error(rogue.verify(&pledge, &[certificate(ISSUING_CA)], now)),
+            ValidationError::UntrustedCertificateChain
+        );
+        assert_eq!(
+            validation_error(TrustStore::new().verify(&pledge, &[certificate(ISSUING_CA)], now)),
+            ValidationError::UntrustedCertificateChain
+        );
+        // the pledge is no CA, nothing it signed is trusted
+        let pledge_anchor = not_err!(TrustStore::from_der([PLEDGE]));
+        assert_eq!(
+            validation_error(pledge_anchor.verify(&certificate(ISSUING_CA), &[], now)),
+            ValidationError::UntrustedCertificateChain
+        );
+
+        let later = now + Duration::days(365 * 200);
+        assert_eq!(
+            validation_error(store.verify(&pledge, &[certificate(ISSUING_CA)], later)),
+            ValidationError::CertificateNotValid
+        );
+    }
+
+    #[test]
+    fn signs_and_verifies_x5c() {
+        let store = not_err!(TrustStore::from_der([ROOT_CA]));
+        let general = signed(&[PLEDGE, ISSUING_CA]);
+        let header = general.signatures()[0].protected_header_registered();
+        assert_eq!(
+            header.x509_chain,
+            Some(vec![BASE64.encode(PLEDGE), BASE64.encode(ISSUING_CA)])
+        );
+        assert_eq!(
+            header.x509_fingerprint_sha256,
+            Some(thumbprint_sha256(PLEDGE))
+        );
+
+        let (signature, signer) = not_err!(general.verify_x509(0, &store, Utc::now()));
+        assert_eq!(signer.der(), PLEDGE);
+        assert_eq!(signature.signature(), general.signatures()[0].signature());
+
+        let rogue = not_err!(TrustStore::from_der([ROGUE_CA]));
+        assert_eq!(
+            validation_error(general.verify_x509(0, &rogue, Utc::now())),
+            ValidationError::UntrustedCertificateChain
+        );
+        // the signature has to be made by the key of the first certificate
+        assert_eq!(
+            validation_error(signed(&[ISSUING_CA]).verify_x509(0, &store, Utc::now())),
+            ValidationError::WrongAlgorithmHeader
+        );
+    }
+
+    #[test]
+    fn checks_thumbprints() {
+        let store = not_err!(TrustStore::from_der([ROOT_CA]));
+        let mut header = RegisteredHeader::default().with_x509_chain(&[PLEDGE, ISSUING_CA]);
+        not_err!(verify_header(&header, &store, Utc::now()));
+
+        header.x509_fingerprint = Some(
+            BASE64URL_NOPAD
+                .encode(digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, PLEDGE).as_ref()),
+        );
+        not_err!(verify_header(&header, &store, Utc::now()));
+
+        header.x509_fingerprint_sha256 = Some(thumbprint_sha256(ISSUING_CA));
+        assert_eq!(
+            validation_error(verify_header(&header, &store, Utc::now())),
+            ValidationError::CertificateThumbprintMismatch
+        );
+        assert_eq!(
+            validation_error(verify_header(
+                &RegisteredHeader::default(),
+                &store,
+                Utc::now()
+            )),
+            ValidationError::MissingCertificateChain
+        );
+    }
+
+    #[test]
+    fn verifies_detached_signatures() {
+        let key = not_err!(signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            PLEDGE_KEY,
+            &ring::rand::SystemRandom::new()
+        ));
+        let message = digest::digest(&digest::SHA256, b"firmware image");
+        let signed = not_err!(key.sign(&ring::rand::SystemRandom::new(), message.as_ref()));
+
+        let pledge = certificate(PLEDGE);
+        not_err!(pledge.verify_signature(message.as_ref(), signed.as_ref()));
+        assert_eq!(
+            validation_error(pledge.verify_signature(b"another image", signed.as_ref())),
+            ValidationError::InvalidSignature
+        );
+        assert_eq!(
+            validation_error(certificate(ISSUING_CA).verify_signature(message.as_ref(), signed.as_ref())),
+            ValidationError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn signs_and_verifies_x5c_with_ed25519() {
+        let store = not_err!(TrustStore::from_der([ED25519_CA]));
+        let pledge = certificate(ED25519_PLEDGE);
+        assert_eq!(pledge.key_algorithm(), KeyAlgorithm::Ed25519);
+        assert!(pledge.is_issued_by(&certificate(ED25519_CA)));
+
+        let header = Header::<Empty>::from(
+            RegisteredHeader {
+                algorithm: SignatureAlgorithm::EdDSA,
+                ..Default::default()
+            }
+            .with_x509_chain(&[ED25519_PLEDGE]),
+        );
+        let secret = Secret::Ed25519KeyPair(Arc::new(not_err!(
+            signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(ED25519_PLEDGE_KEY)
+        )));
+        let mut general = General::new(b"{\"status\":true}".to_vec());
+        not_err!(general.sign(header, None, &secret));
+        let general = not_err!(General::deserialize(general.serialize().as_bytes()));
+
+        let (_, signer) = not_err!(general.verify_x509(0, &store, Utc::now()));
+        assert_eq!(signer.der(), ED25519_PLEDGE);
+        assert!(matches!(
+            pledge.public_key_secret(SignatureAlgorithm::ES256),
+            Err(Error::ValidationError(ValidationError::WrongAlgorithmHeader))
+        ));
+        assert_eq!(
+            validation_error(signed(&[PLEDGE, ISSUING_CA]).verify_x509(0, &store, Utc::now())),
+            ValidationError::UntrustedCertificateChain
+        );
+    }
+}
@@ -0,0 +1,63 @@
+#!/usr/bin/env bash
+# Generates the certificate chain the x509 tests verify:
+#
+#   root_ca.der            P-256 root, the trust anchor of the tests
+#   issuing_ca.der         P-384 intermediate, signed by the root with ecdsa-with-SHA256
+#   pledge.der             P-256 IDevID, signed by the intermediate with ecdsa-with-SHA384
+#   pledge_key.p8          PKCS#8 key of the IDevID
+#   rogue_ca.der           self-signed root with the same subject as root_ca.der but another key
+#   ed25519_ca.der         Ed25519 root
+#   ed25519_pledge.der     Ed25519 IDevID, signed by the Ed25519 root
+#   ed25519_pledge_key.p8  PKCS#8 key of the Ed25519 IDevID
+#
+# Everything is valid for 100 years from the time of generation.
+set -euo pipefail
+cd "$(dirname "$0")"
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+cat > "$work/ext.cnf" <<EOF
+[v3_brski_ca]
+basicConstraints=critical,CA:TRUE
+keyUsage=critical,keyCertSign,cRLSign
+subjectKeyIdentifier=hash
+[v3_brski_leaf]
+basicConstraints=critical,CA:FALSE
+keyUsage=critical,digitalSignature
+EOF
+config() { cat "$(openssl version -d | cut -d'"' -f2)/openssl.cnf" "$work/ext.cnf"; }
+
+for key in root:prime256v1 issuing:secp384r1 pledge:prime256v1 rogue:prime256v1; do
+    openssl ecparam -genkey -name "${key#*:}" -noout -out "$work/${key%%:*}.key"
+done
+
+openssl req -x509 -new -key "$work/root.key" -subj "/O=Example Manufacturer/CN=Example MASA Root CA" \
+    -days 36500 -sha256 -extensions v3_brski_ca -config <(config) -out "$work/root.pem"
+openssl req -x509 -new -key "$work/rogue.key" -subj "/O=Example Manufacturer/CN=Example MASA Root CA" \
+    -days 36500 -sha256 -extensions v3_brski_ca -config <(config) -out "$work/rogue.pem"
+
+openssl req -new -key "$work/issuing.key" -subj "/O=Example Manufacturer/CN=Example IDevID Issuing CA" -out "$work/issuing.csr"
+openssl x509 -req -in "$work/issuing.csr" -CA "$work/root.pem" -CAkey "$work/root.key" -set_serial 2 \
+    -days 36500 -sha256 -extfile "$work/ext.cnf" -extensions v3_brski_ca -out "$work/issuing.pem"
+
+openssl req -new -key "$work/pledge.key" -subj "/CN=pledge/serialNumber=00-D0-E5-F2-00-02" -out "$work/pledge.csr"
+openssl x509 -req -in "$work/pledge.csr" -CA "$work/issuing.pem" -CAkey "$work/issuing.key" -set_serial 3 \
+    -days 36500 -sha384 -extfile "$work/ext.cnf" -extensions v3_brski_leaf -out "$work/pledge.pem"
+
+openssl verify -CAfile "$work/root.pem" -untrusted "$work/issuing.pem" "$work/pledge.pem"
+
+for key in ed25519_root ed25519_pledge; do
+    openssl genpkey -algorithm ed25519 -out "$work/$key.key"
+done
+openssl req -x509 -new -key "$work/ed25519_root.key" -subj "/O=Example Manufacturer/CN=Example Ed25519 Root CA" \
+    -days 36500 -extensions v3_brski_ca -config <(config) -out "$work/ed25519_root.pem"
+openssl req -new -key "$work/ed25519_pledge.key" -subj "/CN=pledge/serialNumber=00-D0-E5-F2-00-03" -out "$work/ed25519_pledge.csr"
+openssl x509 -req -in "$work/ed25519_pledge.csr" -CA "$work/ed25519_root.pem" -CAkey "$work/ed25519_root.key" -set_serial 4 \
+    -days 36500 -extfile "$work/ext.cnf" -extensions v3_brski_leaf -out "$work/ed25519_pledge.pem"
+openssl verify -CAfile "$work/ed25519_root.pem" "$work/ed25519_pledge.pem"
+
+for cert in root:root_ca issuing:issuing_ca pledge:pledge rogue:rogue_ca ed25519_root:ed25519_ca ed25519_pledge:ed25519_pledge; do
+    openssl x509 -in "$work/${cert%%:*}.pem" -outform DER -out "${cert#*:}.der"
+done
+openssl pkcs8 -topk8 -nocrypt -in "$work/pledge.key" -outform DER -out pledge_key.p8
+openssl pkey -in "$work/ed25519_pledge.key" -outform DER -out ed25519_pledge_key.p8
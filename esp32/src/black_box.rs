@@ -0,0 +1,187 @@
+#[cfg(feature = "black-box")]
+use std::sync::{Arc, Mutex, OnceLock};
+
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+#[cfg(feature = "black-box")]
+use consts::ble::{BLACK_BOX_READ_UUID, BLACK_BOX_UUID};
+use data_encoding::HEXLOWER;
+#[cfg(feature = "black-box")]
+use esp32_nimble::{utilities::BleUuid, BLEServer, NimbleProperties};
+#[cfg(feature = "black-box")]
+use esp_idf_svc::nvs::{EspNvs, NvsDefault};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+#[cfg(feature = "black-box")]
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// NVS namespace of the ring buffer, separate from the credentials so it can be erased on its own
+#[cfg(feature = "black-box")]
+const NAMESPACE: &str = "blackbox";
+/// Records kept, the oldest one is overwritten
+#[cfg(feature = "black-box")]
+const CAPACITY: u32 = 32;
+/// Sequence number of the next record
+#[cfg(feature = "black-box")]
+const SEQUENCE_KEY: &str = "seq";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub enum Event {
+    /// A protocol artifact the pledge received or produced, only its hash is kept
+    Artifact { name: String, sha256: String },
+    Transition { from: String, to: String },
+    Error { step: String, reason_code: ReasonCode, detail: String },
+}
+
+#[cfg(feature = "black-box")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Record {
+    pub sequence: u32,
+    /// Wall clock, unix seconds. Only meaningful once the time was synchronized
+    pub timestamp: i64,
+    /// Milliseconds since boot
+    pub uptime: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[cfg(feature = "black-box")]
+struct BlackBox {
+    nvs: EspNvs<NvsDefault>,
+    sequence: u32,
+}
+
+#[cfg(feature = "black-box")]
+static BLACK_BOX: OnceLock<Mutex<BlackBox>> = OnceLock::new();
+
+/// Opens the ring buffer in flash and prints the records left by earlier runs to the console
+#[cfg(feature = "black-box")]
+pub fn init(partition: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+    let sequence = nvs.get_u32(SEQUENCE_KEY)?.unwrap_or(0);
+    let black_box = BLACK_BOX.get_or_init(|| Mutex::new(BlackBox { nvs, sequence }));
+
+    let records = read_all(&black_box.lock().unwrap());
+    info!("Black box holds {} records", records.len());
+    for record in records {
+        info!("Black box: {}", serde_json::to_string(&record).unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// Recording is compiled out without the `black-box` feature
+#[cfg(not(feature = "black-box"))]
+pub fn init(_partition: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    Ok(())
+}
+
+pub fn artifact(name: &str, artifact: &[u8]) {
+    // skip hashing when recording is compiled out
+    if !cfg!(feature = "black-box") {
+        return;
+    }
+    let sha256 = ring::digest::digest(&ring::digest::SHA256, artifact);
+    record(Event::Artifact { name: name.to_string(), sha256: HEXLOWER.encode(sha256.as_ref()) });
+}
+
+pub fn transition(from: impl std::fmt::Debug, to: impl std::fmt::Debug) {
+    record(Event::Transition { from: format!("{:?}", from), to: format!("{:?}", to) });
+}
+
+pub fn error(step: &str, reason_code: ReasonCode, detail: impl std::fmt::Display) {
+    record(Event::Error { step: step.to_string(), reason_code, detail: detail.to_string() });
+}
+
+/// Never fails the onboarding step that is recorded, problems with the flash are only logged
+#[cfg(feature = "black-box")]
+fn record(event: Event) {
+    let Some(black_box) = BLACK_BOX.get() else {
+        return;
+    };
+    let mut black_box = black_box.lock().unwrap();
+
+    let record = Record {
+        sequence: black_box.sequence,
+        timestamp: chrono::Utc::now().timestamp(),
+        uptime: unsafe { esp_idf_svc::sys::esp_timer_get_time() } as u64 / 1000,
+        event,
+    };
+
+    let result = serde_json::to_vec(&record).map_err(anyhow::Error::from).and_then(|serialized| {
+        black_box.nvs.set_blob(&slot(record.sequence), &serialized)?;
+        black_box.nvs.set_u32(SEQUENCE_KEY, record.sequence.wrapping_add(1))?;
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => black_box.sequence = record.sequence.wrapping_add(1),
+        Err(e) => warn!("Unable to write black box record: {}", e),
+    }
+}
+
+#[cfg(not(feature = "black-box"))]
+fn record(_event: Event) {}
+
+/// All records, oldest first
+#[cfg(feature = "black-box")]
+pub fn dump() -> Vec<Record> {
+    match BLACK_BOX.get() {
+        Some(black_box) => read_all(&black_box.lock().unwrap()),
+        None => vec![],
+    }
+}
+
+#[cfg(feature = "black-box")]
+fn read_all(black_box: &BlackBox) -> Vec<Record> {
+    let mut records: Vec<Record> = (0..CAPACITY)
+        .filter_map(|index| {
+            let key = slot(index);
+            let length = black_box.nvs.blob_len(&key).ok().flatten()?;
+            let mut blob = vec![0u8; length];
+            let blob = black_box.nvs.get_blob(&key, &mut blob).ok().flatten()?;
+            serde_json::from_slice(blob).ok()
+        })
+        .collect();
+    records.sort_by_key(|record| record.sequence);
+    records
+}
+
+#[cfg(feature = "black-box")]
+fn slot(sequence: u32) -> String {
+    format!("r{}", sequence % CAPACITY)
+}
+
+/// Read-only GATT service handing out the records as JSON lines in MTU sized chunks, an empty read ends the dump
+#[cfg(feature = "black-box")]
+pub fn handle_black_box(server: &mut BLEServer) -> BleUuid {
+    let service_uuid = BleUuid::from_uuid128_string(BLACK_BOX_UUID).unwrap();
+    let read_uuid = BleUuid::from_uuid128_string(BLACK_BOX_READ_UUID).unwrap();
+
+    let service = server.create_service(service_uuid);
+    let read_characteristic = service.lock().create_characteristic(read_uuid, NimbleProperties::READ);
+
+    // the dump being read, taken when the first chunk is read
+    let pending: Arc<Mutex<Option<Vec<u8>>>> = Arc::new(Mutex::new(None));
+
+    read_characteristic.lock().on_read(move |characteristic, args| {
+        let mut pending = pending.lock().unwrap();
+        let dump = pending.get_or_insert_with(|| {
+            dump()
+                .iter()
+                .filter_map(|record| serde_json::to_string(record).ok())
+                .flat_map(|line| (line + "\n").into_bytes())
+                .collect()
+        });
+
+        let chunk_size = (args.mtu() - 3) as usize;
+        let chunk: Vec<u8> = dump.drain(..chunk_size.min(dump.len())).collect();
+        if chunk.is_empty() {
+            *pending = None;
+        }
+        characteristic.set_value(&chunk);
+    });
+
+    info!("Black box service on uid: {:?}", service_uuid);
+    service_uuid
+}
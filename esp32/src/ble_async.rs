@@ -1,31 +1,99 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, Mutex};
 
-use consts::ble::{TPVR_READ_UUID, TPVR_UUID, TPVR_WRITE_UUID};
-use esp32_nimble::{BLEAdvertisementData, BLEDevice};
+use consts::ble::{
+    CA_CERTS_READ_UUID, CA_CERTS_UUID, CA_CERTS_WRITE_UUID, ENROLL_RESPONSE_READ_UUID, ENROLL_RESPONSE_UUID,
+    ENROLL_RESPONSE_WRITE_UUID, NETWORK_PROFILE_READ_UUID, NETWORK_PROFILE_UUID, NETWORK_PROFILE_WRITE_UUID,
+    STATUS_READ_UUID, STATUS_UUID, STATUS_WRITE_UUID, TPER_READ_UUID, TPER_UUID, TPER_WRITE_UUID, TPVR_READ_UUID,
+    TPVR_UUID, TPVR_WRITE_UUID, VOUCHER_READ_UUID, VOUCHER_UUID, VOUCHER_WRITE_UUID,
+};
+use esp32_nimble::{BLEAdvertisementData, BLEDevice, BLEServer, NimbleProperties};
 use esp32_nimble::utilities::BleUuid;
 use esp_idf_svc::hal::delay::FreeRtos;
 use log::info;
 
-use crate::tpvr;
+#[cfg(feature = "black-box")]
+use crate::black_box;
+use crate::{prm, tpvr};
 
-pub(crate) struct Uuids {
-    pub tpvr: BleUuid,
-    pub tpvr_read: BleUuid,
-    pub tpvr_write: BleUuid,
+/// GATT service of one PRM exchange, the agent writes the request and reads the response
+struct Exchange {
+    name: &'static str,
+    service: &'static str,
+    write: &'static str,
+    read: &'static str,
+    handler: fn(&[u8]) -> anyhow::Result<Vec<u8>>,
 }
 
-pub(crate) static UUIDS: LazyLock<Uuids> = LazyLock::new(|| {
-    //let private_key = ring::signature::EcdsaKeyPair::from_pkcs8(&ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING, PRIVATE_KEY, &ring::rand::SystemRandom::new()).unwrap();
-    let tpvr = BleUuid::from_uuid128_string(TPVR_UUID).unwrap();
-    let tpvr_read = BleUuid::from_uuid128_string(TPVR_READ_UUID).unwrap();
-    let tpvr_write = BleUuid::from_uuid128_string(TPVR_WRITE_UUID).unwrap();
+static EXCHANGES: [Exchange; 7] = [
+    Exchange { name: "tpvr", service: TPVR_UUID, write: TPVR_WRITE_UUID, read: TPVR_READ_UUID, handler: tpvr::signed_pvr },
+    Exchange { name: "tper", service: TPER_UUID, write: TPER_WRITE_UUID, read: TPER_READ_UUID, handler: prm::signed_per },
+    Exchange { name: "voucher", service: VOUCHER_UUID, write: VOUCHER_WRITE_UUID, read: VOUCHER_READ_UUID, handler: prm::voucher_status },
+    Exchange { name: "cacerts", service: CA_CERTS_UUID, write: CA_CERTS_WRITE_UUID, read: CA_CERTS_READ_UUID, handler: prm::accept_ca_certs },
+    Exchange {
+        name: "enroll",
+        service: ENROLL_RESPONSE_UUID,
+        write: ENROLL_RESPONSE_WRITE_UUID,
+        read: ENROLL_RESPONSE_READ_UUID,
+        handler: prm::enroll_status,
+    },
+    Exchange {
+        name: "network",
+        service: NETWORK_PROFILE_UUID,
+        write: NETWORK_PROFILE_WRITE_UUID,
+        read: NETWORK_PROFILE_READ_UUID,
+        handler: prm::network_profile,
+    },
+    Exchange { name: "status", service: STATUS_UUID, write: STATUS_WRITE_UUID, read: STATUS_READ_UUID, handler: prm::pledge_status },
+];
 
-    Uuids {
-        tpvr,
-        tpvr_read,
-        tpvr_write,
-    }
-});
+/// Registers the service of an exchange. The agent writes the request in chunks of the MTU payload size, a shorter
+/// write ends it. Once the response is computed the read characteristic notifies, and every read hands out the next
+/// chunk until an empty read marks the end, see `ble_framing` of the registrar-agent.
+fn handle_exchange(server: &mut BLEServer, exchange: &'static Exchange) -> BleUuid {
+    let service_uuid = BleUuid::from_uuid128_string(exchange.service).unwrap();
+    let service = server.create_service(service_uuid);
+
+    let write_characteristic = service
+        .lock()
+        .create_characteristic(BleUuid::from_uuid128_string(exchange.write).unwrap(), NimbleProperties::WRITE);
+    let read_characteristic = service.lock().create_characteristic(
+        BleUuid::from_uuid128_string(exchange.read).unwrap(),
+        NimbleProperties::READ | NimbleProperties::NOTIFY,
+    );
+
+    let request = Arc::new(Mutex::new(vec![]));
+    let response = Arc::new(Mutex::new(vec![]));
+    let pending = Arc::clone(&response);
+
+    read_characteristic.lock().on_read(move |characteristic, args| {
+        let chunk_size = (args.mtu() - 3) as usize;
+        let mut pending = pending.lock().unwrap();
+        let chunk: Vec<u8> = pending.drain(..chunk_size.min(pending.len())).collect();
+        characteristic.set_value(&chunk);
+    });
+
+    write_characteristic.lock().on_write(move |args| {
+        request.lock().unwrap().extend(args.recv_data());
+        if (args.recv_data().len() as u16) >= args.desc().mtu() - 3 {
+            return;
+        }
+
+        // take the buf to make stack space
+        let received = std::mem::take(&mut *request.lock().unwrap());
+        info!("Received {} request with length {}", exchange.name, received.len());
+
+        match prm::recorded(exchange.name, &received, exchange.handler) {
+            Ok(computed) => {
+                *response.lock().unwrap() = computed;
+                args.notify();
+            }
+            Err(e) => info!("Error answering {} request: {}", exchange.name, e),
+        }
+    });
+
+    info!("{} service on uid: {:?}", exchange.name, service_uuid);
+    service_uuid
+}
 
 pub async fn run_ble() -> tokio::task::JoinHandle<()> {
     tokio::task::spawn(async move {
@@ -53,15 +121,18 @@ pub async fn run_ble() -> tokio::task::JoinHandle<()> {
             info!("Disconnected from {:?}", client_desc);
         });
 
-        let tpvr_handle = tpvr::handle_tpvr(server);
+        let services: Vec<BleUuid> = EXCHANGES.iter().map(|exchange| handle_exchange(server, exchange)).collect();
+        #[cfg(feature = "black-box")]
+        black_box::handle_black_box(server);
 
         let ble_advertiser = ble_device.get_advertising();
         ble_advertiser
             .lock()
             .set_data(
                 BLEAdvertisementData::new()
-                    .name("ESP32 Server")
-                    .add_service_uuid(tpvr_handle),
+                    .name(tpvr::SERIAL_NUMBER)
+                    // agents find pledges by the tPVR service, a second 128-bit UUID does not fit the advertisement
+                    .add_service_uuid(services[0]),
             )
             .unwrap();
 
@@ -0,0 +1,233 @@
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::anyhow;
+use brski_prm_artifacts::network::NetworkProfile;
+use data_encoding::BASE64;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{info, warn};
+
+/// NVS namespace of the domain credentials, at most 15 characters like the keys
+const NAMESPACE: &str = "brski";
+
+const LDEVID_KEY: &str = "ldevid";
+const PRIVATE_KEY_KEY: &str = "ldevid_key";
+const DOMAIN_CA_KEY: &str = "domain_ca";
+/// CA certificates of the domain as JSON array of base64 DER, missing if the registrar-agent sent none
+const CACERTS_KEY: &str = "domain_cacerts";
+/// Written last, so credentials of an interrupted store are never loaded
+const COMPLETE_KEY: &str = "complete";
+/// Production network from the registrar-agent as JSON, only used together with the domain credentials
+const NETWORK_PROFILE_KEY: &str = "net_profile";
+/// MASA trust anchor installed by an OTA update, DER encoded. It belongs to the manufacturer, not the domain, so it
+/// is kept by [`clear`]
+const MASA_ANCHOR_KEY: &str = "masa_anchor";
+
+/// Credentials of the domain the pledge was onboarded into, as opposed to the IDevID compiled into the firmware
+#[derive(Clone)]
+pub struct DomainCredentials {
+    /// LDevID certificate, DER encoded
+    pub ldevid: Vec<u8>,
+    /// Private key of the LDevID, PKCS#8 DER encoded. Empty if the key is kept in the secure element of the
+    /// `keystore`, the LDevID is issued for the IDevID key.
+    pub private_key: Vec<u8>,
+    /// Pinned domain CA from the voucher, DER encoded
+    pub domain_ca: Vec<u8>,
+    /// CA certificates of the domain, DER encoded and anchored in `domain_ca`, see `trust_store`
+    pub cacerts: Vec<Vec<u8>>,
+}
+
+impl DomainCredentials {
+    /// The pinned domain CA and the CA certificates of the domain as trust anchors for TLS and EAP-TLS
+    pub fn ca_bundle(&self) -> Vec<u8> {
+        crate::trust_store::pem_bundle(std::iter::once(&self.domain_ca).chain(&self.cacerts))
+    }
+}
+
+impl std::fmt::Debug for DomainCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DomainCredentials")
+            .field("ldevid", &self.ldevid.len())
+            .field("private_key", &"..")
+            .field("domain_ca", &self.domain_ca.len())
+            .field("cacerts", &self.cacerts.len())
+            .finish()
+    }
+}
+
+struct Store {
+    nvs: EspNvs<NvsDefault>,
+    /// Last loaded or stored credentials
+    credentials: Option<DomainCredentials>,
+    network_profile: Option<NetworkProfile>,
+    masa_anchor: Option<Vec<u8>>,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+
+/// Opens the credential namespace and loads the domain credentials stored by an earlier onboarding
+pub fn init(partition: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+    let credentials = load(&nvs)?;
+    let network_profile = match credentials {
+        Some(_) => load_network_profile(&nvs)?,
+        None => None,
+    };
+    let masa_anchor = match nvs.blob_len(MASA_ANCHOR_KEY)? {
+        Some(_) => Some(read_blob(&nvs, MASA_ANCHOR_KEY)?),
+        None => None,
+    };
+    if STORE.set(Mutex::new(Store { nvs, credentials, network_profile, masa_anchor })).is_err() {
+        return Err(anyhow!("credential store is already initialized"));
+    }
+    Ok(())
+}
+
+/// `None` if the pledge was not onboarded yet or the stored credentials are unusable
+pub fn domain_credentials() -> Option<DomainCredentials> {
+    STORE.get()?.lock().unwrap().credentials.clone()
+}
+
+/// `None` until the registrar-agent provisioned a production network after the onboarding
+pub fn network_profile() -> Option<NetworkProfile> {
+    STORE.get()?.lock().unwrap().network_profile.clone()
+}
+
+/// `None` until an OTA update installed a MASA trust anchor, vouchers are then accepted without checking the MASA
+pub fn masa_anchor() -> Option<Vec<u8>> {
+    STORE.get()?.lock().unwrap().masa_anchor.clone()
+}
+
+fn load(nvs: &EspNvs<NvsDefault>) -> anyhow::Result<Option<DomainCredentials>> {
+    if nvs.get_u8(COMPLETE_KEY)? != Some(1) {
+        info!("No domain credentials stored, the pledge needs to be onboarded");
+        return Ok(None);
+    }
+
+    let credentials = DomainCredentials {
+        ldevid: read_blob(nvs, LDEVID_KEY)?,
+        private_key: match nvs.blob_len(PRIVATE_KEY_KEY)? {
+            Some(_) => read_blob(nvs, PRIVATE_KEY_KEY)?,
+            None => Vec::new(),
+        },
+        domain_ca: read_blob(nvs, DOMAIN_CA_KEY)?,
+        cacerts: match nvs.blob_len(CACERTS_KEY)? {
+            Some(_) => serde_json::from_slice::<Vec<String>>(&read_blob(nvs, CACERTS_KEY)?)?
+                .iter()
+                .map(|encoded| BASE64.decode(encoded.as_bytes()))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        },
+    };
+
+    let rng = crate::biscuit::jwa::checked_rng()?;
+    let usable = match credentials.private_key.as_slice() {
+        [] => Ok(()),
+        pkcs8 => ring::signature::EcdsaKeyPair::from_pkcs8(crate::SIGNING_ALGORITHM, pkcs8, rng).map(|_| ()),
+    };
+    if let Err(e) = usable {
+        warn!("Ignoring stored domain credentials with an unusable private key: {}", e);
+        return Ok(None);
+    }
+
+    info!("Loaded domain credentials from NVS: {:?}", credentials);
+    Ok(Some(credentials))
+}
+
+fn load_network_profile(nvs: &EspNvs<NvsDefault>) -> anyhow::Result<Option<NetworkProfile>> {
+    if nvs.blob_len(NETWORK_PROFILE_KEY)?.is_none() {
+        return Ok(None);
+    }
+
+    match serde_json::from_slice::<NetworkProfile>(&read_blob(nvs, NETWORK_PROFILE_KEY)?) {
+        Ok(profile) => {
+            info!("Loaded network profile from NVS: {:?}", profile);
+            Ok(Some(profile))
+        }
+        Err(e) => {
+            warn!("Ignoring unusable network profile: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// Replaces the stored domain credentials after bootstrapping, so a re-provisioned pledge does not need to be reflashed
+pub fn persist(credentials: &DomainCredentials) -> anyhow::Result<()> {
+    let mut store = store()?.lock().unwrap();
+    let nvs = &mut store.nvs;
+
+    nvs.set_u8(COMPLETE_KEY, 0)?;
+    // a profile of the previous domain may rely on its LDevID
+    nvs.remove(NETWORK_PROFILE_KEY)?;
+    nvs.set_blob(DOMAIN_CA_KEY, &credentials.domain_ca)?;
+    if credentials.cacerts.is_empty() {
+        nvs.remove(CACERTS_KEY)?;
+    } else {
+        let encoded: Vec<String> = credentials.cacerts.iter().map(|der| BASE64.encode(der)).collect();
+        nvs.set_blob(CACERTS_KEY, &serde_json::to_vec(&encoded)?)?;
+    }
+    // NVS has no empty blobs, a missing key stands for one kept in the secure element
+    if credentials.private_key.is_empty() {
+        nvs.remove(PRIVATE_KEY_KEY)?;
+    } else {
+        nvs.set_blob(PRIVATE_KEY_KEY, &credentials.private_key)?;
+    }
+    nvs.set_blob(LDEVID_KEY, &credentials.ldevid)?;
+    nvs.set_u8(COMPLETE_KEY, 1)?;
+    store.credentials = Some(credentials.clone());
+    store.network_profile = None;
+    crate::black_box::artifact("ldevid", &credentials.ldevid);
+
+    info!("Stored domain credentials in NVS: {:?}", credentials);
+    Ok(())
+}
+
+/// Replaces the stored production network, the pledge has to be onboarded
+pub fn persist_network_profile(profile: &NetworkProfile) -> anyhow::Result<()> {
+    let mut store = store()?.lock().unwrap();
+    if store.credentials.is_none() {
+        return Err(anyhow!("network profiles are only accepted after the onboarding"));
+    }
+
+    store.nvs.set_blob(NETWORK_PROFILE_KEY, &serde_json::to_vec(profile)?)?;
+    store.network_profile = Some(profile.clone());
+
+    info!("Stored network profile in NVS: {:?}", profile);
+    Ok(())
+}
+
+/// Replaces the MASA trust anchor, the signature of the update has to be verified before
+pub fn persist_masa_anchor(anchor: &[u8]) -> anyhow::Result<()> {
+    let mut store = store()?.lock().unwrap();
+    store.nvs.set_blob(MASA_ANCHOR_KEY, anchor)?;
+    store.masa_anchor = Some(anchor.to_vec());
+    crate::black_box::artifact("masa-anchor", anchor);
+
+    info!("Stored MASA trust anchor with {} bytes in NVS", anchor.len());
+    Ok(())
+}
+
+/// Forgets the domain, the pledge falls back to its IDevID on the next boot
+pub fn clear() -> anyhow::Result<()> {
+    let mut store = store()?.lock().unwrap();
+
+    store.nvs.remove(COMPLETE_KEY)?;
+    for key in [LDEVID_KEY, PRIVATE_KEY_KEY, DOMAIN_CA_KEY, CACERTS_KEY, NETWORK_PROFILE_KEY] {
+        store.nvs.remove(key)?;
+    }
+    store.credentials = None;
+    store.network_profile = None;
+
+    info!("Removed domain credentials from NVS");
+    Ok(())
+}
+
+fn store() -> anyhow::Result<&'static Mutex<Store>> {
+    STORE.get().ok_or(anyhow!("credential store is not initialized"))
+}
+
+fn read_blob(nvs: &EspNvs<NvsDefault>, key: &str) -> anyhow::Result<Vec<u8>> {
+    let length = nvs.blob_len(key)?.ok_or(anyhow!("{} is missing", key))?;
+    let mut blob = vec![0u8; length];
+    nvs.get_blob(key, &mut blob)?;
+    Ok(blob)
+}
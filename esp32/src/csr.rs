@@ -0,0 +1,86 @@
+//! PKCS#10 certification requests (RFC 2986) for the PER. ring has no X.509 support, the request is encoded with the
+//! `der` and `x509-cert` crates the registrar and MASA use for their ASN.1 as well.
+
+use der::{
+    asn1::{Any, BitString, ObjectIdentifier, PrintableStringRef, SetOfVec, UintRef, Utf8StringRef},
+    Encode,
+};
+use ietf_voucher::serial_number;
+use x509_cert::{
+    attr::AttributeTypeAndValue,
+    name::{RdnSequence, RelativeDistinguishedName},
+    request::{CertReq, CertReqInfo, Version},
+    spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned},
+};
+
+use crate::keystore::Keystore;
+
+/// id-at-commonName
+const COMMON_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+/// id-at-serialNumber
+const SERIAL_NUMBER: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.5");
+/// id-ecPublicKey
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+
+/// prime256v1
+#[cfg(not(feature = "p384"))]
+const CURVE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+/// ecdsa-with-SHA256
+#[cfg(not(feature = "p384"))]
+const SIGNATURE_ALGORITHM: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+/// secp384r1
+#[cfg(feature = "p384")]
+const CURVE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+/// ecdsa-with-SHA384
+#[cfg(feature = "p384")]
+const SIGNATURE_ALGORITHM: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+
+/// Requests a certificate for `key` with the serial-number as common name and serial number attribute.
+/// `key` signs in the fixed encoding of `SIGNING_ALGORITHM`, the signature is re-encoded as DER.
+pub(crate) fn certification_request(key: &dyn Keystore, serial_number: &str) -> anyhow::Result<Vec<u8>> {
+    serial_number::validate(serial_number)?;
+    // PrintableString can not carry a serial-number with other characters, the registrar reads both as UTF-8
+    let serial_number_value = if serial_number::is_printable(serial_number) {
+        Any::encode_from(&PrintableStringRef::new(serial_number)?)?
+    } else {
+        Any::encode_from(&Utf8StringRef::new(serial_number)?)?
+    };
+
+    let info = CertReqInfo {
+        version: Version::V1,
+        subject: RdnSequence(vec![
+            rdn(COMMON_NAME, Any::encode_from(&Utf8StringRef::new(serial_number)?)?)?,
+            rdn(SERIAL_NUMBER, serial_number_value)?,
+        ]),
+        public_key: SubjectPublicKeyInfoOwned {
+            algorithm: AlgorithmIdentifierOwned {
+                oid: EC_PUBLIC_KEY,
+                parameters: Some(Any::encode_from(&CURVE)?),
+            },
+            subject_public_key: BitString::from_bytes(key.public_key())?,
+        },
+        attributes: SetOfVec::new(),
+    };
+
+    let signature = key.sign(&info.to_der()?)?;
+
+    Ok(CertReq {
+        info,
+        algorithm: AlgorithmIdentifierOwned {
+            oid: SIGNATURE_ALGORITHM,
+            parameters: None,
+        },
+        signature: BitString::from_bytes(&ecdsa_signature(&signature)?)?,
+    }
+    .to_der()?)
+}
+
+fn rdn(oid: ObjectIdentifier, value: Any) -> der::Result<RelativeDistinguishedName> {
+    Ok(RelativeDistinguishedName(SetOfVec::try_from(vec![AttributeTypeAndValue { oid, value }])?))
+}
+
+/// `r || s` as ECDSA-Sig-Value
+fn ecdsa_signature(fixed: &[u8]) -> der::Result<Vec<u8>> {
+    let (r, s) = fixed.split_at(fixed.len() / 2);
+    vec![UintRef::new(r)?, UintRef::new(s)?].to_der()
+}
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+use log::{error, info};
+
+use crate::{biscuit, black_box};
+
+/// Bytes drawn for the startup health tests, twice the adaptive proportion window
+const STARTUP_SAMPLES: usize = 1024;
+
+/// Min-entropy per byte assumed for the health test cutoffs, see NIST SP 800-90B Section 4.4.
+/// Both cutoffs give a false positive rate of 2^-20.
+const ASSUMED_ENTROPY_BITS: usize = 4;
+/// A byte repeated this often in a row fails the repetition count test
+const REPETITION_CUTOFF: usize = 1 + 20_usize.div_ceil(ASSUMED_ENTROPY_BITS);
+const PROPORTION_WINDOW: usize = 512;
+/// The first byte of a window occurring this often in it fails the adaptive proportion test
+const PROPORTION_CUTOFF: usize = 62;
+
+const UNTESTED: u8 = 0;
+const HEALTHY: u8 = 1;
+const FAILED: u8 = 2;
+
+static HEALTH: AtomicU8 = AtomicU8::new(UNTESTED);
+/// Last word handed out, for the continuous test of every draw
+static LAST_WORD: AtomicU32 = AtomicU32::new(0);
+
+extern "C" {
+    // not part of the generated bindings, see `bootloader_random.h`
+    fn bootloader_random_enable();
+    fn bootloader_random_disable();
+}
+
+/// The hardware TRNG, it only hands out bytes after passing the startup health tests
+pub struct HardwareRng;
+
+impl biscuit::jwa::RandomSource for HardwareRng {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), biscuit::errors::Error> {
+        fill(dest).map_err(|e| biscuit::errors::Error::GenericError(e.to_string()))
+    }
+
+    fn check(&self) -> Result<(), biscuit::errors::Error> {
+        check().map_err(|e| biscuit::errors::Error::GenericError(e.to_string()))
+    }
+}
+
+static HARDWARE_RNG: HardwareRng = HardwareRng;
+
+/// Runs the startup health tests and routes the key and nonce generation of biscuit through the TRNG.
+/// Has to run before Wi-Fi and Bluetooth are started, as the SAR ADC noise source can not be used alongside the radio.
+/// Once the radio is up, it keeps feeding the TRNG.
+pub fn init() -> anyhow::Result<()> {
+    let mut samples = vec![0u8; STARTUP_SAMPLES];
+    unsafe {
+        bootloader_random_enable();
+        esp_idf_svc::sys::esp_fill_random(samples.as_mut_ptr().cast(), samples.len());
+        bootloader_random_disable();
+    }
+
+    if let Err(e) = health_tests(&samples) {
+        HEALTH.store(FAILED, Ordering::SeqCst);
+        black_box::error("entropy", ReasonCode::InternalError, &e);
+        return Err(e);
+    }
+
+    LAST_WORD.store(unsafe { esp_idf_svc::sys::esp_random() }, Ordering::SeqCst);
+    HEALTH.store(HEALTHY, Ordering::SeqCst);
+    biscuit::jwa::set_random_source(&HARDWARE_RNG)?;
+    info!("Hardware RNG passed the startup health tests");
+    Ok(())
+}
+
+/// Fills `dest` from the TRNG. Draws the same 32 bit word twice in a row, the TRNG is considered broken for the rest of the run
+pub fn fill(dest: &mut [u8]) -> anyhow::Result<()> {
+    check()?;
+    for chunk in dest.chunks_mut(4) {
+        let word = unsafe { esp_idf_svc::sys::esp_random() };
+        if LAST_WORD.swap(word, Ordering::SeqCst) == word {
+            HEALTH.store(FAILED, Ordering::SeqCst);
+            error!("Hardware RNG repeated a word, refusing to generate keys and nonces");
+            black_box::error("entropy", ReasonCode::InternalError, "repeated word");
+            return Err(anyhow::anyhow!("hardware RNG failed the continuous test"));
+        }
+        chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+    }
+    Ok(())
+}
+
+/// Fails unless the startup health tests passed and no draw failed since
+pub fn check() -> anyhow::Result<()> {
+    match HEALTH.load(Ordering::SeqCst) {
+        HEALTHY => Ok(()),
+        UNTESTED => Err(anyhow::anyhow!("hardware RNG was not tested yet")),
+        _ => Err(anyhow::anyhow!("hardware RNG failed its health tests")),
+    }
+}
+
+/// Repetition count and adaptive proportion tests, see NIST SP 800-90B Section 4.4
+fn health_tests(samples: &[u8]) -> anyhow::Result<()> {
+    let mut run = 1;
+    for pair in samples.windows(2) {
+        run = if pair[0] == pair[1] { run + 1 } else { 1 };
+        if run >= REPETITION_CUTOFF {
+            return Err(anyhow::anyhow!("hardware RNG failed the repetition count test"));
+        }
+    }
+
+    for window in samples.chunks_exact(PROPORTION_WINDOW) {
+        let occurrences = window.iter().filter(|sample| **sample == window[0]).count();
+        if occurrences >= PROPORTION_CUTOFF {
+            return Err(anyhow::anyhow!("hardware RNG failed the adaptive proportion test"));
+        }
+    }
+
+    Ok(())
+}
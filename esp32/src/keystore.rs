@@ -0,0 +1,117 @@
+//! Private key of the IDevID.
+//!
+//! By default the key is compiled in from `data/private_key.der` and signed with ring. With the `atecc608` feature,
+//! the key is kept in an ATECC608A/B secure element on I2C instead and never exists in flash, see [`atecc608`].
+
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+#[cfg(feature = "atecc608")]
+use esp_idf_svc::hal::{
+    gpio::{InputPin, OutputPin},
+    i2c::I2c,
+    peripheral::Peripheral,
+};
+use log::info;
+
+use crate::biscuit::jws::Secret;
+
+#[cfg(feature = "atecc608")]
+mod atecc608;
+
+#[cfg(all(feature = "atecc608", feature = "p384"))]
+compile_error!("the ATECC608 only supports P-256 keys, the `atecc608` and `p384` features exclude each other");
+
+pub(crate) trait Keystore: Send + Sync {
+    /// The public key as uncompressed SEC1 point, as in the IDevID
+    fn public_key(&self) -> &[u8];
+
+    /// Signs `message` with `SIGNING_ALGORITHM`, the signature is `r || s` in the fixed encoding
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// PKCS#8 of the key, `None` if the key can not leave the keystore
+    fn pkcs8(&self) -> Option<&[u8]>;
+
+    /// Signing secret of the key for the biscuit JWS module
+    fn jws_secret(&self) -> Secret;
+}
+
+static KEYSTORE: OnceLock<Box<dyn Keystore>> = OnceLock::new();
+
+/// Loads the compiled in IDevID key
+#[cfg(not(feature = "atecc608"))]
+pub fn init() -> anyhow::Result<()> {
+    set(Box::new(SoftwareKey::from_pkcs8(include_bytes!("../data/private_key.der"))?))
+}
+
+/// Wakes the secure element on I2C and reads the public key of the IDevID from it
+#[cfg(feature = "atecc608")]
+pub fn init(
+    i2c: impl Peripheral<P = impl I2c> + 'static,
+    sda: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    scl: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+) -> anyhow::Result<()> {
+    set(Box::new(atecc608::Atecc608::new(i2c, sda, scl)?))
+}
+
+fn set(keystore: Box<dyn Keystore>) -> anyhow::Result<()> {
+    // the certificate is not parsed, it has to carry the public key somewhere
+    let public_key = keystore.public_key();
+    if !crate::IDEVID_CERTIFICATE.windows(public_key.len()).any(|window| window == public_key) {
+        return Err(anyhow!(
+            "the IDevID certificate is not issued for the key in the keystore, its public key is {}",
+            data_encoding::HEXLOWER.encode(public_key)
+        ));
+    }
+
+    info!("IDevID key loaded, the private key {} exportable", if keystore.pkcs8().is_some() { "is" } else { "is not" });
+    KEYSTORE.set(keystore).map_err(|_| anyhow!("keystore is already initialized"))
+}
+
+/// The IDevID key, available once [`init`] succeeded
+pub(crate) fn idevid_key() -> anyhow::Result<&'static dyn Keystore> {
+    KEYSTORE.get().map(AsRef::as_ref).ok_or(anyhow!("the IDevID key is not available"))
+}
+
+/// Key pair in RAM, signed with ring
+#[cfg(not(feature = "atecc608"))]
+struct SoftwareKey {
+    key_pair: std::sync::Arc<ring::signature::EcdsaKeyPair>,
+    pkcs8: &'static [u8],
+}
+
+#[cfg(not(feature = "atecc608"))]
+impl SoftwareKey {
+    fn from_pkcs8(pkcs8: &'static [u8]) -> anyhow::Result<Self> {
+        let rng = crate::biscuit::jwa::checked_rng()?;
+        let key_pair = ring::signature::EcdsaKeyPair::from_pkcs8(crate::SIGNING_ALGORITHM, pkcs8, rng)
+            .map_err(|e| anyhow!("invalid IDevID key: {}", e))?;
+        Ok(Self {
+            key_pair: std::sync::Arc::new(key_pair),
+            pkcs8,
+        })
+    }
+}
+
+#[cfg(not(feature = "atecc608"))]
+impl Keystore for SoftwareKey {
+    fn public_key(&self) -> &[u8] {
+        use ring::signature::KeyPair;
+        self.key_pair.public_key().as_ref()
+    }
+
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        // refuses to sign once the entropy health tests failed, a bad ECDSA nonce leaks the IDevID key
+        let rng = crate::biscuit::jwa::checked_rng()?;
+        let signature = self.key_pair.sign(rng, message).map_err(|_| anyhow!("signing failed"))?;
+        Ok(signature.as_ref().to_vec())
+    }
+
+    fn pkcs8(&self) -> Option<&[u8]> {
+        Some(self.pkcs8)
+    }
+
+    fn jws_secret(&self) -> Secret {
+        Secret::EcdsaKeyPair(std::sync::Arc::clone(&self.key_pair))
+    }
+}
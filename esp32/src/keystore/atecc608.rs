@@ -0,0 +1,225 @@
+//! IDevID key in slot 0 of an ATECC608A/B on I2C.
+//!
+//! The key is generated inside the chip during provisioning and slot 0 is locked, so it can only be used through
+//! the Sign command. Messages are hashed here and signed in the external message mode: the digest is loaded into
+//! TempKey with a pass-through Nonce command and signed from there.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use esp_idf_svc::hal::{
+    delay::{Ets, FreeRtos, TickType},
+    gpio::{InputPin, OutputPin},
+    i2c::{I2c, I2cConfig, I2cDriver},
+    peripheral::Peripheral,
+    units::Hertz,
+};
+use log::debug;
+
+use super::Keystore;
+use crate::biscuit::{
+    errors::Error,
+    jwa::SignatureAlgorithm,
+    jws::{EcdsaSigner, Secret},
+};
+
+const ADDRESS: u8 = 0x60;
+const SLOT: u16 = 0;
+
+/// Word addresses, the first byte of every write
+const WORD_SLEEP: u8 = 0x01;
+const WORD_COMMAND: u8 = 0x03;
+
+const OPCODE_NONCE: u8 = 0x16;
+const OPCODE_GENKEY: u8 = 0x40;
+const OPCODE_SIGN: u8 = 0x41;
+/// GenKey mode computing the public key of the private key in the slot
+const GENKEY_PUBLIC: u8 = 0x00;
+/// Nonce mode passing 32 bytes through to TempKey unchanged
+const NONCE_PASS_THROUGH: u8 = 0x03;
+/// Sign mode for a digest in TempKey that was not computed by the chip
+const SIGN_EXTERNAL: u8 = 0x80;
+
+/// Maximum execution times of the commands, from the datasheet
+const GENKEY_MS: u32 = 115;
+const NONCE_MS: u32 = 7;
+const SIGN_MS: u32 = 115;
+
+/// SDA has to be high this long after the wake pulse, tWHI
+const WAKE_DELAY_US: u32 = 1500;
+/// The answer to a wake, status 0x11 and its CRC
+const WAKE_RESPONSE: [u8; 4] = [0x04, 0x11, 0x33, 0x43];
+const BUS_TIMEOUT_MS: u32 = 50;
+
+#[derive(Clone)]
+pub(super) struct Atecc608 {
+    device: Arc<Mutex<I2cDriver<'static>>>,
+    /// Uncompressed SEC1 point of the key in `SLOT`
+    public_key: Arc<[u8]>,
+}
+
+impl Atecc608 {
+    pub(super) fn new(
+        i2c: impl Peripheral<P = impl I2c> + 'static,
+        sda: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+        scl: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    ) -> anyhow::Result<Self> {
+        // the wake pulse has to be at least 60 µs low, a zero byte is long enough at 100 kHz
+        let config = I2cConfig::new().baudrate(Hertz(100_000));
+        let mut device = I2cDriver::new(i2c, sda, scl, &config)?;
+
+        let point = session(&mut device, |device| execute(device, OPCODE_GENKEY, GENKEY_PUBLIC, SLOT, &[], GENKEY_MS))?;
+        if point.len() != 64 {
+            return Err(anyhow!("the secure element returned a public key of {} bytes", point.len()));
+        }
+
+        Ok(Self {
+            device: Arc::new(Mutex::new(device)),
+            public_key: [&[0x04], point.as_slice()].concat().into(),
+        })
+    }
+
+    fn sign_digest(&self, digest: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut device = self.device.lock().map_err(|_| anyhow!("the secure element is poisoned"))?;
+
+        let signature = session(&mut device, |device| {
+            execute(device, OPCODE_NONCE, NONCE_PASS_THROUGH, 0, digest, NONCE_MS)?;
+            execute(device, OPCODE_SIGN, SIGN_EXTERNAL, SLOT, &[], SIGN_MS)
+        })?;
+        if signature.len() != 64 {
+            return Err(anyhow!("the secure element returned a signature of {} bytes", signature.len()));
+        }
+        Ok(signature)
+    }
+}
+
+impl Keystore for Atecc608 {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.sign_digest(ring::digest::digest(&ring::digest::SHA256, message).as_ref())
+    }
+
+    fn pkcs8(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn jws_secret(&self) -> Secret {
+        Secret::EcdsaSigner(Arc::new(self.clone()))
+    }
+}
+
+impl EcdsaSigner for Atecc608 {
+    fn sign(&self, data: &[u8], algorithm: SignatureAlgorithm) -> Result<Vec<u8>, Error> {
+        if algorithm != SignatureAlgorithm::ES256 {
+            return Err(Error::UnsupportedOperation);
+        }
+        Keystore::sign(self, data).map_err(|e| Error::GenericError(e.to_string()))
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+/// Wakes the chip, runs `commands` and puts it back to sleep. TempKey is cleared by sleep, so commands building on
+/// each other have to run in the same session.
+fn session<T>(
+    device: &mut I2cDriver<'static>,
+    commands: impl FnOnce(&mut I2cDriver<'static>) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    wake(device)?;
+    let result = commands(device);
+    if let Err(e) = device.write(ADDRESS, &[WORD_SLEEP], timeout()) {
+        debug!("Unable to put the secure element to sleep: {}", e);
+    }
+    result
+}
+
+fn wake(device: &mut I2cDriver<'static>) -> anyhow::Result<()> {
+    // SDA is held low by the zero byte to the general call address, nobody acknowledges it
+    let _ = device.write(0x00, &[0x00], timeout());
+    Ets::delay_us(WAKE_DELAY_US);
+
+    let mut response = [0u8; 4];
+    device.read(ADDRESS, &mut response, timeout())?;
+    if response != WAKE_RESPONSE {
+        return Err(anyhow!("the secure element did not wake up, it answered {:02x?}", response));
+    }
+    Ok(())
+}
+
+fn execute(
+    device: &mut I2cDriver<'static>,
+    opcode: u8,
+    p1: u8,
+    p2: u16,
+    data: &[u8],
+    execution_ms: u32,
+) -> anyhow::Result<Vec<u8>> {
+    // count, opcode, p1, p2 and the CRC, followed by the data
+    let count = u8::try_from(7 + data.len()).map_err(|_| anyhow!("command data too long"))?;
+    let mut packet = vec![WORD_COMMAND, count, opcode, p1];
+    packet.extend_from_slice(&p2.to_le_bytes());
+    packet.extend_from_slice(data);
+    let crc = crc16(&packet[1..]);
+    packet.extend_from_slice(&crc);
+    device.write(ADDRESS, &packet, timeout())?;
+
+    // the chip does not acknowledge reads before the command finished
+    FreeRtos::delay_ms(execution_ms);
+    let mut response = [0u8; 75];
+    device.read(ADDRESS, &mut response[..1], timeout())?;
+    let count = usize::from(response[0]);
+    if !(4..=response.len()).contains(&count) {
+        return Err(anyhow!("the secure element answered with a count of {}", count));
+    }
+    device.read(ADDRESS, &mut response[1..count], timeout())?;
+
+    let (packet, crc) = response[..count].split_at(count - 2);
+    if crc16(packet) != crc {
+        return Err(anyhow!("the response of the secure element has an invalid CRC"));
+    }
+    match packet {
+        [4, 0x00] => Ok(Vec::new()),
+        [4, status] => Err(anyhow!("the secure element failed command {:#04x}: {}", opcode, status_message(*status))),
+        [_, data @ ..] => Ok(data.to_vec()),
+        [] => unreachable!("the count is at least 4"),
+    }
+}
+
+fn status_message(status: u8) -> &'static str {
+    match status {
+        0x01 => "verify miscompare",
+        0x03 => "parse error",
+        0x05 => "ECC fault",
+        0x07 => "self test error",
+        0x0f => "execution error",
+        0x11 => "woke up without command",
+        0xee => "watchdog about to expire",
+        0xff => "CRC or communication error",
+        _ => "unknown status",
+    }
+}
+
+/// CRC-16 of the chip, polynomial 0x8005 over the bits LSB first, little endian
+fn crc16(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0;
+    for byte in data {
+        for bit in 0..8 {
+            let data_bit = u16::from(byte >> bit) & 1;
+            let crc_bit = crc >> 15;
+            crc <<= 1;
+            if data_bit != crc_bit {
+                crc ^= 0x8005;
+            }
+        }
+    }
+    crc.to_le_bytes()
+}
+
+fn timeout() -> u32 {
+    TickType::new_millis(BUS_TIMEOUT_MS.into()).ticks()
+}
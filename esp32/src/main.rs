@@ -1,19 +1,31 @@
 
 #![feature(lazy_cell)]
-use std::sync::{Arc, LazyLock};
 
 
 use ble_async::run_ble;
+#[cfg(feature = "usb")]
+use usb_async::run_usb;
 
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 
 use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
 use esp_idf_svc::{hal::peripherals::Peripherals, nvs::EspDefaultNvsPartition};
 use tokio::join;
+mod black_box;
 mod ble_async;
+mod credential_store;
+mod csr;
+mod entropy;
+mod keystore;
+#[cfg(feature = "ota")]
+mod ota;
+mod prm;
 mod tpvr;
+mod trust_store;
+#[cfg(feature = "usb")]
+mod usb_async;
 mod wifi_async;
-use log::info;
+use log::{error, info, warn};
 
 use wifi_async::{run_wifi};
 
@@ -21,32 +33,18 @@ use wifi_async::{run_wifi};
 //static CERTIFICATE : &[u8] = include_bytes!("../data/pledge.der");
 //static PRIVATE_KEY : &[u8] = include_bytes!("../data/key.der");
 
-struct Credentials {
-    certificate: &'static [u8],
-    private_key: Arc<ring::signature::EcdsaKeyPair>,
-}
+/// Curve of the IDevID in `data/`, P-384 is selected with the `p384` feature
+#[cfg(not(feature = "p384"))]
+static SIGNING_ALGORITHM: &ring::signature::EcdsaSigningAlgorithm = &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+#[cfg(not(feature = "p384"))]
+const JWS_ALGORITHM: biscuit::jwa::SignatureAlgorithm = biscuit::jwa::SignatureAlgorithm::ES256;
+#[cfg(feature = "p384")]
+static SIGNING_ALGORITHM: &ring::signature::EcdsaSigningAlgorithm = &ring::signature::ECDSA_P384_SHA384_FIXED_SIGNING;
+#[cfg(feature = "p384")]
+const JWS_ALGORITHM: biscuit::jwa::SignatureAlgorithm = biscuit::jwa::SignatureAlgorithm::ES384;
 
-static CREDENTIALS: LazyLock<Credentials> = LazyLock::new(|| {
-    //let private_key = ring::signature::EcdsaKeyPair::from_pkcs8(&ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING, PRIVATE_KEY, &ring::rand::SystemRandom::new()).unwrap();
-    let rng = ring::rand::SystemRandom::new();
-    let key_data = include_bytes!("../data/private_key.der");
-    // convert key_data to ring format 
-    let keypair = ring::signature::EcdsaKeyPair::from_pkcs8(
-        &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
-        key_data.as_ref(),
-        &rng,
-    )
-    .unwrap();
-
-    
-
-    let private_key = Arc::new(keypair);
-    let certificate = include_bytes!("../data/pledge.der");
-    Credentials {
-        certificate,
-        private_key,
-    }
-});
+/// IDevID certificate, issued for the key in the [`keystore`]
+static IDEVID_CERTIFICATE: &[u8] = include_bytes!("../data/pledge.der");
 
 
 mod biscuit;
@@ -57,6 +55,7 @@ fn main() {
     esp_idf_svc::sys::link_patches();
 
     // Bind the log crate to the ESP Logging facilities
+    #[cfg(feature = "console")]
     esp_idf_svc::log::EspLogger::initialize_default();
 
     // eventfd is needed by our mio poll implementation.  Note you should set max_fds
@@ -80,13 +79,42 @@ async fn run() {
     let timer = esp_idf_svc::timer::EspTaskTimerService::new().unwrap();
     let nvs = EspDefaultNvsPartition::take().expect("Unable to gather NVS partition");
 
+    if let Err(e) = black_box::init(nvs.clone()) {
+        warn!("Unable to open the black box, onboarding will not be recorded: {}", e);
+    }
+    // before the radio is started, see `entropy::init`
+    if let Err(e) = entropy::init() {
+        error!("Hardware RNG is unusable, keys and nonces will not be generated: {}", e);
+    }
+    #[cfg(not(feature = "atecc608"))]
+    let idevid = keystore::init();
+    #[cfg(feature = "atecc608")]
+    let idevid = keystore::init(peripherals.i2c0, peripherals.pins.gpio8, peripherals.pins.gpio9);
+    if let Err(e) = idevid {
+        error!("IDevID key is unavailable, voucher requests will not be signed: {}", e);
+    }
+    if let Err(e) = credential_store::init(nvs.clone()) {
+        warn!("Unable to open the credential store, domain credentials will not survive a reboot: {}", e);
+    }
+    #[cfg(feature = "ota")]
+    if let Err(e) = ota::init(nvs.clone()) {
+        warn!("Unable to open the OTA state, the firmware will not be updated: {}", e);
+    }
+
     let esp_wifi = EspWifi::new(peripherals.modem, sysloop.clone(), Some(nvs))
         .expect("Unable to gather EspWifi");
 
     let wifi = AsyncWifi::wrap(esp_wifi, sysloop, timer).expect("Unable to gather AsyncWifi");
 
+    // the USB exchange blocks on reads, so it runs on its own thread
+    #[cfg(feature = "usb")]
+    let _usb = run_usb(peripherals.usb_serial, peripherals.pins.gpio19, peripherals.pins.gpio20);
+
     info!("Starting async run loop");
 
+    #[cfg(feature = "ota")]
+    join!(run_wifi(wifi), run_ble(), ota::run_ota());
+    #[cfg(not(feature = "ota"))]
     join!(run_wifi(wifi), run_ble());
 
     loop {
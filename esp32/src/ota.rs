@@ -0,0 +1,290 @@
+//! Over-the-air updates of the firmware and the MASA trust anchor once the pledge is onboarded.
+//!
+//! Updates are fetched over HTTPS with the LDevID as client certificate, the server has to chain to the domain CA or
+//! the CA certificates of the domain.
+//! Each artifact comes with a detached signature at `<url>.sig`, made by the domain CA or a signer it issued. A
+//! 3 MB image does not fit in RAM and ring only verifies whole messages, so the signature is made over the SHA-256
+//! digest of the artifact:
+//!
+//! ```sh
+//! openssl dgst -sha256 -binary fw.bin > fw.sha256
+//! openssl dgst -sha256 -sign signer.key -out fw.bin.sig fw.sha256
+//! ```
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+use chrono::Utc;
+use data_encoding::BASE64;
+use embedded_svc::http::client::Client;
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::io::{Read, Write};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::ota::EspOta;
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_svc::tls::X509;
+use log::{info, warn};
+use ring::digest;
+
+use crate::biscuit::x509::{Certificate, TrustStore};
+use crate::black_box;
+use crate::credential_store::{self, DomainCredentials};
+use crate::wifi_async::wait_online;
+
+/// HTTPS URL of the firmware image, set at build time. Without it the firmware is not updated.
+const FIRMWARE_URL: Option<&str> = option_env!("BRSKI_OTA_URL");
+/// HTTPS URL of the DER encoded MASA trust anchor, set at build time. Without it the anchor is not updated.
+const MASA_ANCHOR_URL: Option<&str> = option_env!("BRSKI_OTA_MASA_ANCHOR_URL");
+/// Base64 encoded DER certificate of the update signer, set at build time. It has to chain to the domain CA,
+/// without it the updates have to be signed by the domain CA itself.
+const SIGNER: Option<&str> = option_env!("BRSKI_OTA_SIGNER");
+
+/// NVS namespace of the update state
+const NAMESPACE: &str = "ota";
+/// Signature of the installed image, an image with the same signature is not downloaded again
+const INSTALLED_KEY: &str = "installed_sig";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Time to wait for SNTP, certificate validity can not be checked before
+const CLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// TLS and signature checks need more than the default stack of a thread
+const STACK_SIZE: usize = 16 * 1024;
+const CHUNK_SIZE: usize = 4 * 1024;
+/// Large enough for a signature of an RSA key with 8192 bits
+const MAX_SIGNATURE_LENGTH: usize = 1024;
+const MAX_ANCHOR_LENGTH: usize = 8 * 1024;
+
+static NVS: OnceLock<Mutex<EspNvs<NvsDefault>>> = OnceLock::new();
+
+/// Certificates handed to the HTTP client, which needs them for `'static`. They are leaked once per enrollment.
+static TLS_CREDENTIALS: Mutex<Option<(Vec<u8>, TlsCredentials)>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct TlsCredentials {
+    ldevid: &'static [u8],
+    private_key: &'static [u8],
+    /// NUL terminated PEM bundle of [`DomainCredentials::ca_bundle`]
+    ca_bundle: &'static [u8],
+}
+
+/// Opens the namespace of the update state
+pub fn init(partition: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+    if NVS.set(Mutex::new(nvs)).is_err() {
+        return Err(anyhow!("OTA state is already initialized"));
+    }
+    Ok(())
+}
+
+/// Accepts the running image once the pledge is online and checks for updates every [`CHECK_INTERVAL`] after the
+/// onboarding. A new firmware is booted right away.
+pub async fn run_ota() {
+    wait_online().await;
+    // a new image that does not get online is rolled back by the bootloader on the next reset
+    if let Err(e) = EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+        warn!("Unable to mark the running firmware as valid: {}", e);
+    }
+
+    let sntp = match EspSntp::new_default() {
+        Ok(sntp) => sntp,
+        Err(e) => {
+            warn!("Unable to start SNTP, updates are disabled: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        wait_online().await;
+        match credential_store::domain_credentials() {
+            None => info!("Not onboarded, skipping the update check"),
+            Some(_) if !synchronized(&sntp).await => warn!("Clock is not synchronized, skipping the update check"),
+            Some(credentials) => {
+                if let Err(e) = check_in_thread(credentials).await {
+                    warn!("Update check failed: {}", e);
+                    black_box::error("ota", ReasonCode::InternalError, &e);
+                }
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+async fn synchronized(sntp: &EspSntp<'_>) -> bool {
+    let deadline = tokio::time::Instant::now() + CLOCK_TIMEOUT;
+    while sntp.get_sync_status() != SyncStatus::Completed {
+        if tokio::time::Instant::now() > deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    true
+}
+
+/// The blocking HTTP client and the flash writes run on their own thread
+async fn check_in_thread(credentials: DomainCredentials) -> anyhow::Result<()> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let _ = sender.send(check(&credentials));
+    })?;
+    receiver.await?
+}
+
+fn check(credentials: &DomainCredentials) -> anyhow::Result<()> {
+    let signer = signer(credentials)?;
+
+    if let Some(url) = MASA_ANCHOR_URL {
+        update_masa_anchor(credentials, &signer, url)?;
+    }
+    if let Some(url) = FIRMWARE_URL {
+        update_firmware(credentials, &signer, url)?;
+    }
+    Ok(())
+}
+
+/// The domain CA, or the signer compiled in if it chains to the domain CA over the CA certificates of the domain and
+/// is valid
+fn signer(credentials: &DomainCredentials) -> anyhow::Result<Certificate> {
+    let Some(encoded) = SIGNER else {
+        return Ok(Certificate::from_der(&credentials.domain_ca)?);
+    };
+
+    let signer = Certificate::from_der(&BASE64.decode(encoded.as_bytes())?)?;
+    let intermediates = credentials
+        .cacerts
+        .iter()
+        .map(|der| Certificate::from_der(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    TrustStore::from_der([&credentials.domain_ca])?.verify(&signer, &intermediates, Utc::now())?;
+    Ok(signer)
+}
+
+fn update_masa_anchor(credentials: &DomainCredentials, signer: &Certificate, url: &str) -> anyhow::Result<()> {
+    let mut client = client(credentials)?;
+    let anchor = fetch(&mut client, url, MAX_ANCHOR_LENGTH)?;
+    let signature = fetch(&mut client, &format!("{url}.sig"), MAX_SIGNATURE_LENGTH)?;
+
+    signer.verify_signature(digest::digest(&digest::SHA256, &anchor).as_ref(), &signature)?;
+    // only anchors the voucher verification can use are installed
+    TrustStore::from_der([&anchor])?;
+
+    if credential_store::masa_anchor().as_ref() == Some(&anchor) {
+        info!("MASA trust anchor is up to date");
+        return Ok(());
+    }
+    credential_store::persist_masa_anchor(&anchor)
+}
+
+/// Writes the image to the next OTA slot while hashing it, and boots it once its signature is verified
+fn update_firmware(credentials: &DomainCredentials, signer: &Certificate, url: &str) -> anyhow::Result<()> {
+    let mut client = client(credentials)?;
+    let signature = fetch(&mut client, &format!("{url}.sig"), MAX_SIGNATURE_LENGTH)?;
+    if installed_signature()?.as_ref() == Some(&signature) {
+        info!("Firmware is up to date");
+        return Ok(());
+    }
+
+    let mut response = client.get(url)?.submit()?;
+    if response.status() != 200 {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut length = 0;
+    let written: anyhow::Result<()> = loop {
+        let count = match response.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(count) => count,
+            Err(e) => break Err(e.into()),
+        };
+        context.update(&buffer[..count]);
+        if let Err(e) = update.write_all(&buffer[..count]) {
+            break Err(e.into());
+        }
+        length += count;
+    };
+
+    let verified = written.and_then(|()| {
+        signer.verify_signature(context.finish().as_ref(), &signature)?;
+        Ok(())
+    });
+    if let Err(e) = verified {
+        update.abort()?;
+        return Err(e);
+    }
+
+    // sets the boot partition to the new slot
+    update.complete()?;
+    NVS.get().ok_or(anyhow!("OTA state is not initialized"))?.lock().unwrap().set_blob(INSTALLED_KEY, &signature)?;
+    black_box::artifact("firmware-signature", &signature);
+
+    info!("Installed firmware with {} bytes from {}, restarting", length, url);
+    esp_idf_svc::hal::reset::restart();
+}
+
+fn installed_signature() -> anyhow::Result<Option<Vec<u8>>> {
+    let nvs = NVS.get().ok_or(anyhow!("OTA state is not initialized"))?.lock().unwrap();
+    let Some(length) = nvs.blob_len(INSTALLED_KEY)? else {
+        return Ok(None);
+    };
+    let mut signature = vec![0u8; length];
+    nvs.get_blob(INSTALLED_KEY, &mut signature)?;
+    Ok(Some(signature))
+}
+
+/// HTTPS client authenticated with the LDevID, the server is verified against the CA bundle of the domain
+fn client(credentials: &DomainCredentials) -> anyhow::Result<Client<EspHttpConnection>> {
+    if credentials.private_key.is_empty() {
+        return Err(anyhow!("TLS needs the private key of the LDevID, it is kept in the secure element"));
+    }
+    let tls = tls_credentials(credentials);
+
+    let connection = EspHttpConnection::new(&Configuration {
+        client_certificate: Some(X509::der(tls.ldevid)),
+        private_key: Some(X509::der(tls.private_key)),
+        server_certificate: Some(X509::pem_until_nul(tls.ca_bundle)),
+        ..Default::default()
+    })?;
+    Ok(Client::wrap(connection))
+}
+
+fn tls_credentials(credentials: &DomainCredentials) -> TlsCredentials {
+    let mut leaked = TLS_CREDENTIALS.lock().unwrap();
+    match &*leaked {
+        Some((ldevid, tls)) if *ldevid == credentials.ldevid => *tls,
+        _ => {
+            let tls = TlsCredentials {
+                ldevid: credentials.ldevid.clone().leak(),
+                private_key: credentials.private_key.clone().leak(),
+                ca_bundle: credentials.ca_bundle().leak(),
+            };
+            *leaked = Some((credentials.ldevid.clone(), tls));
+            tls
+        }
+    }
+}
+
+/// Reads the body of a `GET` of `url`, at most `limit` bytes
+fn fetch(client: &mut Client<EspHttpConnection>, url: &str, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let mut response = client.get(url)?.submit()?;
+    if response.status() != 200 {
+        return Err(anyhow!("{} answered {}", url, response.status()));
+    }
+
+    let mut body = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        let count = response.read(&mut buffer)?;
+        if count == 0 {
+            return Ok(body);
+        }
+        if body.len() + count > limit {
+            return Err(anyhow!("{} is larger than {} bytes", url, limit));
+        }
+        body.extend_from_slice(&buffer[..count]);
+    }
+}
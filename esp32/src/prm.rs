@@ -0,0 +1,291 @@
+//! BRSKI-PRM responder exchanges of the pledge after the tPVR, shared by the BLE and USB transports.
+//! Requests and responses are the bodies of the matching HTTP endpoints of the Linux pledge.
+
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use brski_prm_artifacts::ietf_voucher::artifact::VoucherArtifact;
+use brski_prm_artifacts::ietf_voucher::pki::X509Req;
+use brski_prm_artifacts::network::NetworkProfile;
+use brski_prm_artifacts::per::response_payload::{ResponsePayload, ResponsePayloadInner};
+use brski_prm_artifacts::status::pledge::status::{PledgeStatus, PledgeStatusDetails, StatusContext, StatusQuery};
+use brski_prm_artifacts::status::reason_code::ReasonCode;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::Serialize;
+
+use crate::biscuit::errors::{Error, ValidationError};
+use crate::biscuit::jws::General;
+use crate::biscuit::x509::TrustStore;
+use crate::credential_store::{self, DomainCredentials};
+use crate::tpvr::SERIAL_NUMBER;
+use crate::wifi_async::{self, Connectivity};
+use crate::{black_box, csr, keystore, trust_store};
+
+/// Progress of the onboarding since boot
+struct Onboarding {
+    /// Nonce of the last voucher request, the voucher has to carry it
+    nonce: Option<Vec<u8>>,
+    /// pinned-domain-cert of the accepted voucher
+    domain_ca: Option<Vec<u8>>,
+    /// CA certificates of the domain anchored in `domain_ca`
+    cacerts: Vec<Vec<u8>>,
+    status: Option<(PledgeStatusDetails, ReasonCode)>,
+}
+
+static ONBOARDING: Mutex<Onboarding> = Mutex::new(Onboarding {
+    nonce: None,
+    domain_ca: None,
+    cacerts: Vec::new(),
+    status: None,
+});
+
+/// Runs an exchange and records request, response and failure in the black box
+pub(crate) fn recorded(
+    step: &str,
+    request: &[u8],
+    exchange: impl FnOnce(&[u8]) -> anyhow::Result<Vec<u8>>,
+) -> anyhow::Result<Vec<u8>> {
+    black_box::artifact(&format!("{}-request", step), request);
+
+    match exchange(request) {
+        Ok(response) => {
+            black_box::artifact(&format!("{}-response", step), &response);
+            Ok(response)
+        }
+        Err(e) => {
+            let reason_code = if e.is::<serde_json::Error>() {
+                ReasonCode::MalformedArtifact
+            } else {
+                ReasonCode::InternalError
+            };
+            black_box::error(step, reason_code, &e);
+            Err(e)
+        }
+    }
+}
+
+/// Signs the canonically serialized payload with the IDevID in general JWS syntax
+pub(crate) fn sign(payload: &impl Serialize) -> anyhow::Result<String> {
+    let payload = ietf_voucher::canonical::to_vec(payload)?;
+
+    let header = crate::biscuit::jws::Header::<crate::biscuit::Empty>::from(
+        crate::biscuit::jws::RegisteredHeader {
+            algorithm: crate::JWS_ALGORITHM,
+            media_type: Some("JWT".to_string()),
+            ..Default::default()
+        }
+        .with_x509_chain(&[crate::IDEVID_CERTIFICATE]),
+    );
+
+    let signable = crate::biscuit::jws::Signable::new(header, payload)?;
+    Ok(signable.sign(keystore::idevid_key()?.jws_secret())?.serialize_general())
+}
+
+/// Remembers the nonce of the voucher request just handed out
+pub(crate) fn voucher_requested(nonce: Option<Vec<u8>>) {
+    let mut onboarding = ONBOARDING.lock().unwrap();
+    onboarding.nonce = nonce;
+    onboarding.domain_ca = None;
+    onboarding.cacerts.clear();
+}
+
+/// Answers the PER trigger with a certification request for the IDevID key, like the Linux pledge
+pub(crate) fn signed_per(trigger: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let trigger: brski_prm_artifacts::per::trigger::Trigger = serde_json::from_slice(trigger)?;
+    info!("PER trigger: {}", trigger);
+
+    let csr = csr::certification_request(keystore::idevid_key()?, SERIAL_NUMBER)?;
+    let payload = ResponsePayload {
+        csr: ResponsePayloadInner {
+            p10_csr: X509Req::try_from(csr)?,
+        },
+        ssh_host_key: None,
+    };
+
+    Ok(sign(&payload)?.into_bytes())
+}
+
+/// Accepts the voucher if it is issued for this pledge and the last voucher request, and answers with the voucher status.
+/// The MASA signature is only verified once an OTA update installed a MASA trust anchor, see [`verify_masa`].
+pub(crate) fn voucher_status(voucher: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let signed = General::deserialize(voucher)?;
+    let voucher: VoucherArtifact = signed.deserialize_json_payload()?;
+    let trusted = match credential_store::masa_anchor() {
+        Some(anchor) => verify_masa(&signed, &anchor, voucher.details.created_on),
+        None => Ok(()),
+    };
+
+    let mut onboarding = ONBOARDING.lock().unwrap();
+    let checked = if let Err(reason_code) = trusted {
+        Err(reason_code)
+    } else if voucher.details.serial_number != SERIAL_NUMBER {
+        Err(ReasonCode::SerialNumberMismatch)
+    } else if onboarding.nonce.is_none() || voucher.details.nonce != onboarding.nonce {
+        Err(ReasonCode::NonceMismatch)
+    } else {
+        voucher
+            .details
+            .pinned_domain_cert
+            .map(|cert| cert.as_ref().to_vec())
+            .ok_or(ReasonCode::MissingPinnedDomainCert)
+    };
+
+    let reason_code = match checked {
+        Ok(domain_ca) => {
+            info!("Accepted voucher, pinned domain certificate with {} bytes", domain_ca.len());
+            onboarding.domain_ca = Some(domain_ca);
+            onboarding.cacerts.clear();
+            onboarding.status = Some((PledgeStatusDetails::VoucherSuccess, ReasonCode::Success));
+            ReasonCode::Success
+        }
+        Err(reason_code) => {
+            info!("Rejected voucher: {}", reason_code);
+            black_box::error("voucher", reason_code, "voucher rejected");
+            onboarding.status = Some((PledgeStatusDetails::VoucherError, reason_code));
+            reason_code
+        }
+    };
+
+    let status = brski_prm_artifacts::status::voucher::status::Status {
+        status: reason_code.is_success(),
+        reason: Some(reason_code.to_string()),
+        reason_code: Some(reason_code),
+        reason_context: brski_prm_artifacts::status::voucher::status::ReasonContext {
+            pvs_details: "JSON".to_string(),
+        },
+        ..Default::default()
+    };
+    Ok(sign(&status)?.into_bytes())
+}
+
+/// Verifies the MASA signature with the chain in its `x5c` header. The pledge only has a clock once the OTA check
+/// synchronized it, so the validity periods are checked at the creation time of the voucher if that is later.
+fn verify_masa(voucher: &General, anchor: &[u8], created_on: Option<DateTime<Utc>>) -> Result<(), ReasonCode> {
+    let trust_store = TrustStore::from_der([anchor]).map_err(|e| {
+        info!("Unable to parse the MASA trust anchor: {}", e);
+        ReasonCode::InternalError
+    })?;
+    let now = Utc::now().max(created_on.unwrap_or_default());
+
+    voucher.verify_x509(0, &trust_store, now).map(|_| ()).map_err(|e| {
+        info!("MASA signature of the voucher is not trusted: {}", e);
+        match e {
+            Error::ValidationError(
+                ValidationError::UntrustedCertificateChain | ValidationError::MissingCertificateChain,
+            ) => ReasonCode::UntrustedMasa,
+            _ => ReasonCode::InvalidSignature,
+        }
+    })
+}
+
+/// Keeps the CA certificates of the domain on the path to the pinned-domain-cert of the accepted voucher, if the
+/// registrar signature chains to it. They are stored with the LDevID.
+pub(crate) fn accept_ca_certs(cacerts: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut onboarding = ONBOARDING.lock().unwrap();
+    let domain_ca = onboarding.domain_ca.as_ref().ok_or(anyhow!("no voucher pinned a domain yet"))?;
+
+    let cacerts = match trust_store::unwrap_cacerts(cacerts, domain_ca) {
+        Ok(cacerts) => cacerts,
+        Err(e) => {
+            black_box::error("cacerts", ReasonCode::InvalidSignature, "CA certificates rejected");
+            return Err(e.context("the wrapped CA certificates are not signed within the pinned domain"));
+        }
+    };
+    let Some(cacerts) = trust_store::anchored_chain(domain_ca, &cacerts)? else {
+        black_box::error("cacerts", ReasonCode::UntrustedCertificate, "CA certificates rejected");
+        return Err(anyhow!("CA certificates are not anchored in the pinned-domain-cert"));
+    };
+
+    info!("Accepted {} CA certificates of the domain", cacerts.len());
+    onboarding.cacerts = cacerts;
+    Ok(vec![])
+}
+
+/// Stores the LDevID with the pinned domain CA and answers with the enroll status
+pub(crate) fn enroll_status(ldevid: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let key = keystore::idevid_key()?;
+    let mut onboarding = ONBOARDING.lock().unwrap();
+    let public_key = key.public_key();
+
+    let stored = match &onboarding.domain_ca {
+        None => Err(ReasonCode::MissingPinnedDomainCert),
+        // the certificate is not parsed, it has to carry the public key of the certification request somewhere
+        Some(_) if !ldevid.windows(public_key.len()).any(|window| window == public_key) => Err(ReasonCode::KeyMismatch),
+        Some(domain_ca) => credential_store::persist(&DomainCredentials {
+            ldevid: ldevid.to_vec(),
+            // empty if the key is kept in the keystore
+            private_key: key.pkcs8().map(<[u8]>::to_vec).unwrap_or_default(),
+            domain_ca: domain_ca.clone(),
+            cacerts: onboarding.cacerts.clone(),
+        })
+        .map_err(|e| {
+            info!("Unable to store the domain credentials: {}", e);
+            ReasonCode::InternalError
+        }),
+    };
+
+    let reason_code = match stored {
+        Ok(()) => {
+            // a production network of an earlier domain was dropped with its credentials
+            wifi_async::provision(None);
+            onboarding.status = Some((PledgeStatusDetails::EnrollSuccess, ReasonCode::Success));
+            ReasonCode::Success
+        }
+        Err(reason_code) => {
+            black_box::error("enroll", reason_code, "LDevID rejected");
+            onboarding.status = Some((PledgeStatusDetails::EnrollError, reason_code));
+            reason_code
+        }
+    };
+
+    let status = brski_prm_artifacts::status::enroll::status::Status {
+        status: reason_code.is_success(),
+        reason: reason_code.to_string(),
+        reason_code: Some(reason_code),
+        ..Default::default()
+    };
+    Ok(sign(&status)?.into_bytes())
+}
+
+/// Stores the production network handed out by the agent after the enrollment, the Wi-Fi loop switches to it
+pub(crate) fn network_profile(profile: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let profile: NetworkProfile = serde_json::from_slice(profile)?;
+    profile.validate()?;
+
+    credential_store::persist_network_profile(&profile)?;
+    info!("Provisioned production network {}", profile.ssid);
+    wifi_async::provision(Some(profile));
+    Ok(vec![])
+}
+
+/// Answers the status query of the agent, the query itself is not verified like on the Linux pledge
+pub(crate) fn pledge_status(query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let _query: StatusQuery = General::deserialize(query)?.deserialize_json_payload()?;
+
+    let status = match (&*wifi_async::connectivity().borrow(), ONBOARDING.lock().unwrap().status.clone()) {
+        (Connectivity::CaptivePortal { detail, .. }, _) => PledgeStatus {
+            status: PledgeStatusDetails::ConnectError,
+            reason: Some(ReasonCode::CaptivePortal.to_string()),
+            reason_code: Some(ReasonCode::CaptivePortal),
+            reason_context: Some(StatusContext {
+                pvs_details: detail.clone(),
+            }),
+            ..Default::default()
+        },
+        (_, Some((status, reason_code))) => PledgeStatus {
+            status,
+            reason: Some(reason_code.to_string()),
+            reason_code: Some(reason_code),
+            ..Default::default()
+        },
+        // onboarded before the last reboot
+        (_, None) if credential_store::domain_credentials().is_some() => PledgeStatus {
+            status: PledgeStatusDetails::EnrollSuccess,
+            ..Default::default()
+        },
+        (_, None) => PledgeStatus::default(),
+    };
+
+    Ok(sign(&status)?.into_bytes())
+}
@@ -0,0 +1,88 @@
+//! Domain CA certificates of the pledge (RFC 8995 Section 5.9.1)
+//!
+//! The pinned-domain-cert of the voucher only covers the registrar. After the voucher, the registrar-agent hands the
+//! pledge the CA certificates of the domain, wrapped and signed by the registrar. The signature has to chain to the
+//! pinned-domain-cert, and only the certificates on the validated path to the pinned-domain-cert are installed. They
+//! are trusted next to it for TLS and EAP-TLS afterwards.
+
+use chrono::{DateTime, Utc};
+use data_encoding::BASE64;
+use serde::Deserialize;
+
+use crate::biscuit::errors::{Error, ValidationError};
+use crate::biscuit::jws::General;
+use crate::biscuit::x509::{Certificate, TrustStore};
+
+/// Payload of the wrapped CA certificates, the DER certificates base64 encoded with the standard alphabet
+#[derive(Deserialize)]
+struct WrappedCaCerts {
+    x5bag: Vec<String>,
+}
+
+/// The DER encoded certificates of the wrapped CA certificates, once the JWS signature is verified with the signer of
+/// its `x5c` header chaining to the pinned-domain-cert
+pub fn unwrap_cacerts(wrapped: &[u8], pinned_domain_cert: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let signed = General::deserialize(wrapped)?;
+    let trust_store = TrustStore::from_der([pinned_domain_cert])?;
+    signed.verify_x509(0, &trust_store, validation_time(&Certificate::from_der(pinned_domain_cert)?))?;
+
+    let payload: WrappedCaCerts = signed.deserialize_json_payload()?;
+    let cacerts = payload
+        .x5bag
+        .iter()
+        .map(|encoded| BASE64.decode(encoded.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(cacerts)
+}
+
+/// The pledge only has a clock once the OTA check synchronized it, so validity is checked at the start of the
+/// pinned-domain-cert if that is later
+fn validation_time(pinned_domain_cert: &Certificate) -> DateTime<Utc> {
+    Utc::now().max(pinned_domain_cert.not_before())
+}
+
+/// The certificates of the bundle anchored in the pinned-domain-cert: the pinned-domain-cert itself if the bundle
+/// contains it, otherwise the path from the pinned-domain-cert to a root of the bundle, e.g. when the voucher pinned the
+/// registrar certificate instead of the domain CA. `None` if the bundle is not anchored, any certificate off the path
+/// is dropped.
+pub fn anchored_chain(pinned_domain_cert: &[u8], cacerts: &[Vec<u8>]) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    if cacerts.iter().any(|certificate| certificate == pinned_domain_cert) {
+        return Ok(Some(vec![pinned_domain_cert.to_vec()]));
+    }
+
+    let pinned_domain_cert = Certificate::from_der(pinned_domain_cert)?;
+    let certificates = cacerts
+        .iter()
+        .map(|der| Certificate::from_der(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    let roots = certificates
+        .iter()
+        .filter(|certificate| certificate.subject() == certificate.issuer())
+        .map(Certificate::der);
+    let trust_store = TrustStore::from_der(roots)?;
+    if trust_store.is_empty() {
+        return Ok(None);
+    }
+
+    match trust_store.verify_path(&pinned_domain_cert, &certificates, validation_time(&pinned_domain_cert)) {
+        Ok(path) => Ok(Some(path.iter().map(|certificate| certificate.der().to_vec()).collect())),
+        Err(Error::ValidationError(ValidationError::UntrustedCertificateChain)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The certificates as PEM, terminated by NUL as mbedTLS expects it for TLS and EAP-TLS
+pub fn pem_bundle<C: AsRef<[u8]>>(certificates: impl IntoIterator<Item = C>) -> Vec<u8> {
+    let mut pem = String::new();
+    for certificate in certificates {
+        pem.push_str("-----BEGIN CERTIFICATE-----\n");
+        for line in BASE64.encode(certificate.as_ref()).as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+    }
+    let mut pem = pem.into_bytes();
+    pem.push(0);
+    pem
+}
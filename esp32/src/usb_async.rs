@@ -0,0 +1,112 @@
+use consts::usb::{
+    CA_CERTS, ENROLL_RESPONSE, ERROR_FLAG, FRAME_MAGIC, HEADER_LENGTH, IDENTIFY, MAX_PAYLOAD_LENGTH, STATUS, TPER, TPVR,
+    VOUCHER,
+};
+use esp_idf_svc::hal::delay::BLOCK;
+use esp_idf_svc::hal::usb_serial::{UsbDMinGpio, UsbDPlusGpio, UsbSerial, UsbSerialConfig, UsbSerialDriver};
+use esp_idf_svc::sys::EspError;
+use log::{info, warn};
+
+use crate::{prm, tpvr};
+
+/// Signing the PVR needs more than the default stack of a thread
+const STACK_SIZE: usize = 16 * 1024;
+
+/// Answers the PRM exchanges the registrar-agent sends as frames over the USB Serial/JTAG port,
+/// the wired counterpart of the BLE services for production line fixtures. See `consts::usb` for the framing.
+pub fn run_usb(usb_serial: UsbSerial, d_minus: UsbDMinGpio, d_plus: UsbDPlusGpio) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut driver = match UsbSerialDriver::new(usb_serial, d_minus, d_plus, &UsbSerialConfig::new()) {
+                Ok(driver) => driver,
+                Err(e) => {
+                    warn!("Unable to install the USB serial driver: {}", e);
+                    return;
+                }
+            };
+            info!("Waiting for PRM frames on USB");
+
+            loop {
+                let (exchange, request) = match read_frame(&mut driver) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        warn!("Error reading from USB: {}", e);
+                        continue;
+                    }
+                };
+                info!("Received USB frame of exchange {} with length {}", exchange, request.len());
+
+                let (exchange, response) = match handle(exchange, &request) {
+                    Ok(response) => (exchange, response),
+                    Err(e) => {
+                        info!("Error answering USB frame: {}", e);
+                        (exchange | ERROR_FLAG, e.to_string().into_bytes())
+                    }
+                };
+
+                if let Err(e) = write_frame(&mut driver, exchange, &response) {
+                    warn!("Error writing to USB: {}", e);
+                }
+            }
+        })
+        .unwrap()
+}
+
+fn handle(exchange: u8, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match exchange {
+        IDENTIFY => Ok(tpvr::SERIAL_NUMBER.as_bytes().to_vec()),
+        TPVR => prm::recorded("tpvr", request, tpvr::signed_pvr),
+        TPER => prm::recorded("tper", request, prm::signed_per),
+        VOUCHER => prm::recorded("voucher", request, prm::voucher_status),
+        CA_CERTS => prm::recorded("cacerts", request, prm::accept_ca_certs),
+        ENROLL_RESPONSE => prm::recorded("enroll", request, prm::enroll_status),
+        STATUS => prm::recorded("status", request, prm::pledge_status),
+        _ => Err(anyhow::anyhow!("exchange {} is not supported by this pledge", exchange)),
+    }
+}
+
+/// Skips everything up to the next frame magic, then reads header and payload
+fn read_frame(driver: &mut UsbSerialDriver<'_>) -> Result<(u8, Vec<u8>), EspError> {
+    let mut header = [0u8; HEADER_LENGTH];
+    loop {
+        read_exact(driver, &mut header[..1])?;
+        if header[0] != FRAME_MAGIC {
+            continue;
+        }
+
+        read_exact(driver, &mut header[1..])?;
+        let length = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        if length > MAX_PAYLOAD_LENGTH {
+            warn!("Dropping USB frame announcing {} bytes", length);
+            continue;
+        }
+
+        let mut payload = vec![0u8; length];
+        read_exact(driver, &mut payload)?;
+        return Ok((header[1], payload));
+    }
+}
+
+fn read_exact(driver: &mut UsbSerialDriver<'_>, mut buf: &mut [u8]) -> Result<(), EspError> {
+    while !buf.is_empty() {
+        let read = driver.read(buf, BLOCK)?;
+        buf = &mut buf[read..];
+    }
+    Ok(())
+}
+
+fn write_frame(driver: &mut UsbSerialDriver<'_>, exchange: u8, payload: &[u8]) -> Result<(), EspError> {
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    frame.push(FRAME_MAGIC);
+    frame.push(exchange);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    let mut remaining = frame.as_slice();
+    while !remaining.is_empty() {
+        let written = driver.write(remaining, BLOCK)?;
+        remaining = &remaining[written..];
+    }
+    Ok(())
+}
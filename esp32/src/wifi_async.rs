@@ -1,23 +1,41 @@
 
-use embedded_svc::wifi::{ClientConfiguration, Configuration};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use brski_prm_artifacts::network::{NetworkProfile, NetworkSecurity};
+use embedded_svc::wifi::{AccessPointInfo, AuthMethod, ClientConfiguration, Configuration};
 
 
 
 
 use esp_idf_svc::wifi::{AsyncWifi, EspWifi};
 
-use esp_idf_svc::sys::{EspError};
-use log::info;
+use esp_idf_svc::sys::{
+  esp, esp_eap_client_set_ca_cert, esp_eap_client_set_certificate_and_key, esp_eap_client_set_identity,
+  esp_wifi_sta_enterprise_disable, esp_wifi_sta_enterprise_enable, esp_wifi_sta_get_ap_info, wifi_ap_record_t, EspError,
+  ESP_ERR_INVALID_ARG,
+};
+use log::{info, warn};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature = "http")]
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
 
+use crate::credential_store::{self, DomainCredentials};
 
 pub async fn run_wifi(wifi: AsyncWifi<EspWifi<'static>> ) -> tokio::task::JoinHandle<()> {
 
   tokio::spawn(async move {
-    let mut wifi_loop = WifiLoop { wifi };
-    wifi_loop.configure().await.unwrap();
-    wifi_loop.initial_connect().await.unwrap();
+    let mut wifi_loop = WifiLoop {
+      wifi,
+      policy: BootstrapPolicy::default(),
+      production: PRODUCTION_NETWORK.subscribe(),
+      current: None,
+      eap_credentials: None,
+    };
+    wifi_loop.start().await.unwrap();
+    wifi_loop.initial_connect().await;
 
     info!("Preparing to launch echo server...");
     //tokio::spawn(echo_server());
@@ -31,65 +49,543 @@ pub async fn run_wifi(wifi: AsyncWifi<EspWifi<'static>> ) -> tokio::task::JoinHa
 
 // To test, run `cargo run`, then when the server is up, use `nc -v espressif 12345` from
 // a machine on the same Wi-Fi network.
+#[cfg(feature = "http")]
 const TCP_LISTENING_PORT: u16 = 3001;
-pub struct WifiLoop<'a> {
-  pub wifi: AsyncWifi<EspWifi<'a>>,
+
+/// Registrar checked for reachability after joining a network, as `host:port`, set at build time.
+/// Without it, a network counts as usable once the pledge got an IP address.
+const REGISTRAR_ADDRESS: Option<&str> = option_env!("BRSKI_REGISTRAR");
+
+/// Plain `http://` URL answering `204 No Content`, fetched after joining a network, set at build time.
+/// Anything else means the network intercepts traffic with a captive portal, which would otherwise only show up
+/// as failing TLS to the registrar.
+#[cfg(feature = "http")]
+const CAPTIVE_PORTAL_CHECK: Option<&str> = option_env!("BRSKI_CAPTIVE_PORTAL_CHECK");
+
+/// Network state as seen by the onboarding steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Connectivity {
+  Connecting,
+  Online { ssid: String },
+  /// Lost the network or roaming, onboarding steps needing it wait instead of failing
+  Reconnecting,
+  /// The last tried network is behind a captive portal, the next one is tried
+  CaptivePortal { ssid: &'static str, detail: String },
 }
 
-impl<'a> WifiLoop<'a> {
-  pub async fn configure(&mut self) -> Result<(), EspError> {
+#[derive(Debug)]
+struct CaptivePortalError(String);
+
+impl std::fmt::Display for CaptivePortalError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "captive portal detected, {}", self.0)
+  }
+}
+
+impl std::error::Error for CaptivePortalError {}
+
+static CONNECTIVITY: LazyLock<watch::Sender<Connectivity>> = LazyLock::new(|| watch::Sender::new(Connectivity::Connecting));
+
+/// Production network provisioned by the registrar-agent, starts out with the one stored by an earlier onboarding
+static PRODUCTION_NETWORK: LazyLock<watch::Sender<Option<NetworkProfile>>> =
+  LazyLock::new(|| watch::Sender::new(credential_store::network_profile()));
+
+fn publish(connectivity: Connectivity) {
+  let previous = CONNECTIVITY.send_replace(connectivity.clone());
+  if previous != connectivity {
+    crate::black_box::transition(previous, connectivity);
+  }
+}
+
+pub fn connectivity() -> watch::Receiver<Connectivity> {
+  CONNECTIVITY.subscribe()
+}
+
+/// Pauses an onboarding step until the pledge is online again, so a dropped AP or a roam does not fail the
+/// onboarding attempt
+pub async fn wait_online() -> String {
+  let mut connectivity = connectivity();
+  loop {
+    if let Connectivity::Online { ssid } = &*connectivity.borrow_and_update() {
+      return ssid.clone();
+    }
+    if connectivity.changed().await.is_err() {
+      // the sender is static and never dropped
+      std::future::pending::<()>().await;
+    }
+  }
+}
+
+/// Switches to `profile` at the next connectivity check, `None` falls back to the bootstrap networks
+pub fn provision(profile: Option<NetworkProfile>) {
+  PRODUCTION_NETWORK.send_if_modified(|current| {
+    if *current == profile {
+      return false;
+    }
+    *current = profile;
+    true
+  });
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BootstrapSecurity {
+  /// Open onboarding SSID
+  Open,
+  /// WPA2-PSK bootstrap network with the PSK embedded in the firmware
+  Wpa2Psk(&'static str),
+}
+
+/// Candidate network to onboard through
+#[derive(Debug, Clone, Copy)]
+pub struct BootstrapNetwork {
+  pub ssid: &'static str,
+  pub security: BootstrapSecurity,
+  /// Lower values are tried first
+  pub priority: u8,
+  /// How long joining the network, getting an address and reaching the registrar may take
+  pub timeout: Duration,
+}
+
+/// Network the pledge joins, the provisioned production network is tried before the bootstrap networks. These stay
+/// a fallback, so a pledge whose production network went away can still be re-provisioned.
+#[derive(Debug, Clone)]
+enum Candidate {
+  Production(NetworkProfile),
+  Bootstrap(BootstrapNetwork),
+}
+
+impl Candidate {
+  fn ssid(&self) -> &str {
+    match self {
+      Candidate::Production(profile) => &profile.ssid,
+      Candidate::Bootstrap(network) => network.ssid,
+    }
+  }
+
+  fn priority(&self) -> u8 {
+    match self {
+      Candidate::Production(_) => 0,
+      Candidate::Bootstrap(network) => network.priority,
+    }
+  }
+
+  fn timeout(&self, policy: &BootstrapPolicy) -> Duration {
+    match self {
+      Candidate::Production(_) => policy.production_timeout,
+      Candidate::Bootstrap(network) => network.timeout,
+    }
+  }
+
+  fn configuration(&self, bssid: Option<[u8; 6]>) -> Result<Configuration, EspError> {
+    let profile = match self {
+      Candidate::Production(profile) => profile,
+      Candidate::Bootstrap(network) => return network.configuration(bssid),
+    };
+
+    let mut ssid: heapless::String<32> = heapless::String::new();
+    ssid.push_str(&profile.ssid).map_err(|_| invalid_argument())?;
+
+    let mut password: heapless::String<64> = heapless::String::new();
+    let auth_method = match &profile.security {
+      NetworkSecurity::Wpa2Psk { psk } => {
+        password.push_str(psk).map_err(|_| invalid_argument())?;
+        AuthMethod::WPA2Personal
+      }
+      NetworkSecurity::EapTls { .. } => AuthMethod::WPA2Enterprise,
+    };
+
+    Ok(Configuration::Client(ClientConfiguration {
+      ssid,
+      bssid,
+      password,
+      auth_method,
+      ..Default::default()
+    }))
+  }
+}
+
+/// Bootstrap networks, tried by priority and cycled through until the registrar is reachable over one of them
+#[derive(Debug, Clone)]
+pub struct BootstrapPolicy {
+  pub networks: Vec<BootstrapNetwork>,
+  /// Pause after all networks failed before starting over
+  pub cycle_backoff: Duration,
+  pub registrar_timeout: Duration,
+  /// How long to wait for an address after associating before the network counts as failed
+  pub dhcp_timeout: Duration,
+  /// Interval of the signal checks while connected
+  pub roam_check_interval: Duration,
+  /// Below this RSSI in dBm the pledge scans for a better access point of a bootstrap network
+  pub roam_rssi: i8,
+  /// How many dB an access point has to be stronger to roam to it
+  pub roam_hysteresis: i8,
+  /// How long joining the provisioned production network and getting an address may take
+  pub production_timeout: Duration,
+}
+
+impl Default for BootstrapPolicy {
+  fn default() -> Self {
+    Self {
+      networks: vec![
+        BootstrapNetwork {
+          ssid: "HM-iotroam",
+          security: BootstrapSecurity::Wpa2Psk("ESP-Julian!"),
+          priority: 0,
+          timeout: Duration::from_secs(20),
+        },
+        BootstrapNetwork {
+          ssid: "brski-onboarding",
+          security: BootstrapSecurity::Open,
+          priority: 1,
+          timeout: Duration::from_secs(15),
+        },
+      ],
+      cycle_backoff: Duration::from_secs(10),
+      registrar_timeout: Duration::from_secs(5),
+      dhcp_timeout: Duration::from_secs(10),
+      roam_check_interval: Duration::from_secs(10),
+      roam_rssi: -75,
+      roam_hysteresis: 8,
+      production_timeout: Duration::from_secs(20),
+    }
+  }
+}
+
+impl BootstrapPolicy {
+  /// Networks in the order they are tried. The production network goes first, then the network used last so a
+  /// dropped connection is retried before switching networks
+  fn candidates(&self, production: Option<&NetworkProfile>, current: Option<&str>) -> Vec<Candidate> {
+    let mut networks: Vec<Candidate> = production
+      .cloned()
+      .map(Candidate::Production)
+      .into_iter()
+      .chain(self.networks.iter().copied().map(Candidate::Bootstrap))
+      .collect();
+    networks.sort_by_key(|network| {
+      (!matches!(network, Candidate::Production(_)), Some(network.ssid()) != current, network.priority())
+    });
+    networks
+  }
+}
 
+impl BootstrapNetwork {
+  /// With a BSSID the pledge joins that access point only, e.g. when roaming
+  fn configuration(&self, bssid: Option<[u8; 6]>) -> Result<Configuration, EspError> {
     let mut ssid: heapless::String<32> = heapless::String::new();
-    ssid.push_str("HM-iotroam").unwrap();
+    ssid.push_str(self.ssid).map_err(|_| invalid_argument())?;
 
     let mut password: heapless::String<64> = heapless::String::new();
-    password.push_str("ESP-Julian!").unwrap(); 
+    let auth_method = match self.security {
+      BootstrapSecurity::Open => AuthMethod::None,
+      BootstrapSecurity::Wpa2Psk(psk) => {
+        password.push_str(psk).map_err(|_| invalid_argument())?;
+        AuthMethod::WPA2Personal
+      }
+    };
 
-    info!("Setting Wi-Fi credentials...");
-    self.wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+    Ok(Configuration::Client(ClientConfiguration {
       ssid,
+      bssid,
       password,
+      auth_method,
       ..Default::default()
-    }))?;
+    }))
+  }
+}
+
+fn invalid_argument() -> EspError {
+  EspError::from_infallible::<ESP_ERR_INVALID_ARG>()
+}
+
+/// Signal strength of the access point the pledge is associated with, in dBm
+fn current_rssi() -> Result<i8, EspError> {
+  let mut record = wifi_ap_record_t::default();
+  esp!(unsafe { esp_wifi_sta_get_ap_info(&mut record) })?;
+  Ok(record.rssi)
+}
+
+/// Hands the LDevID to the supplicant, the authentication server has to chain to the pinned domain CA.
+/// ESP-IDF keeps pointers to the certificates and the key, they have to stay alive while EAP-TLS is enabled.
+/// `ca_bundle` is the PEM bundle of [`DomainCredentials::ca_bundle`]. The supplicant keeps pointers to the buffers,
+/// they have to outlive the connection.
+fn enable_eap_tls(credentials: &DomainCredentials, ca_bundle: &[u8], identity: &str) -> Result<(), EspError> {
+  let length = |buffer: &[u8]| i32::try_from(buffer.len()).map_err(|_| invalid_argument());
+  esp!(unsafe { esp_eap_client_set_identity(identity.as_ptr(), length(identity.as_bytes())?) })?;
+  esp!(unsafe { esp_eap_client_set_ca_cert(ca_bundle.as_ptr(), length(ca_bundle)?) })?;
+  esp!(unsafe {
+    esp_eap_client_set_certificate_and_key(
+      credentials.ldevid.as_ptr(),
+      length(&credentials.ldevid)?,
+      credentials.private_key.as_ptr(),
+      length(&credentials.private_key)?,
+      std::ptr::null(),
+      0,
+    )
+  })?;
+  esp!(unsafe { esp_wifi_sta_enterprise_enable() })
+}
+
+pub struct WifiLoop<'a> {
+  pub wifi: AsyncWifi<EspWifi<'a>>,
+  pub policy: BootstrapPolicy,
+  production: watch::Receiver<Option<NetworkProfile>>,
+  /// SSID of the network the registrar was last reached over
+  current: Option<String>,
+  /// Domain credentials and their CA bundle handed to the supplicant while EAP-TLS is enabled
+  eap_credentials: Option<(DomainCredentials, Vec<u8>)>,
+}
+
+impl<'a> WifiLoop<'a> {
+  fn candidates(&self) -> Vec<Candidate> {
+    let production = self.production.borrow().clone();
+    self.policy.candidates(production.as_ref(), self.current.as_deref())
+  }
+
+  pub async fn start(&mut self) -> Result<(), EspError> {
+    // EAP-TLS is only set up while joining, the bootstrap networks are a fallback
+    let first = self
+      .candidates()
+      .into_iter()
+      .find(|network| matches!(network, Candidate::Bootstrap(_)))
+      .ok_or_else(invalid_argument)?;
+    self.wifi.set_configuration(&first.configuration(None)?)?;
 
     info!("Starting Wi-Fi driver...");
     self.wifi.start().await
   }
 
-  pub async fn initial_connect(&mut self) -> Result<(), EspError> {
-    self.do_connect_loop(true).await
+  pub async fn initial_connect(&mut self) {
+    self.reconnect().await
   }
 
+  /// Never returns, disconnects, DHCP failures and driver errors end in a reconnect instead of an error
   pub async fn stay_connected(mut self) -> Result<(), EspError> {
-    self.do_connect_loop(false).await
-  }
-
-  async fn do_connect_loop(
-    &mut self,
-    exit_after_first_connect: bool,
-  ) -> Result<(), EspError> {
     loop {
-      let wifi = &mut self.wifi;
       // Wait for disconnect before trying to connect again.  This loop ensures
       // we stay connected and is commonly missing from trivial examples as it's
       // way too difficult to showcase the core logic of an example and have
       // a proper Wi-Fi event loop without a robust async runtime.  Fortunately, we can do it
       // now!
-      wifi.wifi_wait(|s| s.is_up(), None).await?;
+      match tokio::time::timeout(self.policy.roam_check_interval, self.wifi.wifi_wait(|s| s.is_up(), None)).await {
+        Err(_) => {
+          // still connected
+          if self.production.has_changed().unwrap_or(false) {
+            info!("Production network was provisioned, switching networks...");
+            self.reconnect().await;
+            continue;
+          }
+          if let Err(err) = self.roam().await {
+            warn!("Roaming failed: {}", err);
+            self.reconnect().await;
+          }
+          continue;
+        }
+        Ok(Ok(())) => warn!("Lost Wi-Fi connection to {:?}", self.current),
+        Ok(Err(err)) => warn!("Wi-Fi driver error: {}", err),
+      }
 
-      info!("Connecting to Wi-Fi...");
-      wifi.connect().await?;
+      self.reconnect().await;
+    }
+  }
 
-      info!("Waiting for association...");
-      wifi.ip_wait_while(|s| s.is_up().map(|s| !s), None).await?;
+  async fn reconnect(&mut self) {
+    publish(Connectivity::Reconnecting);
+    loop {
+      match self.select_network().await {
+        Ok(()) => return,
+        Err(err) => {
+          warn!("Selecting a network failed: {}, retrying in {:?}", err, self.policy.cycle_backoff);
+          tokio::time::sleep(self.policy.cycle_backoff).await;
+        }
+      }
+    }
+  }
 
-      if exit_after_first_connect {
-        return Ok(());
+  /// Cycles through the production and bootstrap networks until one of them is usable
+  async fn select_network(&mut self) -> Result<(), EspError> {
+    self.production.mark_unchanged();
+    loop {
+      for network in self.candidates() {
+        let timeout = network.timeout(&self.policy);
+        info!("Trying network {}...", network.ssid());
+
+        match tokio::time::timeout(timeout, self.join(&network, None)).await {
+          Ok(Ok(())) => {
+            match network {
+              Candidate::Production(_) => info!("Joined production network {}", network.ssid()),
+              Candidate::Bootstrap(_) => info!("Onboarding through {}", network.ssid()),
+            }
+            self.online(network.ssid());
+            return Ok(());
+          }
+          Ok(Err(err)) => {
+            warn!("Network {} failed: {}", network.ssid(), err);
+            if let (Some(CaptivePortalError(detail)), Candidate::Bootstrap(bootstrap)) = (err.downcast_ref(), &network) {
+              publish(Connectivity::CaptivePortal { ssid: bootstrap.ssid, detail: detail.clone() });
+            }
+          }
+          Err(_) => warn!("Network {} timed out after {:?}", network.ssid(), timeout),
+        }
+
+        if self.wifi.is_connected()? {
+          self.wifi.disconnect().await?;
+        }
       }
+
+      warn!("No network was usable, retrying in {:?}", self.policy.cycle_backoff);
+      tokio::time::sleep(self.policy.cycle_backoff).await;
     }
   }
+
+  fn online(&mut self, ssid: &str) {
+    self.current = Some(ssid.to_string());
+    publish(Connectivity::Online { ssid: ssid.to_string() });
+  }
+
+  /// Moves to a clearly stronger access point of any candidate network once the signal got weak
+  async fn roam(&mut self) -> Result<(), EspError> {
+    let rssi = current_rssi()?;
+    if rssi >= self.policy.roam_rssi {
+      return Ok(());
+    }
+
+    info!("Signal of {:?} dropped to {} dBm, scanning for a better access point...", self.current, rssi);
+    let access_points: Vec<AccessPointInfo> = self.wifi.scan().await?;
+
+    let threshold = rssi.saturating_add(self.policy.roam_hysteresis);
+    let candidates = self.candidates();
+    let best = access_points
+      .iter()
+      .filter(|access_point| access_point.signal_strength >= threshold)
+      .filter_map(|access_point| {
+        let network = candidates.iter().find(|network| network.ssid() == access_point.ssid.as_str())?;
+        Some((network, access_point.bssid, access_point.signal_strength))
+      })
+      .max_by_key(|(network, _, signal_strength)| (*signal_strength, std::cmp::Reverse(network.priority())));
+
+    let Some((network, bssid, signal_strength)) = best else {
+      return Ok(());
+    };
+
+    let network = network.clone();
+    let timeout = network.timeout(&self.policy);
+    info!("Roaming to {} ({:02x?}) at {} dBm", network.ssid(), bssid, signal_strength);
+    publish(Connectivity::Reconnecting);
+    match tokio::time::timeout(timeout, self.join(&network, Some(bssid))).await {
+      Ok(Ok(())) => self.online(network.ssid()),
+      Ok(Err(err)) => {
+        warn!("Roaming to {} failed: {}", network.ssid(), err);
+        self.reconnect().await;
+      }
+      Err(_) => {
+        warn!("Roaming to {} timed out", network.ssid());
+        self.reconnect().await;
+      }
+    }
+    Ok(())
+  }
+
+  /// Sets up or tears down EAP-TLS for the network about to be joined
+  fn configure_enterprise(&mut self, network: &Candidate) -> anyhow::Result<()> {
+    if let Candidate::Production(NetworkProfile { security: NetworkSecurity::EapTls { identity }, .. }) = network {
+      let credentials = credential_store::domain_credentials()
+        .ok_or_else(|| anyhow::anyhow!("EAP-TLS needs the LDevID, the pledge is not onboarded"))?;
+      if credentials.private_key.is_empty() {
+        return Err(anyhow::anyhow!("EAP-TLS needs the private key of the LDevID, it is kept in the secure element"));
+      }
+      let identity = identity.as_deref().unwrap_or(crate::tpvr::SERIAL_NUMBER);
+      let ca_bundle = credentials.ca_bundle();
+      enable_eap_tls(&credentials, &ca_bundle, identity)?;
+      self.eap_credentials = Some((credentials, ca_bundle));
+    } else if self.eap_credentials.is_some() {
+      esp!(unsafe { esp_wifi_sta_enterprise_disable() })?;
+      self.eap_credentials = None;
+    }
+    Ok(())
+  }
+
+  async fn join(&mut self, network: &Candidate, bssid: Option<[u8; 6]>) -> anyhow::Result<()> {
+    if self.wifi.is_connected()? {
+      self.wifi.disconnect().await?;
+    }
+    self.configure_enterprise(network)?;
+    self.wifi.set_configuration(&network.configuration(bssid)?)?;
+
+    info!("Connecting to Wi-Fi...");
+    self.wifi.connect().await?;
+
+    info!("Waiting for an address...");
+    self.wifi
+      .ip_wait_while(|s| s.is_up().map(|s| !s), Some(self.policy.dhcp_timeout))
+      .await
+      .map_err(|err| anyhow::anyhow!("no address from DHCP within {:?}: {}", self.policy.dhcp_timeout, err))?;
+
+    // the production network is used once onboarded, it does not need to reach the registrar
+    if let Candidate::Production(_) = network {
+      return Ok(());
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(url) = CAPTIVE_PORTAL_CHECK {
+      info!("Checking {} for a captive portal...", url);
+      tokio::time::timeout(self.policy.registrar_timeout, check_captive_portal(url))
+        .await
+        .map_err(|_| anyhow::anyhow!("captive portal check {} did not answer", url))??;
+    }
+
+    if let Some(registrar) = REGISTRAR_ADDRESS {
+      info!("Checking that registrar {} is reachable...", registrar);
+      tokio::time::timeout(self.policy.registrar_timeout, TcpStream::connect(registrar))
+        .await
+        .map_err(|_| anyhow::anyhow!("registrar {} did not answer", registrar))??;
+    }
+
+    Ok(())
+  }
+}
+
+/// Fetches the check url with a bare HTTP/1.0 request, portals answer it with a redirect or their login page
+#[cfg(feature = "http")]
+async fn check_captive_portal(url: &str) -> anyhow::Result<()> {
+  let target = url
+    .strip_prefix("http://")
+    .ok_or_else(|| anyhow::anyhow!("captive portal check {} is not a plain http:// URL", url))?;
+  let (authority, path) = match target.split_once('/') {
+    Some((authority, path)) => (authority, format!("/{path}")),
+    None => (target, "/".to_string()),
+  };
+  let address = if authority.contains(':') { authority.to_string() } else { format!("{authority}:80") };
+
+  let mut stream = TcpStream::connect(address).await?;
+  stream
+    .write_all(format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\nConnection: close\r\n\r\n").as_bytes())
+    .await?;
+
+  // the status line and headers are enough, portal pages can be large
+  let mut response = Vec::new();
+  (&mut stream).take(1024).read_to_end(&mut response).await?;
+  let response = String::from_utf8_lossy(&response);
+
+  let status = response.lines().next().and_then(|line| line.split_whitespace().nth(1)).unwrap_or_default();
+  if status == "204" {
+    return Ok(());
+  }
+
+  let location = response
+    .lines()
+    .take_while(|line| !line.is_empty())
+    .find_map(|line| {
+      let (name, value) = line.split_once(':')?;
+      name.eq_ignore_ascii_case("location").then(|| value.trim())
+    });
+  let detail = match location {
+    Some(location) => format!("redirected to {location}"),
+    None => format!("connectivity check answered {status:?} instead of 204"),
+  };
+  Err(CaptivePortalError(detail).into())
 }
 
+#[cfg(feature = "http")]
 pub async fn echo_server() -> anyhow::Result<()> {
   let addr = format!("0.0.0.0:{TCP_LISTENING_PORT}");
 
@@ -59,7 +59,7 @@ class _ScanScreenState extends State<WifiScreen> {
       Snackbar.show(ABC.b, prettyException("System Devices Error:", e), success: false);
     }
     try {
-      await FlutterBluePlus.startScan(timeout: const Duration(seconds: 15), withNames: ["ESP32 Server"]);
+      await FlutterBluePlus.startScan(timeout: const Duration(seconds: 15), withServices: [Guid("9b574847-f706-436c-bed7-fc01eb0965c1")]);
     } catch (e) {
       Snackbar.show(ABC.b, prettyException("Start Scan Error:", e), success: false);
     }